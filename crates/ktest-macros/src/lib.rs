@@ -0,0 +1,64 @@
+//! Provides the `#[kernel_test]` attribute of the vOS in-kernel test framework.
+//!
+//! The attribute keeps the function only in the test build and registers it to the
+//! `custom_test_frameworks` test runner of the kernel (`crate::ktest::test_runner`):
+//!
+//! ```ignore
+//! #[kernel_test]
+//! fn list_is_empty() {
+//!     let mut head = List::new();
+//!     head.init_empty();
+//!     assert!(list::is_empty(&head));
+//! }
+//! ```
+//!
+//! The test function takes no parameter, and returns `()` or `Result<(), E>` where `E: Debug`.
+
+use proc_macro::{TokenStream, TokenTree};
+
+
+#[proc_macro_attribute]
+pub fn kernel_test(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return compile_error("`#[kernel_test]` does not take any argument");
+    }
+    let name = match fn_name(&item) {
+        Some(name) => name,
+        None => return compile_error("`#[kernel_test]` can only be applied to a function"),
+    };
+
+    let registry = format!(r#"
+        #[cfg(test)]
+        #[test_case]
+        #[allow(non_upper_case_globals)]
+        static __KERNEL_TEST_{name}: crate::ktest::KernelTest = crate::ktest::KernelTest {{
+            name: concat!(module_path!(), "::", "{name}"),
+            func: || crate::ktest::TestResult::is_success({name}()),
+        }};
+    "#);
+
+    let mut out: TokenStream = "#[cfg(test)]".parse().unwrap();
+    out.extend(item);
+    out.extend(registry.parse::<TokenStream>().unwrap());
+    out
+}
+
+/// Find the function name: the identifier following the `fn` keyword.
+fn fn_name(item: &TokenStream) -> Option<String> {
+    let mut iter = item.clone().into_iter();
+    while let Some(tt) = iter.next() {
+        if let TokenTree::Ident(ident) = tt {
+            if ident.to_string() == "fn" {
+                return match iter.next() {
+                    Some(TokenTree::Ident(name)) => Some(name.to_string()),
+                    _ => None,
+                };
+            }
+        }
+    }
+    None
+}
+
+fn compile_error(msg: &str) -> TokenStream {
+    format!("compile_error!({:?});", msg).parse().unwrap()
+}
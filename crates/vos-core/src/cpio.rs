@@ -0,0 +1,229 @@
+//! Parser of the cpio archives in the `newc` format, which is used by the Linux initramfs.
+//!
+//! Each entry is a 110-byte ASCII header followed by the NUL-terminated name and the file data,
+//! the name and the data are both padded to 4 bytes. The header fields are 8-digit hex numbers:
+//!
+//! ```text
+//!     magic[6] ino mode uid gid nlink mtime filesize devmajor devminor rdevmajor rdevminor
+//!     namesize check
+//! ```
+//!
+//! The archive ends with the entry named `TRAILER!!!`. As in Linux, several archives can be
+//! concatenated, with NUL padding between them. The parser never panics on a malformed input.
+
+use core::fmt;
+
+
+/// Size of the `newc` header.
+pub const HEADER_SIZE: usize = 110;
+
+/// Magic of the `newc` format.
+const MAGIC: &[u8; 6] = b"070701";
+/// Magic of the `newc` format with the checksums, which are ignored.
+const MAGIC_CRC: &[u8; 6] = b"070702";
+/// Name of the entry ending an archive.
+const TRAILER: &str = "TRAILER!!!";
+
+/// cpio parse errors.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CpioError {
+    /// The data ends in the middle of an entry.
+    Truncated,
+    /// Not a `newc` header.
+    BadMagic,
+    /// A header field is not a hex number.
+    BadNumber,
+    /// The name is empty, not NUL-terminated or not UTF-8.
+    BadName,
+}
+
+impl fmt::Display for CpioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            CpioError::Truncated => "truncated archive",
+            CpioError::BadMagic => "bad magic",
+            CpioError::BadNumber => "bad header number",
+            CpioError::BadName => "bad name",
+        };
+        f.write_str(msg)
+    }
+}
+
+/// Entry of an archive.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Entry<'a> {
+    pub ino: u32,
+    /// File type and permission bits.
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub nlink: u32,
+    pub mtime: u32,
+    pub dev_major: u32,
+    pub dev_minor: u32,
+    /// Device number of a device file.
+    pub rdev_major: u32,
+    pub rdev_minor: u32,
+    /// Path of the entry, usually relative to the archive root and without a leading `/`.
+    pub name: &'a str,
+    /// File content, or the target of a symlink.
+    pub data: &'a [u8],
+}
+
+#[inline(always)]
+fn pad4(off: usize) -> usize {
+    (off + 3) & !3usize
+}
+
+fn read_hex(field: &[u8]) -> Result<u32, CpioError> {
+    let mut val = 0u32;
+    for &c in field {
+        let digit = match c {
+            b'0'..=b'9' => c - b'0',
+            b'a'..=b'f' => c - b'a' + 10,
+            b'A'..=b'F' => c - b'A' + 10,
+            _ => return Err(CpioError::BadNumber),
+        };
+        val = val << 4 | digit as u32;
+    }
+    Ok(val)
+}
+
+/// Iterator over the entries of the archives in `data`, the trailers are skipped. The iteration
+/// stops after the first error.
+pub struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+    failed: bool,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0, failed: false }
+    }
+
+    /// Parse the entry at the current offset and move to the next one.
+    fn parse_entry(&mut self) -> Result<Entry<'a>, CpioError> {
+        let data = self.data;
+        let start = self.offset;
+        let header = data.get(start..start + HEADER_SIZE).ok_or(CpioError::Truncated)?;
+        if &header[..6] != MAGIC && &header[..6] != MAGIC_CRC {
+            return Err(CpioError::BadMagic);
+        }
+        let mut fields = [0u32; 13];
+        for (idx, field) in fields.iter_mut().enumerate() {
+            *field = read_hex(&header[6 + idx * 8..14 + idx * 8])?;
+        }
+        let file_size = fields[6] as usize;
+        let name_size = fields[11] as usize;
+
+        let name_start = start + HEADER_SIZE;
+        let name = data.get(name_start..name_start + name_size).ok_or(CpioError::Truncated)?;
+        let name = match name.split_last() {
+            Some((0, name)) if !name.is_empty() => name,
+            _ => return Err(CpioError::BadName),
+        };
+        let name = core::str::from_utf8(name).map_err(|_| CpioError::BadName)?;
+
+        let data_start = pad4(name_start + name_size);
+        let file = data.get(data_start..data_start + file_size).ok_or(CpioError::Truncated)?;
+        self.offset = pad4(data_start + file_size);
+
+        Ok(Entry {
+            ino: fields[0],
+            mode: fields[1],
+            uid: fields[2],
+            gid: fields[3],
+            nlink: fields[4],
+            mtime: fields[5],
+            dev_major: fields[7],
+            dev_minor: fields[8],
+            rdev_major: fields[9],
+            rdev_minor: fields[10],
+            name,
+            data: file,
+        })
+    }
+}
+
+impl<'a> Iterator for Reader<'a> {
+    type Item = Result<Entry<'a>, CpioError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.failed {
+            // Skip the NUL padding between the archives.
+            while self.offset < self.data.len() && self.data[self.offset] == 0 {
+                self.offset += 1;
+            }
+            if self.offset >= self.data.len() {
+                return None;
+            }
+
+            match self.parse_entry() {
+                Ok(entry) if entry.name == TRAILER => continue,
+                Ok(entry) => return Some(Ok(entry)),
+                Err(err) => {
+                    self.failed = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::format;
+    use std::vec::Vec;
+    use super::*;
+
+    fn push_entry(archive: &mut Vec<u8>, name: &str, mode: u32, data: &[u8]) {
+        let header = format!("070701{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}\
+                              {:08x}{:08x}{:08x}", 1, mode, 0, 0, 1, 0, data.len(), 0, 0, 0, 0,
+                             name.len() + 1, 0);
+        archive.extend_from_slice(header.as_bytes());
+        archive.extend_from_slice(name.as_bytes());
+        archive.push(0);
+        archive.resize(pad4(archive.len()), 0);
+        archive.extend_from_slice(data);
+        archive.resize(pad4(archive.len()), 0);
+    }
+
+    #[test]
+    fn read_entries() {
+        let mut archive = Vec::new();
+        push_entry(&mut archive, "bin", 0o040755, &[]);
+        push_entry(&mut archive, "bin/init", 0o100755, b"hello");
+        push_entry(&mut archive, TRAILER, 0, &[]);
+        // A second archive after the padding.
+        archive.resize(archive.len() + 512, 0);
+        push_entry(&mut archive, "sh", 0o120777, b"bin/init");
+        push_entry(&mut archive, TRAILER, 0, &[]);
+
+        let entries: Vec<_> = Reader::new(&archive).map(|e| e.unwrap()).collect();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].name, "bin");
+        assert_eq!(entries[0].mode, 0o040755);
+        assert_eq!(entries[1].name, "bin/init");
+        assert_eq!(entries[1].data, b"hello");
+        assert_eq!(entries[2].name, "sh");
+        assert_eq!(entries[2].data, b"bin/init");
+    }
+
+    #[test]
+    fn reject_archive() {
+        let mut archive = Vec::new();
+        push_entry(&mut archive, "init", 0o100755, b"hello");
+        let mut reader = Reader::new(&archive[..archive.len() - 4]);
+        assert_eq!(reader.next(), Some(Err(CpioError::Truncated)));
+        assert_eq!(reader.next(), None);
+
+        archive[3] = b'8';
+        assert_eq!(Reader::new(&archive).next(), Some(Err(CpioError::BadMagic)));
+        archive[3] = b'7';
+        archive[6] = b'x';
+        assert_eq!(Reader::new(&archive).next(), Some(Err(CpioError::BadNumber)));
+    }
+}
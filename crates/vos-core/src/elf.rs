@@ -0,0 +1,284 @@
+//! ELF64 file format parser.
+//!
+//! Only the parts needed to load a static RISC-V executable are supported: the file header and
+//! the program headers. All values are read in little-endian and the parser never panics on a
+//! malformed input.
+
+use core::fmt;
+
+
+/// Size of the ELF64 file header.
+pub const EHDR_SIZE: usize = 64;
+/// Size of the ELF64 program header.
+pub const PHDR_SIZE: usize = 56;
+
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+
+/// Machine type of RISC-V.
+pub const EM_RISCV: u16 = 243;
+
+/// Executable file.
+pub const ET_EXEC: u16 = 2;
+/// Shared object file, a static PIE executable is also of this type.
+pub const ET_DYN: u16 = 3;
+
+/// Loadable segment.
+pub const PT_LOAD: u32 = 1;
+/// Program interpreter.
+pub const PT_INTERP: u32 = 3;
+/// Location of the program header table itself.
+pub const PT_PHDR: u32 = 6;
+/// Stack flags.
+pub const PT_GNU_STACK: u32 = 0x6474_e551;
+
+/// Segment is executable.
+pub const PF_X: u32 = 1 << 0;
+/// Segment is writable.
+pub const PF_W: u32 = 1 << 1;
+/// Segment is readable.
+pub const PF_R: u32 = 1 << 2;
+
+/// ELF parse errors.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ElfError {
+    /// The data is shorter than the header.
+    Truncated,
+    /// Not an ELF file.
+    BadMagic,
+    /// Not a 64-bit file.
+    BadClass,
+    /// Not a little-endian file.
+    BadEndian,
+    /// Unknown ELF version.
+    BadVersion,
+    /// Not a RISC-V file.
+    BadMachine,
+    /// Neither an executable nor a shared object.
+    BadType,
+    /// Invalid program header table.
+    BadProgramHeader,
+    /// Invalid segment.
+    BadSegment,
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            ElfError::Truncated => "truncated file",
+            ElfError::BadMagic => "bad magic",
+            ElfError::BadClass => "not ELF64",
+            ElfError::BadEndian => "not little-endian",
+            ElfError::BadVersion => "bad version",
+            ElfError::BadMachine => "not RISC-V",
+            ElfError::BadType => "not an executable",
+            ElfError::BadProgramHeader => "bad program header table",
+            ElfError::BadSegment => "bad segment",
+        };
+        f.write_str(msg)
+    }
+}
+
+#[inline(always)]
+fn read_u16(data: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes([data[off], data[off + 1]])
+}
+
+#[inline(always)]
+fn read_u32(data: &[u8], off: usize) -> u32 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&data[off..off + 4]);
+    u32::from_le_bytes(buf)
+}
+
+#[inline(always)]
+fn read_u64(data: &[u8], off: usize) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&data[off..off + 8]);
+    u64::from_le_bytes(buf)
+}
+
+/// ELF64 file header, only the fields used by the loader.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FileHeader {
+    pub e_type: u16,
+    pub e_machine: u16,
+    pub e_entry: u64,
+    pub e_phoff: u64,
+    pub e_flags: u32,
+    pub e_phentsize: u16,
+    pub e_phnum: u16,
+}
+
+impl FileHeader {
+    /// Parse and validate the file header of a RISC-V executable (`ET_EXEC` or `ET_DYN`).
+    pub fn parse(data: &[u8]) -> Result<Self, ElfError> {
+        if data.len() < EHDR_SIZE {
+            return Err(ElfError::Truncated);
+        }
+        if data[0..4] != ELF_MAGIC {
+            return Err(ElfError::BadMagic);
+        }
+        if data[4] != ELFCLASS64 {
+            return Err(ElfError::BadClass);
+        }
+        if data[5] != ELFDATA2LSB {
+            return Err(ElfError::BadEndian);
+        }
+        if data[6] != EV_CURRENT || read_u32(data, 20) != EV_CURRENT as u32 {
+            return Err(ElfError::BadVersion);
+        }
+
+        let header = Self {
+            e_type: read_u16(data, 16),
+            e_machine: read_u16(data, 18),
+            e_entry: read_u64(data, 24),
+            e_phoff: read_u64(data, 32),
+            e_flags: read_u32(data, 48),
+            e_phentsize: read_u16(data, 54),
+            e_phnum: read_u16(data, 56),
+        };
+        if header.e_machine != EM_RISCV {
+            return Err(ElfError::BadMachine);
+        }
+        if header.e_type != ET_EXEC && header.e_type != ET_DYN {
+            return Err(ElfError::BadType);
+        }
+        if header.e_phnum == 0 || header.e_phentsize as usize != PHDR_SIZE {
+            return Err(ElfError::BadProgramHeader);
+        }
+        header.program_headers_offset().ok_or(ElfError::BadProgramHeader)?;
+
+        Ok(header)
+    }
+
+    /// Get the file offset range `(start, end)` of the program header table. Returns `None` if
+    /// the range overflows.
+    pub fn program_headers_offset(&self) -> Option<(u64, u64)> {
+        let size = (self.e_phnum as u64).checked_mul(PHDR_SIZE as u64)?;
+        Some((self.e_phoff, self.e_phoff.checked_add(size)?))
+    }
+}
+
+/// ELF64 program header.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub p_flags: u32,
+    pub p_offset: u64,
+    pub p_vaddr: u64,
+    pub p_filesz: u64,
+    pub p_memsz: u64,
+    pub p_align: u64,
+}
+
+impl ProgramHeader {
+    /// Parse a program header.
+    pub fn parse(data: &[u8]) -> Result<Self, ElfError> {
+        if data.len() < PHDR_SIZE {
+            return Err(ElfError::Truncated);
+        }
+
+        Ok(Self {
+            p_type: read_u32(data, 0),
+            p_flags: read_u32(data, 4),
+            p_offset: read_u64(data, 8),
+            p_vaddr: read_u64(data, 16),
+            p_filesz: read_u64(data, 32),
+            p_memsz: read_u64(data, 40),
+            p_align: read_u64(data, 48),
+        })
+    }
+
+    /// Validate a `PT_LOAD` segment of a file with `file_size` bytes: the file content must be
+    /// in the file, the memory size must not be less than the file size, the ranges must not
+    /// overflow and the offset must be congruent with the address modulo the alignment.
+    pub fn validate_load(&self, file_size: u64) -> Result<(), ElfError> {
+        match self.p_offset.checked_add(self.p_filesz) {
+            Some(end) if end <= file_size => {}
+            _ => return Err(ElfError::BadSegment),
+        }
+        if self.p_filesz > self.p_memsz || self.p_vaddr.checked_add(self.p_memsz).is_none() {
+            return Err(ElfError::BadSegment);
+        }
+        if self.p_align > 1 && (!self.p_align.is_power_of_two() ||
+            (self.p_vaddr & (self.p_align - 1)) != (self.p_offset & (self.p_align - 1))) {
+            return Err(ElfError::BadSegment);
+        }
+
+        Ok(())
+    }
+
+    /// Check if the file offset `offset` is in the file content of this segment.
+    #[inline]
+    pub fn contains_offset(&self, offset: u64) -> bool {
+        offset >= self.p_offset && offset - self.p_offset < self.p_filesz
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+    use super::*;
+
+    fn header(e_type: u16, machine: u16) -> Vec<u8> {
+        let mut data = std::vec![0u8; EHDR_SIZE];
+        data[0..4].copy_from_slice(&ELF_MAGIC);
+        data[4] = ELFCLASS64;
+        data[5] = ELFDATA2LSB;
+        data[6] = EV_CURRENT;
+        data[16..18].copy_from_slice(&e_type.to_le_bytes());
+        data[18..20].copy_from_slice(&machine.to_le_bytes());
+        data[20..24].copy_from_slice(&1u32.to_le_bytes());
+        data[24..32].copy_from_slice(&0x1234u64.to_le_bytes());
+        data[32..40].copy_from_slice(&(EHDR_SIZE as u64).to_le_bytes());
+        data[54..56].copy_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+        data[56..58].copy_from_slice(&2u16.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn parse_header() {
+        let h = FileHeader::parse(&header(ET_EXEC, EM_RISCV)).unwrap();
+        assert_eq!(h.e_entry, 0x1234);
+        assert_eq!(h.e_phnum, 2);
+        assert_eq!(h.program_headers_offset(), Some((64, 64 + 2 * 56)));
+    }
+
+    #[test]
+    fn reject_header() {
+        assert_eq!(FileHeader::parse(&[0u8; 10]), Err(ElfError::Truncated));
+        assert_eq!(FileHeader::parse(&header(ET_EXEC, 62)), Err(ElfError::BadMachine));
+        assert_eq!(FileHeader::parse(&header(1, EM_RISCV)), Err(ElfError::BadType));
+
+        let mut data = header(ET_EXEC, EM_RISCV);
+        data[4] = 1;
+        assert_eq!(FileHeader::parse(&data), Err(ElfError::BadClass));
+        let mut data = header(ET_EXEC, EM_RISCV);
+        data[1] = b'X';
+        assert_eq!(FileHeader::parse(&data), Err(ElfError::BadMagic));
+    }
+
+    #[test]
+    fn validate_segment() {
+        let mut ph = ProgramHeader {
+            p_type: PT_LOAD,
+            p_flags: PF_R | PF_X,
+            p_offset: 0x1000,
+            p_vaddr: 0x2000_1000,
+            p_filesz: 0x800,
+            p_memsz: 0x1000,
+            p_align: 0x1000,
+        };
+        assert_eq!(ph.validate_load(0x1800), Ok(()));
+        assert_eq!(ph.validate_load(0x17ff), Err(ElfError::BadSegment));
+        ph.p_vaddr += 8;
+        assert_eq!(ph.validate_load(0x1800), Err(ElfError::BadSegment));
+        ph.p_vaddr -= 8;
+        ph.p_memsz = 0x10;
+        assert_eq!(ph.validate_load(0x1800), Err(ElfError::BadSegment));
+    }
+}
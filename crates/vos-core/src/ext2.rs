@@ -0,0 +1,665 @@
+//! On-disk format of the ext2 file system, revision 0 and 1.
+//!
+//! - The [`SuperBlock`] is at the byte 1024 of the volume. The blocks after `first_data_block`
+//!   are split into the block groups; each group has a block bitmap, an inode bitmap and an
+//!   inode table, described by its [`GroupDesc`] in the table following the superblock.
+//! - An inode ([`DiskInode`]) maps the blocks of a file by 12 direct block numbers, then an
+//!   indirect, a double indirect and a triple indirect block, see [`block_path`]. A zero block
+//!   number is a hole, which reads as zeros.
+//! - A directory is a list of the variable-length [`DirEntry`]s, which never cross a block. A
+//!   removed entry is merged into the previous one, or its inode number is set to 0 if it is the
+//!   first of the block.
+//! - A symlink target shorter than 60 bytes is stored in the block numbers of the inode (a fast
+//!   symlink), a longer one in a data block.
+//!
+//! The features are checked by [`SuperBlock::check_features`]: a volume with an unknown
+//! incompatible feature can not be mounted, and one with an unknown read-only compatible feature
+//! can only be mounted read-only. Nothing here panics on a malformed volume.
+
+use core::fmt;
+
+
+/// Offset of the superblock in the volume.
+pub const SUPERBLOCK_OFFSET: u64 = 1024;
+pub const SUPERBLOCK_SIZE: usize = 1024;
+pub const EXT2_MAGIC: u16 = 0xef53;
+
+/// Inode number of the root directory.
+pub const ROOT_INO: u32 = 2;
+/// First non-reserved inode of a revision 0 volume.
+const GOOD_OLD_FIRST_INO: u32 = 11;
+/// Inode size of a revision 0 volume.
+const GOOD_OLD_INODE_SIZE: u32 = 128;
+/// Size of a group descriptor.
+pub const GROUP_DESC_SIZE: usize = 32;
+
+// States of the file system in `s_state`.
+/// Cleanly unmounted. The flag is cleared while mounted read-write.
+pub const STATE_VALID: u16 = 1;
+/// Errors were detected.
+pub const STATE_ERROR: u16 = 2;
+
+// Feature flags.
+/// Incompatible: the directory entries have the file type.
+pub const FEATURE_INCOMPAT_FILETYPE: u32 = 0x0002;
+/// Read-only compatible: the superblock backups are only in the groups 0, 1 and the powers of 3,
+/// 5 and 7.
+pub const FEATURE_RO_COMPAT_SPARSE_SUPER: u32 = 0x0001;
+/// Read-only compatible: the regular files may be larger than 2GiB.
+pub const FEATURE_RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+/// Read-only compatible: unused by Linux.
+pub const FEATURE_RO_COMPAT_BTREE_DIR: u32 = 0x0004;
+const SUPPORTED_INCOMPAT: u32 = FEATURE_INCOMPAT_FILETYPE;
+const SUPPORTED_RO_COMPAT: u32 =
+    FEATURE_RO_COMPAT_SPARSE_SUPER | FEATURE_RO_COMPAT_LARGE_FILE | FEATURE_RO_COMPAT_BTREE_DIR;
+
+// Block numbers of an inode.
+/// Count of the direct block numbers.
+pub const N_DIRECT: usize = 12;
+/// Index of the indirect block number, the double and the triple indirect ones follow.
+pub const IND_BLOCK: usize = 12;
+pub const N_BLOCKS: usize = 15;
+/// Max length of a fast symlink target, stored in the block numbers.
+pub const FAST_SYMLINK_MAX: usize = N_BLOCKS * 4 - 1;
+
+/// Inode flag: the directory is indexed by a hash tree, which is compatible with the linear
+/// format.
+pub const INDEX_FL: u32 = 0x0000_1000;
+/// Max link count of an inode.
+pub const LINK_MAX: u16 = 32000;
+
+// File types of a directory entry.
+pub const FT_UNKNOWN: u8 = 0;
+pub const FT_REG_FILE: u8 = 1;
+pub const FT_DIR: u8 = 2;
+pub const FT_CHRDEV: u8 = 3;
+pub const FT_BLKDEV: u8 = 4;
+pub const FT_FIFO: u8 = 5;
+pub const FT_SOCK: u8 = 6;
+pub const FT_SYMLINK: u8 = 7;
+
+/// Size of the header of a directory entry, the name follows.
+pub const DIR_ENTRY_HEADER: usize = 8;
+/// Max length of a name.
+pub const NAME_MAX: usize = 255;
+
+// File type bits of `i_mode`.
+const S_IFMT: u16 = 0o170000;
+const S_IFSOCK: u16 = 0o140000;
+const S_IFLNK: u16 = 0o120000;
+const S_IFREG: u16 = 0o100000;
+const S_IFBLK: u16 = 0o060000;
+const S_IFDIR: u16 = 0o040000;
+const S_IFCHR: u16 = 0o020000;
+const S_IFIFO: u16 = 0o010000;
+
+/// Superblock errors.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Ext2Error {
+    /// The magic number is not `0xef53`.
+    BadMagic,
+    /// A field of the superblock is invalid.
+    BadGeometry,
+    /// The incompatible features, which are unknown, are set.
+    Incompatible(u32),
+}
+
+impl fmt::Display for Ext2Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ext2Error::BadMagic => f.write_str("bad magic number"),
+            Ext2Error::BadGeometry => f.write_str("bad superblock"),
+            Ext2Error::Incompatible(features) => {
+                write!(f, "unsupported incompatible features {:#x}", features)
+            }
+        }
+    }
+}
+
+#[inline(always)]
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+#[inline(always)]
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+#[inline(always)]
+fn write_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+#[inline(always)]
+fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// The fields of the superblock used by the driver.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SuperBlock {
+    pub inodes_count: u32,
+    pub blocks_count: u32,
+    pub free_blocks_count: u32,
+    pub free_inodes_count: u32,
+    pub first_data_block: u32,
+    /// The block size is `1024 << log_block_size`.
+    pub log_block_size: u32,
+    pub blocks_per_group: u32,
+    pub inodes_per_group: u32,
+    /// Last mount and write times, in seconds.
+    pub mtime: u32,
+    pub wtime: u32,
+    pub mnt_count: u16,
+    /// Mounts before a check is due, not checked if negative.
+    pub max_mnt_count: i16,
+    pub state: u16,
+    pub rev_level: u32,
+    pub first_ino: u32,
+    pub inode_size: u32,
+    pub feature_compat: u32,
+    pub feature_incompat: u32,
+    pub feature_ro_compat: u32,
+}
+
+impl SuperBlock {
+    /// Parse the superblock `raw`.
+    pub fn parse(raw: &[u8; SUPERBLOCK_SIZE]) -> Result<SuperBlock, Ext2Error> {
+        if read_u16(raw, 56) != EXT2_MAGIC {
+            return Err(Ext2Error::BadMagic);
+        }
+        let rev_level = read_u32(raw, 76);
+        let (first_ino, inode_size, features) = if rev_level == 0 {
+            (GOOD_OLD_FIRST_INO, GOOD_OLD_INODE_SIZE, (0, 0, 0))
+        } else {
+            let features = (read_u32(raw, 92), read_u32(raw, 96), read_u32(raw, 100));
+            (read_u32(raw, 84), read_u16(raw, 88) as u32, features)
+        };
+        let sb = SuperBlock {
+            inodes_count: read_u32(raw, 0),
+            blocks_count: read_u32(raw, 4),
+            free_blocks_count: read_u32(raw, 12),
+            free_inodes_count: read_u32(raw, 16),
+            first_data_block: read_u32(raw, 20),
+            log_block_size: read_u32(raw, 24),
+            blocks_per_group: read_u32(raw, 32),
+            inodes_per_group: read_u32(raw, 40),
+            mtime: read_u32(raw, 44),
+            wtime: read_u32(raw, 48),
+            mnt_count: read_u16(raw, 52),
+            max_mnt_count: read_u16(raw, 54) as i16,
+            state: read_u16(raw, 58),
+            rev_level,
+            first_ino,
+            inode_size,
+            feature_compat: features.0,
+            feature_incompat: features.1,
+            feature_ro_compat: features.2,
+        };
+
+        if sb.log_block_size > 6 {
+            return Err(Ext2Error::BadGeometry);
+        }
+        let bits_per_block = sb.block_size() * 8;
+        if sb.blocks_per_group == 0 || sb.blocks_per_group > bits_per_block ||
+            sb.inodes_per_group == 0 || sb.inodes_per_group > bits_per_block ||
+            !sb.inode_size.is_power_of_two() || sb.inode_size < GOOD_OLD_INODE_SIZE ||
+            sb.inode_size > sb.block_size() || sb.first_data_block >= sb.blocks_count ||
+            sb.first_ino <= ROOT_INO || sb.first_ino > sb.inodes_count {
+            return Err(Ext2Error::BadGeometry);
+        }
+        if (sb.group_count() as u64) * (sb.inodes_per_group as u64) < sb.inodes_count as u64 {
+            return Err(Ext2Error::BadGeometry);
+        }
+        Ok(sb)
+    }
+
+    /// Write the fields changed by the driver, the counts, the times and the state, to `raw`.
+    pub fn write(&self, raw: &mut [u8; SUPERBLOCK_SIZE]) {
+        write_u32(raw, 12, self.free_blocks_count);
+        write_u32(raw, 16, self.free_inodes_count);
+        write_u32(raw, 44, self.mtime);
+        write_u32(raw, 48, self.wtime);
+        write_u16(raw, 52, self.mnt_count);
+        write_u16(raw, 58, self.state);
+    }
+
+    /// Check the features. Returns `Ok(true)` if the volume can only be mounted read-only.
+    pub fn check_features(&self) -> Result<bool, Ext2Error> {
+        let incompat = self.feature_incompat & !SUPPORTED_INCOMPAT;
+        if incompat != 0 {
+            return Err(Ext2Error::Incompatible(incompat));
+        }
+        Ok(self.feature_ro_compat & !SUPPORTED_RO_COMPAT != 0)
+    }
+
+    /// Size of a block in bytes.
+    #[inline(always)]
+    pub fn block_size(&self) -> u32 {
+        1024 << self.log_block_size
+    }
+
+    /// Count of the block groups.
+    #[inline(always)]
+    pub fn group_count(&self) -> u32 {
+        let blocks = self.blocks_count - self.first_data_block;
+        (blocks + self.blocks_per_group - 1) / self.blocks_per_group
+    }
+
+    /// Count of the blocks in the `group`, the last group may be smaller.
+    pub fn group_blocks(&self, group: u32) -> u32 {
+        let first = self.first_data_block + group * self.blocks_per_group;
+        (self.blocks_count - first).min(self.blocks_per_group)
+    }
+
+    /// First block of the `group`.
+    #[inline(always)]
+    pub fn group_first_block(&self, group: u32) -> u32 {
+        self.first_data_block + group * self.blocks_per_group
+    }
+
+    /// Block of the group descriptor table, after the superblock.
+    #[inline(always)]
+    pub fn group_desc_block(&self) -> u32 {
+        self.first_data_block + 1
+    }
+
+    /// Get the group of the inode `ino` and its index in the group.
+    #[inline(always)]
+    pub fn inode_group(&self, ino: u32) -> (u32, u32) {
+        ((ino - 1) / self.inodes_per_group, (ino - 1) % self.inodes_per_group)
+    }
+
+    /// Check if the directory entries have the file type.
+    #[inline(always)]
+    pub fn has_file_type(&self) -> bool {
+        self.feature_incompat & FEATURE_INCOMPAT_FILETYPE != 0
+    }
+
+    /// Check if the regular files may be larger than 2GiB.
+    #[inline(always)]
+    pub fn has_large_file(&self) -> bool {
+        self.feature_ro_compat & FEATURE_RO_COMPAT_LARGE_FILE != 0
+    }
+}
+
+/// A block group descriptor.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct GroupDesc {
+    pub block_bitmap: u32,
+    pub inode_bitmap: u32,
+    pub inode_table: u32,
+    pub free_blocks_count: u16,
+    pub free_inodes_count: u16,
+    pub used_dirs_count: u16,
+}
+
+impl GroupDesc {
+    /// Parse a descriptor from the 32 bytes `raw`.
+    pub fn parse(raw: &[u8]) -> GroupDesc {
+        GroupDesc {
+            block_bitmap: read_u32(raw, 0),
+            inode_bitmap: read_u32(raw, 4),
+            inode_table: read_u32(raw, 8),
+            free_blocks_count: read_u16(raw, 12),
+            free_inodes_count: read_u16(raw, 14),
+            used_dirs_count: read_u16(raw, 16),
+        }
+    }
+
+    /// Write the descriptor to the 32 bytes `raw`, the padding is kept.
+    pub fn write(&self, raw: &mut [u8]) {
+        write_u32(raw, 0, self.block_bitmap);
+        write_u32(raw, 4, self.inode_bitmap);
+        write_u32(raw, 8, self.inode_table);
+        write_u16(raw, 12, self.free_blocks_count);
+        write_u16(raw, 14, self.free_inodes_count);
+        write_u16(raw, 16, self.used_dirs_count);
+    }
+}
+
+/// The fields of an inode used by the driver, the times are in seconds.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct DiskInode {
+    /// File type and permission bits.
+    pub mode: u16,
+    pub uid: u32,
+    pub gid: u32,
+    /// Size in bytes, the high 32 bits are only used by the regular files.
+    pub size: u64,
+    pub atime: u32,
+    pub ctime: u32,
+    pub mtime: u32,
+    /// Deletion time, set when the inode is freed.
+    pub dtime: u32,
+    pub links_count: u16,
+    /// Count of the 512-byte sectors allocated, the indirect blocks included.
+    pub blocks: u32,
+    pub flags: u32,
+    pub block: [u32; N_BLOCKS],
+    pub generation: u32,
+    /// Block of the extended attributes, counted in `blocks`.
+    pub file_acl: u32,
+}
+
+impl DiskInode {
+    /// Parse an inode from the at least 128 bytes `raw`.
+    pub fn parse(raw: &[u8]) -> DiskInode {
+        let mode = read_u16(raw, 0);
+        let mut size = read_u32(raw, 4) as u64;
+        if mode & S_IFMT == S_IFREG {
+            size |= (read_u32(raw, 108) as u64) << 32;
+        }
+        let mut block = [0u32; N_BLOCKS];
+        for (i, block) in block.iter_mut().enumerate() {
+            *block = read_u32(raw, 40 + i * 4);
+        }
+        DiskInode {
+            mode,
+            uid: read_u16(raw, 2) as u32 | (read_u16(raw, 120) as u32) << 16,
+            gid: read_u16(raw, 24) as u32 | (read_u16(raw, 122) as u32) << 16,
+            size,
+            atime: read_u32(raw, 8),
+            ctime: read_u32(raw, 12),
+            mtime: read_u32(raw, 16),
+            dtime: read_u32(raw, 20),
+            links_count: read_u16(raw, 26),
+            blocks: read_u32(raw, 28),
+            flags: read_u32(raw, 32),
+            block,
+            generation: read_u32(raw, 100),
+            file_acl: read_u32(raw, 104),
+        }
+    }
+
+    /// Write the inode to the at least 128 bytes `raw`, the other fields are kept.
+    pub fn write(&self, raw: &mut [u8]) {
+        write_u16(raw, 0, self.mode);
+        write_u16(raw, 2, self.uid as u16);
+        write_u32(raw, 4, self.size as u32);
+        write_u32(raw, 8, self.atime);
+        write_u32(raw, 12, self.ctime);
+        write_u32(raw, 16, self.mtime);
+        write_u32(raw, 20, self.dtime);
+        write_u16(raw, 24, self.gid as u16);
+        write_u16(raw, 26, self.links_count);
+        write_u32(raw, 28, self.blocks);
+        write_u32(raw, 32, self.flags);
+        for (i, &block) in self.block.iter().enumerate() {
+            write_u32(raw, 40 + i * 4, block);
+        }
+        write_u32(raw, 100, self.generation);
+        write_u32(raw, 104, self.file_acl);
+        if self.is_reg() {
+            write_u32(raw, 108, (self.size >> 32) as u32);
+        }
+        write_u16(raw, 120, (self.uid >> 16) as u16);
+        write_u16(raw, 122, (self.gid >> 16) as u16);
+    }
+
+    #[inline(always)]
+    pub fn is_reg(&self) -> bool {
+        self.mode & S_IFMT == S_IFREG
+    }
+
+    #[inline(always)]
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+
+    /// Check if it is a fast symlink, with the target in the block numbers, on a volume of
+    /// `block_size`.
+    pub fn is_fast_symlink(&self, block_size: u32) -> bool {
+        let acl_sectors = if self.file_acl != 0 { block_size >> 9 } else { 0 };
+        self.mode & S_IFMT == S_IFLNK && self.blocks == acl_sectors
+    }
+
+    /// Get the target of a fast symlink.
+    pub fn fast_symlink(&self, buf: &mut [u8; N_BLOCKS * 4]) -> usize {
+        for (i, block) in self.block.iter().enumerate() {
+            buf[i * 4..i * 4 + 4].copy_from_slice(&block.to_le_bytes());
+        }
+        (self.size as usize).min(FAST_SYMLINK_MAX)
+    }
+
+    /// Store the `target` of a fast symlink, at most [`FAST_SYMLINK_MAX`] bytes.
+    pub fn set_fast_symlink(&mut self, target: &[u8]) {
+        let mut buf = [0u8; N_BLOCKS * 4];
+        buf[..target.len()].copy_from_slice(target);
+        for (i, block) in self.block.iter_mut().enumerate() {
+            *block = read_u32(&buf, i * 4);
+        }
+        self.size = target.len() as u64;
+    }
+}
+
+/// Get the file type of a directory entry for the inode `mode`.
+pub fn file_type(mode: u16) -> u8 {
+    match mode & S_IFMT {
+        S_IFREG => FT_REG_FILE,
+        S_IFDIR => FT_DIR,
+        S_IFCHR => FT_CHRDEV,
+        S_IFBLK => FT_BLKDEV,
+        S_IFIFO => FT_FIFO,
+        S_IFSOCK => FT_SOCK,
+        S_IFLNK => FT_SYMLINK,
+        _ => FT_UNKNOWN,
+    }
+}
+
+/// The header of a directory entry.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct DirEntry {
+    /// Inode number, 0 for an unused entry.
+    pub inode: u32,
+    /// Length of the entry up to the next one.
+    pub rec_len: u16,
+    pub name_len: u8,
+    /// File type if the volume has the `filetype` feature, or the high byte of the name length.
+    pub file_type: u8,
+}
+
+impl DirEntry {
+    /// Parse the header from the 8 bytes `raw`.
+    pub fn parse(raw: &[u8]) -> DirEntry {
+        DirEntry {
+            inode: read_u32(raw, 0),
+            rec_len: read_u16(raw, 4),
+            name_len: raw[6],
+            file_type: raw[7],
+        }
+    }
+
+    /// Write the header to the 8 bytes `raw`.
+    pub fn write(&self, raw: &mut [u8]) {
+        write_u32(raw, 0, self.inode);
+        write_u16(raw, 4, self.rec_len);
+        raw[6] = self.name_len;
+        raw[7] = self.file_type;
+    }
+
+    /// Check the entry at `offset` of a block of `block_size` bytes: the length is aligned and
+    /// holds the name, and the entry does not cross the block.
+    pub fn is_valid(&self, offset: usize, block_size: usize) -> bool {
+        let rec_len = self.rec_len as usize;
+        rec_len % 4 == 0 && rec_len >= rec_len_of(self.name_len as usize) &&
+            offset + rec_len <= block_size
+    }
+
+    /// Space left after the name, for a new entry.
+    #[inline(always)]
+    pub fn free_space(&self) -> usize {
+        let used = if self.inode == 0 { 0 } else { rec_len_of(self.name_len as usize) };
+        self.rec_len as usize - used
+    }
+}
+
+/// Length of an entry with a name of `name_len` bytes, aligned to 4 bytes.
+#[inline(always)]
+pub const fn rec_len_of(name_len: usize) -> usize {
+    (DIR_ENTRY_HEADER + name_len + 3) & !3
+}
+
+/// Get the path of the logical block `index` of a file through the block numbers, with
+/// `per_block` numbers in an indirect block. Returns the offsets in the inode and in the
+/// indirect blocks, and the depth, or `None` beyond the triple indirect block.
+pub fn block_path(index: u64, per_block: u64) -> Option<([usize; 4], usize)> {
+    let mut path = [0usize; 4];
+    if index < N_DIRECT as u64 {
+        path[0] = index as usize;
+        return Some((path, 1));
+    }
+    let mut rest = index - N_DIRECT as u64;
+    let mut span = per_block;
+    for depth in 1..=3 {
+        if rest < span {
+            path[0] = IND_BLOCK + depth - 1;
+            for slot in &mut path[1..=depth] {
+                span /= per_block;
+                *slot = (rest / span) as usize;
+                rest %= span;
+            }
+            return Some((path, depth + 1));
+        }
+        rest -= span;
+        span *= per_block;
+    }
+    None
+}
+
+/// Find the first clear bit from `start` before `end` in the `bitmap`.
+pub fn find_zero_bit(bitmap: &[u8], start: usize, end: usize) -> Option<usize> {
+    let mut bit = start;
+    while bit < end {
+        let byte = bitmap[bit / 8];
+        if byte == 0xff && bit % 8 == 0 {
+            bit += 8;
+            continue;
+        }
+        if byte & (1 << (bit % 8)) == 0 {
+            return Some(bit);
+        }
+        bit += 1;
+    }
+    None
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build the superblock of a volume of 8192 1KiB blocks in one group.
+    fn superblock() -> [u8; SUPERBLOCK_SIZE] {
+        let mut raw = [0u8; SUPERBLOCK_SIZE];
+        write_u32(&mut raw, 0, 2048);
+        write_u32(&mut raw, 4, 8192);
+        write_u32(&mut raw, 12, 7000);
+        write_u32(&mut raw, 16, 2000);
+        write_u32(&mut raw, 20, 1);
+        write_u32(&mut raw, 32, 8192);
+        write_u32(&mut raw, 40, 2048);
+        write_u16(&mut raw, 56, EXT2_MAGIC);
+        write_u16(&mut raw, 58, STATE_VALID);
+        write_u32(&mut raw, 76, 1);
+        write_u32(&mut raw, 84, 11);
+        write_u16(&mut raw, 88, 256);
+        write_u32(&mut raw, 96, FEATURE_INCOMPAT_FILETYPE);
+        raw
+    }
+
+    #[test]
+    fn parse_superblock() {
+        let mut raw = superblock();
+        let mut sb = SuperBlock::parse(&raw).unwrap();
+        assert_eq!(sb.block_size(), 1024);
+        assert_eq!(sb.group_count(), 1);
+        assert_eq!(sb.group_blocks(0), 8191);
+        assert_eq!(sb.group_desc_block(), 2);
+        assert_eq!(sb.inode_group(ROOT_INO), (0, 1));
+        assert_eq!(sb.inode_size, 256);
+        assert!(sb.has_file_type());
+        assert_eq!(sb.check_features(), Ok(false));
+
+        sb.state &= !STATE_VALID;
+        sb.free_blocks_count = 6000;
+        sb.write(&mut raw);
+        assert_eq!(SuperBlock::parse(&raw), Ok(sb));
+
+        write_u32(&mut raw, 100, 0x400);
+        assert_eq!(SuperBlock::parse(&raw).unwrap().check_features(), Ok(true));
+        write_u32(&mut raw, 96, 0x40 | FEATURE_INCOMPAT_FILETYPE);
+        assert_eq!(SuperBlock::parse(&raw).unwrap().check_features(),
+                   Err(Ext2Error::Incompatible(0x40)));
+        write_u32(&mut raw, 40, 1024);
+        assert_eq!(SuperBlock::parse(&raw), Err(Ext2Error::BadGeometry));
+        write_u16(&mut raw, 56, 0);
+        assert_eq!(SuperBlock::parse(&raw), Err(Ext2Error::BadMagic));
+    }
+
+    #[test]
+    fn inodes() {
+        let mut raw = [0u8; 128];
+        let mut inode = DiskInode {
+            mode: S_IFREG | 0o644,
+            uid: 0x12345,
+            gid: 100,
+            size: 5 << 32 | 42,
+            links_count: 2,
+            blocks: 16,
+            ..DiskInode::default()
+        };
+        inode.block[IND_BLOCK] = 77;
+        inode.write(&mut raw);
+        assert_eq!(DiskInode::parse(&raw), inode);
+        assert_eq!(read_u32(&raw, 108), 5);
+        assert_eq!(file_type(inode.mode), FT_REG_FILE);
+
+        let mut link = DiskInode { mode: S_IFLNK | 0o777, ..DiskInode::default() };
+        link.set_fast_symlink(b"/usr/lib/target");
+        assert!(link.is_fast_symlink(1024));
+        link.write(&mut raw);
+        let mut buf = [0u8; N_BLOCKS * 4];
+        let len = DiskInode::parse(&raw).fast_symlink(&mut buf);
+        assert_eq!(&buf[..len], b"/usr/lib/target");
+        // The high size bits are the directory ACL of the other inodes.
+        assert_eq!(read_u32(&raw, 108), 5);
+    }
+
+    #[test]
+    fn dir_entries() {
+        let entry = DirEntry { inode: 12, rec_len: 1012, name_len: 5, file_type: FT_DIR };
+        let mut raw = [0u8; DIR_ENTRY_HEADER];
+        entry.write(&mut raw);
+        assert_eq!(DirEntry::parse(&raw), entry);
+        assert_eq!(rec_len_of(5), 16);
+        assert_eq!(rec_len_of(8), 16);
+        assert_eq!(entry.free_space(), 996);
+        assert!(entry.is_valid(12, 1024));
+        assert!(!entry.is_valid(16, 1024));
+        assert!(!DirEntry { rec_len: 10, ..entry }.is_valid(0, 1024));
+    }
+
+    #[test]
+    fn block_paths() {
+        assert_eq!(block_path(3, 256), Some(([3, 0, 0, 0], 1)));
+        assert_eq!(block_path(12, 256), Some(([12, 0, 0, 0], 2)));
+        assert_eq!(block_path(12 + 255, 256), Some(([12, 255, 0, 0], 2)));
+        assert_eq!(block_path(12 + 256, 256), Some(([13, 0, 0, 0], 3)));
+        assert_eq!(block_path(12 + 256 + 256 * 256 - 1, 256), Some(([13, 255, 255, 0], 3)));
+        let triple = 12 + 256 + 256 * 256;
+        assert_eq!(block_path(triple + 256 + 3, 256), Some(([14, 0, 1, 3], 4)));
+        assert_eq!(block_path(triple + 256 * 256 * 256, 256), None);
+    }
+
+    #[test]
+    fn bitmaps() {
+        let mut bitmap = [0xffu8; 4];
+        assert_eq!(find_zero_bit(&bitmap, 0, 32), None);
+        bitmap[2] = 0b1110_1111;
+        assert_eq!(find_zero_bit(&bitmap, 0, 32), Some(20));
+        assert_eq!(find_zero_bit(&bitmap, 21, 32), None);
+        assert_eq!(find_zero_bit(&bitmap, 0, 20), None);
+    }
+}
@@ -0,0 +1,824 @@
+//! On-disk format of the FAT12/16/32 file systems.
+//!
+//! - [`Volume`] is the geometry read from the boot sector (the BIOS parameter block). The FAT
+//!   type is decided by the count of the clusters, as the specification says.
+//! - The entries of the file allocation table chain the clusters of a file, see
+//!   [`Volume::fat_entry`] and [`Volume::set_fat_entry`].
+//! - A directory is an array of the 32-byte [`DirEntry`]s. A long file name (VFAT) is stored in
+//!   the long name entries before the short entry, in the reverse order, and checked by the
+//!   [`lfn_checksum`] of the short name; [`LongName`] collects them while scanning.
+//! - A name which does not fit a short entry gets a generated short name with a numeric tail,
+//!   e.g. `LONGFI~1.TXT` for `longfilename.txt`.
+//!
+//! The offsets are in bytes from the start of the volume. Nothing here panics on a malformed
+//! volume.
+
+use core::fmt;
+
+
+/// Size of a directory entry.
+pub const DIR_ENTRY_SIZE: usize = 32;
+
+// Attributes of a directory entry.
+pub const ATTR_READ_ONLY: u8 = 0x01;
+pub const ATTR_HIDDEN: u8 = 0x02;
+pub const ATTR_SYSTEM: u8 = 0x04;
+pub const ATTR_VOLUME_ID: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+/// Attributes of a long name entry.
+pub const ATTR_LONG_NAME: u8 = 0x0f;
+
+/// First name byte of a free entry.
+pub const ENTRY_FREE: u8 = 0xe5;
+/// First name byte of the free entry ending the directory.
+pub const ENTRY_END: u8 = 0x00;
+/// Names of the `.` and `..` entries of a subdirectory.
+pub const DOT_NAME: [u8; 11] = *b".          ";
+pub const DOTDOT_NAME: [u8; 11] = *b"..         ";
+/// First name byte standing for `0xe5` in a used entry.
+const ENTRY_KANJI_E5: u8 = 0x05;
+
+// Case flags of the short name in the `nt_res` byte, used by Windows NT and Linux.
+/// The base name is in lower case.
+pub const CASE_LOWER_BASE: u8 = 0x08;
+/// The extension is in lower case.
+pub const CASE_LOWER_EXT: u8 = 0x10;
+
+/// Flag of the ordinal of the last long name entry, which is stored first.
+pub const LFN_LAST: u8 = 0x40;
+/// Count of the UTF-16 units in a long name entry.
+pub const LFN_CHARS: usize = 13;
+/// Max length of a long name in UTF-16 units.
+pub const LFN_MAX: usize = 255;
+/// Offsets of the UTF-16 units in a long name entry.
+const LFN_OFFSETS: [usize; LFN_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+/// Characters allowed in a short name besides the letters and the digits.
+const SHORT_SPECIAL: &[u8] = b"!#$%&'()-@^_`{}~";
+
+/// FAT32 FSInfo signatures, at the offsets 0 and 484.
+const FSINFO_LEAD_SIG: u32 = 0x4161_5252;
+const FSINFO_STRUCT_SIG: u32 = 0x6141_7272;
+/// Offset of the free cluster count in the FSInfo sector, the next free cluster hint follows.
+const FSINFO_FREE_COUNT: usize = 488;
+
+/// Boot sector errors.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FatError {
+    /// The boot signature `0x55 0xaa` is missing.
+    NoSignature,
+    /// A field of the BIOS parameter block is invalid.
+    BadGeometry,
+}
+
+impl fmt::Display for FatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            FatError::NoSignature => "no boot signature",
+            FatError::BadGeometry => "bad BIOS parameter block",
+        };
+        f.write_str(msg)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+/// A FAT entry.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FatEntry {
+    Free,
+    /// The next cluster of the chain.
+    Next(u32),
+    /// The last cluster of the chain.
+    End,
+    /// A bad cluster, or an invalid value.
+    Bad,
+}
+
+#[inline(always)]
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+#[inline(always)]
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+/// Geometry of a FAT volume.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Volume {
+    pub fat_type: FatType,
+    pub bytes_per_sector: u32,
+    pub sectors_per_cluster: u32,
+    pub reserved_sectors: u32,
+    pub num_fats: u32,
+    /// Sectors of a FAT.
+    pub fat_sectors: u32,
+    /// Count of the entries of the fixed root directory of FAT12/16.
+    pub root_entries: u32,
+    pub total_sectors: u32,
+    /// First sector of cluster 2.
+    pub data_sector: u32,
+    /// Count of the data clusters, numbered from 2.
+    pub cluster_count: u32,
+    /// First cluster of the root directory of FAT32.
+    pub root_cluster: u32,
+    /// Sector of the FAT32 FSInfo, 0 if none.
+    pub fsinfo_sector: u32,
+    /// The FATs are mirrored, or only the `active_fat` is used.
+    pub mirror: bool,
+    pub active_fat: u32,
+}
+
+impl Volume {
+    /// Parse the boot sector.
+    pub fn parse(bs: &[u8; 512]) -> Result<Volume, FatError> {
+        if bs[510] != 0x55 || bs[511] != 0xaa {
+            return Err(FatError::NoSignature);
+        }
+        let bytes_per_sector = read_u16(bs, 11) as u32;
+        let sectors_per_cluster = bs[13] as u32;
+        let reserved_sectors = read_u16(bs, 14) as u32;
+        let num_fats = bs[16] as u32;
+        let root_entries = read_u16(bs, 17) as u32;
+        let total_sectors = match read_u16(bs, 19) {
+            0 => read_u32(bs, 32),
+            total => total as u32,
+        };
+        let fat_sectors = match read_u16(bs, 22) {
+            0 => read_u32(bs, 36),
+            sectors => sectors as u32,
+        };
+        if !matches!(bytes_per_sector, 512 | 1024 | 2048 | 4096) ||
+            !sectors_per_cluster.is_power_of_two() || reserved_sectors == 0 || num_fats == 0 ||
+            fat_sectors == 0 {
+            return Err(FatError::BadGeometry);
+        }
+
+        let root_size = root_entries * DIR_ENTRY_SIZE as u32;
+        let root_sectors = (root_size + bytes_per_sector - 1) / bytes_per_sector;
+        let data_sector = num_fats.checked_mul(fat_sectors)
+            .and_then(|sectors| sectors.checked_add(reserved_sectors + root_sectors))
+            .filter(|&data| data < total_sectors)
+            .ok_or(FatError::BadGeometry)?;
+        let cluster_count = (total_sectors - data_sector) / sectors_per_cluster;
+        let fat_type = match cluster_count {
+            0 => return Err(FatError::BadGeometry),
+            1..=4084 => FatType::Fat12,
+            4085..=65524 => FatType::Fat16,
+            _ => FatType::Fat32,
+        };
+
+        let mut volume = Volume {
+            fat_type,
+            bytes_per_sector,
+            sectors_per_cluster,
+            reserved_sectors,
+            num_fats,
+            fat_sectors,
+            root_entries,
+            total_sectors,
+            data_sector,
+            cluster_count,
+            root_cluster: 0,
+            fsinfo_sector: 0,
+            mirror: true,
+            active_fat: 0,
+        };
+        if fat_type == FatType::Fat32 {
+            let ext_flags = read_u16(bs, 40);
+            volume.mirror = ext_flags & 0x80 == 0;
+            volume.active_fat = if volume.mirror { 0 } else { (ext_flags & 0xf) as u32 };
+            volume.root_cluster = read_u32(bs, 44);
+            volume.fsinfo_sector = match read_u16(bs, 48) as u32 {
+                0xffff => 0,
+                sector => sector,
+            };
+            if root_entries != 0 || !volume.is_data_cluster(volume.root_cluster) ||
+                volume.active_fat >= num_fats {
+                return Err(FatError::BadGeometry);
+            }
+        } else if root_entries == 0 {
+            return Err(FatError::BadGeometry);
+        }
+        // The FAT must cover all clusters.
+        let fat_bits = match fat_type {
+            FatType::Fat12 => 12,
+            FatType::Fat16 => 16,
+            FatType::Fat32 => 32,
+        };
+        if (fat_sectors as u64 * bytes_per_sector as u64 * 8) / fat_bits <
+            cluster_count as u64 + 2 {
+            return Err(FatError::BadGeometry);
+        }
+        Ok(volume)
+    }
+
+    /// Size of a cluster in bytes.
+    #[inline(always)]
+    pub fn cluster_size(&self) -> u32 {
+        self.bytes_per_sector * self.sectors_per_cluster
+    }
+
+    /// Check if `cluster` is a data cluster of the volume.
+    #[inline(always)]
+    pub fn is_data_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster - 2 < self.cluster_count
+    }
+
+    /// Offset of the data `cluster`.
+    pub fn cluster_offset(&self, cluster: u32) -> u64 {
+        (self.data_sector as u64 + (cluster - 2) as u64 * self.sectors_per_cluster as u64) *
+            self.bytes_per_sector as u64
+    }
+
+    /// Offset of the fixed root directory of FAT12/16.
+    pub fn root_dir_offset(&self) -> u64 {
+        (self.reserved_sectors + self.num_fats * self.fat_sectors) as u64 *
+            self.bytes_per_sector as u64
+    }
+
+    /// Size of the fixed root directory of FAT12/16 in bytes.
+    pub fn root_dir_size(&self) -> u64 {
+        self.root_entries as u64 * DIR_ENTRY_SIZE as u64
+    }
+
+    /// Offset of the FAT `index`.
+    pub fn fat_offset(&self, index: u32) -> u64 {
+        (self.reserved_sectors + index * self.fat_sectors) as u64 * self.bytes_per_sector as u64
+    }
+
+    /// Offset of the entry of `cluster` in a FAT, and the count of bytes holding it.
+    pub fn fat_entry_pos(&self, cluster: u32) -> (u64, usize) {
+        match self.fat_type {
+            FatType::Fat12 => (cluster as u64 + cluster as u64 / 2, 2),
+            FatType::Fat16 => (cluster as u64 * 2, 2),
+            FatType::Fat32 => (cluster as u64 * 4, 4),
+        }
+    }
+
+    /// Get the raw entry of `cluster` from the bytes `raw` at [`fat_entry_pos`].
+    ///
+    /// [`fat_entry_pos`]: Volume::fat_entry_pos
+    pub fn fat_entry_raw(&self, cluster: u32, raw: &[u8]) -> u32 {
+        match self.fat_type {
+            FatType::Fat12 => {
+                let value = read_u16(raw, 0) as u32;
+                if cluster & 1 != 0 { value >> 4 } else { value & 0xfff }
+            }
+            FatType::Fat16 => read_u16(raw, 0) as u32,
+            FatType::Fat32 => read_u32(raw, 0) & 0x0fff_ffff,
+        }
+    }
+
+    /// Decode the entry of `cluster` from the bytes `raw` at [`fat_entry_pos`].
+    ///
+    /// [`fat_entry_pos`]: Volume::fat_entry_pos
+    pub fn fat_entry(&self, cluster: u32, raw: &[u8]) -> FatEntry {
+        let value = self.fat_entry_raw(cluster, raw);
+        let end_min = self.end_of_chain() & !7;
+        match value {
+            0 => FatEntry::Free,
+            _ if value >= end_min => FatEntry::End,
+            _ if self.is_data_cluster(value) => FatEntry::Next(value),
+            _ => FatEntry::Bad,
+        }
+    }
+
+    /// Set the entry of `cluster` in the bytes `raw` at [`fat_entry_pos`] to `value`, the other
+    /// bits are kept.
+    ///
+    /// [`fat_entry_pos`]: Volume::fat_entry_pos
+    pub fn set_fat_entry(&self, cluster: u32, raw: &mut [u8], value: u32) {
+        match self.fat_type {
+            FatType::Fat12 => {
+                let old = read_u16(raw, 0);
+                let new = if cluster & 1 != 0 {
+                    (old & 0x000f) | ((value as u16 & 0xfff) << 4)
+                } else {
+                    (old & 0xf000) | (value as u16 & 0xfff)
+                };
+                raw[..2].copy_from_slice(&new.to_le_bytes());
+            }
+            FatType::Fat16 => raw[..2].copy_from_slice(&(value as u16).to_le_bytes()),
+            FatType::Fat32 => {
+                let new = (read_u32(raw, 0) & 0xf000_0000) | (value & 0x0fff_ffff);
+                raw[..4].copy_from_slice(&new.to_le_bytes());
+            }
+        }
+    }
+
+    /// Value of the FAT entry of the last cluster of a chain.
+    pub fn end_of_chain(&self) -> u32 {
+        match self.fat_type {
+            FatType::Fat12 => 0xfff,
+            FatType::Fat16 => 0xffff,
+            FatType::Fat32 => 0x0fff_ffff,
+        }
+    }
+}
+
+/// The free cluster hints in the FAT32 FSInfo sector, `u32::MAX` if unknown.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FsInfo {
+    pub free_count: u32,
+    pub next_free: u32,
+}
+
+impl FsInfo {
+    /// Parse the FSInfo `sector`, `None` if the signatures do not match.
+    pub fn parse(sector: &[u8; 512]) -> Option<FsInfo> {
+        if read_u32(sector, 0) != FSINFO_LEAD_SIG || read_u32(sector, 484) != FSINFO_STRUCT_SIG {
+            return None;
+        }
+        Some(FsInfo {
+            free_count: read_u32(sector, FSINFO_FREE_COUNT),
+            next_free: read_u32(sector, FSINFO_FREE_COUNT + 4),
+        })
+    }
+
+    /// Write the hints to the FSInfo `sector`.
+    pub fn write(&self, sector: &mut [u8; 512]) {
+        sector[FSINFO_FREE_COUNT..FSINFO_FREE_COUNT + 4]
+            .copy_from_slice(&self.free_count.to_le_bytes());
+        sector[FSINFO_FREE_COUNT + 4..FSINFO_FREE_COUNT + 8]
+            .copy_from_slice(&self.next_free.to_le_bytes());
+    }
+}
+
+/// A short directory entry.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct DirEntry {
+    /// The 8.3 name padded by spaces, without the dot.
+    pub name: [u8; 11],
+    pub attr: u8,
+    /// Case flags, see [`CASE_LOWER_BASE`] and [`CASE_LOWER_EXT`].
+    pub case: u8,
+    /// Creation time in 10ms units, 0 to 199.
+    pub ctime_cs: u8,
+    pub ctime: u16,
+    pub cdate: u16,
+    pub adate: u16,
+    pub first_cluster: u32,
+    pub mtime: u16,
+    pub mdate: u16,
+    pub size: u32,
+}
+
+impl DirEntry {
+    /// Parse a short entry from the 32 bytes `raw`.
+    pub fn parse(raw: &[u8]) -> DirEntry {
+        let mut name = [0u8; 11];
+        name.copy_from_slice(&raw[..11]);
+        DirEntry {
+            name,
+            attr: raw[11],
+            case: raw[12],
+            ctime_cs: raw[13],
+            ctime: read_u16(raw, 14),
+            cdate: read_u16(raw, 16),
+            adate: read_u16(raw, 18),
+            first_cluster: (read_u16(raw, 20) as u32) << 16 | read_u16(raw, 26) as u32,
+            mtime: read_u16(raw, 22),
+            mdate: read_u16(raw, 24),
+            size: read_u32(raw, 28),
+        }
+    }
+
+    /// Write the entry to the 32 bytes `raw`.
+    pub fn write(&self, raw: &mut [u8]) {
+        raw[..11].copy_from_slice(&self.name);
+        raw[11] = self.attr;
+        raw[12] = self.case;
+        raw[13] = self.ctime_cs;
+        raw[14..16].copy_from_slice(&self.ctime.to_le_bytes());
+        raw[16..18].copy_from_slice(&self.cdate.to_le_bytes());
+        raw[18..20].copy_from_slice(&self.adate.to_le_bytes());
+        raw[20..22].copy_from_slice(&((self.first_cluster >> 16) as u16).to_le_bytes());
+        raw[22..24].copy_from_slice(&self.mtime.to_le_bytes());
+        raw[24..26].copy_from_slice(&self.mdate.to_le_bytes());
+        raw[26..28].copy_from_slice(&(self.first_cluster as u16).to_le_bytes());
+        raw[28..32].copy_from_slice(&self.size.to_le_bytes());
+    }
+
+    #[inline(always)]
+    pub fn is_dir(&self) -> bool {
+        self.attr & ATTR_DIRECTORY != 0
+    }
+
+    /// Check if it is the `.` or `..` entry, no other short name starts with a dot.
+    #[inline(always)]
+    pub fn is_dot(&self) -> bool {
+        self.name[0] == b'.'
+    }
+
+    /// Check if it is the volume label, which is not a file.
+    #[inline(always)]
+    pub fn is_volume_label(&self) -> bool {
+        self.attr & (ATTR_VOLUME_ID | ATTR_DIRECTORY) == ATTR_VOLUME_ID
+    }
+
+    /// Write the displayed short name, e.g. `readme.txt` with the case flags applied, to `buf`.
+    /// Returns the length.
+    pub fn display_name(&self, buf: &mut [u8; 12]) -> usize {
+        let mut len = 0;
+        let mut push = |c: u8, lower: bool| {
+            buf[len] = if lower { c.to_ascii_lowercase() } else { c };
+            len += 1;
+        };
+        let base_len = self.name[..8].iter().rposition(|&c| c != b' ').map_or(0, |i| i + 1);
+        for (i, &c) in self.name[..base_len].iter().enumerate() {
+            let c = if i == 0 && c == ENTRY_KANJI_E5 { ENTRY_FREE } else { c };
+            push(c, self.case & CASE_LOWER_BASE != 0);
+        }
+        let ext_len = self.name[8..].iter().rposition(|&c| c != b' ').map_or(0, |i| i + 1);
+        if ext_len > 0 {
+            push(b'.', false);
+            for &c in &self.name[8..8 + ext_len] {
+                push(c, self.case & CASE_LOWER_EXT != 0);
+            }
+        }
+        len
+    }
+}
+
+/// Check if the entry `raw` is a long name entry.
+#[inline(always)]
+pub fn is_lfn_entry(raw: &[u8]) -> bool {
+    raw[11] & 0x3f == ATTR_LONG_NAME
+}
+
+/// Checksum of the short `name`, stored in its long name entries.
+pub fn lfn_checksum(name: &[u8; 11]) -> u8 {
+    name.iter().fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c))
+}
+
+/// Count of the long name entries for a name of `len` UTF-16 units.
+#[inline(always)]
+pub fn lfn_entry_count(len: usize) -> usize {
+    (len + LFN_CHARS - 1) / LFN_CHARS
+}
+
+/// Fill the 32 bytes `raw` with the long name entry `seq` (from 1) of the UTF-16 `name`, which
+/// holds the units from `(seq - 1) * 13`. The name is terminated by a 0 and padded by `0xffff`.
+pub fn write_lfn_entry(raw: &mut [u8], name: &[u16], seq: usize, checksum: u8) {
+    let last = seq == lfn_entry_count(name.len());
+    raw[..DIR_ENTRY_SIZE].fill(0);
+    raw[0] = seq as u8 | if last { LFN_LAST } else { 0 };
+    raw[11] = ATTR_LONG_NAME;
+    raw[13] = checksum;
+    let start = (seq - 1) * LFN_CHARS;
+    for (i, &offset) in LFN_OFFSETS.iter().enumerate() {
+        let unit = match name.get(start + i) {
+            Some(&unit) => unit,
+            None if start + i == name.len() => 0,
+            None => 0xffff,
+        };
+        raw[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+    }
+}
+
+/// Collects the long name entries before a short entry.
+pub struct LongName {
+    units: [u16; LFN_MAX + LFN_CHARS],
+    /// Ordinal of the next expected entry, 0 if none.
+    next: u8,
+    checksum: u8,
+    len: usize,
+}
+
+impl LongName {
+    pub const fn new() -> LongName {
+        LongName { units: [0; LFN_MAX + LFN_CHARS], next: 0, checksum: 0, len: 0 }
+    }
+
+    /// Drop the collected entries.
+    #[inline(always)]
+    pub fn reset(&mut self) {
+        self.next = 0;
+    }
+
+    /// Add the long name entry `raw`. A broken sequence is dropped.
+    pub fn push(&mut self, raw: &[u8]) {
+        let ord = raw[0];
+        let seq = ord & 0x1f;
+        if ord & LFN_LAST != 0 {
+            if seq == 0 || seq as usize * LFN_CHARS > self.units.len() {
+                self.next = 0;
+                return;
+            }
+            self.checksum = raw[13];
+            self.len = seq as usize * LFN_CHARS;
+        } else if seq == 0 || seq != self.next || raw[13] != self.checksum {
+            self.next = 0;
+            return;
+        }
+        let start = (seq as usize - 1) * LFN_CHARS;
+        for (i, &offset) in LFN_OFFSETS.iter().enumerate() {
+            self.units[start + i] = read_u16(raw, offset);
+        }
+        self.next = seq - 1;
+    }
+
+    /// Finish at the short entry of the `name`. Returns the long name in UTF-16 if the entries
+    /// are complete and match the checksum of the short name.
+    pub fn finish(&mut self, name: &[u8; 11]) -> Option<&[u16]> {
+        // After the first entry, the next expected ordinal is 0.
+        let complete = self.next == 0 && self.len > 0 && self.checksum == lfn_checksum(name);
+        let len = core::mem::take(&mut self.len);
+        if !complete {
+            return None;
+        }
+        let units = &self.units[..len];
+        let len = units.iter().position(|&unit| unit == 0).unwrap_or(len);
+        if len == 0 || len > LFN_MAX { None } else { Some(&units[..len]) }
+    }
+}
+
+/// Map a character of a name to the short name character, `None` if it is not allowed.
+fn short_char(c: char) -> Option<u8> {
+    if !c.is_ascii() {
+        return None;
+    }
+    let c = c as u8;
+    if c.is_ascii_alphanumeric() || SHORT_SPECIAL.contains(&c) {
+        Some(c.to_ascii_uppercase())
+    } else {
+        None
+    }
+}
+
+/// Get the case flag of a part of a short name, `None` if it mixes the cases.
+fn part_case(part: &str, lower_flag: u8) -> Option<u8> {
+    let lower = part.bytes().any(|c| c.is_ascii_lowercase());
+    let upper = part.bytes().any(|c| c.is_ascii_uppercase());
+    match (lower, upper) {
+        (true, true) => None,
+        (true, false) => Some(lower_flag),
+        _ => Some(0),
+    }
+}
+
+/// Make the short entry name of `name` if it can be stored in a short entry only: an 8.3 name of
+/// the allowed characters, whose base name and extension are each in one case. Returns the
+/// name and the case flags.
+pub fn exact_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, ext) = match name.rfind('.') {
+        Some(dot) => (&name[..dot], &name[dot + 1..]),
+        None => (name, ""),
+    };
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 || (ext.is_empty() && base != name) {
+        return None;
+    }
+    let mut short = [b' '; 11];
+    for (i, c) in base.chars().enumerate() {
+        short[i] = short_char(c)?;
+    }
+    for (i, c) in ext.chars().enumerate() {
+        short[8 + i] = short_char(c)?;
+    }
+    if short[0] == ENTRY_FREE {
+        short[0] = ENTRY_KANJI_E5;
+    }
+    Some((short, part_case(base, CASE_LOWER_BASE)? | part_case(ext, CASE_LOWER_EXT)?))
+}
+
+/// Make the short entry name of a long `name` with the numeric tail `~n`, e.g. `LONGFI~1TXT`
+/// for `longfilename.txt` and `n = 1`. The characters not allowed become `_`, the spaces and
+/// the dots other than the extension dot are dropped.
+pub fn numbered_short_name(name: &str, n: u32) -> [u8; 11] {
+    let name = name.trim_start_matches('.');
+    let (base, ext) = match name.rfind('.') {
+        Some(dot) => (&name[..dot], &name[dot + 1..]),
+        None => (name, ""),
+    };
+    let mut short = [b' '; 11];
+    let convert = |c: char| match c {
+        ' ' | '.' => None,
+        _ => Some(short_char(c).unwrap_or(b'_')),
+    };
+
+    let mut tail = [0u8; 11];
+    let mut tail_len = 0;
+    tail[0] = b'~';
+    let mut digits = [0u8; 10];
+    let mut count = 0;
+    let mut value = n.max(1);
+    while value > 0 {
+        digits[count] = b'0' + (value % 10) as u8;
+        value /= 10;
+        count += 1;
+    }
+    tail_len += 1;
+    for &digit in digits[..count].iter().rev() {
+        tail[tail_len] = digit;
+        tail_len += 1;
+    }
+    let tail_len = tail_len.min(7);
+
+    let mut len = 0;
+    for c in base.chars().filter_map(convert) {
+        if len == 8 - tail_len {
+            break;
+        }
+        short[len] = c;
+        len += 1;
+    }
+    if len == 0 {
+        short[0] = b'_';
+        len = 1;
+    }
+    short[len..len + tail_len].copy_from_slice(&tail[..tail_len]);
+    for (i, c) in ext.chars().filter_map(convert).take(3).enumerate() {
+        short[8 + i] = c;
+    }
+    if short[0] == ENTRY_FREE {
+        short[0] = ENTRY_KANJI_E5;
+    }
+    short
+}
+
+/// Days from 1970-01-01 to the date, in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// The date of the days from 1970-01-01, in the proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Convert a FAT `date` and `time` to the seconds since the Unix epoch. The FAT times are local
+/// times, taken as UTC. An invalid date reads as 1980-01-01.
+pub fn fat_time_to_unix(date: u16, time: u16) -> u64 {
+    let year = 1980 + (date >> 9) as i64;
+    let month = ((date >> 5) & 0xf).clamp(1, 12) as u32;
+    let day = (date & 0x1f).max(1) as u32;
+    let secs = ((time >> 11) as u64 * 60 + ((time >> 5) & 0x3f) as u64) * 60 +
+        (time & 0x1f) as u64 * 2;
+    days_from_civil(year, month, day) as u64 * 86400 + secs
+}
+
+/// Convert the seconds since the Unix epoch to a FAT date and time, which is clamped to the
+/// range from 1980 to 2107.
+pub fn unix_to_fat_time(secs: u64) -> (u16, u16) {
+    let min = days_from_civil(1980, 1, 1) as u64 * 86400;
+    let max = days_from_civil(2108, 1, 1) as u64 * 86400 - 2;
+    let secs = secs.clamp(min, max);
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let rem = secs % 86400;
+    let date = ((year - 1980) as u16) << 9 | (month as u16) << 5 | day as u16;
+    let time = ((rem / 3600) as u16) << 11 | ((rem / 60 % 60) as u16) << 5 | (rem % 60 / 2) as u16;
+    (date, time)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build the boot sector of a FAT16 volume of `total` sectors.
+    fn fat16_boot_sector(total: u32) -> [u8; 512] {
+        let mut bs = [0u8; 512];
+        bs[11..13].copy_from_slice(&512u16.to_le_bytes());
+        bs[13] = 4;
+        bs[14..16].copy_from_slice(&4u16.to_le_bytes());
+        bs[16] = 2;
+        bs[17..19].copy_from_slice(&512u16.to_le_bytes());
+        bs[32..36].copy_from_slice(&total.to_le_bytes());
+        bs[22..24].copy_from_slice(&64u16.to_le_bytes());
+        bs[510] = 0x55;
+        bs[511] = 0xaa;
+        bs
+    }
+
+    #[test]
+    fn parse_volume() {
+        let mut bs = fat16_boot_sector(65536);
+        let volume = Volume::parse(&bs).unwrap();
+        assert_eq!(volume.fat_type, FatType::Fat16);
+        assert_eq!(volume.data_sector, 4 + 2 * 64 + 32);
+        assert_eq!(volume.cluster_count, (65536 - 164) / 4);
+        assert_eq!(volume.cluster_size(), 2048);
+        assert_eq!(volume.root_dir_offset(), 132 * 512);
+        assert_eq!(volume.cluster_offset(2), 164 * 512);
+
+        // Too few clusters for FAT16.
+        bs[13] = 64;
+        assert_eq!(Volume::parse(&bs).unwrap().fat_type, FatType::Fat12);
+        bs[13] = 3;
+        assert_eq!(Volume::parse(&bs), Err(FatError::BadGeometry));
+        bs[510] = 0;
+        assert_eq!(Volume::parse(&bs), Err(FatError::NoSignature));
+    }
+
+    #[test]
+    fn fsinfo() {
+        let mut sector = [0u8; 512];
+        assert_eq!(FsInfo::parse(&sector), None);
+        sector[..4].copy_from_slice(&FSINFO_LEAD_SIG.to_le_bytes());
+        sector[484..488].copy_from_slice(&FSINFO_STRUCT_SIG.to_le_bytes());
+        let info = FsInfo { free_count: 1000, next_free: 42 };
+        info.write(&mut sector);
+        assert_eq!(FsInfo::parse(&sector), Some(info));
+        assert_eq!(sector[510], 0);
+    }
+
+    #[test]
+    fn fat_entries() {
+        let mut volume = Volume::parse(&fat16_boot_sector(65536)).unwrap();
+        volume.fat_type = FatType::Fat12;
+        let mut fat = [0u8; 9];
+        for (cluster, value) in [(2u32, 0x123u32), (3, 0xfff), (4, 0x456)] {
+            let (offset, len) = volume.fat_entry_pos(cluster);
+            let offset = offset as usize;
+            volume.set_fat_entry(cluster, &mut fat[offset..offset + len], value);
+        }
+        assert_eq!(fat[3..6], [0x23, 0xf1, 0xff]);
+        let entry = |cluster: u32| {
+            let (offset, len) = volume.fat_entry_pos(cluster);
+            volume.fat_entry(cluster, &fat[offset as usize..offset as usize + len])
+        };
+        assert_eq!(entry(2), FatEntry::Next(0x123));
+        assert_eq!(entry(3), FatEntry::End);
+        assert_eq!(entry(4), FatEntry::Next(0x456));
+        assert_eq!(entry(5), FatEntry::Free);
+
+        volume.fat_type = FatType::Fat32;
+        let mut raw = 0xf000_0000u32.to_le_bytes();
+        volume.set_fat_entry(7, &mut raw, 0x0fff_fff7);
+        assert_eq!(u32::from_le_bytes(raw), 0xffff_fff7);
+        assert_eq!(volume.fat_entry(7, &raw), FatEntry::Bad);
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(exact_short_name("README.TXT"), Some((*b"README  TXT", 0)));
+        assert_eq!(exact_short_name("readme.TXT"), Some((*b"README  TXT", CASE_LOWER_BASE)));
+        assert_eq!(exact_short_name("Readme.txt"), None);
+        assert_eq!(exact_short_name("longfilename.txt"), None);
+        assert_eq!(exact_short_name("a.b.c"), None);
+        assert_eq!(exact_short_name("a b"), None);
+        assert_eq!(numbered_short_name("longfilename.txt", 1), *b"LONGFI~1TXT");
+        assert_eq!(numbered_short_name(".bashrc", 12), *b"BASHR~12   ");
+        assert_eq!(numbered_short_name("my file+.tar.gz", 3), *b"MYFILE~3GZ ");
+
+        let entry = DirEntry { name: *b"README  TXT", case: CASE_LOWER_EXT, ..DirEntry::default() };
+        let mut buf = [0u8; 12];
+        let len = entry.display_name(&mut buf);
+        assert_eq!(&buf[..len], b"README.txt");
+
+        // A long name over two entries, stored in the reverse order.
+        let name: std::vec::Vec<u16> = "A long file name.txt".encode_utf16().collect();
+        let short = numbered_short_name("A long file name.txt", 1);
+        let checksum = lfn_checksum(&short);
+        let mut entries = [[0u8; DIR_ENTRY_SIZE]; 2];
+        for (i, raw) in entries.iter_mut().enumerate() {
+            write_lfn_entry(raw, &name, 2 - i, checksum);
+        }
+        assert_eq!(entries[0][0], 2 | LFN_LAST);
+        let mut long = LongName::new();
+        for raw in &entries {
+            long.push(raw);
+        }
+        assert_eq!(long.finish(&short), Some(&name[..]));
+        // A mismatched checksum drops the name.
+        for raw in &entries {
+            long.push(raw);
+        }
+        assert_eq!(long.finish(b"OTHER      "), None);
+    }
+
+    #[test]
+    fn timestamps() {
+        let (date, time) = unix_to_fat_time(1_700_000_000);
+        // 2023-11-14 22:13:20 UTC.
+        assert_eq!(date, (43 << 9) | (11 << 5) | 14);
+        assert_eq!(time, (22 << 11) | (13 << 5) | 10);
+        assert_eq!(fat_time_to_unix(date, time), 1_700_000_000);
+        assert_eq!(unix_to_fat_time(0), ((1 << 5) | 1, 0));
+        assert_eq!(fat_time_to_unix((1 << 5) | 1, 0), 315_532_800);
+    }
+}
@@ -0,0 +1,26 @@
+//! Platform-independent core algorithms of the vOS kernel.
+//!
+//! This crate contains the pure algorithms and data structures used by the kernel: the intrusive
+//! containers, the bit/alignment helpers, the buddy page allocator and its zones, the ASID
+//! allocator, the slab size calculation, the page cache read-ahead, the ELF parser, the cpio
+//! parser, the partition table parser and the FAT and ext2 on-disk formats. Nothing here touches
+//! the hardware, so the crate is `#![no_std]` for the kernel and is tested on the host with the
+//! normal `cargo test` (see the `Readme.md` in the project root).
+
+#![no_std]
+// The intrusive containers work on raw pointers and the objects are placed in the static memory
+// by `const fn new()`, the same as the kernel code.
+#![allow(clippy::new_without_default, clippy::not_unsafe_ptr_arg_deref)]
+
+extern crate static_assertions as sa;
+
+#[cfg(test)]
+extern crate std;
+
+pub mod cpio;
+pub mod elf;
+pub mod ext2;
+pub mod fat;
+pub mod mm;
+pub mod partition;
+pub mod util;
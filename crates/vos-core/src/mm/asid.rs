@@ -0,0 +1,214 @@
+//! Address space identifier (ASID) allocator.
+//!
+//! The ASIDs are allocated with a generation-based rollover (like the Linux riscv `context.c`):
+//! the context of an address space is `generation | asid`, and is valid only if its generation
+//! is the current one.
+//!
+//! When all ASIDs of the current generation are used, the generation is bumped and the ASID
+//! bitmap is reset, except for the ASIDs active on each hart, which are *reserved* so the
+//! running address spaces can keep them. Each hart flushes its whole TLB on its next slow path
+//! switch after a rollover, so the reused ASIDs never hit the stale entries.
+//!
+//! ASID 0 is kept for the kernel. If there are too few ASIDs for the harts, the ASIDs are
+//! disabled and all contexts use ASID 0.
+//!
+//! The allocator is split into the [`Asids`] read by the lock-free fast path, the [`AsidMap`]
+//! protected by the allocator lock of the caller, and an [`AsidCpu`] for each hart.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+
+/// Max number of ASID bits of Sv39.
+pub const MAX_ASID_BITS: usize = 16;
+
+/// Per-hart ASID state.
+pub struct AsidCpu {
+    /// Context active on this hart, 0 while a rollover is in progress.
+    active: AtomicUsize,
+    /// Context kept across the last rollover, only accessed with the allocator lock held.
+    reserved: AtomicUsize,
+    /// Set by a rollover, the TLB must be flushed on the next switch.
+    flush_pending: AtomicBool,
+}
+
+impl AsidCpu {
+    pub const fn new() -> Self {
+        Self {
+            active: AtomicUsize::new(0),
+            reserved: AtomicUsize::new(0),
+            flush_pending: AtomicBool::new(false),
+        }
+    }
+}
+
+/// ASIDs used in the current generation.
+pub struct AsidMap([u64; (1 << MAX_ASID_BITS) / 64]);
+
+impl AsidMap {
+    pub const fn new() -> Self {
+        Self([0; (1 << MAX_ASID_BITS) / 64])
+    }
+
+    #[inline(always)]
+    fn set(&mut self, asid: usize) {
+        self.0[asid / 64] |= 1 << (asid % 64);
+    }
+
+    #[inline(always)]
+    fn test(&self, asid: usize) -> bool {
+        self.0[asid / 64] & (1 << (asid % 64)) != 0
+    }
+
+    /// Clear all ASIDs except the kernel one.
+    fn reset(&mut self) {
+        self.0.fill(0);
+        self.0[0] = 1;
+    }
+
+    fn find_free(&self, count: usize) -> Option<usize> {
+        self.0[..(count + 63) / 64].iter().enumerate()
+            .find(|(_, &bits)| bits != u64::MAX)
+            .map(|(idx, &bits)| idx * 64 + bits.trailing_ones() as usize)
+            .filter(|&asid| asid < count)
+    }
+}
+
+/// The ASID bits and the current generation.
+pub struct Asids {
+    /// Number of ASID bits, 0 if the ASIDs are disabled.
+    bits: usize,
+    /// Current generation, in the bits above the ASID bits.
+    generation: AtomicUsize,
+}
+
+impl Asids {
+    /// Create the allocator with the ASIDs disabled.
+    pub const fn new() -> Self {
+        Self { bits: 0, generation: AtomicUsize::new(0) }
+    }
+
+    /// Enable the ASIDs with `bits` ASID bits for `nr_cpus` harts, and reset the `map`. Each hart
+    /// reserves one ASID on a rollover, the ASIDs are kept disabled if there are not enough ones
+    /// left. Returns whether the ASIDs are enabled.
+    pub fn init(&mut self, bits: usize, nr_cpus: usize, map: &mut AsidMap) -> bool {
+        debug_assert!(bits <= MAX_ASID_BITS);
+        if (1usize << bits) > 2 * nr_cpus {
+            self.bits = bits;
+            self.generation.store(1 << bits, Ordering::Relaxed);
+            map.reset();
+        }
+        self.enabled()
+    }
+
+    /// Check if the ASIDs are enabled.
+    #[inline(always)]
+    pub fn enabled(&self) -> bool {
+        self.bits != 0
+    }
+
+    /// Get the ASID of a `context`.
+    #[inline(always)]
+    pub fn context_asid(&self, context: usize) -> usize {
+        context & self.asid_mask()
+    }
+
+    /// Get the current generation.
+    #[inline(always)]
+    pub fn generation(&self) -> usize {
+        self.generation.load(Ordering::Relaxed)
+    }
+
+    #[inline(always)]
+    fn asid_mask(&self) -> usize {
+        (1 << self.bits) - 1
+    }
+
+    /// The lock-free fast path of a switch to the `context` on the hart `cpu`. Returns the ASID
+    /// if the context is valid and no rollover is in progress (which clears the `active`), or
+    /// `None` if the [`switch_locked`] slow path must be taken.
+    ///
+    /// [`switch_locked`]: Asids::switch_locked
+    pub fn switch_fast(&self, cpu: &AsidCpu, context: &AtomicUsize) -> Option<usize> {
+        let cur = context.load(Ordering::Relaxed);
+        let old_active = cpu.active.load(Ordering::Relaxed);
+        if old_active != 0 && cur & !self.asid_mask() == self.generation() &&
+            cpu.active.compare_exchange(old_active, cur, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok() {
+            return Some(self.context_asid(cur));
+        }
+        None
+    }
+
+    /// The slow path of a switch to the `context` on the hart `cpu` of the `cpus`, a new ASID is
+    /// allocated if the context is not in the current generation, and the `context` is updated.
+    /// Returns the ASID, and whether the whole TLB of the hart must be flushed.
+    ///
+    /// The allocator lock which protects the `map` must be held.
+    pub fn switch_locked(&self, map: &mut AsidMap, cpus: &[AsidCpu], cpu: &AsidCpu,
+                         context: &AtomicUsize) -> (usize, bool) {
+        let mut cur = context.load(Ordering::Relaxed);
+        if cur & !self.asid_mask() != self.generation() {
+            cur = self.new_context(map, cpus, cur);
+            context.store(cur, Ordering::Relaxed);
+        }
+        let flush = cpu.flush_pending.swap(false, Ordering::Relaxed);
+        cpu.active.store(cur, Ordering::Relaxed);
+
+        (self.context_asid(cur), flush)
+    }
+
+    /// Allocate a context of the current generation for the old context `old`, the old ASID is
+    /// kept if it is reserved or still free.
+    fn new_context(&self, map: &mut AsidMap, cpus: &[AsidCpu], old: usize) -> usize {
+        if old != 0 {
+            let asid = self.context_asid(old);
+            let new = self.generation() | asid;
+            if Self::update_reserved(cpus, old, new) {
+                return new;
+            }
+            if !map.test(asid) {
+                map.set(asid);
+                return new;
+            }
+        }
+
+        let count = 1 << self.bits;
+        let asid = map.find_free(count).unwrap_or_else(|| {
+            self.rollover(map, cpus);
+            map.find_free(count).expect("No free ASID after rollover.")
+        });
+        map.set(asid);
+        self.generation() | asid
+    }
+
+    /// Replace the reserved context `old` by `new` on all harts. Returns true if `old` is
+    /// reserved.
+    fn update_reserved(cpus: &[AsidCpu], old: usize, new: usize) -> bool {
+        let mut hit = false;
+        for cpu in cpus {
+            if cpu.reserved.load(Ordering::Relaxed) == old {
+                cpu.reserved.store(new, Ordering::Relaxed);
+                hit = true;
+            }
+        }
+
+        hit
+    }
+
+    /// Start a new generation: the bitmap is reset to the reserved ASIDs, and all harts flush
+    /// their TLB on the next switch.
+    fn rollover(&self, map: &mut AsidMap, cpus: &[AsidCpu]) {
+        self.generation.fetch_add(1 << self.bits, Ordering::Relaxed);
+        map.reset();
+        for cpu in cpus {
+            let mut context = cpu.active.swap(0, Ordering::Relaxed);
+            // A hart which has not switched since the last rollover keeps its reserved one.
+            if context == 0 {
+                context = cpu.reserved.load(Ordering::Relaxed);
+            }
+            map.set(self.context_asid(context));
+            cpu.reserved.store(context, Ordering::Relaxed);
+            cpu.flush_pending.store(true, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,13 @@
+//! Memory management algorithms.
+
+pub mod asid;
+pub mod page;
+pub mod page_cache;
+pub mod slab;
+pub mod zones;
+
+
+/// Order of page-size.
+pub const PAGE_ORDER: usize = 12;
+/// Page size.
+pub const PAGE_SIZE: usize = 1 << PAGE_ORDER;
@@ -0,0 +1,765 @@
+//! Binary buddy allocator of the physical pages.
+//!
+//! The algorithm is the **Binary Buddy Allocator** used in the Linux OS and documented in
+//! [Chapter 6  Physical Page Allocation]. A [`Zone`] manages a contiguous memory region: the
+//! region is split into blocks of `2^order` pages, and the free blocks of each order are linked
+//! in the free list of the [`FreeArea`] of that order. Each pair of buddy blocks shares one bit
+//! in the bitmap of the free area, which is toggled on each allocation and free of the blocks,
+//! so the bit is 1 iff exactly one of the buddies is free.
+//!
+//! The zone only uses the memory addresses passed to [`Zone::init`], so it can manage either the
+//! real physical memory (the kernel) or any memory buffer (the host tests).
+//!
+//! [Chapter 6  Physical Page Allocation]: https://www.kernel.org/doc/gorman/html/understand/understand009.html
+//! [`Zone`]: Zone
+//! [`FreeArea`]: FreeArea
+//! [`Zone::init`]: Zone::init
+
+use core::mem::size_of;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicU32, Ordering};
+use crate::mm::PAGE_SIZE;
+use crate::util::align::{align_down, align_up, get_order};
+use crate::util::bit;
+use crate::util::list::{self, List};
+
+
+/// Count of the free areas, the max block is `2^(MAX_FREE_AREA_ORDER - 1)` pages.
+pub const MAX_FREE_AREA_ORDER: usize = 10;
+
+/// Max order used to alloc pages.
+pub const PAGE_ALLOC_MAX_ORDER: u32 = MAX_FREE_AREA_ORDER as u32;
+/// The order at which allocations are deemed costly to service.
+pub const PAGE_ALLOC_COSTLY_ORDER: u32 = 3;
+
+/// Alignment of the allocatable memory of a zone: the size of the max block.
+const ZONE_ALIGNMENT: usize = PAGE_SIZE << (MAX_FREE_AREA_ORDER - 1usize);
+
+// todo: remove `PageFlag`.
+
+/// Page flags definition.
+#[repr(u32)]
+#[derive(Copy, Clone)]
+pub enum PageFlag {
+    /// Page is used in the SLAB/SLUB allocator.
+    Slab = 1 << 0,
+    /// Page is shared between multiple processes.
+    Shared = 1 << 1,
+}
+
+impl PageFlag {
+    pub const fn val(self) -> u32 {
+        self as u32
+    }
+}
+
+/// Each **page** is described by the `Page` structure.
+///
+/// We guarantee that `size_of::<Page>() % 16 == 0` and the `Page` object will be allocated
+/// with the align of `size_of::<Page>()` or 16. So the least 4 bits of the pointer to a `Page`
+/// object will always guard to 0 and can be safety used, such as save the page alloc order.
+///
+/// If a **page** is allocated, then the `Page` structure associated with it will be free to
+/// use to store private data, except for the last 8 bytes which are used to store the page
+/// flags and ref count. [`get_private`] method can be used to retrieve the private area start
+/// address, and [`get_private_size`] method can be used to get the available size of private
+/// area, this is a **const** function so it can be called at the compile-time context to do
+/// some static assertions. The private size is guaranteed to at least 40 bytes; the private
+/// data address is guaranteed to be aligned with 16.
+///
+/// The least 24 bits of the inner `flags` field are used by the Page allocator, while the most
+/// 8 bits can be used to store custom flags. See methods [`read_custom_flags`],
+/// [`set_custom_flags`] and [`replace_custom_flags`] for more info, note that though the param
+/// or return type is `u32`, only the least 8 bits is valid and used.
+///
+/// When the **page** is allocated, all instance methods are invalid except for the
+/// [`get_private`], `ref_count` and `flags` operation methods.
+///
+/// [`get_private`]: self::Page::get_private
+/// [`get_private_size`]: self::Page::get_private_size
+/// [`read_custom_flags`]: self::Page::read_custom_flags
+/// [`set_custom_flags`]: self::Page::set_custom_flags
+/// [`replace_custom_flags`]: self::Page::replace_custom_flags
+#[repr(C)]
+pub struct Page {
+    /// Entry of the free list. Must be the first field, see [`Page::from_list`].
+    head: List,
+    padding: [usize; 3],
+    ref_count: AtomicU32,
+    /// bits\[3:0] store the idx of zone that self page belongs. bits\[23:4] store inner flags.
+    /// bits\[31:24] store custom flags.
+    flags: u32,
+}
+
+// Assert the size of `Page` equals to multiple times of 16bytes.
+sa::const_assert_eq!(size_of::<Page>() % 16, 0);
+
+/// Bits to store the zone idx. See [`Page::flags`](Page).
+const ZONE_IDX_BITS: u32 = 4;
+/// Max count of the zones, limited by the bits of the zone idx saved in the `Page`.
+pub const MAX_NR_ZONES: usize = 1 << ZONE_IDX_BITS;
+
+impl Page {
+    /// Get the available size for private usage.
+    #[inline(always)]
+    pub const fn get_private_size() -> usize {
+        size_of::<Page>() - size_of::<u32>() * 2
+    }
+
+    /// Get a pointer of the private memory.
+    ///
+    /// **Note**: data in this memory may be in **uninitialized** state.
+    #[inline(always)]
+    pub fn get_private(&mut self) -> *mut u8 {
+        self as *mut Page as *mut u8
+    }
+
+    /// Get a pointer of the private memory region and cast to special type.
+    ///
+    /// **Note**: data in this memory may be in **uninitialized** state.
+    #[inline(always)]
+    pub fn cast_private<T>(&mut self) -> *mut T {
+        self.get_private() as _
+    }
+
+    /// Translate a pointer to the `Page` pointer.
+    ///
+    /// **Warning**: `p` **must** be fetched from ether [`get_private`] or [`cast_private`],
+    /// otherwise result is **undefined**.
+    ///
+    /// [`get_private`]: Page::get_private
+    /// [`cast_private`]: Page::cast_private
+    #[inline(always)]
+    pub fn from_private<T>(p: *mut T) -> *mut Page {
+        p as _
+    }
+
+    /// Get the `Page` from its free list entry.
+    #[inline(always)]
+    fn from_list(head: *mut List) -> *mut Page {
+        // `head` is the first field of the `repr(C)` struct.
+        head as *mut Page
+    }
+
+    /// Get the flags. **Note**: custom flags are also returned.
+    #[inline(always)]
+    pub fn read_flags(&self) -> u32 {
+        self.flags >> ZONE_IDX_BITS
+    }
+
+    /// Check if the `flag` is set.
+    #[inline(always)]
+    pub fn is_flag_set(&self, flag: PageFlag) -> bool {
+        ((self.flags >> ZONE_IDX_BITS) & flag.val()) != 0
+    }
+
+    /// Set page flag.
+    #[inline(always)]
+    pub fn set_flag(&mut self, flag: PageFlag) {
+        self.flags |= flag.val() << ZONE_IDX_BITS;
+    }
+
+    /// Clear page flag.
+    #[inline(always)]
+    pub fn clear_flag(&mut self, flag: PageFlag) {
+        self.flags &= !(flag.val() << ZONE_IDX_BITS);
+    }
+
+    /// Get the custom flags.
+    #[inline(always)]
+    pub fn read_custom_flags(&self) -> u32 {
+        self.flags >> 24
+    }
+
+    /// Replace the custom flags. This will overwrite the total custom flags value.
+    ///
+    /// **Note**: Only the least 8 bits of `flag` is used.
+    #[inline(always)]
+    pub fn replace_custom_flags(&mut self, flag: u32) {
+        self.flags = (self.flags & 0xff_ffffu32) | (flag << 24);
+    }
+
+    /// Set the certain custom flags. If a bit of `flag` is 1, **set** the correspond custom flag bit;
+    /// otherwise leave the correspond flag bit unchanged.
+    ///
+    /// **Note**: Only the least 8 bits of `flag` is used.
+    #[inline(always)]
+    pub fn set_custom_flags(&mut self, flag: u32) {
+        self.flags |= flag << 24;
+    }
+
+    /// Clear the certain custom flags. If a bit of `flag` is 1, then **clear** the correspond custom
+    /// flag bit; otherwise leave the correspond flag bit unchanged.
+    ///
+    /// **Note**: Only the least 8 bits of `flag` is used.
+    #[inline(always)]
+    pub fn clear_custom_flags(&mut self, flag: u32) {
+        self.flags &= !(flag << 24);
+    }
+
+    /// Get the idx of the zone that the page belongs to.
+    #[inline(always)]
+    pub fn get_zone_idx(&self) -> usize {
+        (self.flags & 0x0fu32) as usize
+    }
+
+    /// Get the ref count. **Note**: only available when page has been set [`PageFlag::Shared`] flag.
+    ///
+    /// [`PageFlag::Shared`]: self::PageFlag::Shared
+    #[inline(always)]
+    pub fn ref_count(&self) -> u32 {
+        self.ref_count.load(Ordering::Relaxed)
+    }
+
+    /// Increase the ref count of the shared page.
+    ///
+    /// **Note**: it is an **Undefined Behavior** if the page has no [`PageFlag::Shared`] flag set.
+    ///
+    /// [`PageFlag::Shared`]: self::PageFlag::Shared
+    #[inline(always)]
+    pub fn increase_ref(&mut self) {
+        self.ref_count.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Decrease the ref count and return current ref count (after the decrease operation).
+    ///
+    /// **Note**: it is an **Undefined Behavior** if the page has no [`PageFlag::Shared`] flag set.
+    ///
+    /// [`PageFlag::Shared`]: self::PageFlag::Shared
+    #[inline(always)]
+    pub fn decrease_ref(&mut self) -> u32 {
+        self.ref_count.fetch_sub(1, Ordering::AcqRel) - 1u32
+    }
+}
+
+
+/// Free blocks of one order.
+#[repr(C)]
+pub struct FreeArea {
+    free_list: List,
+    /// One bit for each pair of buddies. Not used on the max order.
+    bitmap: *mut u8,
+}
+
+impl FreeArea {
+    pub const fn new() -> Self {
+        Self {
+            free_list: List::new(),
+            bitmap: null_mut(),
+        }
+    }
+}
+
+/// A memory region managed by the buddy allocator.
+///
+/// The zone contains the heads of the free lists, so it **must not** be moved after [`init`].
+///
+/// [`init`]: Zone::init
+#[repr(C)]
+pub struct Zone {
+    free_areas: [FreeArea; MAX_FREE_AREA_ORDER],
+    free_pages: usize,
+    max_pages: usize,
+    mem_start: usize,
+    mem_size: usize,
+    /// Address of the `Page` object array.
+    page_base: usize,
+    /// Address of the first allocatable page.
+    alloc_start: usize,
+    /// Idx of this zone, saved in the allocated `Page`.
+    idx: usize,
+}
+
+impl Zone {
+    pub const fn new() -> Self {
+        const VAL: FreeArea = FreeArea::new();
+        Self {
+            free_areas: [VAL; MAX_FREE_AREA_ORDER],
+            free_pages: 0,
+            max_pages: 0,
+            mem_start: 0,
+            mem_size: 0,
+            page_base: 0,
+            alloc_start: 0,
+            idx: 0,
+        }
+    }
+
+    /// Init the zone to manage the memory region `[mem_start, mem_start + mem_size)`.
+    ///
+    /// The memory below `meta_start` is not used. The bitmaps and the `Page` objects are placed
+    /// from `meta_start`, then the allocatable pages start from the next address aligned to the
+    /// max block size. `idx` is the zone idx saved in each allocated `Page`, must be less than 16.
+    ///
+    /// Returns `false` if the region is too small to hold a max block.
+    ///
+    /// # Safety
+    /// The memory region must be valid and not used by others. `self` must not be moved after
+    /// this call.
+    pub unsafe fn init(&mut self, idx: usize, mem_start: usize, mem_size: usize, meta_start: usize) -> bool {
+        debug_assert!(idx < (1usize << ZONE_IDX_BITS));
+        debug_assert!(meta_start >= mem_start);
+        for area in &mut self.free_areas {
+            area.free_list.init_empty();
+            area.bitmap = null_mut();
+        }
+        self.idx = idx;
+        self.mem_start = mem_start;
+        self.mem_size = mem_size;
+        self.free_pages = 0;
+        self.max_pages = 0;
+
+        let mem_end = align_down(mem_start + mem_size, get_order(ZONE_ALIGNMENT));
+        let alloc_min_addr = align_up(meta_start, get_order(ZONE_ALIGNMENT));
+        if alloc_min_addr >= mem_end {
+            return false;
+        }
+        // Upper bound of the page count, used to size the bitmaps.
+        let max_alloc_pages = (mem_end - alloc_min_addr) / PAGE_SIZE;
+
+        // Init the free area bitmap. We alloc the bitmap with align of 8bytes.
+        let bitmap_base = align_up(meta_start, get_order(size_of::<u64>()));
+        let mut bitmap_len = 0usize;
+        for i in 0..(MAX_FREE_AREA_ORDER - 1) {
+            bitmap_len += ((max_alloc_pages >> (i + 1usize)) + 7) / 8;
+        }
+        let page_start = align_up(bitmap_base + bitmap_len, get_order(32usize));
+        if page_start >= mem_end {
+            return false;
+        }
+
+        // Each max block needs the `Page` objects and the pages, and the pages start from an
+        // aligned address after the `Page` objects.
+        const PAGE_COUNT_LAST_AREA: usize = 1usize << (MAX_FREE_AREA_ORDER - 1usize);
+        let mut large_blocks = (mem_end - page_start) /
+            ((PAGE_SIZE + size_of::<Page>()) << (MAX_FREE_AREA_ORDER - 1usize));
+        let alloc_start = loop {
+            if large_blocks == 0 {
+                return false;
+            }
+            let page_end = page_start + size_of::<Page>() * (large_blocks * PAGE_COUNT_LAST_AREA);
+            let alloc_start = align_up(page_end, get_order(ZONE_ALIGNMENT));
+            if alloc_start + (large_blocks * PAGE_COUNT_LAST_AREA) * PAGE_SIZE <= mem_end {
+                break alloc_start;
+            }
+            large_blocks -= 1;
+        };
+        let alloc_pages = large_blocks * PAGE_COUNT_LAST_AREA;
+
+        // Zero the bitmaps.
+        (bitmap_base as *mut u8).write_bytes(0, page_start - bitmap_base);
+        let mut bitmap = bitmap_base;
+        for i in 0..(MAX_FREE_AREA_ORDER - 1) {
+            let free_area = self.free_areas.get_unchecked_mut(i);
+            free_area.bitmap = bitmap as *mut u8;
+            bitmap += ((max_alloc_pages >> (i + 1usize)) + 7) / 8;
+        }
+
+        // All max blocks go to the free list of the max order.
+        let free_area = self.free_areas.get_unchecked_mut(MAX_FREE_AREA_ORDER - 1usize);
+        let page_base = page_start as *mut Page;
+        for i in 0..large_blocks {
+            let page = page_base.add(i * PAGE_COUNT_LAST_AREA);
+            (*page).flags = 0;
+            list::tail_append(&mut free_area.free_list, &mut (*page).head);
+        }
+
+        self.page_base = page_start;
+        self.alloc_start = alloc_start;
+        self.free_pages = alloc_pages;
+        self.max_pages = alloc_pages;
+        true
+    }
+
+    /// Zone idx.
+    #[inline(always)]
+    pub fn idx(&self) -> usize {
+        self.idx
+    }
+
+    /// Count of the free pages.
+    #[inline(always)]
+    pub fn free_pages(&self) -> usize {
+        self.free_pages
+    }
+
+    /// Count of all allocatable pages.
+    #[inline(always)]
+    pub fn max_pages(&self) -> usize {
+        self.max_pages
+    }
+
+    /// Start address of the memory region passed to [`init`](Zone::init).
+    #[inline(always)]
+    pub fn mem_start(&self) -> usize {
+        self.mem_start
+    }
+
+    /// Size of the memory region passed to [`init`](Zone::init).
+    #[inline(always)]
+    pub fn mem_size(&self) -> usize {
+        self.mem_size
+    }
+
+    /// Address of the `Page` object array.
+    #[inline(always)]
+    pub fn page_base(&self) -> *const Page {
+        self.page_base as *const Page
+    }
+
+    /// Address of the first allocatable page.
+    #[inline(always)]
+    pub fn alloc_start(&self) -> usize {
+        self.alloc_start
+    }
+
+    /// End address (exclusive) of the allocatable pages.
+    #[inline(always)]
+    pub fn alloc_end(&self) -> usize {
+        self.alloc_start + self.max_pages * PAGE_SIZE
+    }
+
+    /// Check if the `page` is a `Page` object of this zone.
+    #[inline]
+    pub fn contains_page(&self, page: *const Page) -> bool {
+        let addr = page as usize;
+        addr >= self.page_base && addr < self.page_base + self.max_pages * size_of::<Page>()
+    }
+
+    /// Check if the `addr` is in the allocatable pages of this zone.
+    #[inline]
+    pub fn contains_address(&self, addr: usize) -> bool {
+        addr >= self.alloc_start && addr < self.alloc_end()
+    }
+
+    /// Get the address of the page described by `page`. Returns 0 if `page` is null.
+    #[inline]
+    pub fn page_to_address(&self, page: *const Page) -> usize {
+        if page.is_null() {
+            return 0;
+        }
+
+        let index = (page as usize - self.page_base) / size_of::<Page>();
+        self.alloc_start + index * PAGE_SIZE
+    }
+
+    /// Get the `Page` of the page at `addr`. Returns null if `addr` is not in this zone.
+    #[inline]
+    pub fn address_to_page(&self, addr: usize) -> *mut Page {
+        if !self.contains_address(addr) {
+            return null_mut();
+        }
+
+        let index = (addr - self.alloc_start) / PAGE_SIZE;
+        (self.page_base as *mut Page).wrapping_add(index)
+    }
+
+    /// Count of the free blocks of `order`.
+    pub fn free_blocks(&self, order: usize) -> usize {
+        list::count(&self.free_areas[order].free_list)
+    }
+
+    /// Call `handle` with the order and the first `Page` of each free block.
+    pub fn for_each_free_block<F>(&mut self, mut handle: F)
+        where F: FnMut(usize, *mut Page) {
+        for (order, area) in self.free_areas.iter_mut().enumerate() {
+            list::for_each(&mut area.free_list, |cur| {
+                handle(order, Page::from_list(cur));
+                true
+            });
+        }
+    }
+
+    /// Allocate `2^order` contiguous pages. Returns null if there is no enough memory.
+    ///
+    /// The flags of the returned `Page` are cleared (except for the zone idx), and the ref count
+    /// is reset to 0.
+    pub fn alloc(&mut self, order: usize) -> *mut Page {
+        if order >= MAX_FREE_AREA_ORDER || (1usize << order) > self.free_pages {
+            return null_mut();
+        }
+
+        unsafe {
+            let page = self.alloc_on_areas(order);
+            if !page.is_null() {
+                // Directly assign to clear the flags.
+                (*page).flags = self.idx as u32;
+                // Reset the ref count.
+                (*page).ref_count.store(0, Ordering::Relaxed);
+            }
+            page
+        }
+    }
+
+    /// Free the `2^order` pages starting from `page`, which must be returned by [`alloc`] with
+    /// the same `order`.
+    ///
+    /// [`alloc`]: Zone::alloc
+    pub fn free(&mut self, page: *mut Page, order: usize) {
+        assert!(order < MAX_FREE_AREA_ORDER && self.contains_page(page), "Free page invalid.");
+
+        let base = self.page_base as *mut Page;
+        let mut page_idx = (page as usize - self.page_base) / size_of::<Page>();
+        if page_idx & ((1usize << order) - 1) != 0 || page_idx + (1usize << order) > self.max_pages {
+            panic!("Free page invalid.");
+        }
+
+        let mut index = page_idx >> (1usize + order);
+        let mut cur_order = order;
+        self.free_pages += 1usize << order;
+        unsafe {
+            while cur_order < MAX_FREE_AREA_ORDER - 1usize {
+                let area = self.free_areas.get_unchecked_mut(cur_order);
+                if !bit::test_and_change_bit_array(area.bitmap, index) {
+                    break;
+                }
+
+                // Previous bit in bitmap is 1, so the buddy block is free, then do merge.
+                let buddy = base.add(page_idx ^ (1usize << cur_order));
+                list::delete(&mut (*buddy).head);
+
+                cur_order += 1usize;
+                index >>= 1usize;
+                page_idx &= !0usize << cur_order;
+            }
+
+            let area = self.free_areas.get_unchecked_mut(cur_order);
+            list::head_append(&mut area.free_list, &mut (*base.add(page_idx)).head);
+        }
+    }
+
+    /// Take the free page at `addr` out of the free lists, as if it was allocated by
+    /// [`alloc`](Zone::alloc) with order 0. The free block containing the page is split, the
+    /// other parts of the block stay free. Used to keep the memory handed over by the boot
+    /// loader (e.g. the initrd) from being allocated.
+    ///
+    /// Returns `false` if the page is not in this zone or is not free.
+    pub fn reserve(&mut self, addr: usize) -> bool {
+        let page = self.address_to_page(addr);
+        if page.is_null() {
+            return false;
+        }
+
+        let base = self.page_base as *mut Page;
+        let page_idx = (page as usize - self.page_base) / size_of::<Page>();
+        unsafe {
+            for order in 0..MAX_FREE_AREA_ORDER {
+                let block_idx = page_idx & (!0usize << order);
+                let block = &mut (*base.add(block_idx)).head as *mut List;
+                let area = self.free_areas.get_unchecked_mut(order);
+                let mut found = false;
+                list::for_each(&mut area.free_list, |cur| {
+                    found = cur == block;
+                    !found
+                });
+                if !found {
+                    continue;
+                }
+
+                list::delete(&mut *block);
+                if order != MAX_FREE_AREA_ORDER - 1usize {
+                    bitmap_mark_used(area.bitmap, block_idx, order);
+                }
+                self.free_pages -= 1;
+                // Split the block, the halves without the page go back to the free lists.
+                let mut cur_order = order;
+                while cur_order > 0 {
+                    cur_order -= 1usize;
+                    let buddy_idx = (page_idx & (!0usize << cur_order)) ^ (1usize << cur_order);
+                    let area = self.free_areas.get_unchecked_mut(cur_order);
+                    let buddy = &mut (*base.add(buddy_idx));
+                    buddy.flags = 0;
+                    list::head_append(&mut area.free_list, &mut buddy.head);
+                    bitmap_mark_used(area.bitmap, buddy_idx, cur_order);
+                }
+
+                (*page).flags = self.idx as u32;
+                (*page).ref_count.store(0, Ordering::Relaxed);
+                return true;
+            }
+        }
+        false
+    }
+
+    unsafe fn alloc_on_areas(&mut self, order: usize) -> *mut Page {
+        for current_order in order..MAX_FREE_AREA_ORDER {
+            let free_area = self.free_areas.get_unchecked_mut(current_order);
+            if list::is_empty(&free_area.free_list) {
+                continue;
+            }
+
+            let page_head = free_area.free_list.next;
+            let page = Page::from_list(page_head);
+            list::delete(&mut *page_head);
+            let index = (page as usize - self.page_base) / size_of::<Page>();
+            if current_order != MAX_FREE_AREA_ORDER - 1usize {
+                bitmap_mark_used(free_area.bitmap, index, current_order);
+            }
+
+            self.free_pages -= 1usize << order;
+            self.expand(page, index, order, current_order);
+            return page;
+        }
+
+        null_mut()
+    }
+
+    /// Split the block of `high` order at `page`, put the unused halves to the free lists until
+    /// the block is `low` order.
+    unsafe fn expand(&mut self, page: *mut Page, index: usize, low: usize, mut high: usize) {
+        let mut size = 1usize << high;
+        while low < high {
+            high -= 1usize;
+            size >>= 1usize;
+            let area = self.free_areas.get_unchecked_mut(high);
+            let buddy = &mut (*page.add(size));
+            buddy.flags = 0;
+            list::head_append(&mut area.free_list, &mut buddy.head);
+            bitmap_mark_used(area.bitmap, index + size, high);
+        }
+    }
+}
+
+#[inline(always)]
+fn bitmap_mark_used(bitmap: *mut u8, index: usize, order: usize) {
+    bit::change_bit_array(bitmap, index >> (1usize + order));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::boxed::Box;
+    use std::vec::Vec;
+    use super::*;
+
+    const MEM_SIZE: usize = 16 << 20;
+
+    /// A simulated memory region and the zone managing it. The zone is boxed so it is not moved.
+    fn new_zone(mem: &mut Vec<u8>) -> Box<Zone> {
+        let mut zone = Box::new(Zone::new());
+        let start = mem.as_mut_ptr() as usize;
+        assert!(unsafe { zone.init(1, start, mem.len(), start) });
+        zone
+    }
+
+    #[test]
+    fn init_layout() {
+        let mut mem = std::vec![0u8; MEM_SIZE];
+        let zone = new_zone(&mut mem);
+        let start = mem.as_ptr() as usize;
+
+        assert!(zone.max_pages() > 0);
+        assert_eq!(zone.max_pages() % (1 << (MAX_FREE_AREA_ORDER - 1)), 0);
+        assert_eq!(zone.free_pages(), zone.max_pages());
+        assert_eq!(zone.alloc_start() % ZONE_ALIGNMENT, 0);
+        assert!(zone.page_base() as usize >= start);
+        assert!(zone.alloc_end() <= start + MEM_SIZE);
+        assert_eq!(zone.free_blocks(MAX_FREE_AREA_ORDER - 1), zone.max_pages() >> (MAX_FREE_AREA_ORDER - 1));
+    }
+
+    #[test]
+    fn too_small_region() {
+        let mut mem = std::vec![0u8; ZONE_ALIGNMENT];
+        let mut zone = Box::new(Zone::new());
+        let start = mem.as_mut_ptr() as usize;
+        assert!(!unsafe { zone.init(0, start, mem.len(), start) });
+        assert!(zone.alloc(0).is_null());
+    }
+
+    #[test]
+    fn split_and_merge() {
+        let mut mem = std::vec![0u8; MEM_SIZE];
+        let mut zone = new_zone(&mut mem);
+        let max = zone.max_pages();
+
+        let page = zone.alloc(0);
+        assert!(!page.is_null());
+        assert_eq!(unsafe { (*page).get_zone_idx() }, 1);
+        assert_eq!(zone.free_pages(), max - 1);
+        // Splitting a max block leaves one free block on each lower order.
+        for order in 0..(MAX_FREE_AREA_ORDER - 1) {
+            assert_eq!(zone.free_blocks(order), 1);
+        }
+
+        let addr = zone.page_to_address(page);
+        assert_eq!(zone.address_to_page(addr), page);
+        assert_eq!(addr, zone.alloc_start());
+
+        zone.free(page, 0);
+        assert_eq!(zone.free_pages(), max);
+        for order in 0..(MAX_FREE_AREA_ORDER - 1) {
+            assert_eq!(zone.free_blocks(order), 0);
+        }
+    }
+
+    #[test]
+    fn reserve_pages() {
+        let mut mem = std::vec![0u8; MEM_SIZE];
+        let mut zone = new_zone(&mut mem);
+        let max = zone.max_pages();
+        let start = zone.alloc_start() + 3 * PAGE_SIZE;
+        let end = start + 700 * PAGE_SIZE;
+
+        for addr in (start..end).step_by(PAGE_SIZE) {
+            assert!(zone.reserve(addr));
+        }
+        assert!(!zone.reserve(start));
+        assert!(!zone.reserve(zone.alloc_end()));
+        assert_eq!(zone.free_pages(), max - 700);
+
+        // The reserved pages are never allocated.
+        let mut pages = Vec::new();
+        loop {
+            let page = zone.alloc(0);
+            if page.is_null() {
+                break;
+            }
+            let addr = zone.page_to_address(page);
+            assert!(addr < start || addr >= end);
+            pages.push(page);
+        }
+        assert_eq!(pages.len(), max - 700);
+
+        for addr in (start..end).step_by(PAGE_SIZE) {
+            zone.free(zone.address_to_page(addr), 0);
+        }
+        for page in pages {
+            zone.free(page, 0);
+        }
+        assert_eq!(zone.free_pages(), max);
+        assert_eq!(zone.free_blocks(MAX_FREE_AREA_ORDER - 1), max >> (MAX_FREE_AREA_ORDER - 1));
+    }
+
+    #[test]
+    fn exhaust() {
+        let mut mem = std::vec![0u8; MEM_SIZE];
+        let mut zone = new_zone(&mut mem);
+        let max_order = MAX_FREE_AREA_ORDER - 1;
+
+        let mut pages = Vec::new();
+        loop {
+            let page = zone.alloc(max_order);
+            if page.is_null() {
+                break;
+            }
+            pages.push(page);
+        }
+        assert_eq!(pages.len(), zone.max_pages() >> max_order);
+        assert_eq!(zone.free_pages(), 0);
+        assert!(zone.alloc(0).is_null());
+
+        for page in pages {
+            zone.free(page, max_order);
+        }
+        assert_eq!(zone.free_pages(), zone.max_pages());
+    }
+
+    #[test]
+    fn address_out_of_zone() {
+        let mut mem = std::vec![0u8; MEM_SIZE];
+        let zone = new_zone(&mut mem);
+        assert!(zone.address_to_page(zone.alloc_start() - PAGE_SIZE).is_null());
+        assert!(zone.address_to_page(zone.alloc_end()).is_null());
+        assert_eq!(zone.page_to_address(null_mut()), 0);
+    }
+}
@@ -0,0 +1,85 @@
+//! Algorithms of the page cache: the read-ahead window and the dirty sector masks.
+//!
+//! The read-ahead window of a cache starts at [`READ_AHEAD_MIN`] pages on a random miss, and
+//! doubles up to [`READ_AHEAD_MAX`] while each miss is the first page after the last window. The
+//! window never reads past the end of the object, but always reads the missed page.
+//!
+//! The dirty state of a cached page is a mask of its sectors, one bit for each sector of
+//! [`SECTOR_SIZE`] bytes, so a writeback only writes the blocks holding the dirty sectors.
+
+use core::ops::Range;
+use crate::mm::PAGE_SIZE;
+use crate::partition::SECTOR_SIZE;
+
+
+/// Min count of the pages read ahead.
+pub const READ_AHEAD_MIN: u64 = 4;
+/// Max count of the pages read ahead, 128 KiB.
+pub const READ_AHEAD_MAX: u64 = 32;
+
+const SECTOR_SHIFT: usize = 9;
+sa::const_assert_eq!(1 << SECTOR_SHIFT, SECTOR_SIZE);
+
+/// Count of the sectors of a page, the bits of a dirty mask.
+pub const SECTORS_PER_PAGE: usize = PAGE_SIZE / SECTOR_SIZE;
+sa::const_assert!(SECTORS_PER_PAGE <= 32);
+
+/// Read-ahead state of a cache.
+#[derive(Debug, Eq, PartialEq)]
+pub struct ReadAhead {
+    /// First page after the last window.
+    next: u64,
+    /// Size of the last window, 0 if there is none.
+    size: u64,
+}
+
+impl ReadAhead {
+    pub const fn new() -> Self {
+        Self { next: 0, size: 0 }
+    }
+
+    /// Get the pages to read for a miss of the page `index`, in an object of `nr_pages` pages.
+    pub fn window(&self, index: u64, nr_pages: u64) -> Range<u64> {
+        let size = if index == self.next && self.size > 0 {
+            (self.size * 2).min(READ_AHEAD_MAX)
+        } else {
+            READ_AHEAD_MIN
+        };
+        index..(index + size).min(nr_pages).max(index + 1)
+    }
+
+    /// Record the `count` pages read from the page `index`, at most the [`window`].
+    ///
+    /// [`window`]: ReadAhead::window
+    pub fn update(&mut self, index: u64, count: u64) {
+        self.next = index + count;
+        self.size = count;
+    }
+
+    /// Drop the window if it reaches the pages from `first`, which are truncated.
+    pub fn truncate(&mut self, first: u64) {
+        if self.next > first {
+            *self = Self::new();
+        }
+    }
+}
+
+/// Mask of the sectors overlapping the bytes `range` of a page.
+pub fn sector_mask(range: Range<usize>) -> u32 {
+    if range.is_empty() {
+        return 0;
+    }
+    let first = range.start >> SECTOR_SHIFT;
+    let end = (range.end + SECTOR_SIZE - 1) >> SECTOR_SHIFT;
+    (((1u64 << end) - 1) & !((1u64 << first) - 1)) as u32
+}
+
+/// Unmap the `blocks` of `block_size` bytes of a page which have no sector in the `dirty` mask,
+/// so they are not written back.
+pub fn clean_blocks(dirty: u32, block_size: usize, blocks: &mut [Option<u64>]) {
+    for (i, block) in blocks.iter_mut().enumerate() {
+        if dirty & sector_mask(i * block_size..(i + 1) * block_size) == 0 {
+            *block = None;
+        }
+    }
+}
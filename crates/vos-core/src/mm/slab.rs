@@ -0,0 +1,128 @@
+//! Slab size calculation.
+//!
+//! Determines the page order and the object count of a slab given the object size, see the
+//! Linux `mm/slub.c` for more details.
+
+use crate::mm::{PAGE_ORDER, PAGE_SIZE};
+use crate::mm::page::{PAGE_ALLOC_COSTLY_ORDER, PAGE_ALLOC_MAX_ORDER};
+
+
+/// Max order of the slab pages in the normal case.
+pub const SLUB_MAX_ORDER: u32 = PAGE_ALLOC_COSTLY_ORDER;
+
+/// Max object count of a slab. The object count is stored in 15 bits of the slab counters.
+pub const MAX_OBJS_PER_PAGE: u32 = (1u32 << 15) - 1u32;
+
+/// Calculates the best order used to alloc pages for a slab with the special `size` object.
+/// `nr_cpus` is the count of the cpus, more objects are placed in a slab if there are more cpus.
+/// Returns `None` if we cannot find an appropriate order.
+pub fn calc_order(size: u32, nr_cpus: usize) -> Option<u32> {
+    // 4 * (fls(nr_cpus) + 1)
+    let mut min_objects = 4u32 * (usize::BITS - nr_cpus.leading_zeros() + 1u32);
+    let max_objects = order_objects(SLUB_MAX_ORDER, size);
+    min_objects = core::cmp::min(min_objects, max_objects);
+
+    while min_objects > 1 {
+        let mut fraction = 16u32;
+        while fraction >= 4u32 {
+            let order = calc_slab_order(size, min_objects, SLUB_MAX_ORDER, fraction);
+            if order <= SLUB_MAX_ORDER {
+                return Some(order);
+            }
+            fraction /= 2u32;
+        }
+        min_objects -= 1;
+    }
+
+    // We were unable to place multiple objects in a slab. Now lets see if we can place
+    // a single object there.
+    let order = calc_slab_order(size, 1, SLUB_MAX_ORDER, 1);
+    if order <= SLUB_MAX_ORDER {
+        return Some(order);
+    }
+
+    // This slab cannot be placed using SLUB_MAX_ORDER.
+    let order = calc_slab_order(size, 1, PAGE_ALLOC_MAX_ORDER, 1);
+    if order < PAGE_ALLOC_MAX_ORDER {
+        return Some(order);
+    }
+
+    None
+}
+
+/// Calculates the order of allocation given an slab object size.
+///
+/// Generally order 0 allocations should be preferred since order 0 does not cause fragmentation
+/// in the page allocator. We go to a higher order if more than 1/16th of the slab would be
+/// wasted.
+///
+/// In order to reach satisfactory performance we must ensure that a minimum number of objects
+/// is in the slab. Otherwise we may generate too much activity on the partial lists which
+/// requires taking the list_lock.
+///
+/// `max_order` specifies the order where we begin to stop considering the number of objects
+/// in a slab as critical. If we reach `max_order` then we try to keep the page order as low
+/// as possible. So we accept more waste of space in favor of a small page order.
+pub fn calc_slab_order(size: u32, min_objects: u32, max_order: u32, fract_leftover: u32) -> u32 {
+    let min_order = 0u32;
+    if order_objects(min_order, size) > MAX_OBJS_PER_PAGE {
+        return get_order((size * MAX_OBJS_PER_PAGE) as usize) - 1;
+    }
+
+    let mut order = core::cmp::max(min_order, get_order((size * min_objects) as usize));
+    while order <= max_order {
+        let slab_size = PAGE_SIZE << order;
+        let rem = slab_size % size as usize;
+        if rem <= (slab_size / fract_leftover as usize) {
+            break;
+        }
+
+        order += 1;
+    }
+
+    order
+}
+
+/// Calculates the object count for a slab allocating the `order` page.
+#[inline(always)]
+pub const fn order_objects(order: u32, size: u32) -> u32 {
+    ((PAGE_SIZE << order) / size as usize) as _
+}
+
+/// Determine the allocation order of a memory size. The result is undefined if the `size` is 0.
+#[inline(always)]
+pub const fn get_order(mut size: usize) -> u32 {
+    size -= 1usize;
+    size >>= PAGE_ORDER;
+    usize::BITS - size.leading_zeros()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_order_boundary() {
+        assert_eq!(get_order(1), 0);
+        assert_eq!(get_order(PAGE_SIZE), 0);
+        assert_eq!(get_order(PAGE_SIZE + 1), 1);
+        assert_eq!(get_order(PAGE_SIZE << 3), 3);
+    }
+
+    #[test]
+    fn small_objects_use_order_0() {
+        for size in [8u32, 16, 32, 64, 128, 256] {
+            assert_eq!(calc_order(size, 1), Some(0));
+        }
+    }
+
+    #[test]
+    fn large_objects() {
+        // A single object of the max order.
+        let size = (PAGE_SIZE << (PAGE_ALLOC_MAX_ORDER - 1)) as u32;
+        assert_eq!(calc_order(size, 4), Some(PAGE_ALLOC_MAX_ORDER - 1));
+        // Can not be placed on any order.
+        assert_eq!(calc_order(size + 8, 4), None);
+    }
+}
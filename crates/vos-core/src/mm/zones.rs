@@ -0,0 +1,183 @@
+//! Zones of the buddy allocator over the memory regions.
+//!
+//! Each memory region is managed by its own [`Zone`], so the holes between the regions are never
+//! used; a region crossing the DMA32 limit (4GiB on the real machine) is split into two zones.
+//! The zones below the limit are the `DMA32` zones, addressable by the 32-bit DMA devices, and
+//! the others are the `Normal` zones. The allocations with [`gfp::GFP_DMA32`] (or
+//! [`gfp::GFP_DMA`], there is no smaller DMA zone on RISC-V) are only served by the `DMA32`
+//! zones, the other allocations try the `Normal` zones first and fall back to the `DMA32` zones.
+//! The zone idx is saved in each allocated [`Page`], so the page is returned to its zone.
+//!
+//! The limit is a parameter of [`Zones::new`], so the host tests can split a memory buffer.
+
+use core::ptr::null_mut;
+use crate::mm::page::{Page, Zone, MAX_NR_ZONES};
+
+
+/// Page alloc flags type.
+pub type GfpAllocFlag = u32;
+
+pub mod gfp {
+    use super::GfpAllocFlag;
+
+    pub const GFP_DMA: GfpAllocFlag = 1u32;
+    pub const GFP_DMA32: GfpAllocFlag = 1u32 << 2;
+    pub const GFP_COMPOUND: GfpAllocFlag = 1u32 << 18;
+    pub const GFP_RECLAIMABLE: GfpAllocFlag = 1u32 << 4;
+
+    pub const GFP_KERNEL: GfpAllocFlag = 0u32; // todo
+    pub const GFP_NO_WAIT: GfpAllocFlag = 0u32;
+}
+
+/// End address (exclusive) of the `DMA32` zones of the real machine.
+pub const DMA32_LIMIT: usize = 1usize << 32;
+
+/// Zone types, see [the mod document](self).
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ZoneType {
+    Dma32,
+    Normal,
+}
+
+/// Error of [`Zones::add_zone`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ZoneError {
+    /// All [`MAX_NR_ZONES`] zones are used.
+    TooMany,
+    /// The memory can not hold a max block.
+    TooSmall,
+}
+
+/// Get the zone types to allocate from by the `flags`, in the order of preference.
+pub fn zone_fallback_list(flags: GfpAllocFlag) -> &'static [ZoneType] {
+    if flags & (gfp::GFP_DMA | gfp::GFP_DMA32) != 0 {
+        &[ZoneType::Dma32]
+    } else {
+        &[ZoneType::Normal, ZoneType::Dma32]
+    }
+}
+
+/// The zones of the page allocator, in the order they are added.
+///
+/// The zones contain the heads of the free lists, so it **must not** be moved after a zone is
+/// added.
+pub struct Zones {
+    zones: [Zone; MAX_NR_ZONES],
+    types: [ZoneType; MAX_NR_ZONES],
+    /// Count of the initialized zones.
+    nr_zones: usize,
+    dma32_limit: usize,
+}
+
+impl Zones {
+    /// Create an empty zone list, the memory below `dma32_limit` is the `DMA32` zones.
+    pub const fn new(dma32_limit: usize) -> Self {
+        const VAL: Zone = Zone::new();
+        Self {
+            zones: [VAL; MAX_NR_ZONES],
+            types: [ZoneType::Normal; MAX_NR_ZONES],
+            nr_zones: 0,
+            dma32_limit,
+        }
+    }
+
+    /// Split the memory region `[start, end)` into the zones `(type, start, end)` at the DMA32
+    /// limit.
+    pub fn split_region(&self, start: usize, end: usize)
+        -> impl Iterator<Item = (ZoneType, usize, usize)> {
+        let limit = self.dma32_limit;
+        let dma32 = (start < limit).then(|| (ZoneType::Dma32, start, end.min(limit)));
+        let normal = (end > limit).then(|| (ZoneType::Normal, start.max(limit), end));
+        [dma32, normal].into_iter().flatten()
+    }
+
+    /// Init the next zone of the type `ty` to manage the memory `[start, end)`. The bitmaps and
+    /// the `Page` objects of the zone are placed from `start`.
+    ///
+    /// # Safety
+    /// The memory must be valid and not used by others, see [`Zone::init`].
+    pub unsafe fn add_zone(&mut self, ty: ZoneType, start: usize, end: usize)
+        -> Result<&Zone, ZoneError> {
+        let idx = self.nr_zones;
+        if idx == MAX_NR_ZONES {
+            return Err(ZoneError::TooMany);
+        }
+        let zone = &mut self.zones[idx];
+        if !zone.init(idx, start, end - start, start) {
+            return Err(ZoneError::TooSmall);
+        }
+        self.types[idx] = ty;
+        self.nr_zones = idx + 1;
+        Ok(&self.zones[idx])
+    }
+
+    /// Get the initialized zones.
+    #[inline(always)]
+    pub fn zones(&self) -> &[Zone] {
+        &self.zones[..self.nr_zones]
+    }
+
+    #[inline(always)]
+    pub fn zones_mut(&mut self) -> &mut [Zone] {
+        &mut self.zones[..self.nr_zones]
+    }
+
+    /// Get the type of the zone `idx`.
+    #[inline(always)]
+    pub fn zone_type(&self, idx: usize) -> ZoneType {
+        self.types[idx]
+    }
+
+    /// Allocate `2^order` pages from the zones allowed by the `flags`. Returns null if there is
+    /// no free block.
+    pub fn alloc(&mut self, flags: GfpAllocFlag, order: usize) -> *mut Page {
+        for &ty in zone_fallback_list(flags) {
+            let types = &self.types;
+            let zones = self.zones[..self.nr_zones].iter_mut().zip(types);
+            for (zone, _) in zones.filter(|(_, t)| **t == ty) {
+                let page = zone.alloc(order);
+                if !page.is_null() {
+                    return page;
+                }
+            }
+        }
+
+        null_mut()
+    }
+
+    /// Free the `2^order` pages of the `page` to its zone.
+    ///
+    /// # Safety
+    /// The `page` must be allocated from these zones with the same `order`.
+    pub unsafe fn free(&mut self, page: *mut Page, order: usize) {
+        let zone_idx = (*page).get_zone_idx();
+        debug_assert!(zone_idx < self.nr_zones);
+        self.zones.get_unchecked_mut(zone_idx).free(page, order);
+    }
+
+    /// Get the address of an allocated `Page`.
+    ///
+    /// # Safety
+    /// The `page` must be allocated from these zones.
+    #[inline(always)]
+    pub unsafe fn page_to_address(&self, page: *const Page) -> usize {
+        self.zones.get_unchecked((*page).get_zone_idx()).page_to_address(page)
+    }
+
+    /// Get the `Page` of the address `addr`, null if it is not managed by the zones.
+    pub fn address_to_page(&self, addr: usize) -> *mut Page {
+        self.zones().iter()
+            .find(|zone| zone.contains_address(addr))
+            .map_or(null_mut(), |zone| zone.address_to_page(addr))
+    }
+
+    /// Get the count of the pages managed by the zones.
+    pub fn nr_total_pages(&self) -> usize {
+        self.zones().iter().map(|zone| zone.max_pages()).sum()
+    }
+
+    /// Get the count of the free pages.
+    pub fn nr_free_pages(&self) -> usize {
+        self.zones().iter().map(|zone| zone.free_pages()).sum()
+    }
+}
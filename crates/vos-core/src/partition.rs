@@ -0,0 +1,432 @@
+//! Parser of the partition tables on a disk: the MBR (with the extended partitions) and the GPT.
+//!
+//! The disk is read by the sectors of [`SECTOR_SIZE`] bytes through a callback, the found
+//! partitions are reported in the order of their numbers:
+//!
+//! - MBR: the primary partitions are numbered `1` to `4` by their slots, the logical partitions
+//!   in the chain of the extended boot records (EBR) are numbered from `5`.
+//! - GPT: the used entries are numbered from `1` by their index. The header and the entry array
+//!   are checked by their CRC32, the backup header at the last sector is used if the primary one
+//!   is broken.
+//!
+//! Like Linux, a disk has a GPT only if the MBR has a protective partition (type `0xee`), and an
+//! MBR is ignored if any boot indicator is not `0x00` or `0x80`, which is common for a file
+//! system (e.g. FAT) written to the whole disk. The parser never panics on a malformed table.
+
+use core::fmt;
+
+
+/// Size of a sector.
+pub const SECTOR_SIZE: usize = 512;
+
+/// MBR partition type of the protective partition covering a GPT disk.
+pub const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xee;
+/// MBR partition types of the extended partition.
+const MBR_TYPE_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+/// Offset of the partition entries in an MBR or EBR.
+const MBR_ENTRIES: usize = 446;
+/// Max count of the logical partitions, which also stops a looped EBR chain.
+const MAX_LOGICAL: u32 = 64;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+/// Size of the GPT header covered by its CRC32.
+const GPT_HEADER_SIZE: usize = 92;
+/// Max count of the GPT entries read.
+const GPT_MAX_ENTRIES: u32 = 256;
+
+/// Partition table errors.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PartitionError {
+    /// A sector can not be read.
+    Io,
+    /// The MBR is protective, but neither GPT header is valid.
+    BadGpt,
+}
+
+impl fmt::Display for PartitionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            PartitionError::Io => "I/O error",
+            PartitionError::BadGpt => "bad GPT",
+        };
+        f.write_str(msg)
+    }
+}
+
+/// Kind of a partition table.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Scheme {
+    Mbr,
+    Gpt,
+}
+
+/// Type of a partition.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PartitionType {
+    /// The system id of an MBR partition.
+    Mbr(u8),
+    /// The type GUID of a GPT partition, in the on-disk byte order.
+    Gpt([u8; 16]),
+}
+
+/// A partition, in sectors of the disk.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Partition {
+    /// Partition number, from 1.
+    pub number: u32,
+    pub start: u64,
+    pub sectors: u64,
+    pub kind: PartitionType,
+}
+
+type Sector = [u8; SECTOR_SIZE];
+
+#[inline(always)]
+fn le_u32(buf: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes([buf[off], buf[off + 1], buf[off + 2], buf[off + 3]])
+}
+
+#[inline(always)]
+fn le_u64(buf: &[u8], off: usize) -> u64 {
+    le_u32(buf, off) as u64 | (le_u32(buf, off + 4) as u64) << 32
+}
+
+/// Update the CRC32 (IEEE 802.3, the one of the GPT) `crc` with `data`. Start with `0`.
+pub fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// An entry of an MBR or EBR.
+struct MbrEntry {
+    boot: u8,
+    kind: u8,
+    start: u64,
+    sectors: u64,
+}
+
+fn mbr_entry(sector: &Sector, slot: usize) -> MbrEntry {
+    let entry = &sector[MBR_ENTRIES + 16 * slot..MBR_ENTRIES + 16 * (slot + 1)];
+    MbrEntry {
+        boot: entry[0],
+        kind: entry[4],
+        start: le_u32(entry, 8) as u64,
+        sectors: le_u32(entry, 12) as u64,
+    }
+}
+
+#[inline(always)]
+fn has_mbr_signature(sector: &Sector) -> bool {
+    sector[510] == 0x55 && sector[511] == 0xaa
+}
+
+/// Scan the partition table of a disk of `capacity` sectors. The sector `lba` is read by
+/// `read(lba, buf)`, which returns `false` on an I/O error. Each partition is passed to `add`.
+///
+/// Returns the kind of the table, or `None` if the disk is not partitioned.
+pub fn scan<R, F>(capacity: u64, mut read: R, mut add: F) -> Result<Option<Scheme>, PartitionError>
+    where R: FnMut(u64, &mut Sector) -> bool, F: FnMut(Partition) {
+    let mut mbr = [0u8; SECTOR_SIZE];
+    if capacity == 0 || !read(0, &mut mbr) {
+        return Err(PartitionError::Io);
+    }
+    if !has_mbr_signature(&mbr) || (0..4).any(|slot| mbr_entry(&mbr, slot).boot & 0x7f != 0) {
+        return Ok(None);
+    }
+    if (0..4).any(|slot| mbr_entry(&mbr, slot).kind == MBR_TYPE_GPT_PROTECTIVE) {
+        return scan_gpt(capacity, &mut read, &mut add).map(|_| Some(Scheme::Gpt));
+    }
+
+    let in_disk = |start: u64, sectors: u64| start > 0 && start.saturating_add(sectors) <= capacity;
+    let mut extended = None;
+    for slot in 0..4 {
+        let entry = mbr_entry(&mbr, slot);
+        if entry.kind == 0 || entry.sectors == 0 || !in_disk(entry.start, entry.sectors) {
+            continue;
+        }
+        if MBR_TYPE_EXTENDED.contains(&entry.kind) {
+            extended.get_or_insert((entry.start, entry.sectors));
+            continue;
+        }
+        add(Partition {
+            number: slot as u32 + 1,
+            start: entry.start,
+            sectors: entry.sectors,
+            kind: PartitionType::Mbr(entry.kind),
+        });
+    }
+
+    let Some((ext_start, ext_sectors)) = extended else {
+        return Ok(Some(Scheme::Mbr));
+    };
+    // The logical partition is relative to its EBR, the next EBR is relative to the extended
+    // partition.
+    let mut ebr_lba = ext_start;
+    let mut ebr = [0u8; SECTOR_SIZE];
+    for number in 5..5 + MAX_LOGICAL {
+        if !read(ebr_lba, &mut ebr) {
+            return Err(PartitionError::Io);
+        }
+        if !has_mbr_signature(&ebr) {
+            break;
+        }
+        let logical = mbr_entry(&ebr, 0);
+        let start = ebr_lba + logical.start;
+        if logical.kind != 0 && logical.sectors != 0 && logical.start != 0 &&
+            start.saturating_add(logical.sectors) <= ext_start + ext_sectors {
+            add(Partition {
+                number,
+                start,
+                sectors: logical.sectors,
+                kind: PartitionType::Mbr(logical.kind),
+            });
+        }
+        let next = mbr_entry(&ebr, 1);
+        if next.start == 0 || next.start >= ext_sectors || ext_start + next.start <= ebr_lba {
+            break;
+        }
+        ebr_lba = ext_start + next.start;
+    }
+    Ok(Some(Scheme::Mbr))
+}
+
+/// A checked GPT header.
+struct GptHeader {
+    first_usable: u64,
+    last_usable: u64,
+    entries_lba: u64,
+    num_entries: u32,
+    entry_size: u32,
+    entries_crc: u32,
+}
+
+/// Read and check the GPT header at `lba`.
+fn gpt_header<R>(capacity: u64, lba: u64, read: &mut R) -> Result<Option<GptHeader>, PartitionError>
+    where R: FnMut(u64, &mut Sector) -> bool {
+    let mut sector = [0u8; SECTOR_SIZE];
+    if !read(lba, &mut sector) {
+        return Err(PartitionError::Io);
+    }
+    let size = le_u32(&sector, 12) as usize;
+    if &sector[..8] != GPT_SIGNATURE || !(GPT_HEADER_SIZE..=SECTOR_SIZE).contains(&size) {
+        return Ok(None);
+    }
+    let crc = le_u32(&sector, 16);
+    sector[16..20].fill(0);
+    if crc32(0, &sector[..size]) != crc || le_u64(&sector, 24) != lba {
+        return Ok(None);
+    }
+
+    let header = GptHeader {
+        first_usable: le_u64(&sector, 40),
+        last_usable: le_u64(&sector, 48),
+        entries_lba: le_u64(&sector, 72),
+        num_entries: le_u32(&sector, 80),
+        entry_size: le_u32(&sector, 84),
+        entries_crc: le_u32(&sector, 88),
+    };
+    let entry_size = header.entry_size as usize;
+    if entry_size < 128 || !entry_size.is_power_of_two() || entry_size > SECTOR_SIZE ||
+        header.first_usable > header.last_usable || header.last_usable >= capacity ||
+        header.entries_lba == 0 || header.entries_lba >= capacity {
+        return Ok(None);
+    }
+    Ok(Some(header))
+}
+
+/// Call `handle` with the index and the bytes of each entry of the GPT `header`, and check the
+/// CRC32 of the entry array. Returns `false` if the CRC32 does not match.
+fn gpt_entries<R, H>(header: &GptHeader, read: &mut R, mut handle: H)
+    -> Result<bool, PartitionError>
+    where R: FnMut(u64, &mut Sector) -> bool, H: FnMut(u32, &[u8]) {
+    let entry_size = header.entry_size as usize;
+    let per_sector = SECTOR_SIZE / entry_size;
+    let mut sector = [0u8; SECTOR_SIZE];
+    let mut crc = 0;
+    for idx in 0..header.num_entries {
+        let slot = idx as usize % per_sector;
+        if slot == 0 {
+            let lba = header.entries_lba + (idx as usize / per_sector) as u64;
+            if !read(lba, &mut sector) {
+                return Err(PartitionError::Io);
+            }
+        }
+        let entry = &sector[slot * entry_size..(slot + 1) * entry_size];
+        crc = crc32(crc, entry);
+        if idx < GPT_MAX_ENTRIES {
+            handle(idx, entry);
+        }
+    }
+    Ok(crc == header.entries_crc)
+}
+
+fn scan_gpt<R, F>(capacity: u64, read: &mut R, add: &mut F) -> Result<(), PartitionError>
+    where R: FnMut(u64, &mut Sector) -> bool, F: FnMut(Partition) {
+    for lba in [1, capacity - 1] {
+        let Some(header) = gpt_header(capacity, lba, read)? else {
+            continue;
+        };
+        // Check the entries before reporting any of them.
+        if !gpt_entries(&header, read, |_, _| {})? {
+            continue;
+        }
+        gpt_entries(&header, read, |idx, entry| {
+            let mut kind = [0u8; 16];
+            kind.copy_from_slice(&entry[..16]);
+            let (first, last) = (le_u64(entry, 32), le_u64(entry, 40));
+            if kind == [0; 16] || first < header.first_usable || last > header.last_usable ||
+                first > last {
+                return;
+            }
+            add(Partition {
+                number: idx + 1,
+                start: first,
+                sectors: last - first + 1,
+                kind: PartitionType::Gpt(kind),
+            });
+        })?;
+        return Ok(());
+    }
+    Err(PartitionError::BadGpt)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::vec;
+    use std::vec::Vec;
+    use super::*;
+
+    const DISK_SECTORS: u64 = 2048;
+
+    fn read_from(disk: &[u8]) -> impl FnMut(u64, &mut Sector) -> bool + '_ {
+        |lba, buf| {
+            let off = lba as usize * SECTOR_SIZE;
+            match disk.get(off..off + SECTOR_SIZE) {
+                Some(data) => {
+                    buf.copy_from_slice(data);
+                    true
+                }
+                None => false,
+            }
+        }
+    }
+
+    fn scan_disk(disk: &[u8]) -> (Result<Option<Scheme>, PartitionError>, Vec<Partition>) {
+        let mut parts = Vec::new();
+        let result = scan(DISK_SECTORS, read_from(disk), |part| parts.push(part));
+        (result, parts)
+    }
+
+    fn set_entry(disk: &mut [u8], lba: u64, slot: usize, kind: u8, start: u32, sectors: u32) {
+        let off = lba as usize * SECTOR_SIZE + MBR_ENTRIES + 16 * slot;
+        disk[off + 4] = kind;
+        disk[off + 8..off + 12].copy_from_slice(&start.to_le_bytes());
+        disk[off + 12..off + 16].copy_from_slice(&sectors.to_le_bytes());
+        let sig = lba as usize * SECTOR_SIZE + 510;
+        disk[sig..sig + 2].copy_from_slice(&[0x55, 0xaa]);
+    }
+
+    fn mbr(kind: u8, start: u64, sectors: u64) -> Partition {
+        Partition { number: 0, start, sectors, kind: PartitionType::Mbr(kind) }
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(0, b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(crc32(0, b"1234"), b"56789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn scan_mbr() {
+        let mut disk = vec![0u8; DISK_SECTORS as usize * SECTOR_SIZE];
+        assert_eq!(scan_disk(&disk).0, Ok(None));
+
+        set_entry(&mut disk, 0, 0, 0x83, 2, 100);
+        set_entry(&mut disk, 0, 2, 0x05, 200, 1000);
+        // Logical partitions: one at 210, the next EBR at 200 + 500.
+        set_entry(&mut disk, 200, 0, 0x0c, 10, 50);
+        set_entry(&mut disk, 200, 1, 0x05, 500, 300);
+        set_entry(&mut disk, 700, 0, 0x83, 4, 20);
+        // Out of the disk, ignored.
+        set_entry(&mut disk, 0, 3, 0x83, 2000, 100);
+        let (result, parts) = scan_disk(&disk);
+        assert_eq!(result, Ok(Some(Scheme::Mbr)));
+        assert_eq!(parts, [
+            Partition { number: 1, ..mbr(0x83, 2, 100) },
+            Partition { number: 5, ..mbr(0x0c, 210, 50) },
+            Partition { number: 6, ..mbr(0x83, 704, 20) },
+        ]);
+
+        // A looped EBR chain stops.
+        set_entry(&mut disk, 700, 1, 0x05, 500, 300);
+        assert_eq!(scan_disk(&disk).1.len(), 3);
+
+        // A FAT boot sector is not an MBR.
+        disk[MBR_ENTRIES] = 0x12;
+        assert_eq!(scan_disk(&disk).0, Ok(None));
+    }
+
+    fn write_gpt_header(disk: &mut [u8], lba: u64, entries_lba: u64, entries_crc: u32) {
+        let off = lba as usize * SECTOR_SIZE;
+        let header = &mut disk[off..off + SECTOR_SIZE];
+        header[..8].copy_from_slice(GPT_SIGNATURE);
+        header[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+        header[12..16].copy_from_slice(&(GPT_HEADER_SIZE as u32).to_le_bytes());
+        header[24..32].copy_from_slice(&lba.to_le_bytes());
+        header[40..48].copy_from_slice(&34u64.to_le_bytes());
+        header[48..56].copy_from_slice(&(DISK_SECTORS - 34).to_le_bytes());
+        header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+        header[80..84].copy_from_slice(&128u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+        let crc = crc32(0, &header[..GPT_HEADER_SIZE]);
+        header[16..20].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    #[test]
+    fn scan_gpt() {
+        let mut disk = vec![0u8; DISK_SECTORS as usize * SECTOR_SIZE];
+        set_entry(&mut disk, 0, 0, MBR_TYPE_GPT_PROTECTIVE, 1, DISK_SECTORS as u32 - 1);
+        // Entries of 128 bytes: #1 used, #2 empty, #4 used.
+        let mut entries = vec![0u8; 128 * 128];
+        for (idx, first, last) in [(0usize, 34u64, 99u64), (3, 100, 1000)] {
+            let entry = &mut entries[idx * 128..(idx + 1) * 128];
+            entry[..16].copy_from_slice(&[0xaf; 16]);
+            entry[32..40].copy_from_slice(&first.to_le_bytes());
+            entry[40..48].copy_from_slice(&last.to_le_bytes());
+        }
+        let entries_crc = crc32(0, &entries);
+        let backup_entries = DISK_SECTORS - 33;
+        for lba in [2, backup_entries] {
+            let off = lba as usize * SECTOR_SIZE;
+            disk[off..off + entries.len()].copy_from_slice(&entries);
+        }
+        write_gpt_header(&mut disk, 1, 2, entries_crc);
+        write_gpt_header(&mut disk, DISK_SECTORS - 1, backup_entries, entries_crc);
+
+        let expected = [
+            Partition { number: 1, start: 34, sectors: 66, kind: PartitionType::Gpt([0xaf; 16]) },
+            Partition { number: 4, start: 100, sectors: 901, kind: PartitionType::Gpt([0xaf; 16]) },
+        ];
+        let (result, parts) = scan_disk(&disk);
+        assert_eq!(result, Ok(Some(Scheme::Gpt)));
+        assert_eq!(parts, expected);
+
+        // Broken primary entries, the backup is used.
+        disk[2 * SECTOR_SIZE + 32] = 0;
+        let (result, parts) = scan_disk(&disk);
+        assert_eq!(result, Ok(Some(Scheme::Gpt)));
+        assert_eq!(parts, expected);
+
+        // Both broken.
+        disk[(DISK_SECTORS - 1) as usize * SECTOR_SIZE] = 0;
+        assert_eq!(scan_disk(&disk), (Err(PartitionError::BadGpt), Vec::new()));
+    }
+}
@@ -87,3 +87,35 @@ pub const fn align_down_by(val: usize, align: usize) -> usize {
     let order = get_order(align);
     align_down(val, order)
 }
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn align_up_down() {
+        assert_eq!(align_up(0, 12), 0);
+        assert_eq!(align_up(1, 12), 4096);
+        assert_eq!(align_up(4096, 12), 4096);
+        assert_eq!(align_down(4095, 12), 0);
+        assert_eq!(align_down(8193, 12), 8192);
+        assert_eq!(align_up_by(17, 16), 32);
+        assert_eq!(align_down_by(17, 16), 16);
+    }
+
+    #[test]
+    fn align_of_type() {
+        assert_eq!(align_up_of::<u64>(9), 16);
+        assert_eq!(align_down_of::<u32>(7), 4);
+        assert_eq!(align_up_of::<u8>(7), 7);
+    }
+
+    #[test]
+    fn order_of_alignment() {
+        assert_eq!(get_order(1), 0);
+        assert_eq!(get_order(4096), 12);
+        assert_eq!(get_order(1 << 30), 30);
+    }
+}
@@ -28,3 +28,30 @@ pub fn test_and_change_bit_array(bits: *mut u8, pos: usize) -> bool {
         (old_val & ((1usize << bits_pos) as u8)) != 0u8
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn change_bit() {
+        assert_eq!(change_bit_u8(0b0000_0000, 3), 0b0000_1000);
+        assert_eq!(change_bit_u8(0b1111_1111, 7), 0b0111_1111);
+
+        let mut bits = [0u8; 4];
+        change_bit_array(bits.as_mut_ptr(), 9);
+        assert_eq!(bits, [0, 0b10, 0, 0]);
+        change_bit_array(bits.as_mut_ptr(), 9);
+        assert_eq!(bits, [0; 4]);
+    }
+
+    #[test]
+    fn test_and_change_bit() {
+        let mut bits = [0u8; 2];
+        assert!(!test_and_change_bit_array(bits.as_mut_ptr(), 15));
+        assert_eq!(bits, [0, 0x80]);
+        assert!(test_and_change_bit_array(bits.as_mut_ptr(), 15));
+        assert_eq!(bits, [0, 0]);
+    }
+}
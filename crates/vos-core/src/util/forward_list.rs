@@ -20,7 +20,7 @@ impl ForwardList {
 
     /// Returns `true` if list is empty.
     #[inline(always)]
-    pub const fn is_empty(self) -> bool {
+    pub fn is_empty(self) -> bool {
         self.next.is_null()
     }
 
@@ -44,3 +44,28 @@ pub fn remove_next(head: &mut ForwardList) {
         head.next = (*head.next).next;
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_and_remove() {
+        let mut head = ForwardList::new_empty();
+        let mut a = ForwardList::new_empty();
+        let mut b = ForwardList::new_empty();
+        assert!(head.is_empty());
+
+        insert_after(&mut head, &mut a);
+        insert_after(&mut head, &mut b);
+        assert_eq!(head.next(), &mut b as *mut ForwardList);
+        assert_eq!(b.next(), &mut a as *mut ForwardList);
+        assert!(a.is_empty());
+
+        remove_next(&mut head);
+        assert_eq!(head.next(), &mut a as *mut ForwardList);
+        remove_next(&mut head);
+        assert!(head.is_empty());
+    }
+}
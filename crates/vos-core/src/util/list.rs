@@ -90,7 +90,7 @@ pub fn insert_after(node: &mut List, entry: &mut List) {
 /// Test whether a list is empty.
 #[inline(always)]
 pub fn is_empty(head: &List) -> bool {
-    (head as *const List) == head.next
+    core::ptr::eq(head, head.next)
 }
 
 /// Check whether the `entry` is the last item of list `head`.
@@ -98,13 +98,13 @@ pub fn is_empty(head: &List) -> bool {
 /// **Note**: This function will return `true` if `head` and `entry` are the same empty list.
 #[inline(always)]
 pub fn is_last(head: &List, entry: &List) -> bool {
-    (entry as *const List) == head.prev
+    core::ptr::eq(entry, head.prev)
 }
 
 /// Test whether a list has just one entry.
 #[inline(always)]
 pub fn is_singular(head: &List) -> bool {
-    (head as *const List) != head.next && (head.next == head.prev)
+    !core::ptr::eq(head, head.next) && (head.next == head.prev)
 }
 
 /// Delete `entry` from list. No effect if list is empty.
@@ -148,7 +148,7 @@ pub fn next(cur: *mut List) -> *mut List {
 
 /// Iterate over the list, break if `handle` returns `false`.
 #[inline]
-pub fn for_each<F>(head: &mut List, handle: F)
+pub fn for_each<F>(head: &mut List, mut handle: F)
     where F: FnMut(*mut List) -> bool {
     let head_ptr = head as *mut List;
     let mut cur = head.next;
@@ -160,3 +160,70 @@ pub fn for_each<F>(head: &mut List, handle: F)
         cur = tmp;
     }
 }
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn append_and_delete() {
+        let mut head = List::new();
+        let mut a = List::new();
+        let mut b = List::new();
+        head.init_empty();
+        assert!(is_empty(&head));
+        assert!(is_last(&head, &head));
+
+        tail_append(&mut head, &mut a);
+        head_append(&mut head, &mut b);
+        assert_eq!(count(&head), 2);
+        assert_eq!(head.next, &mut b as *mut List);
+        assert!(is_last(&head, &a));
+
+        delete_and_init_empty(&mut b);
+        assert!(is_singular(&head));
+        assert!(is_empty(&b));
+        delete(&mut a);
+        assert!(is_empty(&head));
+    }
+
+    #[test]
+    fn insert_keeps_order() {
+        let mut head = List::new();
+        let mut entries = [List::new(); 3];
+        head.init_empty();
+        let (first, rest) = entries.split_at_mut(1);
+        let (second, third) = rest.split_at_mut(1);
+        tail_append(&mut head, &mut third[0]);
+        insert_before(&mut third[0], &mut first[0]);
+        insert_after(&mut first[0], &mut second[0]);
+
+        let expected = [&first[0] as *const List, &second[0], &third[0]];
+        let mut idx = 0usize;
+        for_each(&mut head, |cur| {
+            assert_eq!(cur as *const List, expected[idx]);
+            idx += 1;
+            true
+        });
+        assert_eq!(idx, 3);
+    }
+
+    #[test]
+    fn for_each_break() {
+        let mut head = List::new();
+        let mut entries = [List::new(); 4];
+        head.init_empty();
+        for e in entries.iter_mut() {
+            tail_append(&mut head, e);
+        }
+
+        let mut visited = 0usize;
+        for_each(&mut head, |_| {
+            visited += 1;
+            visited < 3
+        });
+        assert_eq!(visited, 3);
+    }
+}
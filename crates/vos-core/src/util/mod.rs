@@ -0,0 +1,7 @@
+//! Intrusive containers and the bit/alignment helpers.
+
+pub mod align;
+pub mod bit;
+pub mod forward_list;
+pub mod list;
+pub mod rbtree;
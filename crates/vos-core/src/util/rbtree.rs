@@ -0,0 +1,442 @@
+//! Red-black tree (Intrusive) implementation.
+//!
+//! Like the Linux `rbtree`, the tree does not know the key of the entries: the [`RbNode`] is
+//! embedded in the entry struct, the caller decides the position of a new node by a compare
+//! closure in [`insert`] and searches the tree by walking the [`left`] and [`right`] children.
+//!
+//! [`left`]: RbNode::left
+//! [`right`]: RbNode::right
+
+use core::cmp::Ordering;
+use core::ptr::null_mut;
+
+
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Color {
+    Red = 0,
+    Black = 1,
+}
+
+/// Red-black tree node. Embedded in the actual entry struct.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct RbNode {
+    parent: *mut RbNode,
+    left: *mut RbNode,
+    right: *mut RbNode,
+    color: Color,
+}
+
+impl RbNode {
+    /// Construct a node which is not in any tree.
+    pub const fn new() -> Self {
+        Self {
+            parent: null_mut(),
+            left: null_mut(),
+            right: null_mut(),
+            color: Color::Red,
+        }
+    }
+
+    /// Get the left child.
+    #[inline(always)]
+    pub const fn left(&self) -> *mut RbNode {
+        self.left
+    }
+
+    /// Get the right child.
+    #[inline(always)]
+    pub const fn right(&self) -> *mut RbNode {
+        self.right
+    }
+
+    /// Get the parent node, null if this is the root.
+    #[inline(always)]
+    pub const fn parent(&self) -> *mut RbNode {
+        self.parent
+    }
+
+    /// Returns `true` if the node is red.
+    #[inline(always)]
+    pub fn is_red(&self) -> bool {
+        self.color == Color::Red
+    }
+}
+
+/// Root of a red-black tree.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct RbRoot {
+    pub node: *mut RbNode,
+}
+
+impl RbRoot {
+    /// Construct an empty tree.
+    pub const fn new() -> Self {
+        Self {
+            node: null_mut(),
+        }
+    }
+
+    /// Returns `true` if the tree is empty.
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.node.is_null()
+    }
+}
+
+#[inline(always)]
+fn is_black(node: *mut RbNode) -> bool {
+    node.is_null() || unsafe { (*node).color == Color::Black }
+}
+
+#[inline(always)]
+fn set_color(node: *mut RbNode, color: Color) {
+    if !node.is_null() {
+        unsafe { (*node).color = color; }
+    }
+}
+
+/// Replace the child `old` of `parent` (or the root if `parent` is null) with `new`.
+unsafe fn change_child(root: &mut RbRoot, parent: *mut RbNode, old: *mut RbNode,
+                       new: *mut RbNode) {
+    if parent.is_null() {
+        root.node = new;
+    } else if (*parent).left == old {
+        (*parent).left = new;
+    } else {
+        (*parent).right = new;
+    }
+}
+
+unsafe fn rotate_left(root: &mut RbRoot, x: *mut RbNode) {
+    let y = (*x).right;
+    (*x).right = (*y).left;
+    if !(*y).left.is_null() {
+        (*(*y).left).parent = x;
+    }
+    (*y).parent = (*x).parent;
+    change_child(root, (*x).parent, x, y);
+    (*y).left = x;
+    (*x).parent = y;
+}
+
+unsafe fn rotate_right(root: &mut RbRoot, x: *mut RbNode) {
+    let y = (*x).left;
+    (*x).left = (*y).right;
+    if !(*y).right.is_null() {
+        (*(*y).right).parent = x;
+    }
+    (*y).parent = (*x).parent;
+    change_child(root, (*x).parent, x, y);
+    (*y).right = x;
+    (*x).parent = y;
+}
+
+/// Insert `node` to the tree. `go_left` is called on the nodes along the search path, and returns
+/// `true` if the new node should be placed in the left subtree of the given node.
+pub fn insert<F>(root: &mut RbRoot, node: *mut RbNode, mut go_left: F)
+    where F: FnMut(*mut RbNode) -> bool {
+    unsafe {
+        let mut parent = null_mut();
+        let mut link = &mut root.node as *mut *mut RbNode;
+        while !(*link).is_null() {
+            parent = *link;
+            link = if go_left(parent) {
+                &mut (*parent).left
+            } else {
+                &mut (*parent).right
+            };
+        }
+
+        node.write(RbNode {
+            parent,
+            left: null_mut(),
+            right: null_mut(),
+            color: Color::Red,
+        });
+        *link = node;
+        insert_fixup(root, node);
+    }
+}
+
+unsafe fn insert_fixup(root: &mut RbRoot, mut node: *mut RbNode) {
+    loop {
+        let mut parent = (*node).parent;
+        if is_black(parent) {
+            break;
+        }
+        // A red node is never the root, so the grandparent exists.
+        let gparent = (*parent).parent;
+        if parent == (*gparent).left {
+            let uncle = (*gparent).right;
+            if !is_black(uncle) {
+                set_color(parent, Color::Black);
+                set_color(uncle, Color::Black);
+                set_color(gparent, Color::Red);
+                node = gparent;
+                continue;
+            }
+            if node == (*parent).right {
+                rotate_left(root, parent);
+                node = parent;
+                parent = (*node).parent;
+            }
+            set_color(parent, Color::Black);
+            set_color(gparent, Color::Red);
+            rotate_right(root, gparent);
+        } else {
+            let uncle = (*gparent).left;
+            if !is_black(uncle) {
+                set_color(parent, Color::Black);
+                set_color(uncle, Color::Black);
+                set_color(gparent, Color::Red);
+                node = gparent;
+                continue;
+            }
+            if node == (*parent).left {
+                rotate_right(root, parent);
+                node = parent;
+                parent = (*node).parent;
+            }
+            set_color(parent, Color::Black);
+            set_color(gparent, Color::Red);
+            rotate_left(root, gparent);
+        }
+    }
+    set_color(root.node, Color::Black);
+}
+
+/// Remove `node` from the tree.
+pub fn erase(root: &mut RbRoot, node: *mut RbNode) {
+    unsafe {
+        let child;
+        let parent;
+        let removed_color;
+        if (*node).left.is_null() || (*node).right.is_null() {
+            // At most one child, replace the node with it.
+            child = if (*node).left.is_null() { (*node).right } else { (*node).left };
+            parent = (*node).parent;
+            removed_color = (*node).color;
+            change_child(root, parent, node, child);
+            if !child.is_null() {
+                (*child).parent = parent;
+            }
+        } else {
+            // Two children, replace the node with its successor.
+            let successor = first_of((*node).right);
+            child = (*successor).right;
+            removed_color = (*successor).color;
+            if (*successor).parent == node {
+                parent = successor;
+            } else {
+                parent = (*successor).parent;
+                (*parent).left = child;
+                if !child.is_null() {
+                    (*child).parent = parent;
+                }
+                (*successor).right = (*node).right;
+                (*(*node).right).parent = successor;
+            }
+            change_child(root, (*node).parent, node, successor);
+            (*successor).parent = (*node).parent;
+            (*successor).left = (*node).left;
+            (*(*node).left).parent = successor;
+            (*successor).color = (*node).color;
+        }
+
+        if removed_color == Color::Black {
+            erase_fixup(root, child, parent);
+        }
+    }
+}
+
+unsafe fn erase_fixup(root: &mut RbRoot, mut node: *mut RbNode, mut parent: *mut RbNode) {
+    while node != root.node && is_black(node) {
+        if node == (*parent).left {
+            let mut sibling = (*parent).right;
+            if !is_black(sibling) {
+                set_color(sibling, Color::Black);
+                set_color(parent, Color::Red);
+                rotate_left(root, parent);
+                sibling = (*parent).right;
+            }
+            if is_black((*sibling).left) && is_black((*sibling).right) {
+                set_color(sibling, Color::Red);
+                node = parent;
+                parent = (*node).parent;
+            } else {
+                if is_black((*sibling).right) {
+                    set_color((*sibling).left, Color::Black);
+                    set_color(sibling, Color::Red);
+                    rotate_right(root, sibling);
+                    sibling = (*parent).right;
+                }
+                (*sibling).color = (*parent).color;
+                set_color(parent, Color::Black);
+                set_color((*sibling).right, Color::Black);
+                rotate_left(root, parent);
+                node = root.node;
+                break;
+            }
+        } else {
+            let mut sibling = (*parent).left;
+            if !is_black(sibling) {
+                set_color(sibling, Color::Black);
+                set_color(parent, Color::Red);
+                rotate_right(root, parent);
+                sibling = (*parent).left;
+            }
+            if is_black((*sibling).left) && is_black((*sibling).right) {
+                set_color(sibling, Color::Red);
+                node = parent;
+                parent = (*node).parent;
+            } else {
+                if is_black((*sibling).left) {
+                    set_color((*sibling).right, Color::Black);
+                    set_color(sibling, Color::Red);
+                    rotate_left(root, sibling);
+                    sibling = (*parent).left;
+                }
+                (*sibling).color = (*parent).color;
+                set_color(parent, Color::Black);
+                set_color((*sibling).left, Color::Black);
+                rotate_right(root, parent);
+                node = root.node;
+                break;
+            }
+        }
+    }
+    set_color(node, Color::Black);
+}
+
+#[inline]
+fn first_of(mut node: *mut RbNode) -> *mut RbNode {
+    unsafe {
+        while !(*node).left.is_null() {
+            node = (*node).left;
+        }
+    }
+    node
+}
+
+#[inline]
+fn last_of(mut node: *mut RbNode) -> *mut RbNode {
+    unsafe {
+        while !(*node).right.is_null() {
+            node = (*node).right;
+        }
+    }
+    node
+}
+
+/// Get the first (leftmost) node, null if the tree is empty.
+pub fn first(root: &RbRoot) -> *mut RbNode {
+    if root.node.is_null() { null_mut() } else { first_of(root.node) }
+}
+
+/// Get the last (rightmost) node, null if the tree is empty.
+pub fn last(root: &RbRoot) -> *mut RbNode {
+    if root.node.is_null() { null_mut() } else { last_of(root.node) }
+}
+
+/// Get the next node in order, null if `node` is the last one.
+pub fn next(mut node: *mut RbNode) -> *mut RbNode {
+    unsafe {
+        if !(*node).right.is_null() {
+            return first_of((*node).right);
+        }
+        let mut parent = (*node).parent;
+        while !parent.is_null() && node == (*parent).right {
+            node = parent;
+            parent = (*node).parent;
+        }
+        parent
+    }
+}
+
+/// Get the previous node in order, null if `node` is the first one.
+pub fn prev(mut node: *mut RbNode) -> *mut RbNode {
+    unsafe {
+        if !(*node).left.is_null() {
+            return last_of((*node).left);
+        }
+        let mut parent = (*node).parent;
+        while !parent.is_null() && node == (*parent).left {
+            node = parent;
+            parent = (*node).parent;
+        }
+        parent
+    }
+}
+
+/// Find a node in the tree. `cmp` returns the order of the searched key relative to the given
+/// node: `Less` to search the left subtree, `Greater` the right one, and `Equal` if found.
+pub fn find<F>(root: &RbRoot, mut cmp: F) -> *mut RbNode
+    where F: FnMut(*mut RbNode) -> Ordering {
+    let mut node = root.node;
+    while !node.is_null() {
+        node = match cmp(node) {
+            Ordering::Less => unsafe { (*node).left },
+            Ordering::Greater => unsafe { (*node).right },
+            Ordering::Equal => return node,
+        };
+    }
+
+    null_mut()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+    use super::*;
+
+    #[repr(C)]
+    struct Entry {
+        node: RbNode,
+        key: u32,
+    }
+
+    fn key_of(node: *mut RbNode) -> u32 {
+        unsafe { (*(node as *mut Entry)).key }
+    }
+
+    fn keys(root: &RbRoot) -> Vec<u32> {
+        let mut ret = Vec::new();
+        let mut node = first(root);
+        while !node.is_null() {
+            ret.push(key_of(node));
+            node = next(node);
+        }
+        ret
+    }
+
+    #[test]
+    fn insert_find_erase() {
+        let mut entries: Vec<Entry> = (0..16)
+            .map(|i| Entry { node: RbNode::new(), key: (i * 7) % 16 })
+            .collect();
+        let mut root = RbRoot::new();
+        for e in entries.iter_mut() {
+            let key = e.key;
+            insert(&mut root, &mut e.node, |n| key < key_of(n));
+        }
+        assert_eq!(keys(&root), (0..16).collect::<Vec<_>>());
+        assert_eq!(key_of(last(&root)), 15);
+        assert_eq!(key_of(prev(last(&root))), 14);
+
+        let found = find(&root, |n| 9.cmp(&key_of(n)));
+        assert_eq!(key_of(found), 9);
+        erase(&mut root, found);
+        assert!(find(&root, |n| 9.cmp(&key_of(n))).is_null());
+        assert_eq!(keys(&root).len(), 15);
+
+        for e in entries.iter_mut().filter(|e| e.key != 9) {
+            erase(&mut root, &mut e.node);
+        }
+        assert!(root.is_empty());
+        assert!(first(&root).is_null());
+    }
+}
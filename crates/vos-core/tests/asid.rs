@@ -0,0 +1,132 @@
+//! Tests of the ASID allocator: the harts switch between simulated address spaces until the
+//! ASIDs roll over, the running ones must stay valid and never be handed out again.
+
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use vos_core::mm::asid::{AsidCpu, AsidMap, Asids};
+
+/// 3 bits: ASID 0 is the kernel one, 7 are left for the address spaces.
+const BITS: usize = 3;
+const NR_CPUS: usize = 2;
+
+struct Harts {
+    asids: Asids,
+    map: Box<AsidMap>,
+    cpus: Vec<AsidCpu>,
+}
+
+impl Harts {
+    fn new(bits: usize) -> Self {
+        let mut harts = Self {
+            asids: Asids::new(),
+            map: Box::new(AsidMap::new()),
+            cpus: (0..NR_CPUS).map(|_| AsidCpu::new()).collect(),
+        };
+        harts.asids.init(bits, NR_CPUS, &mut harts.map);
+        harts
+    }
+
+    /// Switch the hart `cpu` to the `context` the way the kernel does: the fast path first.
+    fn switch(&mut self, cpu: usize, context: &AtomicUsize) -> (usize, bool) {
+        if let Some(asid) = self.asids.switch_fast(&self.cpus[cpu], context) {
+            return (asid, false);
+        }
+        self.asids.switch_locked(&mut self.map, &self.cpus, &self.cpus[cpu], context)
+    }
+
+    fn is_current(&self, context: &AtomicUsize) -> bool {
+        let context = context.load(Ordering::Relaxed);
+        context - self.asids.context_asid(context) == self.asids.generation()
+    }
+}
+
+fn contexts(count: usize) -> Vec<AtomicUsize> {
+    (0..count).map(|_| AtomicUsize::new(0)).collect()
+}
+
+#[test]
+fn allocates_unique_asids() {
+    let mut harts = Harts::new(BITS);
+    assert!(harts.asids.enabled());
+    let spaces = contexts(7);
+    let mut used = BTreeSet::new();
+    for space in &spaces {
+        let (asid, flush) = harts.switch(0, space);
+        assert!(!flush);
+        assert_ne!(asid, 0);
+        assert!(used.insert(asid), "ASID {} allocated twice", asid);
+    }
+
+    // A valid context keeps its ASID on any hart, by the fast path once the hart is active.
+    let asid = harts.asids.context_asid(spaces[3].load(Ordering::Relaxed));
+    assert_eq!(harts.switch(1, &spaces[3]), (asid, false));
+    assert_eq!(harts.asids.switch_fast(&harts.cpus[1], &spaces[3]), Some(asid));
+    assert_eq!(harts.asids.switch_fast(&harts.cpus[0], &AtomicUsize::new(0)), None);
+}
+
+#[test]
+fn rollover_keeps_running_asids() {
+    let mut harts = Harts::new(BITS);
+    let running = AtomicUsize::new(0);
+    let (running_asid, _) = harts.switch(1, &running);
+    let spaces = contexts(6);
+    for space in &spaces {
+        harts.switch(0, space);
+    }
+    let last = &spaces[5];
+    let last_asid = harts.asids.context_asid(last.load(Ordering::Relaxed));
+    let generation = harts.asids.generation();
+
+    // No ASID is left, the new address space starts a new generation.
+    let new = AtomicUsize::new(0);
+    let (new_asid, flush) = harts.switch(0, &new);
+    assert!(flush, "the TLB must be flushed after a rollover");
+    assert_ne!(harts.asids.generation(), generation);
+    assert!(new_asid != running_asid && new_asid != last_asid);
+
+    // The other hart is forced to the slow path, keeps its reserved ASID and flushes its TLB.
+    assert_eq!(harts.asids.switch_fast(&harts.cpus[1], &running), None);
+    assert_eq!(harts.switch(1, &running), (running_asid, true));
+    assert!(harts.is_current(&running));
+    assert_eq!(harts.switch(1, &running), (running_asid, false));
+
+    // The ASIDs reserved by the harts are not handed out in the new generation, the old
+    // address spaces get the other ones.
+    let mut used = BTreeSet::from([running_asid, last_asid, new_asid]);
+    for space in &spaces[..4] {
+        let (asid, flush) = harts.switch(0, space);
+        assert!(!flush);
+        assert!(harts.is_current(space));
+        assert!(used.insert(asid), "ASID {} reused in the generation", asid);
+    }
+    assert_eq!(used.len(), 7);
+    assert_eq!(harts.switch(0, last), (last_asid, false));
+    assert!(harts.is_current(last));
+}
+
+#[test]
+fn stale_context_keeps_free_asid() {
+    let mut harts = Harts::new(BITS);
+    let spaces = contexts(7);
+    for space in &spaces {
+        harts.switch(0, space);
+    }
+    let old = spaces[2].load(Ordering::Relaxed);
+    // The rollover reserves only the ASID of `spaces[6]` on the hart 0.
+    harts.switch(0, &AtomicUsize::new(0));
+    assert!(!harts.is_current(&spaces[2]));
+
+    // The old ASID is not used in the new generation yet, so it is kept.
+    let (asid, _) = harts.switch(1, &spaces[2]);
+    assert_eq!(asid, harts.asids.context_asid(old));
+    assert!(harts.is_current(&spaces[2]));
+}
+
+#[test]
+fn too_few_asids_disabled() {
+    // 4 ASIDs are not enough for 2 harts to reserve one each and still allocate.
+    let harts = Harts::new(2);
+    assert!(!harts.asids.enabled());
+    assert_eq!(harts.asids.context_asid(0x1234), 0);
+    assert!(!Harts::new(0).asids.enabled());
+}
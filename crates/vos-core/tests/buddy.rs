@@ -0,0 +1,140 @@
+//! Property tests of the buddy page allocator: random alloc/free sequences on a simulated memory
+//! region, the allocator invariants are checked after each operation.
+
+mod common;
+
+use std::collections::BTreeMap;
+use proptest::prelude::*;
+use vos_core::mm::PAGE_SIZE;
+use vos_core::mm::page::{Page, Zone, MAX_FREE_AREA_ORDER};
+use common::{op, Allocated, Op, TestMemory, MAX_BLOCK_SIZE, MAX_ORDER};
+
+/// The simulated memory and the zone. The zone is boxed so it is not moved after init.
+struct TestZone {
+    mem: TestMemory,
+    zone: Box<Zone>,
+    allocated: Allocated,
+}
+
+impl TestZone {
+    fn new(mem_size: usize, meta_offset: usize) -> Option<Self> {
+        let mem = TestMemory::new(mem_size, 1);
+        let start = mem.start;
+        let mut zone = Box::new(Zone::new());
+        if !unsafe { zone.init(0, start, mem_size, start + meta_offset) } {
+            return None;
+        }
+        Some(Self { mem, zone, allocated: Allocated::default() })
+    }
+
+    fn alloc(&mut self, order: usize) {
+        let free = self.zone.free_pages();
+        let page = self.zone.alloc(order);
+        if page.is_null() {
+            // Alloc only fails if no free block is large enough.
+            let has_block = (order..MAX_FREE_AREA_ORDER).any(|o| self.zone.free_blocks(o) > 0);
+            assert!(!has_block, "alloc of order {} failed with free blocks", order);
+            assert_eq!(self.zone.free_pages(), free);
+            return;
+        }
+
+        let addr = self.zone.page_to_address(page);
+        assert_eq!(self.zone.address_to_page(addr), page);
+        assert_eq!(self.zone.free_pages(), free - (1 << order));
+        self.allocated.insert(addr, order);
+    }
+
+    fn free(&mut self, select: usize) {
+        let Some(addr) = self.allocated.select(select) else {
+            return;
+        };
+        let order = self.allocated.remove(addr);
+        let free = self.zone.free_pages();
+        self.zone.free(self.zone.address_to_page(addr), order);
+        assert_eq!(self.zone.free_pages(), free + (1 << order));
+    }
+
+    fn check_invariants(&mut self) {
+        let zone = &mut self.zone;
+        let (alloc_start, alloc_end) = (zone.alloc_start(), zone.alloc_end());
+        let max_pages = zone.max_pages();
+
+        // Collect all blocks, free or allocated: start address -> (end address, is free).
+        let mut blocks = BTreeMap::new();
+        let mut free_pages = 0usize;
+        let mut free_blocks: Vec<(*mut Page, usize)> = Vec::new();
+        zone.for_each_free_block(|order, page| free_blocks.push((page, order)));
+        for (page, order) in free_blocks {
+            free_pages += 1 << order;
+            let addr = zone.page_to_address(page);
+            assert!(blocks.insert(addr, (addr + (PAGE_SIZE << order), true)).is_none());
+        }
+        for (addr, order) in self.allocated.iter() {
+            assert!(blocks.insert(addr, (addr + (PAGE_SIZE << order), false)).is_none());
+        }
+
+        // Free count is conserved.
+        let allocated_pages = self.allocated.pages();
+        assert_eq!(zone.free_pages(), free_pages);
+        assert_eq!(free_pages + allocated_pages, max_pages);
+
+        // Blocks are aligned to their size, in the region and never overlap.
+        let mut last_end = alloc_start;
+        for (&start, &(end, _)) in &blocks {
+            let size = end - start;
+            assert_eq!((start - alloc_start) % size, 0, "block {:#x} not aligned", start);
+            assert!(start >= last_end, "block {:#x} overlaps", start);
+            assert!(end <= alloc_end);
+            last_end = end;
+        }
+        // Blocks cover the whole region.
+        assert_eq!(last_end, alloc_end);
+        assert_eq!(blocks.values().map(|(end, _)| *end).sum::<usize>()
+                       - blocks.keys().sum::<usize>(), max_pages * PAGE_SIZE);
+    }
+
+    fn free_all(&mut self) {
+        while !self.allocated.is_empty() {
+            self.free(0);
+        }
+        assert_eq!(self.zone.free_pages(), self.zone.max_pages());
+        // All buddies are merged back.
+        assert_eq!(self.zone.free_blocks(MAX_ORDER), self.zone.max_pages() >> MAX_ORDER);
+        for order in 0..MAX_ORDER {
+            assert_eq!(self.zone.free_blocks(order), 0);
+        }
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn init_any_region(blocks in 1usize..12, extra in 0usize..MAX_BLOCK_SIZE, meta in 0usize..4096) {
+        let mem_size = blocks * MAX_BLOCK_SIZE + extra;
+        if let Some(mut tz) = TestZone::new(mem_size, meta) {
+            let start = tz.mem.start;
+            let zone = &tz.zone;
+            prop_assert!(zone.max_pages() > 0);
+            prop_assert!(zone.page_base() as usize >= start + meta);
+            prop_assert!(zone.alloc_start() >= zone.page_base() as usize);
+            prop_assert!(zone.alloc_end() <= start + mem_size);
+            prop_assert_eq!(zone.free_pages(), zone.max_pages());
+            tz.check_invariants();
+        }
+    }
+
+    #[test]
+    fn random_alloc_free(ops in prop::collection::vec(op(), 1..200)) {
+        let mut tz = TestZone::new(10 * MAX_BLOCK_SIZE, 0).unwrap();
+        for op in ops {
+            match op {
+                Op::Alloc(order, _) => tz.alloc(order),
+                Op::Free(select) => tz.free(select),
+            }
+            tz.check_invariants();
+        }
+        tz.free_all();
+        tz.check_invariants();
+    }
+}
@@ -0,0 +1,84 @@
+//! Fixtures shared by the page allocator tests: the simulated memory, the allocated blocks and
+//! the random alloc/free operations.
+
+// Each test crate uses a part of the fixtures.
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+use proptest::prelude::*;
+use vos_core::mm::PAGE_SIZE;
+use vos_core::mm::page::MAX_FREE_AREA_ORDER;
+
+pub const MAX_ORDER: usize = MAX_FREE_AREA_ORDER - 1;
+pub const MAX_BLOCK_SIZE: usize = PAGE_SIZE << MAX_ORDER;
+
+/// The simulated memory, from `start` aligned to the requested bytes.
+pub struct TestMemory {
+    _buf: Vec<u8>,
+    pub start: usize,
+}
+
+impl TestMemory {
+    pub fn new(size: usize, align: usize) -> Self {
+        let buf = vec![0u8; size + align - 1];
+        let start = (buf.as_ptr() as usize + align - 1) / align * align;
+        Self { _buf: buf, start }
+    }
+}
+
+/// Allocated blocks: address -> order.
+#[derive(Default)]
+pub struct Allocated(BTreeMap<usize, usize>);
+
+impl Allocated {
+    pub fn insert(&mut self, addr: usize, order: usize) {
+        assert!(self.0.insert(addr, order).is_none(), "block {:#x} allocated twice", addr);
+    }
+
+    /// Remove the block at `addr`, returns its order.
+    pub fn remove(&mut self, addr: usize) -> usize {
+        self.0.remove(&addr).unwrap()
+    }
+
+    /// Get the block selected by the index (modulo the allocated count).
+    pub fn select(&self, select: usize) -> Option<usize> {
+        if self.0.is_empty() {
+            return None;
+        }
+        self.0.keys().nth(select % self.0.len()).copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Count of the allocated pages.
+    pub fn pages(&self) -> usize {
+        self.0.values().map(|order| 1usize << order).sum()
+    }
+
+    /// Iterate the blocks as `(address, order)`.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.0.iter().map(|(&addr, &order)| (addr, order))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Op {
+    /// Allocate a block of the order, only from the DMA32 zones if the flag is set.
+    Alloc(usize, bool),
+    /// Free the allocated block selected by the index (modulo the allocated count).
+    Free(usize),
+}
+
+pub fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        3 => (0..MAX_FREE_AREA_ORDER, any::<bool>())
+            .prop_map(|(order, dma32)| Op::Alloc(order, dma32)),
+        2 => any::<usize>().prop_map(Op::Free),
+    ]
+}
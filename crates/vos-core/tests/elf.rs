@@ -0,0 +1,27 @@
+//! Property tests of the ELF parser: malformed input is rejected without panic, and a validated
+//! segment never reads out of the file.
+
+use proptest::prelude::*;
+use vos_core::elf::{FileHeader, ProgramHeader, EHDR_SIZE, PHDR_SIZE};
+
+proptest! {
+    #[test]
+    fn parse_arbitrary_bytes(data in proptest::collection::vec(any::<u8>(), 0..2 * EHDR_SIZE)) {
+        if let Ok(header) = FileHeader::parse(&data) {
+            prop_assert!(header.e_phnum > 0);
+            prop_assert!(header.program_headers_offset().is_some());
+        }
+        let _ = ProgramHeader::parse(&data);
+    }
+
+    #[test]
+    fn validated_segment_in_file(data in proptest::collection::vec(any::<u8>(), PHDR_SIZE),
+                                 file_size in any::<u64>()) {
+        let ph = ProgramHeader::parse(&data).unwrap();
+        if ph.validate_load(file_size).is_ok() {
+            prop_assert!(ph.p_offset + ph.p_filesz <= file_size);
+            prop_assert!(ph.p_filesz <= ph.p_memsz);
+            prop_assert!(ph.p_vaddr.checked_add(ph.p_memsz).is_some());
+        }
+    }
+}
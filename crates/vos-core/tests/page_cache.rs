@@ -0,0 +1,108 @@
+//! Tests of the page cache algorithms: the read-ahead window over the simulated misses, and the
+//! dirty sector masks of the written bytes.
+
+use vos_core::mm::page_cache::{
+    clean_blocks, sector_mask, ReadAhead, READ_AHEAD_MAX, READ_AHEAD_MIN, SECTORS_PER_PAGE,
+};
+use vos_core::mm::PAGE_SIZE;
+
+const NR_PAGES: u64 = 1000;
+
+/// Miss the page `index` and read its window, the way the cache does. Returns the window.
+fn miss(ra: &mut ReadAhead, index: u64, nr_pages: u64) -> (u64, u64) {
+    let window = ra.window(index, nr_pages);
+    ra.update(window.start, window.end - window.start);
+    (window.start, window.end)
+}
+
+#[test]
+fn sequential_window_grows() {
+    let mut ra = ReadAhead::new();
+    let mut index = 0;
+    let mut sizes = Vec::new();
+    while index < 200 {
+        let (start, end) = miss(&mut ra, index, NR_PAGES);
+        assert_eq!(start, index);
+        sizes.push(end - start);
+        index = end;
+    }
+    assert_eq!(sizes[..5], [READ_AHEAD_MIN, 8, 16, READ_AHEAD_MAX, READ_AHEAD_MAX]);
+    assert!(sizes.iter().all(|&size| size <= READ_AHEAD_MAX));
+}
+
+#[test]
+fn random_miss_resets_window() {
+    let mut ra = ReadAhead::new();
+    miss(&mut ra, 0, NR_PAGES);
+    miss(&mut ra, 4, NR_PAGES);
+    assert_eq!(miss(&mut ra, 12, NR_PAGES), (12, 28));
+
+    // A miss elsewhere, even inside the last window, starts over.
+    assert_eq!(miss(&mut ra, 100, NR_PAGES), (100, 100 + READ_AHEAD_MIN));
+    assert_eq!(miss(&mut ra, 101, NR_PAGES), (101, 101 + READ_AHEAD_MIN));
+    assert_eq!(miss(&mut ra, 105, NR_PAGES), (105, 113));
+}
+
+#[test]
+fn window_clamped_to_end() {
+    let mut ra = ReadAhead::new();
+    assert_eq!(miss(&mut ra, 0, 3), (0, 3));
+    // The missed page past the end is still read, e.g. for a write at the end.
+    assert_eq!(ra.window(3, 3), 3..4);
+    assert_eq!(ra.window(10, 0), 10..11);
+
+    // A short read does not grow the window beyond what was read.
+    let mut ra = ReadAhead::new();
+    assert_eq!(miss(&mut ra, 0, 6), (0, 4));
+    assert_eq!(miss(&mut ra, 4, 6), (4, 6));
+    assert_eq!(ra.window(6, NR_PAGES), 6..10);
+}
+
+#[test]
+fn truncate_drops_window() {
+    let mut ra = ReadAhead::new();
+    miss(&mut ra, 0, NR_PAGES);
+    miss(&mut ra, 4, NR_PAGES);
+    // The truncation after the window keeps it.
+    ra.truncate(12);
+    assert_eq!(ra.window(12, NR_PAGES), 12..28);
+    ra.truncate(11);
+    assert_eq!(ra, ReadAhead::new());
+    assert_eq!(ra.window(12, NR_PAGES), 12..12 + READ_AHEAD_MIN);
+}
+
+#[test]
+fn sector_masks() {
+    let full = (1u32 << SECTORS_PER_PAGE) - 1;
+    assert_eq!(sector_mask(0..0), 0);
+    assert_eq!(sector_mask(100..100), 0);
+    assert_eq!(sector_mask(0..1), 0b1);
+    assert_eq!(sector_mask(0..512), 0b1);
+    assert_eq!(sector_mask(511..513), 0b11);
+    assert_eq!(sector_mask(512..1024), 0b10);
+    assert_eq!(sector_mask(1000..2100), 0b11110);
+    assert_eq!(sector_mask(0..PAGE_SIZE), full);
+    assert_eq!(sector_mask(PAGE_SIZE - 1..PAGE_SIZE), 1 << (SECTORS_PER_PAGE - 1));
+}
+
+#[test]
+fn clean_blocks_keeps_dirty() {
+    // 1 KiB blocks: the block 1 has the dirty sector 3, the block 3 the sector 6.
+    let mut blocks: Vec<Option<u64>> = (0..4).map(|i| Some(100 + i * 2)).collect();
+    clean_blocks(0b0100_1000, 1024, &mut blocks);
+    assert_eq!(blocks, [None, Some(102), None, Some(106)]);
+
+    // A block of the sector size is a sector, holes are kept.
+    let mut blocks: Vec<Option<u64>> = (0..SECTORS_PER_PAGE as u64).map(Some).collect();
+    blocks[1] = None;
+    clean_blocks(0b11, 512, &mut blocks);
+    assert_eq!(blocks[..3], [Some(0), None, None]);
+    assert!(blocks[2..].iter().all(Option::is_none));
+
+    let mut blocks = [Some(7)];
+    clean_blocks(0, PAGE_SIZE, &mut blocks);
+    assert_eq!(blocks, [None]);
+    let mut blocks = [Some(7)];
+    clean_blocks(1 << (SECTORS_PER_PAGE - 1), PAGE_SIZE, &mut blocks);
+    assert_eq!(blocks, [Some(7)]);
+}
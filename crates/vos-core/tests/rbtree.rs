@@ -0,0 +1,79 @@
+//! Property tests of the intrusive red-black tree: random inserts and erases keep the tree
+//! ordered and balanced, compared with the `BTreeSet`.
+
+use std::collections::BTreeSet;
+use proptest::prelude::*;
+use vos_core::util::rbtree::{self, RbNode, RbRoot};
+
+#[repr(C)]
+struct Entry {
+    node: RbNode,
+    key: u16,
+}
+
+fn key_of(node: *mut RbNode) -> u16 {
+    unsafe { (*(node as *mut Entry)).key }
+}
+
+/// Check the red-black properties of the subtree, returns the black height.
+fn check_subtree(node: *mut RbNode, parent: *mut RbNode) -> usize {
+    if node.is_null() {
+        return 1;
+    }
+    let n = unsafe { &*node };
+    assert_eq!(n.parent(), parent);
+    if !n.left().is_null() {
+        assert!(key_of(n.left()) < key_of(node));
+    }
+    if !n.right().is_null() {
+        assert!(key_of(n.right()) > key_of(node));
+    }
+    if n.is_red() {
+        for child in [n.left(), n.right()] {
+            assert!(child.is_null() || unsafe { !(*child).is_red() }, "red node has a red child");
+        }
+    }
+
+    let left = check_subtree(n.left(), node);
+    let right = check_subtree(n.right(), node);
+    assert_eq!(left, right, "black height differs");
+    left + if n.is_red() { 0 } else { 1 }
+}
+
+fn collect(root: &RbRoot) -> Vec<u16> {
+    let mut ret = Vec::new();
+    let mut node = rbtree::first(root);
+    while !node.is_null() {
+        ret.push(key_of(node));
+        node = rbtree::next(node);
+    }
+    ret
+}
+
+proptest! {
+    #[test]
+    fn random_insert_erase(ops in proptest::collection::vec((any::<bool>(), 0u16..256), 1..400)) {
+        let mut entries: Vec<Box<Entry>> = Vec::new();
+        let mut root = RbRoot::new();
+        let mut model = BTreeSet::new();
+
+        for (is_insert, key) in ops {
+            let found = rbtree::find(&root, |n| key.cmp(&key_of(n)));
+            prop_assert_eq!(!found.is_null(), model.contains(&key));
+            if is_insert && found.is_null() {
+                let mut entry = Box::new(Entry { node: RbNode::new(), key });
+                rbtree::insert(&mut root, &mut entry.node, |n| key < key_of(n));
+                entries.push(entry);
+                model.insert(key);
+            } else if !is_insert && !found.is_null() {
+                rbtree::erase(&mut root, found);
+                model.remove(&key);
+            }
+
+            let root_is_red = !root.is_empty() && unsafe { (*root.node).is_red() };
+            prop_assert!(!root_is_red);
+            check_subtree(root.node, std::ptr::null_mut());
+            prop_assert_eq!(collect(&root), model.iter().copied().collect::<Vec<_>>());
+        }
+    }
+}
@@ -0,0 +1,56 @@
+//! Property tests of the slab size calculation.
+
+use proptest::prelude::*;
+use vos_core::mm::PAGE_SIZE;
+use vos_core::mm::page::PAGE_ALLOC_MAX_ORDER;
+use vos_core::mm::slab::{calc_order, calc_slab_order, get_order, order_objects, MAX_OBJS_PER_PAGE,
+                         SLUB_MAX_ORDER};
+
+/// Max object size in words: a single object of the max order.
+const MAX_WORDS: u32 = ((PAGE_SIZE << (PAGE_ALLOC_MAX_ORDER - 1)) / 8) as u32;
+
+proptest! {
+    #[test]
+    fn get_order_covers_size(size in 1usize..(PAGE_SIZE << PAGE_ALLOC_MAX_ORDER)) {
+        let order = get_order(size);
+        prop_assert!(PAGE_SIZE << order >= size);
+        if order > 0 {
+            prop_assert!(PAGE_SIZE << (order - 1) < size);
+        }
+    }
+
+    #[test]
+    fn layout_fits_slab(words in 1u32..=MAX_WORDS, nr_cpus in 1usize..64) {
+        // The object size is always rounded to the word boundary.
+        let size = words * 8;
+        let order = calc_order(size, nr_cpus);
+        prop_assert!(order.is_some());
+        let order = order.unwrap();
+        prop_assert!(order < PAGE_ALLOC_MAX_ORDER);
+
+        let objects = order_objects(order, size);
+        prop_assert!(objects >= 1);
+        prop_assert!(objects <= MAX_OBJS_PER_PAGE);
+
+        let slab_size = PAGE_SIZE << order;
+        let (objects, size) = (objects as usize, size as usize);
+        prop_assert!(objects * size <= slab_size);
+        // Leftover is less than one object.
+        prop_assert!(slab_size - objects * size < size);
+
+        // Objects that fit in the costly order never use a larger order.
+        if size <= PAGE_SIZE << SLUB_MAX_ORDER {
+            prop_assert!(order <= SLUB_MAX_ORDER);
+        }
+    }
+
+    #[test]
+    fn slab_order_leftover(size in 8u32..8192, min_objects in 1u32..16, fraction in 1u32..17) {
+        let order = calc_slab_order(size, min_objects, SLUB_MAX_ORDER, fraction);
+        if order <= SLUB_MAX_ORDER {
+            let slab_size = PAGE_SIZE << order;
+            prop_assert!(slab_size % size as usize <= slab_size / fraction as usize);
+            prop_assert!(order_objects(order, size) >= min_objects);
+        }
+    }
+}
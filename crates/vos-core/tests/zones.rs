@@ -0,0 +1,244 @@
+//! Tests of the zone list of the page allocator: the zones are built from synthetic memory
+//! regions in a buffer, with the DMA32 limit placed inside the buffer.
+
+mod common;
+
+use proptest::prelude::*;
+use vos_core::mm::PAGE_SIZE;
+use vos_core::mm::page::{MAX_FREE_AREA_ORDER, MAX_NR_ZONES};
+use vos_core::mm::zones::{gfp, GfpAllocFlag, ZoneError, ZoneType, Zones};
+use common::{op, Allocated, Op, TestMemory, MAX_BLOCK_SIZE, MAX_ORDER};
+
+/// A region of this size holds at least one max block after the meta data.
+const REGION_SIZE: usize = 3 * MAX_BLOCK_SIZE;
+
+/// The simulated memory and the zones. The zones are boxed so they are not moved after init.
+struct TestZones {
+    _mem: TestMemory,
+    /// First address of the memory, aligned to the max block size.
+    base: usize,
+    zones: Box<Zones>,
+    allocated: Allocated,
+}
+
+impl TestZones {
+    /// Create the memory of `size` bytes, the memory from `limit` bytes is above the DMA32 limit.
+    fn new(size: usize, limit: usize) -> Self {
+        let mem = TestMemory::new(size, MAX_BLOCK_SIZE);
+        let base = mem.start;
+        let zones = Box::new(Zones::new(base + limit));
+        Self { _mem: mem, base, zones, allocated: Allocated::default() }
+    }
+
+    /// Add the zones of the region `[start, end)` of the memory, the way the kernel does.
+    fn add_region(&mut self, start: usize, end: usize) -> Vec<Result<ZoneType, ZoneError>> {
+        let split: Vec<_> = self.zones.split_region(self.base + start, self.base + end).collect();
+        split.into_iter()
+            .map(|(ty, start, end)| unsafe { self.zones.add_zone(ty, start, end) }.map(|_| ty))
+            .collect()
+    }
+
+    fn limit(&self) -> usize {
+        self.zones.zones().iter()
+            .filter(|zone| self.zones.zone_type(zone.idx()) == ZoneType::Dma32)
+            .map(|zone| zone.alloc_end())
+            .max()
+            .unwrap_or(self.base)
+    }
+
+    /// Allocate a block, returns its address or `None`.
+    fn alloc(&mut self, flags: GfpAllocFlag, order: usize) -> Option<usize> {
+        let page = self.zones.alloc(flags, order);
+        if page.is_null() {
+            return None;
+        }
+        let addr = unsafe { self.zones.page_to_address(page) };
+        assert_eq!(self.zones.address_to_page(addr), page);
+        self.allocated.insert(addr, order);
+        Some(addr)
+    }
+
+    fn free(&mut self, addr: usize) {
+        let order = self.allocated.remove(addr);
+        unsafe { self.zones.free(self.zones.address_to_page(addr), order) };
+    }
+
+    fn free_all(&mut self) {
+        let blocks: Vec<usize> = self.allocated.iter().map(|(addr, _)| addr).collect();
+        for addr in blocks {
+            self.free(addr);
+        }
+        assert_eq!(self.zones.nr_free_pages(), self.zones.nr_total_pages());
+    }
+
+    /// Check if a zone of the type `ty` has a free block of at least `order`.
+    fn has_block(&self, ty: ZoneType, order: usize) -> bool {
+        self.zones.zones().iter()
+            .filter(|zone| self.zones.zone_type(zone.idx()) == ty)
+            .any(|zone| (order..MAX_FREE_AREA_ORDER).any(|o| zone.free_blocks(o) > 0))
+    }
+}
+
+#[test]
+fn zones_from_regions() {
+    // Two regions with a hole between them, all below the limit.
+    let mut tz = TestZones::new(8 * MAX_BLOCK_SIZE, 16 * MAX_BLOCK_SIZE);
+    assert_eq!(tz.add_region(0, REGION_SIZE), [Ok(ZoneType::Dma32)]);
+    assert_eq!(tz.add_region(5 * MAX_BLOCK_SIZE, 8 * MAX_BLOCK_SIZE), [Ok(ZoneType::Dma32)]);
+    let zones = tz.zones.zones();
+    assert_eq!(zones.len(), 2);
+    for (idx, zone) in zones.iter().enumerate() {
+        assert_eq!(zone.idx(), idx);
+        assert!(zone.max_pages() > 0);
+        assert!(zone.alloc_start() >= zone.mem_start());
+        assert!(zone.alloc_end() <= zone.mem_start() + zone.mem_size());
+    }
+    let total = tz.zones.nr_total_pages();
+    assert_eq!(total, zones.iter().map(|zone| zone.max_pages()).sum::<usize>());
+    assert_eq!(tz.zones.nr_free_pages(), total);
+
+    // The pages of the hole are never managed.
+    let hole = tz.base + 4 * MAX_BLOCK_SIZE;
+    assert!(tz.zones.address_to_page(hole).is_null());
+
+    // Every page is allocated once, from the zone which manages it.
+    while let Some(addr) = tz.alloc(0, 0) {
+        let page = tz.zones.address_to_page(addr);
+        let zone = &tz.zones.zones()[unsafe { (*page).get_zone_idx() }];
+        assert!(addr >= zone.alloc_start() && addr < zone.alloc_end());
+    }
+    assert_eq!(tz.allocated.len(), total);
+    assert_eq!(tz.zones.nr_free_pages(), 0);
+    tz.free_all();
+}
+
+#[test]
+fn region_crossing_limit_split() {
+    let limit = 4 * MAX_BLOCK_SIZE;
+    let mut tz = TestZones::new(16 * MAX_BLOCK_SIZE, limit);
+    let base = tz.base;
+    let split = |start: usize, end: usize| -> Vec<(ZoneType, usize, usize)> {
+        tz.zones.split_region(base + start, base + end)
+            .map(|(ty, start, end)| (ty, start - base, end - base))
+            .collect()
+    };
+    assert_eq!(split(0, limit), [(ZoneType::Dma32, 0, limit)]);
+    assert_eq!(split(limit, 2 * limit), [(ZoneType::Normal, limit, 2 * limit)]);
+    assert_eq!(split(MAX_BLOCK_SIZE, 2 * limit),
+               [(ZoneType::Dma32, MAX_BLOCK_SIZE, limit), (ZoneType::Normal, limit, 2 * limit)]);
+
+    assert_eq!(tz.add_region(0, 2 * limit), [Ok(ZoneType::Dma32), Ok(ZoneType::Normal)]);
+    let zones = tz.zones.zones();
+    assert_eq!(zones.len(), 2);
+    assert_eq!((zones[0].mem_start(), zones[0].mem_size()), (base, limit));
+    assert!(zones[0].alloc_end() <= base + limit);
+    assert_eq!((zones[1].mem_start(), zones[1].mem_size()), (base + limit, limit));
+    assert_eq!(tz.zones.zone_type(1), ZoneType::Normal);
+
+    // A split half too small for a max block is dropped, the other half is kept.
+    let mut tz = TestZones::new(16 * MAX_BLOCK_SIZE, limit);
+    assert_eq!(tz.add_region(limit - PAGE_SIZE, 2 * limit),
+               [Err(ZoneError::TooSmall), Ok(ZoneType::Normal)]);
+    assert_eq!(tz.zones.zones().len(), 1);
+    assert_eq!(tz.zones.zones()[0].idx(), 0);
+}
+
+#[test]
+fn too_many_zones() {
+    let mut tz = TestZones::new((MAX_NR_ZONES + 1) * REGION_SIZE, 0);
+    for i in 0..MAX_NR_ZONES {
+        // A too small region does not take a zone.
+        if i == 3 {
+            let start = i * REGION_SIZE;
+            assert_eq!(tz.add_region(start, start + PAGE_SIZE), [Err(ZoneError::TooSmall)]);
+        }
+        assert_eq!(tz.add_region(i * REGION_SIZE, (i + 1) * REGION_SIZE), [Ok(ZoneType::Normal)]);
+    }
+    let last = MAX_NR_ZONES * REGION_SIZE;
+    assert_eq!(tz.add_region(last, last + REGION_SIZE), [Err(ZoneError::TooMany)]);
+    assert_eq!(tz.zones.zones().len(), MAX_NR_ZONES);
+    assert!(tz.zones.address_to_page(tz.base + last + MAX_BLOCK_SIZE).is_null());
+
+    // The zone idx kept in each page returns it to its zone, the last idx included.
+    let mut pages = Vec::new();
+    while let Some(addr) = tz.alloc(0, MAX_ORDER) {
+        pages.push(addr);
+    }
+    let page = tz.zones.address_to_page(*pages.last().unwrap());
+    assert_eq!(unsafe { (*page).get_zone_idx() }, MAX_NR_ZONES - 1);
+    assert_eq!(pages.len() << MAX_ORDER, tz.zones.nr_total_pages());
+    tz.free_all();
+}
+
+#[test]
+fn dma32_never_falls_back() {
+    let limit = 4 * MAX_BLOCK_SIZE;
+    let mut tz = TestZones::new(8 * MAX_BLOCK_SIZE, limit);
+    tz.add_region(0, 8 * MAX_BLOCK_SIZE);
+    let limit = tz.base + limit;
+    let dma32_pages = tz.zones.zones()[0].max_pages();
+
+    // The normal allocations prefer the Normal zone.
+    let normal = tz.alloc(0, 0).unwrap();
+    assert!(normal >= limit);
+    tz.free(normal);
+
+    // The DMA32 allocations take all DMA32 pages, then fail with the Normal pages free.
+    for flags in [gfp::GFP_DMA32, gfp::GFP_DMA] {
+        let mut count = 0;
+        while let Some(addr) = tz.alloc(flags, 0) {
+            assert!(addr + PAGE_SIZE <= limit, "{:#x} above the DMA32 limit", addr);
+            count += 1;
+        }
+        assert_eq!(count, dma32_pages);
+        assert_eq!(tz.zones.nr_free_pages(), tz.zones.nr_total_pages() - dma32_pages);
+        tz.free_all();
+    }
+
+    // The normal allocations fall back to the DMA32 zone when the Normal zone is full.
+    let addrs: Vec<usize> = std::iter::from_fn(|| tz.alloc(gfp::GFP_KERNEL, MAX_ORDER)).collect();
+    let normal_blocks = tz.zones.zones()[1].max_pages() >> MAX_ORDER;
+    assert!(addrs[..normal_blocks].iter().all(|&addr| addr >= limit));
+    assert!(addrs[normal_blocks..].iter().all(|&addr| addr < limit));
+    assert!(addrs.len() > normal_blocks);
+    tz.free_all();
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn random_zone_alloc(limit in 1usize..8, ops in prop::collection::vec(op(), 1..200)) {
+        let mut tz = TestZones::new(9 * MAX_BLOCK_SIZE, limit * MAX_BLOCK_SIZE);
+        // Two regions around a hole, the limit may split either.
+        tz.add_region(0, 4 * MAX_BLOCK_SIZE);
+        tz.add_region(5 * MAX_BLOCK_SIZE, 9 * MAX_BLOCK_SIZE);
+        let limit = tz.limit();
+        for op in ops {
+            match op {
+                Op::Alloc(order, dma32) => {
+                    let flags: GfpAllocFlag = if dma32 { gfp::GFP_DMA32 } else { 0 };
+                    match tz.alloc(flags, order) {
+                        Some(addr) if dma32 => {
+                            prop_assert!(addr + (PAGE_SIZE << order) <= limit);
+                        }
+                        Some(_) => {}
+                        // It only fails if no allowed zone has a block large enough.
+                        None => {
+                            prop_assert!(!tz.has_block(ZoneType::Dma32, order));
+                            prop_assert!(dma32 || !tz.has_block(ZoneType::Normal, order));
+                        }
+                    }
+                }
+                Op::Free(select) => {
+                    if let Some(addr) = tz.allocated.select(select) {
+                        tz.free(addr);
+                    }
+                }
+            }
+            let allocated = tz.allocated.pages();
+            prop_assert_eq!(tz.zones.nr_free_pages() + allocated, tz.zones.nr_total_pages());
+        }
+        tz.free_all();
+    }
+}
@@ -98,6 +98,13 @@ pub const fn freg(r: FRegister) -> usize {
 /// supervisor mode if `SPP` bit is 1, or user mode if `SPP` bit is 0.
 pub const SSTATUS_SPP_BIT: usize = 1usize << 8;
 
+/// `FS` field of the `sstatus` register with the `Dirty` status. The trap handler reloads the
+/// floating registers and the `fcsr` from the trap frame if the status is `Dirty` on the return.
+pub const SSTATUS_FS_DIRTY: usize = 3usize << 13;
+
+/// `SSIP` bit in `sip` register. Supervisor software interrupt pending.
+pub const SIP_SSIP_BIT: usize = 1usize << 1;
+
 ////////////////////// Registers R/W //////////////////////
 
 /// Read the `tp` register value.
@@ -243,6 +250,14 @@ pub fn sie_write(val: usize) {
     }
 }
 
+/// Clear the bits of the `sip` register if the corresponding bits in `clear_bits` is 1.
+#[inline(always)]
+pub fn sip_clear_bits(clear_bits: usize) {
+    unsafe {
+        asm!("csrrc x0, sip, {}", in(reg) clear_bits, options(nomem, nostack));
+    }
+}
+
 #[inline(always)]
 pub fn sscratch_read() -> usize {
     unsafe {
@@ -310,6 +325,14 @@ pub fn satp_fense(vaddr: usize, asid: usize) {
     }
 }
 
+/// Flush all TLB entries of all address spaces on the current hart.
+#[inline(always)]
+pub fn sfence_vma_all() {
+    unsafe {
+        asm!("sfence.vma zero, zero", options(nostack));
+    }
+}
+
 /// Synchronize based on the address space identifier This allows us to
 /// fence a particular process rather than the entire TLB.
 #[inline(always)]
@@ -372,6 +395,14 @@ pub fn is_irq_disabled() -> bool {
 
 //////////////////// Other Instructions ///////////////////
 
+/// Wait for interrupt. The hart may be stalled until an interrupt is pending.
+#[inline(always)]
+pub fn wait_for_interrupt() {
+    unsafe {
+        asm!("wfi", options(nomem, nostack));
+    }
+}
+
 /// Generate a `fence` instruction. If no order option is set, make a full fence on device "io"
 /// and memory "rw".
 #[macro_export]
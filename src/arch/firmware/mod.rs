@@ -0,0 +1,9 @@
+//! Calls into the machine mode firmware.
+//!
+//! The kernel runs its own M-mode code instead of a standard SBI implementation (QEMU is launched
+//! with `-bios none`), so the interfaces here are served by the M-mode trap handler in
+//! `asm/m_trap.S`. The function ids are passed in `a7` like the SBI calling convention.
+
+mod riscv;
+
+pub use riscv::*;
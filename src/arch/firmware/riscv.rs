@@ -0,0 +1,77 @@
+//! Firmware calls implemented by the `ecall` instruction from S-mode.
+
+use core::arch::asm;
+
+
+/// Function id of the M-mode `ecall` handler. See `asm/m_trap.S`.
+#[repr(usize)]
+#[derive(Copy, Clone)]
+enum FwFunction {
+    ReadHartId = 0,
+    SetTimer = 1,
+    SendIpi = 2,
+    SystemReset = 3,
+}
+
+/// Reset type of [`system_reset`].
+///
+/// [`system_reset`]: system_reset
+#[repr(usize)]
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum ResetType {
+    Shutdown = 0,
+    ColdReboot = 1,
+    WarmReboot = 2,
+}
+
+/// Reset reason of [`system_reset`].
+///
+/// [`system_reset`]: system_reset
+#[repr(usize)]
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum ResetReason {
+    NoReason = 0,
+    SystemFailure = 1,
+}
+
+#[inline(always)]
+fn fw_call(func: FwFunction, arg0: usize, arg1: usize) -> usize {
+    unsafe {
+        let ret;
+        asm!(
+            "ecall",
+            inlateout("a0") arg0 => ret,
+            in("a1") arg1,
+            in("a7") func as usize,
+            out("t0") _, out("t1") _, out("t2") _, out("t3") _,
+            out("t4") _, out("t5") _, out("t6") _,
+            options(nostack)
+        );
+        ret
+    }
+}
+
+/// Read the `mhartid` of current hart.
+#[inline]
+pub fn read_hart_id() -> usize {
+    fw_call(FwFunction::ReadHartId, 0, 0)
+}
+
+/// Set the next timer event after `delta` ticks.
+#[inline]
+pub fn set_timer(delta: usize) {
+    fw_call(FwFunction::SetTimer, delta, 0);
+}
+
+/// Raise a supervisor software interrupt on the harts in `hart_mask`. Bit `N` of `hart_mask`
+/// refers to the hart with a hart id of `N`.
+#[inline]
+pub fn send_ipi(hart_mask: usize) {
+    fw_call(FwFunction::SendIpi, hart_mask, 0);
+}
+
+/// Reset the system. This function returns only if the reset request is failed.
+#[inline]
+pub fn system_reset(ty: ResetType, reason: ResetReason) {
+    fw_call(FwFunction::SystemReset, ty as usize, reason as usize);
+}
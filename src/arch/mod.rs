@@ -3,3 +3,4 @@
 pub(crate) mod atomic;
 pub(crate) mod barrier;
 pub(crate) mod cpu;
+pub(crate) mod firmware;
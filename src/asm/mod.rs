@@ -3,6 +3,7 @@ mod m_trap_asm;
 mod trap_asm;
 mod mem_asm;
 mod sched_asm;
+mod user_init_asm;
 
 pub(crate) mod mem_v {
     extern "C" {
@@ -16,6 +17,8 @@ pub(crate) mod mem_v {
         pub static RODATA_END: usize;
         pub static BSS_START: usize;
         pub static BSS_END: usize;
+        pub static KSYMS_START: usize;
+        pub static KSYMS_END: usize;
         pub static KERNEL_STACK_START: usize;
         pub static KERNEL_STACK_END: usize;
         pub static mut KERNEL_TABLE: usize;
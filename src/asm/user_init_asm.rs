@@ -0,0 +1 @@
+core::arch::global_asm!(include_str!("user_init.S"));
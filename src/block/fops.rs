@@ -0,0 +1,105 @@
+//! Block device files.
+//!
+//! A block device file is opened on the [`BlockDev`] of its device number. The reads and the
+//! writes go through the page cache of the block device, which the mounted file systems read
+//! their metadata through too, so they see the same data. `fsync` writes back the cache and
+//! flushes the disk.
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::errno::{E_ACCESS, E_BAD_FD, E_NO_DEV_OR_ADDR, E_NO_SPACE, E_NOT_TTY};
+use crate::fs::file::{readable, seek_pos, writable, File, SEEK_END};
+use crate::fs::inode::{Stat, S_IFBLK};
+use crate::mm::address_space::AddressSpace;
+use crate::mm::uaccess::write_user;
+use crate::mm::page_cache::PageIo;
+use crate::mm::PAGE_SIZE;
+use super::{get_bdev_by_dev, BlockDev, SECTOR_SIZE};
+
+
+// Block device `ioctl` requests.
+const BLKROGET: u32 = 0x125e;
+const BLKGETSIZE: u32 = 0x1260;
+const BLKSSZGET: u32 = 0x1268;
+const BLKGETSIZE64: u32 = 0x80081272;
+
+/// An open block device.
+struct BlockDevFile {
+    bdev: Arc<BlockDev>,
+    flags: usize,
+    pos: AtomicUsize,
+}
+
+/// Open the block device of the device number `dev` with the open `flags`.
+///
+/// Returns `Err` with `E_NO_DEV_OR_ADDR` if there is no such device, or `E_ACCESS` if it is
+/// opened for writing but read-only.
+pub fn blkdev_open(dev: u64, flags: usize) -> Result<Arc<dyn File>, i32> {
+    let bdev = get_bdev_by_dev(dev).ok_or(E_NO_DEV_OR_ADDR)?;
+    if writable(flags) && bdev.read_only() {
+        return Err(E_ACCESS);
+    }
+    Ok(Arc::new(BlockDevFile { bdev, flags, pos: AtomicUsize::new(0) }))
+}
+
+impl File for BlockDevFile {
+    fn flags(&self) -> usize {
+        self.flags
+    }
+
+    fn stat(&self) -> Result<Stat, i32> {
+        Ok(Stat {
+            mode: S_IFBLK | 0o660,
+            nlink: 1,
+            rdev: self.bdev.dev(),
+            blksize: PAGE_SIZE as u32,
+            ..Stat::default()
+        })
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize, i32> {
+        if !readable(self.flags) {
+            return Err(E_BAD_FD);
+        }
+        let pos = self.pos.load(Ordering::Acquire);
+        let len = self.bdev.cache().read(pos as u64, buf)?;
+        self.pos.store(pos + len, Ordering::Release);
+        Ok(len)
+    }
+
+    /// Write at the file offset, a write at the end of the device fails with `E_NO_SPACE`.
+    fn write(&self, buf: &[u8]) -> Result<usize, i32> {
+        if !writable(self.flags) {
+            return Err(E_BAD_FD);
+        }
+        let pos = self.pos.load(Ordering::Acquire);
+        let len = self.bdev.cache().write(pos as u64, buf)?;
+        if len == 0 && !buf.is_empty() {
+            return Err(E_NO_SPACE);
+        }
+        self.pos.store(pos + len, Ordering::Release);
+        Ok(len)
+    }
+
+    /// Set the file offset, `SEEK_END` is relative to the size of the device.
+    fn seek(&self, offset: isize, whence: usize) -> Result<usize, i32> {
+        let size = if whence == SEEK_END { self.bdev.size() as usize } else { 0 };
+        seek_pos(&self.pos, offset, whence, size)
+    }
+
+    fn sync(&self, _data_only: bool) -> Result<(), i32> {
+        self.bdev.cache().sync()
+    }
+
+    /// Get the size, the logical sector size or the read-only flag of the device.
+    fn ioctl(&self, cmd: u32, arg: usize, aspace: &mut AddressSpace) -> Result<usize, i32> {
+        let bdev = &self.bdev;
+        match cmd {
+            BLKROGET => write_user(aspace, arg, &(bdev.read_only() as i32)),
+            BLKGETSIZE => write_user(aspace, arg, &(bdev.nr_sectors() as usize)),
+            BLKSSZGET => write_user(aspace, arg, &(SECTOR_SIZE as i32)),
+            BLKGETSIZE64 => write_user(aspace, arg, &bdev.size()),
+            _ => Err(E_NOT_TTY),
+        }.map(|_| 0)
+    }
+}
@@ -0,0 +1,457 @@
+//! Generic block layer.
+//!
+//! A disk driver implements [`BlockDevice`] and registers the disk by [`register_disk`]. The
+//! disk is exposed as a [`BlockDev`] named by the driver (e.g. `vda`), and its partitions found
+//! in the MBR or the GPT are exposed as the block devices of the following minor numbers (e.g.
+//! `vda1`). The file systems look up a block device by the path of its device file, or by its
+//! `/dev/<name>` path while there is no such file, see [`lookup_bdev`]. The device files of the
+//! block devices are created in `/dev` when the disk is registered, and opened as in [`fops`].
+//!
+//! The I/O is submitted to a [`BlockDev`] as [`Bio`]s, asynchronously by
+//! [`BlockDev::submit_bio`] with a completion callback, or synchronously by the helpers like
+//! [`BlockDev::read_sectors`]. The bios are merged and sorted in the [`RequestQueue`] of the disk
+//! before the driver takes them. A synchronous I/O may be issued with the interrupts disabled
+//! (e.g. in a syscall or at boot), so it polls the driver by [`BlockDevice::poll`] while waiting.
+//!
+//! Each block device has a [`PageCache`] of its content, which the file systems read their
+//! metadata through.
+//!
+//! [`PageCache`]: crate::mm::page_cache::PageCache
+//! [`Bio`]: request::Bio
+//! [`RequestQueue`]: request::RequestQueue
+
+pub(crate) mod fops;
+#[cfg(test)]
+pub(crate) mod ram;
+pub(crate) mod request;
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::ops::Range;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicI32, Ordering};
+use vos_core::partition::{self, Partition};
+use crate::base::sync::lock::SpinLockPure;
+use crate::errno::{E_EXIST, E_INVALID, E_IO, E_NO_DEV, E_NO_ENT, E_NO_SPACE, E_NOT_BLOCK,
+                   E_READ_ONLY_FS};
+use crate::fs::devtmpfs;
+use crate::fs::inode::{make_dev, InodeType};
+use crate::fs::lookup_path;
+use crate::mm::page_cache::{PageCache, PageIo};
+use crate::mm::PAGE_ORDER;
+use request::{split_bio, Bio, BlockOp, Request, RequestQueue};
+
+
+pub const SECTOR_SHIFT: usize = 9;
+/// Size of a sector, the unit of the block I/O.
+pub const SECTOR_SIZE: usize = 1 << SECTOR_SHIFT;
+
+/// Operations of a disk driver.
+pub trait BlockDevice {
+    /// Name of the disk, e.g. `vda`.
+    fn name(&self) -> &str;
+
+    fn major(&self) -> u32;
+
+    /// Minor number of the whole disk, the partitions take the following ones.
+    fn first_minor(&self) -> u32;
+
+    /// Count of the minor numbers of the disk and its partitions, 1 if the disk can not be
+    /// partitioned.
+    fn minors(&self) -> u32 {
+        1
+    }
+
+    /// Count of the sectors.
+    fn capacity(&self) -> u64;
+
+    fn read_only(&self) -> bool {
+        false
+    }
+
+    /// The request queue of the disk.
+    fn queue(&self) -> &RequestQueue;
+
+    /// Start the request `rq`, which is finished by [`Request::end`] later. Returns the request
+    /// back if the driver can not take it now, the queue is stopped until the driver calls
+    /// [`RequestQueue::restart`].
+    fn queue_rq(&self, rq: Box<Request>) -> Result<(), Box<Request>>;
+
+    /// Finish the requests done by the device without waiting for its interrupt.
+    fn poll(&self) {}
+}
+
+/// A block device: a whole disk or a partition of it.
+pub struct BlockDev {
+    name: String,
+    dev: u64,
+    disk: Arc<dyn BlockDevice>,
+    /// First sector on the disk.
+    start: u64,
+    nr_sectors: u64,
+    /// Partition number, 0 for the whole disk.
+    partno: u32,
+    cache: PageCache,
+}
+
+impl BlockDev {
+    #[inline(always)]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Device number.
+    #[inline(always)]
+    pub fn dev(&self) -> u64 {
+        self.dev
+    }
+
+    #[inline(always)]
+    pub fn partno(&self) -> u32 {
+        self.partno
+    }
+
+    /// Count of the sectors.
+    #[inline(always)]
+    pub fn nr_sectors(&self) -> u64 {
+        self.nr_sectors
+    }
+
+    /// Size in bytes.
+    #[inline(always)]
+    pub fn size(&self) -> u64 {
+        self.nr_sectors << SECTOR_SHIFT
+    }
+
+    #[inline(always)]
+    pub fn read_only(&self) -> bool {
+        self.disk.read_only()
+    }
+
+    /// Check the range of the `bio` and remap it to the disk.
+    fn check_bio(&self, bio: &mut Bio) -> Result<(), i32> {
+        if bio.op == BlockOp::Flush {
+            return Ok(());
+        }
+        if bio.len % SECTOR_SIZE != 0 || bio.buf.is_null() {
+            return Err(E_INVALID);
+        }
+        if bio.sector > self.nr_sectors || bio.nr_sectors() > self.nr_sectors - bio.sector {
+            return Err(E_IO);
+        }
+        if bio.op == BlockOp::Write && self.read_only() {
+            return Err(E_READ_ONLY_FS);
+        }
+        bio.sector += self.start;
+        Ok(())
+    }
+
+    /// Submit the `bio`, which is finished by its `end_io` callback. The bio beyond the end of
+    /// the device fails with `E_IO`, a write to a read-only device fails with `E_READ_ONLY_FS`.
+    pub fn submit_bio(&self, mut bio: Box<Bio>) {
+        if let Err(errno) = self.check_bio(&mut bio) {
+            bio.end(Err(errno));
+            return;
+        }
+        if bio.op != BlockOp::Flush && bio.len == 0 {
+            bio.end(Ok(()));
+            return;
+        }
+
+        let disk = &*self.disk;
+        let queue = disk.queue();
+        let max_sectors = queue.limits().max_sectors;
+        if bio.nr_sectors() > max_sectors {
+            for part in split_bio(bio, max_sectors) {
+                queue.add_bio(part);
+            }
+        } else {
+            queue.add_bio(bio);
+        }
+        queue.run(disk);
+    }
+
+    /// Hold the submitted bios in the queue of the disk to merge them, until
+    /// [`unplug`](Self::unplug).
+    pub fn plug(&self) {
+        self.disk.queue().plug();
+    }
+
+    /// Release a [`plug`](Self::plug) and start the held bios.
+    pub fn unplug(&self) {
+        self.disk.queue().unplug(&*self.disk);
+    }
+
+    /// Poll the driver for the finished bios, to wait for them with the interrupts disabled.
+    pub fn poll(&self) {
+        self.disk.poll();
+    }
+
+    /// Submit a bio and wait for it.
+    fn submit_wait(&self, op: BlockOp, sector: u64, buf: *mut u8, len: usize)
+        -> Result<(), i32> {
+        const PENDING: i32 = -1;
+        fn end_wait(bio: Box<Bio>, result: Result<(), i32>) {
+            let status = unsafe { &*(bio.private as *const AtomicI32) };
+            status.store(result.err().unwrap_or(0), Ordering::Release);
+        }
+
+        let status = AtomicI32::new(PENDING);
+        let private = &status as *const AtomicI32 as *mut ();
+        self.submit_bio(Bio::new(op, sector, buf, len, end_wait, private));
+        loop {
+            match status.load(Ordering::Acquire) {
+                PENDING => {
+                    self.poll();
+                    core::hint::spin_loop();
+                }
+                0 => return Ok(()),
+                errno => return Err(errno),
+            }
+        }
+    }
+
+    /// Read the sectors from `sector` into `buf`, whose length is a multiple of the sector size.
+    pub fn read_sectors(&self, sector: u64, buf: &mut [u8]) -> Result<(), i32> {
+        self.submit_wait(BlockOp::Read, sector, buf.as_mut_ptr(), buf.len())
+    }
+
+    /// Write `buf`, whose length is a multiple of the sector size, to the sectors from `sector`.
+    pub fn write_sectors(&self, sector: u64, buf: &[u8]) -> Result<(), i32> {
+        self.submit_wait(BlockOp::Write, sector, buf.as_ptr() as *mut u8, buf.len())
+    }
+
+    /// Write back the volatile cache of the disk.
+    pub fn flush(&self) -> Result<(), i32> {
+        self.submit_wait(BlockOp::Flush, 0, null_mut(), 0)
+    }
+
+    /// Read the bytes at `offset` into `buf`. Returns the count of bytes read, 0 at the end of
+    /// the device.
+    pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, i32> {
+        let size = self.size();
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min((size - offset) as usize);
+        let mut bounce = [0u8; SECTOR_SIZE];
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done as u64;
+            let sector = pos >> SECTOR_SHIFT;
+            let in_sector = pos as usize % SECTOR_SIZE;
+            if in_sector == 0 && len - done >= SECTOR_SIZE {
+                let count = (len - done) / SECTOR_SIZE * SECTOR_SIZE;
+                self.read_sectors(sector, &mut buf[done..done + count])?;
+                done += count;
+            } else {
+                self.read_sectors(sector, &mut bounce)?;
+                let count = (SECTOR_SIZE - in_sector).min(len - done);
+                buf[done..done + count].copy_from_slice(&bounce[in_sector..in_sector + count]);
+                done += count;
+            }
+        }
+        Ok(len)
+    }
+
+    /// Write `buf` at `offset`, the partial sectors are read first. Returns the count of bytes
+    /// written, which is less than `buf.len()` at the end of the device.
+    pub fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, i32> {
+        let size = self.size();
+        if offset >= size {
+            return if buf.is_empty() { Ok(0) } else { Err(E_NO_SPACE) };
+        }
+        let len = buf.len().min((size - offset) as usize);
+        let mut bounce = [0u8; SECTOR_SIZE];
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done as u64;
+            let sector = pos >> SECTOR_SHIFT;
+            let in_sector = pos as usize % SECTOR_SIZE;
+            if in_sector == 0 && len - done >= SECTOR_SIZE {
+                let count = (len - done) / SECTOR_SIZE * SECTOR_SIZE;
+                self.write_sectors(sector, &buf[done..done + count])?;
+                done += count;
+            } else {
+                let count = (SECTOR_SIZE - in_sector).min(len - done);
+                self.read_sectors(sector, &mut bounce)?;
+                bounce[in_sector..in_sector + count].copy_from_slice(&buf[done..done + count]);
+                self.write_sectors(sector, &bounce)?;
+                done += count;
+            }
+        }
+        Ok(len)
+    }
+}
+
+impl PageIo for BlockDev {
+    fn cache(&self) -> &PageCache {
+        &self.cache
+    }
+
+    fn bdev(&self) -> &BlockDev {
+        self
+    }
+
+    fn size(&self) -> u64 {
+        self.size()
+    }
+
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn map_page(&self, index: u64, alloc: Range<usize>, blocks: &mut [Option<u64>])
+        -> Result<(), i32> {
+        let first = index << (PAGE_ORDER - SECTOR_SHIFT);
+        for (i, block) in blocks.iter_mut().enumerate() {
+            let sector = first + i as u64;
+            *block = if sector < self.nr_sectors { Some(sector) } else { None };
+        }
+        let end = (index << PAGE_ORDER) + alloc.end as u64;
+        if !alloc.is_empty() && end > self.size() {
+            return Err(E_NO_SPACE);
+        }
+        Ok(())
+    }
+}
+
+/// Create a block device with its page cache.
+pub(crate) fn new_bdev(name: String, dev: u64, disk: &Arc<dyn BlockDevice>, start: u64,
+                       nr_sectors: u64, partno: u32) -> Arc<BlockDev> {
+    Arc::new_cyclic(|me: &Weak<BlockDev>| BlockDev {
+        name,
+        dev,
+        disk: disk.clone(),
+        start,
+        nr_sectors,
+        partno,
+        cache: PageCache::new(me.clone()),
+    })
+}
+
+/// All block devices, the partitions follow their disk.
+static mut BLOCK_DEVS: Vec<Arc<BlockDev>> = Vec::new();
+static BLOCK_DEVS_LOCK: SpinLockPure = SpinLockPure::new();
+
+/// Name of the partition `number` of the `disk`: `sda1`, or `nvme0n1p1` if the disk name ends
+/// with a digit.
+fn partition_name(disk: &str, number: u32) -> String {
+    if disk.ends_with(|c: char| c.is_ascii_digit()) {
+        format!("{}p{}", disk, number)
+    } else {
+        format!("{}{}", disk, number)
+    }
+}
+
+/// Read the partition table of the whole disk `bdev`.
+fn scan_partitions(bdev: &BlockDev) -> Vec<Partition> {
+    let mut parts = Vec::new();
+    let result = partition::scan(
+        bdev.nr_sectors,
+        |lba, buf| bdev.read_sectors(lba, buf).is_ok(),
+        |part| parts.push(part),
+    );
+    match result {
+        Ok(Some(scheme)) => debug!("{}: {:?} partition table", bdev.name, scheme),
+        Ok(None) => {}
+        Err(err) => warn!("{}: read partition table failed: {}", bdev.name, err),
+    }
+    parts
+}
+
+/// Register the `disk` and its partitions as the block devices.
+///
+/// Returns `Err` with `E_EXIST` if a device of the same name or device number exists.
+pub fn register_disk(disk: Arc<dyn BlockDevice>) -> Result<(), i32> {
+    let (major, first_minor) = (disk.major(), disk.first_minor());
+    let whole = new_bdev(String::from(disk.name()), make_dev(major, first_minor), &disk, 0,
+                         disk.capacity(), 0);
+
+    let mut bdevs = Vec::new();
+    if disk.minors() > 1 {
+        for part in scan_partitions(&whole) {
+            if part.number >= disk.minors() {
+                warn!("{}: partition {} ignored, too many partitions", whole.name, part.number);
+                continue;
+            }
+            let name = partition_name(&whole.name, part.number);
+            let dev = make_dev(major, first_minor + part.number);
+            bdevs.push(new_bdev(name, dev, &disk, part.start, part.sectors, part.number));
+        }
+    }
+    bdevs.insert(0, whole);
+
+    let guard = BLOCK_DEVS_LOCK.lock_guard_irq_save();
+    let all = unsafe { &mut *core::ptr::addr_of_mut!(BLOCK_DEVS) };
+    if all.iter().any(|b| b.name == bdevs[0].name || b.dev == bdevs[0].dev) {
+        return Err(E_EXIST);
+    }
+    for bdev in &bdevs {
+        info!("Block device {}: {} sectors ({} KiB){}", bdev.name, bdev.nr_sectors,
+            bdev.size() >> 10, if bdev.read_only() { ", read-only" } else { "" });
+    }
+    all.extend(bdevs.iter().cloned());
+    drop(guard);
+
+    for bdev in &bdevs {
+        if let Err(errno) = devtmpfs::create_node(&bdev.name, InodeType::BlockDevice, 0o660,
+                                                  bdev.dev) {
+            warn!("Create the device file of {} failed, errno = {}", bdev.name, errno);
+        }
+    }
+    Ok(())
+}
+
+/// Remove the block devices of the disk of the `major` and `first_minor` numbers.
+pub fn unregister_disk(major: u32, first_minor: u32) {
+    let removed: Vec<Arc<BlockDev>> = {
+        let _guard = BLOCK_DEVS_LOCK.lock_guard_irq_save();
+        let all = unsafe { &mut *core::ptr::addr_of_mut!(BLOCK_DEVS) };
+        let (removed, kept) = core::mem::take(all).into_iter().partition(|bdev| {
+            let disk = &bdev.disk;
+            disk.major() == major && disk.first_minor() == first_minor
+        });
+        *all = kept;
+        removed
+    };
+    for bdev in removed {
+        let _ = devtmpfs::delete_node(&bdev.name, bdev.dev);
+    }
+}
+
+/// Find the block device `name`, e.g. `vda1`.
+pub fn get_bdev(name: &str) -> Option<Arc<BlockDev>> {
+    let _guard = BLOCK_DEVS_LOCK.lock_guard_irq_save();
+    let all = unsafe { &*core::ptr::addr_of!(BLOCK_DEVS) };
+    all.iter().find(|bdev| bdev.name == name).cloned()
+}
+
+/// Find the block device of the device number `dev`.
+pub fn get_bdev_by_dev(dev: u64) -> Option<Arc<BlockDev>> {
+    let _guard = BLOCK_DEVS_LOCK.lock_guard_irq_save();
+    let all = unsafe { &*core::ptr::addr_of!(BLOCK_DEVS) };
+    all.iter().find(|bdev| bdev.dev == dev).cloned()
+}
+
+/// Find the block device of the device file at `path`. A path `/dev/<name>` without the file
+/// is resolved by the device name.
+///
+/// Returns `Err` with `E_NOT_BLOCK` if the file is not a block device file, or `E_NO_DEV` if
+/// there is no such device.
+pub fn lookup_bdev(path: &str) -> Result<Arc<BlockDev>, i32> {
+    match lookup_path(None, path, true) {
+        Ok(dentry) => {
+            let stat = dentry.inode().stat()?;
+            if stat.inode_type() != InodeType::BlockDevice {
+                return Err(E_NOT_BLOCK);
+            }
+            get_bdev_by_dev(stat.rdev).ok_or(E_NO_DEV)
+        }
+        Err(E_NO_ENT) => path.strip_prefix("/dev/").and_then(get_bdev).ok_or(E_NO_ENT),
+        Err(errno) => Err(errno),
+    }
+}
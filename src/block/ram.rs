@@ -0,0 +1,90 @@
+//! A disk in the memory for the tests, which finishes the requests at once.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use super::request::{BlockOp, QueueLimits, Request, RequestQueue};
+use super::{new_bdev, BlockDev, BlockDevice, SECTOR_SHIFT, SECTOR_SIZE};
+
+
+/// Major number of the RAM disks.
+const RAM_MAJOR: u32 = 1;
+
+pub struct RamDisk {
+    name: String,
+    minor: u32,
+    queue: RequestQueue,
+    data: UnsafeCell<Vec<u8>>,
+    /// Count of the sectors written.
+    pub written: AtomicUsize,
+}
+
+impl RamDisk {
+    /// Create the disk `ram<minor>` holding the image `data`, whose size must be a multiple of
+    /// the sector size.
+    pub fn new(minor: u32, data: Vec<u8>) -> Arc<RamDisk> {
+        Arc::new(RamDisk {
+            name: alloc::format!("ram{}", minor),
+            minor,
+            queue: RequestQueue::new(QueueLimits { max_sectors: 64, max_segments: 16 }),
+            data: UnsafeCell::new(data),
+            written: AtomicUsize::new(0),
+        })
+    }
+
+    /// Create a block device of the whole disk, which is not registered.
+    pub fn bdev(self: &Arc<RamDisk>) -> Arc<BlockDev> {
+        let disk: Arc<dyn BlockDevice> = self.clone();
+        new_bdev(self.name.clone(), 0, &disk, 0, disk.capacity(), 0)
+    }
+
+    /// Get the bytes of the `sector`, behind the cache.
+    pub fn sector(&self, sector: u64) -> &mut [u8] {
+        let start = (sector as usize) << SECTOR_SHIFT;
+        unsafe { &mut (*self.data.get())[start..start + SECTOR_SIZE] }
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn major(&self) -> u32 {
+        RAM_MAJOR
+    }
+
+    fn first_minor(&self) -> u32 {
+        self.minor
+    }
+
+    fn capacity(&self) -> u64 {
+        unsafe { ((*self.data.get()).len() >> SECTOR_SHIFT) as u64 }
+    }
+
+    fn queue(&self) -> &RequestQueue {
+        &self.queue
+    }
+
+    fn queue_rq(&self, rq: Box<Request>) -> Result<(), Box<Request>> {
+        for bio in rq.bios() {
+            let start = (bio.sector as usize) << SECTOR_SHIFT;
+            let disk = unsafe { &mut (*self.data.get())[start..start + bio.len] };
+            match bio.op {
+                BlockOp::Read => unsafe {
+                    bio.buf.copy_from_nonoverlapping(disk.as_ptr(), bio.len);
+                },
+                BlockOp::Write => {
+                    unsafe { disk.as_mut_ptr().copy_from_nonoverlapping(bio.buf, bio.len) };
+                    self.written.fetch_add(bio.nr_sectors() as usize, Ordering::Relaxed);
+                }
+                BlockOp::Flush => {}
+            }
+        }
+        rq.end(Ok(()));
+        Ok(())
+    }
+}
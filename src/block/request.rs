@@ -0,0 +1,434 @@
+//! Block I/O requests and the request queue of a disk.
+//!
+//! A [`Bio`] is an I/O on the contiguous sectors of a disk with one buffer, its submitter is
+//! called back by the [`EndIo`] function when it finishes. The [`RequestQueue`] of a disk
+//! collects the bios into [`Request`]s before the driver takes them:
+//!
+//! - A bio is merged into a pending request of the same operation if it is adjacent to the
+//!   request's front or back, within the [`QueueLimits`] of the driver. Each merged bio is a
+//!   segment of the request.
+//! - The pending requests are sorted by the sector, and dispatched by a one-way elevator (C-LOOK):
+//!   the next request is the first one at or after the end of the last dispatched request,
+//!   wrapping to the lowest sector.
+//! - A flush is a barrier: the bios submitted after it are never merged with or sorted before the
+//!   requests submitted before it, and it is dispatched after them. It does not wait for the
+//!   requests already in the driver, so a caller which needs the order waits for its writes
+//!   first.
+//!
+//! The requests are dispatched to [`BlockDevice::queue_rq`] unless the queue is plugged (to
+//! gather a batch of bios) or stopped. A driver which has no room for a request returns it, then
+//! the queue is stopped until the driver calls [`RequestQueue::restart`] after finishing a
+//! request. Requests on overlapping sectors are not ordered, the same as in Linux.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+use crate::base::sync::lock::SpinLockPure;
+use super::{BlockDevice, SECTOR_SHIFT};
+
+
+/// Operation of a bio.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum BlockOp {
+    Read,
+    Write,
+    /// Write back the volatile cache of the device, has no data.
+    Flush,
+}
+
+/// Completion callback of a bio, called once with the result, maybe in the interrupt context.
+pub type EndIo = fn(bio: Box<Bio>, result: Result<(), i32>);
+
+/// A block I/O.
+pub struct Bio {
+    pub op: BlockOp,
+    /// First sector of the I/O. It is the sector of the submitted device, and is remapped to the
+    /// sector of the disk when submitted to a partition.
+    pub sector: u64,
+    /// Buffer of `len` bytes in the kernel memory, which is physically contiguous. Null for a
+    /// flush.
+    pub buf: *mut u8,
+    /// Length of the I/O, a multiple of the sector size.
+    pub len: usize,
+    pub end_io: EndIo,
+    /// Private data of the submitter.
+    pub private: *mut (),
+}
+
+impl Bio {
+    pub fn new(op: BlockOp, sector: u64, buf: *mut u8, len: usize, end_io: EndIo,
+               private: *mut ()) -> Box<Bio> {
+        Box::new(Bio { op, sector, buf, len, end_io, private })
+    }
+
+    /// Count of the sectors.
+    #[inline(always)]
+    pub fn nr_sectors(&self) -> u64 {
+        (self.len >> SECTOR_SHIFT) as u64
+    }
+
+    /// The sector after the last one.
+    #[inline(always)]
+    pub fn end_sector(&self) -> u64 {
+        self.sector + self.nr_sectors()
+    }
+
+    /// Finish the bio with the `result`.
+    pub fn end(self: Box<Self>, result: Result<(), i32>) {
+        (self.end_io)(self, result)
+    }
+}
+
+/// The bios split from a large bio, see [`split_bio`].
+struct SplitParent {
+    bio: Box<Bio>,
+    remaining: AtomicUsize,
+    /// The first error, 0 if none.
+    error: AtomicI32,
+}
+
+fn split_end_io(bio: Box<Bio>, result: Result<(), i32>) {
+    let parent = bio.private as *mut SplitParent;
+    let parent_ref = unsafe { &*parent };
+    if let Err(errno) = result {
+        let _ = parent_ref.error.compare_exchange(0, errno, Ordering::AcqRel, Ordering::Acquire);
+    }
+    if parent_ref.remaining.fetch_sub(1, Ordering::AcqRel) == 1 {
+        let parent = unsafe { Box::from_raw(parent) };
+        let result = match parent.error.load(Ordering::Acquire) {
+            0 => Ok(()),
+            errno => Err(errno),
+        };
+        parent.bio.end(result);
+    }
+}
+
+/// Split the read or write `bio` into the bios of at most `max_sectors` sectors. The `bio` is
+/// finished when all the split bios finish.
+pub(super) fn split_bio(bio: Box<Bio>, max_sectors: u64) -> Vec<Box<Bio>> {
+    let chunk = (max_sectors as usize) << SECTOR_SHIFT;
+    let count = (bio.len + chunk - 1) / chunk;
+    let (op, sector, buf, len) = (bio.op, bio.sector, bio.buf, bio.len);
+    let parent = Box::into_raw(Box::new(SplitParent {
+        bio,
+        remaining: AtomicUsize::new(count),
+        error: AtomicI32::new(0),
+    }));
+
+    (0..count).map(|idx| {
+        let off = idx * chunk;
+        let sector = sector + (off >> SECTOR_SHIFT) as u64;
+        let part_len = chunk.min(len - off);
+        Bio::new(op, sector, unsafe { buf.add(off) }, part_len, split_end_io, parent as *mut ())
+    }).collect()
+}
+
+/// A request to the driver: the bios on the contiguous sectors, in the sector order.
+pub struct Request {
+    op: BlockOp,
+    sector: u64,
+    nr_sectors: u64,
+    bios: Vec<Box<Bio>>,
+}
+
+impl Request {
+    fn new(bio: Box<Bio>) -> Box<Request> {
+        let mut bios = Vec::with_capacity(1);
+        let (op, sector, nr_sectors) = (bio.op, bio.sector, bio.nr_sectors());
+        bios.push(bio);
+        Box::new(Request { op, sector, nr_sectors, bios })
+    }
+
+    #[inline(always)]
+    pub fn op(&self) -> BlockOp {
+        self.op
+    }
+
+    /// First sector on the disk.
+    #[inline(always)]
+    pub fn sector(&self) -> u64 {
+        self.sector
+    }
+
+    #[inline(always)]
+    pub fn nr_sectors(&self) -> u64 {
+        self.nr_sectors
+    }
+
+    #[inline(always)]
+    pub fn end_sector(&self) -> u64 {
+        self.sector + self.nr_sectors
+    }
+
+    /// The segments of the request, a flush has one bio without data.
+    #[inline(always)]
+    pub fn bios(&self) -> &[Box<Bio>] {
+        &self.bios
+    }
+
+    /// Finish all bios of the request with the `result`.
+    pub fn end(self: Box<Self>, result: Result<(), i32>) {
+        for bio in self.bios {
+            bio.end(result);
+        }
+    }
+
+    /// Merge the `bio` to the back or the front of the request if they are adjacent. Returns the
+    /// `bio` back if it can not be merged.
+    fn try_merge(&mut self, bio: Box<Bio>, limits: &QueueLimits) -> Result<(), Box<Bio>> {
+        if self.op != bio.op || self.op == BlockOp::Flush ||
+            self.bios.len() >= limits.max_segments ||
+            self.nr_sectors + bio.nr_sectors() > limits.max_sectors {
+            return Err(bio);
+        }
+        if self.end_sector() == bio.sector {
+            self.nr_sectors += bio.nr_sectors();
+            self.bios.push(bio);
+        } else if bio.end_sector() == self.sector {
+            self.sector = bio.sector;
+            self.nr_sectors += bio.nr_sectors();
+            self.bios.insert(0, bio);
+        } else {
+            return Err(bio);
+        }
+        Ok(())
+    }
+}
+
+/// Limits of a request, set by the driver.
+#[derive(Copy, Clone, Debug)]
+pub struct QueueLimits {
+    /// Max count of the sectors.
+    pub max_sectors: u64,
+    /// Max count of the segments (bios).
+    pub max_segments: usize,
+}
+
+struct QueueState {
+    /// The pending requests. The requests between two flushes are sorted by the sector.
+    pending: Vec<Box<Request>>,
+    /// The elevator position: the end sector of the last dispatched request.
+    head: u64,
+    plugged: u32,
+    stopped: bool,
+    /// Count of the restarts, to find a restart while dispatching a request.
+    restarts: usize,
+}
+
+impl QueueState {
+    /// Range of the pending requests submitted after the last flush.
+    fn tail_start(&self) -> usize {
+        self.pending.iter().rposition(|rq| rq.op == BlockOp::Flush).map_or(0, |pos| pos + 1)
+    }
+
+    /// Range of the pending requests submitted before the first flush.
+    fn head_end(&self) -> usize {
+        self.pending.iter().position(|rq| rq.op == BlockOp::Flush).unwrap_or(self.pending.len())
+    }
+
+    /// Insert the request at its sector position in `pending[start..end]`.
+    fn insert_sorted(&mut self, rq: Box<Request>, start: usize, end: usize) {
+        let pos = self.pending[start..end].partition_point(|other| other.sector <= rq.sector);
+        self.pending.insert(start + pos, rq);
+    }
+
+    fn add_bio(&mut self, mut bio: Box<Bio>, limits: &QueueLimits) {
+        let start = self.tail_start();
+        if bio.op == BlockOp::Flush {
+            self.pending.push(Request::new(bio));
+            return;
+        }
+        for rq in &mut self.pending[start..] {
+            bio = match rq.try_merge(bio, limits) {
+                Ok(()) => return,
+                Err(bio) => bio,
+            };
+        }
+        let end = self.pending.len();
+        self.insert_sorted(Request::new(bio), start, end);
+    }
+
+    /// Take the next request by the elevator.
+    fn next_request(&mut self) -> Option<Box<Request>> {
+        let end = self.head_end();
+        if end == 0 {
+            // A flush after all requests before it are dispatched, or empty.
+            return if self.pending.is_empty() { None } else { Some(self.pending.remove(0)) };
+        }
+        let head = self.head;
+        let pos = self.pending[..end].iter().position(|rq| rq.sector >= head).unwrap_or(0);
+        let rq = self.pending.remove(pos);
+        self.head = rq.end_sector();
+        Some(rq)
+    }
+
+    /// Put back the request the driver can not take now, it is the next one to dispatch.
+    fn requeue(&mut self, rq: Box<Request>) {
+        if rq.op == BlockOp::Flush {
+            self.pending.insert(0, rq);
+            return;
+        }
+        self.head = rq.sector;
+        let end = self.head_end();
+        self.insert_sorted(rq, 0, end);
+    }
+}
+
+/// Request queue of a disk. The operations are serialized by an inner lock, and can be called
+/// from the interrupt handler.
+pub struct RequestQueue {
+    limits: QueueLimits,
+    lock: SpinLockPure,
+    state: UnsafeCell<QueueState>,
+}
+
+unsafe impl Send for RequestQueue {}
+unsafe impl Sync for RequestQueue {}
+
+impl RequestQueue {
+    pub const fn new(limits: QueueLimits) -> Self {
+        Self {
+            limits,
+            lock: SpinLockPure::new(),
+            state: UnsafeCell::new(QueueState {
+                pending: Vec::new(),
+                head: 0,
+                plugged: 0,
+                stopped: false,
+                restarts: 0,
+            }),
+        }
+    }
+
+    #[inline(always)]
+    pub fn limits(&self) -> &QueueLimits {
+        &self.limits
+    }
+
+    /// Add a bio of at most `max_sectors`, merged or sorted into the pending requests. The
+    /// requests are dispatched by [`run`](Self::run).
+    pub(super) fn add_bio(&self, bio: Box<Bio>) {
+        let _guard = self.lock.lock_guard_irq_save();
+        unsafe { (*self.state.get()).add_bio(bio, &self.limits) };
+    }
+
+    /// Dispatch the pending requests to the driver `dev` until the queue is empty, plugged or
+    /// stopped.
+    pub fn run(&self, dev: &dyn BlockDevice) {
+        loop {
+            let (rq, restarts) = {
+                let _guard = self.lock.lock_guard_irq_save();
+                let state = unsafe { &mut *self.state.get() };
+                if state.plugged > 0 || state.stopped {
+                    return;
+                }
+                match state.next_request() {
+                    Some(rq) => (rq, state.restarts),
+                    None => return,
+                }
+            };
+
+            // The driver may finish requests and restart the queue in the call.
+            if let Err(rq) = dev.queue_rq(rq) {
+                let _guard = self.lock.lock_guard_irq_save();
+                let state = unsafe { &mut *self.state.get() };
+                state.requeue(rq);
+                if state.restarts == restarts {
+                    state.stopped = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Restart the queue stopped by a full driver, called by the driver `dev` after it finishes
+    /// requests.
+    pub fn restart(&self, dev: &dyn BlockDevice) {
+        {
+            let _guard = self.lock.lock_guard_irq_save();
+            let state = unsafe { &mut *self.state.get() };
+            state.stopped = false;
+            state.restarts = state.restarts.wrapping_add(1);
+        }
+        self.run(dev);
+    }
+
+    /// Hold the requests in the queue to merge and sort a batch of bios, until
+    /// [`unplug`](Self::unplug).
+    pub fn plug(&self) {
+        let _guard = self.lock.lock_guard_irq_save();
+        unsafe { (*self.state.get()).plugged += 1 };
+    }
+
+    /// Release a [`plug`](Self::plug), and dispatch the requests to the driver `dev` if the
+    /// queue is not plugged any more.
+    pub fn unplug(&self, dev: &dyn BlockDevice) {
+        {
+            let _guard = self.lock.lock_guard_irq_save();
+            let state = unsafe { &mut *self.state.get() };
+            state.plugged = state.plugged.saturating_sub(1);
+        }
+        self.run(dev);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use core::ptr::null_mut;
+    use super::*;
+
+    const LIMITS: QueueLimits = QueueLimits { max_sectors: 16, max_segments: 4 };
+
+    fn end_io_nop(_bio: Box<Bio>, _result: Result<(), i32>) {}
+
+    fn test_bio(op: BlockOp, sector: u64, sectors: usize) -> Box<Bio> {
+        Bio::new(op, sector, null_mut(), sectors << SECTOR_SHIFT, end_io_nop, null_mut())
+    }
+
+    fn dispatch_all(state: &mut QueueState) -> Vec<(BlockOp, u64, u64, usize)> {
+        let mut out = Vec::new();
+        while let Some(rq) = state.next_request() {
+            out.push((rq.op, rq.sector, rq.nr_sectors, rq.bios.len()));
+        }
+        out
+    }
+
+    #[kernel_test]
+    fn request_queue_merge_and_sort() {
+        let mut state = QueueState {
+            pending: Vec::new(), head: 0, plugged: 0, stopped: false, restarts: 0,
+        };
+        // Back and front merges, a read does not merge with a write.
+        state.add_bio(test_bio(BlockOp::Write, 100, 2), &LIMITS);
+        state.add_bio(test_bio(BlockOp::Write, 102, 2), &LIMITS);
+        state.add_bio(test_bio(BlockOp::Write, 98, 2), &LIMITS);
+        state.add_bio(test_bio(BlockOp::Read, 104, 2), &LIMITS);
+        state.add_bio(test_bio(BlockOp::Read, 10, 2), &LIMITS);
+        // Over the limit of sectors.
+        state.add_bio(test_bio(BlockOp::Write, 104, 12), &LIMITS);
+        // After the flush, nothing merges or sorts before it.
+        state.add_bio(test_bio(BlockOp::Flush, 0, 0), &LIMITS);
+        state.add_bio(test_bio(BlockOp::Read, 8, 2), &LIMITS);
+        state.add_bio(test_bio(BlockOp::Read, 6, 2), &LIMITS);
+
+        state.head = 50;
+        assert_eq!(dispatch_all(&mut state), [
+            (BlockOp::Write, 98, 6, 3),
+            (BlockOp::Read, 104, 2, 1),
+            (BlockOp::Read, 10, 2, 1),
+            (BlockOp::Write, 104, 12, 1),
+            (BlockOp::Flush, 0, 0, 1),
+            (BlockOp::Read, 6, 4, 2),
+        ]);
+
+        // Split a large bio, the parent finishes with the last part.
+        let parts = split_bio(test_bio(BlockOp::Read, 0, 40), LIMITS.max_sectors);
+        let sizes: Vec<_> = parts.iter().map(|bio| (bio.sector, bio.nr_sectors())).collect();
+        assert_eq!(sizes, [(0, 16), (16, 16), (32, 8)]);
+        for bio in parts {
+            bio.end(Ok(()));
+        }
+    }
+}
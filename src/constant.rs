@@ -2,6 +2,7 @@
 
 /////////////// MM RELATED ///////////////////
 
+pub const ORDER_4KB: usize = 12;
 // 2M = 0x20_0000 = 1 << 21
 pub const ORDER_2MB: usize = 21;
 pub const ORDER_1GB: usize = 30;
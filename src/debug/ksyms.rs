@@ -0,0 +1,146 @@
+//! Kernel symbol table.
+//!
+//! The kernel reserves [`KSYMS_CAPACITY`] bytes in the `.ksyms` section, `tools/ksyms.py` reads
+//! the symbols of the linked kernel image and writes the table into the section. Layout of the
+//! table (little endian):
+//!
+//! ```text
+//! +----------------------+ 0
+//! | KsymsHeader          |
+//! +----------------------+ size_of::<KsymsHeader>()
+//! | KsymEntry * count    |   sorted by `addr`
+//! +----------------------+ strtab_off
+//! | names (UTF-8, no NUL)|
+//! +----------------------+
+//! ```
+//!
+//! [`KSYMS_CAPACITY`]: KSYMS_CAPACITY
+
+use core::mem::size_of;
+use core::slice;
+use crate::asm::mem_v::{KSYMS_END, KSYMS_START};
+
+
+/// `"KSYM"`.
+const KSYMS_MAGIC: u32 = 0x4d59_534b;
+const KSYMS_VERSION: u16 = 1;
+/// Bytes reserved for the symbol table, including the header.
+pub const KSYMS_CAPACITY: usize = 512 * 1024;
+
+#[repr(C)]
+struct KsymsHeader {
+    magic: u32,
+    version: u16,
+    _reserved: u16,
+    /// Number of entries.
+    count: u32,
+    /// Offset of the string table, from the start of the header.
+    strtab_off: u32,
+}
+
+#[repr(C)]
+struct KsymEntry {
+    addr: u64,
+    size: u32,
+    name_off: u32,
+    name_len: u32,
+    _reserved: u32,
+}
+
+#[repr(C)]
+struct KsymsArea {
+    header: KsymsHeader,
+    _data: [u8; KSYMS_CAPACITY - size_of::<KsymsHeader>()],
+}
+
+/// The reserved space. The table is always read through the linker symbols so that the compiler
+/// can not assume the content of this empty table.
+#[used]
+#[link_section = ".ksyms"]
+static KSYMS_AREA: KsymsArea = KsymsArea {
+    header: KsymsHeader {
+        magic: KSYMS_MAGIC,
+        version: KSYMS_VERSION,
+        _reserved: 0,
+        count: 0,
+        strtab_off: size_of::<KsymsHeader>() as u32,
+    },
+    _data: [0u8; KSYMS_CAPACITY - size_of::<KsymsHeader>()],
+};
+
+/// A kernel symbol.
+#[derive(Copy, Clone, Debug)]
+pub struct Symbol {
+    pub name: &'static str,
+    pub addr: usize,
+    /// Size of the symbol. 0 if unknown (for example, an assembly label).
+    pub size: usize,
+}
+
+/// Get the table header, `None` if the table is not valid.
+fn header() -> Option<&'static KsymsHeader> {
+    let (start, end) = unsafe { (KSYMS_START, KSYMS_END) };
+    if end < start + size_of::<KsymsHeader>() {
+        return None;
+    }
+
+    let header = unsafe { &*(start as *const KsymsHeader) };
+    let entries_end = size_of::<KsymsHeader>() + header.count as usize * size_of::<KsymEntry>();
+    if header.magic != KSYMS_MAGIC || header.version != KSYMS_VERSION
+        || entries_end > header.strtab_off as usize || header.strtab_off as usize > end - start {
+        return None;
+    }
+    Some(header)
+}
+
+fn entries(header: &'static KsymsHeader) -> &'static [KsymEntry] {
+    unsafe {
+        let ptr = (header as *const KsymsHeader).add(1) as *const KsymEntry;
+        slice::from_raw_parts(ptr, header.count as usize)
+    }
+}
+
+fn entry_name(header: &'static KsymsHeader, entry: &KsymEntry) -> &'static str {
+    let limit = unsafe { KSYMS_END - KSYMS_START };
+    let off = header.strtab_off as usize + entry.name_off as usize;
+    let len = entry.name_len as usize;
+    if off + len > limit {
+        return "<bad symbol>";
+    }
+
+    let bytes = unsafe {
+        slice::from_raw_parts((header as *const KsymsHeader as *const u8).add(off), len)
+    };
+    core::str::from_utf8(bytes).unwrap_or("<bad symbol>")
+}
+
+/// Check if the symbol table has been filled.
+pub fn is_available() -> bool {
+    header().map_or(false, |h| h.count > 0)
+}
+
+/// Find the symbol that contains `addr`. Returns the symbol and the offset of `addr` in it.
+pub fn lookup_symbol(addr: usize) -> Option<(Symbol, usize)> {
+    let header = header()?;
+    let entries = entries(header);
+
+    // The last entry whose address <= addr.
+    let idx = entries.partition_point(|e| e.addr as usize <= addr);
+    if idx == 0 {
+        return None;
+    }
+    let entry = &entries[idx - 1];
+    let sym_addr = entry.addr as usize;
+    let size = entry.size as usize;
+    let off = addr - sym_addr;
+    if size != 0 && off >= size {
+        return None;
+    }
+
+    let sym = Symbol {
+        name: entry_name(header, entry),
+        addr: sym_addr,
+        size,
+    };
+    Some((sym, off))
+}
@@ -0,0 +1,113 @@
+//! Kernel debugging facilities: stack unwinding and symbolization.
+//!
+//! The kernel is built with the frame pointer enabled (`-Cforce-frame-pointers=yes`, see
+//! `.cargo/config.toml`), so the backtrace is produced by walking the frame records on the stack.
+//! The return addresses are symbolized against the kernel symbol table embedded in the `.ksyms`
+//! section, which is filled by `tools/ksyms.py` after the link (the cargo runner does this).
+//!
+//! A backtrace is printed on a kernel panic, on an S-mode exception, or on demand by calling
+//! [`dump_stack`].
+//!
+//! [`dump_stack`]: dump_stack
+
+mod ksyms;
+mod unwind;
+
+pub use ksyms::lookup_symbol;
+pub use unwind::walk_stack;
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::arch::cpu::{self, Register};
+use crate::proc::task::TaskTrapFrame;
+
+
+/// Max frames printed in one backtrace.
+const MAX_BACKTRACE_DEPTH: usize = 64;
+
+/// Set if the backtrace of the current panic has been printed by the trap handler.
+static TRAP_TRACE_DUMPED: AtomicBool = AtomicBool::new(false);
+/// Set when the first panic happens, used to avoid the recursive panic in the unwinder.
+static IN_PANIC: AtomicBool = AtomicBool::new(false);
+
+fn print_frame(idx: usize, pc: usize) {
+    match lookup_symbol(pc) {
+        Some((sym, off)) => {
+            println_k!("  #{:<2} [<{:#018x}>] {}+{:#x}/{:#x}", idx, pc, sym.name, off, sym.size);
+        }
+        None => {
+            println_k!("  #{:<2} [<{:#018x}>] ?", idx, pc);
+        }
+    }
+}
+
+/// Print the frames starting from the frame record `fp`. `first_idx` is the index of the first
+/// printed frame.
+fn print_frames_from(fp: usize, first_idx: usize) {
+    let mut idx = first_idx;
+    walk_stack(fp, |frame| {
+        // `ra` is the address after the call instruction, use the address of the call itself to
+        // symbolize, otherwise a call at the end of the function is reported as the next one.
+        print_frame(idx, frame.ra - 4);
+        idx += 1;
+        idx < MAX_BACKTRACE_DEPTH
+    });
+    if idx == first_idx {
+        println_k!("  <no frame record>");
+    }
+    if !ksyms::is_available() {
+        println_k!("  (kernel symbol table is empty, run `tools/ksyms.py` on the kernel image)");
+    }
+}
+
+/// Read the frame pointer of the caller.
+#[inline(always)]
+fn read_fp() -> usize {
+    let fp: usize;
+    unsafe {
+        asm!("mv {}, s0", out(reg) fp);
+    }
+    fp
+}
+
+/// Print the backtrace of the current call stack.
+#[inline(never)]
+pub fn dump_stack() {
+    let fp = read_fp();
+    println_k!("Call trace:");
+    print_frames_from(fp, 0);
+}
+
+/// Print the backtrace of the context saved in the trap `frame`. `epc` is the address of the
+/// trapped instruction.
+pub fn dump_trap_stack(epc: usize, frame: &TaskTrapFrame) {
+    let fp = frame.regs[cpu::reg(Register::S0)];
+    let ra = frame.regs[cpu::reg(Register::Ra)];
+    println_k!("Exception trace (ra: {:#x}, sp: {:#x}, fp: {:#x}):",
+        ra, frame.regs[cpu::reg(Register::Sp)], fp);
+    print_frame(0, epc);
+    print_frames_from(fp, 1);
+}
+
+/// Print the backtrace of the S-mode exception which is going to panic. The panic handler will
+/// not print the backtrace again.
+pub fn dump_fatal_trap_stack(epc: usize, frame: &TaskTrapFrame) {
+    TRAP_TRACE_DUMPED.store(true, Ordering::Release);
+    dump_trap_stack(epc, frame);
+}
+
+/// Print the backtrace on the panic path. Called by the panic handler.
+#[inline(never)]
+pub(crate) fn panic_backtrace() {
+    if IN_PANIC.swap(true, Ordering::AcqRel) {
+        println_k!("Recursive panic, backtrace skipped.");
+        return;
+    }
+    if TRAP_TRACE_DUMPED.load(Ordering::Acquire) {
+        return;
+    }
+
+    let fp = read_fp();
+    println_k!("Call trace:");
+    print_frames_from(fp, 0);
+}
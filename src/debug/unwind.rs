@@ -0,0 +1,162 @@
+//! Frame pointer based stack unwinder.
+//!
+//! With the frame pointer enabled, the RISC-V prologue saves a *frame record* at the top of each
+//! frame, and `fp` (`s0`) points to the end of the record:
+//!
+//! ```text
+//!   high addr  +-----------------+ <-- fp
+//!              | return address  |  fp - 8
+//!              | caller's fp     |  fp - 16
+//!              | ...             |
+//!   low addr   +-----------------+ <-- sp
+//! ```
+//!
+//! The assembly code (trap entry, context switch) does not build a frame record but keeps `s0`
+//! unchanged, so the walk naturally continues to the interrupted frames, which may live on
+//! another stack.
+
+use core::mem::size_of;
+use crate::arch::cpu;
+use crate::asm::mem_v::{KERNEL_STACK_END, KERNEL_STACK_START, TEXT_END, TEXT_START};
+use crate::mm::PAGE_SIZE;
+use crate::proc::kernel::KERNEL_THREAD_STACK_ORDER;
+use crate::proc::task::{TaskInfo, TaskTrapFrame, TaskType};
+use crate::smp;
+
+
+/// Kind of a kernel stack.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum StackKind {
+    /// The boot stack defined in the linker script.
+    Boot,
+    /// The per-cpu trap stack.
+    Trap,
+    /// The kernel stack of the current task.
+    Thread,
+    /// Stack that the bounds are not known.
+    Unknown,
+}
+
+/// Memory range `[start, end)` of a stack.
+#[derive(Copy, Clone, Debug)]
+pub struct StackRange {
+    pub start: usize,
+    pub end: usize,
+    pub kind: StackKind,
+}
+
+impl StackRange {
+    #[inline(always)]
+    fn contains(&self, addr: usize) -> bool {
+        self.start <= addr && addr <= self.end
+    }
+
+    /// Check if the frame record ends at `fp` is fully inside this stack.
+    #[inline(always)]
+    fn contains_record(&self, fp: usize) -> bool {
+        fp >= self.start + FRAME_RECORD_SIZE && fp <= self.end
+    }
+}
+
+/// One unwound frame.
+#[derive(Copy, Clone, Debug)]
+pub struct StackFrame {
+    /// The frame pointer, points to the end of the frame record.
+    pub fp: usize,
+    /// The return address saved in the frame record.
+    pub ra: usize,
+    /// The stack this frame lives on.
+    pub stack: StackKind,
+}
+
+const FRAME_RECORD_SIZE: usize = 2 * size_of::<usize>();
+/// Max size of a frame on the stack whose bounds are not known.
+const UNKNOWN_STACK_SIZE: usize = PAGE_SIZE << KERNEL_THREAD_STACK_ORDER;
+
+/// Find the known stack that contains `addr`.
+fn find_stack(addr: usize) -> StackRange {
+    let (boot_start, boot_end) = unsafe { (KERNEL_STACK_START, KERNEL_STACK_END) };
+    if boot_start <= addr && addr <= boot_end {
+        return StackRange { start: boot_start, end: boot_end, kind: StackKind::Boot };
+    }
+
+    for id in 0..smp::get_cpu_count() {
+        let (start, end) = smp::get_cpu_trap_stack_range(id);
+        if start <= addr && addr <= end {
+            return StackRange { start, end, kind: StackKind::Trap };
+        }
+    }
+
+    // `sscratch` holds the trap frame of the running task.
+    let frame = cpu::sscratch_read() as *mut TaskTrapFrame;
+    if !frame.is_null() {
+        let (frame, task) = unsafe { (&*frame, &*TaskInfo::from_trap_frame_ptr(frame)) };
+        let base = frame.kernel_stack as usize;
+        if base != 0 {
+            let size = match task.task_type() {
+                TaskType::Kernel => PAGE_SIZE << KERNEL_THREAD_STACK_ORDER,
+                TaskType::User => PAGE_SIZE,
+            };
+            let (start, end) = (base & !(PAGE_SIZE - 1), (base & !(PAGE_SIZE - 1)) + size);
+            if start <= addr && addr <= end {
+                return StackRange { start, end, kind: StackKind::Thread };
+            }
+        }
+    }
+
+    StackRange {
+        start: addr.saturating_sub(FRAME_RECORD_SIZE),
+        end: addr.saturating_add(UNKNOWN_STACK_SIZE),
+        kind: StackKind::Unknown,
+    }
+}
+
+#[inline(always)]
+fn is_kernel_text(addr: usize) -> bool {
+    unsafe { TEXT_START <= addr && addr < TEXT_END }
+}
+
+/// Walk the frame records starting from `fp`, the innermost frame first. The walk stops if
+/// `handle` returns `false`, or the next record is not valid: misaligned, out of the stack, not
+/// going to the outer frame, or the return address is not a kernel text address.
+///
+/// Walking from one stack into another is allowed (a trap handler frame is followed by the frames
+/// of the interrupted context), but never into a stack whose bounds are not known.
+pub fn walk_stack<F>(mut fp: usize, mut handle: F)
+    where F: FnMut(&StackFrame) -> bool {
+    let mut stack = find_stack(fp);
+
+    loop {
+        if fp == 0 || fp % size_of::<usize>() != 0 || !stack.contains_record(fp) {
+            break;
+        }
+
+        // SAFETY: the record is checked to be inside the stack memory.
+        let (ra, prev_fp) = unsafe {
+            let record = fp as *const usize;
+            (record.sub(1).read(), record.sub(2).read())
+        };
+        if !is_kernel_text(ra) {
+            break;
+        }
+
+        let frame = StackFrame { fp, ra, stack: stack.kind };
+        if !handle(&frame) {
+            break;
+        }
+
+        if stack.contains(prev_fp) {
+            // The outer frame must be on the higher address.
+            if prev_fp <= fp {
+                break;
+            }
+        } else {
+            let next = find_stack(prev_fp);
+            if next.kind == StackKind::Unknown || next.kind == stack.kind {
+                break;
+            }
+            stack = next;
+        }
+        fp = prev_fp;
+    }
+}
@@ -0,0 +1,267 @@
+//! Device interrupt requests (IRQ).
+//!
+//! The external interrupts of the devices are routed by an interrupt controller, which is
+//! registered as the [`IrqChip`] by its driver (e.g. the PLIC). A device driver installs the
+//! handler of its interrupt line by [`request_irq`], the line is enabled on the chip once both the
+//! handler and the chip are present, so the drivers can be probed in any order.
+//!
+//! On a supervisor external interrupt, the trap handler calls [`handle_external_irq`], which
+//! claims the pending interrupts from the chip and dispatches them to the handlers. The handlers
+//! run in the interrupt context with the interrupts disabled, and must not sleep.
+//!
+//! The interrupts of each line, and the timer and inter-processor interrupts, are counted per
+//! CPU and shown by [`show_interrupts`] in `/proc/interrupts`.
+
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::base::sync::lock::SpinLockPure;
+use crate::errno::{E_BUSY, E_INVALID};
+use crate::smp::PerCpuPtr;
+
+
+/// Max count of the interrupt lines, the line 0 means no interrupt.
+pub const NR_IRQS: usize = 128;
+
+/// Handler of an interrupt line, called with the line number and the `data` passed to
+/// [`request_irq`].
+pub type IrqHandler = fn(irq: u32, data: *mut ());
+
+/// Interrupt controller operations.
+pub trait IrqChip {
+    fn name(&self) -> &'static str;
+
+    /// Enable the interrupt line `irq`.
+    fn enable(&self, irq: u32);
+
+    /// Disable the interrupt line `irq`.
+    fn disable(&self, irq: u32);
+
+    /// Claim the highest priority pending interrupt of the current hart. Returns `None` if
+    /// there is no pending interrupt.
+    fn claim(&self) -> Option<u32>;
+
+    /// Signal the completion of the claimed interrupt `irq`.
+    fn complete(&self, irq: u32);
+}
+
+#[derive(Copy, Clone)]
+struct IrqAction {
+    name: &'static str,
+    handler: Option<IrqHandler>,
+    data: *mut (),
+}
+
+impl IrqAction {
+    const fn new() -> Self {
+        Self { name: "", handler: None, data: null_mut() }
+    }
+}
+
+/// Interrupt counts of a CPU.
+struct IrqStats {
+    /// Count of each interrupt line.
+    lines: [AtomicUsize; NR_IRQS],
+    /// Count of the timer interrupts.
+    timer: AtomicUsize,
+    /// Count of the inter-processor interrupts.
+    ipi: AtomicUsize,
+}
+
+static mut IRQ_CHIP: Option<&'static dyn IrqChip> = None;
+static mut IRQ_ACTIONS: [IrqAction; NR_IRQS] = [IrqAction::new(); NR_IRQS];
+static IRQ_LOCK: SpinLockPure = SpinLockPure::new();
+static mut IRQ_STATS: PerCpuPtr<IrqStats> = PerCpuPtr::null();
+
+
+/// Alloc the interrupt counts of the CPUs. Must be called before the interrupts are enabled.
+pub(crate) fn init() {
+    unsafe {
+        let stats = &mut *core::ptr::addr_of_mut!(IRQ_STATS);
+        stats.init();
+        for cpu in stats.as_array_mut() {
+            // All zeros are valid counts.
+            (cpu as *mut IrqStats).write_bytes(0, 1);
+        }
+    }
+}
+
+/// Get the interrupt counts of the current CPU, called in the interrupt context.
+#[inline(always)]
+fn local_stats() -> &'static IrqStats {
+    unsafe { (*core::ptr::addr_of!(IRQ_STATS)).get_ref_raw() }
+}
+
+/// Count a timer interrupt of the current CPU.
+pub(crate) fn count_timer_irq() {
+    local_stats().timer.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Count an inter-processor interrupt of the current CPU.
+pub(crate) fn count_ipi() {
+    local_stats().ipi.fetch_add(1, Ordering::Relaxed);
+}
+
+
+/// Register the interrupt controller, the lines already requested are enabled on it.
+pub fn register_irq_chip(chip: &'static dyn IrqChip) {
+    let _guard = IRQ_LOCK.lock_guard_irq_save();
+    unsafe {
+        if let Some(old) = IRQ_CHIP {
+            warn!("IRQ chip {} replaced by {}.", old.name(), chip.name());
+        }
+        IRQ_CHIP = Some(chip);
+        for (irq, action) in IRQ_ACTIONS.iter().enumerate() {
+            if action.handler.is_some() {
+                chip.enable(irq as u32);
+            }
+        }
+    }
+    info!("IRQ chip {} registered.", chip.name());
+}
+
+/// Install the `handler` of the interrupt line `irq` for the device `name`, the line is enabled
+/// if the interrupt controller is registered.
+///
+/// Returns `Err` with `E_INVALID` if `irq` is out of range, or `E_BUSY` if the line already has
+/// a handler.
+pub fn request_irq(irq: u32, name: &'static str, handler: IrqHandler, data: *mut ())
+    -> Result<(), i32> {
+    if irq == 0 || irq as usize >= NR_IRQS {
+        return Err(E_INVALID);
+    }
+
+    let _guard = IRQ_LOCK.lock_guard_irq_save();
+    let action = unsafe { &mut IRQ_ACTIONS[irq as usize] };
+    if action.handler.is_some() {
+        return Err(E_BUSY);
+    }
+    *action = IrqAction { name, handler: Some(handler), data };
+    if let Some(chip) = unsafe { IRQ_CHIP } {
+        chip.enable(irq);
+    }
+    Ok(())
+}
+
+/// Disable the interrupt line `irq` and remove its handler.
+pub fn free_irq(irq: u32) {
+    if irq == 0 || irq as usize >= NR_IRQS {
+        return;
+    }
+
+    let _guard = IRQ_LOCK.lock_guard_irq_save();
+    if let Some(chip) = unsafe { IRQ_CHIP } {
+        chip.disable(irq);
+    }
+    unsafe { IRQ_ACTIONS[irq as usize] = IrqAction::new(); }
+}
+
+/// Handle the supervisor external interrupt: dispatch all pending interrupts of the current
+/// hart to their handlers. Called by the trap handler with the interrupts disabled.
+pub(crate) fn handle_external_irq() {
+    let chip = match unsafe { IRQ_CHIP } {
+        Some(chip) => chip,
+        None => return,
+    };
+
+    while let Some(irq) = chip.claim() {
+        if let Some(count) = local_stats().lines.get(irq as usize) {
+            count.fetch_add(1, Ordering::Relaxed);
+        }
+        let action = {
+            let _guard = IRQ_LOCK.lock_guard();
+            unsafe { IRQ_ACTIONS.get(irq as usize).copied() }.unwrap_or(IrqAction::new())
+        };
+        match action.handler {
+            Some(handler) => {
+                trace!("IRQ {} of {}.", irq, action.name);
+                handler(irq, action.data);
+            }
+            None => {
+                warn!("Spurious IRQ {}, disabled.", irq);
+                chip.disable(irq);
+            }
+        }
+        chip.complete(irq);
+    }
+}
+
+/// Write the interrupt counts of each CPU like `/proc/interrupts` of Linux: a line for each
+/// interrupt line with a handler, then the timer and the inter-processor interrupts.
+pub fn show_interrupts(out: &mut dyn Write) -> fmt::Result {
+    let stats = unsafe { (*core::ptr::addr_of!(IRQ_STATS)).as_array_mut() };
+    write!(out, "{:11}", "")?;
+    for cpu in 0..stats.len() {
+        write!(out, "CPU{:<8}", cpu)?;
+    }
+    writeln!(out)?;
+
+    let (chip, lines): (_, Vec<(usize, &str)>) = {
+        let _guard = IRQ_LOCK.lock_guard_irq_save();
+        let actions = unsafe { &*core::ptr::addr_of!(IRQ_ACTIONS) };
+        let lines = actions.iter().enumerate()
+            .filter(|(_, action)| action.handler.is_some())
+            .map(|(irq, action)| (irq, action.name))
+            .collect();
+        (unsafe { IRQ_CHIP }.map_or("", |chip| chip.name()), lines)
+    };
+    for (irq, name) in lines {
+        write!(out, "{:>3}: ", irq)?;
+        for cpu in stats.iter() {
+            write!(out, "{:>10} ", cpu.lines[irq].load(Ordering::Relaxed))?;
+        }
+        writeln!(out, "{:>8} {:>4}  {}", chip, irq, name)?;
+    }
+
+    let locals: [(&str, fn(&IrqStats) -> &AtomicUsize, &str); 2] = [
+        ("LOC", |cpu| &cpu.timer, "Local timer interrupts"),
+        ("IPI", |cpu| &cpu.ipi, "Inter-processor interrupts"),
+    ];
+    for (name, count, desc) in locals {
+        write!(out, "{:>3}: ", name)?;
+        for cpu in stats.iter() {
+            write!(out, "{:>10} ", count(cpu).load(Ordering::Relaxed))?;
+        }
+        writeln!(out, "  {}", desc)?;
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::String;
+    use super::*;
+
+    /// Get the sum of the counts of the CPUs on the line `name` of `/proc/interrupts`.
+    fn total_count(name: &str) -> usize {
+        let mut out = String::new();
+        show_interrupts(&mut out).unwrap();
+        let line = out.lines().find(|line| line.trim_start().starts_with(name)).unwrap();
+        line.split_whitespace().skip(1).map_while(|count| count.parse::<usize>().ok()).sum()
+    }
+
+    #[kernel_test]
+    fn interrupt_counts() {
+        let mut out = String::new();
+        show_interrupts(&mut out).unwrap();
+        let nr_cpus = unsafe { (*core::ptr::addr_of!(IRQ_STATS)).as_array_mut().len() };
+        let header = out.lines().next().unwrap();
+        assert_eq!(header.len(), 11 + 11 * nr_cpus);
+        assert!(header.trim_start().starts_with("CPU0"));
+        for line in out.lines().skip(1) {
+            // The counts of all CPUs follow the name.
+            let counts = line.split_whitespace().skip(1)
+                .filter(|count| count.parse::<usize>().is_ok()).count();
+            assert!(counts >= nr_cpus, "{:?}", line);
+        }
+
+        let (timer, ipi) = (total_count("LOC:"), total_count("IPI:"));
+        count_timer_irq();
+        count_ipi();
+        count_ipi();
+        assert!(total_count("LOC:") > timer);
+        assert!(total_count("IPI:") >= ipi + 2);
+    }
+}
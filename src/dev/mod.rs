@@ -1,13 +1,164 @@
 //! Device definitions.
+//!
+//! The probed devices are kept in the global device list. A device with a device number has a
+//! device file in `/dev`, created when it is added and removed when it is deleted, see
+//! [`devtmpfs`](crate::fs::devtmpfs).
 
+pub mod irq;
 pub mod pm;
 
+use core::mem::size_of;
+use core::ptr::{null_mut, write};
+use fdt::node::FdtNode;
+use crate::base::sync::lock::SpinLockPure;
 use crate::driver::Driver;
+use crate::errno::E_NO_MEM;
+use crate::fs::devtmpfs;
+use crate::fs::inode::{InodeType, S_IALLUGO, S_IFCHR};
+use crate::mm::kzalloc;
+use crate::util::list::{self, List};
 
 
+/// Resources of a device parsed from the DeviceTree node.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct DeviceResource {
+    /// Base address of the first MMIO region (the first `reg` entry). 0 if not present.
+    pub mmio_base: usize,
+    /// Size of the first MMIO region.
+    pub mmio_size: usize,
+    /// The first interrupt number. 0 if not present.
+    pub irq: u32,
+}
+
 #[repr(C)]
 pub struct Device {
     pub(crate) init_name: &'static str,
     pub(crate) driver: Option<&'static dyn Driver>,
     pub driver_data: *mut (),
+    /// The `compatible` string that matched the driver.
+    pub(crate) compatible: &'static str,
+    pub resource: DeviceResource,
+    /// The DeviceTree node of the device.
+    pub(crate) of_node: Option<FdtNode<'static, 'static>>,
+    /// Device number of the device file, 0 if the device has no file.
+    pub devt: u64,
+    /// File type and permission bits of the device file.
+    pub devnode_mode: u32,
+    /// Entry of the global device list. Devices are linked in the probe order.
+    list: List,
+}
+
+impl Device {
+    /// Create a device object that has not been bound to a driver.
+    pub const fn new(init_name: &'static str, compatible: &'static str, resource: DeviceResource) -> Self {
+        Self {
+            init_name,
+            driver: None,
+            driver_data: null_mut(),
+            compatible,
+            resource,
+            of_node: None,
+            devt: 0,
+            devnode_mode: S_IFCHR | 0o600,
+            list: List::new(),
+        }
+    }
+
+    /// Get the device name.
+    #[inline(always)]
+    pub fn name(&self) -> &'static str {
+        self.init_name
+    }
+}
+
+
+/// All devices that have been successfully probed, in the probe order.
+static mut DEVICE_LIST: List = List::new();
+static DEVICE_LIST_LOCK: SpinLockPure = SpinLockPure::new();
+
+/// Init the device list and the device files. Must be called before any device is added.
+pub(crate) fn init() {
+    unsafe {
+        DEVICE_LIST.init_empty();
+    }
+    irq::init();
+    devtmpfs::init();
+}
+
+/// Add a probed device to the tail of the global device list, and create its device file.
+pub(crate) fn device_add(dev: &mut Device) {
+    {
+        let _guard = DEVICE_LIST_LOCK.lock_guard_irq_save();
+        list::tail_append(unsafe { &mut DEVICE_LIST }, &mut dev.list);
+    }
+    if dev.devt != 0 {
+        let kind = InodeType::from_mode(dev.devnode_mode).unwrap_or(InodeType::CharDevice);
+        let mode = dev.devnode_mode & S_IALLUGO;
+        if let Err(errno) = devtmpfs::create_node(dev.name(), kind, mode, dev.devt) {
+            warn!("Create the device file of {} failed, errno = {}", dev.name(), errno);
+        }
+    }
+}
+
+/// Remove a device from the global device list, and remove its device file.
+pub(crate) fn device_del(dev: &mut Device) {
+    {
+        let _guard = DEVICE_LIST_LOCK.lock_guard_irq_save();
+        list::delete_and_init_empty(&mut dev.list);
+    }
+    if dev.devt != 0 {
+        let _ = devtmpfs::delete_node(dev.name(), dev.devt);
+    }
+}
+
+/// Create and add a device which is not in the DeviceTree but only has a device file, e.g. a
+/// memory device. The `mode` is the file type and the permission bits of the device file of the
+/// `devt` number.
+pub(crate) fn device_create(name: &'static str, mode: u32, devt: u64) -> Result<(), i32> {
+    let dev = kzalloc(size_of::<Device>(), 0) as *mut Device;
+    if dev.is_null() {
+        return Err(E_NO_MEM);
+    }
+    let dev = unsafe {
+        write(dev, Device::new(name, "", DeviceResource::default()));
+        &mut *dev
+    };
+    dev.devt = devt;
+    dev.devnode_mode = mode;
+    device_add(dev);
+    Ok(())
+}
+
+/// Iterate the devices in the **reverse** probe order, so that a device is always visited before
+/// the devices it depends on. Break if `handle` returns `false`.
+///
+/// **Note**: the device list lock is not held when calling `handle`, the caller must make sure
+/// no device is added or removed during the iteration.
+pub(crate) fn for_each_device_reverse<F>(mut handle: F)
+    where F: FnMut(&mut Device) -> bool {
+    unsafe {
+        let head = &mut DEVICE_LIST as *mut List;
+        let mut cur = DEVICE_LIST.prev;
+        while cur != head {
+            let prev = (*cur).prev;
+            let dev = container_of_mut!(cur, Device, list);
+            if !handle(&mut *dev) {
+                break;
+            }
+            cur = prev;
+        }
+    }
+}
+
+/// Iterate the devices in the probe order. Break if `handle` returns `false`.
+///
+/// **Note**: the device list lock is not held when calling `handle`, the caller must make sure
+/// no device is added or removed during the iteration.
+pub(crate) fn for_each_device<F>(mut handle: F)
+    where F: FnMut(&mut Device) -> bool {
+    list::for_each(unsafe { &mut DEVICE_LIST }, |cur| {
+        let dev = unsafe { &mut *container_of_mut!(cur, Device, list) };
+        handle(dev)
+    });
 }
@@ -1,4 +1,26 @@
 //! Power management.
+//!
+//! Provides the system level power operations (power off, restart and halt) and the device
+//! level power management (suspend, resume and shutdown).
+//!
+//! The device callbacks are called in the **reverse** probe order on the shutdown and suspend
+//! path, so a device is always handled before the devices it depends on (for example, the bus
+//! controller); and in the probe order on the resume path.
+//!
+//! The final power operation is delegated to the handler registered by the platform driver
+//! (see [`register_power_off_handler`] and [`register_restart_handler`]). If no handler is
+//! registered or the handler returns, the firmware system reset call is tried.
+//!
+//! [`register_power_off_handler`]: register_power_off_handler
+//! [`register_restart_handler`]: register_restart_handler
+
+use core::num::NonZeroI32;
+use core::sync::atomic::{AtomicU8, Ordering};
+use crate::arch::{cpu, firmware::{self, ResetReason, ResetType}};
+use crate::base::irq;
+use crate::smp;
+use super::{Device, for_each_device, for_each_device_reverse};
+
 
 /// Power-management message.
 #[repr(C)]
@@ -14,3 +36,275 @@ impl PmMessage {
         }
     }
 }
+
+/// Power-management events, used as the `event` of [`PmMessage`].
+pub mod pm_event {
+    pub const ON: i32 = 0x0000;
+    pub const FREEZE: i32 = 0x0001;
+    pub const SUSPEND: i32 = 0x0002;
+    pub const HIBERNATE: i32 = 0x0004;
+    pub const QUIESCE: i32 = 0x0008;
+    pub const RESUME: i32 = 0x0010;
+}
+
+pub const PMSG_ON: PmMessage = PmMessage::new(pm_event::ON);
+pub const PMSG_FREEZE: PmMessage = PmMessage::new(pm_event::FREEZE);
+pub const PMSG_SUSPEND: PmMessage = PmMessage::new(pm_event::SUSPEND);
+pub const PMSG_HIBERNATE: PmMessage = PmMessage::new(pm_event::HIBERNATE);
+pub const PMSG_QUIESCE: PmMessage = PmMessage::new(pm_event::QUIESCE);
+pub const PMSG_RESUME: PmMessage = PmMessage::new(pm_event::RESUME);
+
+
+/// System state.
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum SystemState {
+    Running = 0,
+    Halt = 1,
+    PowerOff = 2,
+    Restart = 3,
+}
+
+static SYSTEM_STATE: AtomicU8 = AtomicU8::new(SystemState::Running as u8);
+
+/// Get current system state.
+pub fn system_state() -> SystemState {
+    match SYSTEM_STATE.load(Ordering::Acquire) {
+        1 => SystemState::Halt,
+        2 => SystemState::PowerOff,
+        3 => SystemState::Restart,
+        _ => SystemState::Running,
+    }
+}
+
+/// Power off handler. `exit_code` is reported to the host (for example, the exit status of the
+/// QEMU process) if the platform supports, 0 means success.
+pub type PowerOffHandler = fn(exit_code: u16);
+/// Restart handler.
+pub type RestartHandler = fn();
+
+static mut POWER_OFF_HANDLER: Option<PowerOffHandler> = None;
+static mut RESTART_HANDLER: Option<RestartHandler> = None;
+
+/// Register the platform power off handler. The last registered handler wins.
+pub fn register_power_off_handler(handler: PowerOffHandler) {
+    unsafe {
+        POWER_OFF_HANDLER = Some(handler);
+    }
+}
+
+/// Register the platform restart handler. The last registered handler wins.
+pub fn register_restart_handler(handler: RestartHandler) {
+    unsafe {
+        RESTART_HANDLER = Some(handler);
+    }
+}
+
+/// Unregister the power off handler if `handler` is the registered one.
+pub fn unregister_power_off_handler(handler: PowerOffHandler) {
+    unsafe {
+        if POWER_OFF_HANDLER.map(|f| f as usize) == Some(handler as usize) {
+            POWER_OFF_HANDLER = None;
+        }
+    }
+}
+
+/// Unregister the restart handler if `handler` is the registered one.
+pub fn unregister_restart_handler(handler: RestartHandler) {
+    unsafe {
+        if RESTART_HANDLER.map(|f| f as usize) == Some(handler as usize) {
+            RESTART_HANDLER = None;
+        }
+    }
+}
+
+
+//////////////////// Device PM ////////////////////////
+
+/// Call the `shutdown` callback of all devices in the reverse probe order.
+pub fn device_shutdown() {
+    for_each_device_reverse(|dev| {
+        if let Some(driver) = dev.driver {
+            debug!("Shutdown device: {}", dev.name());
+            driver.shutdown(dev);
+        }
+        true
+    });
+}
+
+/// Suspend all devices in the reverse probe order. If any device failed to suspend, the devices
+/// that have been suspended are resumed and the error is returned.
+pub fn dpm_suspend(state: PmMessage) -> Result<(), NonZeroI32> {
+    let mut failed: Option<(*const Device, NonZeroI32)> = None;
+    for_each_device_reverse(|dev| {
+        if let Some(driver) = dev.driver {
+            if let Err(e) = driver.suspend(dev, state) {
+                error!("Device {} failed to suspend, error: {}", dev.name(), e);
+                failed = Some((dev as *const Device, e));
+                return false;
+            }
+        }
+        true
+    });
+
+    match failed {
+        None => Ok(()),
+        Some((failed_dev, err)) => {
+            // Devices after the failed one (in probe order) have been suspended.
+            let mut passed = false;
+            for_each_device(|dev| {
+                if passed {
+                    if let Some(driver) = dev.driver {
+                        let _ = driver.resume(dev);
+                    }
+                } else if dev as *const Device == failed_dev {
+                    passed = true;
+                }
+                true
+            });
+            Err(err)
+        }
+    }
+}
+
+/// Resume all devices in the probe order. Errors are logged and ignored.
+pub fn dpm_resume() {
+    for_each_device(|dev| {
+        if let Some(driver) = dev.driver {
+            if let Err(e) = driver.resume(dev) {
+                error!("Device {} failed to resume, error: {}", dev.name(), e);
+            }
+        }
+        true
+    });
+}
+
+
+//////////////////// System PM ////////////////////////
+
+fn kernel_shutdown_prepare(state: SystemState) {
+    SYSTEM_STATE.store(state as u8, Ordering::Release);
+    device_shutdown();
+}
+
+/// Park the current CPU forever.
+fn machine_halt() -> ! {
+    irq::local_irq_disable();
+    loop {
+        cpu::wait_for_interrupt();
+    }
+}
+
+fn machine_power_off(exit_code: u16) -> ! {
+    smp::stop_other_cpus();
+    irq::local_irq_disable();
+
+    if let Some(handler) = unsafe { POWER_OFF_HANDLER } {
+        handler(exit_code);
+    }
+    let reason = if exit_code == 0 { ResetReason::NoReason } else { ResetReason::SystemFailure };
+    firmware::system_reset(ResetType::Shutdown, reason);
+
+    error!("Power off failed, system halted.");
+    machine_halt()
+}
+
+fn machine_restart() -> ! {
+    smp::stop_other_cpus();
+    irq::local_irq_disable();
+
+    if let Some(handler) = unsafe { RESTART_HANDLER } {
+        handler();
+    }
+    firmware::system_reset(ResetType::ColdReboot, ResetReason::NoReason);
+
+    error!("Restart failed, system halted.");
+    machine_halt()
+}
+
+/// Shutdown all devices, stop other CPUs and power off the system.
+pub fn kernel_power_off(exit_code: u16) -> ! {
+    kernel_shutdown_prepare(SystemState::PowerOff);
+    info!("Power down.");
+    machine_power_off(exit_code)
+}
+
+/// Shutdown all devices, stop other CPUs and restart the system.
+pub fn kernel_restart() -> ! {
+    kernel_shutdown_prepare(SystemState::Restart);
+    info!("Restarting system.");
+    machine_restart()
+}
+
+/// Shutdown all devices, stop other CPUs and halt the current CPU.
+pub fn kernel_halt() -> ! {
+    kernel_shutdown_prepare(SystemState::Halt);
+    smp::stop_other_cpus();
+    info!("System halted.");
+    machine_halt()
+}
+
+/// Power off without calling the device callbacks. Used on the panic path where the devices
+/// may be in a bad state.
+pub fn emergency_power_off(exit_code: u16) -> ! {
+    SYSTEM_STATE.store(SystemState::PowerOff as u8, Ordering::Release);
+    machine_power_off(exit_code)
+}
+
+/// Restart without calling the device callbacks. Used on the panic path.
+pub fn emergency_restart() -> ! {
+    SYSTEM_STATE.store(SystemState::Restart as u8, Ordering::Release);
+    machine_restart()
+}
+
+
+//////////////////// Panic Action ////////////////////////
+
+/// The action to take after a kernel panic. Set by the boot param `panic_action`.
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum PanicAction {
+    /// Halt the CPU, keep the machine running for debugging. This is the default action.
+    Halt = 0,
+    /// Power off the machine with a failure exit code, so an automated run can terminate.
+    PowerOff = 1,
+    /// Restart the machine.
+    Restart = 2,
+}
+
+/// Exit code reported to the host when the machine is powered off by a kernel panic.
+pub const PANIC_EXIT_CODE: u16 = 1;
+
+static PANIC_ACTION: AtomicU8 = AtomicU8::new(PanicAction::Halt as u8);
+
+/// Set the panic action from the value of the boot param `panic_action`: `halt`, `poweroff` or
+/// `reboot`.
+pub fn set_panic_action_param(val: &str) {
+    let action = match val {
+        "halt" => PanicAction::Halt,
+        "poweroff" => PanicAction::PowerOff,
+        "reboot" => PanicAction::Restart,
+        _ => {
+            warn!("Unknown panic action: {}", val);
+            return;
+        }
+    };
+    set_panic_action(action);
+}
+
+/// Set the panic action.
+pub fn set_panic_action(action: PanicAction) {
+    PANIC_ACTION.store(action as u8, Ordering::Release);
+}
+
+/// Do the configured panic action. Called by the panic handler.
+pub fn do_panic_action() -> ! {
+    match PANIC_ACTION.load(Ordering::Acquire) {
+        1 => emergency_power_off(PANIC_EXIT_CODE),
+        2 => emergency_restart(),
+        _ => {
+            smp::stop_other_cpus();
+            machine_halt()
+        }
+    }
+}
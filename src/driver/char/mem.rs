@@ -0,0 +1,112 @@
+//! Memory devices of the major number 1, like Linux:
+//!
+//! - `/dev/null` reads nothing and discards the writes.
+//! - `/dev/zero` reads zeros and discards the writes.
+//! - `/dev/full` reads zeros, and a write fails with `ENOSPC`.
+//! - `/dev/random` and `/dev/urandom` read the [`random`](super::random) bytes, and discard the
+//!   writes.
+
+use alloc::sync::Arc;
+use crate::dev;
+use crate::errno::{E_BAD_FD, E_NO_DEV_OR_ADDR, E_NO_SPACE};
+use crate::fs::char_dev::{register_chrdev, CharDevice};
+use crate::fs::file::{readable, writable, File};
+use crate::fs::inode::{make_dev, Stat, S_IFCHR};
+use super::random::get_random_bytes;
+
+
+const MEM_MAJOR: u32 = 1;
+
+/// Kind of a memory device.
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum MemKind {
+    Null,
+    Zero,
+    Full,
+    Random,
+}
+
+/// The memory devices: name, minor number and kind.
+const MEM_DEVICES: [(&str, u32, MemKind); 5] = [
+    ("null", 3, MemKind::Null),
+    ("zero", 5, MemKind::Zero),
+    ("full", 7, MemKind::Full),
+    ("random", 8, MemKind::Random),
+    ("urandom", 9, MemKind::Random),
+];
+
+/// An open memory device.
+struct MemFile {
+    kind: MemKind,
+    minor: u32,
+    flags: usize,
+}
+
+impl File for MemFile {
+    fn flags(&self) -> usize {
+        self.flags
+    }
+
+    fn stat(&self) -> Result<Stat, i32> {
+        Ok(Stat {
+            mode: S_IFCHR | 0o666,
+            nlink: 1,
+            rdev: make_dev(MEM_MAJOR, self.minor),
+            blksize: 4096,
+            ..Stat::default()
+        })
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize, i32> {
+        if !readable(self.flags) {
+            return Err(E_BAD_FD);
+        }
+        match self.kind {
+            MemKind::Null => return Ok(0),
+            MemKind::Zero | MemKind::Full => buf.fill(0),
+            MemKind::Random => get_random_bytes(buf),
+        }
+        Ok(buf.len())
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, i32> {
+        if !writable(self.flags) {
+            return Err(E_BAD_FD);
+        }
+        if self.kind == MemKind::Full && !buf.is_empty() {
+            return Err(E_NO_SPACE);
+        }
+        Ok(buf.len())
+    }
+
+    /// The devices have no position, the offset is always 0.
+    fn seek(&self, _offset: isize, _whence: usize) -> Result<usize, i32> {
+        Ok(0)
+    }
+}
+
+/// The driver of the memory devices.
+struct Mem;
+
+static MEM: Mem = Mem;
+
+impl CharDevice for Mem {
+    fn open(&self, minor: u32, flags: usize) -> Result<Arc<dyn File>, i32> {
+        let &(_, minor, kind) = MEM_DEVICES.iter().find(|dev| dev.1 == minor)
+            .ok_or(E_NO_DEV_OR_ADDR)?;
+        Ok(Arc::new(MemFile { kind, minor, flags }))
+    }
+}
+
+/// Register the memory devices and create their device files.
+pub(super) fn init() {
+    if let Err(errno) = register_chrdev(MEM_MAJOR, 0, 256, "mem", &MEM) {
+        warn!("Register the memory devices failed, errno = {}", errno);
+        return;
+    }
+    for (name, minor, _) in MEM_DEVICES {
+        if let Err(errno) = dev::device_create(name, S_IFCHR | 0o666, make_dev(MEM_MAJOR, minor)) {
+            warn!("Create the device {} failed, errno = {}", name, errno);
+        }
+    }
+}
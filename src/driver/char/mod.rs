@@ -0,0 +1,11 @@
+//! Character device drivers of the devices which are not in the DeviceTree.
+
+pub(crate) mod mem;
+pub(crate) mod random;
+
+
+/// Register the character devices and create their device files.
+pub(crate) fn init() {
+    mem::init();
+    crate::fs::console::init();
+}
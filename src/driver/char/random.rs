@@ -0,0 +1,31 @@
+//! Random numbers, for `/dev/random`, `/dev/urandom` and the `AT_RANDOM` bytes.
+//!
+//! There is no entropy source yet: the numbers are generated by SplitMix64, and the timer is
+//! mixed into each number so the sequence differs between the boots. They are not suitable for
+//! cryptography.
+
+use core::mem::size_of;
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::arch::cpu;
+
+
+/// Increment of the SplitMix64 state.
+const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+static STATE: AtomicU64 = AtomicU64::new(0);
+
+/// Get the next random number.
+fn next_u64() -> u64 {
+    let x = STATE.fetch_add(GAMMA, Ordering::Relaxed).wrapping_add(GAMMA);
+    let mut z = x ^ (cpu::read_time() as u64).rotate_left(32);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Fill `buf` with random bytes.
+pub fn get_random_bytes(buf: &mut [u8]) {
+    for chunk in buf.chunks_mut(size_of::<u64>()) {
+        chunk.copy_from_slice(&next_u64().to_le_bytes()[..chunk.len()]);
+    }
+}
@@ -0,0 +1,3 @@
+//! Interrupt controller drivers.
+
+pub(crate) mod plic;
@@ -0,0 +1,210 @@
+//! Driver of the RISC-V Platform-Level Interrupt Controller (PLIC, `riscv,plic0`). QEMU virt
+//! machine provides it at `0xc000000`.
+//!
+//! The PLIC has an interrupt context for each privilege mode of each hart, the contexts are
+//! listed by the `interrupts-extended` property of the node as the pairs of the hart interrupt
+//! controller phandle and the cause (`9` for the supervisor external interrupt). The registers:
+//!
+//! | Offset | Description |
+//! | ------ | ----------- |
+//! | `0x000000 + 4 * irq` | Priority of the source, `0` disables it. |
+//! | `0x002000 + 0x80 * ctx` | Enable bits of the sources for the context. |
+//! | `0x200000 + 0x1000 * ctx` | Priority threshold of the context. |
+//! | `0x200004 + 0x1000 * ctx` | Claim (read) and complete (write) of the context. |
+//!
+//! All sources are routed to the supervisor context of the hart that probes the PLIC (the boot
+//! hart).
+
+use alloc::vec::Vec;
+use core::num::NonZeroI32;
+use core::ptr::null_mut;
+use crate::dev::Device;
+use crate::dev::irq::{self, IrqChip, NR_IRQS};
+use crate::errno::{E_INVALID, E_NO_DEV};
+use crate::smp::current_cpu_info;
+use super::super::{Metadata, Driver};
+use super::super::of::DeviceId;
+
+
+const PRIORITY_BASE: usize = 0x0;
+const ENABLE_BASE: usize = 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+const CONTEXT_BASE: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+const CONTEXT_THRESHOLD: usize = 0x0;
+const CONTEXT_CLAIM: usize = 0x4;
+
+/// Cause of the supervisor external interrupt in the `interrupts-extended` property.
+const IRQ_S_EXT: u32 = 9;
+
+struct Plic {
+    base: usize,
+    /// Count of the interrupt sources.
+    ndev: u32,
+    /// Supervisor context of each hart, indexed by the hart id.
+    contexts: Vec<Option<u32>>,
+    /// Context which all sources are routed to.
+    target: u32,
+}
+
+static mut PLIC: Plic = Plic { base: 0, ndev: 0, contexts: Vec::new(), target: 0 };
+
+impl Plic {
+    #[inline(always)]
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ((self.base + offset) as *const u32).read_volatile() }
+    }
+
+    #[inline(always)]
+    fn write(&self, offset: usize, val: u32) {
+        unsafe { ((self.base + offset) as *mut u32).write_volatile(val) }
+    }
+
+    fn set_enable(&self, ctx: u32, irq: u32, enable: bool) {
+        let offset = ENABLE_BASE + ENABLE_STRIDE * ctx as usize + (irq as usize / 32) * 4;
+        let bit = 1u32 << (irq % 32);
+        let val = self.read(offset);
+        self.write(offset, if enable { val | bit } else { val & !bit });
+    }
+
+    /// Get the supervisor context of the current hart.
+    fn current_context(&self) -> Option<u32> {
+        let hart = current_cpu_info().get_hart_id();
+        self.contexts.get(hart).copied().flatten()
+    }
+
+    fn valid_irq(&self, irq: u32) -> bool {
+        irq != 0 && irq <= self.ndev
+    }
+}
+
+impl IrqChip for Plic {
+    fn name(&self) -> &'static str {
+        "plic"
+    }
+
+    fn enable(&self, irq: u32) {
+        if self.valid_irq(irq) {
+            self.write(PRIORITY_BASE + 4 * irq as usize, 1);
+            self.set_enable(self.target, irq, true);
+        }
+    }
+
+    fn disable(&self, irq: u32) {
+        if self.valid_irq(irq) {
+            self.set_enable(self.target, irq, false);
+        }
+    }
+
+    fn claim(&self) -> Option<u32> {
+        let ctx = self.current_context()?;
+        match self.read(CONTEXT_BASE + CONTEXT_STRIDE * ctx as usize + CONTEXT_CLAIM) {
+            0 => None,
+            irq => Some(irq),
+        }
+    }
+
+    fn complete(&self, irq: u32) {
+        if let Some(ctx) = self.current_context() {
+            self.write(CONTEXT_BASE + CONTEXT_STRIDE * ctx as usize + CONTEXT_CLAIM, irq);
+        }
+    }
+}
+
+/// Find the hart id of the CPU whose interrupt controller node has the `phandle`.
+fn phandle_to_hart(phandle: u32) -> Option<usize> {
+    let cpus = crate::init::device_tree().find_node("/cpus")?;
+    for cpu in cpus.children() {
+        for intc in cpu.children() {
+            if intc.property("phandle").and_then(|p| p.as_usize()) == Some(phandle as usize) {
+                return cpu.property("reg").and_then(|p| p.as_usize());
+            }
+        }
+    }
+    None
+}
+
+/// Parse the `interrupts-extended` property to the supervisor context of each hart.
+fn parse_contexts(dev: &Device) -> Vec<Option<u32>> {
+    let mut contexts = Vec::new();
+    let prop = dev.of_node.and_then(|node| node.property("interrupts-extended"));
+    let cells = prop.map_or(&[][..], |prop| prop.value);
+    for (ctx, pair) in cells.chunks_exact(8).enumerate() {
+        let phandle = u32::from_be_bytes([pair[0], pair[1], pair[2], pair[3]]);
+        let cause = u32::from_be_bytes([pair[4], pair[5], pair[6], pair[7]]);
+        if cause != IRQ_S_EXT {
+            continue;
+        }
+        if let Some(hart) = phandle_to_hart(phandle) {
+            if contexts.len() <= hart {
+                contexts.resize(hart + 1, None);
+            }
+            contexts[hart] = Some(ctx as u32);
+        }
+    }
+    contexts
+}
+
+struct PlicDriver {
+    metadata: Metadata,
+    match_table: &'static [DeviceId],
+}
+
+impl Driver for PlicDriver {
+    fn get_metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    fn get_match_table(&self) -> Option<&[DeviceId]> {
+        Some(self.match_table)
+    }
+
+    fn probe(&self, dev: &mut Device) -> Result<(), NonZeroI32> {
+        let base = dev.resource.mmio_base;
+        if base == 0 {
+            return Err(NonZeroI32::new(-E_INVALID).unwrap());
+        }
+        let ndev = dev.of_node
+            .and_then(|node| node.property("riscv,ndev"))
+            .and_then(|prop| prop.as_usize())
+            .unwrap_or(NR_IRQS - 1)
+            .min(NR_IRQS - 1) as u32;
+        let contexts = parse_contexts(dev);
+        let hart = current_cpu_info().get_hart_id();
+        let target = match contexts.get(hart).copied().flatten() {
+            Some(ctx) => ctx,
+            None => {
+                warn!("PLIC @{:#x} has no supervisor context for hart #{}", base, hart);
+                return Err(NonZeroI32::new(-E_NO_DEV).unwrap());
+            }
+        };
+
+        let plic = unsafe { &mut PLIC };
+        *plic = Plic { base, ndev, contexts, target };
+        // Disable all sources of the target context, and accept any priority.
+        for irq in 1..=ndev {
+            plic.set_enable(target, irq, false);
+        }
+        plic.write(CONTEXT_BASE + CONTEXT_STRIDE * target as usize + CONTEXT_THRESHOLD, 0);
+        irq::register_irq_chip(unsafe { &PLIC });
+        info!("PLIC @{:#x}, {} sources, routed to context {}", base, ndev, target);
+        Ok(())
+    }
+
+    fn remove(&self, dev: &mut Device) -> Result<(), NonZeroI32> {
+        dev.driver_data = null_mut();
+        Ok(())
+    }
+}
+
+static PLIC_DRIVER: PlicDriver = PlicDriver {
+    metadata: Metadata::with_name("plic"),
+    match_table: &[
+        DeviceId::with_compat("riscv,plic0"),
+        DeviceId::with_compat("sifive,plic-1.0.0"),
+    ],
+};
+
+pub fn export_driver() -> &'static dyn Driver {
+    &PLIC_DRIVER
+}
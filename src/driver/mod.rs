@@ -1,10 +1,20 @@
 pub(crate) mod boot;
+pub(crate) mod char;
+pub(crate) mod irqchip;
 pub(crate) mod of;
 pub(crate) mod uart;
 pub(crate) mod cpu;
+pub(crate) mod power;
+pub(crate) mod virtio;
 
+use core::mem::size_of;
 use core::num::NonZeroI32;
-use crate::dev::{Device, pm::PmMessage};
+use core::ptr::write;
+use fdt::Fdt;
+use fdt::node::FdtNode;
+use crate::dev::{self, Device, DeviceResource, pm::PmMessage};
+use crate::errno::E_NO_DEV;
+use crate::mm::{kfree, kzalloc};
 
 
 pub struct Metadata {
@@ -49,3 +59,91 @@ pub trait Driver {
         Ok(())
     }
 }
+
+
+/// All built-in drivers. Probed in this order for each DeviceTree node.
+static BUILTIN_DRIVERS: [fn() -> &'static dyn Driver; 4] = [
+    cpu::export_driver,
+    power::sifive_test::export_driver,
+    irqchip::plic::export_driver,
+    virtio::mmio::export_driver,
+];
+
+/// Find the first entry of the `table` that matches the `node`. Returns the matched compatible
+/// string (empty if matched by the `name` or `ty` only).
+fn match_node(table: &[of::DeviceId], node: &FdtNode<'_, 'static>) -> Option<&'static str> {
+    let node_name = node.name.split('@').next().unwrap_or("");
+    let node_type = node.property("device_type")
+        .and_then(|p| p.as_str())
+        .unwrap_or("");
+    for id in table {
+        if !id.name.is_empty() && id.name != node_name {
+            continue;
+        }
+        if !id.ty.is_empty() && id.ty != node_type {
+            continue;
+        }
+        if id.compatible.is_empty() {
+            return Some("");
+        }
+        if let Some(compat) = node.compatible() {
+            if let Some(c) = compat.all().find(|c| *c == id.compatible) {
+                return Some(c);
+            }
+        }
+    }
+    None
+}
+
+/// Collect the resources of the `node`.
+fn node_resource(node: &FdtNode<'_, 'static>) -> DeviceResource {
+    let mut res = DeviceResource::default();
+    if let Some(region) = node.reg().and_then(|mut r| r.next()) {
+        res.mmio_base = region.starting_address as usize;
+        res.mmio_size = region.size.unwrap_or_default();
+    }
+    if let Some(irq) = node.interrupts().and_then(|mut i| i.next()) {
+        res.irq = irq as u32;
+    }
+    res
+}
+
+/// Walk all nodes of the DeviceTree, bind each node to the first matched built-in driver and
+/// probe. The successfully probed devices are added to the global device list.
+pub(crate) fn probe_device_tree(fdt: &'static Fdt<'static>) {
+    for node in fdt.all_nodes() {
+        for export in BUILTIN_DRIVERS {
+            let driver = export();
+            let matched = match driver.get_match_table().and_then(|t| match_node(t, &node)) {
+                Some(compat) => compat,
+                None => continue,
+            };
+
+            let dev = kzalloc(size_of::<Device>(), 0) as *mut Device;
+            assert!(!dev.is_null(), "Out of memory when creating device {}", node.name);
+            let dev = unsafe {
+                write(dev, Device::new(node.name, matched, node_resource(&node)));
+                &mut *dev
+            };
+            dev.of_node = Some(node);
+            dev.driver = Some(driver);
+            match driver.probe(dev) {
+                Ok(_) => {
+                    debug!("Device {} bound to driver {}", node.name, driver.get_metadata().name);
+                    dev::device_add(dev);
+                }
+                Err(e) if e.get() == -E_NO_DEV => {
+                    // The node has no device behind, e.g. an empty virtio-mmio slot.
+                    debug!("No device {} for driver {}", node.name, driver.get_metadata().name);
+                    kfree(dev as *mut Device as *mut u8);
+                }
+                Err(e) => {
+                    warn!("Driver {} failed to probe {}, error: {}",
+                        driver.get_metadata().name, node.name, e);
+                    kfree(dev as *mut Device as *mut u8);
+                }
+            }
+            break;
+        }
+    }
+}
@@ -0,0 +1,3 @@
+//! Platform power control drivers.
+
+pub(crate) mod sifive_test;
@@ -0,0 +1,95 @@
+//! Driver of the SiFive test finisher (`sifive,test0`). QEMU virt machine provides this device
+//! at `0x100000` to power off or reset the machine.
+//!
+//! Write a 32-bit value to the register:
+//!
+//! | Value | Description |
+//! | ----- | ----------- |
+//! | `0x5555` | Power off, QEMU exits with status 0. |
+//! | `(code << 16) \| 0x3333` | Power off, QEMU exits with status `code`. |
+//! | `0x7777` | Reset the machine. |
+
+use core::num::NonZeroI32;
+use core::ptr::null_mut;
+use crate::dev::Device;
+use crate::dev::pm;
+use crate::errno::E_INVALID;
+use super::super::{Metadata, Driver};
+use super::super::of::DeviceId;
+
+
+const FINISHER_FAIL: u32 = 0x3333;
+const FINISHER_PASS: u32 = 0x5555;
+const FINISHER_RESET: u32 = 0x7777;
+
+/// MMIO base address of the probed device.
+static mut TEST_BASE: usize = 0;
+
+fn write_finisher(val: u32) {
+    unsafe {
+        if TEST_BASE != 0 {
+            (TEST_BASE as *mut u32).write_volatile(val);
+        }
+    }
+}
+
+fn power_off(exit_code: u16) {
+    if exit_code == 0 {
+        write_finisher(FINISHER_PASS);
+    } else {
+        write_finisher(((exit_code as u32) << 16) | FINISHER_FAIL);
+    }
+}
+
+fn restart() {
+    write_finisher(FINISHER_RESET);
+}
+
+struct SifiveTestDriver {
+    metadata: Metadata,
+    match_table: &'static [DeviceId],
+}
+
+impl Driver for SifiveTestDriver {
+    fn get_metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    fn get_match_table(&self) -> Option<&[DeviceId]> {
+        Some(self.match_table)
+    }
+
+    fn probe(&self, dev: &mut Device) -> Result<(), NonZeroI32> {
+        let base = dev.resource.mmio_base;
+        if base == 0 {
+            return Err(NonZeroI32::new(-E_INVALID).unwrap());
+        }
+
+        unsafe {
+            TEST_BASE = base;
+        }
+        pm::register_power_off_handler(power_off);
+        pm::register_restart_handler(restart);
+        info!("sifive,test0 power controller @{:#x}", base);
+        Ok(())
+    }
+
+    fn remove(&self, dev: &mut Device) -> Result<(), NonZeroI32> {
+        pm::unregister_power_off_handler(power_off);
+        pm::unregister_restart_handler(restart);
+        unsafe {
+            TEST_BASE = 0;
+        }
+        dev.driver_data = null_mut();
+        Ok(())
+    }
+}
+
+static SIFIVE_TEST_DRIVER: SifiveTestDriver = SifiveTestDriver {
+    metadata: Metadata::with_name("sifive-test"),
+    match_table: &[DeviceId::with_compat("sifive,test0")],
+};
+
+pub fn export_driver() -> &'static dyn Driver {
+    &SIFIVE_TEST_DRIVER
+}
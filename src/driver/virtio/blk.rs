@@ -0,0 +1,301 @@
+//! VirtIO block device driver (virtio-blk).
+//!
+//! The device has one request queue. A request is a chain of the device-readable header (the
+//! type and the sector), the data buffers (device-writable for a read), and the device-writable
+//! status byte. Each bio of a block request is a data buffer, so the merged bios go in one
+//! device request.
+//!
+//! The disks are named `vda`, `vdb`, ..., and each takes [`VIRTBLK_MINORS`] minor numbers of the
+//! major [`VIRTBLK_MAJOR`] for itself and its partitions, the same as Linux.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicU32, Ordering};
+use crate::block::{self, BlockDevice, SECTOR_SIZE};
+use crate::block::request::{BlockOp, QueueLimits, Request, RequestQueue};
+use crate::errno::{E_IO, E_NO_SPACE};
+use super::{VirtioDevice, VirtioDriver, VIRTIO_ID_BLOCK};
+use super::queue::{Buffer, VIRTQ_MAX_SIZE};
+
+
+/// Major device number of the virtio-blk disks.
+pub const VIRTBLK_MAJOR: u32 = 254;
+/// Count of the minor numbers of a disk: the whole disk and 15 partitions.
+pub const VIRTBLK_MINORS: u32 = 16;
+
+// Feature bits.
+/// Max size of a segment is in `size_max`.
+const VIRTIO_BLK_F_SIZE_MAX: u32 = 1;
+/// Max count of the segments of a request is in `seg_max`.
+const VIRTIO_BLK_F_SEG_MAX: u32 = 2;
+/// The device is read-only.
+const VIRTIO_BLK_F_RO: u32 = 5;
+/// The device supports the flush request.
+const VIRTIO_BLK_F_FLUSH: u32 = 9;
+
+// Offsets of the configuration.
+const CONFIG_CAPACITY: usize = 0;
+const CONFIG_SIZE_MAX: usize = 8;
+const CONFIG_SEG_MAX: usize = 12;
+
+// Request types.
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_T_FLUSH: u32 = 4;
+
+// Request status.
+const VIRTIO_BLK_S_OK: u8 = 0;
+const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+
+/// Index of the request queue.
+const REQUEST_QUEUE: u16 = 0;
+/// Max count of the sectors of a request.
+const MAX_SECTORS: u64 = 1024;
+
+/// Header of a request.
+#[repr(C)]
+struct VirtioBlkReqHeader {
+    kind: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+/// A request in the device. The header and the status must stay at the same address until the
+/// device finishes it, so it is boxed.
+struct InflightRequest {
+    header: VirtioBlkReqHeader,
+    status: u8,
+    rq: Box<Request>,
+}
+
+/// Index of the next disk.
+static NEXT_INDEX: AtomicU32 = AtomicU32::new(0);
+
+/// A virtio-blk disk.
+struct VirtioBlk {
+    vdev: *mut VirtioDevice,
+    name: String,
+    index: u32,
+    capacity: u64,
+    read_only: bool,
+    flush: bool,
+    queue: RequestQueue,
+}
+
+impl VirtioBlk {
+    #[inline(always)]
+    fn vdev(&self) -> &VirtioDevice {
+        unsafe { &*self.vdev }
+    }
+
+    /// Finish the requests done by the device, and restart the queue if any.
+    fn complete(&self) {
+        let Some(vq) = self.vdev().queue(REQUEST_QUEUE) else {
+            return;
+        };
+        let mut done = false;
+        while let Some((token, _len)) = vq.pop_used() {
+            let inflight = unsafe { Box::from_raw(token as *mut InflightRequest) };
+            let result = match inflight.status {
+                VIRTIO_BLK_S_OK => Ok(()),
+                status => {
+                    warn!("{}: request {:?} of sector {} failed, status {}", self.name,
+                        inflight.rq.op(), inflight.rq.sector(), status);
+                    Err(E_IO)
+                }
+            };
+            inflight.rq.end(result);
+            done = true;
+        }
+        if done {
+            self.queue.restart(self);
+        }
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn major(&self) -> u32 {
+        VIRTBLK_MAJOR
+    }
+
+    fn first_minor(&self) -> u32 {
+        self.index * VIRTBLK_MINORS
+    }
+
+    fn minors(&self) -> u32 {
+        VIRTBLK_MINORS
+    }
+
+    fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn queue(&self) -> &RequestQueue {
+        &self.queue
+    }
+
+    fn queue_rq(&self, rq: Box<Request>) -> Result<(), Box<Request>> {
+        let kind = match rq.op() {
+            BlockOp::Read => VIRTIO_BLK_T_IN,
+            BlockOp::Write => VIRTIO_BLK_T_OUT,
+            // Without the flush feature, the device has no volatile cache.
+            BlockOp::Flush if !self.flush => {
+                rq.end(Ok(()));
+                return Ok(());
+            }
+            BlockOp::Flush => VIRTIO_BLK_T_FLUSH,
+        };
+        let sector = if rq.op() == BlockOp::Flush { 0 } else { rq.sector() };
+        let inflight = Box::new(InflightRequest {
+            header: VirtioBlkReqHeader { kind, reserved: 0, sector },
+            status: VIRTIO_BLK_S_UNSUPP,
+            rq,
+        });
+
+        let header_len = core::mem::size_of::<VirtioBlkReqHeader>() as u32;
+        let mut bufs = Vec::with_capacity(inflight.rq.bios().len() + 2);
+        bufs.push(Buffer::readable(&inflight.header as *const _ as usize, header_len));
+        if inflight.rq.op() != BlockOp::Flush {
+            let writable = inflight.rq.op() == BlockOp::Read;
+            for bio in inflight.rq.bios() {
+                bufs.push(Buffer { addr: bio.buf as usize, len: bio.len as u32, writable });
+            }
+        }
+        bufs.push(Buffer::writable(&inflight.status as *const u8 as usize, 1));
+
+        let vdev = self.vdev();
+        let vq = vdev.queue(REQUEST_QUEUE).expect("virtio-blk request queue is not set up");
+        let token = Box::into_raw(inflight);
+        match vq.add_buf(&bufs, token as usize) {
+            Ok(()) => {
+                vdev.kick(REQUEST_QUEUE);
+                Ok(())
+            }
+            Err(E_NO_SPACE) if vq.num_free() < vq.size() => {
+                // Full, retry when a request finishes.
+                Err(unsafe { Box::from_raw(token) }.rq)
+            }
+            Err(errno) => {
+                let inflight = unsafe { Box::from_raw(token) };
+                warn!("{}: add request failed, errno = {}", self.name, errno);
+                inflight.rq.end(Err(E_IO));
+                Ok(())
+            }
+        }
+    }
+
+    fn poll(&self) {
+        self.complete();
+    }
+}
+
+struct VirtioBlkDriver;
+
+impl VirtioBlkDriver {
+    fn read_config_u32(dev: &VirtioDevice, offset: usize) -> u32 {
+        let mut buf = [0u8; 4];
+        dev.read_config(offset, &mut buf);
+        u32::from_le_bytes(buf)
+    }
+}
+
+impl VirtioDriver for VirtioBlkDriver {
+    fn name(&self) -> &'static str {
+        "virtio-blk"
+    }
+
+    fn device_id(&self) -> u32 {
+        VIRTIO_ID_BLOCK
+    }
+
+    fn features(&self) -> u64 {
+        [VIRTIO_BLK_F_SIZE_MAX, VIRTIO_BLK_F_SEG_MAX, VIRTIO_BLK_F_RO, VIRTIO_BLK_F_FLUSH]
+            .iter()
+            .fold(0, |features, bit| features | 1u64 << bit)
+    }
+
+    fn probe(&self, dev: &mut VirtioDevice) -> Result<(), i32> {
+        let mut capacity = [0u8; 8];
+        dev.read_config(CONFIG_CAPACITY, &mut capacity);
+        let capacity = u64::from_le_bytes(capacity);
+
+        dev.setup_queue(REQUEST_QUEUE, VIRTQ_MAX_SIZE)?;
+        let queue_size = dev.queue(REQUEST_QUEUE).map_or(0, |vq| vq.size()) as usize;
+        // The header and the status take 2 descriptors.
+        let mut max_segments = queue_size.saturating_sub(2).max(1);
+        if dev.has_feature(VIRTIO_BLK_F_SEG_MAX) {
+            let seg_max = Self::read_config_u32(dev, CONFIG_SEG_MAX) as usize;
+            if seg_max > 0 {
+                max_segments = max_segments.min(seg_max);
+            }
+        }
+        // A segment of one bio may be as large as a request.
+        let mut max_sectors = MAX_SECTORS;
+        if dev.has_feature(VIRTIO_BLK_F_SIZE_MAX) {
+            let size_max = Self::read_config_u32(dev, CONFIG_SIZE_MAX) as u64;
+            if size_max >= SECTOR_SIZE as u64 {
+                max_sectors = max_sectors.min(size_max / SECTOR_SIZE as u64);
+            }
+        }
+
+        let index = NEXT_INDEX.fetch_add(1, Ordering::Relaxed);
+        if index >= 26 {
+            warn!("Too many virtio-blk disks, disk #{} ignored.", index);
+            return Err(E_NO_SPACE);
+        }
+        let mut name = String::from("vd");
+        name.push((b'a' + index as u8) as char);
+        let blk = Arc::new(VirtioBlk {
+            vdev: dev as *mut VirtioDevice,
+            name,
+            index,
+            capacity,
+            read_only: dev.has_feature(VIRTIO_BLK_F_RO),
+            flush: dev.has_feature(VIRTIO_BLK_F_FLUSH),
+            queue: RequestQueue::new(QueueLimits { max_sectors, max_segments }),
+        });
+        dev.driver_data = Arc::into_raw(blk.clone()) as *mut ();
+
+        // The partition table is read when registering.
+        dev.set_ready();
+        if let Err(errno) = block::register_disk(blk) {
+            unsafe { drop(Arc::from_raw(dev.driver_data as *const VirtioBlk)) };
+            dev.driver_data = null_mut();
+            return Err(errno);
+        }
+        Ok(())
+    }
+
+    fn remove(&self, dev: &mut VirtioDevice) {
+        if dev.driver_data.is_null() {
+            return;
+        }
+        let blk = unsafe { Arc::from_raw(dev.driver_data as *const VirtioBlk) };
+        dev.driver_data = null_mut();
+        block::unregister_disk(VIRTBLK_MAJOR, blk.first_minor());
+    }
+
+    fn handle_queue(&self, dev: &mut VirtioDevice, _index: u16) {
+        if !dev.driver_data.is_null() {
+            let blk = unsafe { &*(dev.driver_data as *const VirtioBlk) };
+            blk.complete();
+        }
+    }
+}
+
+static VIRTIO_BLK_DRIVER: VirtioBlkDriver = VirtioBlkDriver;
+
+pub fn export_driver() -> &'static dyn VirtioDriver {
+    &VIRTIO_BLK_DRIVER
+}
@@ -0,0 +1,295 @@
+//! VirtIO MMIO transport (`virtio,mmio`). QEMU virt machine provides 8 slots from `0x10001000`,
+//! each `0x1000` bytes, a slot without a device reads the device id 0.
+//!
+//! Both the modern interface (version 2, VirtIO 1.x) and the legacy interface (version 1, the
+//! QEMU default unless `-global virtio-mmio.force-legacy=false`) are supported. The legacy one
+//! sets a queue by the page frame number of its rings, and has no `FEATURES_OK` step.
+
+use core::num::NonZeroI32;
+use core::ptr::write;
+use crate::dev::Device;
+use crate::dev::irq;
+use crate::errno::{E_INVALID, E_NO_DEV, E_NO_MEM};
+use crate::mm::{kfree, kzalloc, PAGE_ORDER, PAGE_SIZE};
+use super::{find_driver, VirtioDevice, VIRTIO_STATUS_ACKNOWLEDGE, VIRTIO_STATUS_FAILED};
+use super::queue::VirtQueue;
+use super::super::{Metadata, Driver};
+use super::super::of::DeviceId;
+
+
+/// Little-endian "virt".
+const VIRTIO_MMIO_MAGIC: u32 = 0x7472_6976;
+
+// Register offsets.
+const MAGIC_VALUE: usize = 0x000;
+const VERSION: usize = 0x004;
+const DEVICE_ID: usize = 0x008;
+const VENDOR_ID: usize = 0x00c;
+const DEVICE_FEATURES: usize = 0x010;
+const DEVICE_FEATURES_SEL: usize = 0x014;
+const DRIVER_FEATURES: usize = 0x020;
+const DRIVER_FEATURES_SEL: usize = 0x024;
+/// Legacy only.
+const GUEST_PAGE_SIZE: usize = 0x028;
+const QUEUE_SEL: usize = 0x030;
+const QUEUE_NUM_MAX: usize = 0x034;
+const QUEUE_NUM: usize = 0x038;
+/// Legacy only.
+const QUEUE_ALIGN: usize = 0x03c;
+/// Legacy only.
+const QUEUE_PFN: usize = 0x040;
+const QUEUE_READY: usize = 0x044;
+const QUEUE_NOTIFY: usize = 0x050;
+const INTERRUPT_STATUS: usize = 0x060;
+const INTERRUPT_ACK: usize = 0x064;
+const STATUS: usize = 0x070;
+const QUEUE_DESC_LOW: usize = 0x080;
+const QUEUE_DESC_HIGH: usize = 0x084;
+const QUEUE_DRIVER_LOW: usize = 0x090;
+const QUEUE_DRIVER_HIGH: usize = 0x094;
+const QUEUE_DEVICE_LOW: usize = 0x0a0;
+const QUEUE_DEVICE_HIGH: usize = 0x0a4;
+const CONFIG_GENERATION: usize = 0x0fc;
+const CONFIG: usize = 0x100;
+
+/// Registers of a VirtIO MMIO device.
+pub struct VirtioMmio {
+    base: usize,
+    version: u32,
+}
+
+impl VirtioMmio {
+    #[inline(always)]
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ((self.base + offset) as *const u32).read_volatile() }
+    }
+
+    #[inline(always)]
+    fn write(&self, offset: usize, val: u32) {
+        unsafe { ((self.base + offset) as *mut u32).write_volatile(val) }
+    }
+
+    /// Check if the device uses the legacy interface.
+    #[inline(always)]
+    pub fn is_legacy(&self) -> bool {
+        self.version == 1
+    }
+
+    pub fn device_id(&self) -> u32 {
+        self.read(DEVICE_ID)
+    }
+
+    pub fn vendor_id(&self) -> u32 {
+        self.read(VENDOR_ID)
+    }
+
+    pub fn status(&self) -> u32 {
+        self.read(STATUS)
+    }
+
+    pub fn set_status(&self, status: u32) {
+        self.write(STATUS, status);
+    }
+
+    /// Reset the device, which also resets the queues.
+    pub fn reset(&self) {
+        self.write(STATUS, 0);
+    }
+
+    /// Read the feature bits offered by the device. The legacy device has only 32 bits.
+    pub fn device_features(&self) -> u64 {
+        self.write(DEVICE_FEATURES_SEL, 0);
+        let low = self.read(DEVICE_FEATURES) as u64;
+        if self.is_legacy() {
+            return low;
+        }
+        self.write(DEVICE_FEATURES_SEL, 1);
+        low | (self.read(DEVICE_FEATURES) as u64) << 32
+    }
+
+    /// Write the feature bits accepted by the driver.
+    pub fn set_driver_features(&self, features: u64) {
+        self.write(DRIVER_FEATURES_SEL, 0);
+        self.write(DRIVER_FEATURES, features as u32);
+        if !self.is_legacy() {
+            self.write(DRIVER_FEATURES_SEL, 1);
+            self.write(DRIVER_FEATURES, (features >> 32) as u32);
+        }
+    }
+
+    /// Get the max size of the queue `index`, 0 if the queue is not available.
+    pub fn queue_max_size(&self, index: u16) -> u16 {
+        self.write(QUEUE_SEL, index as u32);
+        self.read(QUEUE_NUM_MAX).min(u16::MAX as u32) as u16
+    }
+
+    /// Tell the device the rings of the `queue` and make it ready.
+    pub fn setup_queue(&self, queue: &VirtQueue) {
+        self.write(QUEUE_SEL, queue.index() as u32);
+        self.write(QUEUE_NUM, queue.size() as u32);
+        if self.is_legacy() {
+            self.write(QUEUE_ALIGN, PAGE_SIZE as u32);
+            self.write(QUEUE_PFN, (queue.desc_addr() >> PAGE_ORDER) as u32);
+            return;
+        }
+
+        let (desc, avail, used) = (queue.desc_addr(), queue.avail_addr(), queue.used_addr());
+        self.write(QUEUE_DESC_LOW, desc as u32);
+        self.write(QUEUE_DESC_HIGH, (desc >> 32) as u32);
+        self.write(QUEUE_DRIVER_LOW, avail as u32);
+        self.write(QUEUE_DRIVER_HIGH, (avail >> 32) as u32);
+        self.write(QUEUE_DEVICE_LOW, used as u32);
+        self.write(QUEUE_DEVICE_HIGH, (used >> 32) as u32);
+        self.write(QUEUE_READY, 1);
+    }
+
+    /// Stop the device from using the queue `index`.
+    pub fn disable_queue(&self, index: u16) {
+        self.write(QUEUE_SEL, index as u32);
+        if self.is_legacy() {
+            self.write(QUEUE_PFN, 0);
+        } else {
+            self.write(QUEUE_READY, 0);
+        }
+    }
+
+    /// Notify the device of the new buffers of the queue `index`.
+    pub fn notify(&self, index: u16) {
+        self.write(QUEUE_NOTIFY, index as u32);
+    }
+
+    /// Read and acknowledge the interrupt status: bit 0 for the used buffers, bit 1 for the
+    /// configuration change.
+    pub fn ack_interrupt(&self) -> u32 {
+        let status = self.read(INTERRUPT_STATUS);
+        self.write(INTERRUPT_ACK, status);
+        status
+    }
+
+    /// Read `buf.len()` bytes of the device configuration at `offset`. The read is retried if the
+    /// device changes the configuration meanwhile.
+    pub fn read_config(&self, offset: usize, buf: &mut [u8]) {
+        loop {
+            let generation = if self.is_legacy() { 0 } else { self.read(CONFIG_GENERATION) };
+            for (idx, byte) in buf.iter_mut().enumerate() {
+                let addr = self.base + CONFIG + offset + idx;
+                *byte = unsafe { (addr as *const u8).read_volatile() };
+            }
+            if self.is_legacy() || generation == self.read(CONFIG_GENERATION) {
+                break;
+            }
+        }
+    }
+}
+
+/// Interrupt handler of a VirtIO MMIO device.
+fn virtio_mmio_irq(_irq: u32, data: *mut ()) {
+    let dev = unsafe { &mut *(data as *mut VirtioDevice) };
+    let status = dev.transport().ack_interrupt();
+    dev.handle_interrupt(status);
+}
+
+struct VirtioMmioDriver {
+    metadata: Metadata,
+    match_table: &'static [DeviceId],
+}
+
+impl Driver for VirtioMmioDriver {
+    fn get_metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    fn get_match_table(&self) -> Option<&[DeviceId]> {
+        Some(self.match_table)
+    }
+
+    fn probe(&self, dev: &mut Device) -> Result<(), NonZeroI32> {
+        let to_err = |errno: i32| NonZeroI32::new(-errno).unwrap();
+        let base = dev.resource.mmio_base;
+        if base == 0 {
+            return Err(to_err(E_INVALID));
+        }
+        let transport = VirtioMmio { base, version: 0 };
+        if transport.read(MAGIC_VALUE) != VIRTIO_MMIO_MAGIC {
+            warn!("Bad virtio-mmio magic @{:#x}", base);
+            return Err(to_err(E_NO_DEV));
+        }
+        let version = transport.read(VERSION);
+        if version != 1 && version != 2 {
+            warn!("Unsupported virtio-mmio version {} @{:#x}", version, base);
+            return Err(to_err(E_NO_DEV));
+        }
+        let transport = VirtioMmio { base, version };
+        if transport.device_id() == 0 {
+            return Err(to_err(E_NO_DEV));
+        }
+        if transport.is_legacy() {
+            transport.write(GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+        }
+
+        let ptr = kzalloc(core::mem::size_of::<VirtioDevice>(), 0) as *mut VirtioDevice;
+        if ptr.is_null() {
+            return Err(to_err(E_NO_MEM));
+        }
+        let vdev = unsafe {
+            write(ptr, VirtioDevice::new(transport, dev.resource.irq));
+            &mut *ptr
+        };
+        info!("virtio-mmio @{:#x}: version {}, device id {}, vendor {:#x}", base, version,
+            vdev.device_id(), vdev.transport().vendor_id());
+
+        vdev.transport().reset();
+        vdev.add_status(VIRTIO_STATUS_ACKNOWLEDGE);
+        let driver = match find_driver(vdev.device_id()) {
+            Some(driver) => driver,
+            None => {
+                info!("No driver for virtio device id {}", vdev.device_id());
+                dev.driver_data = ptr as *mut ();
+                return Ok(());
+            }
+        };
+
+        let irq = dev.resource.irq;
+        let result = irq::request_irq(irq, driver.name(), virtio_mmio_irq, ptr as *mut ())
+            .and_then(|_| vdev.bind_driver(driver).map_err(|errno| {
+                irq::free_irq(irq);
+                errno
+            }));
+        if let Err(errno) = result {
+            vdev.add_status(VIRTIO_STATUS_FAILED);
+            unsafe {
+                core::ptr::drop_in_place(ptr);
+                kfree(ptr as *mut u8);
+            }
+            return Err(to_err(errno));
+        }
+        dev.driver_data = ptr as *mut ();
+        Ok(())
+    }
+
+    fn remove(&self, dev: &mut Device) -> Result<(), NonZeroI32> {
+        let ptr = dev.driver_data as *mut VirtioDevice;
+        if ptr.is_null() {
+            return Ok(());
+        }
+        let vdev = unsafe { &mut *ptr };
+        if vdev.unbind_driver() {
+            irq::free_irq(dev.resource.irq);
+        }
+        unsafe {
+            core::ptr::drop_in_place(ptr);
+            kfree(ptr as *mut u8);
+        }
+        dev.driver_data = core::ptr::null_mut();
+        Ok(())
+    }
+}
+
+static VIRTIO_MMIO_DRIVER: VirtioMmioDriver = VirtioMmioDriver {
+    metadata: Metadata::with_name("virtio-mmio"),
+    match_table: &[DeviceId::with_compat("virtio,mmio")],
+};
+
+pub fn export_driver() -> &'static dyn Driver {
+    &VIRTIO_MMIO_DRIVER
+}
@@ -0,0 +1,249 @@
+//! VirtIO devices.
+//!
+//! A VirtIO device is found by its transport (only [`mmio`] for now), then bound to the
+//! device-class driver of its device id, which implements [`VirtioDriver`]. The binding follows
+//! the initialization sequence of the VirtIO spec:
+//!
+//! 1. Reset the device, set the `ACKNOWLEDGE` and the `DRIVER` status bits.
+//! 2. Negotiate the features: the driver features offered by the device, plus
+//!    [`VIRTIO_F_VERSION_1`] for a modern device, then set and check the `FEATURES_OK` bit.
+//! 3. Call [`VirtioDriver::probe`], which sets up the virtqueues by
+//!    [`VirtioDevice::setup_queue`].
+//! 4. Set the `DRIVER_OK` bit, unless the driver has done it by [`VirtioDevice::set_ready`].
+//!
+//! The requests are offered to the device by the [`VirtQueue`]s, the device interrupt is
+//! dispatched to [`VirtioDriver::handle_queue`] for each queue with used buffers.
+//!
+//! [`VirtQueue`]: queue::VirtQueue
+
+pub(crate) mod blk;
+pub(crate) mod mmio;
+pub(crate) mod queue;
+
+use alloc::vec::Vec;
+use core::ptr::null_mut;
+use crate::errno::{E_NO_DEV, E_NO_ENT};
+use mmio::VirtioMmio;
+use queue::{VirtQueue, VIRTQ_MAX_SIZE};
+
+
+// Device status bits.
+pub const VIRTIO_STATUS_ACKNOWLEDGE: u32 = 1;
+pub const VIRTIO_STATUS_DRIVER: u32 = 2;
+pub const VIRTIO_STATUS_DRIVER_OK: u32 = 4;
+pub const VIRTIO_STATUS_FEATURES_OK: u32 = 8;
+pub const VIRTIO_STATUS_FAILED: u32 = 128;
+
+/// The device complies to the VirtIO 1.x spec (not legacy).
+pub const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+// Device ids.
+pub const VIRTIO_ID_NET: u32 = 1;
+pub const VIRTIO_ID_BLOCK: u32 = 2;
+pub const VIRTIO_ID_CONSOLE: u32 = 3;
+pub const VIRTIO_ID_RNG: u32 = 4;
+
+/// Interrupt status bit of the used buffers.
+const VIRTIO_INT_VRING: u32 = 1;
+/// Interrupt status bit of the configuration change.
+const VIRTIO_INT_CONFIG: u32 = 2;
+
+/// Driver of a VirtIO device class.
+pub trait VirtioDriver {
+    fn name(&self) -> &'static str;
+
+    /// The device id handled by this driver, e.g. [`VIRTIO_ID_BLOCK`].
+    fn device_id(&self) -> u32;
+
+    /// The device feature bits supported by this driver, the transport features are handled by
+    /// the core.
+    fn features(&self) -> u64 {
+        0
+    }
+
+    /// Set up the device after the features are negotiated. Returns `Err` with the errno on
+    /// failure, the device is reset then.
+    fn probe(&self, dev: &mut VirtioDevice) -> Result<(), i32>;
+
+    /// Release the device, which is reset after this call.
+    fn remove(&self, _dev: &mut VirtioDevice) {}
+
+    /// Handle the used buffers of the queue `index`. Called in the interrupt context.
+    fn handle_queue(&self, dev: &mut VirtioDevice, index: u16);
+
+    /// Handle the change of the device configuration. Called in the interrupt context.
+    fn config_changed(&self, _dev: &mut VirtioDevice) {}
+}
+
+/// All built-in VirtIO drivers.
+static VIRTIO_DRIVERS: [fn() -> &'static dyn VirtioDriver; 1] = [
+    blk::export_driver,
+];
+
+/// Find the built-in driver of the `device_id`.
+fn find_driver(device_id: u32) -> Option<&'static dyn VirtioDriver> {
+    VIRTIO_DRIVERS.iter().map(|export| export()).find(|driver| driver.device_id() == device_id)
+}
+
+/// A VirtIO device.
+pub struct VirtioDevice {
+    transport: VirtioMmio,
+    irq: u32,
+    device_id: u32,
+    /// The negotiated features.
+    features: u64,
+    queues: Vec<VirtQueue>,
+    driver: Option<&'static dyn VirtioDriver>,
+    /// Private data of the driver.
+    pub driver_data: *mut (),
+}
+
+impl VirtioDevice {
+    fn new(transport: VirtioMmio, irq: u32) -> Self {
+        Self {
+            device_id: transport.device_id(),
+            transport,
+            irq,
+            features: 0,
+            queues: Vec::new(),
+            driver: None,
+            driver_data: null_mut(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn transport(&self) -> &VirtioMmio {
+        &self.transport
+    }
+
+    #[inline(always)]
+    pub fn device_id(&self) -> u32 {
+        self.device_id
+    }
+
+    #[inline(always)]
+    pub fn irq(&self) -> u32 {
+        self.irq
+    }
+
+    /// Check if the feature `bit` is negotiated.
+    #[inline(always)]
+    pub fn has_feature(&self, bit: u32) -> bool {
+        self.features & (1u64 << bit) != 0
+    }
+
+    fn add_status(&self, bits: u32) {
+        self.transport.set_status(self.transport.status() | bits);
+    }
+
+    /// Set the `DRIVER_OK` status, the device can be used after this call.
+    pub fn set_ready(&self) {
+        if self.transport.status() & VIRTIO_STATUS_DRIVER_OK == 0 {
+            self.add_status(VIRTIO_STATUS_DRIVER_OK);
+        }
+    }
+
+    /// Read `buf.len()` bytes of the device configuration at `offset`.
+    pub fn read_config(&self, offset: usize, buf: &mut [u8]) {
+        self.transport.read_config(offset, buf);
+    }
+
+    /// Set up the queue `index` with at most `max_size` entries, the size is rounded down to a
+    /// power of 2. Returns `Err` with `E_NO_ENT` if the device does not have the queue.
+    pub fn setup_queue(&mut self, index: u16, max_size: u16) -> Result<(), i32> {
+        let size = self.transport.queue_max_size(index).min(max_size).min(VIRTQ_MAX_SIZE);
+        if size == 0 {
+            return Err(E_NO_ENT);
+        }
+        let queue = VirtQueue::new(index, 1u16 << (15 - size.leading_zeros()))?;
+        self.transport.setup_queue(&queue);
+        self.queues.push(queue);
+        Ok(())
+    }
+
+    /// Get the queue `index` set up by [`setup_queue`](Self::setup_queue).
+    pub fn queue(&self, index: u16) -> Option<&VirtQueue> {
+        self.queues.iter().find(|queue| queue.index() == index)
+    }
+
+    /// Notify the device of the new buffers of the queue `index` if it wants.
+    pub fn kick(&self, index: u16) {
+        if let Some(queue) = self.queue(index) {
+            if queue.should_notify() {
+                self.transport.notify(index);
+            }
+        }
+    }
+
+    /// Negotiate the features with the `driver` and probe it.
+    fn bind_driver(&mut self, driver: &'static dyn VirtioDriver) -> Result<(), i32> {
+        self.add_status(VIRTIO_STATUS_DRIVER);
+        let offered = self.transport.device_features();
+        let mut features = offered & driver.features();
+        if !self.transport.is_legacy() {
+            if offered & VIRTIO_F_VERSION_1 == 0 {
+                warn!("Virtio device {} does not offer VERSION_1.", self.device_id);
+                return Err(E_NO_DEV);
+            }
+            features |= VIRTIO_F_VERSION_1;
+        }
+        self.transport.set_driver_features(features);
+        self.features = features;
+        if !self.transport.is_legacy() {
+            self.add_status(VIRTIO_STATUS_FEATURES_OK);
+            if self.transport.status() & VIRTIO_STATUS_FEATURES_OK == 0 {
+                warn!("Virtio device {} rejected the features {:#x}.", self.device_id, features);
+                return Err(E_NO_DEV);
+            }
+        }
+
+        self.driver = Some(driver);
+        if let Err(errno) = driver.probe(self) {
+            self.driver = None;
+            self.reset();
+            return Err(errno);
+        }
+        self.set_ready();
+        Ok(())
+    }
+
+    /// Remove the driver if bound. Returns `true` if there was a driver.
+    fn unbind_driver(&mut self) -> bool {
+        match self.driver.take() {
+            Some(driver) => {
+                driver.remove(self);
+                self.reset();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reset the device and free the queues.
+    fn reset(&mut self) {
+        self.transport.reset();
+        for queue in &self.queues {
+            self.transport.disable_queue(queue.index());
+        }
+        self.queues.clear();
+    }
+
+    /// Dispatch the interrupt of the `status` bits to the driver.
+    fn handle_interrupt(&mut self, status: u32) {
+        let driver = match self.driver {
+            Some(driver) => driver,
+            None => return,
+        };
+        if status & VIRTIO_INT_VRING != 0 {
+            for pos in 0..self.queues.len() {
+                let index = self.queues[pos].index();
+                if self.queues[pos].has_used() {
+                    driver.handle_queue(self, index);
+                }
+            }
+        }
+        if status & VIRTIO_INT_CONFIG != 0 {
+            driver.config_changed(self);
+        }
+    }
+}
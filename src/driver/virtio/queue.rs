@@ -0,0 +1,349 @@
+//! Split virtqueue.
+//!
+//! A virtqueue is made of three rings shared with the device:
+//!
+//! - The descriptor table, each descriptor points to a buffer and can be chained to the next
+//!   one. A request is a chain of the device-readable buffers followed by the device-writable
+//!   buffers.
+//! - The available ring, the driver puts the head of a chain here to offer it to the device.
+//! - The used ring, the device puts the head of a processed chain and the length written here.
+//!
+//! The rings are allocated from the page allocator in the legacy layout (the used ring starts at
+//! the next page after the available ring), which works for both the legacy and the modern
+//! devices. The memory is identity mapped, so the buffer addresses are physical addresses and
+//! must be physically contiguous, e.g. from `kmalloc` or the page allocator.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::mem::size_of;
+use crate::base::sync::lock::SpinLockPure;
+use crate::errno::{E_INVALID, E_NO_MEM, E_NO_SPACE};
+use crate::mm::page;
+use crate::mm::{PAGE_ORDER, PAGE_SIZE};
+use crate::util::align::{align_up, get_order};
+use crate::smp_mb;
+
+
+/// Max size of a virtqueue used by the drivers.
+pub const VIRTQ_MAX_SIZE: u16 = 256;
+
+/// The buffer continues via the `next` field.
+const VIRTQ_DESC_F_NEXT: u16 = 1;
+/// The buffer is device write-only (otherwise device read-only).
+const VIRTQ_DESC_F_WRITE: u16 = 2;
+/// The device does not need a notification when buffers are added.
+const VIRTQ_USED_F_NO_NOTIFY: u16 = 1;
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct VirtqDesc {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct VirtqUsedElem {
+    id: u32,
+    len: u32,
+}
+
+/// A buffer of a request.
+#[derive(Copy, Clone, Debug)]
+pub struct Buffer {
+    /// Physical address.
+    pub addr: usize,
+    pub len: u32,
+    /// The device writes the buffer, otherwise reads it.
+    pub writable: bool,
+}
+
+impl Buffer {
+    /// A device-readable buffer of `len` bytes at `addr`.
+    pub const fn readable(addr: usize, len: u32) -> Self {
+        Self { addr, len, writable: false }
+    }
+
+    /// A device-writable buffer of `len` bytes at `addr`.
+    pub const fn writable(addr: usize, len: u32) -> Self {
+        Self { addr, len, writable: true }
+    }
+}
+
+/// Mutable state of the driver side.
+struct QueueState {
+    /// Head of the free descriptor list, linked by the `next` field.
+    free_head: u16,
+    num_free: u16,
+    /// Next index of the available ring to write.
+    avail_idx: u16,
+    /// Next index of the used ring to read.
+    last_used_idx: u16,
+    /// Token of each chain, indexed by the head descriptor.
+    tokens: Vec<usize>,
+}
+
+/// A split virtqueue. The operations are serialized by an inner lock, and can be called from
+/// the interrupt handler.
+pub struct VirtQueue {
+    index: u16,
+    size: u16,
+    /// Address and order of the ring pages.
+    pages: usize,
+    order: usize,
+    desc: *mut VirtqDesc,
+    /// The available ring: `flags`, `idx`, `ring[size]`, `used_event`.
+    avail: *mut u16,
+    /// The used ring: `flags`, `idx`, then `ring[size]` of [`VirtqUsedElem`] at offset 4.
+    used: *mut u16,
+    lock: SpinLockPure,
+    state: UnsafeCell<QueueState>,
+}
+
+unsafe impl Send for VirtQueue {}
+unsafe impl Sync for VirtQueue {}
+
+impl VirtQueue {
+    /// Get the offsets of the available ring and the used ring, and the total size of the rings
+    /// of a queue of `size`.
+    const fn layout(size: usize) -> (usize, usize, usize) {
+        let avail = size_of::<VirtqDesc>() * size;
+        let used = align_up(avail + 2 * (3 + size), PAGE_ORDER);
+        (avail, used, used + align_up(2 * 3 + size_of::<VirtqUsedElem>() * size, PAGE_ORDER))
+    }
+
+    /// Allocate the queue `index` of `size` entries, which must be a power of 2. Returns `Err`
+    /// with `E_INVALID` for a bad size or `E_NO_MEM` if out of memory.
+    pub fn new(index: u16, size: u16) -> Result<Self, i32> {
+        if size == 0 || !size.is_power_of_two() {
+            return Err(E_INVALID);
+        }
+        let (avail, used, total) = Self::layout(size as usize);
+        let order = get_order(((total + PAGE_SIZE - 1) / PAGE_SIZE).next_power_of_two());
+        let pages = page::alloc_pages(0, order);
+        if pages == 0 {
+            return Err(E_NO_MEM);
+        }
+        unsafe { (pages as *mut u8).write_bytes(0, PAGE_SIZE << order); }
+
+        let desc = pages as *mut VirtqDesc;
+        // Link all descriptors to the free list.
+        for idx in 0..size {
+            unsafe { (*desc.add(idx as usize)).next = idx.wrapping_add(1); }
+        }
+        Ok(Self {
+            index,
+            size,
+            pages,
+            order,
+            desc,
+            avail: (pages + avail) as *mut u16,
+            used: (pages + used) as *mut u16,
+            lock: SpinLockPure::new(),
+            state: UnsafeCell::new(QueueState {
+                free_head: 0,
+                num_free: size,
+                avail_idx: 0,
+                last_used_idx: 0,
+                tokens: vec![0; size as usize],
+            }),
+        })
+    }
+
+    /// Queue index of the device.
+    #[inline(always)]
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    /// Count of the descriptors.
+    #[inline(always)]
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Physical address of the descriptor table.
+    #[inline(always)]
+    pub fn desc_addr(&self) -> usize {
+        self.desc as usize
+    }
+
+    /// Physical address of the available ring.
+    #[inline(always)]
+    pub fn avail_addr(&self) -> usize {
+        self.avail as usize
+    }
+
+    /// Physical address of the used ring.
+    #[inline(always)]
+    pub fn used_addr(&self) -> usize {
+        self.used as usize
+    }
+
+    /// Count of the free descriptors.
+    pub fn num_free(&self) -> u16 {
+        let _guard = self.lock.lock_guard_irq_save();
+        unsafe { (*self.state.get()).num_free }
+    }
+
+    /// Offer the request made of the buffers `bufs` to the device, the readable buffers must be
+    /// before the writable ones. The `token` is returned by [`pop_used`](Self::pop_used) when the
+    /// device finishes the request. The device is not notified, see [`should_notify`].
+    ///
+    /// Returns `Err` with `E_INVALID` if `bufs` is empty, or `E_NO_SPACE` if there are not enough
+    /// free descriptors.
+    ///
+    /// [`should_notify`]: Self::should_notify
+    pub fn add_buf(&self, bufs: &[Buffer], token: usize) -> Result<(), i32> {
+        if bufs.is_empty() {
+            return Err(E_INVALID);
+        }
+
+        let _guard = self.lock.lock_guard_irq_save();
+        let state = unsafe { &mut *self.state.get() };
+        if bufs.len() > state.num_free as usize {
+            return Err(E_NO_SPACE);
+        }
+
+        let head = state.free_head;
+        let mut idx = head;
+        for (i, buf) in bufs.iter().enumerate() {
+            let desc = unsafe { &mut *self.desc.add(idx as usize) };
+            desc.addr = buf.addr as u64;
+            desc.len = buf.len;
+            desc.flags = if buf.writable { VIRTQ_DESC_F_WRITE } else { 0 };
+            if i + 1 < bufs.len() {
+                desc.flags |= VIRTQ_DESC_F_NEXT;
+            }
+            let next = desc.next;
+            if i + 1 == bufs.len() {
+                state.free_head = next;
+            }
+            idx = next;
+        }
+        state.num_free -= bufs.len() as u16;
+        state.tokens[head as usize] = token;
+
+        let slot = state.avail_idx % self.size;
+        unsafe { self.avail.add(2 + slot as usize).write_volatile(head); }
+        // The ring entry must be visible before the index.
+        smp_mb!(w);
+        state.avail_idx = state.avail_idx.wrapping_add(1);
+        unsafe { self.avail.add(1).write_volatile(state.avail_idx); }
+        Ok(())
+    }
+
+    /// Check if the device wants a notification of the new buffers.
+    pub fn should_notify(&self) -> bool {
+        // The index update must be visible before reading the flags.
+        smp_mb!();
+        unsafe { self.used.read_volatile() & VIRTQ_USED_F_NO_NOTIFY == 0 }
+    }
+
+    /// Check if the device has used buffers not popped yet.
+    pub fn has_used(&self) -> bool {
+        let _guard = self.lock.lock_guard_irq_save();
+        let state = unsafe { &*self.state.get() };
+        unsafe { self.used.add(1).read_volatile() != state.last_used_idx }
+    }
+
+    /// Take a request finished by the device, its descriptors are freed. Returns the `token`
+    /// passed to [`add_buf`](Self::add_buf) and the count of bytes written by the device, or
+    /// `None` if there is no finished request.
+    pub fn pop_used(&self) -> Option<(usize, u32)> {
+        let _guard = self.lock.lock_guard_irq_save();
+        let state = unsafe { &mut *self.state.get() };
+        if unsafe { self.used.add(1).read_volatile() } == state.last_used_idx {
+            return None;
+        }
+        // Read the entry after the index.
+        smp_mb!(r);
+
+        let slot = state.last_used_idx % self.size;
+        let elem = unsafe {
+            let ring = self.used.add(2) as *const VirtqUsedElem;
+            ring.add(slot as usize).read_volatile()
+        };
+        state.last_used_idx = state.last_used_idx.wrapping_add(1);
+
+        let head = elem.id as u16;
+        if head >= self.size {
+            warn!("Virtqueue {} used a bad descriptor {}.", self.index, elem.id);
+            return None;
+        }
+        // Put the chain back to the free list.
+        let mut idx = head;
+        loop {
+            let desc = unsafe { &mut *self.desc.add(idx as usize) };
+            state.num_free += 1;
+            if desc.flags & VIRTQ_DESC_F_NEXT == 0 {
+                desc.next = state.free_head;
+                break;
+            }
+            idx = desc.next;
+        }
+        state.free_head = head;
+        Some((state.tokens[head as usize], elem.len))
+    }
+}
+
+impl Drop for VirtQueue {
+    fn drop(&mut self) {
+        page::free_pages(self.pages, self.order);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Play the device: take the next available chain and mark it used with `len` bytes.
+    fn device_use(queue: &VirtQueue, next_avail: &mut u16, len: u32) {
+        unsafe {
+            let head = queue.avail.add(2 + (*next_avail % queue.size) as usize).read();
+            *next_avail += 1;
+            let used_idx = queue.used.add(1).read();
+            let ring = queue.used.add(2) as *mut VirtqUsedElem;
+            let elem = VirtqUsedElem { id: head as u32, len };
+            ring.add((used_idx % queue.size) as usize).write(elem);
+            queue.used.add(1).write(used_idx.wrapping_add(1));
+        }
+    }
+
+    #[kernel_test]
+    fn virtqueue_add_and_pop() {
+        let queue = VirtQueue::new(0, 4).unwrap();
+        assert_eq!(queue.desc_addr() % PAGE_SIZE, 0);
+        assert_eq!(queue.used_addr() % PAGE_SIZE, 0);
+        assert!(queue.pop_used().is_none());
+        assert_eq!(VirtQueue::new(0, 3).err(), Some(E_INVALID));
+
+        let bufs = [Buffer::readable(0x1000, 16), Buffer::writable(0x2000, 512),
+                    Buffer::writable(0x3000, 1)];
+        queue.add_buf(&bufs, 11).unwrap();
+        assert_eq!(queue.num_free(), 1);
+        assert_eq!(queue.add_buf(&bufs[..2], 12), Err(E_NO_SPACE));
+        queue.add_buf(&bufs[..1], 13).unwrap();
+        assert_eq!(queue.num_free(), 0);
+        assert!(queue.should_notify());
+
+        let mut next_avail = 0;
+        device_use(&queue, &mut next_avail, 513);
+        assert!(queue.has_used());
+        assert_eq!(queue.pop_used(), Some((11, 513)));
+        assert_eq!(queue.num_free(), 3);
+        device_use(&queue, &mut next_avail, 0);
+        assert_eq!(queue.pop_used(), Some((13, 0)));
+        assert!(!queue.has_used());
+        assert_eq!(queue.num_free(), 4);
+
+        // The freed descriptors are reused.
+        queue.add_buf(&bufs, 14).unwrap();
+        device_use(&queue, &mut next_avail, 1);
+        assert_eq!(queue.pop_used(), Some((14, 1)));
+    }
+}
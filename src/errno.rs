@@ -1,4 +1,37 @@
 //! Error number definitions.
 
+pub const E_PERM: i32 = 1;
+pub const E_NO_ENT: i32 = 2;
+pub const E_SRCH: i32 = 3;
+pub const E_INTR: i32 = 4;
+pub const E_IO: i32 = 5;
+pub const E_NO_DEV_OR_ADDR: i32 = 6;
+pub const E_TOO_BIG: i32 = 7;
+pub const E_NO_EXEC: i32 = 8;
+pub const E_BAD_FD: i32 = 9;
+pub const E_CHILD: i32 = 10;
+pub const E_AGAIN: i32 = 11;
+pub const E_NO_MEM: i32 = 12;
+pub const E_ACCESS: i32 = 13;
+pub const E_FAULT: i32 = 14;
+pub const E_NOT_BLOCK: i32 = 15;
+pub const E_BUSY: i32 = 16;
+pub const E_EXIST: i32 = 17;
+pub const E_CROSS_DEV: i32 = 18;
+pub const E_NO_DEV: i32 = 19;
+pub const E_NOT_DIR: i32 = 20;
+pub const E_IS_DIR: i32 = 21;
 pub const E_INVALID: i32 = 22;
+pub const E_TOO_MANY_FILES: i32 = 24;
+pub const E_NOT_TTY: i32 = 25;
+pub const E_FILE_TOO_BIG: i32 = 27;
+pub const E_NO_SPACE: i32 = 28;
+pub const E_ILLEGAL_SEEK: i32 = 29;
+pub const E_READ_ONLY_FS: i32 = 30;
+pub const E_TOO_MANY_LINKS: i32 = 31;
+pub const E_RANGE: i32 = 34;
+pub const E_NAME_TOO_LONG: i32 = 36;
 pub const E_NO_SYS: i32 = 38;
+pub const E_NOT_EMPTY: i32 = 39;
+pub const E_LOOP: i32 = 40;
+pub const E_TIMED_OUT: i32 = 110;
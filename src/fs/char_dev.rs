@@ -0,0 +1,94 @@
+//! Character device registry.
+//!
+//! A character device driver registers a range of minor numbers of a major number with its
+//! [`CharDevice`] operations by [`register_chrdev`], the major number can be allocated
+//! dynamically. Opening a character device file calls the driver of its device number, which
+//! returns the open file: the reads, the writes and the `ioctl` requests then go to the driver
+//! through the [`File`] operations.
+//!
+//! The device files are created in `/dev` by the driver core when the devices are added, see
+//! [`devtmpfs`](super::devtmpfs).
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use crate::base::sync::lock::SpinLockPure;
+use crate::errno::{E_BUSY, E_INVALID, E_NO_DEV_OR_ADDR};
+use crate::fs::file::File;
+use crate::fs::inode::{major, minor};
+
+
+/// Count of the minor numbers of a major number.
+pub const MINOR_COUNT: u32 = 1 << 20;
+
+/// The major numbers allocated dynamically, from the last one like Linux.
+const DYNAMIC_MAJORS: core::ops::RangeInclusive<u32> = 234..=254;
+
+/// Operations of a character device driver.
+pub trait CharDevice {
+    /// Open the device of the `minor` number with the open `flags`.
+    fn open(&self, minor: u32, flags: usize) -> Result<Arc<dyn File>, i32>;
+}
+
+/// A range of device numbers registered by a driver.
+struct CharDevRegion {
+    major: u32,
+    base_minor: u32,
+    count: u32,
+    name: &'static str,
+    ops: &'static dyn CharDevice,
+}
+
+static mut CHAR_DEVS: Vec<CharDevRegion> = Vec::new();
+static CHAR_DEVS_LOCK: SpinLockPure = SpinLockPure::new();
+
+/// Register the `count` minor numbers from `base_minor` of the `major` number to the driver
+/// `name` with the operations `ops`. A free major number is allocated if `major` is 0. Returns
+/// the major number.
+///
+/// Returns `Err` with `E_BUSY` if a number of the range is registered, or no major number is
+/// free.
+pub fn register_chrdev(major: u32, base_minor: u32, count: u32, name: &'static str,
+                       ops: &'static dyn CharDevice) -> Result<u32, i32> {
+    if count == 0 || base_minor.checked_add(count).map_or(true, |end| end > MINOR_COUNT) {
+        return Err(E_INVALID);
+    }
+    let _guard = CHAR_DEVS_LOCK.lock_guard_irq_save();
+    let regions = unsafe { &mut *core::ptr::addr_of_mut!(CHAR_DEVS) };
+    let major = match major {
+        0 => DYNAMIC_MAJORS.rev()
+            .find(|&major| regions.iter().all(|region| region.major != major))
+            .ok_or(E_BUSY)?,
+        major => major,
+    };
+    let overlapped = regions.iter().find(|region| {
+        region.major == major && region.base_minor < base_minor + count &&
+            base_minor < region.base_minor + region.count
+    });
+    if let Some(region) = overlapped {
+        warn!("Char device {}: major {} is taken by {}", name, major, region.name);
+        return Err(E_BUSY);
+    }
+    debug!("Char device {}: major {}, minor {}~{}", name, major, base_minor,
+        base_minor + count - 1);
+    regions.push(CharDevRegion { major, base_minor, count, name, ops });
+    Ok(major)
+}
+
+/// Open the character device of the device number `dev` with the open `flags`.
+///
+/// Returns `Err` with `E_NO_DEV_OR_ADDR` if no driver registered the number.
+pub fn chrdev_open(dev: u64, flags: usize) -> Result<Arc<dyn File>, i32> {
+    let (major, minor) = (major(dev), minor(dev));
+    let ops = {
+        let _guard = CHAR_DEVS_LOCK.lock_guard_irq_save();
+        let regions = unsafe { &*core::ptr::addr_of!(CHAR_DEVS) };
+        regions.iter()
+            .find(|region| {
+                region.major == major && (region.base_minor..region.base_minor + region.count)
+                    .contains(&minor)
+            })
+            .map(|region| region.ops)
+            .ok_or(E_NO_DEV_OR_ADDR)?
+    };
+    ops.open(minor, flags)
+}
@@ -0,0 +1,187 @@
+//! The console file on the UART, which is the standard input and output of the processes
+//! created by the kernel. It is also opened by the device files `/dev/console`, `/dev/tty` and
+//! `/dev/ttyS0`.
+
+use alloc::sync::Arc;
+use crate::dev;
+use crate::driver::uart::Uart;
+use crate::errno::{E_AGAIN, E_NOT_TTY};
+use crate::fs::char_dev::{register_chrdev, CharDevice};
+use crate::fs::file::{File, O_RDWR};
+use crate::fs::inode::{make_dev, InodeType, Stat, S_IFCHR};
+use crate::mm::address_space::AddressSpace;
+use crate::mm::uaccess::{read_user, write_user};
+
+
+/// Major device number of `/dev/tty` and the console.
+const TTYAUX_MAJOR: u32 = 5;
+/// Minor device number of `/dev/tty`, the controlling terminal.
+const TTY_MINOR: u32 = 0;
+/// Minor device number of the console.
+const CONSOLE_MINOR: u32 = 1;
+/// Major device number of the serial ports.
+const TTY_SERIAL_MAJOR: u32 = 4;
+/// Minor device number of the first serial port `ttyS0`.
+const TTYS0_MINOR: u32 = 64;
+
+// Terminal `ioctl` requests.
+const TCGETS: u32 = 0x5401;
+const TCSETS: u32 = 0x5402;
+const TCSETSW: u32 = 0x5403;
+const TCSETSF: u32 = 0x5404;
+const TIOCGWINSZ: u32 = 0x5413;
+const TIOCSWINSZ: u32 = 0x5414;
+
+/// Count of the control characters of the `struct termios`.
+const NCCS: usize = 19;
+
+/// `struct termios` of the `TCGETS` and `TCSETS` requests.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct Termios {
+    c_iflag: u32,
+    c_oflag: u32,
+    c_cflag: u32,
+    c_lflag: u32,
+    c_line: u8,
+    c_cc: [u8; NCCS],
+}
+
+/// `struct winsize` of the `TIOCGWINSZ` and `TIOCSWINSZ` requests.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct WinSize {
+    ws_row: u16,
+    ws_col: u16,
+    ws_xpixel: u16,
+    ws_ypixel: u16,
+}
+
+impl Termios {
+    /// The fixed settings of the console: the canonical mode with echo, 8-bit characters at
+    /// 38400 baud.
+    fn console() -> Termios {
+        const ICRNL: u32 = 0o400;
+        const OPOST: u32 = 0o1;
+        const ONLCR: u32 = 0o4;
+        const B38400: u32 = 0o17;
+        const CS8: u32 = 0o60;
+        const CREAD: u32 = 0o200;
+        const ISIG: u32 = 0o1;
+        const ICANON: u32 = 0o2;
+        const ECHO: u32 = 0o10;
+        const ECHOE: u32 = 0o20;
+        const ECHOK: u32 = 0o40;
+        const IEXTEN: u32 = 0o100000;
+
+        let mut c_cc = [0u8; NCCS];
+        // `VINTR`, `VQUIT`, `VERASE`, `VKILL`, `VEOF`, `VTIME` and `VMIN`.
+        c_cc[..7].copy_from_slice(&[0x03, 0x1c, 0x7f, 0x15, 0x04, 0, 1]);
+        Termios {
+            c_iflag: ICRNL,
+            c_oflag: OPOST | ONLCR,
+            c_cflag: B38400 | CS8 | CREAD,
+            c_lflag: ISIG | ICANON | ECHO | ECHOE | ECHOK | IEXTEN,
+            c_line: 0,
+            c_cc,
+        }
+    }
+}
+
+/// The UART console. Like a terminal in the canonical mode, `\r` is converted to `\n` and the
+/// input is echoed.
+pub struct Console;
+
+/// Open the console.
+pub fn console_file() -> Arc<dyn File> {
+    Arc::new(Console)
+}
+
+impl File for Console {
+    fn flags(&self) -> usize {
+        O_RDWR
+    }
+
+    fn stat(&self) -> Result<Stat, i32> {
+        Ok(Stat {
+            mode: InodeType::CharDevice.mode() | 0o620,
+            nlink: 1,
+            rdev: make_dev(TTYAUX_MAJOR, CONSOLE_MINOR),
+            blksize: 1024,
+            ..Stat::default()
+        })
+    }
+
+    /// Read the available input, at most one line. Returns `Err` with `E_AGAIN` if no input is
+    /// available.
+    fn read(&self, buf: &mut [u8]) -> Result<usize, i32> {
+        let uart = Uart::default();
+        let mut len = 0;
+        while len < buf.len() {
+            let Some(c) = uart.get() else {
+                break;
+            };
+            let c = if c == b'\r' { b'\n' } else { c };
+            uart.put(c);
+            buf[len] = c;
+            len += 1;
+            if c == b'\n' {
+                break;
+            }
+        }
+
+        if len == 0 && !buf.is_empty() {
+            return Err(E_AGAIN);
+        }
+        Ok(len)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, i32> {
+        let uart = Uart::default();
+        for &c in buf {
+            uart.put(c);
+        }
+        Ok(buf.len())
+    }
+
+    /// Get the terminal settings and the window size. The settings are fixed, a change of them
+    /// is accepted but ignored.
+    fn ioctl(&self, cmd: u32, arg: usize, aspace: &mut AddressSpace) -> Result<usize, i32> {
+        match cmd {
+            TCGETS => write_user(aspace, arg, &Termios::console()),
+            TCSETS | TCSETSW | TCSETSF => read_user::<Termios>(aspace, arg).map(|_| ()),
+            TIOCGWINSZ => {
+                write_user(aspace, arg, &WinSize { ws_row: 24, ws_col: 80, ..WinSize::default() })
+            }
+            TIOCSWINSZ => read_user::<WinSize>(aspace, arg).map(|_| ()),
+            _ => Err(E_NOT_TTY),
+        }.map(|_| 0)
+    }
+}
+
+/// The driver of the console devices.
+struct ConsoleDriver;
+
+static CONSOLE_DRIVER: ConsoleDriver = ConsoleDriver;
+
+impl CharDevice for ConsoleDriver {
+    fn open(&self, _minor: u32, _flags: usize) -> Result<Arc<dyn File>, i32> {
+        Ok(console_file())
+    }
+}
+
+/// Register the console devices and create their device files.
+pub(crate) fn init() {
+    let devices = [
+        ("tty", TTYAUX_MAJOR, TTY_MINOR, 0o666),
+        ("console", TTYAUX_MAJOR, CONSOLE_MINOR, 0o600),
+        ("ttyS0", TTY_SERIAL_MAJOR, TTYS0_MINOR, 0o660),
+    ];
+    for (name, major, minor, mode) in devices {
+        let result = register_chrdev(major, minor, 1, name, &CONSOLE_DRIVER)
+            .and_then(|_| dev::device_create(name, S_IFCHR | mode, make_dev(major, minor)));
+        if let Err(errno) = result {
+            warn!("Create the console device {} failed, errno = {}", name, errno);
+        }
+    }
+}
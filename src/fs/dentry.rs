@@ -0,0 +1,183 @@
+//! Dentries and the path resolution.
+//!
+//! A [`Dentry`] names an inode by its parent dentry and the name in the parent directory. The
+//! dentries are not cached, each path resolution builds a new chain from its start directory,
+//! but the chain keeps the parents, so `..` of a mounted root returns to the directory which
+//! contains the mount point, and the path of a dentry can be rebuilt for `getcwd`.
+//!
+//! The path resolution handles `.` and `..` by the dentry chain (`..` of the root is the root),
+//! steps into the mounted file systems, and follows the symlinks: the target of a symlink is
+//! spliced before the rest of the path, at most [`MAX_SYMLINK_FOLLOWS`] times.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use crate::errno::{E_LOOP, E_NAME_TOO_LONG, E_NO_ENT, E_NOT_DIR};
+use crate::fs::inode::{Inode, InodeType};
+use crate::fs::mount::{mounted_root, root_dentry};
+
+
+/// Max length of a path, the NUL included.
+pub const PATH_MAX: usize = 4096;
+/// Max length of a name in a directory.
+pub const NAME_MAX: usize = 255;
+/// Max count of the symlinks followed in a path resolution.
+const MAX_SYMLINK_FOLLOWS: usize = 40;
+
+/// A named inode in the directory tree.
+pub struct Dentry {
+    name: String,
+    inode: Arc<dyn Inode>,
+    /// Parent directory, `None` for the root.
+    parent: Option<Arc<Dentry>>,
+}
+
+impl Dentry {
+    pub fn new(name: String, inode: Arc<dyn Inode>, parent: Option<Arc<Dentry>>) -> Arc<Dentry> {
+        Arc::new(Dentry { name, inode, parent })
+    }
+
+    /// Get the name in the parent directory, `/` for the root.
+    #[inline(always)]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline(always)]
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        &self.inode
+    }
+
+    /// Get the parent directory, `None` for the root.
+    #[inline(always)]
+    pub fn parent(&self) -> Option<&Arc<Dentry>> {
+        self.parent.as_ref()
+    }
+
+    /// Get the inode type.
+    pub fn inode_type(&self) -> Result<InodeType, i32> {
+        Ok(self.inode.stat()?.inode_type())
+    }
+
+    /// Build the absolute path from the root.
+    pub fn path(&self) -> String {
+        let mut names = alloc::vec::Vec::new();
+        let mut cur = self;
+        while let Some(parent) = cur.parent.as_deref() {
+            names.push(cur.name.as_str());
+            cur = parent;
+        }
+        if names.is_empty() {
+            return String::from("/");
+        }
+
+        let mut path = String::new();
+        for name in names.iter().rev() {
+            path.push('/');
+            path.push_str(name);
+        }
+        path
+    }
+}
+
+/// Look up the child `name` of the directory `dir`, and step into the file systems mounted on
+/// it.
+fn lookup_child(dir: &Arc<Dentry>, name: &str) -> Result<Arc<Dentry>, i32> {
+    if name.len() > NAME_MAX {
+        return Err(E_NAME_TOO_LONG);
+    }
+    let mut inode = dir.inode.lookup(name)?;
+    // The mounts may be stacked on the same directory.
+    while let Some(root) = mounted_root(&inode) {
+        inode = root;
+    }
+
+    Ok(Dentry::new(String::from(name), inode, Some(dir.clone())))
+}
+
+/// Resolve the `path` relative to the directory `base`, or the root if `base` is `None`. The
+/// symlink at the last component is followed if `follow` or the path ends with `/`, which also
+/// requires the result to be a directory.
+///
+/// Returns `Err` with `E_NO_ENT` for an empty path or a missing component, `E_NOT_DIR` if a
+/// middle component is not a directory, or `E_LOOP` if too many symlinks are followed.
+pub fn lookup_path(base: Option<&Arc<Dentry>>, path: &str, follow: bool)
+    -> Result<Arc<Dentry>, i32> {
+    if path.is_empty() {
+        return Err(E_NO_ENT);
+    }
+    let must_dir = path.ends_with('/');
+    let follow = follow || must_dir;
+    let mut cur = match base {
+        Some(base) if !path.starts_with('/') => base.clone(),
+        _ => root_dentry()?,
+    };
+
+    let mut path = String::from(path);
+    let mut pos = 0;
+    let mut links = 0;
+    loop {
+        pos += path[pos..].len() - path[pos..].trim_start_matches('/').len();
+        if pos == path.len() {
+            break;
+        }
+        let end = path[pos..].find('/').map_or(path.len(), |idx| pos + idx);
+        let last = path[end..].trim_start_matches('/').is_empty();
+        let name = &path[pos..end];
+        if name == "." || name == ".." {
+            if cur.inode_type()? != InodeType::Dir {
+                return Err(E_NOT_DIR);
+            }
+            if name == ".." {
+                cur = cur.parent.clone().unwrap_or(cur);
+            }
+            pos = end;
+            continue;
+        }
+
+        let child = lookup_child(&cur, name)?;
+        if (follow || !last) && child.inode_type()? == InodeType::Symlink {
+            links += 1;
+            if links > MAX_SYMLINK_FOLLOWS {
+                return Err(E_LOOP);
+            }
+            let mut target = child.inode.read_link()?;
+            if target.is_empty() {
+                return Err(E_NO_ENT);
+            }
+            if target.starts_with('/') {
+                cur = root_dentry()?;
+            }
+            target.push('/');
+            target.push_str(&path[end..]);
+            path = target;
+            pos = 0;
+            continue;
+        }
+        cur = child;
+        pos = end;
+    }
+
+    if must_dir && cur.inode_type()? != InodeType::Dir {
+        return Err(E_NOT_DIR);
+    }
+    Ok(cur)
+}
+
+/// Resolve the parent directory of the `path` relative to `base` like [`lookup_path`]. Returns
+/// the parent and the last name, which may be `.` or `..`; the name of `/` is `.`.
+pub fn lookup_parent<'a>(base: Option<&Arc<Dentry>>, path: &'a str)
+    -> Result<(Arc<Dentry>, &'a str), i32> {
+    let trimmed = path.trim_end_matches('/');
+    if trimmed.is_empty() {
+        return Ok((lookup_path(base, path, true)?, "."));
+    }
+
+    let (parent, name) = match trimmed.rfind('/') {
+        Some(idx) => (lookup_path(base, &trimmed[..=idx], true)?, &trimmed[idx + 1..]),
+        None => (lookup_path(base, ".", true)?, trimmed),
+    };
+    if name.len() > NAME_MAX {
+        return Err(E_NAME_TOO_LONG);
+    }
+    Ok((parent, name))
+}
@@ -0,0 +1,123 @@
+//! devtmpfs, the device files maintained by the kernel.
+//!
+//! The device files live in a [`tmpfs`](super::tmpfs) instance created before the devices are
+//! probed. The driver core creates the file of a device with a device number when the device is
+//! added and removes it when the device is removed, see [`device_add`]; the block layer does
+//! the same for the disks and their partitions. Mounting `devtmpfs` mounts this instance, which
+//! is mounted on `/dev` at boot. The files can also be created, renamed and removed by the users
+//! like on a tmpfs.
+//!
+//! [`device_add`]: crate::dev::device_add
+
+use alloc::sync::Arc;
+use crate::base::sync::lock::SpinLockPure;
+use crate::errno::{E_INVALID, E_NO_DEV, E_NO_ENT};
+use crate::fs::inode::{Inode, InodeType};
+use crate::fs::mount::{FileSystem, SuperBlock};
+use crate::fs::tmpfs::TMPFS;
+
+
+/// The `devtmpfs` file system type.
+pub struct DevTmpFs;
+
+pub static DEVTMPFS: DevTmpFs = DevTmpFs;
+
+/// The tmpfs of the device files.
+static mut DEVTMPFS_SB: Option<Arc<dyn SuperBlock>> = None;
+/// Serializes the changes of the device files by the kernel.
+static DEVTMPFS_LOCK: SpinLockPure = SpinLockPure::new();
+
+/// Create the tmpfs of the device files. Must be called before any device is added.
+pub(crate) fn init() {
+    let sb = TMPFS.mount("devtmpfs", "")
+        .unwrap_or_else(|err| panic!("Create devtmpfs failed, errno = {}.", err));
+    let _guard = DEVTMPFS_LOCK.lock_guard_irq_save();
+    unsafe { *core::ptr::addr_of_mut!(DEVTMPFS_SB) = Some(sb) };
+}
+
+/// Get the root directory of the device files.
+fn root() -> Result<Arc<dyn Inode>, i32> {
+    let sb = unsafe { &*core::ptr::addr_of!(DEVTMPFS_SB) };
+    sb.as_ref().map(|sb| sb.root()).ok_or(E_NO_DEV)
+}
+
+/// Create the device file `name` of the `kind` (a char or a block device) with the permission
+/// bits `mode` for the device number `dev`. The `name` is relative to `/dev`, the missing
+/// directories of it are created.
+pub fn create_node(name: &str, kind: InodeType, mode: u32, dev: u64) -> Result<(), i32> {
+    let _guard = DEVTMPFS_LOCK.lock_guard_irq_save();
+    let mut dir = root()?;
+    let (dirs, file) = name.rsplit_once('/').unwrap_or(("", name));
+    if file.is_empty() {
+        return Err(E_INVALID);
+    }
+    for part in dirs.split('/').filter(|part| !part.is_empty()) {
+        dir = match dir.lookup(part) {
+            Err(E_NO_ENT) => dir.create(part, InodeType::Dir, 0o755)?,
+            result => result?,
+        };
+    }
+    dir.mknod(file, kind, mode, dev)?;
+    Ok(())
+}
+
+/// Remove the device file `name` created by [`create_node`], unless it has been replaced by a
+/// file of another device number.
+pub fn delete_node(name: &str, dev: u64) -> Result<(), i32> {
+    let _guard = DEVTMPFS_LOCK.lock_guard_irq_save();
+    let mut dir = root()?;
+    let (dirs, file) = name.rsplit_once('/').unwrap_or(("", name));
+    for part in dirs.split('/').filter(|part| !part.is_empty()) {
+        dir = dir.lookup(part)?;
+    }
+    if dir.lookup(file)?.stat()?.rdev != dev {
+        return Err(E_NO_ENT);
+    }
+    dir.unlink(file)
+}
+
+impl FileSystem for DevTmpFs {
+    fn name(&self) -> &'static str {
+        "devtmpfs"
+    }
+
+    /// Mount the device files, the `source` and the `data` are ignored.
+    fn mount(&self, _source: &str, _data: &str) -> Result<Arc<dyn SuperBlock>, i32> {
+        let _guard = DEVTMPFS_LOCK.lock_guard_irq_save();
+        let sb = unsafe { &*core::ptr::addr_of!(DEVTMPFS_SB) };
+        sb.clone().ok_or(E_NO_DEV)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::file::{O_RDONLY, O_RDWR};
+    use crate::fs::inode::make_dev;
+    use crate::fs::vfs;
+
+    #[kernel_test]
+    fn open_dev_null() {
+        // `/dev` is the devtmpfs instance, its files are opened by the drivers.
+        let stat = vfs::stat(None, "/dev", true).unwrap();
+        assert_eq!(stat.dev, root().unwrap().stat().unwrap().dev);
+        let stat = vfs::stat(None, "/dev/null", true).unwrap();
+        assert_eq!((stat.inode_type(), stat.rdev), (InodeType::CharDevice, make_dev(1, 3)));
+        let null = vfs::open(None, "/dev/null", O_RDWR, 0).unwrap();
+        assert_eq!(null.write(b"discarded"), Ok(9));
+        let mut buf = [0xffu8; 4];
+        assert_eq!(null.read(&mut buf), Ok(0));
+        assert_eq!(null.stat().unwrap().rdev, make_dev(1, 3));
+
+        // A node created by the kernel opens the same device, and is only removed by its number.
+        create_node("ktest/null", InodeType::CharDevice, 0o600, make_dev(1, 3)).unwrap();
+        let stat = vfs::stat(None, "/dev/ktest/null", true).unwrap();
+        assert_eq!(stat.mode & 0o777, 0o600);
+        let file = vfs::open(None, "/dev/ktest/null", O_RDONLY, 0).unwrap();
+        assert_eq!(file.read(&mut buf), Ok(0));
+        assert_eq!(delete_node("ktest/null", make_dev(1, 5)), Err(E_NO_ENT));
+        delete_node("ktest/null", make_dev(1, 3)).unwrap();
+        assert_eq!(vfs::stat(None, "/dev/ktest/null", true).err(), Some(E_NO_ENT));
+        vfs::unlink(None, "/dev/ktest", true).unwrap();
+    }
+}
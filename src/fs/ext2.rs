@@ -0,0 +1,1580 @@
+//! Second extended file system (`ext2`).
+//!
+//! The on-disk format is in [`vos_core::ext2`]. A volume is mounted from a block device, e.g.
+//! `mount -t ext2 /dev/vda2 /mnt`, and can be populated on the host by `mke2fs -d <dir>`. The
+//! mount option `ro` mounts it read-only, and so is a read-only device. A volume with unknown
+//! read-only compatible features can only be mounted read-only, and the block size must not be
+//! larger than the page size.
+//!
+//! - The superblock, the group descriptors, the bitmaps, the inode tables and the indirect blocks
+//!   are read and written through the page cache of the block device. A block is allocated from
+//!   the block bitmap near the last block allocated for the inode, an inode from the inode bitmap
+//!   of the group of its directory. The free counts of the superblock are written on sync.
+//! - A file, a directory or a long symlink is a [`PageIo`] with its own page cache: a page is
+//!   mapped through the direct and the indirect blocks of the inode. The holes of a sparse file
+//!   read as zeros and are allocated when written. A freed block is discarded from the block
+//!   device cache, so a stale indirect block never overwrites the file reusing it.
+//! - A directory is read and written through its cache. A new entry takes the free space after
+//!   an entry, or a new block at the end of the directory.
+//!
+//! The loaded inodes stay in the inode table until the unmount, like the [`fat`](super::fat)
+//! ones. An inode without links is freed with its blocks when its last open file is closed. The
+//! owners and the permission bits are kept, but not checked yet.
+//!
+//! The superblock state is not clean while the volume is mounted read-write, and the state at the
+//! mount is restored by the unmount, so `e2fsck` checks a volume which was not unmounted or was
+//! not clean. An inconsistency found on the volume marks it with errors.
+//!
+//! The namespace changes are serialized by the directory lock of the file system. The lock order
+//! is: the directory lock, the lock of a page cache, the inode lock, then the allocation lock.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use vos_core::ext2::{block_path, file_type, find_zero_bit, rec_len_of, DirEntry as DiskEntry,
+                     DiskInode, GroupDesc, SuperBlock as DiskSuperBlock, DIR_ENTRY_HEADER,
+                     FAST_SYMLINK_MAX, FT_BLKDEV, FT_CHRDEV, FT_DIR, FT_FIFO, FT_REG_FILE,
+                     FT_SOCK, FT_SYMLINK, GROUP_DESC_SIZE, INDEX_FL, IND_BLOCK, LINK_MAX, NAME_MAX,
+                     N_BLOCKS, N_DIRECT, ROOT_INO, STATE_ERROR, STATE_VALID, SUPERBLOCK_OFFSET,
+                     SUPERBLOCK_SIZE};
+use crate::base::sync::lock::SpinLockPure;
+use crate::block::{self, BlockDev, SECTOR_SIZE};
+use crate::errno::{E_CROSS_DEV, E_EXIST, E_FILE_TOO_BIG, E_INVALID, E_IO, E_IS_DIR,
+                   E_NAME_TOO_LONG, E_NO_ENT, E_NO_SPACE, E_NOT_DIR, E_NOT_EMPTY, E_PERM,
+                   E_READ_ONLY_FS, E_TOO_MANY_LINKS};
+use crate::fs::inode::{current_time, make_dev, DirEntry, Inode, InodeType, Stat, S_IALLUGO};
+use crate::fs::mount::{FileSystem, SuperBlock};
+use crate::mm::page_cache::{PageCache, PageIo};
+use crate::mm::PAGE_SIZE;
+
+
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+/// Zeros to clear the tail of the last block of a truncated file.
+static ZEROS: [u8; PAGE_SIZE] = [0; PAGE_SIZE];
+
+/// The `ext2` file system type.
+pub struct Ext2;
+
+pub static EXT2: Ext2 = Ext2;
+
+/// Convert an inode time in nanoseconds to the seconds on the disk.
+#[inline(always)]
+fn to_secs(time: u64) -> u32 {
+    (time / NSEC_PER_SEC) as u32
+}
+
+#[inline(always)]
+fn from_secs(secs: u32) -> u64 {
+    secs as u64 * NSEC_PER_SEC
+}
+
+/// Get the inode type of the file type of a directory entry.
+fn kind_of_file_type(file_type: u8) -> Option<InodeType> {
+    match file_type {
+        FT_REG_FILE => Some(InodeType::File),
+        FT_DIR => Some(InodeType::Dir),
+        FT_CHRDEV => Some(InodeType::CharDevice),
+        FT_BLKDEV => Some(InodeType::BlockDevice),
+        FT_FIFO => Some(InodeType::Fifo),
+        FT_SOCK => Some(InodeType::Socket),
+        FT_SYMLINK => Some(InodeType::Symlink),
+        _ => None,
+    }
+}
+
+/// Check the `name` of a new entry.
+fn check_name(name: &str) -> Result<(), i32> {
+    if name.len() > NAME_MAX {
+        return Err(E_NAME_TOO_LONG);
+    }
+    if name.is_empty() || name.bytes().any(|c| c == b'/' || c == 0) {
+        return Err(E_INVALID);
+    }
+    Ok(())
+}
+
+/// Allocation state of a volume, protected by the allocation lock.
+struct AllocState {
+    groups: Vec<GroupDesc>,
+    free_blocks: u32,
+    free_inodes: u32,
+    /// State in the superblock.
+    state: u16,
+    /// State at the mount, restored by the unmount.
+    mount_state: u16,
+}
+
+/// State of a mounted volume shared by its inodes.
+struct Ext2Fs {
+    bdev: Arc<BlockDev>,
+    /// The superblock at the mount. The free counts and the state are in the `AllocState`.
+    sb: DiskSuperBlock,
+    block_size: u32,
+    /// Max size of a regular file.
+    max_size: u64,
+    read_only: bool,
+    /// An inconsistency was found.
+    errors: AtomicBool,
+    next_generation: AtomicU32,
+    /// Serializes the namespace changes.
+    dir_lock: SpinLockPure,
+    alloc_lock: SpinLockPure,
+    alloc: UnsafeCell<AllocState>,
+    table_lock: SpinLockPure,
+    /// Loaded inodes by the inode number.
+    inodes: UnsafeCell<BTreeMap<u32, Arc<Ext2Inode>>>,
+}
+
+impl Ext2Fs {
+    fn check_writable(&self) -> Result<(), i32> {
+        if self.read_only { Err(E_READ_ONLY_FS) } else { Ok(()) }
+    }
+
+    /// Report an inconsistency of the volume, which is marked with errors.
+    fn error(&self, args: fmt::Arguments) {
+        warn!("{}: ext2 error: {}", self.bdev.name(), args);
+        self.errors.store(true, Ordering::Relaxed);
+    }
+
+    /// Call `f` with the allocation state under the allocation lock.
+    fn with_alloc<F, R>(&self, f: F) -> R where F: FnOnce(&mut AllocState) -> R {
+        let _guard = self.alloc_lock.lock_guard_irq_save();
+        f(unsafe { &mut *self.alloc.get() })
+    }
+
+    /// Read the metadata at `offset` of the volume through the block device cache.
+    fn read_meta(&self, offset: u64, buf: &mut [u8]) -> Result<(), i32> {
+        match self.bdev.cache().read(offset, buf)? {
+            len if len == buf.len() => Ok(()),
+            _ => Err(E_IO),
+        }
+    }
+
+    /// Write the metadata at `offset` of the volume through the block device cache.
+    fn write_meta(&self, offset: u64, buf: &[u8]) -> Result<(), i32> {
+        match self.bdev.cache().write(offset, buf)? {
+            len if len == buf.len() => Ok(()),
+            _ => Err(E_IO),
+        }
+    }
+
+    #[inline(always)]
+    fn block_offset(&self, block: u32) -> u64 {
+        block as u64 * self.block_size as u64
+    }
+
+    /// Check a block number read from the volume.
+    fn check_block(&self, block: u32) -> Result<u32, i32> {
+        if block < self.sb.first_data_block || block >= self.sb.blocks_count {
+            self.error(format_args!("block {} out of range", block));
+            return Err(E_IO);
+        }
+        Ok(block)
+    }
+
+    /// Write the descriptor of the `group`. The allocation lock must be held.
+    fn write_group(&self, group: usize, desc: &GroupDesc) -> Result<(), i32> {
+        let offset = self.block_offset(self.sb.group_desc_block()) +
+            (group * GROUP_DESC_SIZE) as u64;
+        let mut raw = [0u8; GROUP_DESC_SIZE];
+        self.read_meta(offset, &mut raw)?;
+        desc.write(&mut raw);
+        self.write_meta(offset, &raw)
+    }
+
+    /// Allocate a free block, searched from the block `goal`.
+    fn alloc_block(&self, goal: u32) -> Result<u32, i32> {
+        let sb = &self.sb;
+        let goal = if goal > sb.first_data_block && goal < sb.blocks_count {
+            goal
+        } else {
+            sb.first_data_block
+        };
+        let count = sb.group_count() as usize;
+        let start = ((goal - sb.first_data_block) / sb.blocks_per_group) as usize;
+        let mut bitmap = vec![0u8; self.block_size as usize];
+        let _guard = self.alloc_lock.lock_guard_irq_save();
+        let alloc = unsafe { &mut *self.alloc.get() };
+        if alloc.free_blocks == 0 {
+            return Err(E_NO_SPACE);
+        }
+        // The group of the goal is searched from the goal first, and from its start at last.
+        for i in 0..=count {
+            let group = (start + i) % count;
+            let desc = &mut alloc.groups[group];
+            if desc.free_blocks_count == 0 {
+                continue;
+            }
+            let bitmap_offset = self.block_offset(desc.block_bitmap);
+            self.read_meta(bitmap_offset, &mut bitmap)?;
+            let first = sb.group_first_block(group as u32);
+            let from = if i == 0 { (goal - first) as usize } else { 0 };
+            let Some(bit) = find_zero_bit(&bitmap, from, sb.group_blocks(group as u32) as usize)
+                else {
+                continue;
+            };
+            bitmap[bit / 8] |= 1 << (bit % 8);
+            self.write_meta(bitmap_offset + (bit / 8) as u64, &bitmap[bit / 8..bit / 8 + 1])?;
+            desc.free_blocks_count -= 1;
+            alloc.free_blocks -= 1;
+            self.write_group(group, &alloc.groups[group])?;
+            return Ok(first + bit as u32);
+        }
+        alloc.free_blocks = 0;
+        Err(E_NO_SPACE)
+    }
+
+    /// Free the `block`, its dirty metadata in the block device cache is discarded.
+    fn free_block(&self, block: u32) -> Result<(), i32> {
+        let sb = &self.sb;
+        let group = ((block - sb.first_data_block) / sb.blocks_per_group) as usize;
+        let bit = ((block - sb.first_data_block) % sb.blocks_per_group) as usize;
+        let _guard = self.alloc_lock.lock_guard_irq_save();
+        let alloc = unsafe { &mut *self.alloc.get() };
+        let desc = &mut alloc.groups[group];
+        let offset = self.block_offset(desc.block_bitmap) + (bit / 8) as u64;
+        let mut byte = [0u8];
+        self.read_meta(offset, &mut byte)?;
+        if byte[0] & (1 << (bit % 8)) == 0 {
+            self.error(format_args!("freeing free block {}", block));
+            return Ok(());
+        }
+        byte[0] &= !(1 << (bit % 8));
+        self.write_meta(offset, &byte)?;
+        desc.free_blocks_count += 1;
+        alloc.free_blocks += 1;
+        self.write_group(group, &alloc.groups[group])?;
+        self.bdev.cache().discard(self.block_offset(block), self.block_size as u64);
+        Ok(())
+    }
+
+    /// Allocate a free inode, searched from the `group`.
+    fn alloc_inode(&self, group: u32, dir: bool) -> Result<u32, i32> {
+        let sb = &self.sb;
+        let count = sb.group_count() as usize;
+        let mut bitmap = vec![0u8; self.block_size as usize];
+        let _guard = self.alloc_lock.lock_guard_irq_save();
+        let alloc = unsafe { &mut *self.alloc.get() };
+        if alloc.free_inodes == 0 {
+            return Err(E_NO_SPACE);
+        }
+        for i in 0..count {
+            let group = (group as usize + i) % count;
+            let desc = &mut alloc.groups[group];
+            if desc.free_inodes_count == 0 {
+                continue;
+            }
+            let bitmap_offset = self.block_offset(desc.inode_bitmap);
+            self.read_meta(bitmap_offset, &mut bitmap)?;
+            // The reserved inodes are in the group 0.
+            let from = if group == 0 { sb.first_ino as usize - 1 } else { 0 };
+            let Some(bit) = find_zero_bit(&bitmap, from, sb.inodes_per_group as usize) else {
+                continue;
+            };
+            let ino = (group as u32 * sb.inodes_per_group) + bit as u32 + 1;
+            if ino > sb.inodes_count {
+                continue;
+            }
+            bitmap[bit / 8] |= 1 << (bit % 8);
+            self.write_meta(bitmap_offset + (bit / 8) as u64, &bitmap[bit / 8..bit / 8 + 1])?;
+            desc.free_inodes_count -= 1;
+            if dir {
+                desc.used_dirs_count += 1;
+            }
+            alloc.free_inodes -= 1;
+            self.write_group(group, &alloc.groups[group])?;
+            return Ok(ino);
+        }
+        alloc.free_inodes = 0;
+        Err(E_NO_SPACE)
+    }
+
+    /// Free the inode `ino`.
+    fn free_inode(&self, ino: u32, dir: bool) -> Result<(), i32> {
+        let (group, index) = self.sb.inode_group(ino);
+        let (group, bit) = (group as usize, index as usize);
+        let _guard = self.alloc_lock.lock_guard_irq_save();
+        let alloc = unsafe { &mut *self.alloc.get() };
+        let desc = &mut alloc.groups[group];
+        let offset = self.block_offset(desc.inode_bitmap) + (bit / 8) as u64;
+        let mut byte = [0u8];
+        self.read_meta(offset, &mut byte)?;
+        if byte[0] & (1 << (bit % 8)) == 0 {
+            self.error(format_args!("freeing free inode {}", ino));
+            return Ok(());
+        }
+        byte[0] &= !(1 << (bit % 8));
+        self.write_meta(offset, &byte)?;
+        desc.free_inodes_count += 1;
+        if dir {
+            desc.used_dirs_count = desc.used_dirs_count.saturating_sub(1);
+        }
+        alloc.free_inodes += 1;
+        self.write_group(group, &alloc.groups[group])
+    }
+
+    /// Offset of the inode `ino` in the volume.
+    fn inode_offset(&self, ino: u32) -> u64 {
+        let (group, index) = self.sb.inode_group(ino);
+        let table = self.with_alloc(|alloc| alloc.groups[group as usize].inode_table);
+        self.block_offset(table) + index as u64 * self.sb.inode_size as u64
+    }
+
+    fn read_inode(&self, ino: u32) -> Result<DiskInode, i32> {
+        let mut raw = [0u8; 128];
+        self.read_meta(self.inode_offset(ino), &mut raw)?;
+        Ok(DiskInode::parse(&raw))
+    }
+
+    /// Write the inode `ino`, the fields unknown to the driver are kept.
+    fn write_inode(&self, ino: u32, inode: &DiskInode) -> Result<(), i32> {
+        let offset = self.inode_offset(ino);
+        let mut raw = [0u8; 128];
+        self.read_meta(offset, &mut raw)?;
+        inode.write(&mut raw);
+        self.write_meta(offset, &raw)
+    }
+
+    /// Write a new inode `ino`, the rest of the on-disk inode is zeroed.
+    fn write_new_inode(&self, ino: u32, inode: &DiskInode) -> Result<(), i32> {
+        let mut raw = vec![0u8; self.sb.inode_size as usize];
+        inode.write(&mut raw);
+        self.write_meta(self.inode_offset(ino), &raw)
+    }
+
+    /// Write the free counts and the state to the superblock.
+    fn write_super(&self) -> Result<(), i32> {
+        let mut raw = [0u8; SUPERBLOCK_SIZE];
+        self.read_meta(SUPERBLOCK_OFFSET, &mut raw)?;
+        let mut sb = self.sb;
+        self.with_alloc(|alloc| {
+            if self.errors.load(Ordering::Relaxed) {
+                alloc.state |= STATE_ERROR;
+                alloc.mount_state |= STATE_ERROR;
+            }
+            sb.free_blocks_count = alloc.free_blocks;
+            sb.free_inodes_count = alloc.free_inodes;
+            sb.state = alloc.state;
+        });
+        sb.wtime = to_secs(current_time());
+        sb.write(&mut raw);
+        self.write_meta(SUPERBLOCK_OFFSET, &raw)
+    }
+
+    /// Get the inode `ino`, loading it if needed.
+    fn get_inode(self: &Arc<Self>, ino: u32) -> Result<Arc<Ext2Inode>, i32> {
+        if ino == 0 || ino > self.sb.inodes_count || (ino < self.sb.first_ino && ino != ROOT_INO) {
+            self.error(format_args!("bad inode number {}", ino));
+            return Err(E_IO);
+        }
+        {
+            let _guard = self.table_lock.lock_guard_irq_save();
+            if let Some(inode) = unsafe { (*self.inodes.get()).get(&ino) } {
+                return Ok(inode.clone());
+            }
+        }
+
+        let disk = self.read_inode(ino)?;
+        let kind = InodeType::from_mode(disk.mode as u32).filter(|_| disk.links_count > 0);
+        let Some(kind) = kind else {
+            self.error(format_args!("inode {} is deleted or broken", ino));
+            return Err(E_IO);
+        };
+        let inode = Ext2Inode::new(self, ino, kind, disk);
+        let _guard = self.table_lock.lock_guard_irq_save();
+        // Loaded by another CPU meanwhile.
+        let inodes = unsafe { &mut *self.inodes.get() };
+        Ok(inodes.entry(ino).or_insert(inode).clone())
+    }
+
+    /// Add a new inode to the inode table.
+    fn insert_inode(&self, inode: &Arc<Ext2Inode>) {
+        let _guard = self.table_lock.lock_guard_irq_save();
+        unsafe { (*self.inodes.get()).insert(inode.ino, inode.clone()) };
+    }
+
+    /// Remove the inode `ino` from the inode table. Returns it, to be dropped without the lock.
+    fn forget_inode(&self, ino: u32) -> Option<Arc<Ext2Inode>> {
+        let _guard = self.table_lock.lock_guard_irq_save();
+        unsafe { (*self.inodes.get()).remove(&ino) }
+    }
+
+    /// Write back the loaded inodes, the superblock and the metadata. Returns the first error.
+    fn sync(&self) -> Result<(), i32> {
+        let inodes: Vec<Arc<Ext2Inode>> = {
+            let _guard = self.table_lock.lock_guard_irq_save();
+            unsafe { (*self.inodes.get()).values().cloned().collect() }
+        };
+        let result = inodes.iter().fold(Ok(()), |result, inode| result.and(inode.cache.sync()));
+        let result = if self.read_only { result } else { result.and(self.write_super()) };
+        result.and(self.bdev.cache().sync())
+    }
+}
+
+/// An entry found in a directory.
+struct Slot {
+    offset: u64,
+    /// Offset of the previous entry in the block, `None` for the first.
+    prev: Option<u64>,
+    entry: DiskEntry,
+}
+
+/// Fields of an ext2 inode protected by the inode lock.
+struct Ext2InodeInner {
+    disk: DiskInode,
+    /// The inode has no links, it is freed with its blocks when it is dropped.
+    removed: bool,
+    /// Block to allocate the next block of the inode from.
+    goal: u32,
+}
+
+/// An inode of an ext2 volume.
+struct Ext2Inode {
+    fs: Arc<Ext2Fs>,
+    ino: u32,
+    kind: InodeType,
+    cache: PageCache,
+    lock: SpinLockPure,
+    inner: UnsafeCell<Ext2InodeInner>,
+}
+
+impl Ext2Inode {
+    fn new(fs: &Arc<Ext2Fs>, ino: u32, kind: InodeType, disk: DiskInode) -> Arc<Ext2Inode> {
+        let group = fs.sb.inode_group(ino).0;
+        Arc::new_cyclic(|me: &Weak<Ext2Inode>| Ext2Inode {
+            fs: fs.clone(),
+            ino,
+            kind,
+            cache: PageCache::new(me.clone() as Weak<dyn PageIo>),
+            lock: SpinLockPure::new(),
+            inner: UnsafeCell::new(Ext2InodeInner {
+                disk,
+                removed: false,
+                goal: fs.sb.group_first_block(group),
+            }),
+        })
+    }
+
+    /// Call `f` with the fields under the inode lock.
+    fn with_inner<F, R>(&self, f: F) -> R where F: FnOnce(&mut Ext2InodeInner) -> R {
+        let _guard = self.lock.lock_guard_irq_save();
+        f(unsafe { &mut *self.inner.get() })
+    }
+
+    /// Change the on-disk inode by `f` and write it.
+    fn update<F>(&self, f: F) -> Result<(), i32> where F: FnOnce(&mut DiskInode) {
+        self.with_inner(|inner| {
+            f(&mut inner.disk);
+            self.fs.write_inode(self.ino, &inner.disk)
+        })
+    }
+
+    fn mode(&self) -> u16 {
+        self.with_inner(|inner| inner.disk.mode)
+    }
+
+    /// Check if the block numbers map the data, they do not for a fast symlink or a device.
+    fn is_block_mapped(&self, disk: &DiskInode) -> bool {
+        match self.kind {
+            InodeType::File | InodeType::Dir => true,
+            InodeType::Symlink => !disk.is_fast_symlink(self.fs.block_size),
+            _ => false,
+        }
+    }
+
+    /// Read the block number `index` of the indirect `block`.
+    fn read_ptr(&self, block: u32, index: usize) -> Result<u32, i32> {
+        let mut raw = [0u8; 4];
+        self.fs.read_meta(self.fs.block_offset(block) + (index * 4) as u64, &mut raw)?;
+        Ok(u32::from_le_bytes(raw))
+    }
+
+    fn write_ptr(&self, block: u32, index: usize, value: u32) -> Result<(), i32> {
+        let offset = self.fs.block_offset(block) + (index * 4) as u64;
+        self.fs.write_meta(offset, &value.to_le_bytes())
+    }
+
+    /// Allocate a block for the inode, an indirect block is zeroed. The inode lock must be held.
+    fn new_block(&self, inner: &mut Ext2InodeInner, indirect: bool) -> Result<u32, i32> {
+        let fs = &*self.fs;
+        let block = fs.alloc_block(inner.goal)?;
+        inner.goal = block + 1;
+        inner.disk.blocks += fs.block_size / SECTOR_SIZE as u32;
+        if indirect {
+            fs.write_meta(fs.block_offset(block), &ZEROS[..fs.block_size as usize])?;
+        }
+        Ok(block)
+    }
+
+    /// Get the block of the logical block `index`, allocating it and its indirect blocks if
+    /// `alloc`. Returns `None` for a hole. The inode lock must be held.
+    fn get_block(&self, inner: &mut Ext2InodeInner, index: u64, alloc: bool)
+        -> Result<Option<u32>, i32> {
+        let blocks = inner.disk.blocks;
+        let result = self.walk_blocks(inner, index, alloc);
+        if inner.disk.blocks != blocks {
+            self.fs.write_inode(self.ino, &inner.disk)?;
+        }
+        result
+    }
+
+    fn walk_blocks(&self, inner: &mut Ext2InodeInner, index: u64, alloc: bool)
+        -> Result<Option<u32>, i32> {
+        let fs = &*self.fs;
+        let Some((path, depth)) = block_path(index, (fs.block_size / 4) as u64) else {
+            return if alloc { Err(E_FILE_TOO_BIG) } else { Ok(None) };
+        };
+        let mut block = inner.disk.block[path[0]];
+        if block == 0 {
+            if !alloc {
+                return Ok(None);
+            }
+            block = self.new_block(inner, depth > 1)?;
+            inner.disk.block[path[0]] = block;
+        } else {
+            fs.check_block(block)?;
+        }
+        for level in 1..depth {
+            let parent = block;
+            block = self.read_ptr(parent, path[level])?;
+            if block == 0 {
+                if !alloc {
+                    return Ok(None);
+                }
+                block = self.new_block(inner, level + 1 < depth)?;
+                self.write_ptr(parent, path[level], block)?;
+            } else {
+                fs.check_block(block)?;
+            }
+        }
+        Ok(Some(block))
+    }
+
+    /// Free the blocks from the logical block `first`. The inode lock must be held.
+    fn free_blocks_from(&self, inner: &mut Ext2InodeInner, first: u64) -> Result<(), i32> {
+        let per_block = (self.fs.block_size / 4) as u64;
+        let mut result = Ok(());
+        let mut base = 0;
+        for i in 0..N_BLOCKS {
+            // A direct block maps one block, an indirect one `per_block` to the depth.
+            let depth = if i < IND_BLOCK { 0 } else { (i - IND_BLOCK + 1) as u32 };
+            let span = per_block.pow(depth);
+            let block = inner.disk.block[i];
+            if block != 0 && base + span > first {
+                match self.free_tree(inner, block, depth, base, first) {
+                    Ok(true) => inner.disk.block[i] = 0,
+                    Ok(false) => {}
+                    Err(errno) => {
+                        result = Err(errno);
+                        break;
+                    }
+                }
+            }
+            base += span;
+        }
+        result.and(self.fs.write_inode(self.ino, &inner.disk))
+    }
+
+    /// Free the blocks mapped by the `block` at `depth`, 0 for a data block, from the logical
+    /// block `first`; the `block` maps the logical blocks from `base`. Returns `true` if the
+    /// `block` itself is freed. The inode lock must be held.
+    fn free_tree(&self, inner: &mut Ext2InodeInner, block: u32, depth: u32, base: u64,
+                 first: u64) -> Result<bool, i32> {
+        let fs = &*self.fs;
+        fs.check_block(block)?;
+        if depth > 0 {
+            let span = ((fs.block_size / 4) as u64).pow(depth - 1);
+            let offset = fs.block_offset(block);
+            let mut buf = vec![0u8; fs.block_size as usize];
+            fs.read_meta(offset, &mut buf)?;
+            let mut result = Ok(());
+            let mut changed = false;
+            for (i, raw) in buf.chunks_exact_mut(4).enumerate() {
+                let child_base = base + i as u64 * span;
+                let child = u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]);
+                if child == 0 || child_base + span <= first {
+                    continue;
+                }
+                match self.free_tree(inner, child, depth - 1, child_base, first) {
+                    Ok(true) => {
+                        raw.fill(0);
+                        changed = true;
+                    }
+                    Ok(false) => {}
+                    Err(errno) => {
+                        result = Err(errno);
+                        break;
+                    }
+                }
+            }
+            // The block is kept if it still maps blocks before `first`.
+            if changed && (first > base || result.is_err()) {
+                fs.write_meta(offset, &buf)?;
+            }
+            result?;
+            if first > base {
+                return Ok(false);
+            }
+        }
+        fs.free_block(block)?;
+        inner.disk.blocks = inner.disk.blocks.saturating_sub(fs.block_size / SECTOR_SIZE as u32);
+        Ok(true)
+    }
+
+    /// Call `f` with the offset, the offset of the previous entry in the block, the header and
+    /// the name of the used entries of the directory from the offset `from`, until it returns
+    /// `false`. The block of `from` is parsed from its start, `from` may be stale.
+    fn scan<F>(&self, from: u64, mut f: F) -> Result<(), i32>
+        where F: FnMut(u64, Option<u64>, &DiskEntry, &[u8]) -> bool {
+        let block_size = self.fs.block_size as usize;
+        let mut buf = vec![0u8; block_size];
+        let mut start = from - from % block_size as u64;
+        loop {
+            if self.cache.read(start, &mut buf)? < block_size {
+                return Ok(());
+            }
+            let mut pos = 0;
+            let mut prev = None;
+            while pos < block_size {
+                let entry = DiskEntry::parse(&buf[pos..]);
+                let offset = start + pos as u64;
+                if !entry.is_valid(pos, block_size) {
+                    self.fs.error(format_args!("bad entry at {} of directory {}", offset,
+                        self.ino));
+                    return Err(E_IO);
+                }
+                if entry.inode != 0 && offset >= from {
+                    let name = &buf[pos + DIR_ENTRY_HEADER..][..entry.name_len as usize];
+                    if !f(offset, prev, &entry, name) {
+                        return Ok(());
+                    }
+                }
+                prev = Some(offset);
+                pos += entry.rec_len as usize;
+            }
+            start += block_size as u64;
+        }
+    }
+
+    /// Find the entry `name` in the directory.
+    fn find(&self, name: &str) -> Result<Option<Slot>, i32> {
+        let mut found = None;
+        self.scan(0, |offset, prev, entry, raw| {
+            if raw == name.as_bytes() {
+                found = Some(Slot { offset, prev, entry: *entry });
+            }
+            found.is_none()
+        })?;
+        Ok(found)
+    }
+
+    /// Check if the directory has no entries but `.` and `..`.
+    fn is_empty_dir(&self) -> Result<bool, i32> {
+        let mut empty = true;
+        self.scan(0, |_, _, _, name| {
+            empty = name == b"." || name == b"..";
+            empty
+        })?;
+        Ok(empty)
+    }
+
+    /// Fill the header and the name of a new entry at the start of `buf`.
+    fn fill_entry(&self, buf: &mut [u8], name: &str, ino: u32, mode: u16, rec_len: usize) {
+        let entry = DiskEntry {
+            inode: ino,
+            rec_len: rec_len as u16,
+            name_len: name.len() as u8,
+            file_type: if self.fs.sb.has_file_type() { file_type(mode) } else { 0 },
+        };
+        entry.write(buf);
+        buf[DIR_ENTRY_HEADER..DIR_ENTRY_HEADER + name.len()].copy_from_slice(name.as_bytes());
+    }
+
+    /// Add the entry `name` of the inode `ino` with the `mode` to the directory. The directory
+    /// lock must be held.
+    fn add_entry(&self, name: &str, ino: u32, mode: u16) -> Result<(), i32> {
+        let block_size = self.fs.block_size as usize;
+        let need = rec_len_of(name.len());
+        let mut buf = vec![0u8; block_size];
+        let size = self.size();
+        let mut start = 0;
+        while start < size {
+            if self.cache.read(start, &mut buf)? < block_size {
+                return Err(E_IO);
+            }
+            let mut pos = 0;
+            while pos < block_size {
+                let mut entry = DiskEntry::parse(&buf[pos..]);
+                if !entry.is_valid(pos, block_size) {
+                    self.fs.error(format_args!("bad entry at {} of directory {}",
+                        start + pos as u64, self.ino));
+                    return Err(E_IO);
+                }
+                let rec_len = entry.rec_len as usize;
+                if entry.free_space() < need {
+                    pos += rec_len;
+                    continue;
+                }
+                // Take the unused entry, or split the free space after the name.
+                let at = if entry.inode == 0 {
+                    pos
+                } else {
+                    entry.rec_len = rec_len_of(entry.name_len as usize) as u16;
+                    entry.write(&mut buf[pos..]);
+                    pos + entry.rec_len as usize
+                };
+                self.fill_entry(&mut buf[at..], name, ino, mode, pos + rec_len - at);
+                self.cache.write(start + pos as u64, &buf[pos..pos + rec_len])?;
+                return self.dir_changed();
+            }
+            start += block_size as u64;
+        }
+
+        buf.fill(0);
+        self.fill_entry(&mut buf, name, ino, mode, block_size);
+        if self.cache.write(size, &buf)? != block_size {
+            return Err(E_NO_SPACE);
+        }
+        self.update(|disk| disk.size = size + block_size as u64)?;
+        self.dir_changed()
+    }
+
+    /// Remove the entry of the `slot`, merging it into the previous entry of the block.
+    fn remove_entry(&self, slot: &Slot) -> Result<(), i32> {
+        let mut raw = [0u8; DIR_ENTRY_HEADER];
+        match slot.prev {
+            Some(prev) => {
+                if self.cache.read(prev, &mut raw)? != raw.len() {
+                    return Err(E_IO);
+                }
+                let mut entry = DiskEntry::parse(&raw);
+                entry.rec_len += slot.entry.rec_len;
+                entry.write(&mut raw);
+                self.cache.write(prev, &raw)?;
+            }
+            None => {
+                DiskEntry { inode: 0, ..slot.entry }.write(&mut raw);
+                self.cache.write(slot.offset, &raw)?;
+            }
+        }
+        self.dir_changed()
+    }
+
+    /// Point the entry of the `slot` to the inode `ino` with the `mode`.
+    fn set_entry(&self, slot: &Slot, ino: u32, mode: u16) -> Result<(), i32> {
+        let mut raw = [0u8; DIR_ENTRY_HEADER];
+        let mut entry = DiskEntry { inode: ino, ..slot.entry };
+        if self.fs.sb.has_file_type() {
+            entry.file_type = file_type(mode);
+        }
+        entry.write(&mut raw);
+        self.cache.write(slot.offset, &raw)?;
+        self.dir_changed()
+    }
+
+    /// Update the times of the directory after a change. The hash tree index, if any, is not
+    /// updated, so the directory is not indexed any more.
+    fn dir_changed(&self) -> Result<(), i32> {
+        let now = to_secs(current_time());
+        self.update(|disk| {
+            disk.mtime = now;
+            disk.ctime = now;
+            disk.flags &= !INDEX_FL;
+        })
+    }
+
+    /// Fill the first block of a new directory: `.` and `..` to the directory `parent`.
+    fn init_dir(&self, parent: u32) -> Result<(), i32> {
+        let block_size = self.fs.block_size as usize;
+        let mut buf = vec![0u8; block_size];
+        let dot_len = rec_len_of(1);
+        self.fill_entry(&mut buf, ".", self.ino, self.mode(), dot_len);
+        self.fill_entry(&mut buf[dot_len..], "..", parent, self.mode(), block_size - dot_len);
+        if self.cache.write(0, &buf)? != block_size {
+            return Err(E_NO_SPACE);
+        }
+        self.update(|disk| disk.size = block_size as u64)
+    }
+
+    /// Point the `..` entry of a moved directory to the directory `parent`.
+    fn set_dotdot(&self, parent: u32) -> Result<(), i32> {
+        let mut found = None;
+        self.scan(0, |offset, prev, entry, name| {
+            if name == b".." {
+                found = Some(Slot { offset, prev, entry: *entry });
+            }
+            found.is_none()
+        })?;
+        match found {
+            Some(slot) => {
+                let mut raw = [0u8; DIR_ENTRY_HEADER];
+                DiskEntry { inode: parent, ..slot.entry }.write(&mut raw);
+                self.cache.write(slot.offset, &raw)?;
+                Ok(())
+            }
+            None => {
+                self.fs.error(format_args!("no `..` in directory {}", self.ino));
+                Err(E_IO)
+            }
+        }
+    }
+
+    /// Allocate and load a new inode of the `kind` with the permission bits `mode` in the
+    /// directory.
+    fn new_inode(&self, kind: InodeType, mode: u32) -> Result<Arc<Ext2Inode>, i32> {
+        let fs = &self.fs;
+        let dir = kind == InodeType::Dir;
+        let ino = fs.alloc_inode(fs.sb.inode_group(self.ino).0, dir)?;
+        let now = to_secs(current_time());
+        let disk = DiskInode {
+            mode: (kind.mode() | (mode & S_IALLUGO)) as u16,
+            links_count: if dir { 2 } else { 1 },
+            atime: now,
+            ctime: now,
+            mtime: now,
+            generation: fs.next_generation.fetch_add(1, Ordering::Relaxed),
+            ..DiskInode::default()
+        };
+        if let Err(errno) = fs.write_new_inode(ino, &disk) {
+            let _ = fs.free_inode(ino, dir);
+            return Err(errno);
+        }
+        let inode = Ext2Inode::new(fs, ino, kind, disk);
+        fs.insert_inode(&inode);
+        Ok(inode)
+    }
+
+    /// Add `delta` to the link count.
+    fn add_links(&self, delta: i32) -> Result<(), i32> {
+        let now = to_secs(current_time());
+        self.update(|disk| {
+            disk.links_count = disk.links_count.wrapping_add(delta as u16);
+            disk.ctime = now;
+        })
+    }
+
+    /// Remove a link, or all links of a directory. The inode is removed after the last one, and
+    /// freed when it is dropped.
+    fn drop_link(&self) -> Result<(), i32> {
+        let now = to_secs(current_time());
+        let removed = self.with_inner(|inner| {
+            let disk = &mut inner.disk;
+            disk.links_count = if self.kind == InodeType::Dir {
+                0
+            } else {
+                disk.links_count.saturating_sub(1)
+            };
+            disk.ctime = now;
+            inner.removed = disk.links_count == 0;
+            self.fs.write_inode(self.ino, disk).map(|_| inner.removed)
+        })?;
+        if removed {
+            drop(self.fs.forget_inode(self.ino));
+        }
+        Ok(())
+    }
+
+    /// Remove a new inode which failed to be linked, it is freed when it is dropped.
+    fn discard(&self) {
+        self.with_inner(|inner| {
+            inner.disk.links_count = 0;
+            inner.removed = true;
+        });
+        drop(self.fs.forget_inode(self.ino));
+    }
+
+    /// Read the device number of a device file, in the old or the new encoding.
+    fn rdev(disk: &DiskInode) -> u64 {
+        match (disk.block[0], disk.block[1]) {
+            (0, dev) => make_dev((dev & 0xfff00) >> 8, (dev & 0xff) | ((dev >> 12) & 0xfff00)),
+            (dev, _) => make_dev((dev >> 8) & 0xff, dev & 0xff),
+        }
+    }
+
+    fn check_dir(&self) -> Result<(), i32> {
+        if self.kind == InodeType::Dir { Ok(()) } else { Err(E_NOT_DIR) }
+    }
+
+    fn check_file(&self) -> Result<(), i32> {
+        match self.kind {
+            InodeType::File => Ok(()),
+            InodeType::Dir => Err(E_IS_DIR),
+            _ => Err(E_INVALID),
+        }
+    }
+
+    /// Get the inode `inode` of an operation as an inode of the same volume.
+    fn same_fs<'a>(&self, inode: &'a Arc<dyn Inode>) -> Result<&'a Ext2Inode, i32> {
+        inode.as_any().downcast_ref::<Ext2Inode>()
+            .filter(|inode| Arc::ptr_eq(&inode.fs, &self.fs))
+            .ok_or(E_CROSS_DEV)
+    }
+}
+
+impl Drop for Ext2Inode {
+    fn drop(&mut self) {
+        let inner = unsafe { &mut *self.inner.get() };
+        if !inner.removed {
+            return;
+        }
+        self.cache.truncate(0);
+        let result = if self.is_block_mapped(&inner.disk) {
+            self.free_blocks_from(inner, 0)
+        } else {
+            Ok(())
+        };
+        inner.disk.dtime = to_secs(current_time());
+        let result = result
+            .and(self.fs.write_inode(self.ino, &inner.disk))
+            .and(self.fs.free_inode(self.ino, self.kind == InodeType::Dir));
+        if let Err(errno) = result {
+            warn!("{}: free inode {} failed, errno = {}", self.fs.bdev.name(), self.ino, errno);
+        }
+    }
+}
+
+impl PageIo for Ext2Inode {
+    fn cache(&self) -> &PageCache {
+        &self.cache
+    }
+
+    fn bdev(&self) -> &BlockDev {
+        &self.fs.bdev
+    }
+
+    fn size(&self) -> u64 {
+        self.with_inner(|inner| inner.disk.size)
+    }
+
+    fn block_size(&self) -> usize {
+        self.fs.block_size as usize
+    }
+
+    fn map_page(&self, index: u64, alloc: Range<usize>, blocks: &mut [Option<u64>])
+        -> Result<(), i32> {
+        let block_size = self.fs.block_size as usize;
+        let sectors = (block_size / SECTOR_SIZE) as u64;
+        self.with_inner(|inner| {
+            if !self.is_block_mapped(&inner.disk) {
+                blocks.fill(None);
+                return Ok(());
+            }
+            for (i, block) in blocks.iter_mut().enumerate() {
+                let in_page = i * block_size;
+                let alloc = alloc.start < in_page + block_size && in_page < alloc.end;
+                let index = index * (PAGE_SIZE / block_size) as u64 + i as u64;
+                *block = self.get_block(inner, index, alloc)?.map(|block| block as u64 * sectors);
+            }
+            Ok(())
+        })
+    }
+}
+
+impl Inode for Ext2Inode {
+    fn stat(&self) -> Result<Stat, i32> {
+        Ok(self.with_inner(|inner| {
+            let disk = &inner.disk;
+            let rdev = match self.kind {
+                InodeType::CharDevice | InodeType::BlockDevice => Self::rdev(disk),
+                _ => 0,
+            };
+            Stat {
+                dev: self.fs.bdev.dev(),
+                ino: self.ino as u64,
+                mode: disk.mode as u32,
+                nlink: disk.links_count as u32,
+                uid: disk.uid,
+                gid: disk.gid,
+                rdev,
+                size: disk.size,
+                blksize: self.fs.block_size,
+                blocks: disk.blocks as u64,
+                atime: from_secs(disk.atime),
+                mtime: from_secs(disk.mtime),
+                ctime: from_secs(disk.ctime),
+            }
+        }))
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, i32> {
+        self.check_file()?;
+        self.cache.read(offset as u64, buf)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, i32> {
+        self.check_file()?;
+        self.fs.check_writable()?;
+        let offset = offset as u64;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if offset >= self.fs.max_size {
+            return Err(E_FILE_TOO_BIG);
+        }
+        let buf = &buf[..buf.len().min((self.fs.max_size - offset) as usize)];
+
+        // The skipped blocks stay holes.
+        let written = self.cache.write(offset, buf)?;
+        let now = to_secs(current_time());
+        self.update(|disk| {
+            disk.size = disk.size.max(offset + written as u64);
+            disk.mtime = now;
+            disk.ctime = now;
+        })?;
+        Ok(written)
+    }
+
+    fn truncate(&self, size: usize) -> Result<(), i32> {
+        self.check_file()?;
+        self.fs.check_writable()?;
+        let size = size as u64;
+        if size > self.fs.max_size {
+            return Err(E_FILE_TOO_BIG);
+        }
+        let block_size = self.fs.block_size as u64;
+        let old = self.size();
+        // Zero the tail of the last block, which is exposed by extending later.
+        let tail = size % block_size;
+        if size < old && tail != 0 {
+            let mapped = self.with_inner(|inner| self.get_block(inner, size / block_size, false))?;
+            if mapped.is_some() {
+                self.cache.write(size, &ZEROS[..(block_size - tail) as usize])?;
+            }
+        }
+        let now = to_secs(current_time());
+        self.update(|disk| {
+            disk.size = size;
+            disk.mtime = now;
+            disk.ctime = now;
+        })?;
+        if size < old {
+            self.cache.truncate(size);
+            let keep = (size + block_size - 1) / block_size;
+            self.with_inner(|inner| self.free_blocks_from(inner, keep))?;
+        }
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, i32> {
+        self.check_dir()?;
+        let _guard = self.fs.dir_lock.lock_guard_irq_save();
+        let slot = self.find(name)?.ok_or(E_NO_ENT)?;
+        Ok(self.fs.get_inode(slot.entry.inode)? as Arc<dyn Inode>)
+    }
+
+    fn create(&self, name: &str, kind: InodeType, mode: u32) -> Result<Arc<dyn Inode>, i32> {
+        self.check_dir()?;
+        if kind != InodeType::File && kind != InodeType::Dir {
+            return Err(E_PERM);
+        }
+        self.fs.check_writable()?;
+        check_name(name)?;
+        let _guard = self.fs.dir_lock.lock_guard_irq_save();
+        if self.find(name)?.is_some() {
+            return Err(E_EXIST);
+        }
+        let dir = kind == InodeType::Dir;
+        if dir && self.with_inner(|inner| inner.disk.links_count) >= LINK_MAX {
+            return Err(E_TOO_MANY_LINKS);
+        }
+
+        let inode = self.new_inode(kind, mode)?;
+        let result = if dir { inode.init_dir(self.ino) } else { Ok(()) };
+        if let Err(errno) = result.and_then(|_| self.add_entry(name, inode.ino, inode.mode())) {
+            inode.discard();
+            return Err(errno);
+        }
+        if dir {
+            self.add_links(1)?;
+        }
+        Ok(inode as Arc<dyn Inode>)
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>, i32> {
+        self.check_dir()?;
+        self.fs.check_writable()?;
+        check_name(name)?;
+        if target.len() >= self.fs.block_size as usize {
+            return Err(E_NAME_TOO_LONG);
+        }
+        let _guard = self.fs.dir_lock.lock_guard_irq_save();
+        if self.find(name)?.is_some() {
+            return Err(E_EXIST);
+        }
+
+        let inode = self.new_inode(InodeType::Symlink, 0o777)?;
+        let result = if target.len() <= FAST_SYMLINK_MAX {
+            inode.update(|disk| disk.set_fast_symlink(target.as_bytes()))
+        } else {
+            inode.update(|disk| disk.size = target.len() as u64).and_then(|_| {
+                match inode.cache.write(0, target.as_bytes())? {
+                    len if len == target.len() => Ok(()),
+                    _ => Err(E_NO_SPACE),
+                }
+            })
+        };
+        if let Err(errno) = result.and_then(|_| self.add_entry(name, inode.ino, inode.mode())) {
+            inode.discard();
+            return Err(errno);
+        }
+        Ok(inode as Arc<dyn Inode>)
+    }
+
+    fn link(&self, name: &str, inode: &Arc<dyn Inode>) -> Result<(), i32> {
+        self.check_dir()?;
+        let inode = self.same_fs(inode)?;
+        if inode.kind == InodeType::Dir {
+            return Err(E_PERM);
+        }
+        self.fs.check_writable()?;
+        check_name(name)?;
+        let _guard = self.fs.dir_lock.lock_guard_irq_save();
+        if self.find(name)?.is_some() {
+            return Err(E_EXIST);
+        }
+        let (removed, links) = inode.with_inner(|inner| (inner.removed, inner.disk.links_count));
+        if removed {
+            return Err(E_NO_ENT);
+        }
+        if links >= LINK_MAX {
+            return Err(E_TOO_MANY_LINKS);
+        }
+        self.add_entry(name, inode.ino, inode.mode())?;
+        inode.add_links(1)
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), i32> {
+        self.check_dir()?;
+        self.fs.check_writable()?;
+        let _guard = self.fs.dir_lock.lock_guard_irq_save();
+        let slot = self.find(name)?.ok_or(E_NO_ENT)?;
+        let child = self.fs.get_inode(slot.entry.inode)?;
+        let dir = child.kind == InodeType::Dir;
+        if dir && !child.is_empty_dir()? {
+            return Err(E_NOT_EMPTY);
+        }
+        self.remove_entry(&slot)?;
+        child.drop_link()?;
+        if dir {
+            self.add_links(-1)?;
+        }
+        Ok(())
+    }
+
+    fn rename(&self, old_name: &str, new_dir: &Arc<dyn Inode>, new_name: &str)
+        -> Result<(), i32> {
+        self.check_dir()?;
+        let new_dir = self.same_fs(new_dir)?;
+        new_dir.check_dir()?;
+        self.fs.check_writable()?;
+        check_name(new_name)?;
+        let _guard = self.fs.dir_lock.lock_guard_irq_save();
+        let slot = self.find(old_name)?.ok_or(E_NO_ENT)?;
+        let child = self.fs.get_inode(slot.entry.inode)?;
+        let is_dir = child.kind == InodeType::Dir;
+        let same_dir = core::ptr::eq(self, new_dir);
+        let mode = child.mode();
+
+        match new_dir.find(new_name)? {
+            Some(target_slot) => {
+                let target = self.fs.get_inode(target_slot.entry.inode)?;
+                // Two links of the same inode.
+                if Arc::ptr_eq(&target, &child) {
+                    return Ok(());
+                }
+                let target_dir = target.kind == InodeType::Dir;
+                match (is_dir, target_dir) {
+                    (true, false) => return Err(E_NOT_DIR),
+                    (false, true) => return Err(E_IS_DIR),
+                    (true, true) if !target.is_empty_dir()? => return Err(E_NOT_EMPTY),
+                    _ => {}
+                }
+                new_dir.set_entry(&target_slot, child.ino, mode)?;
+                target.drop_link()?;
+                if target_dir {
+                    new_dir.add_links(-1)?;
+                }
+            }
+            None => {
+                let links = new_dir.with_inner(|inner| inner.disk.links_count);
+                if is_dir && !same_dir && links >= LINK_MAX {
+                    return Err(E_TOO_MANY_LINKS);
+                }
+                new_dir.add_entry(new_name, child.ino, mode)?;
+            }
+        }
+
+        // The new entry may split the previous entry of the old one.
+        let slot = self.find(old_name)?.filter(|slot| slot.entry.inode == child.ino)
+            .ok_or(E_IO)?;
+        self.remove_entry(&slot)?;
+        if is_dir && !same_dir {
+            child.set_dotdot(new_dir.ino)?;
+            self.add_links(-1)?;
+            new_dir.add_links(1)?;
+        }
+        let now = to_secs(current_time());
+        child.update(|disk| disk.ctime = now)
+    }
+
+    fn read_dir(&self, offset: usize) -> Result<Option<(DirEntry, usize)>, i32> {
+        self.check_dir()?;
+        let _guard = self.fs.dir_lock.lock_guard_irq_save();
+        let mut found = None;
+        self.scan(offset as u64, |offset, _, entry, name| {
+            found = Some((offset, *entry, String::from_utf8_lossy(name).into_owned()));
+            false
+        })?;
+        let Some((offset, entry, name)) = found else {
+            return Ok(None);
+        };
+        let kind = self.fs.sb.has_file_type().then(|| kind_of_file_type(entry.file_type))
+            .flatten();
+        let kind = match kind {
+            Some(kind) => kind,
+            None => self.fs.get_inode(entry.inode)?.kind,
+        };
+        let next = offset as usize + entry.rec_len as usize;
+        Ok(Some((DirEntry { ino: entry.inode as u64, kind, name }, next)))
+    }
+
+    fn read_link(&self) -> Result<String, i32> {
+        if self.kind != InodeType::Symlink {
+            return Err(E_INVALID);
+        }
+        let disk = self.with_inner(|inner| inner.disk);
+        if disk.is_fast_symlink(self.fs.block_size) {
+            let mut buf = [0u8; N_BLOCKS * 4];
+            let len = disk.fast_symlink(&mut buf);
+            return Ok(String::from_utf8_lossy(&buf[..len]).into_owned());
+        }
+        let mut buf = vec![0u8; (disk.size as usize).min(self.fs.block_size as usize)];
+        let len = self.cache.read(0, &mut buf)?;
+        Ok(String::from_utf8_lossy(&buf[..len]).into_owned())
+    }
+
+    /// Write back the data, then the inode and the indirect blocks.
+    fn sync(&self, _data_only: bool) -> Result<(), i32> {
+        self.cache.sync().and(self.fs.bdev.cache().sync())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// A mounted ext2 volume.
+struct Ext2SuperBlock {
+    fs: Arc<Ext2Fs>,
+    root: Arc<Ext2Inode>,
+}
+
+impl SuperBlock for Ext2SuperBlock {
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+
+    fn sync(&self) -> Result<(), i32> {
+        self.fs.sync()
+    }
+}
+
+impl Drop for Ext2SuperBlock {
+    fn drop(&mut self) {
+        // The loaded inodes refer to the file system, release them.
+        let inodes = {
+            let _guard = self.fs.table_lock.lock_guard_irq_save();
+            core::mem::take(unsafe { &mut *self.fs.inodes.get() })
+        };
+        drop(inodes);
+        let fs = &self.fs;
+        if !fs.read_only {
+            fs.with_alloc(|alloc| alloc.state = alloc.mount_state);
+            if let Err(errno) = fs.write_super().and(fs.bdev.cache().sync()) {
+                warn!("{}: write the ext2 superblock failed, errno = {}", fs.bdev.name(), errno);
+            }
+        }
+        fs.bdev.cache().invalidate();
+    }
+}
+
+impl Ext2 {
+    /// Read and check the group descriptors of the volume `sb`.
+    fn read_groups(bdev: &BlockDev, sb: &DiskSuperBlock) -> Result<Vec<GroupDesc>, i32> {
+        let block_size = sb.block_size() as u64;
+        let count = sb.group_count() as usize;
+        let mut raw = vec![0u8; count * GROUP_DESC_SIZE];
+        if bdev.cache().read(sb.group_desc_block() as u64 * block_size, &mut raw)? != raw.len() {
+            return Err(E_INVALID);
+        }
+        let table_size = sb.inodes_per_group * sb.inode_size;
+        let table_blocks = (table_size + sb.block_size() - 1) / sb.block_size();
+        let groups: Vec<GroupDesc> = raw.chunks_exact(GROUP_DESC_SIZE).map(GroupDesc::parse)
+            .collect();
+        for (group, desc) in groups.iter().enumerate() {
+            let first = sb.group_first_block(group as u32);
+            let range = first..first + sb.group_blocks(group as u32);
+            if !range.contains(&desc.block_bitmap) || !range.contains(&desc.inode_bitmap) ||
+                !range.contains(&desc.inode_table) ||
+                !range.contains(&(desc.inode_table + table_blocks - 1)) {
+                warn!("{}: bad ext2 group descriptor {}", bdev.name(), group);
+                return Err(E_INVALID);
+            }
+        }
+        Ok(groups)
+    }
+}
+
+impl FileSystem for Ext2 {
+    fn name(&self) -> &'static str {
+        "ext2"
+    }
+
+    /// Mount the ext2 volume on the block device `source`. The `data` is the comma-separated
+    /// options, `ro` or `rw`.
+    fn mount(&self, source: &str, data: &str) -> Result<Arc<dyn SuperBlock>, i32> {
+        let mut read_only = false;
+        for option in data.split(',').filter(|option| !option.is_empty()) {
+            match option {
+                "ro" => read_only = true,
+                "rw" => read_only = false,
+                _ => return Err(E_INVALID),
+            }
+        }
+        let bdev = block::lookup_bdev(source)?;
+        let read_only = read_only || bdev.read_only();
+
+        let mut raw = [0u8; SUPERBLOCK_SIZE];
+        if bdev.cache().read(SUPERBLOCK_OFFSET, &mut raw)? != raw.len() {
+            return Err(E_INVALID);
+        }
+        let mut sb = DiskSuperBlock::parse(&raw).map_err(|err| {
+            warn!("{}: not an ext2 volume: {}", bdev.name(), err);
+            E_INVALID
+        })?;
+        let ro_features = sb.check_features().map_err(|err| {
+            warn!("{}: {}", bdev.name(), err);
+            E_INVALID
+        })?;
+        if ro_features && !read_only {
+            warn!("{}: unsupported read-only features {:#x}, mount it read-only", bdev.name(),
+                sb.feature_ro_compat);
+            return Err(E_INVALID);
+        }
+        let block_size = sb.block_size();
+        if block_size as usize > PAGE_SIZE {
+            warn!("{}: ext2 block size {} is larger than the page size", bdev.name(), block_size);
+            return Err(E_INVALID);
+        }
+        if sb.blocks_count as u64 * block_size as u64 > bdev.size() {
+            warn!("{}: the ext2 volume is larger than the device", bdev.name());
+            return Err(E_INVALID);
+        }
+        let groups = Self::read_groups(&bdev, &sb)?;
+
+        if !read_only {
+            if sb.state & STATE_VALID == 0 {
+                warn!("{}: mounting an unchecked ext2 volume, run e2fsck", bdev.name());
+            } else if sb.state & STATE_ERROR != 0 {
+                warn!("{}: mounting an ext2 volume with errors, run e2fsck", bdev.name());
+            } else if sb.max_mnt_count > 0 && sb.mnt_count >= sb.max_mnt_count as u16 {
+                warn!("{}: maximal mount count reached, run e2fsck", bdev.name());
+            }
+            sb.mnt_count = sb.mnt_count.wrapping_add(1);
+            sb.mtime = to_secs(current_time());
+        }
+        // A file maps the direct, the indirect, the double and the triple indirect blocks, and
+        // counts its sectors in 32 bits.
+        let per_block = (block_size / 4) as u64;
+        let blocks = N_DIRECT as u64 + per_block + per_block.pow(2) + per_block.pow(3);
+        let mut max_size = (blocks * block_size as u64).min((u32::MAX as u64) << 9);
+        if !sb.has_large_file() {
+            max_size = max_size.min(i32::MAX as u64);
+        }
+
+        let alloc = AllocState {
+            free_blocks: groups.iter().map(|desc| desc.free_blocks_count as u32).sum(),
+            free_inodes: groups.iter().map(|desc| desc.free_inodes_count as u32).sum(),
+            groups,
+            state: if read_only { sb.state } else { sb.state & !STATE_VALID },
+            mount_state: sb.state,
+        };
+        let fs = Arc::new(Ext2Fs {
+            bdev,
+            sb,
+            block_size,
+            max_size,
+            read_only,
+            errors: AtomicBool::new(false),
+            next_generation: AtomicU32::new(to_secs(current_time())),
+            dir_lock: SpinLockPure::new(),
+            alloc_lock: SpinLockPure::new(),
+            alloc: UnsafeCell::new(alloc),
+            table_lock: SpinLockPure::new(),
+            inodes: UnsafeCell::new(BTreeMap::new()),
+        });
+        // The volume is not clean on the disk until the unmount.
+        if !read_only {
+            fs.write_super().and(fs.bdev.cache().sync())?;
+        }
+        let root = fs.get_inode(ROOT_INO)?;
+        if root.kind != InodeType::Dir {
+            warn!("{}: the ext2 root is not a directory", fs.bdev.name());
+            return Err(E_INVALID);
+        }
+        info!("ext2: {} has {} blocks of {} bytes in {} groups{}.", fs.bdev.name(),
+            sb.blocks_count, block_size, sb.group_count(),
+            if read_only { ", read-only" } else { "" });
+        Ok(Arc::new(Ext2SuperBlock { fs, root }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vos_core::ext2::{EXT2_MAGIC, FEATURE_INCOMPAT_FILETYPE};
+    use crate::block::ram::RamDisk;
+    use crate::block::{register_disk, unregister_disk, BlockDevice};
+    use crate::fs::file::{O_CREAT, O_DIRECTORY, O_RDONLY, O_RDWR};
+    use crate::fs::lookup_path;
+    use crate::fs::mount::{mount, umount};
+    use crate::fs::vfs;
+
+    const BLOCK_SIZE: usize = 1024;
+    const BLOCKS: u32 = 128;
+    const INODES: u32 = 32;
+    /// The boot block, the superblock, the group descriptors, the 2 bitmaps, 4 blocks of the
+    /// inode table and the root directory.
+    const USED_BLOCKS: u32 = 10;
+    const FIRST_INO: u32 = 11;
+
+    /// Build an empty ext2 volume of 1KiB blocks in one group, with the root directory only.
+    fn ext2_image() -> Vec<u8> {
+        let mut image = vec![0u8; BLOCKS as usize * BLOCK_SIZE];
+        let sb = &mut image[SUPERBLOCK_OFFSET as usize..][..SUPERBLOCK_SIZE];
+        for (offset, value) in [(0, INODES), (4, BLOCKS), (12, BLOCKS - USED_BLOCKS),
+                                (16, INODES - FIRST_INO + 1), (20, 1), (32, 8192), (36, 8192),
+                                (40, INODES), (76, 1), (84, FIRST_INO),
+                                (96, FEATURE_INCOMPAT_FILETYPE)] {
+            sb[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        for (offset, value) in [(54, u16::MAX), (56, EXT2_MAGIC), (58, STATE_VALID), (88, 128)] {
+            sb[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+        }
+
+        let desc = GroupDesc {
+            block_bitmap: 3,
+            inode_bitmap: 4,
+            inode_table: 5,
+            free_blocks_count: (BLOCKS - USED_BLOCKS) as u16,
+            free_inodes_count: (INODES - FIRST_INO + 1) as u16,
+            used_dirs_count: 1,
+        };
+        desc.write(&mut image[2 * BLOCK_SIZE..]);
+        // The bits start from the block 1, the first data block.
+        image[3 * BLOCK_SIZE..][..2].copy_from_slice(&[0xff, 0x01]);
+        image[4 * BLOCK_SIZE..][..2].copy_from_slice(&[0xff, 0x03]);
+
+        let mut root = DiskInode {
+            mode: 0o040755,
+            size: BLOCK_SIZE as u64,
+            links_count: 2,
+            blocks: (BLOCK_SIZE / SECTOR_SIZE) as u32,
+            ..DiskInode::default()
+        };
+        root.block[0] = 9;
+        root.write(&mut image[5 * BLOCK_SIZE + 128..]);
+        let dir = &mut image[9 * BLOCK_SIZE..][..BLOCK_SIZE];
+        DiskEntry { inode: ROOT_INO, rec_len: 12, name_len: 1, file_type: FT_DIR }.write(dir);
+        dir[8] = b'.';
+        let rec_len = (BLOCK_SIZE - 12) as u16;
+        DiskEntry { inode: ROOT_INO, rec_len, name_len: 2, file_type: FT_DIR }
+            .write(&mut dir[12..]);
+        dir[20..22].copy_from_slice(b"..");
+        image
+    }
+
+    fn list_dir(path: &str) -> Vec<String> {
+        let mut names = Vec::new();
+        let dir = vfs::open(None, path, O_RDONLY | O_DIRECTORY, 0).unwrap();
+        dir.read_dir(&mut |entry, _| { names.push(entry.name.clone()); true }).unwrap();
+        names.retain(|name| name != "." && name != "..");
+        names
+    }
+
+    /// Read the superblock behind the cache.
+    fn disk_super(ram: &RamDisk) -> DiskSuperBlock {
+        let mut raw = [0u8; SUPERBLOCK_SIZE];
+        let sector = SUPERBLOCK_OFFSET / SECTOR_SIZE as u64;
+        raw[..SECTOR_SIZE].copy_from_slice(ram.sector(sector));
+        raw[SECTOR_SIZE..].copy_from_slice(ram.sector(sector + 1));
+        DiskSuperBlock::parse(&raw).unwrap()
+    }
+
+    #[kernel_test]
+    fn ext2_create_rename_unlink() {
+        let ram = RamDisk::new(3, ext2_image());
+        register_disk(ram.clone()).unwrap();
+        vfs::mkdir(None, "/ext2_test", 0o755).unwrap();
+        mount("/dev/ram3", "/ext2_test", "ext2", "").unwrap();
+        // The volume is not clean while mounted.
+        assert_eq!(disk_super(&ram).state & STATE_VALID, 0);
+
+        let file = vfs::open(None, "/ext2_test/file", O_RDWR | O_CREAT, 0o644).unwrap();
+        assert_eq!(file.write(&[0x5a; 3000]), Ok(3000));
+        drop(file);
+        vfs::mkdir(None, "/ext2_test/dir", 0o755).unwrap();
+        assert_eq!(vfs::stat(None, "/ext2_test", true).unwrap().nlink, 3);
+        assert_eq!(list_dir("/ext2_test"), ["file", "dir"]);
+
+        // Renaming a file into another directory replaces the target, and moving a directory
+        // updates the link counts.
+        vfs::open(None, "/ext2_test/dir/old", O_RDWR | O_CREAT, 0o600).unwrap();
+        vfs::rename(None, "/ext2_test/file", None, "/ext2_test/dir/old", 0).unwrap();
+        assert_eq!(vfs::stat(None, "/ext2_test/file", true).err(), Some(E_NO_ENT));
+        let stat = vfs::stat(None, "/ext2_test/dir/old", true).unwrap();
+        assert_eq!((stat.size, stat.mode & 0o777, stat.nlink), (3000, 0o644, 1));
+        vfs::mkdir(None, "/ext2_test/sub", 0o755).unwrap();
+        vfs::rename(None, "/ext2_test/sub", None, "/ext2_test/dir/sub", 0).unwrap();
+        assert_eq!(vfs::stat(None, "/ext2_test", true).unwrap().nlink, 3);
+        let dir = vfs::stat(None, "/ext2_test/dir", true).unwrap();
+        assert_eq!(dir.nlink, 3);
+        // The `..` entry on the disk, not the parent dentry.
+        let sub = lookup_path(None, "/ext2_test/dir/sub", true).unwrap();
+        assert_eq!(sub.inode().lookup("..").unwrap().stat().unwrap().ino, dir.ino);
+        drop(sub);
+        assert_eq!(vfs::unlink(None, "/ext2_test/dir", true), Err(E_NOT_EMPTY));
+
+        // The changes are written back by the unmount and found by the next mount.
+        umount("/ext2_test").unwrap();
+        assert_eq!(disk_super(&ram).state & STATE_VALID, STATE_VALID);
+        mount("/dev/ram3", "/ext2_test", "ext2", "").unwrap();
+        assert_eq!(list_dir("/ext2_test/dir"), ["old", "sub"]);
+        let file = vfs::open(None, "/ext2_test/dir/old", O_RDONLY, 0).unwrap();
+        let mut buf = [0u8; 3100];
+        assert_eq!(file.read(&mut buf), Ok(3000));
+        assert!(buf[..3000].iter().all(|&byte| byte == 0x5a));
+        drop(file);
+
+        // The removed files free their blocks and inodes.
+        vfs::unlink(None, "/ext2_test/dir/old", false).unwrap();
+        vfs::unlink(None, "/ext2_test/dir/sub", true).unwrap();
+        vfs::unlink(None, "/ext2_test/dir", true).unwrap();
+        assert!(list_dir("/ext2_test").is_empty());
+        assert_eq!(vfs::stat(None, "/ext2_test", true).unwrap().nlink, 2);
+        umount("/ext2_test").unwrap();
+        let sb = disk_super(&ram);
+        assert_eq!((sb.free_blocks_count, sb.free_inodes_count),
+                   (BLOCKS - USED_BLOCKS, INODES - FIRST_INO + 1));
+        vfs::unlink(None, "/ext2_test", true).unwrap();
+        unregister_disk(ram.major(), ram.first_minor());
+    }
+}
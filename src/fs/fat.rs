@@ -0,0 +1,1274 @@
+//! FAT12/16/32 file system with the long file names (`vfat`).
+//!
+//! The on-disk format is in [`vos_core::fat`]. A volume is mounted from a block device, e.g.
+//! `mount -t vfat /dev/vda1 /mnt`; the mount option `ro` mounts it read-only, and so is a
+//! read-only device.
+//!
+//! - The FAT and the FSInfo sector are read and written through the page cache of the block
+//!   device. A free cluster is searched from the next free hint, which is kept in the FSInfo of
+//!   FAT32 with the free cluster count.
+//! - A file or a directory is a [`PageIo`] with its own page cache: a page is mapped to the
+//!   sectors by walking the cluster chain from the last position walked, and a write extends the
+//!   chain. The fixed root directory of FAT12/16 maps to its sectors directly.
+//! - A directory is read and written through its cache. A name which does not fit a short entry
+//!   gets the long name entries and a generated short name. The names are compared ignoring the
+//!   ASCII case, the short name of a long name matches too. A directory grows by a zeroed
+//!   cluster when it has no free entries.
+//!
+//! An inode is found by the position of its short entry in the volume. The loaded inodes stay in
+//! the inode table until the unmount, so their dirty pages are written back and their inode
+//! numbers, allocated when loading, stay the same. A removed file frees its clusters when its
+//! last open file is closed. There are no symlinks, special files, owners or permissions: the
+//! mode comes from the read-only attribute.
+//!
+//! The namespace changes and the entry updates are serialized by the directory lock of the file
+//! system. The lock order is: the directory lock, the lock of a page cache, the inode lock, then
+//! the FAT lock.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::cell::UnsafeCell;
+use core::ops::Range;
+use core::sync::atomic::{AtomicU64, Ordering};
+use vos_core::fat::{exact_short_name, fat_time_to_unix, is_lfn_entry, lfn_checksum,
+                    lfn_entry_count, numbered_short_name, unix_to_fat_time, write_lfn_entry,
+                    DirEntry as ShortEntry, FatEntry, FatType, FsInfo, LongName, Volume,
+                    ATTR_ARCHIVE, ATTR_DIRECTORY, ATTR_READ_ONLY, DIR_ENTRY_SIZE, DOTDOT_NAME,
+                    DOT_NAME, ENTRY_END, ENTRY_FREE, LFN_LAST, LFN_MAX};
+use crate::base::sync::lock::SpinLockPure;
+use crate::block::{self, BlockDev, SECTOR_SIZE};
+use crate::errno::{E_CROSS_DEV, E_EXIST, E_FILE_TOO_BIG, E_INVALID, E_IO, E_IS_DIR,
+                   E_NAME_TOO_LONG, E_NO_ENT, E_NO_SPACE, E_NOT_DIR, E_NOT_EMPTY, E_PERM,
+                   E_READ_ONLY_FS};
+use crate::fs::inode::{current_time, DirEntry, Inode, InodeType, Stat};
+use crate::fs::mount::{FileSystem, SuperBlock};
+use crate::mm::page_cache::{PageCache, PageIo};
+use crate::mm::PAGE_SIZE;
+
+
+/// Inode number of the root directory.
+const ROOT_INO: u64 = 1;
+/// Max size of a file.
+const FILE_MAX_SIZE: u64 = u32::MAX as u64;
+/// Max size of a directory: 65536 entries.
+const DIR_MAX_SIZE: u64 = 65536 * DIR_ENTRY_SIZE as u64;
+/// Max numeric tail of a generated short name.
+const SHORT_TAIL_MAX: u32 = 999_999;
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+/// Zeros to fill the new clusters of a directory and the gaps of a file.
+static ZEROS: [u8; PAGE_SIZE] = [0; PAGE_SIZE];
+
+/// The `vfat` file system type.
+pub struct VFat;
+
+pub static VFAT: VFat = VFat;
+
+/// Convert a FAT date and time, with the 10ms units `cs`, to the inode time in nanoseconds.
+fn from_fat_time(date: u16, time: u16, cs: u8) -> u64 {
+    fat_time_to_unix(date, time) * NSEC_PER_SEC + cs as u64 * 10_000_000
+}
+
+/// Convert an inode time in nanoseconds to a FAT date and time.
+fn to_fat_time(time: u64) -> (u16, u16) {
+    unix_to_fat_time(time / NSEC_PER_SEC)
+}
+
+/// Check the `name` of a new entry. Returns it without the trailing dots, which are dropped like
+/// Linux does.
+fn check_name(name: &str) -> Result<&str, i32> {
+    let name = name.trim_end_matches('.');
+    if name.is_empty() || name.chars().any(|c| c < ' ' || "\"*/:<>?\\|".contains(c)) {
+        return Err(E_INVALID);
+    }
+    if name.encode_utf16().count() > LFN_MAX {
+        return Err(E_NAME_TOO_LONG);
+    }
+    Ok(name)
+}
+
+/// Free cluster state of a volume, protected by the FAT lock.
+struct AllocState {
+    /// Cluster to search a free one from.
+    next_free: u32,
+    /// Count of the free clusters from the FSInfo, `None` if unknown.
+    free_count: Option<u32>,
+    /// The FSInfo is out of date.
+    fsinfo_dirty: bool,
+}
+
+/// State of a mounted volume shared by its inodes.
+struct FatFs {
+    bdev: Arc<BlockDev>,
+    vol: Volume,
+    read_only: bool,
+    next_ino: AtomicU64,
+    /// Serializes the namespace changes and the entry updates.
+    dir_lock: SpinLockPure,
+    fat_lock: SpinLockPure,
+    alloc: UnsafeCell<AllocState>,
+    table_lock: SpinLockPure,
+    /// Loaded inodes by the position of their short entry, the root at 0.
+    inodes: UnsafeCell<BTreeMap<u64, Arc<FatInode>>>,
+}
+
+impl FatFs {
+    fn check_writable(&self) -> Result<(), i32> {
+        if self.read_only { Err(E_READ_ONLY_FS) } else { Ok(()) }
+    }
+
+    /// Read the metadata at `offset` of the volume through the block device cache.
+    fn read_meta(&self, offset: u64, buf: &mut [u8]) -> Result<(), i32> {
+        match self.bdev.cache().read(offset, buf)? {
+            len if len == buf.len() => Ok(()),
+            _ => Err(E_IO),
+        }
+    }
+
+    /// Write the metadata at `offset` of the volume through the block device cache.
+    fn write_meta(&self, offset: u64, buf: &[u8]) -> Result<(), i32> {
+        match self.bdev.cache().write(offset, buf)? {
+            len if len == buf.len() => Ok(()),
+            _ => Err(E_IO),
+        }
+    }
+
+    /// Read the entry of `cluster` from the active FAT.
+    fn fat_get(&self, cluster: u32) -> Result<FatEntry, i32> {
+        let (pos, len) = self.vol.fat_entry_pos(cluster);
+        let mut raw = [0u8; 4];
+        self.read_meta(self.vol.fat_offset(self.vol.active_fat) + pos, &mut raw[..len])?;
+        Ok(self.vol.fat_entry(cluster, &raw))
+    }
+
+    /// Set the entry of `cluster` to `value` in the FATs in use. The FAT lock must be held.
+    fn fat_set(&self, cluster: u32, value: u32) -> Result<(), i32> {
+        let (pos, len) = self.vol.fat_entry_pos(cluster);
+        let fats = if self.vol.mirror {
+            0..self.vol.num_fats
+        } else {
+            self.vol.active_fat..self.vol.active_fat + 1
+        };
+        for index in fats {
+            let offset = self.vol.fat_offset(index) + pos;
+            let mut raw = [0u8; 4];
+            self.read_meta(offset, &mut raw[..len])?;
+            self.vol.set_fat_entry(cluster, &mut raw, value);
+            self.write_meta(offset, &raw[..len])?;
+        }
+        Ok(())
+    }
+
+    /// Allocate a free cluster as the end of a chain, linked after the cluster `prev` unless it
+    /// is 0.
+    fn alloc_cluster(&self, prev: u32) -> Result<u32, i32> {
+        let _guard = self.fat_lock.lock_guard_irq_save();
+        let alloc = unsafe { &mut *self.alloc.get() };
+        let count = self.vol.cluster_count;
+        if alloc.free_count == Some(0) {
+            return Err(E_NO_SPACE);
+        }
+        for i in 0..count {
+            let cluster = 2 + (alloc.next_free - 2 + i) % count;
+            if self.fat_get(cluster)? != FatEntry::Free {
+                continue;
+            }
+            self.fat_set(cluster, self.vol.end_of_chain())?;
+            if prev != 0 {
+                self.fat_set(prev, cluster)?;
+            }
+            alloc.next_free = if cluster - 1 < count { cluster + 1 } else { 2 };
+            alloc.free_count = alloc.free_count.map(|free| free.saturating_sub(1));
+            alloc.fsinfo_dirty = true;
+            return Ok(cluster);
+        }
+        alloc.free_count = Some(0);
+        Err(E_NO_SPACE)
+    }
+
+    /// Free the chain from `cluster`, and end the chain at the cluster `prev` unless it is 0.
+    fn free_chain(&self, prev: u32, mut cluster: u32) -> Result<(), i32> {
+        let _guard = self.fat_lock.lock_guard_irq_save();
+        let alloc = unsafe { &mut *self.alloc.get() };
+        if prev != 0 {
+            self.fat_set(prev, self.vol.end_of_chain())?;
+        }
+        // A broken chain may loop.
+        for _ in 0..self.vol.cluster_count {
+            if !self.vol.is_data_cluster(cluster) {
+                break;
+            }
+            let next = self.fat_get(cluster)?;
+            if next == FatEntry::Free {
+                break;
+            }
+            self.fat_set(cluster, 0)?;
+            alloc.free_count = alloc.free_count.map(|free| free + 1);
+            alloc.fsinfo_dirty = true;
+            match next {
+                FatEntry::Next(next) => cluster = next,
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Count the clusters of the chain from `cluster`.
+    fn chain_len(&self, mut cluster: u32) -> Result<u32, i32> {
+        let mut len = 0;
+        while self.vol.is_data_cluster(cluster) && len < self.vol.cluster_count {
+            len += 1;
+            match self.fat_get(cluster)? {
+                FatEntry::Next(next) => cluster = next,
+                _ => break,
+            }
+        }
+        Ok(len)
+    }
+
+    /// Write the free cluster hints to the FSInfo sector of FAT32.
+    fn write_fsinfo(&self) -> Result<(), i32> {
+        let info = {
+            let _guard = self.fat_lock.lock_guard_irq_save();
+            let alloc = unsafe { &mut *self.alloc.get() };
+            if self.vol.fsinfo_sector == 0 || !core::mem::take(&mut alloc.fsinfo_dirty) {
+                return Ok(());
+            }
+            FsInfo { free_count: alloc.free_count.unwrap_or(u32::MAX), next_free: alloc.next_free }
+        };
+        let offset = self.vol.fsinfo_sector as u64 * self.vol.bytes_per_sector as u64;
+        let mut sector = [0u8; 512];
+        self.read_meta(offset, &mut sector)?;
+        if FsInfo::parse(&sector).is_some() {
+            info.write(&mut sector);
+            self.write_meta(offset, &sector)?;
+        }
+        Ok(())
+    }
+
+    /// Get the loaded inode of the short entry at `pos`.
+    fn get_loaded(&self, pos: u64) -> Option<Arc<FatInode>> {
+        let _guard = self.table_lock.lock_guard_irq_save();
+        unsafe { (*self.inodes.get()).get(&pos).cloned() }
+    }
+
+    /// Set the position of the short entry of a loaded `inode` from `old` to `new`, or remove it
+    /// from the table if `new` is `None`.
+    fn move_loaded(&self, inode: &Arc<FatInode>, old: u64, new: Option<u64>) {
+        let _guard = self.table_lock.lock_guard_irq_save();
+        let inodes = unsafe { &mut *self.inodes.get() };
+        inodes.remove(&old);
+        if let Some(new) = new {
+            inodes.insert(new, inode.clone());
+        }
+    }
+
+    /// Write back the loaded inodes, the FSInfo and the FAT. Returns the first error.
+    fn sync(&self) -> Result<(), i32> {
+        let inodes: Vec<Arc<FatInode>> = {
+            let _guard = self.table_lock.lock_guard_irq_save();
+            unsafe { (*self.inodes.get()).values().cloned().collect() }
+        };
+        inodes.iter()
+            .fold(Ok(()), |result, inode| result.and(inode.cache.sync()))
+            .and(self.write_fsinfo())
+            .and(self.bdev.cache().sync())
+    }
+}
+
+/// An entry of a directory found by a scan.
+#[derive(Clone)]
+struct Slot {
+    /// Offset of the first long name entry, or of the short entry without a long name.
+    start: u64,
+    /// Offset of the short entry.
+    offset: u64,
+    entry: ShortEntry,
+    /// The long name, or the displayed short name.
+    name: String,
+}
+
+impl Slot {
+    /// Check if the entry is named `name`, by the long or the short name.
+    fn matches(&self, name: &str) -> bool {
+        let mut short = [0u8; 12];
+        let len = self.entry.display_name(&mut short);
+        self.name.eq_ignore_ascii_case(name) || short[..len].eq_ignore_ascii_case(name.as_bytes())
+    }
+}
+
+/// Fields of a FAT inode protected by the inode lock.
+struct FatInodeInner {
+    /// The directory holding the short entry and the offset of the entry in it, `None` for the
+    /// root.
+    parent: Option<(Arc<FatInode>, u64)>,
+    /// Position of the short entry in the volume.
+    pos: u64,
+    attr: u8,
+    first_cluster: u32,
+    /// Size of a file, or of the clusters of a directory.
+    size: u64,
+    /// Link count, counted on the first `stat` for a directory.
+    nlink: Option<u32>,
+    atime: u64,
+    mtime: u64,
+    ctime: u64,
+    /// The entry is removed, the clusters are freed when the inode is dropped.
+    removed: bool,
+    /// Last position of the chain walk: the cluster index in the file and the cluster.
+    hint: (u32, u32),
+}
+
+/// A file or a directory of a FAT volume.
+struct FatInode {
+    fs: Arc<FatFs>,
+    ino: u64,
+    kind: InodeType,
+    /// It is the fixed root directory of FAT12/16.
+    fixed_root: bool,
+    me: Weak<FatInode>,
+    cache: PageCache,
+    lock: SpinLockPure,
+    inner: UnsafeCell<FatInodeInner>,
+}
+
+impl FatInode {
+    fn new(fs: &Arc<FatFs>, kind: InodeType, fixed_root: bool, inner: FatInodeInner)
+        -> Arc<FatInode> {
+        Arc::new_cyclic(|me: &Weak<FatInode>| FatInode {
+            fs: fs.clone(),
+            ino: fs.next_ino.fetch_add(1, Ordering::Relaxed),
+            kind,
+            fixed_root,
+            me: me.clone(),
+            cache: PageCache::new(me.clone() as Weak<dyn PageIo>),
+            lock: SpinLockPure::new(),
+            inner: UnsafeCell::new(inner),
+        })
+    }
+
+    /// Load the root directory.
+    fn new_root(fs: &Arc<FatFs>) -> Result<Arc<FatInode>, i32> {
+        let vol = &fs.vol;
+        let fixed_root = vol.fat_type != FatType::Fat32;
+        let (first_cluster, size) = if fixed_root {
+            (0, vol.root_dir_size())
+        } else {
+            let len = fs.chain_len(vol.root_cluster)?;
+            (vol.root_cluster, len as u64 * vol.cluster_size() as u64)
+        };
+        let root = FatInode::new(fs, InodeType::Dir, fixed_root, FatInodeInner {
+            parent: None,
+            pos: 0,
+            attr: ATTR_DIRECTORY,
+            first_cluster,
+            size,
+            nlink: None,
+            atime: 0,
+            mtime: 0,
+            ctime: 0,
+            removed: false,
+            hint: (0, 0),
+        });
+        fs.move_loaded(&root, 0, Some(0));
+        Ok(root)
+    }
+
+    /// Call `f` with the fields under the inode lock.
+    fn with_inner<F, R>(&self, f: F) -> R where F: FnOnce(&mut FatInodeInner) -> R {
+        let _guard = self.lock.lock_guard_irq_save();
+        f(unsafe { &mut *self.inner.get() })
+    }
+
+    #[inline(always)]
+    fn arc(&self) -> Arc<FatInode> {
+        self.me.upgrade().expect("FAT inode is not in an Arc")
+    }
+
+    /// Get the first cluster of a directory as its `..` entries refer to it, 0 for the root.
+    fn dir_cluster(&self) -> u32 {
+        self.with_inner(|inner| if inner.parent.is_none() { 0 } else { inner.first_cluster })
+    }
+
+    /// Get the cluster `idx` of the chain, the missing clusters are allocated if `alloc`.
+    /// Returns `None` beyond the chain. The inode lock must be held.
+    fn cluster_at(&self, inner: &mut FatInodeInner, idx: u32, alloc: bool)
+        -> Result<Option<u32>, i32> {
+        let fs = &*self.fs;
+        if inner.first_cluster == 0 {
+            if !alloc {
+                return Ok(None);
+            }
+            inner.first_cluster = fs.alloc_cluster(0)?;
+            inner.hint = (0, inner.first_cluster);
+        }
+        let (mut i, mut cluster) = match inner.hint {
+            (i, cluster) if cluster != 0 && i <= idx => (i, cluster),
+            _ => (0, inner.first_cluster),
+        };
+        while i < idx {
+            cluster = match fs.fat_get(cluster)? {
+                FatEntry::Next(next) => next,
+                FatEntry::End if alloc => fs.alloc_cluster(cluster)?,
+                FatEntry::End => return Ok(None),
+                FatEntry::Free | FatEntry::Bad => {
+                    warn!("{}: broken cluster chain of inode {}", fs.bdev.name(), self.ino);
+                    return Err(E_IO);
+                }
+            };
+            i += 1;
+        }
+        inner.hint = (i, cluster);
+        Ok(Some(cluster))
+    }
+
+    /// Free the clusters of the chain from the index `keep`. The inode lock must be held.
+    fn free_clusters_from(&self, inner: &mut FatInodeInner, keep: u32) -> Result<(), i32> {
+        if inner.first_cluster == 0 {
+            return Ok(());
+        }
+        if keep == 0 {
+            inner.hint = (0, 0);
+            return self.fs.free_chain(0, core::mem::take(&mut inner.first_cluster));
+        }
+        let last = self.cluster_at(inner, keep - 1, false)?;
+        inner.hint = (0, 0);
+        match last {
+            Some(last) => match self.fs.fat_get(last)? {
+                FatEntry::Next(next) => self.fs.free_chain(last, next),
+                _ => Ok(()),
+            },
+            None => Ok(()),
+        }
+    }
+
+    /// Write zeros to the bytes `from..to` at the end of a file.
+    fn zero_fill(&self, from: u64, to: u64) -> Result<(), i32> {
+        let mut pos = from;
+        while pos < to {
+            let len = (to - pos).min(PAGE_SIZE as u64) as usize;
+            match self.cache.write(pos, &ZEROS[..len])? {
+                0 => return Err(E_NO_SPACE),
+                written => pos += written as u64,
+            }
+        }
+        Ok(())
+    }
+
+    /// Position in the volume of the entry at `offset` of the directory.
+    fn entry_pos(&self, offset: u64) -> Result<u64, i32> {
+        let vol = &self.fs.vol;
+        if self.fixed_root {
+            return Ok(vol.root_dir_offset() + offset);
+        }
+        let cluster_size = vol.cluster_size() as u64;
+        let idx = (offset / cluster_size) as u32;
+        let cluster = self.with_inner(|inner| self.cluster_at(inner, idx, false))?.ok_or(E_IO)?;
+        Ok(vol.cluster_offset(cluster) + offset % cluster_size)
+    }
+
+    /// Call `f` with the entries of the directory from the offset `from`, until it returns
+    /// `false`. The free entries, the volume label, `.` and `..` are skipped.
+    fn scan<F>(&self, from: u64, mut f: F) -> Result<(), i32> where F: FnMut(&Slot) -> bool {
+        let mut buf = [0u8; SECTOR_SIZE];
+        let mut long = LongName::new();
+        let mut start = from;
+        let mut offset = from;
+        loop {
+            let len = self.cache.read(offset, &mut buf)?;
+            if len < DIR_ENTRY_SIZE {
+                return Ok(());
+            }
+            for raw in buf[..len].chunks_exact(DIR_ENTRY_SIZE) {
+                let pos = offset;
+                offset += DIR_ENTRY_SIZE as u64;
+                match raw[0] {
+                    ENTRY_END => return Ok(()),
+                    ENTRY_FREE => {
+                        long.reset();
+                        start = offset;
+                        continue;
+                    }
+                    _ => {}
+                }
+                if is_lfn_entry(raw) {
+                    // The last long name entry comes first.
+                    if raw[0] & LFN_LAST != 0 {
+                        start = pos;
+                    }
+                    long.push(raw);
+                    continue;
+                }
+
+                let entry = ShortEntry::parse(raw);
+                let name = match long.finish(&entry.name) {
+                    Some(units) => String::from_utf16_lossy(units),
+                    None => {
+                        start = pos;
+                        let mut short = [0u8; 12];
+                        let len = entry.display_name(&mut short);
+                        String::from_utf8_lossy(&short[..len]).into_owned()
+                    }
+                };
+                if !entry.is_volume_label() && !entry.is_dot() &&
+                    !f(&Slot { start, offset: pos, entry, name }) {
+                    return Ok(());
+                }
+                start = offset;
+            }
+        }
+    }
+
+    /// Find the entry `name` in the directory.
+    fn find(&self, name: &str) -> Result<Option<Slot>, i32> {
+        let name = name.trim_end_matches('.');
+        let mut found = None;
+        self.scan(0, |slot| {
+            if slot.matches(name) {
+                found = Some(slot.clone());
+            }
+            found.is_none()
+        })?;
+        Ok(found)
+    }
+
+    /// Check if the directory has no entries but `.` and `..`.
+    fn is_empty_dir(&self) -> Result<bool, i32> {
+        let mut empty = true;
+        self.scan(0, |_| {
+            empty = false;
+            false
+        })?;
+        Ok(empty)
+    }
+
+    /// Get the inode of the short `entry` at `offset` of the directory, loading it if needed.
+    /// The directory lock must be held.
+    fn get_inode(&self, offset: u64, entry: &ShortEntry) -> Result<Arc<FatInode>, i32> {
+        let pos = self.entry_pos(offset)?;
+        if let Some(inode) = self.fs.get_loaded(pos) {
+            return Ok(inode);
+        }
+
+        let (kind, size, nlink) = if entry.is_dir() {
+            let len = self.fs.chain_len(entry.first_cluster)? as u64;
+            (InodeType::Dir, len * self.fs.vol.cluster_size() as u64, None)
+        } else {
+            (InodeType::File, entry.size as u64, Some(1))
+        };
+        let inode = FatInode::new(&self.fs, kind, false, FatInodeInner {
+            parent: Some((self.arc(), offset)),
+            pos,
+            attr: entry.attr,
+            first_cluster: entry.first_cluster,
+            size,
+            nlink,
+            atime: from_fat_time(entry.adate, 0, 0),
+            mtime: from_fat_time(entry.mdate, entry.mtime, 0),
+            ctime: from_fat_time(entry.cdate, entry.ctime, entry.ctime_cs),
+            removed: false,
+            hint: (0, 0),
+        });
+        self.fs.move_loaded(&inode, pos, Some(pos));
+        Ok(inode)
+    }
+
+    /// Add a zeroed cluster to the end of the directory.
+    fn grow(&self) -> Result<(), i32> {
+        let cluster_size = self.fs.vol.cluster_size() as u64;
+        let size = self.size();
+        if self.fixed_root || size + cluster_size > DIR_MAX_SIZE {
+            return Err(E_NO_SPACE);
+        }
+        self.zero_fill(size, size + cluster_size)?;
+        self.with_inner(|inner| inner.size += cluster_size);
+        Ok(())
+    }
+
+    /// Find `count` consecutive free entries in the directory. Returns the offset of the first,
+    /// which may be at the end of the directory.
+    fn find_free(&self, count: usize) -> Result<u64, i32> {
+        let mut buf = [0u8; SECTOR_SIZE];
+        let mut offset = 0;
+        let mut run = (0, 0);
+        loop {
+            let len = self.cache.read(offset, &mut buf)?;
+            if len < DIR_ENTRY_SIZE {
+                return Ok(if run.1 > 0 { run.0 } else { offset });
+            }
+            for raw in buf[..len].chunks_exact(DIR_ENTRY_SIZE) {
+                let pos = offset;
+                offset += DIR_ENTRY_SIZE as u64;
+                match raw[0] {
+                    // All entries after the end are free.
+                    ENTRY_END => return Ok(if run.1 > 0 { run.0 } else { pos }),
+                    ENTRY_FREE if run.1 == 0 => run = (pos, 1),
+                    ENTRY_FREE => run.1 += 1,
+                    _ => run = (0, 0),
+                }
+                if run.1 == count {
+                    return Ok(run.0);
+                }
+            }
+        }
+    }
+
+    /// Add the entries of `name` to the directory, with the long name entries unless the name
+    /// fits a short entry. The short name of `entry` is set. Returns the offset of the short
+    /// entry. The directory lock must be held.
+    fn add_entry(&self, name: &str, entry: &mut ShortEntry) -> Result<u64, i32> {
+        let mut used = Vec::new();
+        self.scan(0, |slot| {
+            used.push(slot.entry.name);
+            true
+        })?;
+        let units: Vec<u16> = match exact_short_name(name) {
+            Some((short, case)) if !used.contains(&short) => {
+                entry.name = short;
+                entry.case = case;
+                Vec::new()
+            }
+            _ => {
+                let n = (1..=SHORT_TAIL_MAX)
+                    .find(|&n| !used.contains(&numbered_short_name(name, n)))
+                    .ok_or(E_EXIST)?;
+                entry.name = numbered_short_name(name, n);
+                entry.case = 0;
+                name.encode_utf16().collect()
+            }
+        };
+
+        let count = lfn_entry_count(units.len());
+        let len = (count + 1) * DIR_ENTRY_SIZE;
+        let start = self.find_free(count + 1)?;
+        while self.size() < start + len as u64 {
+            self.grow()?;
+        }
+        let mut raw = vec![0u8; len];
+        let checksum = lfn_checksum(&entry.name);
+        for (i, lfn) in raw.chunks_exact_mut(DIR_ENTRY_SIZE).take(count).enumerate() {
+            write_lfn_entry(lfn, &units, count - i, checksum);
+        }
+        entry.write(&mut raw[count * DIR_ENTRY_SIZE..]);
+        if self.cache.write(start, &raw)? != len {
+            return Err(E_NO_SPACE);
+        }
+        Ok(start + (count * DIR_ENTRY_SIZE) as u64)
+    }
+
+    /// Mark the entries of the `slot` free.
+    fn remove_entries(&self, slot: &Slot) -> Result<(), i32> {
+        let mut pos = slot.start;
+        while pos <= slot.offset {
+            self.cache.write(pos, &[ENTRY_FREE])?;
+            pos += DIR_ENTRY_SIZE as u64;
+        }
+        Ok(())
+    }
+
+    /// Mark the inode removed from its directory, its clusters are freed when it is dropped.
+    fn set_removed(self: &Arc<Self>) {
+        let pos = self.with_inner(|inner| {
+            inner.removed = true;
+            inner.nlink = Some(0);
+            inner.ctime = current_time();
+            inner.pos
+        });
+        self.fs.move_loaded(self, pos, None);
+    }
+
+    /// Add `delta` to the link count of a directory if it is counted.
+    fn add_nlink(&self, delta: i32) {
+        self.with_inner(|inner| {
+            if let Some(nlink) = &mut inner.nlink {
+                *nlink = nlink.wrapping_add(delta as u32);
+            }
+        });
+    }
+
+    /// Write the attributes to the short entry. The directory lock must be held.
+    fn write_entry(&self) -> Result<(), i32> {
+        let (parent, entry) = self.with_inner(|inner| {
+            let entry = (inner.first_cluster, inner.size, inner.attr, inner.mtime, inner.atime);
+            (inner.parent.clone().filter(|_| !inner.removed), entry)
+        });
+        let Some((parent, offset)) = parent else {
+            return Ok(());
+        };
+        let (first_cluster, size, attr, mtime, atime) = entry;
+        let mut raw = [0u8; DIR_ENTRY_SIZE];
+        if parent.cache.read(offset, &mut raw)? != raw.len() {
+            return Err(E_IO);
+        }
+        let mut short = ShortEntry::parse(&raw);
+        short.first_cluster = first_cluster;
+        short.size = if self.kind == InodeType::Dir { 0 } else { size as u32 };
+        short.attr = attr;
+        (short.mdate, short.mtime) = to_fat_time(mtime);
+        short.adate = to_fat_time(atime).0;
+        short.write(&mut raw);
+        parent.cache.write(offset, &raw)?;
+        Ok(())
+    }
+
+    /// Update the modification time of the directory after a change. The directory lock must be
+    /// held.
+    fn touch(&self) -> Result<(), i32> {
+        self.with_inner(|inner| {
+            inner.mtime = current_time();
+            inner.ctime = inner.mtime;
+        });
+        self.write_entry()
+    }
+
+    /// Fill the first cluster of a new directory: `.`, `..` to the `parent`, then zeros.
+    fn init_dir(&self, parent: &FatInode, entry: &ShortEntry) -> Result<(), i32> {
+        let mut buf = vec![0u8; self.fs.vol.cluster_size() as usize];
+        let mut dot = ShortEntry { name: DOT_NAME, case: 0, size: 0, ..*entry };
+        dot.write(&mut buf[..DIR_ENTRY_SIZE]);
+        dot.name = DOTDOT_NAME;
+        dot.first_cluster = parent.dir_cluster();
+        dot.write(&mut buf[DIR_ENTRY_SIZE..DIR_ENTRY_SIZE * 2]);
+        match self.cache.write(0, &buf)? {
+            len if len == buf.len() => Ok(()),
+            _ => Err(E_NO_SPACE),
+        }
+    }
+
+    /// Point the `..` entry of a moved directory to the `parent`.
+    fn set_dotdot(&self, parent: &FatInode) -> Result<(), i32> {
+        let offset = DIR_ENTRY_SIZE as u64;
+        let mut raw = [0u8; DIR_ENTRY_SIZE];
+        if self.cache.read(offset, &mut raw)? != raw.len() || raw[..11] != DOTDOT_NAME {
+            return Ok(());
+        }
+        let mut entry = ShortEntry::parse(&raw);
+        entry.first_cluster = parent.dir_cluster();
+        entry.write(&mut raw);
+        self.cache.write(offset, &raw)?;
+        Ok(())
+    }
+
+    /// Get the link count, counting the subdirectories of a directory on the first call.
+    fn nlink(&self) -> Result<u32, i32> {
+        if let Some(nlink) = self.with_inner(|inner| inner.nlink) {
+            return Ok(nlink);
+        }
+        let _guard = self.fs.dir_lock.lock_guard_irq_save();
+        let mut nlink = 2;
+        self.scan(0, |slot| {
+            if slot.entry.is_dir() {
+                nlink += 1;
+            }
+            true
+        })?;
+        Ok(self.with_inner(|inner| *inner.nlink.get_or_insert(nlink)))
+    }
+
+    fn check_dir(&self) -> Result<(), i32> {
+        if self.kind == InodeType::Dir { Ok(()) } else { Err(E_NOT_DIR) }
+    }
+
+    fn check_file(&self) -> Result<(), i32> {
+        if self.kind == InodeType::Dir { Err(E_IS_DIR) } else { Ok(()) }
+    }
+}
+
+impl Drop for FatInode {
+    fn drop(&mut self) {
+        let inner = self.inner.get_mut();
+        if inner.removed && inner.first_cluster != 0 {
+            self.cache.truncate(0);
+            if let Err(errno) = self.fs.free_chain(0, core::mem::take(&mut inner.first_cluster)) {
+                warn!("{}: free clusters of inode {} failed, errno = {}", self.fs.bdev.name(),
+                    self.ino, errno);
+            }
+        }
+    }
+}
+
+impl PageIo for FatInode {
+    fn cache(&self) -> &PageCache {
+        &self.cache
+    }
+
+    fn bdev(&self) -> &BlockDev {
+        &self.fs.bdev
+    }
+
+    fn size(&self) -> u64 {
+        self.with_inner(|inner| inner.size)
+    }
+
+    fn block_size(&self) -> usize {
+        if self.fixed_root {
+            SECTOR_SIZE
+        } else {
+            (self.fs.vol.cluster_size() as usize).min(PAGE_SIZE)
+        }
+    }
+
+    fn map_page(&self, index: u64, alloc: Range<usize>, blocks: &mut [Option<u64>])
+        -> Result<(), i32> {
+        let vol = &self.fs.vol;
+        let block_size = self.block_size();
+        let start = index * PAGE_SIZE as u64;
+        if self.fixed_root {
+            let size = vol.root_dir_size();
+            if !alloc.is_empty() && start + alloc.end as u64 > size {
+                return Err(E_NO_SPACE);
+            }
+            for (i, block) in blocks.iter_mut().enumerate() {
+                let pos = start + (i * block_size) as u64;
+                *block = (pos < size).then(|| (vol.root_dir_offset() + pos) / SECTOR_SIZE as u64);
+            }
+            return Ok(());
+        }
+
+        let cluster_size = vol.cluster_size() as u64;
+        self.with_inner(|inner| {
+            for (i, block) in blocks.iter_mut().enumerate() {
+                let in_page = i * block_size;
+                let pos = start + in_page as u64;
+                let alloc = alloc.start < in_page + block_size && in_page < alloc.end;
+                let cluster = self.cluster_at(inner, (pos / cluster_size) as u32, alloc)?;
+                *block = cluster.map(|cluster| {
+                    (vol.cluster_offset(cluster) + pos % cluster_size) / SECTOR_SIZE as u64
+                });
+            }
+            Ok(())
+        })
+    }
+}
+
+impl Inode for FatInode {
+    fn stat(&self) -> Result<Stat, i32> {
+        let nlink = self.nlink()?;
+        let cluster_size = self.fs.vol.cluster_size() as u64;
+        Ok(self.with_inner(|inner| {
+            let perm = if self.kind == InodeType::Dir { 0o755 } else { 0o644 };
+            let mut mode = self.kind.mode() | perm;
+            if inner.attr & ATTR_READ_ONLY != 0 {
+                mode &= !0o222;
+            }
+            let clusters = match self.kind {
+                InodeType::Dir => inner.size / cluster_size,
+                _ => (inner.size + cluster_size - 1) / cluster_size,
+            };
+            Stat {
+                dev: self.fs.bdev.dev(),
+                ino: self.ino,
+                mode,
+                nlink,
+                size: inner.size,
+                blksize: cluster_size as u32,
+                blocks: clusters * cluster_size / 512,
+                atime: inner.atime,
+                mtime: inner.mtime,
+                ctime: inner.ctime,
+                ..Stat::default()
+            }
+        }))
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, i32> {
+        self.check_file()?;
+        self.cache.read(offset as u64, buf)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, i32> {
+        self.check_file()?;
+        self.fs.check_writable()?;
+        let offset = offset as u64;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if offset >= FILE_MAX_SIZE {
+            return Err(E_FILE_TOO_BIG);
+        }
+        let buf = &buf[..buf.len().min((FILE_MAX_SIZE - offset) as usize)];
+
+        // The clusters may hold stale data beyond the end.
+        let size = self.size();
+        if offset > size {
+            self.zero_fill(size, offset)?;
+        }
+        let written = self.cache.write(offset, buf)?;
+        self.with_inner(|inner| {
+            inner.size = inner.size.max(offset + written as u64);
+            inner.mtime = current_time();
+            inner.ctime = inner.mtime;
+            inner.attr |= ATTR_ARCHIVE;
+        });
+        let _guard = self.fs.dir_lock.lock_guard_irq_save();
+        self.write_entry()?;
+        Ok(written)
+    }
+
+    fn truncate(&self, size: usize) -> Result<(), i32> {
+        self.check_file()?;
+        self.fs.check_writable()?;
+        let size = size as u64;
+        if size > FILE_MAX_SIZE {
+            return Err(E_FILE_TOO_BIG);
+        }
+        let old = self.size();
+        if size > old {
+            self.zero_fill(old, size)?;
+        }
+        self.with_inner(|inner| {
+            inner.size = size;
+            inner.mtime = current_time();
+            inner.ctime = inner.mtime;
+            inner.attr |= ATTR_ARCHIVE;
+        });
+        if size < old {
+            self.cache.truncate(size);
+            let cluster_size = self.fs.vol.cluster_size() as u64;
+            let keep = ((size + cluster_size - 1) / cluster_size) as u32;
+            self.with_inner(|inner| self.free_clusters_from(inner, keep))?;
+        }
+        let _guard = self.fs.dir_lock.lock_guard_irq_save();
+        self.write_entry()
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, i32> {
+        self.check_dir()?;
+        let _guard = self.fs.dir_lock.lock_guard_irq_save();
+        let slot = self.find(name)?.ok_or(E_NO_ENT)?;
+        Ok(self.get_inode(slot.offset, &slot.entry)? as Arc<dyn Inode>)
+    }
+
+    fn create(&self, name: &str, kind: InodeType, mode: u32) -> Result<Arc<dyn Inode>, i32> {
+        self.check_dir()?;
+        if kind != InodeType::File && kind != InodeType::Dir {
+            return Err(E_PERM);
+        }
+        self.fs.check_writable()?;
+        let name = check_name(name)?;
+        let _guard = self.fs.dir_lock.lock_guard_irq_save();
+        if self.find(name)?.is_some() {
+            return Err(E_EXIST);
+        }
+
+        let now = to_fat_time(current_time());
+        let mut entry = ShortEntry {
+            attr: if kind == InodeType::Dir { ATTR_DIRECTORY } else { ATTR_ARCHIVE },
+            cdate: now.0,
+            ctime: now.1,
+            adate: now.0,
+            mdate: now.0,
+            mtime: now.1,
+            ..ShortEntry::default()
+        };
+        if mode & 0o222 == 0 {
+            entry.attr |= ATTR_READ_ONLY;
+        }
+        if kind == InodeType::Dir {
+            entry.first_cluster = self.fs.alloc_cluster(0)?;
+        }
+        let offset = match self.add_entry(name, &mut entry) {
+            Ok(offset) => offset,
+            Err(errno) => {
+                if entry.first_cluster != 0 {
+                    let _ = self.fs.free_chain(0, entry.first_cluster);
+                }
+                return Err(errno);
+            }
+        };
+        let inode = self.get_inode(offset, &entry)?;
+        if kind == InodeType::Dir {
+            inode.init_dir(self, &entry)?;
+            self.add_nlink(1);
+        }
+        self.touch()?;
+        Ok(inode as Arc<dyn Inode>)
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), i32> {
+        self.check_dir()?;
+        self.fs.check_writable()?;
+        let _guard = self.fs.dir_lock.lock_guard_irq_save();
+        let slot = self.find(name)?.ok_or(E_NO_ENT)?;
+        let child = self.get_inode(slot.offset, &slot.entry)?;
+        if child.kind == InodeType::Dir {
+            if !child.is_empty_dir()? {
+                return Err(E_NOT_EMPTY);
+            }
+            self.add_nlink(-1);
+        }
+        self.remove_entries(&slot)?;
+        child.set_removed();
+        self.touch()
+    }
+
+    fn rename(&self, old_name: &str, new_dir: &Arc<dyn Inode>, new_name: &str)
+        -> Result<(), i32> {
+        self.check_dir()?;
+        let new_dir = new_dir.as_any().downcast_ref::<FatInode>()
+            .filter(|dir| Arc::ptr_eq(&dir.fs, &self.fs))
+            .ok_or(E_CROSS_DEV)?;
+        new_dir.check_dir()?;
+        self.fs.check_writable()?;
+        let new_name = check_name(new_name)?;
+        let _guard = self.fs.dir_lock.lock_guard_irq_save();
+        let slot = self.find(old_name)?.ok_or(E_NO_ENT)?;
+        let child = self.get_inode(slot.offset, &slot.entry)?;
+        let is_dir = child.kind == InodeType::Dir;
+        let same_dir = core::ptr::eq(self, new_dir);
+
+        if let Some(target_slot) = new_dir.find(new_name)? {
+            let target = new_dir.get_inode(target_slot.offset, &target_slot.entry)?;
+            // Renaming to the same name in another case rewrites the entries.
+            if !Arc::ptr_eq(&target, &child) {
+                match (is_dir, target.kind == InodeType::Dir) {
+                    (true, false) => return Err(E_NOT_DIR),
+                    (false, true) => return Err(E_IS_DIR),
+                    (true, true) if !target.is_empty_dir()? => return Err(E_NOT_EMPTY),
+                    (true, true) => new_dir.add_nlink(-1),
+                    _ => {}
+                }
+                new_dir.remove_entries(&target_slot)?;
+                target.set_removed();
+            }
+        }
+
+        // The new entry keeps the attributes, the times and the clusters.
+        let mut entry = slot.entry;
+        let offset = new_dir.add_entry(new_name, &mut entry)?;
+        self.remove_entries(&slot)?;
+        let pos = new_dir.entry_pos(offset)?;
+        let old_pos = child.with_inner(|inner| {
+            inner.parent = Some((new_dir.arc(), offset));
+            inner.ctime = current_time();
+            core::mem::replace(&mut inner.pos, pos)
+        });
+        self.fs.move_loaded(&child, old_pos, Some(pos));
+        if is_dir && !same_dir {
+            child.set_dotdot(new_dir)?;
+            self.add_nlink(-1);
+            new_dir.add_nlink(1);
+        }
+
+        self.touch()?;
+        if !same_dir {
+            new_dir.touch()?;
+        }
+        Ok(())
+    }
+
+    fn read_dir(&self, offset: usize) -> Result<Option<(DirEntry, usize)>, i32> {
+        self.check_dir()?;
+        let _guard = self.fs.dir_lock.lock_guard_irq_save();
+        let entry = match offset {
+            0 => DirEntry { ino: self.ino, kind: InodeType::Dir, name: String::from(".") },
+            1 => {
+                let parent = self.with_inner(|inner| {
+                    inner.parent.as_ref().map_or(self.ino, |(parent, _)| parent.ino)
+                });
+                DirEntry { ino: parent, kind: InodeType::Dir, name: String::from("..") }
+            }
+            _ => {
+                let mut found = None;
+                self.scan(offset as u64 - 2, |slot| {
+                    found = Some(slot.clone());
+                    false
+                })?;
+                let Some(slot) = found else {
+                    return Ok(None);
+                };
+                let child = self.get_inode(slot.offset, &slot.entry)?;
+                let next = slot.offset as usize + DIR_ENTRY_SIZE + 2;
+                return Ok(Some((DirEntry { ino: child.ino, kind: child.kind, name: slot.name },
+                                next)));
+            }
+        };
+        Ok(Some((entry, offset + 1)))
+    }
+
+    /// Write back the data, the entry in the parent directory and the FAT.
+    fn sync(&self, _data_only: bool) -> Result<(), i32> {
+        let parent = self.with_inner(|inner| inner.parent.as_ref().map(|(dir, _)| dir.clone()));
+        let result = self.cache.sync();
+        let result = match parent {
+            Some(parent) => result.and(parent.cache.sync()),
+            None => result,
+        };
+        result.and(self.fs.bdev.cache().sync())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// A mounted FAT volume.
+struct FatSuperBlock {
+    fs: Arc<FatFs>,
+    root: Arc<FatInode>,
+}
+
+impl SuperBlock for FatSuperBlock {
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+
+    fn sync(&self) -> Result<(), i32> {
+        self.fs.sync()
+    }
+}
+
+impl Drop for FatSuperBlock {
+    fn drop(&mut self) {
+        // The loaded inodes refer to the file system, release them.
+        let inodes = {
+            let _guard = self.fs.table_lock.lock_guard_irq_save();
+            core::mem::take(unsafe { &mut *self.fs.inodes.get() })
+        };
+        drop(inodes);
+        self.fs.bdev.cache().invalidate();
+    }
+}
+
+impl FileSystem for VFat {
+    fn name(&self) -> &'static str {
+        "vfat"
+    }
+
+    /// Mount the FAT volume on the block device `source`. The `data` is the comma-separated
+    /// options, `ro` or `rw`.
+    fn mount(&self, source: &str, data: &str) -> Result<Arc<dyn SuperBlock>, i32> {
+        let mut read_only = false;
+        for option in data.split(',').filter(|option| !option.is_empty()) {
+            match option {
+                "ro" => read_only = true,
+                "rw" => read_only = false,
+                _ => return Err(E_INVALID),
+            }
+        }
+        let bdev = block::lookup_bdev(source)?;
+        let read_only = read_only || bdev.read_only();
+
+        let mut boot = [0u8; 512];
+        if bdev.cache().read(0, &mut boot)? != boot.len() {
+            return Err(E_INVALID);
+        }
+        let vol = Volume::parse(&boot).map_err(|err| {
+            warn!("{}: not a FAT volume: {}", bdev.name(), err);
+            E_INVALID
+        })?;
+        if vol.total_sectors as u64 * vol.bytes_per_sector as u64 > bdev.size() {
+            warn!("{}: the FAT volume is larger than the device", bdev.name());
+            return Err(E_INVALID);
+        }
+
+        let mut alloc = AllocState { next_free: 2, free_count: None, fsinfo_dirty: false };
+        if vol.fsinfo_sector != 0 {
+            let mut sector = [0u8; 512];
+            let offset = vol.fsinfo_sector as u64 * vol.bytes_per_sector as u64;
+            bdev.cache().read(offset, &mut sector)?;
+            if let Some(info) = FsInfo::parse(&sector) {
+                alloc.free_count = Some(info.free_count).filter(|&free| free <= vol.cluster_count);
+                if vol.is_data_cluster(info.next_free) {
+                    alloc.next_free = info.next_free;
+                }
+            }
+        }
+
+        let fs = Arc::new(FatFs {
+            bdev,
+            vol,
+            read_only,
+            next_ino: AtomicU64::new(ROOT_INO),
+            dir_lock: SpinLockPure::new(),
+            fat_lock: SpinLockPure::new(),
+            alloc: UnsafeCell::new(alloc),
+            table_lock: SpinLockPure::new(),
+            inodes: UnsafeCell::new(BTreeMap::new()),
+        });
+        let root = FatInode::new_root(&fs)?;
+        info!("vfat: {} is {:?}, {} clusters of {} bytes{}.", fs.bdev.name(), vol.fat_type,
+            vol.cluster_count, vol.cluster_size(), if read_only { ", read-only" } else { "" });
+        Ok(Arc::new(FatSuperBlock { fs, root }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::ram::RamDisk;
+    use crate::block::{register_disk, unregister_disk, BlockDevice};
+    use crate::fs::file::{O_CREAT, O_DIRECTORY, O_RDONLY, O_RDWR};
+    use crate::fs::mount::{mount, umount};
+    use crate::fs::vfs;
+
+    /// Build an empty FAT12 volume of 128 sectors: 1 reserved sector, 1 FAT of 1 sector, a root
+    /// directory of 16 entries and 125 clusters of 1 sector.
+    fn fat12_image() -> Vec<u8> {
+        let mut image = vec![0u8; 128 * SECTOR_SIZE];
+        image[..3].copy_from_slice(&[0xeb, 0x3c, 0x90]);
+        image[11..13].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+        image[13] = 1;
+        image[14..16].copy_from_slice(&1u16.to_le_bytes());
+        image[16] = 1;
+        image[17..19].copy_from_slice(&16u16.to_le_bytes());
+        image[19..21].copy_from_slice(&128u16.to_le_bytes());
+        image[21] = 0xf8;
+        image[22..24].copy_from_slice(&1u16.to_le_bytes());
+        image[510..512].copy_from_slice(&[0x55, 0xaa]);
+        image[SECTOR_SIZE..SECTOR_SIZE + 3].copy_from_slice(&[0xf8, 0xff, 0xff]);
+        image
+    }
+
+    fn list_dir(path: &str) -> Vec<String> {
+        let mut names = Vec::new();
+        let dir = vfs::open(None, path, O_RDONLY | O_DIRECTORY, 0).unwrap();
+        dir.read_dir(&mut |entry, _| { names.push(entry.name.clone()); true }).unwrap();
+        names.retain(|name| name != "." && name != "..");
+        names
+    }
+
+    #[kernel_test]
+    fn fat_create_rename_unlink() {
+        let ram = RamDisk::new(2, fat12_image());
+        register_disk(ram.clone()).unwrap();
+        vfs::mkdir(None, "/fat_test", 0o755).unwrap();
+        mount("/dev/ram2", "/fat_test", "vfat", "").unwrap();
+
+        let file = vfs::open(None, "/fat_test/Long Name.txt", O_RDWR | O_CREAT, 0o644).unwrap();
+        assert_eq!(file.write(b"hello"), Ok(5));
+        drop(file);
+        vfs::mkdir(None, "/fat_test/dir", 0o755).unwrap();
+        assert_eq!(list_dir("/fat_test"), ["Long Name.txt", "dir"]);
+
+        // The names are case-insensitive, a file can be renamed into another directory.
+        assert!(vfs::stat(None, "/fat_test/LONG NAME.TXT", true).is_ok());
+        vfs::rename(None, "/fat_test/long name.txt", None, "/fat_test/dir/short.txt", 0).unwrap();
+        assert_eq!(vfs::stat(None, "/fat_test/Long Name.txt", true).err(), Some(E_NO_ENT));
+        assert_eq!(list_dir("/fat_test"), ["dir"]);
+        assert_eq!(vfs::stat(None, "/fat_test/dir/short.txt", true).unwrap().size, 5);
+        assert_eq!(vfs::unlink(None, "/fat_test/dir", true), Err(E_NOT_EMPTY));
+
+        // The changes are written back by the unmount and found by the next mount.
+        umount("/fat_test").unwrap();
+        assert!(ram.written.load(Ordering::Relaxed) > 0);
+        mount("/dev/ram2", "/fat_test", "vfat", "").unwrap();
+        assert_eq!(list_dir("/fat_test/dir"), ["short.txt"]);
+        let file = vfs::open(None, "/fat_test/dir/short.txt", O_RDONLY, 0).unwrap();
+        let mut buf = [0u8; 8];
+        assert_eq!(file.read(&mut buf), Ok(5));
+        assert_eq!(&buf[..5], b"hello");
+        drop(file);
+
+        vfs::unlink(None, "/fat_test/dir/short.txt", false).unwrap();
+        vfs::unlink(None, "/fat_test/dir", true).unwrap();
+        assert!(list_dir("/fat_test").is_empty());
+        umount("/fat_test").unwrap();
+        vfs::unlink(None, "/fat_test", true).unwrap();
+        unregister_disk(ram.major(), ram.first_minor());
+    }
+}
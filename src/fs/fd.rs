@@ -0,0 +1,127 @@
+//! File descriptor tables.
+//!
+//! Each process owns a [`FdTable`] shared by its threads, which maps the file descriptors to the
+//! open [`File`]s. A forked child gets a copy of the table referring to the same files, and the
+//! descriptors with the close-on-exec flag are closed by `execve`.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use crate::base::sync::lock::SpinLockPure;
+use crate::errno::{E_BAD_FD, E_TOO_MANY_FILES};
+use crate::fs::file::File;
+
+
+/// Max count of the file descriptors of a process.
+pub const NR_OPEN: usize = 1024;
+
+/// An open file descriptor.
+#[derive(Clone)]
+struct FdEntry {
+    file: Arc<dyn File>,
+    cloexec: bool,
+}
+
+/// File descriptor table of a process.
+pub struct FdTable {
+    entries: Vec<Option<FdEntry>>,
+    lock: SpinLockPure,
+}
+
+impl FdTable {
+    pub const fn new() -> FdTable {
+        FdTable { entries: Vec::new(), lock: SpinLockPure::new() }
+    }
+
+    /// Get the file of the descriptor `fd`. Returns `Err` with `E_BAD_FD` if it is not open.
+    pub fn get(&self, fd: usize) -> Result<Arc<dyn File>, i32> {
+        let _guard = self.lock.lock_guard_irq_save();
+        match self.entries.get(fd) {
+            Some(Some(entry)) => Ok(entry.file.clone()),
+            _ => Err(E_BAD_FD),
+        }
+    }
+
+    /// Bind the `file` to the lowest free descriptor. Returns the descriptor, or `Err` with
+    /// `E_TOO_MANY_FILES` if all [`NR_OPEN`] descriptors are used.
+    pub fn alloc(&mut self, file: Arc<dyn File>, cloexec: bool) -> Result<usize, i32> {
+        let _guard = self.lock.lock_guard_irq_save();
+        let entry = Some(FdEntry { file, cloexec });
+        if let Some(fd) = self.entries.iter().position(|entry| entry.is_none()) {
+            self.entries[fd] = entry;
+            return Ok(fd);
+        }
+        if self.entries.len() >= NR_OPEN {
+            return Err(E_TOO_MANY_FILES);
+        }
+        self.entries.push(entry);
+        Ok(self.entries.len() - 1)
+    }
+
+    /// Close the descriptor `fd`, the file is closed if it is the last reference. Returns `Err`
+    /// with `E_BAD_FD` if it is not open.
+    pub fn close(&mut self, fd: usize) -> Result<(), i32> {
+        let file = {
+            let _guard = self.lock.lock_guard_irq_save();
+            self.entries.get_mut(fd).and_then(|entry| entry.take()).ok_or(E_BAD_FD)?
+        };
+        // The file is released without the lock.
+        drop(file);
+        Ok(())
+    }
+
+    /// Copy the table for a forked child, the files are shared.
+    pub fn fork(&self) -> FdTable {
+        let _guard = self.lock.lock_guard_irq_save();
+        FdTable { entries: self.entries.clone(), lock: SpinLockPure::new() }
+    }
+
+    /// Close the descriptors with the close-on-exec flag.
+    pub fn close_on_exec(&mut self) {
+        let closed: Vec<FdEntry> = {
+            let _guard = self.lock.lock_guard_irq_save();
+            self.entries.iter_mut()
+                .filter(|entry| entry.as_ref().map_or(false, |entry| entry.cloexec))
+                .filter_map(|entry| entry.take())
+                .collect()
+        };
+        drop(closed);
+    }
+
+    /// Close all descriptors and free the table.
+    pub fn close_all(&mut self) {
+        let entries = {
+            let _guard = self.lock.lock_guard_irq_save();
+            core::mem::take(&mut self.entries)
+        };
+        drop(entries);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::console::console_file;
+
+    #[kernel_test]
+    fn fd_table() {
+        let mut table = FdTable::new();
+        let console = console_file();
+        assert_eq!(table.alloc(console.clone(), false), Ok(0));
+        assert_eq!(table.alloc(console.clone(), true), Ok(1));
+        assert_eq!(table.alloc(console.clone(), false), Ok(2));
+        assert_eq!(table.close(1), Ok(()));
+        assert_eq!(table.close(1), Err(E_BAD_FD));
+        assert!(table.get(1).is_err());
+
+        // The lowest free descriptor is reused, the close-on-exec ones are closed.
+        assert_eq!(table.alloc(console.clone(), true), Ok(1));
+        let mut child = table.fork();
+        child.close_on_exec();
+        assert!(child.get(0).is_ok() && child.get(1).is_err() && table.get(1).is_ok());
+        child.close_all();
+        table.close_all();
+        assert!(table.get(0).is_err());
+        assert_eq!(Arc::strong_count(&console), 1);
+    }
+}
@@ -0,0 +1,202 @@
+//! File objects: the open files.
+//!
+//! A [`File`] is created by `open` and shared by `Arc` between the file descriptors duplicated
+//! from it, and between the forked processes, so they share the file offset like the Linux
+//! open file description. The file is closed when the last reference is dropped.
+//!
+//! [`InodeFile`] is the file of a regular file, a directory or a symlink on a file system. The
+//! devices provide their own file types, opened from a device file by the driver registered for
+//! its device number, see [`char_dev`] and [`block::fops`].
+//!
+//! [`char_dev`]: crate::fs::char_dev
+//! [`block::fops`]: crate::block::fops
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::errno::{E_BAD_FD, E_ILLEGAL_SEEK, E_INVALID, E_IS_DIR, E_NOT_DIR, E_NOT_TTY};
+use crate::fs::dentry::Dentry;
+use crate::fs::inode::{DirEntry, InodeType, Stat};
+use crate::mm::address_space::AddressSpace;
+
+
+/// Mask of the access mode of the open flags.
+pub const O_ACCMODE: usize = 0o3;
+pub const O_RDONLY: usize = 0o0;
+pub const O_WRONLY: usize = 0o1;
+pub const O_RDWR: usize = 0o2;
+pub const O_CREAT: usize = 0o100;
+pub const O_EXCL: usize = 0o200;
+pub const O_NOCTTY: usize = 0o400;
+pub const O_TRUNC: usize = 0o1000;
+pub const O_APPEND: usize = 0o2000;
+pub const O_NONBLOCK: usize = 0o4000;
+pub const O_DIRECTORY: usize = 0o200000;
+pub const O_NOFOLLOW: usize = 0o400000;
+pub const O_CLOEXEC: usize = 0o2000000;
+
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+
+/// Check if the open `flags` allow reading.
+#[inline(always)]
+pub const fn readable(flags: usize) -> bool {
+    flags & O_ACCMODE != O_WRONLY
+}
+
+/// Check if the open `flags` allow writing.
+#[inline(always)]
+pub const fn writable(flags: usize) -> bool {
+    flags & O_ACCMODE != O_RDONLY
+}
+
+/// Set the file offset `pos` to `offset` relative to the `whence`, `size` is the end for
+/// `SEEK_END`. Returns the new offset.
+pub fn seek_pos(pos: &AtomicUsize, offset: isize, whence: usize, size: usize)
+    -> Result<usize, i32> {
+    let base = match whence {
+        SEEK_SET => 0,
+        SEEK_CUR => pos.load(Ordering::Acquire),
+        SEEK_END => size,
+        _ => return Err(E_INVALID),
+    };
+    let new_pos = if offset < 0 {
+        base.checked_sub(offset.unsigned_abs())
+    } else {
+        base.checked_add(offset as usize)
+    };
+    let new_pos = new_pos.filter(|&new_pos| new_pos <= isize::MAX as usize).ok_or(E_INVALID)?;
+    pos.store(new_pos, Ordering::Release);
+    Ok(new_pos)
+}
+
+/// Open file operations. The defaults fail with the errno Linux returns for a file which does
+/// not support the operation.
+pub trait File {
+    /// Get the open flags.
+    fn flags(&self) -> usize;
+
+    /// Get the attributes of the file.
+    fn stat(&self) -> Result<Stat, i32>;
+
+    /// Read at the file offset into `buf`, and advance the offset. Returns the count of bytes
+    /// read, 0 at the end of the file, or `Err` with `E_AGAIN` if no data is available now.
+    fn read(&self, _buf: &mut [u8]) -> Result<usize, i32> {
+        Err(E_INVALID)
+    }
+
+    /// Write `buf` at the file offset, and advance the offset. Returns the count of bytes
+    /// written.
+    fn write(&self, _buf: &[u8]) -> Result<usize, i32> {
+        Err(E_INVALID)
+    }
+
+    /// Set the file offset to `offset` relative to the `whence` (`SEEK_SET`, `SEEK_CUR` or
+    /// `SEEK_END`). Returns the new offset.
+    fn seek(&self, _offset: isize, _whence: usize) -> Result<usize, i32> {
+        Err(E_ILLEGAL_SEEK)
+    }
+
+    /// Call `handle` with the directory entries from the file offset and the offset of the next
+    /// entry, until it returns false. The offset is advanced past the entries accepted by
+    /// `handle`.
+    fn read_dir(&self, _handle: &mut dyn FnMut(&DirEntry, usize) -> bool) -> Result<(), i32> {
+        Err(E_NOT_DIR)
+    }
+
+    /// Get the dentry of a file on a file system, which can be the base of a relative path.
+    fn dentry(&self) -> Option<&Arc<Dentry>> {
+        None
+    }
+
+    /// Write back the dirty data of the file, and the attributes unless `data_only`.
+    fn sync(&self, _data_only: bool) -> Result<(), i32> {
+        Err(E_INVALID)
+    }
+
+    /// Do the device-specific request `cmd` of `ioctl`. The `arg` is an integer or a user address
+    /// in the address space `aspace` of the caller, depending on the request.
+    fn ioctl(&self, _cmd: u32, _arg: usize, _aspace: &mut AddressSpace) -> Result<usize, i32> {
+        Err(E_NOT_TTY)
+    }
+}
+
+/// An open file on a file system.
+pub struct InodeFile {
+    dentry: Arc<Dentry>,
+    flags: usize,
+    /// File offset, the position for a directory.
+    pos: AtomicUsize,
+}
+
+impl InodeFile {
+    pub fn new(dentry: Arc<Dentry>, flags: usize) -> InodeFile {
+        InodeFile { dentry, flags, pos: AtomicUsize::new(0) }
+    }
+}
+
+impl File for InodeFile {
+    fn flags(&self) -> usize {
+        self.flags
+    }
+
+    fn stat(&self) -> Result<Stat, i32> {
+        self.dentry.inode().stat()
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize, i32> {
+        if !readable(self.flags) {
+            return Err(E_BAD_FD);
+        }
+        if self.dentry.inode_type()? == InodeType::Dir {
+            return Err(E_IS_DIR);
+        }
+
+        let pos = self.pos.load(Ordering::Acquire);
+        let len = self.dentry.inode().read_at(pos, buf)?;
+        self.pos.store(pos + len, Ordering::Release);
+        Ok(len)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, i32> {
+        if !writable(self.flags) {
+            return Err(E_BAD_FD);
+        }
+
+        let inode = self.dentry.inode();
+        let pos = if self.flags & O_APPEND != 0 {
+            inode.stat()?.size as usize
+        } else {
+            self.pos.load(Ordering::Acquire)
+        };
+        let len = inode.write_at(pos, buf)?;
+        self.pos.store(pos + len, Ordering::Release);
+        Ok(len)
+    }
+
+    fn seek(&self, offset: isize, whence: usize) -> Result<usize, i32> {
+        let size = if whence == SEEK_END { self.stat()?.size as usize } else { 0 };
+        seek_pos(&self.pos, offset, whence, size)
+    }
+
+    fn read_dir(&self, handle: &mut dyn FnMut(&DirEntry, usize) -> bool) -> Result<(), i32> {
+        let inode = self.dentry.inode();
+        let mut pos = self.pos.load(Ordering::Acquire);
+        while let Some((entry, next)) = inode.read_dir(pos)? {
+            if !handle(&entry, next) {
+                break;
+            }
+            pos = next;
+        }
+        self.pos.store(pos, Ordering::Release);
+        Ok(())
+    }
+
+    fn dentry(&self) -> Option<&Arc<Dentry>> {
+        Some(&self.dentry)
+    }
+
+    fn sync(&self, data_only: bool) -> Result<(), i32> {
+        self.dentry.inode().sync(data_only)
+    }
+}
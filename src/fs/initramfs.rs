@@ -0,0 +1,114 @@
+//! Initramfs, the cpio archives unpacked into the root file system at boot.
+//!
+//! The archives are in the `newc` format (parsed by [`vos_core::cpio`]) and come from:
+//!
+//! - The archive embedded in the kernel image, with the `embedded-initramfs` feature. The path
+//!   of the archive is given by the `VOS_INITRAMFS` environment variable at build time.
+//! - The initrd loaded by the boot loader (e.g. QEMU `-initrd`), found by the
+//!   `linux,initrd-start` and `linux,initrd-end` properties of the DeviceTree `/chosen` node.
+//!   Its pages are reserved by the page allocator and freed after unpacked.
+//!
+//! As in Linux, the initrd is unpacked after the embedded archive and overwrites its files. The
+//! directories, regular files, symlinks and special files are supported. Hard links are unpacked
+//! as independent files.
+
+use alloc::format;
+use crate::errno::{E_EXIST, E_INVALID, E_NO_ENT};
+use crate::fs::file::{O_CREAT, O_TRUNC, O_WRONLY};
+use crate::fs::inode::{make_dev, InodeType, S_IALLUGO};
+use crate::fs::vfs;
+use crate::mm::page;
+use crate::mm::{PAGE_ORDER, PAGE_SIZE};
+use crate::util::align::{align_down, align_up};
+use vos_core::cpio::{Entry, Reader};
+
+
+/// The archive embedded in the kernel image.
+#[cfg(feature = "embedded-initramfs")]
+static EMBEDDED_INITRAMFS: &[u8] = include_bytes!(env!("VOS_INITRAMFS"));
+#[cfg(not(feature = "embedded-initramfs"))]
+static EMBEDDED_INITRAMFS: &[u8] = &[];
+
+/// Unpack the initramfs archives into the root file system, then free the initrd.
+pub fn populate_rootfs() {
+    if !EMBEDDED_INITRAMFS.is_empty() {
+        unpack_archive("embedded initramfs", EMBEDDED_INITRAMFS);
+    }
+
+    if let Some((start, end)) = crate::init::initrd_range() {
+        let data = unsafe { core::slice::from_raw_parts(start as *const u8, end - start) };
+        unpack_archive("initrd", data);
+
+        for addr in (align_down(start, PAGE_ORDER)..align_up(end, PAGE_ORDER)).step_by(PAGE_SIZE) {
+            if !page::address_to_page(addr).is_null() {
+                page::free_page(addr);
+            }
+        }
+        crate::init::drop_initrd();
+        info!("Freed initrd memory: {}K.", (end - start) / 1024);
+    }
+}
+
+/// Unpack the entries of the archive `data`, the failed entries are skipped.
+fn unpack_archive(what: &str, data: &[u8]) {
+    let mut count = 0usize;
+    for entry in Reader::new(data) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                warn!("Unpack {} failed: {}.", what, err);
+                break;
+            }
+        };
+        match unpack_entry(&entry) {
+            Ok(()) => count += 1,
+            Err(errno) => warn!("Unpack {} of {} failed, errno = {}.", entry.name, what, errno),
+        }
+    }
+    info!("Unpacked {}, {} entries.", what, count);
+}
+
+/// Remove the file at `path` unpacked from an earlier archive, so it is replaced instead of
+/// written through.
+fn remove_old(path: &str) -> Result<(), i32> {
+    match vfs::unlink(None, path, false) {
+        Ok(()) | Err(E_NO_ENT) => Ok(()),
+        Err(errno) => Err(errno),
+    }
+}
+
+/// Create the file of `entry` in the root file system.
+fn unpack_entry(entry: &Entry) -> Result<(), i32> {
+    let name = entry.name.trim_start_matches("./").trim_start_matches('/');
+    if name.is_empty() || name == "." {
+        return Ok(());
+    }
+    let path = format!("/{}", name);
+    let mode = entry.mode & S_IALLUGO;
+
+    match InodeType::from_mode(entry.mode) {
+        Some(InodeType::Dir) => match vfs::mkdir(None, &path, mode) {
+            Err(E_EXIST) => Ok(()),
+            result => result,
+        },
+        Some(InodeType::File) => {
+            remove_old(&path)?;
+            let file = vfs::open(None, &path, O_WRONLY | O_CREAT | O_TRUNC, mode)?;
+            let mut written = 0;
+            while written < entry.data.len() {
+                written += file.write(&entry.data[written..])?;
+            }
+            Ok(())
+        }
+        Some(InodeType::Symlink) => {
+            let target = core::str::from_utf8(entry.data).map_err(|_| E_INVALID)?;
+            remove_old(&path)?;
+            vfs::symlink(None, target, &path)
+        }
+        Some(_) => {
+            remove_old(&path)?;
+            vfs::mknod(None, &path, entry.mode, make_dev(entry.rdev_major, entry.rdev_minor))
+        }
+        None => Err(E_INVALID),
+    }
+}
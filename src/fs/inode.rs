@@ -0,0 +1,230 @@
+//! Inodes, the objects of a file system: regular files, directories, symlinks and the special
+//! files.
+//!
+//! An [`Inode`] is shared by `Arc`, a concrete file system implements the operations it supports
+//! and keeps the defaults for the others. The operations take `&self`, the file system protects
+//! its inodes by its own locks.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::any::Any;
+use crate::arch::cpu;
+use crate::errno::{E_INVALID, E_NOT_DIR, E_PERM};
+use crate::smp::current_cpu_info;
+
+
+/// Mask of the file type bits of a mode.
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFSOCK: u32 = 0o140000;
+pub const S_IFLNK: u32 = 0o120000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFBLK: u32 = 0o060000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFCHR: u32 = 0o020000;
+pub const S_IFIFO: u32 = 0o010000;
+/// Mask of the permission bits of a mode.
+pub const S_IALLUGO: u32 = 0o7777;
+
+/// Type of an inode.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum InodeType {
+    Fifo,
+    CharDevice,
+    Dir,
+    BlockDevice,
+    File,
+    Symlink,
+    Socket,
+}
+
+impl InodeType {
+    /// Get the file type bits of the mode.
+    pub const fn mode(self) -> u32 {
+        match self {
+            InodeType::Fifo => S_IFIFO,
+            InodeType::CharDevice => S_IFCHR,
+            InodeType::Dir => S_IFDIR,
+            InodeType::BlockDevice => S_IFBLK,
+            InodeType::File => S_IFREG,
+            InodeType::Symlink => S_IFLNK,
+            InodeType::Socket => S_IFSOCK,
+        }
+    }
+
+    /// Get the type from the file type bits of the `mode`.
+    pub const fn from_mode(mode: u32) -> Option<InodeType> {
+        match mode & S_IFMT {
+            S_IFIFO => Some(InodeType::Fifo),
+            S_IFCHR => Some(InodeType::CharDevice),
+            S_IFDIR => Some(InodeType::Dir),
+            S_IFBLK => Some(InodeType::BlockDevice),
+            S_IFREG => Some(InodeType::File),
+            S_IFLNK => Some(InodeType::Symlink),
+            S_IFSOCK => Some(InodeType::Socket),
+            _ => None,
+        }
+    }
+
+    /// Get the `d_type` of a `getdents64` entry, which is the file type bits shifted.
+    #[inline(always)]
+    pub const fn dirent_type(self) -> u8 {
+        (self.mode() >> 12) as u8
+    }
+}
+
+/// Build a device number from the `major` and the `minor` number, in the Linux encoding.
+#[inline(always)]
+pub const fn make_dev(major: u32, minor: u32) -> u64 {
+    let (major, minor) = (major as u64, minor as u64);
+    (major & 0xfff) << 8 | (major & !0xfff) << 32 | (minor & 0xff) | (minor & !0xff) << 12
+}
+
+/// Get the major number of the device number `dev`.
+#[inline(always)]
+pub const fn major(dev: u64) -> u32 {
+    ((dev >> 8) & 0xfff | (dev >> 32) & !0xfff) as u32
+}
+
+/// Get the minor number of the device number `dev`.
+#[inline(always)]
+pub const fn minor(dev: u64) -> u32 {
+    (dev & 0xff | (dev >> 12) & !0xff) as u32
+}
+
+/// Get the current time for the inode times in nanoseconds. There is no RTC yet, the time counts
+/// from the boot like the clocks.
+pub fn current_time() -> u64 {
+    let freq = current_cpu_info().get_timebase_freq() as u64;
+    if freq == 0 {
+        return 0;
+    }
+    let ticks = cpu::read_time() as u64;
+    ticks / freq * 1_000_000_000 + ticks % freq * 1_000_000_000 / freq
+}
+
+/// Attributes of an inode. The times are in nanoseconds.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Stat {
+    /// Device number of the file system.
+    pub dev: u64,
+    pub ino: u64,
+    /// File type and permission bits.
+    pub mode: u32,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// Device number of a device file.
+    pub rdev: u64,
+    pub size: u64,
+    pub blksize: u32,
+    /// Count of the 512-byte blocks allocated.
+    pub blocks: u64,
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+}
+
+impl Stat {
+    /// Get the inode type, a regular file if the mode is broken.
+    #[inline(always)]
+    pub fn inode_type(&self) -> InodeType {
+        InodeType::from_mode(self.mode).unwrap_or(InodeType::File)
+    }
+}
+
+/// Entry of a directory.
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    pub ino: u64,
+    pub kind: InodeType,
+    pub name: String,
+}
+
+/// Inode operations. The defaults fail with the errno Linux returns for an inode which does not
+/// support the operation.
+pub trait Inode {
+    /// Get the attributes.
+    fn stat(&self) -> Result<Stat, i32>;
+
+    /// Read the content at `offset` into `buf`. Returns the count of bytes read, 0 at the end of
+    /// the file.
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize, i32> {
+        Err(E_INVALID)
+    }
+
+    /// Write `buf` at `offset`, the file grows if needed. Returns the count of bytes written.
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize, i32> {
+        Err(E_INVALID)
+    }
+
+    /// Change the file size to `size`, the extended part reads as zeros.
+    fn truncate(&self, _size: usize) -> Result<(), i32> {
+        Err(E_INVALID)
+    }
+
+    /// Find the child `name` of a directory. The `name` is never `.` or `..`, which are handled
+    /// by the VFS.
+    fn lookup(&self, _name: &str) -> Result<Arc<dyn Inode>, i32> {
+        Err(E_NOT_DIR)
+    }
+
+    /// Create a child `name` of the `kind` with the permission bits `mode` in a directory.
+    /// Symlinks are created by [`symlink`](Inode::symlink).
+    fn create(&self, _name: &str, _kind: InodeType, _mode: u32) -> Result<Arc<dyn Inode>, i32> {
+        Err(E_NOT_DIR)
+    }
+
+    /// Create a symlink `name` pointing to `target` in a directory.
+    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn Inode>, i32> {
+        Err(E_NOT_DIR)
+    }
+
+    /// Create a special file `name` of the `kind` (a device, a FIFO or a socket) with the
+    /// permission bits `mode` in a directory, `dev` is the device number of a device file.
+    fn mknod(&self, _name: &str, _kind: InodeType, _mode: u32, _dev: u64)
+        -> Result<Arc<dyn Inode>, i32> {
+        Err(E_PERM)
+    }
+
+    /// Add a hard link `name` to the `inode` of the same file system in a directory. The VFS
+    /// checks that the inode is not a directory. The default fails as a file system without hard
+    /// links does.
+    fn link(&self, _name: &str, _inode: &Arc<dyn Inode>) -> Result<(), i32> {
+        Err(E_PERM)
+    }
+
+    /// Remove the child `name` of a directory. A child directory must be empty. The VFS checks
+    /// the child type for `unlink` and `rmdir` before calling this.
+    fn unlink(&self, _name: &str) -> Result<(), i32> {
+        Err(E_NOT_DIR)
+    }
+
+    /// Move the child `old_name` of a directory to `new_name` in the directory `new_dir` of the
+    /// same file system, replacing the target if any. The VFS checks the types of the child and
+    /// the target before calling this; a replaced directory must be empty.
+    fn rename(&self, _old_name: &str, _new_dir: &Arc<dyn Inode>, _new_name: &str)
+        -> Result<(), i32> {
+        Err(E_NOT_DIR)
+    }
+
+    /// Read the directory entry at the position `offset`, `.` and `..` included. Returns the
+    /// entry and the position of the next one, or `None` at the end of the directory.
+    fn read_dir(&self, _offset: usize) -> Result<Option<(DirEntry, usize)>, i32> {
+        Err(E_NOT_DIR)
+    }
+
+    /// Read the target of a symlink.
+    fn read_link(&self) -> Result<String, i32> {
+        Err(E_INVALID)
+    }
+
+    /// Write back the dirty data of the file to the device, and the attributes unless
+    /// `data_only`. Nothing to do for an in-memory file.
+    fn sync(&self, _data_only: bool) -> Result<(), i32> {
+        Ok(())
+    }
+
+    /// Get the inode as `Any`, to downcast the other inode of an operation, e.g. the target
+    /// directory of a rename, to the concrete type.
+    fn as_any(&self) -> &dyn Any;
+}
@@ -1 +1,68 @@
 //! File system.
+//!
+//! The virtual file system (VFS) layer is the common interface of the concrete file systems:
+//!
+//! - A [`FileSystem`] type is registered by its name, and mounting it creates a [`SuperBlock`]
+//!   which provides the root [`Inode`], see [`mount`](mod@mount).
+//! - An [`Inode`] is a file system object, a [`Dentry`] names it in the directory tree. The
+//!   paths are resolved to dentries by [`lookup_path`].
+//! - A [`File`] is an open file, the processes refer to it by the file descriptors in their
+//!   [`FdTable`].
+//!
+//! The generic operations on the paths are in [`vfs`]. The root file system is a [`tmpfs`]
+//! mounted at boot, and the [`initramfs`] is unpacked into it. The device files are kept by
+//! [`devtmpfs`] mounted on `/dev`, and opened by the drivers registered in [`char_dev`] and the
+//! block layer. The kernel state is shown by [`procfs`] mounted on `/proc`. The disks are mounted
+//! as [`fat`] or [`ext2`] volumes.
+//!
+//! [`FileSystem`]: mount::FileSystem
+//! [`SuperBlock`]: mount::SuperBlock
+//! [`Inode`]: inode::Inode
+//! [`File`]: file::File
+
+pub(crate) mod char_dev;
+pub(crate) mod console;
+pub(crate) mod dentry;
+pub(crate) mod devtmpfs;
+pub(crate) mod ext2;
+pub(crate) mod fat;
+pub(crate) mod fd;
+pub(crate) mod file;
+pub(crate) mod initramfs;
+pub(crate) mod inode;
+pub(crate) mod mount;
+pub(crate) mod procfs;
+pub(crate) mod tmpfs;
+pub(crate) mod vfs;
+
+// Re-export
+pub use dentry::{lookup_path, Dentry, PATH_MAX};
+pub use fd::FdTable;
+pub use inode::{InodeType, Stat};
+
+use crate::errno::E_EXIST;
+
+
+/// Register the built-in file systems, mount a tmpfs as the root, unpack the initramfs, and
+/// mount the device files on `/dev` and the procfs on `/proc`.
+pub fn init() {
+    mount::register_filesystem(&tmpfs::TMPFS);
+    mount::register_filesystem(&tmpfs::RAMFS);
+    mount::register_filesystem(&fat::VFAT);
+    mount::register_filesystem(&ext2::EXT2);
+    mount::register_filesystem(&devtmpfs::DEVTMPFS);
+    mount::register_filesystem(&procfs::PROCFS);
+    mount::mount("rootfs", "/", "tmpfs", "")
+        .unwrap_or_else(|err| panic!("Mount the root file system failed, errno = {}.", err));
+    initramfs::populate_rootfs();
+
+    for (fs_name, target) in [("devtmpfs", "/dev"), ("proc", "/proc")] {
+        let result = match vfs::mkdir(None, target, 0o755) {
+            Ok(()) | Err(E_EXIST) => mount::mount(fs_name, target, fs_name, ""),
+            Err(errno) => Err(errno),
+        };
+        if let Err(errno) = result {
+            warn!("Mount {} on {} failed, errno = {}.", fs_name, target, errno);
+        }
+    }
+}
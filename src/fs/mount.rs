@@ -0,0 +1,178 @@
+//! File system types and the mount table.
+//!
+//! A [`FileSystem`] type is registered by its name, and mounting it creates a [`SuperBlock`],
+//! which owns the root inode of the mounted tree. The first mount must be on `/`, it becomes the
+//! root of the path resolution. A later mount covers a directory: the path resolution steps
+//! into the mounted root when it reaches the covered directory, which is identified by the
+//! device and inode number.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, Ordering};
+use crate::base::sync::lock::SpinLockPure;
+use crate::errno::{E_BUSY, E_INVALID, E_NO_DEV, E_NO_ENT, E_NOT_DIR};
+use crate::fs::dentry::{lookup_path, Dentry};
+use crate::fs::inode::{make_dev, Inode, InodeType};
+
+
+/// A file system type.
+pub trait FileSystem {
+    /// Name of the type, used by `mount`.
+    fn name(&self) -> &'static str;
+
+    /// Mount the file system on the device or the name `source` with the options `data`.
+    fn mount(&self, source: &str, data: &str) -> Result<Arc<dyn SuperBlock>, i32>;
+}
+
+/// A mounted file system.
+pub trait SuperBlock {
+    /// Get the root directory.
+    fn root(&self) -> Arc<dyn Inode>;
+
+    /// Write back the dirty data.
+    fn sync(&self) -> Result<(), i32> {
+        Ok(())
+    }
+}
+
+/// Entry of the mount table.
+struct Mount {
+    fs_name: &'static str,
+    source: String,
+    sb: Arc<dyn SuperBlock>,
+    /// Device and inode number of the mounted root.
+    root: (u64, u64),
+    /// Device and inode number of the covered directory, `None` for the root mount.
+    covered: Option<(u64, u64)>,
+}
+
+static mut FILE_SYSTEMS: Vec<&'static dyn FileSystem> = Vec::new();
+static mut MOUNTS: Vec<Mount> = Vec::new();
+/// Lock of the file system types and the mount table.
+static MOUNT_LOCK: SpinLockPure = SpinLockPure::new();
+/// Next minor number of the anonymous devices (major 0).
+static NEXT_ANON_MINOR: AtomicU32 = AtomicU32::new(1);
+
+/// Register a file system type.
+pub fn register_filesystem(fs: &'static dyn FileSystem) {
+    let _guard = MOUNT_LOCK.lock_guard_irq_save();
+    unsafe { (*core::ptr::addr_of_mut!(FILE_SYSTEMS)).push(fs); }
+}
+
+/// Allocate a device number for a file system without a backing device.
+pub fn alloc_anon_dev() -> u64 {
+    make_dev(0, NEXT_ANON_MINOR.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Get the device and inode number of the `inode`.
+fn inode_key(inode: &Arc<dyn Inode>) -> Result<(u64, u64), i32> {
+    let stat = inode.stat()?;
+    Ok((stat.dev, stat.ino))
+}
+
+/// Mount the file system type `fs_name` from the `source` on the directory `target` with the
+/// options `data`. The `target` is resolved from the root, the first mount must be on `/`.
+///
+/// Returns `Err` with `E_NO_DEV` if the type is unknown, or `E_BUSY` if the `target` is already
+/// a mount point.
+pub fn mount(source: &str, target: &str, fs_name: &str, data: &str) -> Result<(), i32> {
+    let fs = {
+        let _guard = MOUNT_LOCK.lock_guard_irq_save();
+        let types = unsafe { &*core::ptr::addr_of!(FILE_SYSTEMS) };
+        *types.iter().find(|fs| fs.name() == fs_name).ok_or(E_NO_DEV)?
+    };
+    let covered = if has_root() {
+        let dentry = lookup_path(None, target, true)?;
+        if dentry.inode().stat()?.inode_type() != InodeType::Dir {
+            return Err(E_NOT_DIR);
+        }
+        Some(inode_key(dentry.inode())?)
+    } else if target == "/" {
+        None
+    } else {
+        return Err(E_NO_ENT);
+    };
+
+    let sb = fs.mount(source, data)?;
+    let root = inode_key(&sb.root())?;
+    let _guard = MOUNT_LOCK.lock_guard_irq_save();
+    let mounts = unsafe { &mut *core::ptr::addr_of_mut!(MOUNTS) };
+    // Check again, the table may change while mounting.
+    if mounts.iter().any(|m| m.covered == covered || m.root == root) {
+        return Err(E_BUSY);
+    }
+    info!("Mount {} ({}) on {}.", source, fs_name, target);
+    mounts.push(Mount { fs_name: fs.name(), source: String::from(source), sb, root, covered });
+
+    Ok(())
+}
+
+/// Unmount the file system mounted on `target`. The dirty data is written back first.
+///
+/// Returns `Err` with `E_INVALID` if the `target` is not a mount point, or `E_BUSY` if it is
+/// the root or covered by another mount.
+pub fn umount(target: &str) -> Result<(), i32> {
+    let dentry = lookup_path(None, target, true)?;
+    let key = inode_key(dentry.inode())?;
+    let sb = {
+        let _guard = MOUNT_LOCK.lock_guard_irq_save();
+        let mounts = unsafe { &mut *core::ptr::addr_of_mut!(MOUNTS) };
+        let idx = mounts.iter().position(|m| m.root == key).ok_or(E_INVALID)?;
+        let covering = mounts.iter().any(|m| m.covered.map(|c| c.0) == Some(key.0));
+        if mounts[idx].covered.is_none() || covering {
+            return Err(E_BUSY);
+        }
+        let m = mounts.remove(idx);
+        info!("Unmount {} ({}) from {}.", m.source, m.fs_name, target);
+        m.sb
+    };
+
+    sb.sync()
+}
+
+/// Write back the dirty data of all mounted file systems. Returns the first error.
+pub fn sync_filesystems() -> Result<(), i32> {
+    let sbs: Vec<Arc<dyn SuperBlock>> = {
+        let _guard = MOUNT_LOCK.lock_guard_irq_save();
+        let mounts = unsafe { &*core::ptr::addr_of!(MOUNTS) };
+        mounts.iter().map(|m| m.sb.clone()).collect()
+    };
+    sbs.iter().fold(Ok(()), |result, sb| result.and(sb.sync()))
+}
+
+/// Check if the root is mounted.
+fn has_root() -> bool {
+    let _guard = MOUNT_LOCK.lock_guard_irq_save();
+    unsafe { !(*core::ptr::addr_of!(MOUNTS)).is_empty() }
+}
+
+/// Get a new dentry of the root directory. Returns `Err` with `E_NO_ENT` if the root is not
+/// mounted.
+pub fn root_dentry() -> Result<Arc<Dentry>, i32> {
+    let _guard = MOUNT_LOCK.lock_guard_irq_save();
+    let mounts = unsafe { &*core::ptr::addr_of!(MOUNTS) };
+    let root = mounts.first().ok_or(E_NO_ENT)?;
+    Ok(Dentry::new(String::from("/"), root.sb.root(), None))
+}
+
+/// Get the root inode of the file system mounted on the directory `inode`, `None` if it is not
+/// a mount point.
+pub(super) fn mounted_root(inode: &Arc<dyn Inode>) -> Option<Arc<dyn Inode>> {
+    // Only the root is mounted in the common case, skip the `stat`.
+    if unsafe { (*core::ptr::addr_of!(MOUNTS)).len() } <= 1 {
+        return None;
+    }
+    let key = inode_key(inode).ok()?;
+    let _guard = MOUNT_LOCK.lock_guard_irq_save();
+    let mounts = unsafe { &*core::ptr::addr_of!(MOUNTS) };
+    mounts.iter().find(|m| m.covered == Some(key)).map(|m| m.sb.root())
+}
+
+/// Check if the directory of the device and inode number `key` is a mount point or a mounted
+/// root, which can not be removed.
+pub(super) fn is_mount_point(key: (u64, u64)) -> bool {
+    let _guard = MOUNT_LOCK.lock_guard_irq_save();
+    let mounts = unsafe { &*core::ptr::addr_of!(MOUNTS) };
+    mounts.iter().any(|m| m.covered == Some(key) || m.root == key)
+}
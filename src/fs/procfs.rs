@@ -0,0 +1,566 @@
+//! procfs, the kernel state as files.
+//!
+//! The files have no content of their own: each read formats the current state of the kernel
+//! and returns the bytes at the file offset, so the files report a size of 0 like Linux. The
+//! root directory holds:
+//!
+//! - `meminfo`: the memory usage from the free areas of the buddy allocator, the page cache and
+//!   the kernel heap.
+//! - `slabinfo`: the slab caches.
+//! - `interrupts`: the interrupt counts of each CPU, see [`show_interrupts`].
+//! - `cpuinfo`: the CPUs with the ISA string and the MMU type of the DeviceTree.
+//! - `cmdline`: the boot command line.
+//! - `self`: a symlink to the directory of the current process.
+//! - `<pid>`: a directory for each process, zombies included, with the `status`, `maps` and
+//!   `stat` files.
+//!
+//! The files are read-only. The file system is registered as `proc` and mounted on `/proc` at
+//! boot.
+//!
+//! [`show_interrupts`]: crate::dev::irq::show_interrupts
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use core::any::Any;
+use core::fmt::{self, Write};
+use crate::dev::irq::show_interrupts;
+use crate::errno::{E_INVALID, E_IS_DIR, E_NO_ENT, E_NO_MEM, E_NOT_DIR, E_PERM, E_SRCH};
+use crate::fs::inode::{current_time, DirEntry, Inode, InodeType, Stat};
+use crate::fs::mount::{alloc_anon_dev, FileSystem, SuperBlock};
+use crate::mm::address_space::MMAP_TOP;
+use crate::mm::mmu::EntryBits;
+use crate::mm::vma::{VmaBacking, VmaTree};
+use crate::mm::{get_alloc_used_bytes, for_each_slab_cache, page, page_cache, PAGE_ORDER,
+                PAGE_SIZE};
+use crate::proc::kernel::ctx::self_task_info;
+use crate::proc::process::{find_process, for_each_process, Process};
+use crate::proc::task::TaskStatus;
+use crate::smp::get_cpu_info_by_cpuid;
+use crate::util::align::align_up;
+
+
+/// The `proc` file system type.
+pub struct ProcFs;
+
+pub static PROCFS: ProcFs = ProcFs;
+
+/// Inode number of the root directory.
+const ROOT_INO: u64 = 1;
+/// The inode number of a process directory or file is the PID shifted by this, plus the kind.
+const PID_INO_SHIFT: u32 = 8;
+
+/// Kind of a procfs inode.
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum ProcEntry {
+    Root = 1,
+    MemInfo,
+    SlabInfo,
+    Interrupts,
+    CpuInfo,
+    CmdLine,
+    SelfLink,
+    PidDir,
+    PidStatus,
+    PidMaps,
+    PidStat,
+}
+
+/// Files of the root directory, the process directories follow them.
+const ROOT_FILES: [(&str, ProcEntry); 6] = [
+    ("meminfo", ProcEntry::MemInfo),
+    ("slabinfo", ProcEntry::SlabInfo),
+    ("interrupts", ProcEntry::Interrupts),
+    ("cpuinfo", ProcEntry::CpuInfo),
+    ("cmdline", ProcEntry::CmdLine),
+    ("self", ProcEntry::SelfLink),
+];
+
+/// Files of a process directory.
+const PID_FILES: [(&str, ProcEntry); 3] = [
+    ("status", ProcEntry::PidStatus),
+    ("maps", ProcEntry::PidMaps),
+    ("stat", ProcEntry::PidStat),
+];
+
+/// Position of the first process directory in the root directory. The position of the directory
+/// of a process is this plus the PID.
+const PID_DIR_POS: usize = 2 + ROOT_FILES.len();
+
+/// A file or a directory of procfs. The process of a process inode may have been reaped, the
+/// reads then fail with `E_SRCH`.
+struct ProcInode {
+    dev: u64,
+    /// PID of a process inode, 0 for the others.
+    pid: u32,
+    entry: ProcEntry,
+}
+
+struct ProcSuperBlock {
+    root: Arc<ProcInode>,
+}
+
+impl ProcInode {
+    fn new(dev: u64, pid: u32, entry: ProcEntry) -> Arc<ProcInode> {
+        Arc::new(ProcInode { dev, pid, entry })
+    }
+
+    fn kind(&self) -> InodeType {
+        match self.entry {
+            ProcEntry::Root | ProcEntry::PidDir => InodeType::Dir,
+            ProcEntry::SelfLink => InodeType::Symlink,
+            _ => InodeType::File,
+        }
+    }
+
+    fn ino(&self) -> u64 {
+        (self.pid as u64) << PID_INO_SHIFT | self.entry as u64
+    }
+
+    /// Get the process of a process inode.
+    fn process(&self) -> Result<&'static mut Process, i32> {
+        let process = find_process(self.pid);
+        if process.is_null() { Err(E_SRCH) } else { Ok(unsafe { &mut *process }) }
+    }
+
+    /// Format the content of a file.
+    fn generate(&self) -> Result<String, i32> {
+        let mut out = String::new();
+        let result = match self.entry {
+            ProcEntry::Root | ProcEntry::PidDir => return Err(E_IS_DIR),
+            ProcEntry::SelfLink => return Err(E_INVALID),
+            ProcEntry::MemInfo => show_meminfo(&mut out),
+            ProcEntry::SlabInfo => show_slabinfo(&mut out),
+            ProcEntry::Interrupts => show_interrupts(&mut out),
+            ProcEntry::CpuInfo => show_cpuinfo(&mut out),
+            ProcEntry::CmdLine => writeln!(out, "{}", crate::init::get_boot_command_line()),
+            ProcEntry::PidStatus => show_pid_status(&mut out, self.process()?),
+            ProcEntry::PidMaps => show_pid_maps(&mut out, self.process()?),
+            ProcEntry::PidStat => show_pid_stat(&mut out, self.process()?),
+        };
+        result.map(|_| out).map_err(|_| E_NO_MEM)
+    }
+
+    /// Get the error of a change in a directory, or of a directory operation on a file.
+    fn change_error(&self) -> i32 {
+        if self.kind() == InodeType::Dir { E_PERM } else { E_NOT_DIR }
+    }
+}
+
+impl Inode for ProcInode {
+    fn stat(&self) -> Result<Stat, i32> {
+        let (perm, nlink) = match self.kind() {
+            InodeType::Dir => (0o555, 2),
+            InodeType::Symlink => (0o777, 1),
+            _ => (0o444, 1),
+        };
+        let now = current_time();
+        Ok(Stat {
+            dev: self.dev,
+            ino: self.ino(),
+            mode: self.kind().mode() | perm,
+            nlink,
+            blksize: 1024,
+            atime: now,
+            mtime: now,
+            ctime: now,
+            ..Stat::default()
+        })
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, i32> {
+        let content = self.generate()?;
+        let content = content.as_bytes().get(offset..).unwrap_or(&[]);
+        let len = content.len().min(buf.len());
+        buf[..len].copy_from_slice(&content[..len]);
+        Ok(len)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, i32> {
+        let files: &[(&str, ProcEntry)] = match self.entry {
+            ProcEntry::Root => &ROOT_FILES,
+            ProcEntry::PidDir => &PID_FILES,
+            _ => return Err(E_NOT_DIR),
+        };
+        if let Some(&(_, entry)) = files.iter().find(|(file, _)| *file == name) {
+            return Ok(ProcInode::new(self.dev, self.pid, entry));
+        }
+        if self.entry != ProcEntry::Root || !name.bytes().all(|c| c.is_ascii_digit()) {
+            return Err(E_NO_ENT);
+        }
+        match name.parse::<u32>() {
+            Ok(pid) if pid != 0 && !find_process(pid).is_null() => {
+                Ok(ProcInode::new(self.dev, pid, ProcEntry::PidDir))
+            }
+            _ => Err(E_NO_ENT),
+        }
+    }
+
+    fn create(&self, _name: &str, _kind: InodeType, _mode: u32) -> Result<Arc<dyn Inode>, i32> {
+        Err(self.change_error())
+    }
+
+    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn Inode>, i32> {
+        Err(self.change_error())
+    }
+
+    fn unlink(&self, _name: &str) -> Result<(), i32> {
+        Err(self.change_error())
+    }
+
+    fn rename(&self, _old_name: &str, _new_dir: &Arc<dyn Inode>, _new_name: &str)
+        -> Result<(), i32> {
+        Err(self.change_error())
+    }
+
+    fn read_dir(&self, offset: usize) -> Result<Option<(DirEntry, usize)>, i32> {
+        let files: &[(&str, ProcEntry)] = match self.entry {
+            ProcEntry::Root => &ROOT_FILES,
+            ProcEntry::PidDir => &PID_FILES,
+            _ => return Err(E_NOT_DIR),
+        };
+        let dir = |ino, name| DirEntry { ino, kind: InodeType::Dir, name: String::from(name) };
+        let entry = match offset {
+            0 => dir(self.ino(), "."),
+            1 => dir(ROOT_INO, ".."),
+            _ if offset - 2 < files.len() => {
+                let (name, entry) = files[offset - 2];
+                let inode = ProcInode { dev: self.dev, pid: self.pid, entry };
+                DirEntry { ino: inode.ino(), kind: inode.kind(), name: String::from(name) }
+            }
+            _ if self.entry == ProcEntry::PidDir => return Ok(None),
+            _ => {
+                // The next process from the PID of the position.
+                let from = (offset - PID_DIR_POS) as u32;
+                let mut next = None;
+                for_each_process(|process| {
+                    if process.pid() >= from && next.map_or(true, |pid| process.pid() < pid) {
+                        next = Some(process.pid());
+                    }
+                    true
+                });
+                let Some(pid) = next else {
+                    return Ok(None);
+                };
+                let ino = ProcInode { dev: self.dev, pid, entry: ProcEntry::PidDir }.ino();
+                let entry = DirEntry { ino, kind: InodeType::Dir, name: pid.to_string() };
+                return Ok(Some((entry, PID_DIR_POS + pid as usize + 1)));
+            }
+        };
+        Ok(Some((entry, offset + 1)))
+    }
+
+    /// Read the `self` symlink: the PID of the current process. A kernel thread has none.
+    fn read_link(&self) -> Result<String, i32> {
+        if self.entry != ProcEntry::SelfLink {
+            return Err(E_INVALID);
+        }
+        let process = self_task_info().process();
+        if process.is_null() {
+            return Err(E_NO_ENT);
+        }
+        Ok(unsafe { (*process).pid() }.to_string())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl SuperBlock for ProcSuperBlock {
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+impl FileSystem for ProcFs {
+    fn name(&self) -> &'static str {
+        "proc"
+    }
+
+    /// Mount a procfs, the `source` and the `data` are ignored.
+    fn mount(&self, _source: &str, _data: &str) -> Result<Arc<dyn SuperBlock>, i32> {
+        let root = ProcInode::new(alloc_anon_dev(), 0, ProcEntry::Root);
+        Ok(Arc::new(ProcSuperBlock { root }))
+    }
+}
+
+/// Write `/proc/meminfo`. The available memory counts the clean page cache, which is reclaimed
+/// when the memory runs out. The slab memory is the used part of the `kmalloc` heap and the
+/// partial slabs of the slab caches.
+fn show_meminfo(out: &mut String) -> fmt::Result {
+    let kb = |pages: usize| pages * (PAGE_SIZE / 1024);
+    let free = page::nr_free_pages();
+    let (cached, dirty) = (page_cache::cached_pages(), page_cache::dirty_pages());
+    let mut slab_pages = 0;
+    for_each_slab_cache(|cache| {
+        slab_pages += (cache.nr_partial() * cache.pages_per_slab()) as usize;
+        true
+    });
+    let slab = get_alloc_used_bytes() / 1024 + kb(slab_pages);
+
+    writeln!(out, "MemTotal:       {:>8} kB", kb(page::nr_total_pages()))?;
+    writeln!(out, "MemFree:        {:>8} kB", kb(free))?;
+    writeln!(out, "MemAvailable:   {:>8} kB", kb(free + cached.saturating_sub(dirty)))?;
+    writeln!(out, "Cached:         {:>8} kB", kb(cached))?;
+    writeln!(out, "Dirty:          {:>8} kB", kb(dirty))?;
+    writeln!(out, "Slab:           {:>8} kB", slab)?;
+    writeln!(out, "SUnreclaim:     {:>8} kB", slab)
+}
+
+/// Write `/proc/slabinfo`. The objects in use are not counted, only the partial slabs are.
+fn show_slabinfo(out: &mut String) -> fmt::Result {
+    writeln!(out, "slabinfo - version: 2.1")?;
+    writeln!(out, "# name            <objsize> <size> <objperslab> <pagesperslab> : slabdata \
+        <partial_slabs>")?;
+    let mut result = Ok(());
+    for_each_slab_cache(|cache| {
+        result = writeln!(out, "{:<17} {:>9} {:>6} {:>12} {:>14} : slabdata {:>15}",
+            cache.name(), cache.object_size(), cache.size(), cache.objects_per_slab(),
+            cache.pages_per_slab(), cache.nr_partial());
+        result.is_ok()
+    });
+    result
+}
+
+/// Write `/proc/cpuinfo`, a block for each CPU.
+fn show_cpuinfo(out: &mut String) -> fmt::Result {
+    for (cpu_id, node) in crate::init::device_tree().cpus().enumerate() {
+        let prop = |name| node.property(name).and_then(|prop| prop.as_str()).unwrap_or("");
+        let mmu = prop("mmu-type");
+        writeln!(out, "processor\t: {}", cpu_id)?;
+        writeln!(out, "hart\t\t: {}", get_cpu_info_by_cpuid(cpu_id).get_hart_id())?;
+        writeln!(out, "isa\t\t: {}", prop("riscv,isa"))?;
+        writeln!(out, "mmu\t\t: {}", mmu.strip_prefix("riscv,").unwrap_or(mmu))?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Get the state of a process as the letter and the name of Linux: the most active state of the
+/// threads.
+fn process_state(process: &mut Process) -> (char, &'static str) {
+    if process.is_zombie() {
+        return ('Z', "zombie");
+    }
+    let mut state = ('S', "sleeping");
+    process.for_each_thread(|task| {
+        match task.status() {
+            TaskStatus::Ready | TaskStatus::Running => {
+                state = ('R', "running");
+                return false;
+            }
+            TaskStatus::Stopped => state = ('T', "stopped"),
+            TaskStatus::UninterruptibleSleep if state.0 == 'S' => state = ('D', "disk sleep"),
+            _ => {}
+        }
+        true
+    });
+    state
+}
+
+/// Get the virtual memory size and the resident set size of a process in bytes, zero for a
+/// zombie, whose address space has been destroyed.
+fn process_memory(process: &Process) -> (usize, usize) {
+    if process.is_zombie() {
+        return (0, 0);
+    }
+    let aspace = process.address_space();
+    (aspace.mapped_size(), aspace.resident_size())
+}
+
+/// Write `/proc/<pid>/status`. There are no users yet, all IDs are root.
+fn show_pid_status(out: &mut String, process: &mut Process) -> fmt::Result {
+    let (state, name) = process_state(process);
+    writeln!(out, "Name:\t{}", process.comm())?;
+    writeln!(out, "State:\t{} ({})", state, name)?;
+    writeln!(out, "Tgid:\t{}", process.pid())?;
+    writeln!(out, "Pid:\t{}", process.pid())?;
+    writeln!(out, "PPid:\t{}", process.ppid())?;
+    writeln!(out, "Uid:\t0\t0\t0\t0")?;
+    writeln!(out, "Gid:\t0\t0\t0\t0")?;
+    if !process.is_zombie() {
+        let (vm_size, rss) = process_memory(process);
+        writeln!(out, "VmSize:\t{:>8} kB", vm_size / 1024)?;
+        writeln!(out, "VmRSS:\t{:>8} kB", rss / 1024)?;
+    }
+    writeln!(out, "Threads:\t{}", process.thread_count())
+}
+
+/// Write `/proc/<pid>/maps`, a line for each VMA. The files are not named, the heap and the main
+/// stack are.
+fn show_pid_maps(out: &mut String, process: &mut Process) -> fmt::Result {
+    if process.is_zombie() {
+        return Ok(());
+    }
+    let aspace = process.address_space();
+    let heap_start = align_up(aspace.brk_start(), PAGE_ORDER);
+    let mut vma = aspace.vmas().first();
+    while !vma.is_null() {
+        let v = unsafe { &*vma };
+        let flag = |bit: EntryBits, c| if v.bits() & bit.val() != 0 { c } else { '-' };
+        let offset = match v.backing() {
+            VmaBacking::File { offset, .. } => *offset,
+            _ => 0,
+        };
+        let line = format!("{:08x}-{:08x} {}{}{}{} {:08x} 00:00 0", v.start(), v.end(),
+            flag(EntryBits::Read, 'r'), flag(EntryBits::Write, 'w'),
+            flag(EntryBits::Execute, 'x'), if v.is_private() { 'p' } else { 's' }, offset);
+        let name = if v.start() == heap_start && aspace.brk() > aspace.brk_start() {
+            "[heap]"
+        } else if v.end() > MMAP_TOP {
+            "[stack]"
+        } else {
+            ""
+        };
+        if name.is_empty() {
+            writeln!(out, "{}", line)?;
+        } else {
+            writeln!(out, "{:<72} {}", line, name)?;
+        }
+        vma = VmaTree::next(vma);
+    }
+    Ok(())
+}
+
+/// Write `/proc/<pid>/stat`, the first 24 fields of Linux. The fields which are not tracked,
+/// like the page faults and the CPU times, are 0.
+fn show_pid_stat(out: &mut String, process: &mut Process) -> fmt::Result {
+    let (state, _) = process_state(process);
+    let (vm_size, rss) = process_memory(process);
+    writeln!(out, "{} ({}) {} {} {} {} 0 -1 0 0 0 0 0 0 0 0 0 20 0 {} 0 0 {} {}",
+        process.pid(), process.comm(), state, process.ppid(), process.pgid(), process.sid(),
+        process.thread_count(), vm_size, rss / PAGE_SIZE)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use crate::fs::lookup_path;
+    use crate::fs::vfs::read_link;
+    use crate::mm::address_space::MapPlacement;
+    use crate::mm::USER_SPACE_START;
+    use crate::proc::kernel::ctx::self_task_info_mut;
+    use crate::proc::user::{build_user_thread, free_user_thread, USER_STACK_SIZE, USER_STACK_TOP};
+    use super::*;
+
+    /// Read the whole file at `path`, in small chunks to check the reads at an offset.
+    fn read_file(path: &str) -> Result<String, i32> {
+        let dentry = lookup_path(None, path, true)?;
+        let mut content = Vec::new();
+        let mut buf = [0u8; 100];
+        loop {
+            let len = dentry.inode().read_at(content.len(), &mut buf)?;
+            if len == 0 {
+                break;
+            }
+            content.extend_from_slice(&buf[..len]);
+        }
+        Ok(String::from_utf8(content).unwrap())
+    }
+
+    #[kernel_test]
+    fn meminfo_fields() {
+        let content = read_file("/proc/meminfo").unwrap();
+        let fields: Vec<(&str, usize)> = content.lines().map(|line| {
+            // The names are padded to 16 columns and the values to 8, like Linux.
+            assert_eq!(line.len(), 27, "{:?}", line);
+            assert_eq!(line.find(|c: char| c.is_ascii_digit()).map(|pos| pos >= 16), Some(true));
+            let (name, value) = line.split_once(':').unwrap();
+            (name, value.strip_suffix(" kB").unwrap().trim_start().parse().unwrap())
+        }).collect();
+        let names: Vec<&str> = fields.iter().map(|&(name, _)| name).collect();
+        assert_eq!(names, ["MemTotal", "MemFree", "MemAvailable", "Cached", "Dirty", "Slab",
+            "SUnreclaim"]);
+
+        let field = |name| fields.iter().find(|&&(field, _)| field == name).unwrap().1;
+        assert_eq!(field("MemTotal"), page::nr_total_pages() * (PAGE_SIZE / 1024));
+        assert!(field("MemFree") > 0 && field("MemFree") <= field("MemAvailable"));
+        assert!(field("MemAvailable") <= field("MemTotal"));
+        assert!(field("Dirty") <= field("Cached"));
+        assert!(field("Slab") > 0);
+        assert_eq!(field("Slab"), field("SUnreclaim"));
+    }
+
+    #[kernel_test]
+    fn process_status_and_maps() {
+        let process = unsafe { &mut *Process::create() };
+        let task = build_user_thread(process, 0, 0);
+        assert!(!task.is_null());
+        let pid = process.pid();
+        process.set_comm(b"/bin/procfs-test-program");
+        let rw = EntryBits::ReadWrite.val();
+        let aspace = process.address_space_mut();
+        let heap = USER_SPACE_START + 0x10_0000;
+        aspace.set_brk_start(heap);
+        aspace.set_brk(heap + 3 * PAGE_SIZE);
+        let shared = aspace.map_anonymous(MapPlacement::Hint(0), 2 * PAGE_SIZE,
+                                          EntryBits::Read.val(), true).unwrap();
+        let stack = USER_STACK_TOP - USER_STACK_SIZE;
+        aspace.add_vma(stack, USER_STACK_SIZE, rw, VmaBacking::Anonymous).unwrap();
+        assert!(aspace.write_bytes(heap, &[1]));
+        assert!(aspace.write_bytes(USER_STACK_TOP - 8, &[2]));
+
+        // The `self` of the kernel thread running on behalf of the process.
+        let current = self_task_info_mut();
+        assert_eq!(read_link(None, "/proc/self"), Err(E_NO_ENT));
+        current.set_process(process);
+        let link = read_link(None, "/proc/self");
+        let status = read_file("/proc/self/status");
+        current.set_process(core::ptr::null_mut());
+        assert_eq!(link, Ok(pid.to_string()));
+
+        let vm_size = (5 * PAGE_SIZE + USER_STACK_SIZE) / 1024;
+        let expected = format!("Name:\tprocfs-test-pro\nState:\tR (running)\nTgid:\t{pid}\n\
+            Pid:\t{pid}\nPPid:\t0\nUid:\t0\t0\t0\t0\nGid:\t0\t0\t0\t0\n\
+            VmSize:\t{:>8} kB\nVmRSS:\t{:>8} kB\nThreads:\t1\n", vm_size, 2 * PAGE_SIZE / 1024,
+            pid = pid);
+        assert_eq!(status, Ok(expected));
+
+        let maps = read_file(&format!("/proc/{}/maps", pid)).unwrap();
+        let lines: Vec<&str> = maps.lines().collect();
+        let heap_line = format!("{:08x}-{:08x} rw-p 00000000 00:00 0", heap, heap + 3 * PAGE_SIZE);
+        assert_eq!(lines[0], format!("{:<72} [heap]", heap_line));
+        assert_eq!(lines[1], format!("{:08x}-{:08x} r--s 00000000 00:00 0", shared,
+            shared + 2 * PAGE_SIZE));
+        let stack_line = format!("{:08x}-{:08x} rw-p 00000000 00:00 0", stack, USER_STACK_TOP);
+        assert_eq!(lines[2], format!("{:<72} [stack]", stack_line));
+        assert_eq!(lines.len(), 3);
+
+        let stat = read_file(&format!("/proc/{}/stat", pid)).unwrap();
+        let fields: Vec<&str> = stat.split_whitespace().collect();
+        assert_eq!(fields.len(), 24);
+        assert_eq!(fields[..4], [pid.to_string().as_str(), "(procfs-test-pro)", "R", "0"]);
+        assert_eq!(fields[23], "2");
+
+        unsafe {
+            free_user_thread(task);
+            Process::destroy(process);
+        }
+        assert_eq!(read_file(&format!("/proc/{}/status", pid)), Err(E_NO_ENT));
+    }
+
+    #[kernel_test]
+    fn root_entries() {
+        let root = lookup_path(None, "/proc", true).unwrap();
+        let mut names = Vec::new();
+        let mut offset = 0;
+        while let Some((entry, next)) = root.inode().read_dir(offset).unwrap() {
+            names.push((entry.name, entry.kind));
+            offset = next;
+        }
+        assert_eq!(names[..8].iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(),
+            [".", "..", "meminfo", "slabinfo", "interrupts", "cpuinfo", "cmdline", "self"]);
+        assert_eq!(names[7].1, InodeType::Symlink);
+        assert!(names[8..].iter().all(|(name, kind)| {
+            *kind == InodeType::Dir && name.parse::<u32>().is_ok()
+        }));
+
+        let interrupts = read_file("/proc/interrupts").unwrap();
+        assert!(interrupts.lines().next().unwrap().starts_with("           CPU0"));
+        assert!(interrupts.contains("Local timer interrupts"));
+        assert_eq!(read_file("/proc/meminfo/x"), Err(E_NOT_DIR));
+        assert_eq!(read_file("/proc/0"), Err(E_NO_ENT));
+    }
+}
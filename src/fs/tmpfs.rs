@@ -0,0 +1,601 @@
+//! In-memory file system (tmpfs), which is also the root file system.
+//!
+//! All files live in the memory: the content of a regular file is kept in the pages allocated
+//! from the buddy allocator ([`mm::page`]), indexed by the page index in the file. Only the
+//! written pages are allocated, so a file can be sparse: the holes read as zeros. Truncating a
+//! file frees the pages after the new size.
+//!
+//! The file system is registered as `tmpfs` and `ramfs`. The mount option `size=<bytes>` limits
+//! the total size of the file pages, with an optional `k`, `m` or `g` suffix; a write beyond the
+//! limit fails with `ENOSPC`. Without the option, the size is only limited by the free memory.
+//!
+//! The device files, the FIFOs and the sockets are created by `mknod`, a tmpfs also backs
+//! [`devtmpfs`](super::devtmpfs).
+//!
+//! Each inode is protected by its own lock. A directory holds its children, and a removed inode
+//! is freed with its pages when the last open file of it is closed. A rename takes the locks of
+//! the two directories one at a time under the rename lock of the file system.
+//!
+//! [`mm::page`]: crate::mm::page
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::any::Any;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use crate::base::sync::lock::SpinLockPure;
+use crate::errno::{E_CROSS_DEV, E_EXIST, E_INVALID, E_IS_DIR, E_NO_ENT, E_NO_MEM, E_NO_SPACE,
+                   E_NOT_DIR, E_NOT_EMPTY};
+use crate::fs::inode::{current_time, DirEntry, Inode, InodeType, Stat};
+use crate::fs::mount::{alloc_anon_dev, FileSystem, SuperBlock};
+use crate::mm::page;
+use crate::mm::PAGE_SIZE;
+
+
+/// Inode number of the root directory.
+const ROOT_INO: u64 = 1;
+
+/// A tmpfs type, registered by its name.
+pub struct TmpFs {
+    name: &'static str,
+}
+
+pub static TMPFS: TmpFs = TmpFs { name: "tmpfs" };
+pub static RAMFS: TmpFs = TmpFs { name: "ramfs" };
+
+/// State of a mounted tmpfs shared by its inodes.
+struct TmpFsInfo {
+    dev: u64,
+    /// Max count of the file pages.
+    max_pages: usize,
+    used_pages: AtomicUsize,
+    next_ino: AtomicU64,
+    /// Serializes the renames, which change two directories.
+    rename_lock: SpinLockPure,
+}
+
+impl TmpFsInfo {
+    /// Account a new file page. Returns `Err` with `E_NO_SPACE` if the size limit is reached.
+    fn charge_page(&self) -> Result<(), i32> {
+        self.used_pages.fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+            if used < self.max_pages { Some(used + 1) } else { None }
+        }).map(|_| ()).map_err(|_| E_NO_SPACE)
+    }
+
+    fn uncharge_pages(&self, count: usize) {
+        self.used_pages.fetch_sub(count, Ordering::AcqRel);
+    }
+}
+
+/// A mounted tmpfs.
+struct TmpSuperBlock {
+    root: Arc<TmpInode>,
+}
+
+/// Content of a tmpfs inode.
+enum TmpData {
+    /// Physical addresses of the file pages by the page index.
+    File(BTreeMap<usize, usize>),
+    /// Children by the name, and the inode number of the parent.
+    Dir(BTreeMap<String, Arc<TmpInode>>, u64),
+    Symlink(String),
+    /// Device number of a device file, 0 for a FIFO or a socket.
+    Special(u64),
+}
+
+/// Fields of a tmpfs inode protected by the inode lock.
+struct TmpInodeInner {
+    /// Permission bits.
+    mode: u32,
+    nlink: u32,
+    /// Size of a regular file.
+    size: usize,
+    atime: u64,
+    mtime: u64,
+    ctime: u64,
+    data: TmpData,
+}
+
+/// A tmpfs inode.
+struct TmpInode {
+    ino: u64,
+    kind: InodeType,
+    info: Arc<TmpFsInfo>,
+    lock: SpinLockPure,
+    inner: UnsafeCell<TmpInodeInner>,
+}
+
+impl TmpInode {
+    fn new(info: &Arc<TmpFsInfo>, ino: u64, kind: InodeType, mode: u32, data: TmpData)
+        -> Arc<TmpInode> {
+        let now = current_time();
+        let nlink = if kind == InodeType::Dir { 2 } else { 1 };
+        Arc::new(TmpInode {
+            ino,
+            kind,
+            info: info.clone(),
+            lock: SpinLockPure::new(),
+            inner: UnsafeCell::new(TmpInodeInner {
+                mode, nlink, size: 0, atime: now, mtime: now, ctime: now, data,
+            }),
+        })
+    }
+
+    /// Call `f` with the fields under the inode lock.
+    fn with_inner<F, R>(&self, f: F) -> R where F: FnOnce(&mut TmpInodeInner) -> R {
+        let _guard = self.lock.lock_guard_irq_save();
+        f(unsafe { &mut *self.inner.get() })
+    }
+
+    /// Call `f` with the fields and the children of a directory under the inode lock. Returns
+    /// `Err` with `E_NOT_DIR` if it is not a directory.
+    fn with_dir<F, R>(&self, f: F) -> Result<R, i32>
+        where F: FnOnce(&mut TmpInodeInner, &mut BTreeMap<String, Arc<TmpInode>>, u64)
+            -> Result<R, i32> {
+        self.with_inner(|inner| {
+            // Take the children out to borrow them with the other fields.
+            let TmpData::Dir(children, parent) = &mut inner.data else {
+                return Err(E_NOT_DIR);
+            };
+            let (mut children, parent) = (core::mem::take(children), *parent);
+            let ret = f(inner, &mut children, parent);
+            inner.data = TmpData::Dir(children, parent);
+            ret
+        })
+    }
+
+    /// Add a new child `name` created by `build` with a new inode number to the directory.
+    fn add_child<F>(&self, name: &str, build: F) -> Result<Arc<dyn Inode>, i32>
+        where F: FnOnce(u64) -> Arc<TmpInode> {
+        self.with_dir(|inner, children, _| {
+            if children.contains_key(name) {
+                return Err(E_EXIST);
+            }
+            let child = build(self.info.next_ino.fetch_add(1, Ordering::Relaxed));
+            if child.kind == InodeType::Dir {
+                inner.nlink += 1;
+            }
+            inner.mtime = current_time();
+            inner.ctime = inner.mtime;
+            children.insert(String::from(name), child.clone());
+            Ok(child as Arc<dyn Inode>)
+        })
+    }
+
+    /// Free the file pages from the page index `start`. The inode lock must be held.
+    fn free_pages_from(&self, pages: &mut BTreeMap<usize, usize>, start: usize) {
+        let freed = pages.split_off(&start);
+        for &addr in freed.values() {
+            page::free_page(addr);
+        }
+        self.info.uncharge_pages(freed.len());
+    }
+}
+
+impl Drop for TmpInode {
+    fn drop(&mut self) {
+        if let TmpData::File(pages) = &mut self.inner.get_mut().data {
+            let mut pages = core::mem::take(pages);
+            self.free_pages_from(&mut pages, 0);
+        }
+    }
+}
+
+impl Inode for TmpInode {
+    fn stat(&self) -> Result<Stat, i32> {
+        Ok(self.with_inner(|inner| {
+            let (size, pages, rdev) = match &inner.data {
+                TmpData::File(pages) => (inner.size, pages.len(), 0),
+                TmpData::Dir(children, _) => (children.len() + 2, 0, 0),
+                TmpData::Symlink(target) => (target.len(), 0, 0),
+                TmpData::Special(dev) => (0, 0, *dev),
+            };
+            Stat {
+                dev: self.info.dev,
+                ino: self.ino,
+                mode: self.kind.mode() | inner.mode,
+                nlink: inner.nlink,
+                rdev,
+                size: size as u64,
+                blksize: PAGE_SIZE as u32,
+                blocks: (pages * (PAGE_SIZE / 512)) as u64,
+                atime: inner.atime,
+                mtime: inner.mtime,
+                ctime: inner.ctime,
+                ..Stat::default()
+            }
+        }))
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, i32> {
+        self.with_inner(|inner| {
+            let TmpData::File(pages) = &inner.data else {
+                return Err(if self.kind == InodeType::Dir { E_IS_DIR } else { E_INVALID });
+            };
+            if offset >= inner.size {
+                return Ok(0);
+            }
+
+            let len = buf.len().min(inner.size - offset);
+            let mut done = 0;
+            while done < len {
+                let pos = offset + done;
+                let page_offset = pos % PAGE_SIZE;
+                let chunk = (PAGE_SIZE - page_offset).min(len - done);
+                let dst = &mut buf[done..done + chunk];
+                match pages.get(&(pos / PAGE_SIZE)) {
+                    Some(&addr) => unsafe {
+                        let src = (addr + page_offset) as *const u8;
+                        dst.copy_from_slice(core::slice::from_raw_parts(src, chunk));
+                    },
+                    // A hole.
+                    None => dst.fill(0),
+                }
+                done += chunk;
+            }
+            inner.atime = current_time();
+            Ok(len)
+        })
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, i32> {
+        offset.checked_add(buf.len()).filter(|&end| end <= isize::MAX as usize)
+            .ok_or(E_INVALID)?;
+        self.with_inner(|inner| {
+            let TmpData::File(pages) = &mut inner.data else {
+                return Err(if self.kind == InodeType::Dir { E_IS_DIR } else { E_INVALID });
+            };
+
+            let mut done = 0;
+            let mut result = Ok(());
+            while done < buf.len() {
+                let pos = offset + done;
+                let page_offset = pos % PAGE_SIZE;
+                let chunk = (PAGE_SIZE - page_offset).min(buf.len() - done);
+                let addr = match pages.get(&(pos / PAGE_SIZE)) {
+                    Some(&addr) => addr,
+                    None => {
+                        if let Err(errno) = self.info.charge_page() {
+                            result = Err(errno);
+                            break;
+                        }
+                        let addr = page::alloc_zeroed_page(0);
+                        if addr == 0 {
+                            self.info.uncharge_pages(1);
+                            result = Err(E_NO_MEM);
+                            break;
+                        }
+                        pages.insert(pos / PAGE_SIZE, addr);
+                        addr
+                    }
+                };
+                unsafe {
+                    let dst = (addr + page_offset) as *mut u8;
+                    core::slice::from_raw_parts_mut(dst, chunk)
+                        .copy_from_slice(&buf[done..done + chunk]);
+                }
+                done += chunk;
+            }
+
+            // Returns the count written before the error.
+            if done == 0 && !buf.is_empty() {
+                return result.map(|_| 0);
+            }
+            inner.size = inner.size.max(offset + done);
+            inner.mtime = current_time();
+            inner.ctime = inner.mtime;
+            Ok(done)
+        })
+    }
+
+    fn truncate(&self, size: usize) -> Result<(), i32> {
+        if size > isize::MAX as usize {
+            return Err(E_INVALID);
+        }
+        self.with_inner(|inner| {
+            let TmpData::File(pages) = &mut inner.data else {
+                return Err(if self.kind == InodeType::Dir { E_IS_DIR } else { E_INVALID });
+            };
+            if size < inner.size {
+                self.free_pages_from(pages, (size + PAGE_SIZE - 1) / PAGE_SIZE);
+                // Zero the tail of the last page, which may be exposed by extending later.
+                if let Some(&addr) = pages.get(&(size / PAGE_SIZE)) {
+                    let page_offset = size % PAGE_SIZE;
+                    unsafe {
+                        ((addr + page_offset) as *mut u8).write_bytes(0, PAGE_SIZE - page_offset);
+                    }
+                }
+            }
+            inner.size = size;
+            inner.mtime = current_time();
+            inner.ctime = inner.mtime;
+            Ok(())
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, i32> {
+        self.with_dir(|_, children, _| {
+            children.get(name).map(|child| child.clone() as Arc<dyn Inode>).ok_or(E_NO_ENT)
+        })
+    }
+
+    fn create(&self, name: &str, kind: InodeType, mode: u32) -> Result<Arc<dyn Inode>, i32> {
+        let data = match kind {
+            InodeType::File => TmpData::File(BTreeMap::new()),
+            InodeType::Dir => TmpData::Dir(BTreeMap::new(), self.ino),
+            _ => return Err(E_INVALID),
+        };
+        self.add_child(name, |ino| TmpInode::new(&self.info, ino, kind, mode, data))
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>, i32> {
+        let data = TmpData::Symlink(String::from(target));
+        self.add_child(name, |ino| TmpInode::new(&self.info, ino, InodeType::Symlink, 0o777, data))
+    }
+
+    fn mknod(&self, name: &str, kind: InodeType, mode: u32, dev: u64)
+        -> Result<Arc<dyn Inode>, i32> {
+        let data = match kind {
+            InodeType::CharDevice | InodeType::BlockDevice => TmpData::Special(dev),
+            InodeType::Fifo | InodeType::Socket => TmpData::Special(0),
+            _ => return Err(E_INVALID),
+        };
+        self.add_child(name, |ino| TmpInode::new(&self.info, ino, kind, mode, data))
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), i32> {
+        let child = self.with_dir(|inner, children, _| {
+            let child = children.get(name).ok_or(E_NO_ENT)?;
+            let empty_dir = child.with_inner(|child| match &child.data {
+                TmpData::Dir(grandchildren, _) => Some(grandchildren.is_empty()),
+                _ => None,
+            });
+            match empty_dir {
+                Some(false) => return Err(E_NOT_EMPTY),
+                Some(true) => inner.nlink -= 1,
+                None => {}
+            }
+            inner.mtime = current_time();
+            inner.ctime = inner.mtime;
+            Ok(children.remove(name).unwrap())
+        })?;
+
+        // The child is freed after the last open file is closed.
+        child.with_inner(|inner| {
+            inner.nlink = 0;
+            inner.ctime = current_time();
+        });
+        Ok(())
+    }
+
+    fn rename(&self, old_name: &str, new_dir: &Arc<dyn Inode>, new_name: &str)
+        -> Result<(), i32> {
+        let new_dir = new_dir.as_any().downcast_ref::<TmpInode>()
+            .filter(|dir| Arc::ptr_eq(&dir.info, &self.info))
+            .ok_or(E_CROSS_DEV)?;
+        let _guard = self.info.rename_lock.lock_guard_irq_save();
+        let child = self.with_dir(|_, children, _| {
+            children.get(old_name).cloned().ok_or(E_NO_ENT)
+        })?;
+        let same_dir = core::ptr::eq(self, new_dir);
+        let moved_dir = child.kind == InodeType::Dir && !same_dir;
+        let now = current_time();
+
+        let replaced = new_dir.with_dir(|inner, children, _| {
+            if let Some(target) = children.get(new_name) {
+                if Arc::ptr_eq(target, &child) {
+                    return Ok(None);
+                }
+                let empty_dir = target.with_inner(|target| match &target.data {
+                    TmpData::Dir(grandchildren, _) => Some(grandchildren.is_empty()),
+                    _ => None,
+                });
+                match empty_dir {
+                    Some(false) => return Err(E_NOT_EMPTY),
+                    Some(true) => inner.nlink -= 1,
+                    None => {}
+                }
+            }
+            if moved_dir {
+                inner.nlink += 1;
+            }
+            if same_dir {
+                children.remove(old_name);
+            }
+            inner.mtime = now;
+            inner.ctime = now;
+            Ok(children.insert(String::from(new_name), child.clone()))
+        })?;
+        if !same_dir {
+            self.with_dir(|inner, children, _| {
+                // Removed by an unlink meanwhile.
+                if children.get(old_name).map_or(false, |cur| Arc::ptr_eq(cur, &child)) {
+                    children.remove(old_name);
+                }
+                if moved_dir {
+                    inner.nlink -= 1;
+                }
+                inner.mtime = now;
+                inner.ctime = now;
+                Ok(())
+            })?;
+        }
+
+        child.with_inner(|inner| {
+            inner.ctime = now;
+            if let TmpData::Dir(_, parent) = &mut inner.data {
+                *parent = new_dir.ino;
+            }
+        });
+        if let Some(replaced) = replaced {
+            replaced.with_inner(|inner| {
+                inner.nlink = 0;
+                inner.ctime = now;
+            });
+        }
+        Ok(())
+    }
+
+    fn read_dir(&self, offset: usize) -> Result<Option<(DirEntry, usize)>, i32> {
+        self.with_dir(|_, children, parent| {
+            let entry = match offset {
+                0 => DirEntry { ino: self.ino, kind: InodeType::Dir, name: String::from(".") },
+                1 => DirEntry { ino: parent, kind: InodeType::Dir, name: String::from("..") },
+                _ => match children.iter().nth(offset - 2) {
+                    Some((name, child)) => {
+                        DirEntry { ino: child.ino, kind: child.kind, name: name.clone() }
+                    }
+                    None => return Ok(None),
+                },
+            };
+            Ok(Some((entry, offset + 1)))
+        })
+    }
+
+    fn read_link(&self) -> Result<String, i32> {
+        self.with_inner(|inner| match &inner.data {
+            TmpData::Symlink(target) => Ok(target.clone()),
+            _ => Err(E_INVALID),
+        })
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl SuperBlock for TmpSuperBlock {
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+/// Parse a size with an optional `k`, `m` or `g` suffix.
+fn parse_size(val: &str) -> Option<usize> {
+    let (num, shift) = match val.as_bytes().last()? {
+        b'k' | b'K' => (&val[..val.len() - 1], 10),
+        b'm' | b'M' => (&val[..val.len() - 1], 20),
+        b'g' | b'G' => (&val[..val.len() - 1], 30),
+        _ => (val, 0),
+    };
+    num.parse::<usize>().ok()?.checked_mul(1 << shift)
+}
+
+impl FileSystem for TmpFs {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Mount a new empty tmpfs, the `source` is ignored. The `data` is the comma-separated
+    /// options, only `size=<bytes>` is supported.
+    fn mount(&self, _source: &str, data: &str) -> Result<Arc<dyn SuperBlock>, i32> {
+        let mut max_pages = usize::MAX;
+        for option in data.split(',').filter(|option| !option.is_empty()) {
+            match option.split_once('=') {
+                Some(("size", val)) => {
+                    let size = parse_size(val).ok_or(E_INVALID)?;
+                    max_pages = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+                }
+                _ => return Err(E_INVALID),
+            }
+        }
+
+        let info = Arc::new(TmpFsInfo {
+            dev: alloc_anon_dev(),
+            max_pages,
+            used_pages: AtomicUsize::new(0),
+            next_ino: AtomicU64::new(ROOT_INO + 1),
+            rename_lock: SpinLockPure::new(),
+        });
+        let root = TmpInode::new(&info, ROOT_INO, InodeType::Dir, 0o755,
+                                 TmpData::Dir(BTreeMap::new(), ROOT_INO));
+        Ok(Arc::new(TmpSuperBlock { root }))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errno::{E_NO_DEV_OR_ADDR, E_PERM};
+    use crate::fs::dentry::{lookup_path, Dentry};
+    use crate::fs::file::{O_CREAT, O_DIRECTORY, O_RDONLY, O_RDWR, SEEK_SET};
+    use crate::fs::inode::{make_dev, S_IFCHR};
+    use crate::fs::vfs;
+
+    #[kernel_test]
+    fn tmpfs_files() {
+        let sb = TMPFS.mount("tmpfs", "size=8k").unwrap();
+        let root = Dentry::new(String::from("/"), sb.root(), None);
+        let base = Some(&root);
+        vfs::mkdir(base, "dir", 0o755).unwrap();
+        assert_eq!(vfs::mkdir(base, "dir", 0o755), Err(E_EXIST));
+        let file = vfs::open(base, "dir/file", O_RDWR | O_CREAT, 0o644).unwrap();
+
+        // A sparse file only allocates the written pages, the holes read as zeros.
+        assert_eq!(file.seek(PAGE_SIZE as isize + 10, SEEK_SET), Ok(PAGE_SIZE + 10));
+        assert_eq!(file.write(b"hello"), Ok(5));
+        let stat = vfs::stat(base, "dir/file", true).unwrap();
+        assert_eq!((stat.size, stat.blocks), (PAGE_SIZE as u64 + 15, 8));
+        let mut buf = [0xffu8; 16];
+        let inode = lookup_path(base, "dir/file", true).unwrap().inode().clone();
+        assert_eq!(inode.read_at(PAGE_SIZE, &mut buf), Ok(15));
+        assert_eq!(&buf[..15], b"\0\0\0\0\0\0\0\0\0\0hello");
+
+        // The size limit allows 2 pages.
+        assert_eq!(inode.write_at(0, &[1u8; PAGE_SIZE + 1]), Ok(PAGE_SIZE));
+        assert_eq!(inode.write_at(2 * PAGE_SIZE, &[1u8]), Err(E_NO_SPACE));
+        inode.truncate(3).unwrap();
+        assert_eq!(inode.read_at(0, &mut buf), Ok(3));
+        inode.truncate(8).unwrap();
+        assert_eq!(inode.read_at(0, &mut buf), Ok(8));
+        assert_eq!(&buf[..8], &[1, 1, 1, 0, 0, 0, 0, 0]);
+
+        // Symlinks are followed, a non-empty directory can not be removed.
+        vfs::symlink(base, "dir/file", "link").unwrap();
+        assert_eq!(vfs::stat(base, "link", true).unwrap().ino, stat.ino);
+        assert_eq!(vfs::read_link(base, "link").unwrap(), "dir/file");
+        assert_eq!(vfs::unlink(base, "dir", true), Err(E_NOT_EMPTY));
+        assert_eq!(vfs::unlink(base, "dir/file", true), Err(E_NOT_DIR));
+        vfs::unlink(base, "dir/file", false).unwrap();
+        assert_eq!(vfs::stat(base, "link", true).err(), Some(E_NO_ENT));
+        vfs::unlink(base, "dir", true).unwrap();
+
+        assert_eq!(sb.root().stat().unwrap().nlink, 2);
+        let mut names = alloc::vec::Vec::new();
+        let dir = vfs::open(base, ".", O_RDONLY | O_DIRECTORY, 0).unwrap();
+        dir.read_dir(&mut |entry, _| { names.push(entry.name.clone()); true }).unwrap();
+        assert_eq!(names, [".", "..", "link"]);
+        assert_eq!(vfs::open(base, "../link", O_RDONLY, 0).err(), Some(E_NO_ENT));
+
+        // The pages of a removed file are freed after it is closed.
+        drop((file, inode));
+        let file = vfs::open(base, "file", O_RDWR | O_CREAT, 0o644).unwrap();
+        assert_eq!(file.write(&[1u8; 2 * PAGE_SIZE]), Ok(2 * PAGE_SIZE));
+    }
+
+    #[kernel_test]
+    fn tmpfs_device_files() {
+        let sb = TMPFS.mount("tmpfs", "").unwrap();
+        let root = Dentry::new(String::from("/"), sb.root(), None);
+        let base = Some(&root);
+
+        // A device file is opened by the driver of its number, here `/dev/zero`.
+        vfs::mknod(base, "zero", S_IFCHR | 0o666, make_dev(1, 5)).unwrap();
+        let stat = vfs::stat(base, "zero", true).unwrap();
+        assert_eq!((stat.mode, stat.rdev, stat.size), (S_IFCHR | 0o666, make_dev(1, 5), 0));
+        let file = vfs::open(base, "zero", O_RDWR, 0).unwrap();
+        let mut buf = [0xffu8; 16];
+        assert_eq!(file.read(&mut buf), Ok(16));
+        assert_eq!(buf, [0u8; 16]);
+        assert_eq!(vfs::mknod(base, "zero", S_IFCHR | 0o666, 0), Err(E_EXIST));
+
+        // A number without a driver can not be opened, a FIFO keeps no device number.
+        vfs::mknod(base, "none", S_IFCHR | 0o600, make_dev(1, 1000)).unwrap();
+        assert_eq!(vfs::open(base, "none", O_RDONLY, 0).err(), Some(E_NO_DEV_OR_ADDR));
+        vfs::mknod(base, "fifo", InodeType::Fifo.mode() | 0o644, make_dev(1, 5)).unwrap();
+        assert_eq!(vfs::stat(base, "fifo", true).unwrap().rdev, 0);
+        assert_eq!(vfs::mknod(base, "dir", InodeType::Dir.mode() | 0o755, 0), Err(E_PERM));
+    }
+}
@@ -0,0 +1,276 @@
+//! Generic file system operations on the paths, used by the syscalls.
+//!
+//! The paths are resolved relative to a base directory (the current directory or the directory
+//! of a `dirfd`) as in [`lookup_path`], then the operation is dispatched to the inode of the
+//! concrete file system.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use crate::errno::{E_BUSY, E_CROSS_DEV, E_EXIST, E_INVALID, E_IS_DIR, E_LOOP, E_NO_ENT,
+                   E_NOT_DIR, E_PERM};
+use crate::block::fops::blkdev_open;
+use crate::fs::char_dev::chrdev_open;
+use crate::fs::dentry::{lookup_parent, lookup_path, Dentry};
+use crate::fs::file::{writable, File, InodeFile, O_CLOEXEC, O_CREAT, O_DIRECTORY, O_EXCL,
+                      O_NOCTTY, O_NOFOLLOW, O_TRUNC};
+use crate::fs::inode::{InodeType, Stat, S_IALLUGO, S_IFMT};
+use crate::fs::mount::is_mount_point;
+
+
+/// `renameat2` flag: fail with `EEXIST` instead of replacing the target.
+pub const RENAME_NOREPLACE: usize = 1;
+
+/// Open the file at `path` relative to `base` with the open `flags`, a regular file is created
+/// with the permission bits `mode` if `O_CREAT` is set and the file does not exist. A device
+/// file is opened by the driver of its device number.
+pub fn open(base: Option<&Arc<Dentry>>, path: &str, flags: usize, mode: u32)
+    -> Result<Arc<dyn File>, i32> {
+    let follow = flags & (O_NOFOLLOW | O_EXCL) == 0;
+    let dentry = if flags & O_CREAT != 0 {
+        if path.ends_with('/') {
+            return Err(E_IS_DIR);
+        }
+        let (parent, name) = lookup_parent(base, path)?;
+        match lookup_path(Some(&parent), name, follow) {
+            Ok(_) if flags & O_EXCL != 0 => return Err(E_EXIST),
+            Ok(dentry) => dentry,
+            Err(E_NO_ENT) => {
+                let inode = parent.inode().create(name, InodeType::File, mode & S_IALLUGO)?;
+                Dentry::new(String::from(name), inode, Some(parent))
+            }
+            Err(errno) => return Err(errno),
+        }
+    } else {
+        lookup_path(base, path, follow)?
+    };
+
+    let kind = dentry.inode_type()?;
+    match kind {
+        InodeType::Symlink => return Err(E_LOOP),
+        InodeType::Dir if writable(flags) || flags & O_CREAT != 0 => return Err(E_IS_DIR),
+        _ if flags & O_DIRECTORY != 0 && kind != InodeType::Dir => return Err(E_NOT_DIR),
+        InodeType::File if flags & O_TRUNC != 0 && writable(flags) => {
+            dentry.inode().truncate(0)?;
+        }
+        _ => {}
+    }
+
+    let flags = flags & !(O_CREAT | O_EXCL | O_NOCTTY | O_TRUNC | O_CLOEXEC);
+    match kind {
+        InodeType::CharDevice => chrdev_open(dentry.inode().stat()?.rdev, flags),
+        InodeType::BlockDevice => blkdev_open(dentry.inode().stat()?.rdev, flags),
+        _ => Ok(Arc::new(InodeFile::new(dentry, flags))),
+    }
+}
+
+/// Create a directory at `path` relative to `base` with the permission bits `mode`.
+pub fn mkdir(base: Option<&Arc<Dentry>>, path: &str, mode: u32) -> Result<(), i32> {
+    let (parent, name) = lookup_parent(base, path)?;
+    if name == "." || name == ".." {
+        return Err(E_EXIST);
+    }
+    parent.inode().create(name, InodeType::Dir, mode & S_IALLUGO)?;
+    Ok(())
+}
+
+/// Create the file at `path` relative to `base` of the file type and the permission bits
+/// `mode`: a regular file, a device file of the device number `dev`, a FIFO or a socket.
+///
+/// Returns `Err` with `E_PERM` for a directory, or `E_INVALID` for a symlink.
+pub fn mknod(base: Option<&Arc<Dentry>>, path: &str, mode: u32, dev: u64) -> Result<(), i32> {
+    // A mode without the file type bits creates a regular file.
+    let kind = match mode & S_IFMT {
+        0 => InodeType::File,
+        _ => InodeType::from_mode(mode).ok_or(E_INVALID)?,
+    };
+    let (parent, name) = lookup_parent(base, path)?;
+    if name == "." || name == ".." || path.ends_with('/') {
+        return Err(E_EXIST);
+    }
+    match kind {
+        InodeType::File => parent.inode().create(name, kind, mode & S_IALLUGO)?,
+        InodeType::Dir => return Err(E_PERM),
+        InodeType::Symlink => return Err(E_INVALID),
+        _ => parent.inode().mknod(name, kind, mode & S_IALLUGO, dev)?,
+    };
+    Ok(())
+}
+
+/// Create a symlink at `path` relative to `base`, pointing to `target`.
+pub fn symlink(base: Option<&Arc<Dentry>>, target: &str, path: &str) -> Result<(), i32> {
+    if target.is_empty() {
+        return Err(E_NO_ENT);
+    }
+    let (parent, name) = lookup_parent(base, path)?;
+    if name == "." || name == ".." || path.ends_with('/') {
+        return Err(E_EXIST);
+    }
+    parent.inode().symlink(name, target)?;
+    Ok(())
+}
+
+/// Create a hard link at `new_path` relative to `new_base` to the file at `old_path` relative to
+/// `old_base`, the symlink at the last component of `old_path` is followed if `follow`.
+///
+/// Returns `Err` with `E_PERM` for a directory, or `E_CROSS_DEV` if the paths are on different
+/// file systems.
+pub fn link(old_base: Option<&Arc<Dentry>>, old_path: &str, new_base: Option<&Arc<Dentry>>,
+            new_path: &str, follow: bool) -> Result<(), i32> {
+    let old = lookup_path(old_base, old_path, follow)?;
+    let old_stat = old.inode().stat()?;
+    if old_stat.inode_type() == InodeType::Dir {
+        return Err(E_PERM);
+    }
+    let (parent, name) = lookup_parent(new_base, new_path)?;
+    if name == "." || name == ".." {
+        return Err(E_EXIST);
+    }
+    match lookup_path(Some(&parent), name, false) {
+        Ok(_) => return Err(E_EXIST),
+        Err(E_NO_ENT) if new_path.ends_with('/') => return Err(E_NO_ENT),
+        Err(E_NO_ENT) => {}
+        Err(errno) => return Err(errno),
+    }
+    if parent.inode().stat()?.dev != old_stat.dev {
+        return Err(E_CROSS_DEV);
+    }
+    parent.inode().link(name, old.inode())
+}
+
+/// Remove the file at `path` relative to `base`, or the empty directory if `dir` (like
+/// `rmdir`). A mount point can not be removed.
+pub fn unlink(base: Option<&Arc<Dentry>>, path: &str, dir: bool) -> Result<(), i32> {
+    let (parent, name) = lookup_parent(base, path)?;
+    if name == "." || name == ".." {
+        return Err(if dir { E_INVALID } else { E_IS_DIR });
+    }
+
+    let child = lookup_path(Some(&parent), name, false)?;
+    let stat = child.inode().stat()?;
+    match stat.inode_type() {
+        InodeType::Dir if !dir => return Err(E_IS_DIR),
+        InodeType::Dir if is_mount_point((stat.dev, stat.ino)) => return Err(E_BUSY),
+        InodeType::Dir => {}
+        _ if dir || path.ends_with('/') => return Err(E_NOT_DIR),
+        _ => {}
+    }
+    parent.inode().unlink(name)
+}
+
+/// Move the file at `old_path` relative to `old_base` to `new_path` relative to `new_base`,
+/// replacing the target unless `flags` has [`RENAME_NOREPLACE`]. A directory can only replace an
+/// empty directory, and can not be moved into itself.
+///
+/// Returns `Err` with `E_CROSS_DEV` if the paths are on different file systems, or `E_BUSY` for
+/// `.`, `..` or a mount point.
+pub fn rename(old_base: Option<&Arc<Dentry>>, old_path: &str, new_base: Option<&Arc<Dentry>>,
+              new_path: &str, flags: usize) -> Result<(), i32> {
+    let (old_parent, old_name) = lookup_parent(old_base, old_path)?;
+    let (new_parent, new_name) = lookup_parent(new_base, new_path)?;
+    if [old_name, new_name].iter().any(|&name| name == "." || name == "..") {
+        return Err(E_BUSY);
+    }
+
+    let old = lookup_path(Some(&old_parent), old_name, false)?;
+    let old_stat = old.inode().stat()?;
+    if old_parent.inode().stat()?.dev != new_parent.inode().stat()?.dev {
+        return Err(E_CROSS_DEV);
+    }
+    let is_dir = old_stat.inode_type() == InodeType::Dir;
+    if is_dir && is_mount_point((old_stat.dev, old_stat.ino)) {
+        return Err(E_BUSY);
+    }
+    if !is_dir && (old_path.ends_with('/') || new_path.ends_with('/')) {
+        return Err(E_NOT_DIR);
+    }
+
+    match lookup_path(Some(&new_parent), new_name, false) {
+        Ok(_) if flags & RENAME_NOREPLACE != 0 => return Err(E_EXIST),
+        Ok(new) => {
+            let new_stat = new.inode().stat()?;
+            if (new_stat.dev, new_stat.ino) == (old_stat.dev, old_stat.ino) {
+                return Ok(());
+            }
+            match (is_dir, new_stat.inode_type() == InodeType::Dir) {
+                (true, false) => return Err(E_NOT_DIR),
+                (false, true) => return Err(E_IS_DIR),
+                (true, true) if is_mount_point((new_stat.dev, new_stat.ino)) => {
+                    return Err(E_BUSY);
+                }
+                _ => {}
+            }
+        }
+        Err(E_NO_ENT) => {}
+        Err(errno) => return Err(errno),
+    }
+
+    // A directory can not be moved into its own subtree.
+    if is_dir {
+        let mut cur = Some(&new_parent);
+        while let Some(dentry) = cur {
+            let stat = dentry.inode().stat()?;
+            if (stat.dev, stat.ino) == (old_stat.dev, old_stat.ino) {
+                return Err(E_INVALID);
+            }
+            cur = dentry.parent();
+        }
+    }
+    old_parent.inode().rename(old_name, new_parent.inode(), new_name)
+}
+
+/// Get the attributes of the file at `path` relative to `base`, the symlink at the last
+/// component is followed if `follow`.
+pub fn stat(base: Option<&Arc<Dentry>>, path: &str, follow: bool) -> Result<Stat, i32> {
+    lookup_path(base, path, follow)?.inode().stat()
+}
+
+/// Read the target of the symlink at `path` relative to `base`.
+pub fn read_link(base: Option<&Arc<Dentry>>, path: &str) -> Result<String, i32> {
+    lookup_path(base, path, false)?.inode().read_link()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::file::O_RDWR;
+    use crate::fs::mount::{mount, umount};
+
+    #[kernel_test]
+    fn dot_dot_across_mount_point() {
+        mkdir(None, "/vfs_test", 0o755).unwrap();
+        mkdir(None, "/vfs_test/mnt", 0o755).unwrap();
+        open(None, "/vfs_test/file", O_RDWR | O_CREAT, 0o644).unwrap();
+        let covered = stat(None, "/vfs_test/mnt", true).unwrap();
+        mount("none", "/vfs_test/mnt", "tmpfs", "").unwrap();
+        mkdir(None, "/vfs_test/mnt/dir", 0o755).unwrap();
+
+        // The path steps into the mounted root, and `..` of it steps back to the covering tree.
+        let root = stat(None, "/vfs_test/mnt", true).unwrap();
+        assert_ne!(root.dev, covered.dev);
+        let dir = lookup_path(None, "/vfs_test/mnt/dir", true).unwrap();
+        assert_eq!(dir.path(), "/vfs_test/mnt/dir");
+        let parent = stat(Some(&dir), "..", true).unwrap();
+        assert_eq!((parent.dev, parent.ino), (root.dev, root.ino));
+        let top = stat(None, "/vfs_test", true).unwrap();
+        let up = stat(Some(&dir), "../..", true).unwrap();
+        assert_eq!((up.dev, up.ino), (top.dev, top.ino));
+        assert!(stat(Some(&dir), "../../file", true).is_ok());
+        assert!(stat(None, "/vfs_test/mnt/../mnt/dir", true).is_ok());
+        // `..` of the root is the root.
+        assert_eq!(stat(None, "/..", true).unwrap().ino, stat(None, "/", true).unwrap().ino);
+
+        // The files can not be moved or linked across the mount point, nor it be removed.
+        assert_eq!(rename(None, "/vfs_test/file", None, "/vfs_test/mnt/file", 0),
+                   Err(E_CROSS_DEV));
+        assert_eq!(link(None, "/vfs_test/file", Some(&dir), "../file", false), Err(E_CROSS_DEV));
+        assert_eq!(unlink(None, "/vfs_test/mnt", true), Err(E_BUSY));
+        assert_eq!(rename(None, "/vfs_test/mnt", None, "/vfs_test/other", 0), Err(E_BUSY));
+
+        drop(dir);
+        umount("/vfs_test/mnt").unwrap();
+        assert_eq!(stat(None, "/vfs_test/mnt/dir", true).err(), Some(E_NO_ENT));
+        unlink(None, "/vfs_test/mnt", true).unwrap();
+        unlink(None, "/vfs_test/file", false).unwrap();
+        unlink(None, "/vfs_test", true).unwrap();
+    }
+}
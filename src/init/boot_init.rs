@@ -1,7 +1,7 @@
 //! Do initialization on boot time.
 
 use fdt::standard_nodes::Memory;
-use crate::constant::{ORDER_1GB, ORDER_2MB};
+use crate::constant::{ORDER_1GB, ORDER_2MB, ORDER_4KB};
 use crate::mm::virt_qemu;
 use crate::mm::mmu::{create_root_table, EntryBits, Mode, Table};
 use crate::util::align;
@@ -18,9 +18,10 @@ use crate::util::align;
 /// and we set it to 0.
 pub fn build_kernel_identity_map(memory: &Memory) -> *mut dyn Table {
     // Construct the id map.
+    let map_4kb = virt_qemu::get_mem_map_4kb();
     let map_2mb = virt_qemu::get_mem_map_2mb();
     let map_1gb = virt_qemu::get_mem_map_1gb();
-    let id_map = create_kernel_identity_map(map_2mb, map_1gb);
+    let id_map = create_kernel_identity_map(map_4kb, map_2mb, map_1gb);
     for region in memory.regions() {
         if let Some(size) = region.size {
             let addr = region.starting_address as usize;
@@ -52,6 +53,7 @@ fn print_id_table_info(root: &dyn Table) {
 }
 
 
+const ENTRY_LEVEL_4KB: u32 = 0;
 const ENTRY_LEVEL_2MB: u32 = 1;
 const ENTRY_LEVEL_1GB: u32 = 2;
 
@@ -69,7 +71,10 @@ fn map_identity<const ORDER: usize, const LEVEL: u32, const LENGTH: usize>(
     }
 }
 
-fn create_kernel_identity_map(map_2mb: &[(usize, usize)], map_1gb: &[(usize, usize)]) -> *mut dyn Table {
+fn create_kernel_identity_map(
+    map_4kb: &[(usize, usize)],
+    map_2mb: &[(usize, usize)],
+    map_1gb: &[(usize, usize)]) -> *mut dyn Table {
     let table = create_root_table(Mode::Sv39);
 
     // Sv39 mode:
@@ -77,7 +82,8 @@ fn create_kernel_identity_map(map_2mb: &[(usize, usize)], map_1gb: &[(usize, usi
     //   level 1 -> 2MiB per entry;
     //   level 2 -> 1GiB per entry;
 
-    // Ignore address [0, 2M), so the deref null pointer will fault as excepted.
+    // Ignore address [0, 2M) except the few device pages (mapped at level 0), so the deref null
+    // pointer will fault as excepted.
     // Then map [2M, 1G) at level 1. And the following memory address will all
     // be mapped by the level 2 entry (1GiB per entry).
     //       root_table          l1_table(ppn=ppn[2]|ppn[1])
@@ -91,9 +97,13 @@ fn create_kernel_identity_map(map_2mb: &[(usize, usize)], map_1gb: &[(usize, usi
     // todo: handle EntryBits::Access & EntryBits::Dirty.
 
     let root = unsafe { &mut *table };
-    // Map 2MiB page
     let bits = EntryBits::Access.val() | EntryBits::Dirty.val() |
         EntryBits::Global.val() | EntryBits::ReadWrite.val();
+    // Map 4KiB page
+    const LENGTH_4KB: usize = 1usize << ORDER_4KB;
+    map_identity::<ORDER_4KB, ENTRY_LEVEL_4KB, LENGTH_4KB>(root, map_4kb, bits);
+
+    // Map 2MiB page
     const LENGTH_2MB: usize = 1usize << ORDER_2MB;
     map_identity::<ORDER_2MB, ENTRY_LEVEL_2MB, LENGTH_2MB>(root, map_2mb, bits);
 
@@ -2,14 +2,33 @@
 
 use core::ptr::copy_nonoverlapping;
 
+use fdt::Fdt;
 use fdt::standard_nodes::Chosen;
-use super::{BOOT_COMMAND_LINE};
+use super::{BOOT_COMMAND_LINE, INITRD_RANGE};
 
 
 pub fn dt_scan_chosen(chosen: &Chosen) {
     if let Some(args) = chosen.bootargs() {
         unsafe {
-            copy_nonoverlapping(args.as_ptr(), BOOT_COMMAND_LINE.as_mut_ptr(), BOOT_COMMAND_LINE.len());
+            // Keep the last byte as the terminating NUL.
+            let len = args.len().min(BOOT_COMMAND_LINE.len() - 1);
+            copy_nonoverlapping(args.as_ptr(), BOOT_COMMAND_LINE.as_mut_ptr(), len);
+        }
+    }
+}
+
+/// Find the initrd from the `linux,initrd-start` and `linux,initrd-end` properties of the
+/// `/chosen` node, which are set by the boot loader (e.g. QEMU `-initrd`).
+pub fn dt_scan_initrd(fdt: &Fdt) {
+    let chosen = match fdt.find_node("/chosen") {
+        Some(node) => node,
+        None => return,
+    };
+    let start = chosen.property("linux,initrd-start").and_then(|prop| prop.as_usize());
+    let end = chosen.property("linux,initrd-end").and_then(|prop| prop.as_usize());
+    if let (Some(start), Some(end)) = (start, end) {
+        if start < end {
+            unsafe { INITRD_RANGE = (start, end); }
         }
     }
 }
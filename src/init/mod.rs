@@ -5,10 +5,11 @@ mod early_init;
 
 use core::mem::size_of;
 use core::ptr::{copy_nonoverlapping, null, slice_from_raw_parts};
+use fdt::Fdt;
 use fdt::standard_nodes::Memory;
 use crate::asm::mem_v::KERNEL_TABLE;
-use crate::driver::of;
-use crate::{logk, mm, smp};
+use crate::driver::{self, of};
+use crate::{dev, fs, logk, mm, smp};
 use crate::util::align;
 
 
@@ -18,6 +19,12 @@ pub static mut BOOT_COMMAND_LINE: [u8; COMMAND_LINE_SIZE] = [0u8; COMMAND_LINE_S
 
 
 static mut DEVICE_TREE_BLOB: *const u8 = null();
+/// The DeviceTree parsed from the kernel copy of the dtb, so the nodes can be kept by the devices.
+static mut DEVICE_TREE: Option<Fdt<'static>> = None;
+
+/// Physical address range `[start, end)` of the initrd loaded by the boot loader, `(0, 0)` if
+/// there is none.
+static mut INITRD_RANGE: (usize, usize) = (0, 0);
 
 /// Setup on boot time (Machine mode).
 ///
@@ -90,9 +97,13 @@ pub fn boot_setup(boot_dtb: *const u8) -> usize {
 /// 7. Prepare the environment for running the kernel thread and user process (smp setup, scheduler
 /// init, process static data init, etc).
 pub fn kernel_setup() {
-    let fdt = unsafe { of::fdt::parse_from_ptr::<'static>(DEVICE_TREE_BLOB) };
+    let fdt = unsafe {
+        DEVICE_TREE = Some(of::fdt::parse_from_ptr::<'static>(DEVICE_TREE_BLOB));
+        device_tree()
+    };
     let chosen = fdt.chosen();
     early_init::dt_scan_chosen(&chosen);
+    early_init::dt_scan_initrd(fdt);
 
     // After this init, we can use the `log` crate macros for logging.
     logk::init();
@@ -117,6 +128,58 @@ pub fn kernel_setup() {
 
     // todo: init slab
 
+    // Handle the boot params that do not depend on the devices.
+    if let Some(action) = get_boot_param("panic_action") {
+        dev::pm::set_panic_action_param(action);
+    }
+
+    smp::set_current_cpu_online();
+
+    // Probe the built-in drivers.
+    dev::init();
+    driver::char::init();
+    driver::probe_device_tree(fdt);
+
+    fs::init();
+}
+
+/// Get the DeviceTree. Must be called after the start of [`kernel_setup`].
+pub fn device_tree() -> &'static Fdt<'static> {
+    unsafe { DEVICE_TREE.as_ref().expect("DeviceTree is not parsed") }
+}
+
+/// Get the boot command line saved from the `/chosen/bootargs` node.
+pub fn get_boot_command_line() -> &'static str {
+    let cmdline = unsafe { &BOOT_COMMAND_LINE };
+    let len = cmdline.iter().position(|c| *c == 0).unwrap_or(cmdline.len());
+    core::str::from_utf8(&cmdline[..len]).unwrap_or("")
+}
+
+/// Get the physical address range `[start, end)` of the initrd passed by the boot loader.
+pub fn initrd_range() -> Option<(usize, usize)> {
+    let (start, end) = unsafe { INITRD_RANGE };
+    if start < end { Some((start, end)) } else { None }
+}
+
+/// Forget the initrd, called when its memory can not be used or has been freed.
+pub fn drop_initrd() {
+    unsafe { INITRD_RANGE = (0, 0); }
+}
+
+/// Find the boot param `name` in the boot command line. The params are separated by spaces and
+/// have the form `name=value` or `name`.
+///
+/// Returns the value of the last matched param, or an empty string if the param has no value.
+/// Returns `None` if the param is not present.
+pub fn get_boot_param(name: &str) -> Option<&'static str> {
+    let mut found = None;
+    for param in get_boot_command_line().split_ascii_whitespace() {
+        let (key, val) = param.split_once('=').unwrap_or((param, ""));
+        if key == name {
+            found = Some(val);
+        }
+    }
+    found
 }
 
 
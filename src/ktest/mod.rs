@@ -0,0 +1,142 @@
+//! In-kernel unit test framework.
+//!
+//! Tests are declared with the `#[kernel_test]` attribute and collected by the
+//! `custom_test_frameworks` feature, so they only exist in the test build (`cargo test`). The
+//! test build boots as usual, then runs all tests one by one in a kernel thread (so the tests can
+//! use the allocator and the scheduler), and finally powers off the machine with the exit code
+//! [`EXIT_SUCCESS`] or [`EXIT_FAILURE`]. A panic in a test can not be recovered, the remaining
+//! tests are skipped and the machine is powered off with [`EXIT_FAILURE`].
+//!
+//! Tests can be filtered by the boot param `ktest_filter=<pattern>`: only the tests whose full
+//! name contains the `pattern` are run.
+//!
+//! # Output Format
+//!
+//! Each result line is printed to the console with the `KTEST:` prefix, the line is never mixed
+//! with other outputs:
+//!
+//! ```text
+//! KTEST:BEGIN total=<n>
+//! KTEST:RUN <name>
+//! KTEST:PASS <name>
+//! KTEST:FAIL <name>
+//! KTEST:IGNORE <name>
+//! KTEST:END passed=<n> failed=<n> ignored=<n>
+//! ```
+//!
+//! If a test panics, `KTEST:FAIL <name>` and `KTEST:END ... aborted` are printed by the panic
+//! handler.
+//!
+//! [`EXIT_SUCCESS`]: EXIT_SUCCESS
+//! [`EXIT_FAILURE`]: EXIT_FAILURE
+
+use core::fmt::Debug;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use core::ptr::null_mut;
+use crate::dev::pm;
+use crate::init;
+use crate::proc::kernel::build_kernel_thread;
+use crate::sched::ready_list_add_task;
+
+
+/// QEMU exit code if all tests are passed.
+pub const EXIT_SUCCESS: u16 = 0;
+/// QEMU exit code if any test is failed.
+pub const EXIT_FAILURE: u16 = 1;
+
+/// A registered test, generated by `#[kernel_test]`.
+pub struct KernelTest {
+    /// Full path of the test function.
+    pub name: &'static str,
+    /// Run the test, returns `true` if the test passed.
+    pub func: fn() -> bool,
+}
+
+/// Return type of a test function.
+pub trait TestResult {
+    fn is_success(self) -> bool;
+}
+
+impl TestResult for () {
+    fn is_success(self) -> bool {
+        true
+    }
+}
+
+impl<E: Debug> TestResult for Result<(), E> {
+    fn is_success(self) -> bool {
+        match self {
+            Ok(_) => true,
+            Err(e) => {
+                println_k!("KTEST:INFO error: {:?}", e);
+                false
+            }
+        }
+    }
+}
+
+/// The running test, null if no test is running.
+static CURRENT_TEST: AtomicPtr<KernelTest> = AtomicPtr::new(null_mut());
+static PASSED: AtomicUsize = AtomicUsize::new(0);
+static FAILED: AtomicUsize = AtomicUsize::new(0);
+static IGNORED: AtomicUsize = AtomicUsize::new(0);
+
+/// Start the test runner thread. `main` is the test harness entry generated by the compiler.
+pub fn start(main: fn()) {
+    let task = build_kernel_thread(test_thread, main as *mut ()).build();
+    ready_list_add_task(task);
+}
+
+extern "C"
+fn test_thread(main: *mut ()) -> usize {
+    // SAFETY: the `main` is passed by `start`.
+    let main = unsafe { core::mem::transmute::<*mut (), fn()>(main) };
+    main();
+    0
+}
+
+/// The `custom_test_frameworks` runner. Never returns.
+pub fn test_runner(tests: &[&KernelTest]) {
+    let filter = init::get_boot_param("ktest_filter").unwrap_or("");
+
+    println_k!("KTEST:BEGIN total={}", tests.len());
+    for test in tests {
+        if !test.name.contains(filter) {
+            IGNORED.fetch_add(1, Ordering::Relaxed);
+            println_k!("KTEST:IGNORE {}", test.name);
+            continue;
+        }
+
+        CURRENT_TEST.store(*test as *const KernelTest as *mut KernelTest, Ordering::Release);
+        println_k!("KTEST:RUN {}", test.name);
+        if (test.func)() {
+            PASSED.fetch_add(1, Ordering::Relaxed);
+            println_k!("KTEST:PASS {}", test.name);
+        } else {
+            FAILED.fetch_add(1, Ordering::Relaxed);
+            println_k!("KTEST:FAIL {}", test.name);
+        }
+        CURRENT_TEST.store(null_mut(), Ordering::Release);
+    }
+
+    let failed = FAILED.load(Ordering::Relaxed);
+    println_k!("KTEST:END passed={} failed={} ignored={}",
+        PASSED.load(Ordering::Relaxed), failed, IGNORED.load(Ordering::Relaxed));
+
+    pm::kernel_power_off(if failed == 0 { EXIT_SUCCESS } else { EXIT_FAILURE });
+}
+
+/// Report the panic of the running test and power off. Called by the panic handler.
+pub fn panic_exit() -> ! {
+    let test = CURRENT_TEST.load(Ordering::Acquire);
+    if !test.is_null() {
+        let test = unsafe { &*test };
+        println_k!("KTEST:FAIL {}", test.name);
+        FAILED.fetch_add(1, Ordering::Relaxed);
+    }
+    println_k!("KTEST:END passed={} failed={} ignored={} aborted",
+        PASSED.load(Ordering::Relaxed), FAILED.load(Ordering::Relaxed),
+        IGNORED.load(Ordering::Relaxed));
+
+    pm::emergency_power_off(EXIT_FAILURE)
+}
@@ -103,3 +103,60 @@ macro_rules! write_once {
         }
     };
 }
+
+
+#[cfg(test)]
+mod tests {
+    #[repr(C)]
+    struct COffsetTest {
+        pub _padding: u8,
+        pub align: u32,
+        pub align64: u64,
+        pub align8: u8,
+    }
+
+    #[repr(C, packed)]
+    struct COffsetPackedTest {
+        pub _padding: u8,
+        pub align: u32,
+        pub align64: u64,
+        pub align8: u8,
+    }
+
+    #[repr(C, packed(4))]
+    struct COffsetAlignTest {
+        pub _padding: u8,
+        pub align: u32,
+        pub align64: u64,
+        pub align8: u8,
+    }
+
+    #[kernel_test]
+    fn offset_of_repr_c() {
+        assert_eq!(offset_of!(COffsetTest, align), 4);
+        assert_eq!(offset_of!(COffsetTest, align64), 8);
+        assert_eq!(offset_of!(COffsetTest, align8), 16);
+    }
+
+    #[kernel_test]
+    fn offset_of_packed() {
+        assert_eq!(offset_of!(COffsetPackedTest, align), 1);
+        assert_eq!(offset_of!(COffsetPackedTest, align64), 5);
+        assert_eq!(offset_of!(COffsetPackedTest, align8), 13);
+
+        assert_eq!(offset_of!(COffsetAlignTest, align), 4);
+        assert_eq!(offset_of!(COffsetAlignTest, align64), 8);
+        assert_eq!(offset_of!(COffsetAlignTest, align8), 16);
+    }
+
+    #[kernel_test]
+    fn container_of_field() {
+        let obj = COffsetTest { _padding: 0, align: 1, align64: 2, align8: 3 };
+        let base = &obj as *const COffsetTest;
+        unsafe {
+            assert_eq!(container_of!(core::ptr::addr_of!(obj.align), COffsetTest, align), base);
+            assert_eq!(container_of!(core::ptr::addr_of!(obj.align64), COffsetTest, align64), base);
+            assert_eq!(container_of!(core::ptr::addr_of!(obj.align8), COffsetTest, align8), base);
+        }
+    }
+}
@@ -4,11 +4,17 @@
 #![feature(inline_const)]   // Needed in 'macros/ptr.rs'.
 #![feature(generic_const_exprs)]    // Using generate type parameters in const expressions
 #![feature(const_refs_to_cell)]     // An negative error reported by v1.66.0-nightly
+#![feature(custom_test_frameworks)] // In-kernel unit tests, see `ktest`.
+#![test_runner(crate::ktest::test_runner)]
+#![reexport_test_harness_main = "test_main"]
 
 #[macro_use]
 extern crate log;
 extern crate static_assertions as sa;
 extern crate alloc;
+#[cfg(test)]
+#[macro_use]
+extern crate ktest_macros;
 
 mod asm;
 #[macro_use]
@@ -27,9 +33,14 @@ mod driver;
 mod smp;
 mod mm;
 mod dev;
+mod block;
+mod debug;
 mod fs;
 mod proc;
 mod sched;
+mod syscall;
+#[cfg(test)]
+mod ktest;
 
 use core::arch::asm;
 
@@ -40,6 +51,7 @@ use core::arch::asm;
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
     println_k!("{}", info);
+    debug::panic_backtrace();
     // if let Some(p) = info.location() {
     //     println_k!(
     //         "Aborting: line {}, file {}: <todo panic message>",
@@ -49,7 +61,12 @@ fn panic(info: &core::panic::PanicInfo) -> ! {
     // } else {
     //     println_k!("Aborting: no information available.");
     // }
-    abort();
+    // Report the running test and power off on the test build.
+    #[cfg(test)]
+    ktest::panic_exit();
+    // Stop other CPUs, then halt, power off or restart as the boot param `panic_action` says.
+    #[cfg(not(test))]
+    dev::pm::do_panic_action();
 }
 
 #[no_mangle]
@@ -62,45 +79,6 @@ fn abort() -> ! {
     }
 }
 
-//////// TEST offset_of! //////////////
-
-#[derive(Default)]
-struct RustOffsetTest {
-    pub _padding: u8,
-    pub align: u32,
-    pub align64: u64,
-    pub align8: u8,
-}
-
-#[repr(C)]
-#[derive(Default)]
-struct COffsetTest {
-    pub _padding: u8,
-    pub align: u32,
-    pub align64: u64,
-    pub align8: u8,
-}
-
-#[repr(C, packed)]
-#[derive(Default)]
-struct COffsetPackedTest {
-    pub _padding: u8,
-    pub align: u32,
-    pub align64: u64,
-    pub align8: u8,
-}
-
-#[repr(C, packed(4))]
-#[derive(Default)]
-struct COffsetAlignTest {
-    pub _padding: u8,
-    pub align: u32,
-    pub align64: u64,
-    pub align8: u8,
-}
-
-///////////// END TEST ////////////////
-
 #[no_mangle]
 /// Do initialization on the machine mode (CPU mode #3).
 /// Returns the SATP value (including the MODE).
@@ -127,43 +105,25 @@ fn kmain() {
     println_k!("Now we are in the Supervisor mode.");
     println_k!();
 
-    macro_rules! show_offset_test {
-        ($ty:tt) => {{
-            let off_test: $ty = Default::default();
-            println_k!("Show info for type: {}", stringify!($ty));
-            println_k!(" * size_of: {}", core::mem::size_of::<$ty>());
-            println_k!(" * align_of: {}", core::mem::align_of::<$ty>());
-            println_k!(" * offset/align: {}", offset_of!($ty, align));
-            println_k!(" * offset/align64: {}", offset_of!($ty, align64));
-            println_k!(" * offset/align8: {}", offset_of!($ty, align8));
-            println_k!(" * ptr: {:p}", &off_test);
-            let ptr_a32 = core::ptr::addr_of!(off_test.align);
-            let ptr = unsafe { container_of!(ptr_a32, $ty, align) };
-            println_k!(" * ptr/align: {:p}, container: {:p}", ptr_a32, ptr);
-            let ptr_a64 = core::ptr::addr_of!(off_test.align64);
-            let ptr = unsafe { container_of!(ptr_a64, $ty, align64) };
-            println_k!(" * ptr/align64: {:p}, container: {:p}", ptr_a64, ptr);
-            let ptr_a8 = core::ptr::addr_of!(off_test.align8);
-            let ptr = unsafe { container_of!(ptr_a8, $ty, align8) };
-            println_k!(" * ptr/align8: {:p}, container: {:p}", ptr_a8, ptr);
-            println_k!();
-        }};
-    }
-
-    show_offset_test!(RustOffsetTest);
-    show_offset_test!(COffsetTest);
-    show_offset_test!(COffsetPackedTest);
-    show_offset_test!(COffsetAlignTest);
-
     // Create the first kernel thread: idle process with TID=0 (All kernel thread has a PID of 0).
     proc::init();
     sched::init();
 
+    // Start the writeback of the dirty page cache.
+    #[cfg(not(test))]
+    mm::page_cache::start_writeback_thread();
+
     // Add the kernel test threads.
+    #[cfg(not(test))]
     proc::add_test_kernel_threads();
+    // Or run the unit tests on the test build.
+    #[cfg(test)]
+    ktest::start(test_main);
 
     // Create the first user process: systemd process with PID=1. All other processes will
     // be forked from this.
+    #[cfg(not(test))]
+    proc::create_init_process();
 
     // Do schedule: We need first select a thread to run, write its `TrapFrame` into the
     // `sscratch` CSR, then set the next timer event and open the interrupt flag.
@@ -0,0 +1,775 @@
+//! User address space.
+//!
+//! Each user process has its own root page table, which is copied from the kernel identity map
+//! table (see [`copy_root_table`]) so all kernel mappings are shared: the kernel code and data
+//! are still accessible after a trap from the U-mode without switching the `satp`. The user
+//! mappings live in the range \[[`USER_SPACE_START`], [`USER_SPACE_END`]), which never overlaps
+//! with the kernel mappings, so the sub-level tables of the user space are private to the
+//! process.
+//!
+//! The valid user ranges are described by the [`Vma`]s, and most pages are populated lazily: a
+//! page fault inside a VMA allocates the page by the VMA backing and restarts the faulting
+//! instruction, see [`AddressSpace::handle_page_fault`]. After [`fork`], the writable pages are
+//! shared copy-on-write: they are mapped read-only in both address spaces and copied on the
+//! first write (the page reference counts are kept by [`page::share_page`]).
+//!
+//! An address space gets an ASID when it is [`activate`]d on a hart, and records the harts it
+//! has run on: a change of the mappings is flushed from the TLBs of all of them by
+//! [`tlb::flush_tlb_range`] before the unmapped pages are freed.
+//!
+//! [`copy_root_table`]: crate::mm::mmu::copy_root_table
+//! [`USER_SPACE_START`]: crate::mm::USER_SPACE_START
+//! [`USER_SPACE_END`]: crate::mm::USER_SPACE_END
+//! [`Vma`]: crate::mm::vma::Vma
+//! [`fork`]: AddressSpace::fork
+//! [`page::share_page`]: crate::mm::page::share_page
+//! [`activate`]: AddressSpace::activate
+//! [`tlb::flush_tlb_range`]: crate::mm::tlb::flush_tlb_range
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::arch::cpu;
+use crate::errno::{E_ACCESS, E_EXIST, E_FAULT, E_INVALID, E_NO_MEM};
+use crate::mm::{asid, build_satp, tlb, get_kernel_identity_table, page, PAGE_ORDER, PAGE_SIZE, USER_SPACE_END,
+                USER_SPACE_START};
+use crate::mm::mmu::{self, EntryBits, Table};
+use crate::mm::vma::{FaultAccess, Vma, VmaBacking, VmaTree};
+use crate::smp::current_cpu_info;
+use crate::util::align::{align_down, align_up};
+
+
+/// Top address (exclusive) of the `mmap` area, which grows down from here. The gap between this
+/// and the [`USER_SPACE_END`] is reserved for the main thread stack.
+pub const MMAP_TOP: usize = USER_SPACE_END - 0x1000_0000;
+
+
+/// Flag bits set on all user leaf entries besides the permission bits.
+const USER_PTE_BITS: u32 = EntryBits::User.val() | EntryBits::Access.val() |
+    EntryBits::Dirty.val();
+
+/// Get the leaf entry bits of a user page with the permission `bits`. A leaf entry must have any
+/// of the `R/W/X` bits, so an inaccessible page (`PROT_NONE`) is mapped readable without the
+/// `User` bit.
+const fn pte_bits(bits: u32) -> u32 {
+    if bits & EntryBits::ReadWriteExecute.val() == 0 {
+        EntryBits::Read.val() | EntryBits::Access.val() | EntryBits::Dirty.val()
+    } else {
+        bits | USER_PTE_BITS
+    }
+}
+
+/// Placement of a new mapping.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum MapPlacement {
+    /// Anywhere below the [`MMAP_TOP`], the address is used if the range is free (0 for no
+    /// hint).
+    Hint(usize),
+    /// Exactly at the address, the old mappings in the range are removed.
+    Fixed(usize),
+    /// Exactly at the address, fails with `E_EXIST` if the range overlaps other mappings.
+    FixedNoReplace(usize),
+}
+
+
+/// Address space of a user process.
+pub struct AddressSpace {
+    table: *mut dyn Table,
+    satp: usize,
+    /// ASID context, see [`asid`](crate::mm::asid).
+    context: AtomicUsize,
+    /// Mask of the cpu ids which have activated this address space.
+    cpus: AtomicUsize,
+    vmas: VmaTree,
+    /// Start of the heap, the program break can not go below this.
+    brk_start: usize,
+    /// Current program break.
+    brk: usize,
+}
+
+impl AddressSpace {
+    /// Create an address space which only contains the kernel mappings. Returns `None` if out
+    /// of memory.
+    pub fn new() -> Option<Self> {
+        let kernel_table = unsafe { &*get_kernel_identity_table() };
+        let table = mmu::copy_root_table(kernel_table);
+        if table.is_null() {
+            return None;
+        }
+
+        let table_ref = unsafe { &*table };
+        let satp = build_satp(table_ref.get_mode(), 0, table_ref.get_addr() as u64);
+        Some(Self {
+            table,
+            satp,
+            context: AtomicUsize::new(0),
+            cpus: AtomicUsize::new(0),
+            vmas: VmaTree::new(),
+            brk_start: USER_SPACE_START,
+            brk: USER_SPACE_START,
+        })
+    }
+
+    /// The `satp` value to switch to this address space, without the ASID. See [`activate`].
+    ///
+    /// [`activate`]: AddressSpace::activate
+    #[inline(always)]
+    pub fn satp(&self) -> usize {
+        self.satp
+    }
+
+    /// Switch this hart to the address space with its ASID, the TLB is flushed if needed. Must
+    /// be called with the interrupts disabled.
+    pub fn activate(&self) {
+        let (asid, flush) = asid::switch_context(&self.context);
+        // Record the hart before any translation is cached, see `flush_tlb_range`.
+        self.cpus.fetch_or(1 << current_cpu_info().get_cpu_id(), Ordering::SeqCst);
+        let satp = self.satp | asid << asid::SATP_ASID_SHIFT;
+        let switch = cpu::satp_read() != satp;
+        if switch {
+            cpu::satp_write(satp);
+        }
+        if flush || (switch && !asid::asid_enabled()) {
+            cpu::sfence_vma_all();
+        }
+    }
+
+    /// Flush the user range `[start, end)` from the TLBs of all harts which have run this
+    /// address space.
+    pub fn flush_tlb_range(&self, start: usize, end: usize) {
+        let cpus = self.cpus.load(Ordering::SeqCst);
+        let asid = asid::context_asid(self.context.load(Ordering::Relaxed));
+        tlb::flush_tlb_range(cpus, asid, start, end);
+    }
+
+    /// Flush all user mappings from the TLBs of all harts which have run this address space.
+    #[inline(always)]
+    pub fn flush_tlb_all(&self) {
+        self.flush_tlb_range(USER_SPACE_START, USER_SPACE_END);
+    }
+
+    /// Get the root page table.
+    #[inline(always)]
+    pub fn table(&self) -> &dyn Table {
+        unsafe { &*self.table }
+    }
+
+    /// Get the mutable root page table.
+    #[inline(always)]
+    pub fn table_mut(&mut self) -> &mut dyn Table {
+        unsafe { &mut *self.table }
+    }
+
+    /// Check if the range `[addr, addr + len)` is in the user space.
+    #[inline]
+    pub fn is_user_range(addr: usize, len: usize) -> bool {
+        addr >= USER_SPACE_START && len <= USER_SPACE_END - addr
+    }
+
+    /// Map a 4KiB user page `v_addr` to the physical page `p_addr`. The `User`, `Access` and
+    /// `Dirty` bits are always set, unless the permission `bits` are empty.
+    pub fn map_page(&mut self, v_addr: usize, p_addr: usize, bits: u32) {
+        assert!(Self::is_user_range(v_addr, PAGE_SIZE), "Map a non-user address {:#x}.", v_addr);
+        self.table_mut().map(v_addr, p_addr, pte_bits(bits), 0);
+    }
+
+    /// Alloc a zeroed page and map it at the user page `v_addr`. Returns the physical address
+    /// of the page, or 0 if out of memory.
+    pub fn alloc_and_map_page(&mut self, v_addr: usize, bits: u32) -> usize {
+        let p_addr = page::alloc_zeroed_page(0);
+        if p_addr != 0 {
+            self.map_page(v_addr, p_addr, bits);
+        }
+
+        p_addr
+    }
+
+    /// Translate a user virtual address to the physical address.
+    pub fn translate(&self, v_addr: usize) -> Option<usize> {
+        if !Self::is_user_range(v_addr, 1) {
+            return None;
+        }
+        self.table().virt_to_phys(v_addr)
+    }
+
+    /// Look up the leaf entry of the user page `v_addr`. Returns the physical address of the page
+    /// and the entry flag bits.
+    pub fn lookup(&self, v_addr: usize) -> Option<(usize, u32)> {
+        if !Self::is_user_range(v_addr, 1) {
+            return None;
+        }
+
+        let v_addr = v_addr & !(PAGE_SIZE - 1);
+        let mut ret = None;
+        mmu::for_each_leaf(self.table(), v_addr, v_addr + PAGE_SIZE, |_, p_addr, bits, _| {
+            ret = Some((p_addr, bits));
+        });
+        ret
+    }
+
+    /// Write `data` to the user address `v_addr` through the physical pages, the range may cross
+    /// pages. The missing pages inside the VMAs are populated. Returns false if any page of the
+    /// range is invalid or out of memory.
+    pub fn write_bytes(&mut self, v_addr: usize, data: &[u8]) -> bool {
+        let mut done = 0;
+        while done < data.len() {
+            let addr = v_addr + done;
+            let writable = matches!(self.lookup(addr),
+                                    Some((_, bits)) if bits & EntryBits::Write.val() != 0);
+            if !writable && self.handle_page_fault(addr, FaultAccess::Write).is_err() {
+                return false;
+            }
+            let Some(p_addr) = self.translate(addr) else {
+                return false;
+            };
+            let len = (PAGE_SIZE - (addr & (PAGE_SIZE - 1))).min(data.len() - done);
+            unsafe {
+                core::ptr::copy_nonoverlapping(data.as_ptr().add(done), p_addr as *mut u8, len);
+            }
+            done += len;
+        }
+
+        true
+    }
+
+    /// Get the VMAs.
+    #[inline(always)]
+    pub fn vmas(&self) -> &VmaTree {
+        &self.vmas
+    }
+
+    /// Get the total size of the VMAs in bytes.
+    pub fn mapped_size(&self) -> usize {
+        let mut size = 0;
+        let mut vma = self.vmas.first();
+        while !vma.is_null() {
+            let v = unsafe { &*vma };
+            size += v.end() - v.start();
+            vma = VmaTree::next(vma);
+        }
+        size
+    }
+
+    /// Get the size of the user pages mapped in the page table, the resident set, in bytes.
+    pub fn resident_size(&self) -> usize {
+        let mut size = 0;
+        mmu::for_each_leaf(self.table(), USER_SPACE_START, USER_SPACE_END, |_, _, bits, level| {
+            if bits & EntryBits::User.val() != 0 {
+                size += PAGE_SIZE << (level * 9);
+            }
+        });
+        size
+    }
+
+    /// Find the VMA containing `addr`.
+    pub fn find_vma(&self, addr: usize) -> Option<&Vma> {
+        let vma = self.vmas.find(addr);
+        if vma.is_null() { None } else { Some(unsafe { &*vma }) }
+    }
+
+    /// Add a VMA of the page-aligned user range `[addr, addr + len)` with the permission `bits`.
+    /// The pages are populated lazily. Returns `Err` if the range overlaps other VMAs.
+    pub fn add_vma(&mut self, addr: usize, len: usize, bits: u32, backing: VmaBacking)
+        -> Result<(), i32> {
+        if addr & (PAGE_SIZE - 1) != 0 || len & (PAGE_SIZE - 1) != 0 ||
+            !Self::is_user_range(addr, len) {
+            return Err(E_INVALID);
+        }
+        self.vmas.insert(addr, addr + len, bits, backing).map(|_| ())
+    }
+
+    /// Remove the page-aligned user range `[addr, addr + len)` from the VMAs, unmap the pages
+    /// and free them (the device pages are not owned and not freed). The page table pages which
+    /// become empty are freed too.
+    pub fn unmap_range(&mut self, addr: usize, len: usize) -> Result<(), i32> {
+        if addr & (PAGE_SIZE - 1) != 0 || !Self::is_user_range(addr, len) {
+            return Err(E_INVALID);
+        }
+
+        let table = self.table;
+        let end = addr + align_up(len, PAGE_ORDER);
+        let mut pages = Vec::new();
+        self.vmas.remove_range(addr, end, |vma, start, end| {
+            let free = !matches!(vma.backing(), VmaBacking::Device { .. });
+            unsafe { unmap_pages(table, start, end, free.then_some(&mut pages)); }
+        })?;
+
+        // Other harts may still access the pages by the stale TLB entries until flushed. A page
+        // flush does not drop the cached branch entries, which may refer to the freed tables.
+        if self.table_mut().free_unused_entry() {
+            self.flush_tlb_all();
+        } else {
+            self.flush_tlb_range(addr, end);
+        }
+        for p_addr in pages {
+            page::put_page(p_addr);
+        }
+        Ok(())
+    }
+
+    /// Allocate and map the page `v_addr` of `vma` by the VMA backing.
+    fn populate_page(&mut self, vma: *const Vma, v_addr: usize) -> Result<(), i32> {
+        let vma = unsafe { &*vma };
+        let offset = v_addr - vma.start();
+        let p_addr = match vma.backing() {
+            VmaBacking::Anonymous | VmaBacking::Shared => page::alloc_zeroed_page(0),
+            VmaBacking::File { file, offset: file_offset } => {
+                let p_addr = page::alloc_zeroed_page(0);
+                if p_addr != 0 {
+                    let buf = unsafe {
+                        core::slice::from_raw_parts_mut(p_addr as *mut u8, PAGE_SIZE)
+                    };
+                    if let Err(errno) = file.read_at(file_offset + offset, buf) {
+                        page::free_page(p_addr);
+                        return Err(errno);
+                    }
+                }
+                p_addr
+            }
+            VmaBacking::Device { p_addr } => p_addr + offset,
+        };
+        if p_addr == 0 {
+            return Err(E_NO_MEM);
+        }
+
+        self.map_page(v_addr, p_addr, vma.bits());
+        Ok(())
+    }
+
+    /// Make the copy-on-write page `p_addr` at `v_addr` of `vma` writable. The private page is
+    /// copied if it is still shared with other address spaces, otherwise it is reused. Returns
+    /// `Err` with `E_ACCESS` for a device page, which is never made writable here.
+    fn break_cow(&mut self, vma: *const Vma, v_addr: usize, p_addr: usize) -> Result<(), i32> {
+        let vma = unsafe { &*vma };
+        if matches!(vma.backing(), VmaBacking::Device { .. }) {
+            return Err(E_ACCESS);
+        }
+        let (bits, private) = (vma.bits(), vma.is_private());
+        if private && page::page_ref_count(p_addr) > 1 {
+            let new_page = page::alloc_page(0);
+            if new_page == 0 {
+                return Err(E_NO_MEM);
+            }
+            unsafe {
+                core::ptr::copy_nonoverlapping(p_addr as *const u8, new_page as *mut u8, PAGE_SIZE);
+            }
+            self.map_page(v_addr, new_page, bits);
+            self.flush_tlb_range(v_addr, v_addr + PAGE_SIZE);
+            page::put_page(p_addr);
+        } else {
+            self.map_page(v_addr, p_addr, bits);
+            self.flush_tlb_range(v_addr, v_addr + PAGE_SIZE);
+        }
+
+        Ok(())
+    }
+
+    /// Handle a page fault of the `access` at the user address `addr`: if the address is in a
+    /// VMA which allows the access, the page is populated by the VMA backing (or copied if it is
+    /// a copy-on-write page), and the faulting instruction can be restarted.
+    ///
+    /// Returns `Err` with `E_FAULT` if the access is invalid, `E_ACCESS` if a read-only device
+    /// page is written, or `E_NO_MEM` if out of memory.
+    pub fn handle_page_fault(&mut self, addr: usize, access: FaultAccess) -> Result<(), i32> {
+        let vma = self.vmas.find(addr);
+        if vma.is_null() || !unsafe { (*vma).allows(access) } {
+            return Err(E_FAULT);
+        }
+
+        let v_addr = align_down(addr, PAGE_ORDER);
+        match self.lookup(v_addr) {
+            // Populated already, the fault is caused by a stale TLB entry.
+            Some((_, bits)) if bits & access.required_bits() != 0 => {
+                let asid = asid::context_asid(self.context.load(Ordering::Relaxed));
+                tlb::local_flush_range(asid, v_addr, v_addr + PAGE_SIZE);
+                Ok(())
+            }
+            // A read-only page in a writable VMA is shared copy-on-write.
+            Some((p_addr, _)) if access == FaultAccess::Write => {
+                self.break_cow(vma, v_addr, p_addr)
+            }
+            Some(_) => Err(E_FAULT),
+            None => self.populate_page(vma, v_addr),
+        }
+    }
+
+    /// Set the start of the heap, usually the end of the executable. The program break is reset
+    /// to the same address.
+    pub fn set_brk_start(&mut self, addr: usize) {
+        debug_assert!(Self::is_user_range(addr, 0) && addr <= MMAP_TOP);
+        self.brk_start = addr;
+        self.brk = addr;
+    }
+
+    /// Get the start of the heap.
+    #[inline(always)]
+    pub fn brk_start(&self) -> usize {
+        self.brk_start
+    }
+
+    /// Get the program break.
+    #[inline(always)]
+    pub fn brk(&self) -> usize {
+        self.brk
+    }
+
+    /// Set the program break to `addr` like the `brk` syscall: the heap VMA is extended or
+    /// shrunk, and the new program break is returned. The program break is unchanged if the
+    /// `addr` is out of the heap range, the heap would overlap other VMAs or out of memory.
+    pub fn set_brk(&mut self, addr: usize) -> usize {
+        if addr < self.brk_start || addr > MMAP_TOP {
+            return self.brk;
+        }
+
+        let heap_start = align_up(self.brk_start, PAGE_ORDER);
+        let old_end = align_up(self.brk, PAGE_ORDER);
+        let new_end = align_up(addr, PAGE_ORDER);
+        if new_end > old_end {
+            if self.vmas.overlaps(old_end, new_end) {
+                return self.brk;
+            }
+            let heap = if old_end > heap_start {
+                self.vmas.find(old_end - 1)
+            } else {
+                core::ptr::null_mut()
+            };
+            if !heap.is_null() {
+                self.vmas.set_end(heap, new_end);
+            } else if self.vmas.insert(old_end, new_end, EntryBits::ReadWrite.val(),
+                                       VmaBacking::Anonymous).is_err() {
+                return self.brk;
+            }
+        } else if new_end < old_end && self.unmap_range(new_end, old_end - new_end).is_err() {
+            return self.brk;
+        }
+
+        self.brk = addr;
+        self.brk
+    }
+
+    /// Find a free range of `len` bytes between the heap and the [`MMAP_TOP`], from the top down.
+    fn get_unmapped_area(&self, len: usize) -> Result<usize, i32> {
+        let floor = align_up(self.brk, PAGE_ORDER);
+        let mut top = MMAP_TOP;
+        let mut vma = self.vmas.last();
+        while !vma.is_null() {
+            let v = unsafe { &*vma };
+            if v.end() <= top && top - v.end() >= len {
+                break;
+            }
+            top = top.min(v.start());
+            vma = VmaTree::prev(vma);
+        }
+
+        match top.checked_sub(len) {
+            Some(start) if start >= floor => Ok(start),
+            _ => Err(E_NO_MEM),
+        }
+    }
+
+    /// Map anonymous zeroed pages of `len` bytes with the permission `bits` at the `placement`,
+    /// the pages are populated lazily. The `shared` pages stay shared with the forked address
+    /// spaces, instead of copy-on-write. Zero `bits` only reserves the range.
+    ///
+    /// Returns the start address, or `Err` with the errno.
+    pub fn map_anonymous(&mut self, placement: MapPlacement, len: usize, bits: u32, shared: bool)
+        -> Result<usize, i32> {
+        if len == 0 || len > USER_SPACE_END - USER_SPACE_START {
+            return Err(E_INVALID);
+        }
+        let len = align_up(len, PAGE_ORDER);
+
+        let start = match placement {
+            MapPlacement::Fixed(addr) => {
+                self.unmap_range(addr, len)?;
+                addr
+            }
+            MapPlacement::FixedNoReplace(addr) => {
+                if addr & (PAGE_SIZE - 1) != 0 || !Self::is_user_range(addr, len) {
+                    return Err(E_INVALID);
+                }
+                if self.vmas.overlaps(addr, addr + len) {
+                    return Err(E_EXIST);
+                }
+                addr
+            }
+            MapPlacement::Hint(addr) => {
+                let addr = align_down(addr, PAGE_ORDER);
+                if addr != 0 && Self::is_user_range(addr, len) &&
+                    !self.vmas.overlaps(addr, addr + len) {
+                    addr
+                } else {
+                    self.get_unmapped_area(len)?
+                }
+            }
+        };
+        let backing = if shared { VmaBacking::Shared } else { VmaBacking::Anonymous };
+        self.vmas.insert(start, start + len, bits, backing)?;
+
+        Ok(start)
+    }
+
+    /// Change the permission of the page-aligned user range `[addr, addr + len)` to `bits`, like
+    /// the `mprotect` syscall. The populated pages are remapped, except that the private pages
+    /// still shared copy-on-write stay read-only.
+    ///
+    /// Returns `Err` with `E_NO_MEM` if any page of the range is not mapped, or `E_ACCESS` if the
+    /// `bits` exceed the maximum permission of a VMA (see [`Vma::max_bits`]).
+    pub fn protect_range(&mut self, addr: usize, len: usize, bits: u32) -> Result<(), i32> {
+        if addr & (PAGE_SIZE - 1) != 0 || !Self::is_user_range(addr, len) {
+            return Err(E_INVALID);
+        }
+        let end = addr + align_up(len, PAGE_ORDER);
+        if addr == end {
+            return Ok(());
+        }
+        self.vmas.protect_range(addr, end, bits)?;
+
+        let table = self.table;
+        let mut vma = self.vmas.find(addr);
+        while !vma.is_null() && unsafe { (*vma).start() } < end {
+            let v = unsafe { &*vma };
+            let private = v.is_private();
+            mmu::for_each_leaf(unsafe { &*table }, v.start(), v.end(), |v_addr, p_addr, _, _| {
+                let mut bits = bits;
+                if private && page::page_ref_count(p_addr) > 1 {
+                    bits &= !EntryBits::Write.val();
+                }
+                // The walk has read the entry, changing it does not affect the walk.
+                unsafe { (*table).map(v_addr, p_addr, pte_bits(bits), 0); }
+            });
+            vma = VmaTree::next(vma);
+        }
+        self.flush_tlb_range(addr, end);
+
+        Ok(())
+    }
+
+    /// Duplicate the address space for `fork`. The VMAs are cloned and the populated pages are
+    /// shared: the writable private pages become read-only copy-on-write pages in both address
+    /// spaces, the pages of the shared and device VMAs are mapped as they are.
+    ///
+    /// Returns `Err` with the errno if out of memory.
+    pub fn fork(&mut self) -> Result<AddressSpace, i32> {
+        let mut child = Self::new().ok_or(E_NO_MEM)?;
+        child.brk_start = self.brk_start;
+        child.brk = self.brk;
+
+        let table = self.table;
+        let write = EntryBits::Write.val();
+        let rwx = EntryBits::ReadWriteExecute.val();
+        let user = EntryBits::User.val();
+        let mut vma = self.vmas.first();
+        while !vma.is_null() {
+            let v = unsafe { &*vma };
+            if let Err(errno) = child.vmas.insert_copy(v) {
+                unsafe { child.destroy(); }
+                return Err(errno);
+            }
+
+            let device = matches!(v.backing(), VmaBacking::Device { .. });
+            let private = v.is_private();
+            mmu::for_each_leaf(unsafe { &*table }, v.start(), v.end(), |v_addr, p_addr, bits, _| {
+                // The inaccessible pages are mapped without the `User` bit.
+                let mut bits = if bits & user != 0 { bits & rwx } else { 0 };
+                if !device {
+                    page::share_page(p_addr);
+                }
+                if private && bits & write != 0 {
+                    bits &= !write;
+                    // The walk has read the entry, changing it does not affect the walk.
+                    unsafe { (*table).map(v_addr, p_addr, pte_bits(bits), 0); }
+                }
+                child.map_page(v_addr, p_addr, bits);
+            });
+            vma = VmaTree::next(vma);
+        }
+        // The writable pages become read-only, on all harts running the threads of this process.
+        self.flush_tlb_all();
+
+        Ok(child)
+    }
+
+    /// Remove all VMAs, unmap all user pages, drop the page references and free the page table.
+    ///
+    /// # Safety
+    ///
+    /// The address space must not be active on any hart, and must not be used after this call.
+    pub unsafe fn destroy(&mut self) {
+        // The device pages are not owned, unmap them first to skip them below.
+        let table = self.table;
+        self.vmas.clear(|vma| {
+            if let VmaBacking::Device { .. } = vma.backing() {
+                unmap_pages(table, vma.start(), vma.end(), None);
+            }
+        });
+        mmu::for_each_leaf(self.table(), USER_SPACE_START, USER_SPACE_END, |_, p_addr, _, level| {
+            debug_assert!(level == 0);
+            page::put_page(p_addr);
+        });
+        mmu::destroy_copied_root_table(&mut *self.table, &*get_kernel_identity_table());
+    }
+}
+
+/// Unmap the mapped pages in the user range `[start, end)` of the page `table`, the physical
+/// pages are pushed to `pages` if any. The TLB is not flushed, the caller must flush it before
+/// dropping the page references.
+unsafe fn unmap_pages(table: *mut dyn Table, start: usize, end: usize,
+                      mut pages: Option<&mut Vec<usize>>) {
+    mmu::for_each_leaf(&*table, start, end, |v_addr, p_addr, _, level| {
+        debug_assert!(level == 0);
+        // The walk has read the entry, clearing it does not affect the walk.
+        (*table).unmap(v_addr);
+        if let Some(pages) = pages.as_mut() {
+            pages.push(p_addr);
+        }
+    });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[kernel_test]
+    fn map_and_translate() {
+        let mut aspace = AddressSpace::new().unwrap();
+        let va = USER_SPACE_START + 3 * PAGE_SIZE;
+        let pa = aspace.alloc_and_map_page(va, EntryBits::ReadWrite.val());
+        assert_ne!(pa, 0);
+        assert_eq!(aspace.translate(va + 8), Some(pa + 8));
+        assert_eq!(aspace.translate(va + PAGE_SIZE), None);
+        let (p, bits) = aspace.lookup(va + 8).unwrap();
+        assert_eq!(p, pa);
+        assert_ne!(bits & EntryBits::User.val(), 0);
+        // Kernel mappings are shared but not visible as the user addresses.
+        assert_eq!(aspace.translate(0x8000_0000), None);
+        assert!(aspace.table().virt_to_phys(0x8000_0000).is_some());
+        unsafe { aspace.destroy(); }
+    }
+
+    #[kernel_test]
+    fn demand_paging() {
+        let mut aspace = AddressSpace::new().unwrap();
+        let addr = aspace.map_anonymous(MapPlacement::Hint(0), 4 * PAGE_SIZE,
+                                        EntryBits::Read.val(), false).unwrap();
+        assert_eq!(addr, MMAP_TOP - 4 * PAGE_SIZE);
+        assert_eq!(aspace.translate(addr), None);
+
+        // A read fault populates a zeroed page, a write fault is invalid.
+        aspace.handle_page_fault(addr + PAGE_SIZE + 8, FaultAccess::Read).unwrap();
+        let pa = aspace.translate(addr + PAGE_SIZE).unwrap();
+        assert_eq!(unsafe { *(pa as *const u64) }, 0);
+        assert_eq!(aspace.handle_page_fault(addr, FaultAccess::Write), Err(E_FAULT));
+        assert_eq!(aspace.handle_page_fault(addr - 1, FaultAccess::Read), Err(E_FAULT));
+
+        // Unmapping the middle page splits the VMA.
+        aspace.unmap_range(addr + PAGE_SIZE, PAGE_SIZE).unwrap();
+        assert_eq!(aspace.vmas().count(), 2);
+        assert_eq!(aspace.translate(addr + PAGE_SIZE), None);
+        assert_eq!(aspace.handle_page_fault(addr + PAGE_SIZE, FaultAccess::Read), Err(E_FAULT));
+        assert_eq!(aspace.map_anonymous(MapPlacement::Hint(0), PAGE_SIZE, 0, false),
+                   Ok(addr + PAGE_SIZE));
+
+        // The heap grows and shrinks lazily.
+        let heap = USER_SPACE_START + 0x10_0000;
+        aspace.set_brk_start(heap + 8);
+        assert_eq!(aspace.set_brk(heap + 0x3000), heap + 0x3000);
+        aspace.handle_page_fault(heap + 0x2000, FaultAccess::Write).unwrap();
+        assert_eq!(aspace.set_brk(heap + 0x1000), heap + 0x1000);
+        assert_eq!(aspace.translate(heap + 0x2000), None);
+        assert!(aspace.find_vma(heap + 0x1000).is_none());
+        unsafe { aspace.destroy(); }
+    }
+
+    #[kernel_test]
+    fn fork_copy_on_write() {
+        let mut parent = AddressSpace::new().unwrap();
+        let rw = EntryBits::ReadWrite.val();
+        let addr = parent.map_anonymous(MapPlacement::Hint(0), PAGE_SIZE, rw, false).unwrap();
+        assert!(parent.write_bytes(addr, &[1, 2, 3]));
+        let pa = parent.translate(addr).unwrap();
+
+        // Both map the same read-only page.
+        let mut child = parent.fork().unwrap();
+        assert_eq!(child.vmas().count(), 1);
+        assert_eq!(child.translate(addr), Some(pa));
+        assert_eq!(parent.lookup(addr).unwrap().1 & EntryBits::Write.val(), 0);
+        assert_eq!(page::page_ref_count(pa), 2);
+
+        // The child copies the page on write, then the parent owns the old page alone.
+        assert!(child.write_bytes(addr + 1, &[9]));
+        let child_pa = child.translate(addr).unwrap();
+        assert_ne!(child_pa, pa);
+        assert_eq!(unsafe { *(child_pa as *const [u8; 3]) }, [1, 9, 3]);
+        assert_eq!(unsafe { *(pa as *const [u8; 3]) }, [1, 2, 3]);
+        assert_eq!(page::page_ref_count(pa), 1);
+        parent.handle_page_fault(addr, FaultAccess::Write).unwrap();
+        assert_eq!(parent.translate(addr), Some(pa));
+        unsafe {
+            child.destroy();
+            parent.destroy();
+        }
+    }
+
+    #[kernel_test]
+    fn shared_and_protect() {
+        let mut parent = AddressSpace::new().unwrap();
+        let rw = EntryBits::ReadWrite.val();
+        let hint = MMAP_TOP - 16 * PAGE_SIZE;
+        let addr = parent.map_anonymous(MapPlacement::Hint(hint), 2 * PAGE_SIZE, rw, true).unwrap();
+        assert_eq!(addr, hint);
+        assert_eq!(parent.map_anonymous(MapPlacement::FixedNoReplace(hint + PAGE_SIZE), PAGE_SIZE,
+                                        rw, false), Err(E_EXIST));
+        assert!(parent.write_bytes(addr, &[1]));
+
+        // The shared page stays writable and is not copied.
+        let mut child = parent.fork().unwrap();
+        assert!(child.write_bytes(addr, &[7]));
+        let pa = parent.translate(addr).unwrap();
+        assert_eq!(child.translate(addr), Some(pa));
+        assert_eq!(unsafe { *(pa as *const u8) }, 7);
+
+        // An inaccessible page is kept but not visible to the user.
+        parent.protect_range(addr, PAGE_SIZE, 0).unwrap();
+        assert_eq!(parent.vmas().count(), 2);
+        assert_eq!(parent.lookup(addr).unwrap().1 & EntryBits::User.val(), 0);
+        assert_eq!(parent.handle_page_fault(addr, FaultAccess::Read), Err(E_FAULT));
+        assert_eq!(parent.protect_range(addr, 3 * PAGE_SIZE, rw), Err(E_NO_MEM));
+        parent.protect_range(addr, PAGE_SIZE, rw).unwrap();
+        let (_, bits) = parent.lookup(addr).unwrap();
+        assert_eq!(bits & (rw | EntryBits::User.val()), rw | EntryBits::User.val());
+        unsafe {
+            child.destroy();
+            parent.destroy();
+        }
+    }
+
+    #[kernel_test]
+    fn device_page_never_writable() {
+        use crate::proc::signal::{map_sigreturn_trampoline, SIGRETURN_TRAMPOLINE};
+
+        let mut parent = AddressSpace::new().unwrap();
+        map_sigreturn_trampoline(&mut parent).unwrap();
+        let addr = SIGRETURN_TRAMPOLINE;
+        let rw = EntryBits::ReadWrite.val();
+        let read = EntryBits::Read.val();
+        assert_eq!(parent.protect_range(addr, PAGE_SIZE, rw), Err(E_ACCESS));
+        assert_eq!(parent.protect_range(addr, PAGE_SIZE, EntryBits::ReadWriteExecute.val()),
+                   Err(E_ACCESS));
+        assert_eq!(parent.handle_page_fault(addr, FaultAccess::Write), Err(E_FAULT));
+        parent.handle_page_fault(addr, FaultAccess::Execute).unwrap();
+        assert_eq!(parent.lookup(addr).unwrap().1 & EntryBits::Write.val(), 0);
+        assert!(!parent.write_bytes(addr, &[0]));
+
+        // The permission can be lowered and restored, the maximum is kept by `fork`.
+        parent.protect_range(addr, PAGE_SIZE, read).unwrap();
+        let mut child = parent.fork().unwrap();
+        assert_eq!(child.protect_range(addr, PAGE_SIZE, rw), Err(E_ACCESS));
+        assert_eq!(child.find_vma(addr).unwrap().bits(), read);
+        child.protect_range(addr, PAGE_SIZE, EntryBits::ReadExecute.val()).unwrap();
+        unsafe {
+            child.destroy();
+            parent.destroy();
+        }
+    }
+}
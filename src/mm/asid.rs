@@ -0,0 +1,93 @@
+//! Address space identifier (ASID) allocator.
+//!
+//! Each user address space gets an ASID, so the TLB entries of different address spaces can
+//! live together and switching the `satp` does not need to flush the TLB. The generation-based
+//! allocator is implemented in [`vos_core::mm::asid`], this module detects the ASID bits of the
+//! hardware and keeps the allocator state with a per-hart [`AsidCpu`].
+//!
+//! ASID 0 is used by the kernel identity map. If the hardware supports too few ASID bits, the
+//! ASIDs are disabled: all address spaces use ASID 0 and the TLB is flushed on each switch.
+
+use core::sync::atomic::AtomicUsize;
+use vos_core::mm::asid::{AsidCpu, AsidMap, Asids, MAX_ASID_BITS};
+use crate::arch::cpu;
+use crate::base::sync::lock::SpinLockPure;
+use crate::mm::get_satp_identity_map;
+use crate::smp::{current_cpu_info, get_cpu_count, PerCpuPtr};
+
+
+/// Bit offset of the ASID field in the `satp`.
+pub const SATP_ASID_SHIFT: usize = 44;
+
+/// The ASID bits and the generation, only changed by [`init`].
+static mut ASIDS: Asids = Asids::new();
+/// ASIDs used in the current generation, protected by the [`ASID_LOCK`].
+static mut ASID_MAP: AsidMap = AsidMap::new();
+static ASID_LOCK: SpinLockPure = SpinLockPure::new();
+static mut ASID_CPUS: PerCpuPtr<AsidCpu> = PerCpuPtr::null();
+
+#[inline(always)]
+fn asids() -> &'static Asids {
+    unsafe { &*core::ptr::addr_of!(ASIDS) }
+}
+
+/// Check if the ASIDs are enabled.
+#[inline(always)]
+pub fn asid_enabled() -> bool {
+    asids().enabled()
+}
+
+/// Get the ASID of a `context`.
+#[inline(always)]
+pub fn context_asid(context: usize) -> usize {
+    asids().context_asid(context)
+}
+
+/// Detect the number of ASID bits by writing all ones to the ASID field of the `satp`, and
+/// init the per-hart state. Called on the boot hart after the `kmalloc` is available.
+pub(super) fn init() {
+    let satp = get_satp_identity_map();
+    cpu::satp_write(satp | (((1 << MAX_ASID_BITS) - 1) << SATP_ASID_SHIFT));
+    let bits = (cpu::satp_read() >> SATP_ASID_SHIFT & ((1 << MAX_ASID_BITS) - 1)).count_ones();
+    cpu::satp_write(satp);
+    cpu::sfence_vma_all();
+
+    unsafe {
+        ASID_CPUS.init();
+        for cpu in ASID_CPUS.as_array_mut() {
+            (cpu as *mut AsidCpu).write(AsidCpu::new());
+        }
+        let asids = &mut *core::ptr::addr_of_mut!(ASIDS);
+        asids.init(bits as usize, get_cpu_count(), &mut *core::ptr::addr_of_mut!(ASID_MAP));
+    }
+    info!("ASID bits: {}, ASIDs {}.", bits, if asid_enabled() { "enabled" } else { "disabled" });
+}
+
+/// Get the ASID of the `context` of an address space for running on this hart, a new one is
+/// allocated if the context is not in the current generation. The `context` is updated.
+///
+/// Returns the ASID, and whether the whole TLB of this hart must be flushed. Always returns
+/// ASID 0 without the flush if the ASIDs are disabled, the caller must flush the TLB when the
+/// `satp` is changed. Must be called with the interrupts disabled.
+pub fn switch_context(context: &AtomicUsize) -> (usize, bool) {
+    let asids = asids();
+    if !asids.enabled() {
+        return (0, false);
+    }
+
+    let cpu = unsafe { ASID_CPUS.get_ref_raw() };
+    if let Some(asid) = asids.switch_fast(cpu, context) {
+        return (asid, false);
+    }
+
+    let _guard = ASID_LOCK.lock_guard_irq_save();
+    let generation = asids.generation();
+    let map = unsafe { &mut *core::ptr::addr_of_mut!(ASID_MAP) };
+    let cpus = unsafe { ASID_CPUS.as_array_mut() };
+    let ret = asids.switch_locked(map, cpus, cpu, context);
+    if asids.generation() != generation {
+        debug!("ASID rollover on cpu #{}.", current_cpu_info().get_cpu_id());
+    }
+
+    ret
+}
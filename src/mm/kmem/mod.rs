@@ -17,18 +17,16 @@ use crate::arch::cpu;
 use crate::barrier;
 use crate::base::irq;
 use crate::base::sync::lock;
-use crate::errno::{E_INVALID, E_NO_SYS};
-use crate::mm::page::{
-    self, gfp::*, alloc_pages, Page,
-    PAGE_ALLOC_COSTLY_ORDER, PAGE_ALLOC_MAX_ORDER, GfpAllocFlag
-};
-use crate::mm::{PAGE_ORDER, PAGE_SIZE};
+use crate::errno::E_INVALID;
+use crate::mm::page::{self, gfp::*, alloc_pages, Page, GfpAllocFlag};
+use crate::mm::PAGE_SIZE;
 use crate::mm::kmem::slub::Slub;
 use crate::sched::PreemptGuard;
 use crate::smp::{get_cpu_count, PerCpuPtr};
 use crate::util::align::{align_up, align_up_by, align_up_of};
 use crate::util::forward_list::ForwardList;
 use crate::util::list::{self, List};
+use vos_core::mm::slab;
 
 
 /// Flags to pass to [`KmemCache::create`]. The ones marked `Debug` are only valid if
@@ -85,6 +83,8 @@ pub struct KmemCache {
     /// Name (only used for display). We do not use `&str` to avoid the **UB** that when we
     /// get the `KmemCache` object with a `core::mem::zeroed` call.
     name: *const u8,
+    /// Length of the `name`.
+    name_len: u32,
     node: *mut KmemCacheNode,
     list: List,
 }
@@ -158,6 +158,58 @@ impl KmemCache {
 
     pub fn free(&mut self, obj: *mut ()) {
     }
+
+    /// Get the name, empty if not set.
+    pub fn name(&self) -> &str {
+        if self.name.is_null() {
+            return "";
+        }
+        unsafe {
+            let bytes = core::slice::from_raw_parts(self.name, self.name_len as usize);
+            core::str::from_utf8_unchecked(bytes)
+        }
+    }
+
+    /// Get the object size without meta data.
+    #[inline(always)]
+    pub fn object_size(&self) -> u32 {
+        self.object_size
+    }
+
+    /// Get the object size including meta data and alignment.
+    #[inline(always)]
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Get the count of objects in a slab.
+    #[inline(always)]
+    pub fn objects_per_slab(&self) -> u32 {
+        self.object_count as u32
+    }
+
+    /// Get the count of pages of a slab.
+    #[inline(always)]
+    pub fn pages_per_slab(&self) -> u32 {
+        1 << self.page_order
+    }
+
+    /// Get the count of the partial slabs on the node.
+    pub fn nr_partial(&self) -> u32 {
+        if self.node.is_null() { 0 } else { unsafe { (*self.node).nr_partial } }
+    }
+}
+
+/// Call `handle` on each slab cache until it returns `false`.
+pub fn for_each_slab_cache<F>(mut handle: F) where F: FnMut(&KmemCache) -> bool {
+    unsafe {
+        // The list is set up with the first cache.
+        let caches = &mut *addr_of_mut!(SLAB_CACHES);
+        if caches.next.is_null() {
+            return;
+        }
+        list::for_each(caches, |cur| handle(&*container_of_mut!(cur, KmemCache, list)));
+    }
 }
 
 // bootstrap slub allocator.
@@ -180,6 +232,7 @@ pub(super) fn kmem_cache_init() {
 /// Create a cache during boot when no slab services are available yet.
 fn create_boot_cache(s: &mut KmemCache, name: &'static str, size: u32, flags: u32) {
     s.name = name.as_ptr();
+    s.name_len = name.len() as u32;
     s.object_size = size;
     s.size = size;
 
@@ -289,10 +342,10 @@ fn calc_sizes(s: &mut KmemCache) -> bool {
     size = align_up_by(size as usize, s.align as usize) as u32;
     s.size = size;
 
-    let order = calc_order(size) as u32;
-    if (order as i32) < 0 {
-        return false;
-    }
+    let order = match slab::calc_order(size, get_cpu_count()) {
+        Some(order) => order,
+        None => return false,
+    };
 
     s.alloc_flags = 0;
     if order != 0 {
@@ -309,98 +362,11 @@ fn calc_sizes(s: &mut KmemCache) -> bool {
     }
 
     s.page_order = order as u16;
-    s.object_count = order_objects(order, size) as u16;
+    s.object_count = slab::order_objects(order, size) as u16;
 
     s.object_count != 0
 }
 
-/// Calculates the best order used to alloc pages for a slub with the special `size` object.
-/// Returning a value less than 0 means that we cannot find an appropriate order.
-fn calc_order(size: u32) -> i32 {
-    const SLUB_MAX_ORDER: u32 = PAGE_ALLOC_COSTLY_ORDER;
-
-    let nr_cpus = get_cpu_count();
-    let mut min_objects = (32u32 - nr_cpus.leading_zeros()) * 4;
-    let max_objects = order_objects(SLUB_MAX_ORDER, size);
-    min_objects = core::cmp::min(min_objects, max_objects);
-
-    while min_objects > 1 {
-        let mut fraction = 16u32;
-        while fraction >= 4u32 {
-            let order = calc_slab_order(size, min_objects, SLUB_MAX_ORDER, fraction);
-            if order <= SLUB_MAX_ORDER {
-                return order as _;
-            }
-            fraction /= 2u32;
-        }
-        min_objects -= 1;
-    }
-
-    // We were unable to place multiple objects in a slab. Now lets see if we can place
-    // a single object there.
-    let order = calc_slab_order(size, 1, SLUB_MAX_ORDER, 1);
-    if order <= SLUB_MAX_ORDER {
-        return order as _;
-    }
-
-    // This slab cannot be placed using SLUB_MAX_ORDER.
-    let order = calc_slab_order(size, 1, PAGE_ALLOC_MAX_ORDER, 1);
-    if order < PAGE_ALLOC_MAX_ORDER {
-        return order as _;
-    }
-
-    -E_NO_SYS
-}
-
-const MAX_OBJS_PER_PAGE: u32 = 1u32 << 16 - 1u32;
-
-/// Calculates the order of allocation given an slab object size.
-///
-/// Generally order 0 allocations should be preferred since order 0 does not cause fragmentation
-/// in the page allocator. We go to a higher order if more than 1/16th of the slab would be
-/// wasted.
-///
-/// In order to reach satisfactory performance we must ensure that a minimum number of objects
-/// is in the slab. Otherwise we may generate too much activity on the partial lists which
-/// requires taking the list_lock.
-///
-/// `max_order` specifies the order where we begin to stop considering the number of objects
-/// in a slab as critical. If we reach `max_order` then we try to keep the page order as low
-/// as possible. So we accept more waste of space in favor of a small page order.
-fn calc_slab_order(size: u32, min_objects: u32, max_order: u32, fract_leftover: u32) -> u32 {
-    let min_order = 0u32;
-    if order_objects(min_order, size) > MAX_OBJS_PER_PAGE {
-        return get_order((size * MAX_OBJS_PER_PAGE) as usize) - 1;
-    }
-
-    let mut order = core::cmp::max(min_order, get_order((size * min_objects) as usize));
-    while order <= max_order {
-        let slab_size = PAGE_SIZE << order;
-        let rem = slab_size % size as usize;
-        if rem <= (slab_size / fract_leftover as usize) {
-            break;
-        }
-
-        order += 1;
-    }
-
-    order
-}
-
-/// Calculates the object count for a slub allocating the `order` page.
-#[inline(always)]
-const fn order_objects(order: u32, size: u32) -> u32 {
-    ((PAGE_SIZE << order) / size) as _
-}
-
-/// Determine the allocation order of a memory size. The result is undefined if the `size` is 0.
-#[inline(always)]
-const fn get_order(mut size: usize) -> u32 {
-    size -= 1usize;
-    size >>= PAGE_ORDER;
-    usize::BITS - size.leading_zeros()
-}
-
 fn set_cpu_partial(s: &mut KmemCache) {
     let nr_objects: u32 = if !kmem_cache_has_cpu_partial(s) {
         0
@@ -1011,6 +977,26 @@ pub fn get_alloc_page_num() -> usize {
     unsafe { KMEM_ALLOC }
 }
 
+/// Get the bytes of the `kmalloc` heap in use, the chunk headers included.
+pub fn get_alloc_used_bytes() -> usize {
+    let mut used = 0;
+    unsafe {
+        let mut head = KMEM_HEAD;
+        let tail = (head as *mut u8).add(KMEM_ALLOC * PAGE_SIZE) as *mut AllocList;
+        while head < tail {
+            let size = (*head).get_size();
+            if size == 0 {
+                break;
+            }
+            if (*head).is_taken() {
+                used += size;
+            }
+            head = (head as *mut u8).add(size) as *mut AllocList;
+        }
+    }
+    used
+}
+
 /// Initialize the kernel's memory.
 pub(super) fn kmem_init() {
     unsafe {
@@ -1144,3 +1130,48 @@ pub fn print_table() {
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[kernel_test]
+    fn cache_accessors() {
+        let mut cache: KmemCache = unsafe { core::mem::zeroed() };
+        assert_eq!(cache.name(), "");
+        assert_eq!(cache.nr_partial(), 0);
+        create_boot_cache(&mut cache, "test_cache", 100, 0);
+        assert_eq!(cache.name(), "test_cache");
+        assert_eq!((cache.object_size(), cache.size()), (100, 100));
+        assert_eq!(cache.pages_per_slab(), 1);
+
+        // The registered caches are all named.
+        let mut count = 0;
+        for_each_slab_cache(|cache| {
+            assert!(!cache.name().is_empty());
+            assert!(cache.object_size() <= cache.size());
+            count += 1;
+            true
+        });
+        let mut visited = 0;
+        for_each_slab_cache(|_| {
+            visited += 1;
+            false
+        });
+        assert_eq!(visited, count.min(1));
+    }
+
+    #[kernel_test]
+    fn alloc_used_bytes() {
+        let used = get_alloc_used_bytes();
+        assert!(used > 0);
+        let ptr = kmalloc(100, 0);
+        assert!(!ptr.is_null());
+        let taken = get_alloc_used_bytes() - used;
+        assert!(taken >= 104 + size_of::<AllocList>(), "{} bytes taken", taken);
+        assert!(taken <= get_alloc_page_num() * PAGE_SIZE);
+        kfree(ptr);
+        assert_eq!(get_alloc_used_bytes(), used);
+    }
+}
@@ -253,6 +253,30 @@ impl Mode {
     pub const fn val_satp(self) -> u64 {
         (self.val() as u64) << 60
     }
+
+    /// Read the mode from the `satp` register value. Returns `None` if the mode
+    /// is reserved.
+    #[inline]
+    pub const fn from_satp(satp: usize) -> Option<Mode> {
+        match satp >> 60 {
+            0 => Some(Mode::Bare),
+            8 => Some(Mode::Sv39),
+            9 => Some(Mode::Sv48),
+            10 => Some(Mode::Sv57),
+            _ => None,
+        }
+    }
+
+    /// Count of the page table levels.
+    #[inline]
+    pub const fn levels(self) -> usize {
+        match self {
+            Mode::Bare => 0,
+            Mode::Sv39 => 3,
+            Mode::Sv48 => 4,
+            Mode::Sv57 => 5,
+        }
+    }
 }
 
 /// Operations of the page table.
@@ -344,6 +368,9 @@ pub fn create_root_table(mode: Mode) -> *mut dyn Table {
 /// S-mode with suitable identity PTEs are set.
 pub fn copy_root_table(root: &dyn Table) -> *mut dyn Table {
     let pt_addr = allocator::alloc_page();
+    if pt_addr == 0 {
+        return null_mut::<Sv39Table>() as *mut dyn Table;
+    }
     let addr = root.get_addr();
     // Page table for each modes has the same size that equals to `PAGE_SIZE`,
     // just copy with ignoring the underlying format.
@@ -361,6 +388,51 @@ pub fn copy_root_table(root: &dyn Table) -> *mut dyn Table {
     }
 }
 
+/// Destroy a root table created by [`copy_root_table`]. The sub-level tables that
+/// are still shared with the `source` root table are kept, all other sub-level
+/// tables and the root table itself are freed.
+///
+/// **Note**: The pages referred by the leaf entries are not freed.
+///
+/// # Safety
+///
+/// The `table` must be created by [`copy_root_table`] from `source`, and must not
+/// be used after this call.
+pub unsafe fn destroy_copied_root_table(table: &mut dyn Table, source: &dyn Table) {
+    let levels = table.get_mode().levels();
+    if levels == 0 {
+        return;
+    }
+
+    let entry = table.get_addr() as *const Entry;
+    let shared = source.get_addr() as *const Entry;
+    for i in 0..ENTRIES_LEN {
+        let v = &*entry.add(i);
+        if v.is_valid() && v.is_branch() && v.get_entry() != (*shared.add(i)).get_entry() {
+            let child = (v.get_entry() & PTE_PPN_MASK) << 2;
+            do_destroy(child as usize, 2, levels as u32);
+        }
+    }
+
+    allocator::free_page(table.get_addr());
+}
+
+/// Walk the page table and call `handle` on each valid leaf entry that maps any
+/// address in the virtual address range `[start, end)`.
+///
+/// The params of `handle` are the virtual address, physical address, entry flag
+/// bits and level of the leaf entry. The range must be in the lower half of the
+/// virtual address space (no sign-extension).
+pub fn for_each_leaf<F>(table: &dyn Table, start: usize, end: usize, mut handle: F)
+    where F: FnMut(usize, usize, u32, u32) {
+    let levels = table.get_mode().levels();
+    if levels == 0 {
+        return;
+    }
+
+    walk_leaf(table.get_addr(), levels - 1, 0, start, end, &mut handle);
+}
+
 /// Build a `Table` trait object pointer from the page table physical address and
 /// the corresponding `Mode`.
 ///
@@ -615,6 +687,29 @@ fn do_destroy(addr: usize, level: u32, max_level: u32) {
     allocator::free_page(addr);
 }
 
+fn walk_leaf<F>(addr: usize, level: usize, base: usize, start: usize, end: usize, handle: &mut F)
+    where F: FnMut(usize, usize, u32, u32) {
+    let shift = level * 9 + PAGE_ORDER;
+    let entry = addr as *const Entry;
+    for i in 0..ENTRIES_LEN {
+        let v_addr = base + (i << shift);
+        if v_addr >= end || v_addr + (1usize << shift) <= start {
+            continue;
+        }
+
+        let v = unsafe { &*entry.add(i) };
+        if v.is_invalid() {
+            continue;
+        }
+        let p_addr = ((v.get_entry() & PTE_PPN_MASK) << 2) as usize;
+        if v.is_leaf() {
+            handle(v_addr, p_addr, (v.get_entry() & PTE_FLAG_MASK) as u32, level as u32);
+        } else if level > 0 {
+            walk_leaf(p_addr, level - 1, v_addr, start, end, handle);
+        }
+    }
+}
+
 const ENTRIES_LEN: usize = 512;
 
 #[repr(C)]
@@ -16,8 +16,14 @@
 
 pub(crate) mod early;
 pub(crate) mod page;
+pub(crate) mod page_cache;
 pub(crate) mod mmu;
 pub(crate) mod virt_qemu;
+pub(crate) mod address_space;
+pub(crate) mod uaccess;
+pub(crate) mod vma;
+pub(crate) mod asid;
+pub(crate) mod tlb;
 mod kmem;
 mod vmem;
 mod rust_alloc;
@@ -25,15 +31,16 @@ mod rust_alloc;
 // Re-export
 pub use vmem::*;
 pub use kmem::*;
+pub use vos_core::mm::{PAGE_ORDER, PAGE_SIZE};
 
 use core::arch::asm;
 use crate::arch::cpu;
 
 
-/// Order of page-size.
-pub const PAGE_ORDER: usize = 12;
-/// Page size.
-pub const PAGE_SIZE: usize = 1 << 12;
+/// Start address of the user space. See the [mod document](self).
+pub const USER_SPACE_START: usize = 0x20_0000_0000;
+/// End address (exclusive) of the user space.
+pub const USER_SPACE_END: usize = 0x40_0000_0000;
 
 
 /// Heap area base address. Init before calling `early_init` and can not change after the
@@ -55,7 +62,7 @@ pub fn set_heap_base_addr(heap_base: usize) {
 
 
 /// Init the physical memory management system, including the buddy allocator and the
-/// `SLAB` allocator.
+/// `SLAB` allocator, then the ASID allocator.
 pub fn early_init(mem_regions: &[(usize, usize)]) {
     // Store the satp value.
     unsafe {
@@ -63,11 +70,16 @@ pub fn early_init(mem_regions: &[(usize, usize)]) {
     }
     // First init the physical memory allocation system.
     page::init(mem_regions);
+    page_cache::init();
     // MMU API enable the page-based allocator feature.
     mmu::enable_page_allocator();
 
     // Init SLUB allocator for the kernel memory management.
     kmem_init();
+
+    // The per-cpu states of the ASID allocator and the TLB shootdown need the `kmalloc`.
+    asid::init();
+    tlb::init();
 }
 
 /// Get the `satp` value of the kernel identity map table.
@@ -77,6 +89,27 @@ pub fn get_satp_identity_map() -> usize {
     }
 }
 
+/// Switch this hart to the kernel identity map if not yet. The identity map uses the ASID 0,
+/// the TLB is flushed only if the ASIDs are disabled, which the user address spaces share.
+pub fn activate_identity_map() {
+    let satp = get_satp_identity_map();
+    if cpu::satp_read() != satp {
+        cpu::satp_write(satp);
+        if !asid::asid_enabled() {
+            cpu::sfence_vma_all();
+        }
+    }
+}
+
+/// Get the kernel identity map table.
+pub fn get_kernel_identity_table() -> *mut dyn mmu::Table {
+    let satp = get_satp_identity_map();
+    let mode = mmu::Mode::from_satp(satp).unwrap();
+    unsafe {
+        mmu::build_table_from_addr(satp_to_table_addr(satp), mode)
+    }
+}
+
 
 /// Alloc a area on the stack. This will simple return the `sp` register value so the
 /// returned ptr will be valid until the next function call.
@@ -102,6 +135,12 @@ extern "C" {
 }
 
 
+/// Read the physical address of the root table from a `satp` value.
+#[inline]
+pub const fn satp_to_table_addr(satp: usize) -> usize {
+    (satp & ((1usize << 44) - 1)) << 12
+}
+
 /// The `SATP` register contains three fields: mode, address space id, and the first level table
 /// address (level 2 for Sv39). This function helps make the 64-bit register contents based on
 /// those three fields.
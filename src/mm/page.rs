@@ -30,6 +30,24 @@
 //! | free_page(addr) | Free a single page from the give address |
 //! | free_pages(addr, order) | Free an order number of pages from the given address |
 //!
+//! ## Shared Pages
+//!
+//! A single page can be mapped by multiple user address spaces (e.g. the copy-on-write pages
+//! after `fork`). [`share_page`] takes one more reference of the page and marks it
+//! [`PageFlag::Shared`], and [`put_page`] drops a reference and frees the page when the last
+//! one is dropped. A page which is not shared has one implicit reference.
+//!
+//! ## Zones
+//!
+//! Each memory region of the DeviceTree is managed by its own [`Zone`], so the holes between
+//! the regions are never used; a region crossing 4GiB is split into two zones. The zones below
+//! 4GiB are the `DMA32` zones, addressable by the 32-bit DMA devices, and the others are the
+//! `Normal` zones. The allocations with [`gfp::GFP_DMA32`] (or [`gfp::GFP_DMA`], there is no
+//! smaller DMA zone on RISC-V) are only served by the `DMA32` zones, the other allocations try
+//! the `Normal` zones first and fall back to the `DMA32` zones. The zone idx is saved in each
+//! allocated [`Page`], so the page is returned to its zone. The zone list is implemented in
+//! [`vos_core::mm::zones`].
+//!
 //! ## Calling Convention
 //! All functions in this mod **must be** called either on the M-mode or on the S-mode with an identity
 //! mapping table is set (`SATP`).
@@ -39,361 +57,95 @@
 //! [Chapter 6  Physical Page Allocation]: https://www.kernel.org/doc/gorman/html/understand/understand009.html
 //! [`Page`]: Page
 //! [`page_to_address`]: page_to_address
-
-use core::mem::size_of;
-use core::ptr::null_mut;
-use core::sync::atomic::{AtomicU32, Ordering};
+//! [`share_page`]: share_page
+//! [`put_page`]: put_page
+//! [`PageFlag::Shared`]: PageFlag::Shared
+//! [`Zone`]: vos_core::mm::page::Zone
+//! [`gfp::GFP_DMA32`]: gfp::GFP_DMA32
+//! [`gfp::GFP_DMA`]: gfp::GFP_DMA
+
+use core::ptr::addr_of_mut;
 use crate::mm::{PAGE_ORDER, PAGE_SIZE};
-use crate::util::align::{align_down, align_up, get_order};
-use crate::util::list::{self, List};
-
+use crate::util::align::{align_down, align_up};
+use vos_core::mm::zones::{ZoneError, ZoneType, Zones, DMA32_LIMIT};
 
-const MAX_FREE_AREA_ORDER: usize = 10;
+// Re-export
+pub use vos_core::mm::page::{Page, PageFlag, PAGE_ALLOC_MAX_ORDER};
+pub use vos_core::mm::zones::{gfp, GfpAllocFlag};
 
-/// Max order used to alloc pages.
-pub const PAGE_ALLOC_MAX_ORDER: u32 = MAX_FREE_AREA_ORDER as u32;
-/// The order at which allocations are deemed costly to service.
-pub const PAGE_ALLOC_COSTLY_ORDER: u32 = 3;
 // todo: page order param use type u32
 
-/// Page alloc flags type.
-pub type GfpAllocFlag = u32;
-
-pub mod gfp {
-    use crate::mm::page::GfpAllocFlag;
-
-    pub const GFP_DMA: GfpAllocFlag = 1u32;
-    pub const GFP_DMA32: GfpAllocFlag = 1u32 << 2;
-    pub const GFP_COMPOUND: GfpAllocFlag = 1u32 << 18;
-    pub const GFP_RECLAIMABLE: GfpAllocFlag = 1u32 << 4;
+/// Memory zone list sorted by the address. The buddy algorithm is implemented in
+/// [`vos_core::mm::page`].
+static mut MEMORY_ZONES: Zones = Zones::new(DMA32_LIMIT);
 
-    pub const GFP_KERNEL: GfpAllocFlag = 0u32; // todo
-    pub const GFP_NO_WAIT: GfpAllocFlag = 0u32;
-}
-
-// todo: remove `PageFlag`.
-
-/// Page flags definition.
-#[repr(u32)]
-#[derive(Copy, Clone)]
-pub enum PageFlag {
-    /// Page is used in the SLAB/SLUB allocator.
-    Slab = 1 << 0,
-    /// Page is shared between multiple processes.
-    Shared = 1 << 1,
-}
 
-impl PageFlag {
-    pub const fn val(self) -> u32 {
-        self as u32
-    }
-}
-
-/// Each **page** is described by the `Page` structure.
-///
-/// We guarantee that `size_of::<Page>() % 16 == 0` and the `Page` object will be allocated
-/// with the align of `size_of::<Page>()` or 16. So the least 4 bits of the pointer to a `Page`
-/// object will always guard to 0 and can be safety used, such as save the page alloc order.
+/// Initialize the buddy allocator system with the sorted memory regions.
 ///
-/// If a **page** is allocated, then the `Page` structure associated with it will be free to
-/// use to store private data, except for the last 8 bytes which are used to store the page
-/// flags and ref count. [`get_private`] method can be used to retrieve the private area start
-/// address, and [`get_private_size`] method can be used to get the available size of private
-/// area, this is a **const** function so it can be called at the compile-time context to do
-/// some static assertions. The private size is guaranteed to at least 40 bytes; the private
-/// data address is guaranteed to be aligned with 16.
-///
-/// The least 24 bits of the inner `flags` field are used by the Page allocator, while the most
-/// 8 bits can be used to store custom flags. See methods [`get_custom_flags`],
-/// [`set_custom_flags`] and [`replace_custom_flags`] for more info, note that though the param
-/// or return type is `u32`, only the least 8 bits is valid and used.
-///
-/// When the **page** is allocated, all instance methods are invalid except for the
-/// [`get_private`], `ref_count` and `flags` operation methods.
-///
-/// [`get_private`]: self::Page::get_private
-/// [`get_private_size`]: self::Page::get_private_size
-/// [`get_custom_flags`]: self::Page::get_custom_flags
-/// [`set_custom_flags`]: self::Page::set_custom_flags
-/// [`replace_custom_flags`]: self::Page::replace_custom_flags
-#[repr(C)]
-pub struct Page {
-    head: List,
-    padding: [usize; 3],
-    ref_count: AtomicU32,
-    /// bits\[3:0] store the idx of zone that self page belongs. bits\[23:4] store inner flags.
-    /// bits\[31:24] store custom flags.
-    flags: u32,
-}
-
-// Assert the size of `Page` equals to multiple times of 16bytes.
-sa::const_assert_eq!(size_of::<Page>() % 16, 0);
-
-/// Bits to store the zone idx. See [`Page::flags`](Page).
-const ZONE_IDX_BITS: u32 = 4;
-
-impl Page {
-    /// Get the available size for private usage.
-    #[inline(always)]
-    pub const fn get_private_size() -> usize {
-        size_of::<Page>() - size_of::<u32>() * 2
-    }
-
-    /// Get a pointer of the private memory.
-    ///
-    /// **Note**: data in this memory may be in **uninitialized** state.
-    #[inline(always)]
-    pub fn get_private(&mut self) -> *mut u8 {
-        self as *mut Page as *mut u8
-    }
-
-    /// Get a pointer of the private memory region and cast to special type.
-    ///
-    /// **Note**: data in this memory may be in **uninitialized** state.
-    #[inline(always)]
-    pub fn cast_private<T>(&mut self) -> *mut T {
-        self.get_private() as _
-    }
-
-    /// Translate a pointer to the `Page` pointer.
-    ///
-    /// **Warning**: `p` **must** be fetched from ether [`get_private`] or [`cast_private`],
-    /// otherwise result is **undefined**.
-    ///
-    /// [`get_private`]: Page::get_private
-    /// [`cast_private`]: Page::cast_private
-    #[inline(always)]
-    pub fn from_private<T>(p: *mut T) -> *mut Page {
-        p as _
-    }
-
-    /// Get the flags. **Note**: custom flags are also returned.
-    #[inline(always)]
-    pub fn read_flags(&self) -> u32 {
-        self.flags >> ZONE_IDX_BITS
-    }
-
-    /// Check if the `flag` is set.
-    #[inline(always)]
-    pub fn is_flag_set(&self, flag: PageFlag) -> bool {
-        ((self.flags >> ZONE_IDX_BITS) & flag.val()) != 0
-    }
-
-    /// Set page flag.
-    #[inline(always)]
-    pub fn set_flag(&mut self, flag: PageFlag) {
-        self.flags |= flag.val() << ZONE_IDX_BITS;
-    }
-
-    /// Clear page flag.
-    #[inline(always)]
-    pub fn clear_flag(&mut self, flag: PageFlag) {
-        self.flags &= !(flag.val() << ZONE_IDX_BITS);
-    }
-
-    /// Get the custom flags.
-    #[inline(always)]
-    pub fn read_custom_flags(&self) -> u32 {
-        self.flags >> 24
-    }
-
-    /// Replace the custom flags. This will overwrite the total custom flags value.
-    ///
-    /// **Note**: Only the least 8 bits of `flag` is used.
-    #[inline(always)]
-    pub fn replace_custom_flags(&mut self, flag: u32) {
-        self.flags = (self.flags & 0xff_ffffu32) | (flag << 24);
-    }
-
-    /// Set the certain custom flags. If a bit of `flag` is 1, **set** the correspond custom flag bit;
-    /// otherwise leave the correspond flag bit unchanged.
-    ///
-    /// **Note**: Only the least 8 bits of `flag` is used.
-    #[inline(always)]
-    pub fn set_custom_flags(&mut self, flag: u32) {
-        self.flags |= flag << 24;
-    }
-
-    /// Clear the certain custom flags. If a bit of `flag` is 1, then **clear** the correspond custom
-    /// flag bit; otherwise leave the correspond flag bit unchanged.
-    ///
-    /// **Note**: Only the least 8 bits of `flag` is used.
-    #[inline(always)]
-    pub fn clear_custom_flags(&mut self, flag: u32) {
-        self.flags &= !(flag << 24);
-    }
-
-    /// Set zone idx.
-    #[inline(always)]
-    fn set_zone_idx(&mut self, idx: usize) {
-        self.flags = (self.flags & !0x0fu32) | (idx & 0x0fusize) as u32;
-    }
-
-    /// Get zone idx.
-    #[inline(always)]
-    fn get_zone_idx(&self) -> usize {
-        (self.flags & 0x0fu32) as usize
-    }
-
-    /// Get the ref count. **Note**: only available when page has been set [`PageFlag::Shared`] flag.
-    ///
-    /// [`PageFlag::Shared`]: self::PageFlag::Shared
-    #[inline(always)]
-    pub fn ref_count(&self) -> u32 {
-        self.ref_count.load(Ordering::Relaxed)
-    }
-
-    /// Increase the ref count of the shared page.
-    ///
-    /// **Note**: it is an **Undefined Behavior** if the page has no [`PageFlag::Shared`] flag set.
-    ///
-    /// [`PageFlag::Shared`]: self::PageFlag::Shared
-    #[inline(always)]
-    pub fn increase_ref(&mut self) {
-        self.ref_count.fetch_add(1, Ordering::AcqRel);
-    }
-
-    /// Decrease the ref count and return current ref count (after the decrease operation).
-    ///
-    /// **Note**: it is an **Undefined Behavior** if the page has no [`PageFlag::Shared`] flag set.
-    ///
-    /// [`PageFlag::Shared`]: self::PageFlag::Shared
-    #[inline(always)]
-    pub fn decrease_ref(&mut self) -> u32 {
-        self.ref_count.fetch_sub(1, Ordering::AcqRel) - 1u32
-    }
-}
-
-
-#[repr(C)]
-struct FreeArea {
-    free_list: List,
-    bitmap: *mut u8,
-}
-
-impl FreeArea {
-    pub const fn new() -> Self {
-        Self {
-            free_list: List::new(),
-            bitmap: null_mut(),
+/// **Note**: After this call, the heap base address **must not** be changed.
+pub fn init(mem_regions: &[(usize, usize)]) {
+    assert!(!mem_regions.is_empty(), "Memory regions is empty!");
+    let heap_base = unsafe { super::HEAP_BASE };
+    let in_region = |&(start, size): &(usize, usize)| {
+        heap_base >= start && heap_base < start + size
+    };
+    assert!(mem_regions.iter().any(in_region), "Heap base is not in the memory regions.");
+
+    for &(mem_start, mem_size) in mem_regions {
+        let end = mem_start + mem_size;
+        // The memory below the heap base holds the firmware, the kernel image and the early
+        // allocations, so the zone of the kernel region is placed from the heap base.
+        let start = if in_region(&(mem_start, mem_size)) { heap_base } else { mem_start };
+        for (ty, start, end) in zones().split_region(start, end) {
+            add_zone(ty, start, end);
         }
     }
-}
+    assert!(!zones().zones().is_empty(), "Memory is too small for the page allocator.");
 
-#[repr(C)]
-struct Zone {
-    free_areas: [FreeArea; MAX_FREE_AREA_ORDER],
-    free_pages: usize,
-    max_pages: usize,
-    mem_start: usize,
-    mem_size: usize,
+    reserve_initrd();
 }
 
-impl Zone {
-    pub const fn new() -> Self {
-        const VAL: FreeArea = FreeArea::new();
-        Self {
-            free_areas: [VAL; MAX_FREE_AREA_ORDER],
-            free_pages: 0,
-            max_pages: 0,
-            mem_start: 0,
-            mem_size: 0,
+/// Init the next zone to manage the memory `[start, end)`. The bitmaps and the `Page` objects
+/// of the zone are placed from `start`.
+fn add_zone(ty: ZoneType, start: usize, end: usize) {
+    match unsafe { zones().add_zone(ty, start, end) } {
+        Ok(zone) => info!("Zone#{} {:?}: [{:#x}, {:#x}), {} page(s).", zone.idx(), ty, start, end,
+            zone.max_pages()),
+        Err(ZoneError::TooMany) => {
+            warn!("Too many memory zones, [{:#x}, {:#x}) ignored.", start, end)
         }
-    }
-
-    pub fn init(&mut self) {
-        for area in &mut self.free_areas {
-            area.free_list.init_empty();
+        Err(ZoneError::TooSmall) => {
+            warn!("Memory [{:#x}, {:#x}) is too small for a zone, ignored.", start, end)
         }
     }
 }
 
-const MAX_ZONE_COUNT: usize = 1;
-/// Memory zone list.
-static mut MEMORY_ZONES: [Zone; MAX_ZONE_COUNT] = [Zone::new(); MAX_ZONE_COUNT];
-/// `Page` object array base address.
-static mut PAGE_OBJ_BASE: usize = 0;
-// We will use ALLOC_START to mark the start of the actual
-// memory we can dish out.
-static mut ALLOC_START: usize = 0;
-// Track the max number than can be allocated.
-static mut ALLOC_PAGES: usize = 0;
-
+/// Get the zone list.
+fn zones() -> &'static mut Zones {
+    unsafe { &mut *addr_of_mut!(MEMORY_ZONES) }
+}
 
-/// Initialize the buddy allocator system.
-///
-/// **Note**: After this call, the heap base address **must not** be changed.
-pub fn init(mem_regions: &[(usize, usize)]) {
-    assert!(!mem_regions.is_empty(), "Memory regions is empty!");
-    if mem_regions.len() > 1 {
-        warn!("Physical memory address is not continuous.");
+/// Reserve the pages of the initrd, which are freed after the initramfs is unpacked.
+fn reserve_initrd() {
+    let (start, end) = match crate::init::initrd_range() {
+        Some(range) => range,
+        None => return,
+    };
+    let zones = zones().zones_mut();
+    // The bitmaps and the `Page` objects may have overwritten the initrd.
+    if zones.iter().any(|zone| start < zone.alloc_start() && end > zone.mem_start()) {
+        warn!("Initrd [{:#x}, {:#x}) overlaps the page allocator data, ignored.", start, end);
+        crate::init::drop_initrd();
+        return;
     }
 
-    unsafe {
-        let zone = &mut MEMORY_ZONES[0];
-        zone.init();
-
-        let &(mem_start, mem_size) = mem_regions.get_unchecked(0usize);
-        zone.mem_start = mem_start;
-        zone.mem_size = mem_size;
-
-        let mem_end = mem_start + mem_size;
-        const ALIGNMENT: usize = PAGE_SIZE << (MAX_FREE_AREA_ORDER - 1usize);
-        let mem_end = align_down(mem_end, get_order(ALIGNMENT));
-
-        let start = super::HEAP_BASE;
-        let alloc_min_addr = align_up(start, get_order(ALIGNMENT));
-        assert!(alloc_min_addr >= mem_start && alloc_min_addr < mem_end);
-        let max_alloc_pages = (mem_end - alloc_min_addr) / PAGE_SIZE;
-
-        // Init the free area bitmap.
-        // We alloc the bitmap with align of 8bytes.
-        let mut bitmap_start = align_up(start, get_order(size_of::<u64>()));
-        let mut bitmap_len = 0usize;
-        for i in 0..(MAX_FREE_AREA_ORDER - 1) {
-            bitmap_len += (max_alloc_pages >> (i + 1usize) + 7) / 8;
-        }
-        let page_start = align_up(bitmap_start + bitmap_len, get_order(32usize));
-        // Cast bitmap to u64 pointer and memset to zero.
-        let bitmap_ptr = bitmap_start as *mut u64;
-        bitmap_ptr.write_bytes(0, (page_start - bitmap_start) / size_of::<u64>());
-        // Init
-        for i in 0..(MAX_FREE_AREA_ORDER - 1) {
-            let free_area = zone.free_areas.get_unchecked_mut(i);
-            free_area.bitmap = bitmap_start as *mut u8;
-
-            bitmap_start += ((max_alloc_pages >> (i + 1usize)) + 7) / 8;
-        }
-
-        // Adjust the min alloc address
-        let max_alloc_large_pages = (mem_end - page_start) /
-            ((PAGE_SIZE + size_of::<Page>()) << (MAX_FREE_AREA_ORDER - 1usize));
-        let alloc_pages = max_alloc_large_pages << (MAX_FREE_AREA_ORDER - 1usize);
-        let page_end = page_start + size_of::<Page>() * alloc_pages;
-        let alloc_start = align_up(page_end, get_order(ALIGNMENT));
-
-        // Init `Page` objects.
-        let free_area = zone.free_areas.get_unchecked_mut(MAX_FREE_AREA_ORDER - 1usize);
-        let list_head = &mut free_area.free_list;
-        let mut prev_node = list_head as *mut List;
-        let page_base = page_start as *mut Page;
-        const PAGE_COUNT_LAST_AREA: usize = 1usize << (MAX_FREE_AREA_ORDER - 1usize);
-        for i in 0..max_alloc_large_pages {
-            // All `Page`obj to free_area[MAX_ORDER - 1].free_list.
-            let page = page_base.add(i * PAGE_COUNT_LAST_AREA);
-            // (*page).flags = 0;
-            let page_head = &mut (*page).head;
-            list::partial_append(&mut *prev_node, page_head);
-            prev_node = page_head as _;
+    let mut count = 0usize;
+    for addr in (align_down(start, PAGE_ORDER)..align_up(end, PAGE_ORDER)).step_by(PAGE_SIZE) {
+        if zones.iter_mut().any(|zone| zone.reserve(addr)) {
+            count += 1;
         }
-        list::partial_append(&mut *prev_node, list_head);
-
-        PAGE_OBJ_BASE = page_start;
-        ALLOC_START = alloc_start;
-        ALLOC_PAGES = alloc_pages;
-        zone.free_pages = alloc_pages;
-        zone.max_pages = alloc_pages;
     }
+    info!("Initrd [{:#x}, {:#x}) reserved, {} page(s).", start, end, count);
 }
 
 /// Allocate a single page and return a struct page.
@@ -502,142 +254,86 @@ pub fn free_pages(addr: usize, order: usize) {
     do_free_pages(page, order);
 }
 
-/// Get the **physical address** of a `Page` struct.
-pub fn page_to_address(page: *const Page) -> usize {
+/// Take one more reference of the allocated single page at the **physical address** `addr`,
+/// the page is marked [`PageFlag::Shared`].
+pub fn share_page(addr: usize) {
+    let page = address_to_page(addr);
+    debug_assert!(!page.is_null());
     unsafe {
-        // todo: debug assert instead of runtime check.
-        // core::intrinsics::unlikely()
-        if page.is_null() {
-            return 0;
+        if !(*page).is_flag_set(PageFlag::Shared) {
+            // The implicit reference of the single owner.
+            (*page).set_flag(PageFlag::Shared);
+            (*page).increase_ref();
         }
-
-        let index = page.offset_from(PAGE_OBJ_BASE as _) as usize;
-        ALLOC_START + index * PAGE_SIZE
+        (*page).increase_ref();
     }
 }
 
-/// Convert a **valid physical address** to a `Page` struct.
-pub fn address_to_page(addr: usize) -> *mut Page {
+/// Drop a reference of the single page at the **physical address** `addr`, the page is freed
+/// if it is the last reference.
+pub fn put_page(addr: usize) {
+    let page = address_to_page(addr);
+    debug_assert!(!page.is_null());
     unsafe {
-        // core::intrinsics::unlikely()
-        if addr <= ALLOC_START {
-            return null_mut();
+        if (*page).is_flag_set(PageFlag::Shared) && (*page).decrease_ref() != 0 {
+            return;
         }
-
-        let index = (addr - ALLOC_START) / PAGE_SIZE;
-        (PAGE_OBJ_BASE as *mut Page).add(index)
     }
+    do_free_pages(page, 0);
 }
 
-
-////////////////////// Inner Impl ///////////////////////////
-
-fn do_alloc_pages(_flags: GfpAllocFlag, order: usize) -> *mut Page {
-    // todo: flags support.
-    let size = 1usize << order;
-    for zone_idx in 0..MAX_ZONE_COUNT {
-        unsafe {
-            let zone = MEMORY_ZONES.get_unchecked_mut(zone_idx);
-            if size > zone.free_pages {
-                continue;
-            }
-
-            // Try alloc on zone
-            let page = alloc_page_on_zone(zone, order);
-            if !page.is_null() {
-                // Directly assign to clear the flags.
-                (*page).flags = zone_idx as u32;
-                // (*page).set_zone_idx(zone_idx);
-                // Reset the ref count.
-                (*page).ref_count.store(0, Ordering::Relaxed);
-                return page;
-            }
-        }
+/// Get the reference count of the single page at the **physical address** `addr`, which is 1 if
+/// the page is not shared.
+pub fn page_ref_count(addr: usize) -> u32 {
+    let page = address_to_page(addr);
+    debug_assert!(!page.is_null());
+    unsafe {
+        if (*page).is_flag_set(PageFlag::Shared) { (*page).ref_count() } else { 1 }
     }
-
-    null_mut()
-}
-
-#[inline(always)]
-fn bitmap_mark_used(bitmap: *mut u8, index: usize, order: usize) {
-    crate::util::bit::change_bit_array(bitmap, index >> (1usize + order));
 }
 
-unsafe fn alloc_page_on_zone(zone: &mut Zone, order: usize) -> *mut Page {
-    for current_order in order..MAX_FREE_AREA_ORDER {
-        let free_area = zone.free_areas.get_unchecked_mut(current_order);
-        if list::is_empty(&free_area.free_list) {
-            continue;
-        }
-
-        // list_entry
-        let page_head = free_area.free_list.next;
-        let page = crate::container_of_mut!(page_head, Page, head);
-        list::delete(&mut *page_head);
-        let index = page.offset_from(PAGE_OBJ_BASE as _) as usize;
-        if current_order != MAX_FREE_AREA_ORDER - 1usize {
-            bitmap_mark_used(free_area.bitmap, index, current_order);
-        }
-
-        zone.free_pages -= 1usize << order;
-        expand_areas(page, index, order, current_order, free_area as _);
-        return page;
+/// Get the **physical address** of a `Page` struct.
+pub fn page_to_address(page: *const Page) -> usize {
+    // todo: debug assert instead of runtime check.
+    // core::intrinsics::unlikely()
+    if page.is_null() {
+        return 0;
     }
 
-    null_mut()
+    unsafe { zones().page_to_address(page) }
 }
 
-unsafe fn expand_areas(page: *mut Page, index: usize, low: usize, mut high: usize, mut area: *mut FreeArea) {
-    let mut size = 1usize << high;
-    while low < high {
-        high -= 1usize;
-        area = area.sub(1);
-        size >>= 1usize;
-        let buddy = &mut (*page.add(size));
-        buddy.flags = 0;
-        list::head_append(&mut (*area).free_list, &mut buddy.head);
-        bitmap_mark_used((*area).bitmap, index + size, high);
-    }
+/// Convert a **valid physical address** to a `Page` struct. Returns null if the `addr` is not
+/// managed by the page allocator.
+pub fn address_to_page(addr: usize) -> *mut Page {
+    zones().address_to_page(addr)
 }
 
-fn do_free_pages(page: *mut Page, order: usize) {
-    assert!(order < MAX_FREE_AREA_ORDER && !page.is_null());
-    unsafe {
-        let zone_idx = (*page).get_zone_idx();
-        debug_assert!(zone_idx < MAX_ZONE_COUNT);
-
-        let zone = MEMORY_ZONES.get_unchecked_mut(zone_idx);
-        let area = zone.free_areas.get_unchecked_mut(order) as *mut FreeArea;
-        free_pages_bulk(zone, page, area, order);
-    }
+/// Get the count of the pages managed by the buddy allocator.
+pub fn nr_total_pages() -> usize {
+    zones().nr_total_pages()
 }
 
-unsafe fn free_pages_bulk(zone: &mut Zone, page: *mut Page, mut area: *mut FreeArea, order: usize) {
-    let mut mask = !0usize << order;
-    let base = PAGE_OBJ_BASE as *mut Page;
-    let mut page_idx = page.offset_from(base) as usize;
-    if (page_idx & !mask != 0) || (page_idx + 1usize << order > zone.max_pages) {
-        panic!("Free page invalid.");
-    }
-
-    let mut index = page_idx >> (1usize + order);
+/// Get the count of the free pages of the zones.
+pub fn nr_free_pages() -> usize {
+    zones().nr_free_pages()
+}
 
-    zone.free_pages += 1usize << order;
-    for _ in order..(MAX_FREE_AREA_ORDER - 1usize) {
-        if !crate::util::bit::test_and_change_bit_array((*area).bitmap, index) {
-            break;
-        }
 
-        // Previous bit in bitmap is 1, so the buddy block is free, then do merge.
-        let buddy = base.add(page_idx ^ (1usize << order));
-        list::delete(&mut (*buddy).head);
+////////////////////// Inner Impl ///////////////////////////
 
-        mask <<= 1usize;
-        area = area.add(1);
-        index >>= 1usize;
-        page_idx &= mask;
+fn do_alloc_pages(flags: GfpAllocFlag, order: usize) -> *mut Page {
+    let page = zones().alloc(flags, order);
+    // Reclaim the clean page cache under memory pressure, and retry once.
+    if page.is_null() && super::page_cache::shrink(1 << order) > 0 {
+        return zones().alloc(flags, order);
     }
-    list::head_append(&mut (*area).free_list, &mut (*base.add(page_idx)).head);
+    page
+}
+
+fn do_free_pages(page: *mut Page, order: usize) {
+    assert!(!page.is_null());
+    unsafe { zones().free(page, order) };
 }
 
 
@@ -646,31 +342,27 @@ unsafe fn free_pages_bulk(zone: &mut Zone, page: *mut Page, mut area: *mut FreeA
 /// Print all page allocations. Called from the M-mode or S-mode with identity PTE is set.
 /// This is mainly used for debugging.
 pub fn print_page_allocations() {
-    unsafe {
-        let zone = MEMORY_ZONES.get_unchecked(0);
-        let num_pages = zone.max_pages;
-
-        let heap_beg = super::HEAP_BASE;
-        let heap_end = zone.mem_start + zone.mem_size;
-
-        let beg = PAGE_OBJ_BASE as *const Page;
-        let end = beg.add(num_pages);
-        let alloc_beg = ALLOC_START;
-        let alloc_end = ALLOC_START + num_pages * PAGE_SIZE;
+    let zones = zones();
+    for zone in zones.zones() {
+        let ty = zones.zone_type(zone.idx());
+        let num_pages = zone.max_pages();
+        let beg = zone.page_base();
+        let end = unsafe { beg.add(num_pages) };
+        let alloc_beg = zone.alloc_start();
+        let alloc_end = zone.alloc_end();
 
         println_k!();
         println_k!(
-            "PAGE ALLOCATION TABLE\nMETA: {:p} -> {:p}\nHEAP: 0x{:x} -> 0x{:x}\nPHYS: \
-            0x{:x} -> 0x{:x}\nMEMORY BEGIN: {:#x}, SIZE: {:#x}",
-            beg, end, heap_beg, heap_end, alloc_beg, alloc_end, zone.mem_start, zone.mem_size
+            "PAGE ALLOCATION TABLE OF ZONE#{} ({:?})\nMETA: {:p} -> {:p}\nPHYS: 0x{:x} -> 0x{:x}\n\
+            MEMORY BEGIN: {:#x}, SIZE: {:#x}",
+            zone.idx(), ty, beg, end, alloc_beg, alloc_end, zone.mem_start(), zone.mem_size()
         );
         println_k!("~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~");
         let mut num = 0;
-        let mut order = 0usize;
-        for free_area in &zone.free_areas {
+        for order in 0..(PAGE_ALLOC_MAX_ORDER as usize) {
             print_k!("FreeArea[{}]: ", order);
 
-            let count = list::count(&free_area.free_list);
+            let count = zone.free_blocks(order);
             if count == 0 {
                 println_k!("<Empty>");
             } else {
@@ -678,7 +370,6 @@ pub fn print_page_allocations() {
             }
 
             num += count << order;
-            order += 1usize;
         }
 
         println_k!("~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~");
@@ -687,3 +378,35 @@ pub fn print_page_allocations() {
         println_k!();
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[kernel_test]
+    fn alloc_and_free_pages() {
+        let addr = alloc_pages(0, 2);
+        assert_ne!(addr, 0);
+        assert_eq!(addr % (PAGE_SIZE << 2), 0, "buddy block must be aligned to its size");
+        free_pages(addr, 2);
+
+        let page = alloc_zeroed_page(0);
+        assert_ne!(page, 0);
+        let words = unsafe { core::slice::from_raw_parts(page as *const u64, PAGE_SIZE / 8) };
+        assert!(words.iter().all(|w| *w == 0));
+        free_page(page);
+    }
+
+    #[kernel_test]
+    fn page_counts() {
+        let (total, free) = (nr_total_pages(), nr_free_pages());
+        assert!(free > 0 && free < total);
+        let addr = alloc_pages(0, 3);
+        assert_ne!(addr, 0);
+        assert_eq!(nr_free_pages(), free - 8);
+        free_pages(addr, 3);
+        assert_eq!(nr_free_pages(), free);
+        assert_eq!(nr_total_pages(), total);
+    }
+}
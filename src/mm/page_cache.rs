@@ -0,0 +1,773 @@
+//! Page cache of the file data and the block devices.
+//!
+//! A [`PageCache`] caches the content of one object, a file or a block device, in the pages of
+//! the buddy allocator ([`mm::page`]) indexed by the page index in the object. The object is a
+//! [`PageIo`]: it owns the cache and maps a page to the sectors of its block device. The file
+//! systems keep their metadata in the cache of the block device, so a file cache maps its pages
+//! by reading the block device cache.
+//!
+//! A cached page is described by its [`Page`] struct: the private area holds a `CachedPage`,
+//! which links the page in the global LRU list, and the custom flags hold the page state.
+//!
+//! - **Read**: a missing page is read with the following pages of the read-ahead window in one
+//!   plugged batch, so the block layer merges them into large requests. The window starts at
+//!   [`READ_AHEAD_MIN`] pages, and doubles up to [`READ_AHEAD_MAX`] while the misses are
+//!   sequential.
+//! - **Write**: a write allocates the blocks of the written bytes and dirties their sectors. The
+//!   dirty pages are written back by the `writeback` kernel thread once they are older than
+//!   [`DIRTY_EXPIRE_SECS`], or by [`PageCache::sync`] and [`sync_all`] (`fsync` and `sync`).
+//!   Only the blocks with dirty sectors are written, so a page of a block device cache holding a
+//!   metadata block never writes its stale copies of the neighbouring file blocks.
+//! - **Eviction**: [`shrink`] frees the least recently used clean pages. The page allocator
+//!   calls it when it runs out of memory.
+//!
+//! There is no sleeping in the kernel yet, so the cache lock is held with the interrupts disabled
+//! while its I/O is polled. The lock order is: the lock of a file cache, the lock of a block
+//! device cache, then the LRU lock or the dirty list lock. [`shrink`] only tries the cache locks.
+//!
+//! [`mm::page`]: crate::mm::page
+//! [`READ_AHEAD_MIN`]: vos_core::mm::page_cache::READ_AHEAD_MIN
+//! [`READ_AHEAD_MAX`]: vos_core::mm::page_cache::READ_AHEAD_MAX
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::ops::Range;
+use core::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+use vos_core::mm::page_cache::{clean_blocks, sector_mask, ReadAhead, SECTORS_PER_PAGE};
+use crate::arch::cpu;
+use crate::base::irq;
+use crate::base::sync::lock::{raw_spin_try_lock, raw_spin_unlock, SpinLockPure};
+use crate::block::request::{Bio, BlockOp};
+use crate::block::{BlockDev, SECTOR_SHIFT};
+use crate::errno::{E_IO, E_NO_MEM};
+use crate::mm::page::{self, Page};
+use crate::mm::{PAGE_ORDER, PAGE_SIZE};
+use crate::proc::kernel::build_kernel_thread;
+use crate::sched::ready_list_add_task;
+use crate::smp::current_cpu_info;
+use crate::util::list::{self, List};
+
+
+/// A dirty page is written back by the writeback thread after this time.
+pub const DIRTY_EXPIRE_SECS: usize = 30;
+/// Interval of the writeback thread.
+pub const WRITEBACK_INTERVAL_SECS: usize = 5;
+
+// The custom page flags are the dirty sector mask of the page: the bit `i` is set if the sector
+// `i` is newer than the device.
+sa::const_assert!(SECTORS_PER_PAGE <= 8);
+
+/// An object cached by a [`PageCache`].
+pub trait PageIo {
+    /// Get the cache of the object.
+    fn cache(&self) -> &PageCache;
+
+    /// Get the block device holding the data.
+    fn bdev(&self) -> &BlockDev;
+
+    /// Size of the object in bytes. The cache does not read beyond it.
+    fn size(&self) -> u64;
+
+    /// Size of the blocks that the pages are mapped by, a multiple of the sector size dividing
+    /// the page size.
+    fn block_size(&self) -> usize;
+
+    /// Set `blocks`, an entry per block of the page `index`, to the first sector of the block,
+    /// or `None` for a hole, which reads as zeros. The holes of the blocks overlapping the bytes
+    /// `alloc` of the page are allocated, nothing is allocated if it is empty.
+    fn map_page(&self, index: u64, alloc: Range<usize>, blocks: &mut [Option<u64>])
+        -> Result<(), i32>;
+}
+
+/// State of a cached page, in the private area of its [`Page`].
+#[repr(C)]
+struct CachedPage {
+    /// Entry of the LRU list.
+    lru: List,
+    cache: *const PageCache,
+    index: u64,
+    /// Time when the page was dirtied, in timer ticks.
+    dirtied: usize,
+}
+
+sa::const_assert!(core::mem::size_of::<CachedPage>() <= Page::get_private_size());
+
+#[inline(always)]
+fn cached_page(addr: usize) -> &'static mut CachedPage {
+    unsafe { &mut *(*page::address_to_page(addr)).cast_private::<CachedPage>() }
+}
+
+#[inline(always)]
+fn dirty_mask(addr: usize) -> u32 {
+    unsafe { (*page::address_to_page(addr)).read_custom_flags() }
+}
+
+#[inline(always)]
+fn is_dirty(addr: usize) -> bool {
+    dirty_mask(addr) != 0
+}
+
+/// Fields of a cache protected by its lock.
+struct PageCacheInner {
+    /// Physical addresses of the pages by the page index.
+    pages: BTreeMap<u64, usize>,
+    nr_dirty: usize,
+    ra: ReadAhead,
+    /// Error of a writeback, reported by the next [`PageCache::sync`].
+    wb_error: i32,
+}
+
+/// Cached pages of an object.
+pub struct PageCache {
+    io: Weak<dyn PageIo>,
+    lock: SpinLockPure,
+    inner: UnsafeCell<PageCacheInner>,
+    /// Entry of the dirty cache list, protected by the `DIRTY_LOCK`.
+    dirty: UnsafeCell<List>,
+}
+
+/// All cached pages, the least recently used first.
+static mut LRU: List = List::new();
+static LRU_LOCK: SpinLockPure = SpinLockPure::new();
+/// Caches with dirty pages.
+static mut DIRTY_CACHES: List = List::new();
+static DIRTY_LOCK: SpinLockPure = SpinLockPure::new();
+
+static NR_CACHED: AtomicUsize = AtomicUsize::new(0);
+static NR_DIRTY: AtomicUsize = AtomicUsize::new(0);
+
+/// Init the LRU and the dirty cache lists.
+pub fn init() {
+    unsafe {
+        (*core::ptr::addr_of_mut!(LRU)).init_empty();
+        (*core::ptr::addr_of_mut!(DIRTY_CACHES)).init_empty();
+    }
+}
+
+/// Count of the cached pages.
+pub fn cached_pages() -> usize {
+    NR_CACHED.load(Ordering::Relaxed)
+}
+
+/// Count of the dirty pages.
+pub fn dirty_pages() -> usize {
+    NR_DIRTY.load(Ordering::Relaxed)
+}
+
+/// Completion of a batch of bios.
+struct Batch {
+    pending: AtomicUsize,
+    error: AtomicI32,
+}
+
+impl Batch {
+    const fn new() -> Batch {
+        Batch { pending: AtomicUsize::new(0), error: AtomicI32::new(0) }
+    }
+
+    fn end_io(bio: Box<Bio>, result: Result<(), i32>) {
+        let batch = unsafe { &*(bio.private as *const Batch) };
+        if let Err(errno) = result {
+            batch.error.store(errno, Ordering::Relaxed);
+        }
+        batch.pending.fetch_sub(1, Ordering::Release);
+    }
+
+    /// Submit the bytes `range` of the page at `addr` to the `sector`.
+    fn submit(&self, bdev: &BlockDev, op: BlockOp, sector: u64, addr: usize, range: Range<usize>) {
+        self.pending.fetch_add(1, Ordering::Relaxed);
+        let private = self as *const Batch as *mut ();
+        let buf = (addr + range.start) as *mut u8;
+        bdev.submit_bio(Bio::new(op, sector, buf, range.len(), Batch::end_io, private));
+    }
+
+    /// Wait for the submitted bios by polling the device.
+    fn wait(&self, bdev: &BlockDev) -> Result<(), i32> {
+        while self.pending.load(Ordering::Acquire) != 0 {
+            bdev.poll();
+            core::hint::spin_loop();
+        }
+        match self.error.load(Ordering::Relaxed) {
+            0 => Ok(()),
+            errno => Err(errno),
+        }
+    }
+}
+
+/// Submit the bios of the mapped blocks of the page `index` at `addr`. The holes are zeroed for
+/// a read, and skipped for a write, like the blocks without dirty sectors.
+fn submit_page(io: &dyn PageIo, batch: &Batch, op: BlockOp, index: u64, addr: usize)
+    -> Result<(), i32> {
+    let block_size = io.block_size();
+    let mut blocks = [None; SECTORS_PER_PAGE];
+    let blocks = &mut blocks[..PAGE_SIZE / block_size];
+    io.map_page(index, 0..0, blocks)?;
+    if op == BlockOp::Write {
+        clean_blocks(dirty_mask(addr), block_size, blocks);
+    }
+
+    let sectors_per_block = (block_size >> SECTOR_SHIFT) as u64;
+    let mut i = 0;
+    while i < blocks.len() {
+        let Some(sector) = blocks[i] else {
+            if op == BlockOp::Read {
+                let hole = (addr + i * block_size) as *mut u8;
+                unsafe { hole.write_bytes(0, block_size) };
+            }
+            i += 1;
+            continue;
+        };
+        // Merge the following contiguous blocks.
+        let mut end = i + 1;
+        while end < blocks.len() &&
+            blocks[end] == Some(sector + (end - i) as u64 * sectors_per_block) {
+            end += 1;
+        }
+        batch.submit(io.bdev(), op, sector, addr, i * block_size..end * block_size);
+        i = end;
+    }
+    Ok(())
+}
+
+impl PageCache {
+    /// Create the cache of the object `io`, which owns the cache.
+    pub fn new(io: Weak<dyn PageIo>) -> PageCache {
+        PageCache {
+            io,
+            lock: SpinLockPure::new(),
+            inner: UnsafeCell::new(PageCacheInner {
+                pages: BTreeMap::new(),
+                nr_dirty: 0,
+                ra: ReadAhead::new(),
+                wb_error: 0,
+            }),
+            dirty: UnsafeCell::new(List::new()),
+        }
+    }
+
+    /// Call `f` with the object and the fields under the cache lock. Returns `Err` with `E_IO` if
+    /// the object is being dropped.
+    fn with_inner<F, R>(&self, f: F) -> Result<R, i32>
+        where F: FnOnce(&dyn PageIo, &mut PageCacheInner) -> Result<R, i32> {
+        let io = self.io.upgrade().ok_or(E_IO)?;
+        let _guard = self.lock.lock_guard_irq_save();
+        f(&*io, unsafe { &mut *self.inner.get() })
+    }
+
+    /// Count of the cached pages.
+    pub fn nr_pages(&self) -> usize {
+        let _guard = self.lock.lock_guard_irq_save();
+        unsafe { (*self.inner.get()).pages.len() }
+    }
+
+    /// Allocate a page and add it as the page `index`. Returns 0 if there is no memory.
+    fn add_page(&self, inner: &mut PageCacheInner, index: u64) -> usize {
+        let addr = page::alloc_page(0);
+        if addr == 0 {
+            return 0;
+        }
+        unsafe { (*page::address_to_page(addr)).replace_custom_flags(0) };
+        let cached = cached_page(addr);
+        cached.cache = self;
+        cached.index = index;
+        cached.dirtied = 0;
+        inner.pages.insert(index, addr);
+        {
+            let _guard = LRU_LOCK.lock_guard_irq_save();
+            list::tail_append(unsafe { &mut *core::ptr::addr_of_mut!(LRU) }, &mut cached.lru);
+        }
+        NR_CACHED.fetch_add(1, Ordering::Relaxed);
+        addr
+    }
+
+    /// Remove and free the page `index`, the dirty data is dropped.
+    fn remove_page(&self, inner: &mut PageCacheInner, index: u64) {
+        let Some(addr) = inner.pages.remove(&index) else {
+            return;
+        };
+        self.clear_dirty(inner, addr, u32::MAX);
+        {
+            let _guard = LRU_LOCK.lock_guard_irq_save();
+            list::delete(&mut cached_page(addr).lru);
+        }
+        NR_CACHED.fetch_sub(1, Ordering::Relaxed);
+        page::free_page(addr);
+    }
+
+    /// Move the page to the tail of the LRU list.
+    fn touch_page(addr: usize) {
+        let _guard = LRU_LOCK.lock_guard_irq_save();
+        let lru = &mut cached_page(addr).lru;
+        list::delete(lru);
+        list::tail_append(unsafe { &mut *core::ptr::addr_of_mut!(LRU) }, lru);
+    }
+
+    /// Dirty the sectors `mask` of the page.
+    fn set_dirty(&self, inner: &mut PageCacheInner, addr: usize, mask: u32) {
+        let was_dirty = is_dirty(addr);
+        unsafe { (*page::address_to_page(addr)).set_custom_flags(mask) };
+        if was_dirty || mask == 0 {
+            return;
+        }
+        cached_page(addr).dirtied = cpu::read_time();
+        NR_DIRTY.fetch_add(1, Ordering::Relaxed);
+        inner.nr_dirty += 1;
+        if inner.nr_dirty == 1 {
+            let _guard = DIRTY_LOCK.lock_guard_irq_save();
+            let head = unsafe { &mut *core::ptr::addr_of_mut!(DIRTY_CACHES) };
+            list::tail_append(head, unsafe { &mut *self.dirty.get() });
+        }
+    }
+
+    /// Clean the sectors `mask` of the page.
+    fn clear_dirty(&self, inner: &mut PageCacheInner, addr: usize, mask: u32) {
+        if !is_dirty(addr) {
+            return;
+        }
+        unsafe { (*page::address_to_page(addr)).clear_custom_flags(mask) };
+        if is_dirty(addr) {
+            return;
+        }
+        NR_DIRTY.fetch_sub(1, Ordering::Relaxed);
+        inner.nr_dirty -= 1;
+        if inner.nr_dirty == 0 {
+            let _guard = DIRTY_LOCK.lock_guard_irq_save();
+            list::delete(unsafe { &mut *self.dirty.get() });
+        }
+    }
+
+    /// Read the new pages `(index, addr)` in one batch. The pages are removed if it fails.
+    fn read_pages(&self, io: &dyn PageIo, inner: &mut PageCacheInner, pages: &[(u64, usize)])
+        -> Result<(), i32> {
+        let bdev = io.bdev();
+        let batch = Batch::new();
+        bdev.plug();
+        let submitted = pages.iter().try_for_each(|&(index, addr)| {
+            submit_page(io, &batch, BlockOp::Read, index, addr)
+        });
+        bdev.unplug();
+        let result = batch.wait(bdev).and(submitted);
+        if result.is_err() {
+            for &(index, _) in pages {
+                self.remove_page(inner, index);
+            }
+        }
+        result
+    }
+
+    /// Find the page `index`, or read it with the read-ahead window. Returns the address.
+    fn find_or_read(&self, io: &dyn PageIo, inner: &mut PageCacheInner, index: u64)
+        -> Result<usize, i32> {
+        if let Some(&addr) = inner.pages.get(&index) {
+            Self::touch_page(addr);
+            return Ok(addr);
+        }
+
+        let nr_pages = (io.size() + PAGE_SIZE as u64 - 1) >> PAGE_ORDER;
+        let window = inner.ra.window(index, nr_pages);
+        let mut pages = Vec::with_capacity((window.end - index) as usize);
+        for i in window {
+            if inner.pages.contains_key(&i) {
+                break;
+            }
+            let addr = self.add_page(inner, i);
+            if addr == 0 {
+                break;
+            }
+            pages.push((i, addr));
+        }
+        if pages.is_empty() {
+            return Err(E_NO_MEM);
+        }
+        inner.ra.update(index, pages.len() as u64);
+        self.read_pages(io, inner, &pages)?;
+        Ok(pages[0].1)
+    }
+
+    /// Read the bytes at `offset` into `buf`. Returns the count of bytes read, which is less than
+    /// `buf.len()` at the end of the object.
+    pub fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize, i32> {
+        self.with_inner(|io, inner| {
+            let size = io.size();
+            if offset >= size {
+                return Ok(0);
+            }
+            let len = buf.len().min((size - offset) as usize);
+            let mut done = 0;
+            while done < len {
+                let pos = offset + done as u64;
+                let in_page = pos as usize % PAGE_SIZE;
+                let count = (PAGE_SIZE - in_page).min(len - done);
+                let addr = match self.find_or_read(io, inner, pos >> PAGE_ORDER) {
+                    Ok(addr) => addr,
+                    Err(_) if done > 0 => break,
+                    Err(errno) => return Err(errno),
+                };
+                let src = unsafe {
+                    core::slice::from_raw_parts((addr + in_page) as *const u8, count)
+                };
+                buf[done..done + count].copy_from_slice(src);
+                done += count;
+            }
+            Ok(done)
+        })
+    }
+
+    /// Write `buf` at `offset` into the cache, the blocks are allocated and the pages are
+    /// dirtied. The size of the object is not changed, the owner updates it. Returns the count of
+    /// bytes written, which is less than `buf.len()` if the device is full.
+    pub fn write(&self, offset: u64, buf: &[u8]) -> Result<usize, i32> {
+        self.with_inner(|io, inner| {
+            let size = io.size();
+            let mut done = 0;
+            while done < buf.len() {
+                let pos = offset + done as u64;
+                let index = pos >> PAGE_ORDER;
+                let in_page = pos as usize % PAGE_SIZE;
+                let count = (PAGE_SIZE - in_page).min(buf.len() - done);
+                let result = self.prepare_write(io, inner, index, in_page..in_page + count, size);
+                let addr = match result {
+                    Ok(addr) => addr,
+                    Err(_) if done > 0 => break,
+                    Err(errno) => return Err(errno),
+                };
+                let dst = unsafe {
+                    core::slice::from_raw_parts_mut((addr + in_page) as *mut u8, count)
+                };
+                dst.copy_from_slice(&buf[done..done + count]);
+                self.set_dirty(inner, addr, sector_mask(in_page..in_page + count));
+                done += count;
+            }
+            Ok(done)
+        })
+    }
+
+    /// Get the page `index` to write the bytes `range` of it, and allocate their blocks. A new
+    /// page is read first unless it is fully overwritten or beyond the end `size`.
+    fn prepare_write(&self, io: &dyn PageIo, inner: &mut PageCacheInner, index: u64,
+                     range: Range<usize>, size: u64) -> Result<usize, i32> {
+        let addr = match inner.pages.get(&index) {
+            Some(&addr) => {
+                Self::touch_page(addr);
+                addr
+            }
+            None => {
+                let addr = self.add_page(inner, index);
+                if addr == 0 {
+                    return Err(E_NO_MEM);
+                }
+                if range.len() == PAGE_SIZE || index << PAGE_ORDER >= size {
+                    unsafe { (addr as *mut u8).write_bytes(0, PAGE_SIZE) };
+                } else {
+                    self.read_pages(io, inner, &[(index, addr)])?;
+                }
+                addr
+            }
+        };
+        let mut blocks = [None; SECTORS_PER_PAGE];
+        io.map_page(index, range, &mut blocks[..PAGE_SIZE / io.block_size()])?;
+        Ok(addr)
+    }
+
+    /// Write back the dirty pages dirtied not later than `before`, in one batch.
+    fn writeback(&self, io: &dyn PageIo, inner: &mut PageCacheInner, before: usize)
+        -> Result<(), i32> {
+        if inner.nr_dirty == 0 {
+            return Ok(());
+        }
+        let pages: Vec<(u64, usize)> = inner.pages.iter()
+            .filter(|(_, &addr)| is_dirty(addr) && cached_page(addr).dirtied <= before)
+            .map(|(&index, &addr)| (index, addr))
+            .collect();
+
+        let bdev = io.bdev();
+        let batch = Batch::new();
+        bdev.plug();
+        let submitted = pages.iter().try_for_each(|&(index, addr)| {
+            submit_page(io, &batch, BlockOp::Write, index, addr)
+        });
+        bdev.unplug();
+        let result = batch.wait(bdev).and(submitted);
+        // The failed pages are not retried, the error is reported by the next sync.
+        for &(_, addr) in &pages {
+            self.clear_dirty(inner, addr, u32::MAX);
+        }
+        if let Err(errno) = result {
+            warn!("{}: writeback of {} page(s) failed, errno = {}", bdev.name(), pages.len(),
+                errno);
+            inner.wb_error = errno;
+        }
+        result
+    }
+
+    /// Write back all dirty pages and flush the device. Returns the error of the earlier
+    /// writebacks if any.
+    pub fn sync(&self) -> Result<(), i32> {
+        self.with_inner(|io, inner| {
+            let result = self.writeback(io, inner, usize::MAX);
+            let error = core::mem::take(&mut inner.wb_error);
+            result?;
+            io.bdev().flush()?;
+            if error != 0 { Err(error) } else { Ok(()) }
+        })
+    }
+
+    /// Drop the pages after `size`, the dirty data is dropped. The rest of the last page is
+    /// zeroed. Called when the object is truncated, before its blocks are freed.
+    pub fn truncate(&self, size: u64) {
+        let _guard = self.lock.lock_guard_irq_save();
+        let inner = unsafe { &mut *self.inner.get() };
+        let first = (size + PAGE_SIZE as u64 - 1) / PAGE_SIZE as u64;
+        let removed: Vec<u64> = inner.pages.range(first..).map(|(&index, _)| index).collect();
+        for index in removed {
+            self.remove_page(inner, index);
+        }
+        let in_page = size as usize % PAGE_SIZE;
+        if in_page != 0 {
+            if let Some(&addr) = inner.pages.get(&(size >> PAGE_ORDER)) {
+                unsafe { ((addr + in_page) as *mut u8).write_bytes(0, PAGE_SIZE - in_page) };
+                self.set_dirty(inner, addr, sector_mask(in_page..PAGE_SIZE));
+            }
+        }
+        inner.ra.truncate(first);
+    }
+
+    /// Clean the sectors of the `len` bytes at `offset`, sector aligned, so their dirty data is
+    /// never written. Called when a block cached as metadata is freed, before it can be reused by
+    /// a file.
+    pub fn discard(&self, offset: u64, len: u64) {
+        let _guard = self.lock.lock_guard_irq_save();
+        let inner = unsafe { &mut *self.inner.get() };
+        let end = offset + len;
+        let mut pos = offset;
+        while pos < end {
+            let in_page = pos as usize % PAGE_SIZE;
+            let count = (PAGE_SIZE - in_page).min((end - pos) as usize);
+            if let Some(&addr) = inner.pages.get(&(pos >> PAGE_ORDER)) {
+                self.clear_dirty(inner, addr, sector_mask(in_page..in_page + count));
+            }
+            pos += count as u64;
+        }
+    }
+
+    /// Drop the clean pages, e.g. when the file system on a block device is unmounted.
+    pub fn invalidate(&self) {
+        let _guard = self.lock.lock_guard_irq_save();
+        let inner = unsafe { &mut *self.inner.get() };
+        let clean: Vec<u64> = inner.pages.iter()
+            .filter(|(_, &addr)| !is_dirty(addr))
+            .map(|(&index, _)| index)
+            .collect();
+        for index in clean {
+            self.remove_page(inner, index);
+        }
+    }
+}
+
+impl Drop for PageCache {
+    fn drop(&mut self) {
+        let inner = unsafe { &mut *self.inner.get() };
+        if inner.nr_dirty > 0 {
+            warn!("Page cache dropped with {} dirty page(s).", inner.nr_dirty);
+        }
+        let indexes: Vec<u64> = inner.pages.keys().copied().collect();
+        for index in indexes {
+            self.remove_page(inner, index);
+        }
+    }
+}
+
+/// Get the objects of the caches with dirty pages.
+fn dirty_caches() -> Vec<Arc<dyn PageIo>> {
+    let _guard = DIRTY_LOCK.lock_guard_irq_save();
+    let mut caches = Vec::new();
+    list::for_each(unsafe { &mut *core::ptr::addr_of_mut!(DIRTY_CACHES) }, |cur| {
+        let cache = unsafe { &*container_of!(cur as *const UnsafeCell<List>, PageCache, dirty) };
+        // Skip the objects being dropped.
+        if let Some(io) = cache.io.upgrade() {
+            caches.push(io);
+        }
+        true
+    });
+    caches
+}
+
+/// Write back all dirty pages and flush the devices. Returns the first error.
+pub fn sync_all() -> Result<(), i32> {
+    dirty_caches().iter().fold(Ok(()), |result, io| result.and(io.cache().sync()))
+}
+
+/// Write back the pages dirtied not later than `before`.
+fn writeback_expired(before: usize) {
+    for io in dirty_caches() {
+        let cache = io.cache();
+        // The error is kept for the next sync.
+        let _ = cache.with_inner(|io, inner| cache.writeback(io, inner, before));
+    }
+}
+
+/// Free at most `nr` least recently used clean pages. Returns the count of pages freed.
+///
+/// It only tries the locks, so it can be called anywhere under memory pressure, e.g. by the page
+/// allocator. The pages of the caches in use are skipped.
+pub fn shrink(nr: usize) -> usize {
+    let flags = irq::local_irq_save();
+    if !raw_spin_try_lock(&LRU_LOCK) {
+        irq::local_irq_restore(flags);
+        return 0;
+    }
+
+    let mut freed = 0;
+    list::for_each(unsafe { &mut *core::ptr::addr_of_mut!(LRU) }, |cur| {
+        if freed >= nr {
+            return false;
+        }
+        let cached = unsafe { &mut *container_of_mut!(cur, CachedPage, lru) };
+        let addr = page::page_to_address(Page::from_private(cached as *mut CachedPage));
+        let cache = unsafe { &*cached.cache };
+        if !raw_spin_try_lock(&cache.lock) {
+            return true;
+        }
+        if is_dirty(addr) {
+            raw_spin_unlock(&cache.lock);
+            return true;
+        }
+        unsafe { (*cache.inner.get()).pages.remove(&cached.index) };
+        list::delete(&mut cached.lru);
+        raw_spin_unlock(&cache.lock);
+        NR_CACHED.fetch_sub(1, Ordering::Relaxed);
+        page::free_page(addr);
+        freed += 1;
+        true
+    });
+
+    raw_spin_unlock(&LRU_LOCK);
+    irq::local_irq_restore(flags);
+    freed
+}
+
+/// Start the kernel thread writing back the expired dirty pages periodically.
+pub fn start_writeback_thread() {
+    let task = build_kernel_thread(writeback_thread, core::ptr::null_mut()).build();
+    ready_list_add_task(task);
+}
+
+extern "C"
+fn writeback_thread(_data: *mut ()) -> usize {
+    let freq = current_cpu_info().get_timebase_freq();
+    let interval = freq * WRITEBACK_INTERVAL_SECS;
+    let expire = freq * DIRTY_EXPIRE_SECS;
+    info!("[Writeback] Start, interval {}s, expire {}s.", WRITEBACK_INTERVAL_SECS,
+        DIRTY_EXPIRE_SECS);
+
+    let mut time = cpu::read_time();
+    loop {
+        let cur = cpu::read_time();
+        if cur >= time + interval {
+            writeback_expired(cur.saturating_sub(expire));
+            time = cur;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vos_core::mm::page_cache::READ_AHEAD_MIN;
+    use crate::block::ram::RamDisk;
+    use crate::block::SECTOR_SIZE;
+    use super::*;
+
+    /// Create a RAM disk of `nr_pages` pages, each sector filled with its number, and its block
+    /// device, which is not registered.
+    fn ram_bdev(nr_pages: usize) -> (Arc<RamDisk>, Arc<BlockDev>) {
+        let data = (0..nr_pages * PAGE_SIZE).map(|pos| (pos >> SECTOR_SHIFT) as u8).collect();
+        let ram = RamDisk::new(0, data);
+        let bdev = ram.bdev();
+        (ram, bdev)
+    }
+
+    fn read_byte(cache: &PageCache, offset: u64) -> u8 {
+        let mut byte = [0u8];
+        assert_eq!(cache.read(offset, &mut byte), Ok(1));
+        byte[0]
+    }
+
+    #[kernel_test]
+    fn read_ahead_and_shrink() {
+        let (ram, bdev) = ram_bdev(64);
+        let cache = bdev.cache();
+        let page = PAGE_SIZE as u64;
+        assert_eq!(read_byte(cache, 3 * SECTOR_SIZE as u64), 3);
+        assert_eq!(cache.nr_pages(), READ_AHEAD_MIN as usize);
+        // The sequential miss doubles the window, a random one starts over.
+        assert_eq!(read_byte(cache, 4 * page), (4 * page >> SECTOR_SHIFT) as u8);
+        assert_eq!(cache.nr_pages(), 12);
+        read_byte(cache, 40 * page);
+        assert_eq!(cache.nr_pages(), 16);
+        // The window stops at the end of the device.
+        read_byte(cache, 62 * page);
+        assert_eq!(cache.nr_pages(), 18);
+        assert!(cached_pages() >= 18);
+
+        // The dirty page is kept, the clean ones are freed and read again from the disk.
+        assert_eq!(cache.write(page + 1, &[0xaa]), Ok(1));
+        let before = cached_pages();
+        assert!(shrink(usize::MAX) >= 17);
+        assert_eq!(cache.nr_pages(), 1);
+        assert!(cached_pages() <= before - 17);
+        ram.sector(0)[0] = 0x55;
+        assert_eq!(read_byte(cache, 0), 0x55);
+        assert_eq!(read_byte(cache, page + 1), 0xaa);
+        assert_eq!(ram.written.load(Ordering::Relaxed), 0);
+
+        assert_eq!(cache.sync(), Ok(()));
+        assert_eq!(ram.sector(page >> SECTOR_SHIFT)[1], 0xaa);
+        cache.invalidate();
+        assert_eq!(cache.nr_pages(), 0);
+    }
+
+    #[kernel_test]
+    fn writeback_dirty_sectors() {
+        let (ram, bdev) = ram_bdev(4);
+        let cache = bdev.cache();
+        let dirty = dirty_pages();
+        // Only the sector 1 of the page 0 is dirty.
+        assert_eq!(cache.write(600, &[0xaa; 100]), Ok(100));
+        assert_eq!(dirty_pages(), dirty + 1);
+        // The neighbours change behind the cache, e.g. the blocks of a file on the device.
+        ram.sector(0).fill(0x55);
+        ram.sector(2).fill(0x55);
+
+        // The page is not expired yet.
+        writeback_expired(0);
+        assert_eq!(ram.written.load(Ordering::Relaxed), 0);
+        assert_eq!(dirty_pages(), dirty + 1);
+        writeback_expired(usize::MAX);
+        assert_eq!(ram.written.load(Ordering::Relaxed), 1);
+        assert_eq!(dirty_pages(), dirty);
+        let sector = ram.sector(1);
+        assert!(sector[..88].iter().all(|&byte| byte == 1));
+        assert!(sector[88..188].iter().all(|&byte| byte == 0xaa));
+        assert!(sector[188..].iter().all(|&byte| byte == 1));
+        assert!(ram.sector(0).iter().chain(ram.sector(2).iter()).all(|&byte| byte == 0x55));
+
+        // A write over 2 sectors writes both, a discarded sector is never written.
+        ram.written.store(0, Ordering::Relaxed);
+        let offset = PAGE_SIZE as u64 + 256;
+        assert_eq!(cache.write(offset, &[0xbb; SECTOR_SIZE]), Ok(SECTOR_SIZE));
+        assert_eq!(cache.sync(), Ok(()));
+        assert_eq!(ram.written.load(Ordering::Relaxed), 2);
+        assert_eq!(cache.write(offset, &[0xcc; SECTOR_SIZE]), Ok(SECTOR_SIZE));
+        cache.discard(PAGE_SIZE as u64, SECTOR_SIZE as u64);
+        assert_eq!(cache.sync(), Ok(()));
+        assert_eq!(ram.written.load(Ordering::Relaxed), 3);
+        let first = (PAGE_SIZE >> SECTOR_SHIFT) as u64;
+        assert_eq!(ram.sector(first)[255..257], [first as u8, 0xbb]);
+        assert_eq!(ram.sector(first + 1)[..2], [0xcc, 0xcc]);
+        assert_eq!(dirty_pages(), dirty);
+    }
+}
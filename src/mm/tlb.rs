@@ -0,0 +1,141 @@
+//! TLB maintenance of the user address spaces.
+//!
+//! A hart caches the translations of an address space tagged by its ASID (see [`asid`]), even
+//! after it switches away, so a change of the user mappings must be flushed on all harts which
+//! have run the address space. The local TLB is flushed by page for small ranges; the other
+//! harts are asked to flush the whole ASID by the [`IpiMessage::TlbFlush`] IPI, and the sender
+//! waits for them to finish before the unmapped pages can be freed.
+//!
+//! Each hart has a flush request slot: the senders merge their requests into the slot of the
+//! target hart, then take a ticket from its `req` counter. The target handles the request and
+//! updates its `done` counter, a sender waits until the `done` reaches its ticket. The traps run
+//! with the interrupts disabled, so a waiting hart also handles its own requests to avoid the
+//! deadlock with a hart which is waiting for it.
+//!
+//! [`asid`]: crate::mm::asid
+//! [`IpiMessage::TlbFlush`]: crate::smp::ipi::IpiMessage::TlbFlush
+
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::arch::cpu;
+use crate::mm::asid::asid_enabled;
+use crate::mm::PAGE_SIZE;
+use crate::smp::{current_cpu_info, get_cpu_count, get_cpu_info_by_cpuid, send_ipi_many, IpiMessage,
+                 PerCpuPtr};
+
+
+/// A range larger than this number of pages flushes the whole ASID.
+const FLUSH_PAGE_LIMIT: usize = 64;
+/// No flush requested.
+const FLUSH_NONE: usize = 0;
+/// Flush the whole TLB, other request values are `asid + 1`.
+const FLUSH_ALL: usize = usize::MAX;
+
+/// Flush request slot of a hart.
+struct TlbFlushCpu {
+    flush: AtomicUsize,
+    req: AtomicUsize,
+    done: AtomicUsize,
+}
+
+static mut TLB_CPUS: PerCpuPtr<TlbFlushCpu> = PerCpuPtr::null();
+
+/// Init the per-hart flush request slots. Called on the boot hart after the `kmalloc` is
+/// available.
+pub(super) fn init() {
+    assert!(get_cpu_count() <= usize::BITS as usize, "Too many CPUs for the TLB cpu mask.");
+    unsafe {
+        TLB_CPUS.init();
+        for cpu in TLB_CPUS.as_array_mut() {
+            (cpu as *mut TlbFlushCpu).write(TlbFlushCpu {
+                flush: AtomicUsize::new(FLUSH_NONE),
+                req: AtomicUsize::new(0),
+                done: AtomicUsize::new(0),
+            });
+        }
+    }
+}
+
+/// Flush the TLB entries of the `asid` on this hart.
+#[inline]
+pub fn local_flush_asid(asid: usize) {
+    if asid_enabled() {
+        cpu::satp_fense_asid(asid);
+    } else {
+        cpu::sfence_vma_all();
+    }
+}
+
+/// Flush the TLB entries of the user range `[start, end)` of the `asid` on this hart.
+pub fn local_flush_range(asid: usize, start: usize, end: usize) {
+    if end - start > FLUSH_PAGE_LIMIT * PAGE_SIZE {
+        return local_flush_asid(asid);
+    }
+
+    let mut addr = start & !(PAGE_SIZE - 1);
+    while addr < end {
+        cpu::satp_fense(addr, asid);
+        addr += PAGE_SIZE;
+    }
+}
+
+/// Flush the user range `[start, end)` of the `asid` on all harts in the `cpus` mask: the range
+/// is flushed on this hart, and the other online harts flush the whole `asid`. Returns after
+/// all harts have finished. Must be called with the interrupts disabled.
+pub fn flush_tlb_range(cpus: usize, asid: usize, start: usize, end: usize) {
+    let self_id = current_cpu_info().get_cpu_id();
+    if cpus & (1 << self_id) != 0 {
+        local_flush_range(asid, start, end);
+    }
+
+    let targets = cpus & !(1 << self_id);
+    if targets == 0 {
+        return;
+    }
+    let slots = unsafe { TLB_CPUS.as_array_mut() };
+    let request = if asid_enabled() { asid + 1 } else { FLUSH_ALL };
+    let mut posted = 0usize;
+    for (id, slot) in slots.iter().enumerate() {
+        if targets & (1 << id) == 0 || !get_cpu_info_by_cpuid(id).is_online() {
+            continue;
+        }
+        // Different ASIDs requested before the target handles them escalate to a full flush.
+        let _ = slot.flush.fetch_update(Ordering::AcqRel, Ordering::Acquire, |old| {
+            Some(if old == FLUSH_NONE || old == request { request } else { FLUSH_ALL })
+        });
+        slot.req.fetch_add(1, Ordering::AcqRel);
+        posted |= 1 << id;
+    }
+    if posted == 0 {
+        return;
+    }
+    send_ipi_many(posted, IpiMessage::TlbFlush);
+
+    for (id, slot) in slots.iter().enumerate() {
+        if posted & (1 << id) == 0 {
+            continue;
+        }
+        // A later ticket is done only after our merged request is handled.
+        let ticket = slot.req.load(Ordering::Acquire);
+        while slot.done.load(Ordering::Acquire) < ticket && get_cpu_info_by_cpuid(id).is_online() {
+            handle_flush_request();
+            spin_loop();
+        }
+    }
+}
+
+/// Handle the flush requests to this hart. Called from the IPI handler.
+pub(crate) fn handle_flush_request() {
+    let slot = unsafe { TLB_CPUS.get_ref_raw() };
+    let req = slot.req.load(Ordering::Acquire);
+    if slot.done.load(Ordering::Acquire) >= req {
+        return;
+    }
+
+    match slot.flush.swap(FLUSH_NONE, Ordering::AcqRel) {
+        FLUSH_NONE => {}
+        FLUSH_ALL => cpu::sfence_vma_all(),
+        request => cpu::satp_fense_asid(request - 1),
+    }
+    slot.done.fetch_max(req, Ordering::AcqRel);
+}
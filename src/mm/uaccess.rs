@@ -0,0 +1,149 @@
+//! Access the user memory from the kernel.
+//!
+//! The trap handler runs on the per-hart stack and saves the registers into the trap frame of
+//! the running thread, so a nested trap caused by the kernel touching a bad user pointer can not
+//! be recovered. Instead of dereferencing the user pointers, the helpers here translate the user
+//! addresses page by page with the process page table, check the `User` and `Read`/`Write` bits,
+//! and copy through the identity map of the physical pages. A page which is not populated yet (or
+//! a copy-on-write page) is handled like the page fault handler does. A bad user pointer just
+//! makes the copy fail with `E_FAULT`.
+
+use core::mem::{size_of, MaybeUninit};
+use crate::errno::{E_FAULT, E_NAME_TOO_LONG};
+use crate::mm::PAGE_SIZE;
+use crate::mm::address_space::AddressSpace;
+use crate::mm::mmu::EntryBits;
+use crate::mm::vma::FaultAccess;
+
+
+/// Walk the user range `[addr, addr + len)` page by page. `handle` gets the physical address and
+/// the length of each chunk, and the offset of the chunk in the range.
+fn walk_user<F>(aspace: &mut AddressSpace, addr: usize, len: usize, access: FaultAccess,
+                mut handle: F) -> Result<(), i32> where F: FnMut(usize, usize, usize) {
+    if !AddressSpace::is_user_range(addr, len) {
+        return Err(E_FAULT);
+    }
+
+    let required = access.required_bits() | EntryBits::User.val();
+    let mut done = 0;
+    while done < len {
+        let cur = addr + done;
+        let p_addr = match aspace.lookup(cur) {
+            Some((p_addr, flags)) if flags & required == required => p_addr,
+            // Not populated yet or a copy-on-write page.
+            _ => {
+                aspace.handle_page_fault(cur, access)?;
+                aspace.lookup(cur).ok_or(E_FAULT)?.0
+            }
+        };
+
+        let offset = cur & (PAGE_SIZE - 1);
+        let chunk = (PAGE_SIZE - offset).min(len - done);
+        handle(p_addr + offset, chunk, done);
+        done += chunk;
+    }
+
+    Ok(())
+}
+
+/// Copy `dst.len()` bytes from the user address `src` to the kernel buffer `dst`.
+pub fn copy_from_user(aspace: &mut AddressSpace, dst: &mut [u8], src: usize) -> Result<(), i32> {
+    walk_user(aspace, src, dst.len(), FaultAccess::Read, |p_addr, len, off| unsafe {
+        core::ptr::copy_nonoverlapping(p_addr as *const u8, dst.as_mut_ptr().add(off), len);
+    })
+}
+
+/// Copy the kernel buffer `src` to the user address `dst`.
+pub fn copy_to_user(aspace: &mut AddressSpace, dst: usize, src: &[u8]) -> Result<(), i32> {
+    walk_user(aspace, dst, src.len(), FaultAccess::Write, |p_addr, len, off| unsafe {
+        core::ptr::copy_nonoverlapping(src.as_ptr().add(off), p_addr as *mut u8, len);
+    })
+}
+
+/// Copy a NUL-terminated string from the user address `src` to `dst`, the NUL is copied too.
+/// Returns the length of the string without the NUL, or `Err` with `E_NAME_TOO_LONG` if no NUL
+/// is found in `dst.len()` bytes.
+///
+/// The string is read page by page, so the pages after the NUL are never touched.
+pub fn copy_string_from_user(aspace: &mut AddressSpace, dst: &mut [u8], src: usize)
+    -> Result<usize, i32> {
+    let mut done = 0;
+    while done < dst.len() {
+        let cur = src.wrapping_add(done);
+        let chunk = (PAGE_SIZE - (cur & (PAGE_SIZE - 1))).min(dst.len() - done);
+        copy_from_user(aspace, &mut dst[done..done + chunk], cur)?;
+        if let Some(pos) = dst[done..done + chunk].iter().position(|&c| c == 0) {
+            return Ok(done + pos);
+        }
+        done += chunk;
+    }
+
+    Err(E_NAME_TOO_LONG)
+}
+
+/// Read a plain value of type `T` from the user address `src`.
+///
+/// `T` must be valid for any bit pattern, such as the integers and the `#[repr(C)]` structs of
+/// integers.
+pub fn read_user<T: Copy>(aspace: &mut AddressSpace, src: usize) -> Result<T, i32> {
+    let mut val = MaybeUninit::<T>::uninit();
+    let buf = unsafe {
+        core::slice::from_raw_parts_mut(val.as_mut_ptr() as *mut u8, size_of::<T>())
+    };
+    copy_from_user(aspace, buf, src)?;
+    Ok(unsafe { val.assume_init() })
+}
+
+/// Write a plain value of type `T` to the user address `dst`.
+pub fn write_user<T: Copy>(aspace: &mut AddressSpace, dst: usize, val: &T) -> Result<(), i32> {
+    let buf = unsafe {
+        core::slice::from_raw_parts(val as *const T as *const u8, size_of::<T>())
+    };
+    copy_to_user(aspace, dst, buf)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::mm::USER_SPACE_START;
+    use crate::mm::address_space::MapPlacement;
+    use super::*;
+
+    #[kernel_test]
+    fn copy_across_pages_and_fault() {
+        let mut aspace = AddressSpace::new().unwrap();
+        let base = USER_SPACE_START;
+        aspace.alloc_and_map_page(base, EntryBits::ReadWrite.val());
+        aspace.alloc_and_map_page(base + PAGE_SIZE, EntryBits::ReadWrite.val());
+        aspace.alloc_and_map_page(base + 2 * PAGE_SIZE, EntryBits::ReadExecute.val());
+
+        let addr = base + PAGE_SIZE - 3;
+        copy_to_user(&mut aspace, addr, b"abcdef").unwrap();
+        let mut buf = [0u8; 6];
+        copy_from_user(&mut aspace, &mut buf, addr).unwrap();
+        assert_eq!(&buf, b"abcdef");
+        write_user(&mut aspace, base + 8, &0x1234_5678usize).unwrap();
+        assert_eq!(read_user::<usize>(&mut aspace, base + 8), Ok(0x1234_5678));
+
+        // Read-only page, unmapped page and kernel address.
+        assert_eq!(copy_to_user(&mut aspace, base + 2 * PAGE_SIZE, b"x"), Err(E_FAULT));
+        assert!(read_user::<u8>(&mut aspace, base + 2 * PAGE_SIZE).is_ok());
+        assert_eq!(read_user::<u8>(&mut aspace, base + 3 * PAGE_SIZE), Err(E_FAULT));
+        assert_eq!(read_user::<u8>(&mut aspace, 0x8000_0000), Err(E_FAULT));
+
+        // The string ends at the end of a page.
+        copy_to_user(&mut aspace, base + 2 * PAGE_SIZE - 4, b"abc\0").unwrap();
+        let mut name = [0xffu8; 16];
+        assert_eq!(copy_string_from_user(&mut aspace, &mut name, base + 2 * PAGE_SIZE - 4), Ok(3));
+        assert_eq!(&name[..4], b"abc\0");
+        assert_eq!(copy_string_from_user(&mut aspace, &mut name[..2], base + 2 * PAGE_SIZE - 4),
+                   Err(E_NAME_TOO_LONG));
+
+        // Pages of a VMA are faulted in.
+        let bits = EntryBits::ReadWrite.val();
+        let addr = aspace.map_anonymous(MapPlacement::Hint(0), 2 * PAGE_SIZE, bits, false).unwrap();
+        copy_to_user(&mut aspace, addr + PAGE_SIZE - 3, b"abcdef").unwrap();
+        assert!(aspace.translate(addr).is_some() && aspace.translate(addr + PAGE_SIZE).is_some());
+        unsafe { aspace.destroy(); }
+    }
+}
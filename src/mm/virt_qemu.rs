@@ -44,10 +44,16 @@ const fn virt_plic_size(cpus: usize) -> usize {
     VIRT_PLIC_CONTEXT_BASE + (cpus * 2) * VIRT_PLIC_CONTEXT_STRIDE
 }
 
+/// Memory map list for page level 0 (4KiB per entry). Only the devices lower than 2M are listed
+/// here, the other address in \[0, 2M) is left unmapped.
+static VIRT_MEM_MAP_4KB: [(usize, usize); 1] = [
+    (0x100000, 0x1000),     // TEST. Used to power off or reset the machine.
+];
+
 // 2M = 0x20_0000 = 1 << 21
 /// Memory map list for page level 1 (2MiB per entry).
 static VIRT_MEM_MAP_2MB: [(usize, usize); 10] = [
-    // (0x100000, 0x2000),     // TEST and RTC. Lower than 2M, ignore to map
+    // (0x100000, 0x2000),     // TEST and RTC. Lower than 2M, TEST is mapped by the 4KiB map
     (0x2000000, 0x10000),   // CLINT
     (align_down(0x2F00000, ORDER_2MB), 0x4000), // ACLINT_SSWI
     (0x3000000, 0x10000),   // PCIE_PIO
@@ -65,6 +71,10 @@ static VIRT_MEM_MAP_1GB: [(usize, usize); 1] = [
     (1usize << ORDER_1GB, 1usize << ORDER_1GB),     // PCIE_MMIO
 ];
 
+pub fn get_mem_map_4kb() -> &'static [(usize, usize)] {
+    &VIRT_MEM_MAP_4KB
+}
+
 pub fn get_mem_map_2mb() -> &'static [(usize, usize)] {
     &VIRT_MEM_MAP_2MB
 }
@@ -0,0 +1,508 @@
+//! Virtual memory areas (VMA) of a user address space.
+//!
+//! A [`Vma`] describes a page-aligned user range whose pages have the same permission and the
+//! same backing. The VMAs of an address space never overlap and are kept in a red-black tree
+//! ([`VmaTree`]) ordered by the start address. The pages of a VMA are allocated lazily by the
+//! page fault handler, see [`AddressSpace::handle_page_fault`].
+//!
+//! The permission of a VMA can be changed by `mprotect` within its maximum permission (like the
+//! `VM_MAY*` flags of Linux): a device VMA keeps at most the permission it is created with, so
+//! the pages not owned by the address space (e.g. the sigreturn trampoline shared by all
+//! processes) never become writable.
+//!
+//! [`AddressSpace::handle_page_fault`]: crate::mm::address_space::AddressSpace::handle_page_fault
+
+use alloc::sync::Arc;
+use core::cmp::Ordering;
+use core::mem::size_of;
+use crate::errno::{E_ACCESS, E_INVALID, E_NO_MEM};
+use crate::mm::{kfree, kzalloc};
+use crate::mm::mmu::EntryBits;
+use crate::util::rbtree::{self, RbNode, RbRoot};
+
+
+/// Backing file of a file VMA.
+pub trait VmaFile {
+    /// Read the file content at `offset` into `buf`. Returns the count of bytes read, which is
+    /// less than the `buf` length at the end of the file.
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, i32>;
+}
+
+/// Backing of a VMA.
+#[derive(Clone)]
+pub enum VmaBacking {
+    /// Zero-filled pages.
+    Anonymous,
+    /// Zero-filled pages shared with the forked address spaces instead of copy-on-write.
+    Shared,
+    /// Private pages filled with the file content from `offset`, the rest of the last page is
+    /// zero-filled.
+    File { file: Arc<dyn VmaFile>, offset: usize },
+    /// Device memory at the physical address `p_addr`, the pages are not owned by the VMA.
+    Device { p_addr: usize },
+}
+
+impl VmaBacking {
+    /// Get the backing of the sub-range starting `delta` bytes from the VMA start.
+    fn advance(&self, delta: usize) -> Self {
+        match self {
+            VmaBacking::Anonymous => VmaBacking::Anonymous,
+            VmaBacking::Shared => VmaBacking::Shared,
+            VmaBacking::File { file, offset } => VmaBacking::File {
+                file: file.clone(),
+                offset: offset + delta,
+            },
+            VmaBacking::Device { p_addr } => VmaBacking::Device { p_addr: p_addr + delta },
+        }
+    }
+
+    /// Get the maximum permission of a new VMA with the permission `bits`.
+    fn max_bits(&self, bits: u32) -> u32 {
+        match self {
+            VmaBacking::Device { .. } => bits,
+            _ => EntryBits::ReadWriteExecute.val(),
+        }
+    }
+}
+
+/// Access type of a page fault.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum FaultAccess {
+    Read,
+    Write,
+    Execute,
+}
+
+impl FaultAccess {
+    /// The [`EntryBits`] required by the access.
+    #[inline]
+    pub const fn required_bits(self) -> u32 {
+        match self {
+            FaultAccess::Read => EntryBits::Read.val(),
+            FaultAccess::Write => EntryBits::Write.val(),
+            FaultAccess::Execute => EntryBits::Execute.val(),
+        }
+    }
+}
+
+/// Virtual memory area.
+#[repr(C)]
+pub struct Vma {
+    node: RbNode,
+    start: usize,
+    end: usize,
+    /// Permission [`EntryBits`] (`R/W/X`) of the pages.
+    bits: u32,
+    /// Permission [`EntryBits`] the `bits` may be changed to.
+    max_bits: u32,
+    backing: VmaBacking,
+}
+
+impl Vma {
+    /// Alloc a VMA object. Returns null if out of memory.
+    fn create(start: usize, end: usize, bits: u32, max_bits: u32, backing: VmaBacking)
+        -> *mut Vma {
+        let ptr = kzalloc(size_of::<Vma>(), 0) as *mut Vma;
+        if !ptr.is_null() {
+            unsafe {
+                ptr.write(Vma {
+                    node: RbNode::new(),
+                    start,
+                    end,
+                    bits,
+                    max_bits,
+                    backing,
+                });
+            }
+        }
+
+        ptr
+    }
+
+    /// Drop and free the VMA object, which must not be in any tree.
+    unsafe fn free(vma: *mut Vma) {
+        core::ptr::drop_in_place(vma);
+        kfree(vma as _);
+    }
+
+    #[inline(always)]
+    fn from_node(node: *mut RbNode) -> *mut Vma {
+        unsafe { container_of_mut!(node, Vma, node) }
+    }
+
+    /// Start address (inclusive).
+    #[inline(always)]
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// End address (exclusive).
+    #[inline(always)]
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    /// Permission [`EntryBits`] of the pages.
+    #[inline(always)]
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    /// Maximum permission [`EntryBits`] of the pages.
+    #[inline(always)]
+    pub fn max_bits(&self) -> u32 {
+        self.max_bits
+    }
+
+    /// Get the backing.
+    #[inline(always)]
+    pub fn backing(&self) -> &VmaBacking {
+        &self.backing
+    }
+
+    /// Check if the pages are private to the address space, which are shared copy-on-write
+    /// after `fork`.
+    #[inline(always)]
+    pub fn is_private(&self) -> bool {
+        matches!(self.backing, VmaBacking::Anonymous | VmaBacking::File { .. })
+    }
+
+    /// Check if `addr` is in the VMA.
+    #[inline(always)]
+    pub fn contains(&self, addr: usize) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    /// Check if the VMA permission allows the `access`.
+    #[inline(always)]
+    pub fn allows(&self, access: FaultAccess) -> bool {
+        self.bits & access.required_bits() != 0
+    }
+}
+
+/// VMAs of an address space, ordered by the start address.
+pub struct VmaTree {
+    root: RbRoot,
+    count: usize,
+}
+
+impl VmaTree {
+    pub const fn new() -> Self {
+        Self {
+            root: RbRoot::new(),
+            count: 0,
+        }
+    }
+
+    /// Get the count of VMAs.
+    #[inline(always)]
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Find the first VMA which ends after `addr` (`addr < end`), the VMA may not contain the
+    /// `addr`. Returns null if not found.
+    pub fn find_after(&self, addr: usize) -> *mut Vma {
+        let mut ret = core::ptr::null_mut();
+        let mut node = self.root.node;
+        while !node.is_null() {
+            let vma = Vma::from_node(node);
+            unsafe {
+                if addr < (*vma).end {
+                    ret = vma;
+                    if (*vma).start <= addr {
+                        break;
+                    }
+                    node = (*node).left();
+                } else {
+                    node = (*node).right();
+                }
+            }
+        }
+
+        ret
+    }
+
+    /// Find the VMA containing `addr`. Returns null if not found.
+    pub fn find(&self, addr: usize) -> *mut Vma {
+        let node = rbtree::find(&self.root, |node| {
+            let vma = unsafe { &*Vma::from_node(node) };
+            if addr < vma.start {
+                Ordering::Less
+            } else if addr >= vma.end {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        });
+
+        if node.is_null() { core::ptr::null_mut() } else { Vma::from_node(node) }
+    }
+
+    /// Get the first VMA, null if empty.
+    #[inline]
+    pub fn first(&self) -> *mut Vma {
+        let node = rbtree::first(&self.root);
+        if node.is_null() { node as _ } else { Vma::from_node(node) }
+    }
+
+    /// Get the last VMA, null if empty.
+    #[inline]
+    pub fn last(&self) -> *mut Vma {
+        let node = rbtree::last(&self.root);
+        if node.is_null() { node as _ } else { Vma::from_node(node) }
+    }
+
+    /// Get the next VMA of `vma`, null if it is the last one.
+    #[inline]
+    pub fn next(vma: *mut Vma) -> *mut Vma {
+        let node = rbtree::next(unsafe { &mut (*vma).node });
+        if node.is_null() { node as _ } else { Vma::from_node(node) }
+    }
+
+    /// Get the previous VMA of `vma`, null if it is the first one.
+    #[inline]
+    pub fn prev(vma: *mut Vma) -> *mut Vma {
+        let node = rbtree::prev(unsafe { &mut (*vma).node });
+        if node.is_null() { node as _ } else { Vma::from_node(node) }
+    }
+
+    /// Check if any VMA overlaps the range `[start, end)`.
+    pub fn overlaps(&self, start: usize, end: usize) -> bool {
+        let vma = self.find_after(start);
+        !vma.is_null() && unsafe { (*vma).start < end }
+    }
+
+    fn link(&mut self, vma: *mut Vma) {
+        let start = unsafe { (*vma).start };
+        rbtree::insert(&mut self.root, unsafe { &mut (*vma).node }, |node| {
+            start < unsafe { (*Vma::from_node(node)).start }
+        });
+        self.count += 1;
+    }
+
+    fn unlink(&mut self, vma: *mut Vma) {
+        rbtree::erase(&mut self.root, unsafe { &mut (*vma).node });
+        self.count -= 1;
+    }
+
+    /// Add a VMA of the page-aligned range `[start, end)`, the maximum permission is decided by
+    /// the `backing`. Returns `Err` with the errno if the range overlaps other VMAs or out of
+    /// memory.
+    pub fn insert(&mut self, start: usize, end: usize, bits: u32, backing: VmaBacking)
+        -> Result<*mut Vma, i32> {
+        let max_bits = backing.max_bits(bits);
+        self.insert_with_max(start, end, bits, max_bits, backing)
+    }
+
+    /// Add a copy of the `vma` of another tree, like [`insert`](VmaTree::insert).
+    pub fn insert_copy(&mut self, vma: &Vma) -> Result<*mut Vma, i32> {
+        self.insert_with_max(vma.start, vma.end, vma.bits, vma.max_bits, vma.backing.clone())
+    }
+
+    fn insert_with_max(&mut self, start: usize, end: usize, bits: u32, max_bits: u32,
+                       backing: VmaBacking) -> Result<*mut Vma, i32> {
+        if start >= end || self.overlaps(start, end) {
+            return Err(E_INVALID);
+        }
+
+        let vma = Vma::create(start, end, bits, max_bits, backing);
+        if vma.is_null() {
+            return Err(E_NO_MEM);
+        }
+        self.link(vma);
+        Ok(vma)
+    }
+
+    /// Extend or shrink the end of `vma` to `end`, the new range must not overlap other VMAs.
+    pub fn set_end(&mut self, vma: *mut Vma, end: usize) {
+        let vma = unsafe { &mut *vma };
+        debug_assert!(vma.start < end);
+        debug_assert!(end <= vma.end || {
+            let next = Self::next(vma);
+            next.is_null() || unsafe { end <= (*next).start }
+        });
+        vma.end = end;
+    }
+
+    /// Split `vma` at the page-aligned `addr` inside it, the part from `addr` becomes a new VMA.
+    /// Returns the new VMA, or `Err` if out of memory.
+    fn split(&mut self, vma: *mut Vma, addr: usize) -> Result<*mut Vma, i32> {
+        let v = unsafe { &mut *vma };
+        debug_assert!(v.start < addr && addr < v.end);
+        let backing = v.backing.advance(addr - v.start);
+        let tail = Vma::create(addr, v.end, v.bits, v.max_bits, backing);
+        if tail.is_null() {
+            return Err(E_NO_MEM);
+        }
+        v.end = addr;
+        self.link(tail);
+        Ok(tail)
+    }
+
+    /// Set the permission `bits` of the page-aligned range `[start, end)`, the VMAs across the
+    /// range boundaries are split.
+    ///
+    /// Returns `Err` with `E_NO_MEM` if any page of the range is not in a VMA or out of memory
+    /// when splitting, or `E_ACCESS` if the `bits` exceed the maximum permission of any VMA in
+    /// the range. The permission is unchanged in that case.
+    pub fn protect_range(&mut self, start: usize, end: usize, bits: u32) -> Result<(), i32> {
+        let first = self.find(start);
+        if first.is_null() {
+            return Err(E_NO_MEM);
+        }
+        let mut vma = first;
+        let mut max_bits = unsafe { (*vma).max_bits };
+        while unsafe { (*vma).end } < end {
+            let next = Self::next(vma);
+            if next.is_null() || unsafe { (*next).start != (*vma).end } {
+                return Err(E_NO_MEM);
+            }
+            vma = next;
+            max_bits &= unsafe { (*vma).max_bits };
+        }
+        if bits & !max_bits != 0 {
+            return Err(E_ACCESS);
+        }
+
+        let mut vma = first;
+        if unsafe { (*vma).start } < start {
+            vma = self.split(vma, start)?;
+        }
+        let last = self.find(end - 1);
+        if unsafe { (*last).end } > end {
+            self.split(last, end)?;
+        }
+        while !vma.is_null() && unsafe { (*vma).start } < end {
+            unsafe { (*vma).bits = bits; }
+            vma = Self::next(vma);
+        }
+
+        Ok(())
+    }
+
+    /// Remove the page-aligned range `[start, end)` from the VMAs: the VMAs inside the range are
+    /// removed, the VMAs across the range boundaries are trimmed or split. `unmap` is called on
+    /// each removed part with the VMA and the part range before the VMA is changed.
+    ///
+    /// Returns `Err` if out of memory when splitting a VMA, nothing is changed in that case.
+    pub fn remove_range<F>(&mut self, start: usize, end: usize, mut unmap: F) -> Result<(), i32>
+        where F: FnMut(&Vma, usize, usize) {
+        let mut vma = self.find_after(start);
+        if vma.is_null() || unsafe { (*vma).start >= end } {
+            return Ok(());
+        }
+
+        // Split the VMA containing the range first, this is the only case that needs memory.
+        unsafe {
+            if (*vma).start < start && end < (*vma).end {
+                let tail = Vma::create(end, (*vma).end, (*vma).bits, (*vma).max_bits,
+                                       (*vma).backing.advance(end - (*vma).start));
+                if tail.is_null() {
+                    return Err(E_NO_MEM);
+                }
+                unmap(&*vma, start, end);
+                (*vma).end = start;
+                self.link(tail);
+                return Ok(());
+            }
+        }
+
+        while !vma.is_null() && unsafe { (*vma).start < end } {
+            let next = Self::next(vma);
+            let v = unsafe { &mut *vma };
+            let part_start = v.start.max(start);
+            let part_end = v.end.min(end);
+            unmap(v, part_start, part_end);
+            if part_start == v.start && part_end == v.end {
+                self.unlink(vma);
+                unsafe { Vma::free(vma); }
+            } else if part_start == v.start {
+                // Trim the head, the order in the tree is unchanged.
+                v.backing = v.backing.advance(part_end - v.start);
+                v.start = part_end;
+            } else {
+                v.end = part_start;
+            }
+            vma = next;
+        }
+
+        Ok(())
+    }
+
+    /// Remove and free all VMAs, `handle` is called on each VMA before it is freed.
+    pub fn clear<F>(&mut self, mut handle: F) where F: FnMut(&Vma) {
+        loop {
+            let vma = self.first();
+            if vma.is_null() {
+                break;
+            }
+            handle(unsafe { &*vma });
+            self.unlink(vma);
+            unsafe { Vma::free(vma); }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RW: u32 = EntryBits::ReadWrite.val();
+
+    #[kernel_test]
+    fn insert_find_and_split() {
+        let mut tree = VmaTree::new();
+        tree.insert(0x1000, 0x5000, RW, VmaBacking::Anonymous).unwrap();
+        tree.insert(0x8000, 0x9000, RW, VmaBacking::Device { p_addr: 0x1000_0000 }).unwrap();
+        assert_eq!(tree.insert(0x4000, 0x6000, RW, VmaBacking::Anonymous).unwrap_err(), E_INVALID);
+        assert!(tree.find(0x5000).is_null());
+        assert_eq!(unsafe { (*tree.find(0x4fff)).start() }, 0x1000);
+        assert_eq!(unsafe { (*tree.find_after(0x5000)).start() }, 0x8000);
+
+        // Split the first VMA and trim the head of the device one.
+        let mut removed = 0;
+        tree.remove_range(0x2000, 0x3000, |_, s, e| removed += e - s).unwrap();
+        tree.remove_range(0x7000, 0x8800, |_, s, e| removed += e - s).unwrap();
+        assert_eq!(removed, 0x1800);
+        assert_eq!(tree.count(), 3);
+        let dev = unsafe { &*tree.last() };
+        assert_eq!(dev.start(), 0x8800);
+        assert!(matches!(dev.backing(), VmaBacking::Device { p_addr: 0x1000_0800 }));
+        assert_eq!(unsafe { (*VmaTree::prev(tree.last())).start() }, 0x3000);
+
+        tree.clear(|_| {});
+        assert_eq!(tree.count(), 0);
+    }
+
+    #[kernel_test]
+    fn protect_range() {
+        let mut tree = VmaTree::new();
+        tree.insert(0x1000, 0x3000, RW, VmaBacking::Anonymous).unwrap();
+        tree.insert(0x3000, 0x6000, RW, VmaBacking::Shared).unwrap();
+        assert_eq!(tree.protect_range(0x5000, 0x7000, 0), Err(E_NO_MEM));
+        assert_eq!(tree.count(), 2);
+
+        // Both VMAs are split at the range boundaries.
+        let read = EntryBits::Read.val();
+        tree.protect_range(0x2000, 0x4000, read).unwrap();
+        assert_eq!(tree.count(), 4);
+        let starts_and_bits = [(0x1000, RW), (0x2000, read), (0x3000, read), (0x4000, RW)];
+        let mut vma = tree.first();
+        for (start, bits) in starts_and_bits {
+            assert_eq!(unsafe { ((*vma).start(), (*vma).bits()) }, (start, bits));
+            vma = VmaTree::next(vma);
+        }
+        assert!(!unsafe { (*tree.find(0x3000)).is_private() });
+
+        // A device VMA can not get more permission than it is created with.
+        tree.insert(0x6000, 0x7000, read, VmaBacking::Device { p_addr: 0x1000_0000 }).unwrap();
+        assert_eq!(tree.protect_range(0x5000, 0x7000, RW), Err(E_ACCESS));
+        assert_eq!(tree.count(), 5);
+        tree.protect_range(0x5000, 0x7000, 0).unwrap();
+        assert_eq!(unsafe { (*tree.last()).max_bits() }, read);
+
+        tree.clear(|_| {});
+    }
+}
@@ -0,0 +1,419 @@
+//! ELF executable loader.
+//!
+//! Load a static RISC-V ELF64 executable into a user [`AddressSpace`]. The headers are parsed by
+//! [`vos_core::elf`], each `PT_LOAD` segment gets a VMA with the `R/W/X` permissions of the
+//! segment flags, and the main thread stack is set up as the Linux does:
+//!
+//! ```text
+//!     USER_STACK_TOP -> +-------------------------+
+//!                       | AT_RANDOM bytes         |
+//!                       | argv and envp strings   |
+//!                       +-------------------------+ (16-byte aligned)
+//!                       | auxv pairs, AT_NULL     |
+//!                       | envp pointers, NULL     |
+//!                       | argv pointers, NULL     |
+//!                 sp -> | argc                    |
+//!                       +-------------------------+
+//! ```
+//!
+//! The sigreturn trampoline page of the [`signal`] handlers is mapped as well.
+//!
+//! The executable is read by an [`ElfSource`], which is implemented for the in-memory blob
+//! (`[u8]`, e.g. from `include_bytes!`) and for the file [`Inode`].
+//!
+//! The executable must be linked in the user space (see [`USER_SPACE_START`]), e.g. with
+//! `-Wl,-Ttext-segment=0x2000000000`. A static PIE (`ET_DYN`) is loaded at [`ELF_DYN_BASE`].
+//! Dynamically linked executables (with a `PT_INTERP`) are not supported.
+//!
+//! [`AddressSpace`]: crate::mm::address_space::AddressSpace
+//! [`USER_SPACE_START`]: crate::mm::USER_SPACE_START
+//! [`signal`]: crate::proc::signal
+//! [`Inode`]: crate::fs::inode::Inode
+
+use core::mem::size_of;
+use vos_core::elf::{ElfError, FileHeader, ProgramHeader, EHDR_SIZE, ET_DYN, PF_R, PF_W, PF_X,
+                    PHDR_SIZE, PT_INTERP, PT_LOAD, PT_PHDR};
+use crate::driver::char::random::get_random_bytes;
+use crate::errno::{E_IO, E_NO_EXEC, E_NO_MEM, E_TOO_BIG};
+use crate::fs::inode::Inode;
+use crate::mm::{PAGE_ORDER, PAGE_SIZE, USER_SPACE_START};
+use crate::mm::address_space::AddressSpace;
+use crate::mm::mmu::EntryBits;
+use crate::mm::vma::VmaBacking;
+use crate::proc::signal::map_sigreturn_trampoline;
+use crate::proc::user::{USER_STACK_SIZE, USER_STACK_TOP};
+use crate::util::align::{align_down, align_up};
+
+
+/// Load address of a static PIE executable.
+pub const ELF_DYN_BASE: usize = USER_SPACE_START;
+
+/// Max size of the argument and environment strings and pointers on the stack.
+pub const ARG_MAX_SIZE: usize = 32 * PAGE_SIZE;
+/// Size of the `AT_RANDOM` bytes.
+const RANDOM_SIZE: usize = 16;
+
+// Auxiliary vector types.
+const AT_NULL: usize = 0;
+const AT_PHDR: usize = 3;
+const AT_PHENT: usize = 4;
+const AT_PHNUM: usize = 5;
+const AT_PAGESZ: usize = 6;
+const AT_BASE: usize = 7;
+const AT_FLAGS: usize = 8;
+const AT_ENTRY: usize = 9;
+const AT_UID: usize = 11;
+const AT_EUID: usize = 12;
+const AT_GID: usize = 13;
+const AT_EGID: usize = 14;
+const AT_CLKTCK: usize = 17;
+const AT_SECURE: usize = 23;
+const AT_RANDOM: usize = 25;
+
+/// Count of the auxiliary vector pairs, including the `AT_NULL`.
+const AUXV_LEN: usize = 15;
+
+/// Source of an ELF executable.
+pub trait ElfSource {
+    /// Total size of the executable.
+    fn size(&self) -> usize;
+
+    /// Read exactly `buf.len()` bytes at `offset`. Returns `Err` with the errno on failure.
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<(), i32>;
+}
+
+impl ElfSource for [u8] {
+    fn size(&self) -> usize {
+        self.len()
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<(), i32> {
+        match offset.checked_add(buf.len()) {
+            Some(end) if end <= self.len() => {
+                buf.copy_from_slice(&self[offset..end]);
+                Ok(())
+            }
+            _ => Err(E_IO),
+        }
+    }
+}
+
+impl ElfSource for dyn Inode {
+    fn size(&self) -> usize {
+        self.stat().map_or(0, |stat| stat.size as usize)
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<(), i32> {
+        let mut done = 0;
+        while done < buf.len() {
+            match Inode::read_at(self, offset + done, &mut buf[done..])? {
+                0 => return Err(E_IO),
+                len => done += len,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Result of [`load_elf`].
+#[derive(Copy, Clone, Debug)]
+pub struct ElfLoadInfo {
+    /// Entry point, the initial `pc` of the main thread.
+    pub entry: usize,
+    /// Initial stack pointer of the main thread, points to the `argc`.
+    pub stack_pointer: usize,
+    /// Offset added to the virtual addresses in the file, non-zero only for a static PIE.
+    pub load_bias: usize,
+    /// Page-aligned end of the highest segment, the initial program break.
+    pub program_break: usize,
+}
+
+fn elf_error(err: ElfError) -> i32 {
+    debug!("Load ELF failed: {}.", err);
+    E_NO_EXEC
+}
+
+fn read_program_header<S: ElfSource + ?Sized>(src: &S, header: &FileHeader, idx: usize)
+    -> Result<ProgramHeader, i32> {
+    let mut buf = [0u8; PHDR_SIZE];
+    src.read_at(header.e_phoff as usize + idx * PHDR_SIZE, &mut buf)?;
+    ProgramHeader::parse(&buf).map_err(elf_error)
+}
+
+/// Convert the segment flags to the [`EntryBits`]. Writable pages must also be readable.
+fn segment_bits(flags: u32) -> u32 {
+    let mut bits = 0;
+    if flags & PF_R != 0 {
+        bits |= EntryBits::Read.val();
+    }
+    if flags & PF_W != 0 {
+        bits |= EntryBits::ReadWrite.val();
+    }
+    if flags & PF_X != 0 {
+        bits |= EntryBits::Execute.val();
+    }
+
+    bits
+}
+
+/// Add the VMA of a `PT_LOAD` segment, map the pages with the file content and copy it. The
+/// rest of the memory (`.bss`) is populated lazily with the zeroed pages. A page shared with the
+/// previous segment belongs to the VMA of the previous segment, and gets the union of the
+/// permissions.
+fn load_segment<S: ElfSource + ?Sized>(aspace: &mut AddressSpace, src: &S, ph: &ProgramHeader,
+                                        bias: usize) -> Result<(), i32> {
+    let start = ph.p_vaddr as usize + bias;
+    let mem_size = ph.p_memsz as usize;
+    if !AddressSpace::is_user_range(start, mem_size) {
+        debug!("Load ELF failed: segment {:#x} is not in the user space.", start);
+        return Err(E_NO_EXEC);
+    }
+    let bits = segment_bits(ph.p_flags);
+    if bits == 0 || mem_size == 0 {
+        // Inaccessible segment, leave it unmapped.
+        return Ok(());
+    }
+
+    let vma_start = match aspace.find_vma(start) {
+        Some(prev) => prev.end(),
+        None => align_down(start, PAGE_ORDER),
+    };
+    let vma_end = align_up(start + mem_size, PAGE_ORDER);
+    if vma_start < vma_end &&
+        aspace.add_vma(vma_start, vma_end - vma_start, bits, VmaBacking::Anonymous).is_err() {
+        debug!("Load ELF failed: segment {:#x} overlaps others.", start);
+        return Err(E_NO_EXEC);
+    }
+
+    let file_end = start + ph.p_filesz as usize;
+    let rwx = EntryBits::ReadWriteExecute.val();
+    let mut page = align_down(start, PAGE_ORDER);
+    while page < file_end {
+        let p_addr = match aspace.lookup(page) {
+            Some((p_addr, old)) => {
+                aspace.map_page(page, p_addr, bits | (old & rwx));
+                p_addr
+            }
+            None => aspace.alloc_and_map_page(page, bits),
+        };
+        if p_addr == 0 {
+            return Err(E_NO_MEM);
+        }
+
+        let copy_start = page.max(start);
+        let copy_end = (page + PAGE_SIZE).min(file_end);
+        let buf = unsafe {
+            core::slice::from_raw_parts_mut((p_addr + copy_start - page) as *mut u8,
+                                            copy_end - copy_start)
+        };
+        src.read_at(ph.p_offset as usize + (copy_start - start), buf)?;
+        page += PAGE_SIZE;
+    }
+
+    Ok(())
+}
+
+/// Generate the `AT_RANDOM` bytes.
+fn random_bytes() -> [u8; RANDOM_SIZE] {
+    let mut bytes = [0u8; RANDOM_SIZE];
+    get_random_bytes(&mut bytes);
+    bytes
+}
+
+/// Add the main thread stack VMA below [`USER_STACK_TOP`] and push the arguments, environments
+/// and the auxiliary vector. Only the touched stack pages are populated. Returns the initial
+/// stack pointer.
+fn setup_stack(aspace: &mut AddressSpace, argv: &[&[u8]], envp: &[&[u8]],
+               auxv: &[(usize, usize); AUXV_LEN - 2]) -> Result<usize, i32> {
+    let strings_size: usize = argv.iter().chain(envp).map(|s| s.len() + 1).sum();
+    let words = 1 + argv.len() + 1 + envp.len() + 1 + AUXV_LEN * 2;
+    if strings_size + words * size_of::<usize>() > ARG_MAX_SIZE {
+        return Err(E_TOO_BIG);
+    }
+
+    aspace.add_vma(USER_STACK_TOP - USER_STACK_SIZE, USER_STACK_SIZE, EntryBits::ReadWrite.val(),
+                   VmaBacking::Anonymous)?;
+
+    let random = USER_STACK_TOP - RANDOM_SIZE;
+    let strings = random - strings_size;
+    let sp = align_down(strings - words * size_of::<usize>(), 4);
+    let mut ok = aspace.write_bytes(random, &random_bytes());
+
+    // Write the strings and the pointer table from the low address.
+    let mut str_cursor = strings;
+    let mut cursor = sp;
+    let mut push = |aspace: &mut AddressSpace, word: usize| {
+        let ret = aspace.write_bytes(cursor, &word.to_le_bytes());
+        cursor += size_of::<usize>();
+        ret
+    };
+    ok &= push(aspace, argv.len());
+    for list in [argv, envp] {
+        for s in list {
+            ok &= push(aspace, str_cursor);
+            ok &= aspace.write_bytes(str_cursor, s) && aspace.write_bytes(str_cursor + s.len(), &[0]);
+            str_cursor += s.len() + 1;
+        }
+        ok &= push(aspace, 0);
+    }
+    for &(key, val) in auxv.iter().chain(&[(AT_RANDOM, random), (AT_NULL, 0)]) {
+        ok &= push(aspace, key) && push(aspace, val);
+    }
+    if !ok {
+        return Err(E_NO_MEM);
+    }
+
+    Ok(sp)
+}
+
+/// Load the ELF executable from `src` into the user address space `aspace`, and set up the
+/// main thread stack with the arguments `argv` and the environments `envp`.
+///
+/// Returns the entry point and the stack pointer for the main thread (see
+/// [`build_user_thread`]), or `Err` with the errno. On failure the address space may be
+/// partially populated, the caller should destroy it.
+///
+/// [`build_user_thread`]: crate::proc::user::build_user_thread
+pub fn load_elf<S: ElfSource + ?Sized>(aspace: &mut AddressSpace, src: &S, argv: &[&[u8]],
+                                       envp: &[&[u8]]) -> Result<ElfLoadInfo, i32> {
+    let mut buf = [0u8; EHDR_SIZE];
+    src.read_at(0, &mut buf)?;
+    let header = FileHeader::parse(&buf).map_err(elf_error)?;
+    match header.program_headers_offset() {
+        Some((_, end)) if end <= src.size() as u64 => {}
+        _ => return Err(elf_error(ElfError::BadProgramHeader)),
+    }
+
+    // Find the load range and the address of the program header table.
+    let mut min_addr = usize::MAX;
+    let mut phdr_addr = 0;
+    for i in 0..header.e_phnum as usize {
+        let ph = read_program_header(src, &header, i)?;
+        match ph.p_type {
+            PT_LOAD => {
+                ph.validate_load(src.size() as u64).map_err(elf_error)?;
+                min_addr = min_addr.min(ph.p_vaddr as usize);
+                if phdr_addr == 0 && ph.contains_offset(header.e_phoff) {
+                    phdr_addr = (ph.p_vaddr + (header.e_phoff - ph.p_offset)) as usize;
+                }
+            }
+            PT_PHDR => phdr_addr = ph.p_vaddr as usize,
+            PT_INTERP => {
+                debug!("Load ELF failed: dynamically linked executable is not supported.");
+                return Err(E_NO_EXEC);
+            }
+            _ => {}
+        }
+    }
+    if min_addr == usize::MAX {
+        return Err(elf_error(ElfError::BadSegment));
+    }
+
+    let bias = if header.e_type == ET_DYN {
+        ELF_DYN_BASE.wrapping_sub(align_down(min_addr, PAGE_ORDER))
+    } else {
+        0
+    };
+    let mut program_break = 0;
+    for i in 0..header.e_phnum as usize {
+        let ph = read_program_header(src, &header, i)?;
+        if ph.p_type == PT_LOAD {
+            load_segment(aspace, src, &ph, bias)?;
+            let end = ph.p_vaddr as usize + bias + ph.p_memsz as usize;
+            program_break = program_break.max(align_up(end, PAGE_ORDER));
+        }
+    }
+
+    let entry = (header.e_entry as usize).wrapping_add(bias);
+    if !AddressSpace::is_user_range(entry, 1) {
+        debug!("Load ELF failed: entry {:#x} is not in the user space.", entry);
+        return Err(E_NO_EXEC);
+    }
+    let phdr_addr = if phdr_addr != 0 { phdr_addr + bias } else { 0 };
+    let auxv = [
+        (AT_PHDR, phdr_addr),
+        (AT_PHENT, PHDR_SIZE),
+        (AT_PHNUM, header.e_phnum as usize),
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_BASE, 0),
+        (AT_FLAGS, 0),
+        (AT_ENTRY, entry),
+        (AT_UID, 0),
+        (AT_EUID, 0),
+        (AT_GID, 0),
+        (AT_EGID, 0),
+        (AT_CLKTCK, 100),
+        (AT_SECURE, 0),
+    ];
+    let stack_pointer = setup_stack(aspace, argv, envp, &auxv)?;
+    map_sigreturn_trampoline(aspace)?;
+
+    Ok(ElfLoadInfo {
+        entry,
+        stack_pointer,
+        load_bias: bias,
+        program_break,
+    })
+}
+
+/// Get the built-in init executable (`asm/user_init.S`).
+#[inline]
+pub fn user_init_elf() -> &'static [u8] {
+    extern "C" {
+        static user_init_elf_start: u8;
+        static user_init_elf_end: u8;
+    }
+
+    unsafe {
+        let start = &user_init_elf_start as *const u8;
+        let size = &user_init_elf_end as *const u8 as usize - start as usize;
+        core::slice::from_raw_parts(start, size)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_word(aspace: &AddressSpace, addr: usize) -> usize {
+        unsafe { *(aspace.translate(addr).unwrap() as *const usize) }
+    }
+
+    #[kernel_test]
+    fn load_built_in_init() {
+        let mut aspace = AddressSpace::new().unwrap();
+        let info = load_elf(&mut aspace, user_init_elf(), &[b"init", b"-v"], &[b"HOME=/"]).unwrap();
+        assert_eq!(info.load_bias, 0);
+        assert!(AddressSpace::is_user_range(info.entry, 4));
+        let (_, bits) = aspace.lookup(info.entry).unwrap();
+        assert_eq!(bits & EntryBits::ReadWriteExecute.val(), EntryBits::ReadExecute.val());
+
+        let sp = info.stack_pointer;
+        assert_eq!(sp & 0xf, 0);
+        assert_eq!(read_word(&aspace, sp), 2);
+        let argv1 = read_word(&aspace, sp + 16);
+        assert_eq!(unsafe { *(aspace.translate(argv1).unwrap() as *const [u8; 3]) }, *b"-v\0");
+        assert_eq!(read_word(&aspace, sp + 24), 0);
+        assert_ne!(read_word(&aspace, sp + 32), 0);
+        assert_eq!(read_word(&aspace, sp + 40), 0);
+        assert_eq!(read_word(&aspace, sp + 48), AT_PHDR);
+        // The stack pages are populated on demand.
+        let stack = aspace.find_vma(sp).unwrap();
+        assert_eq!(stack.start(), USER_STACK_TOP - USER_STACK_SIZE);
+        assert_eq!(aspace.translate(stack.start()), None);
+        unsafe { aspace.destroy(); }
+    }
+
+    #[kernel_test]
+    fn reject_bad_elf() {
+        let mut aspace = AddressSpace::new().unwrap();
+        let mut data = [0u8; EHDR_SIZE];
+        data.copy_from_slice(&user_init_elf()[..EHDR_SIZE]);
+        // e_machine = EM_X86_64.
+        data[18] = 62;
+        assert_eq!(load_elf(&mut aspace, &data[..], &[], &[]).unwrap_err(), E_NO_EXEC);
+        assert_eq!(load_elf(&mut aspace, &data[..10], &[], &[]).unwrap_err(), E_IO);
+        unsafe { aspace.destroy(); }
+    }
+}
@@ -0,0 +1,140 @@
+//! Replace the program of a process by `execve`.
+
+use alloc::sync::Arc;
+use crate::arch::cpu::{self, Register};
+use crate::errno::{E_ACCESS, E_NO_ENT, E_NO_MEM};
+use crate::fs::{lookup_path, Dentry, InodeType};
+use crate::fs::inode::Inode;
+use crate::mm::address_space::AddressSpace;
+use crate::proc::elf::load_elf;
+use crate::proc::signal::kill_other_threads;
+use crate::proc::task::TaskInfo;
+
+
+/// Find the executable at `path` relative to the directory `base` (the root if `None`). It must
+/// be a regular file with an execute permission bit.
+pub(crate) fn open_executable(base: Option<&Arc<Dentry>>, path: &[u8])
+    -> Result<Arc<dyn Inode>, i32> {
+    let path = core::str::from_utf8(path).map_err(|_| E_NO_ENT)?;
+    let dentry = lookup_path(base, path, true)?;
+    let stat = dentry.inode().stat()?;
+    match stat.inode_type() {
+        InodeType::File if stat.mode & 0o111 != 0 => Ok(dentry.inode().clone()),
+        _ => Err(E_ACCESS),
+    }
+}
+
+/// Replace the program of the process of the user thread `task` with the executable at `path`,
+/// the `argv` and `envp` are copied to the new stack. The old address space is kept if the
+/// executable can not be loaded.
+///
+/// The other threads of the process are killed before the old address space is replaced. If
+/// they are still running, the new address space is dropped, the `task` is added to a wait
+/// queue and false is returned: the caller should call `schedule` and restart the `execve`.
+///
+/// On success, the trap frame of `task` is reset to start the new program, the old address
+/// space is destroyed, the parent thread suspended by `CLONE_VFORK` continues, the caught
+/// signals are reset to the default action, the close-on-exec files are closed and the process
+/// is renamed after the executable, true is returned.
+/// Returns `Err` with the errno on failure.
+pub fn do_execve(task: &mut TaskInfo, path: &[u8], argv: &[&[u8]], envp: &[&[u8]])
+    -> Result<bool, i32> {
+    let process = unsafe { &mut *task.process() };
+    let src = open_executable(process.cwd().as_ref(), path)?;
+    let mut aspace = AddressSpace::new().ok_or(E_NO_MEM)?;
+    let info = match load_elf(&mut aspace, &*src, argv, envp) {
+        Ok(info) => info,
+        Err(errno) => {
+            unsafe { aspace.destroy(); }
+            return Err(errno);
+        }
+    };
+    aspace.set_brk_start(info.program_break);
+
+    match kill_other_threads(task) {
+        Ok(true) => {}
+        ret => {
+            unsafe { aspace.destroy(); }
+            return ret;
+        }
+    }
+    let mut old = core::mem::replace(process.address_space_mut(), aspace);
+    process.address_space().activate();
+    unsafe { old.destroy(); }
+    process.vfork_release();
+
+    process.with_signal(|signal, _| signal.reset_on_exec());
+    process.files_mut().close_on_exec();
+    process.set_comm(path);
+    task.set_clear_child_tid(0);
+    let frame = task.trap_frame_mut();
+    frame.satp = process.address_space().satp();
+    frame.regs = [0; 32];
+    frame.fregs = [0; 32];
+    frame.fcsr = 0;
+    frame.regs[cpu::reg(Register::Sp)] = info.stack_pointer;
+    frame.pc = info.entry;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::String;
+    use super::*;
+    use crate::errno::E_AGAIN;
+    use crate::fs::file::{O_CREAT, O_WRONLY};
+    use crate::fs::mount::FileSystem;
+    use crate::fs::tmpfs::TMPFS;
+    use crate::fs::vfs;
+    use crate::mm::activate_identity_map;
+    use crate::proc::elf::user_init_elf;
+    use crate::proc::process::Process;
+    use crate::proc::signal::start_group_exit;
+    use crate::proc::task::TaskStatus;
+    use crate::proc::user::{build_user_thread, free_user_thread};
+    use crate::util::list;
+
+    #[kernel_test]
+    fn execve_replaces_program() {
+        let sb = TMPFS.mount("tmpfs", "").unwrap();
+        let root = Dentry::new(String::from("/"), sb.root(), None);
+        let file = vfs::open(Some(&root), "init", O_WRONLY | O_CREAT, 0o755).unwrap();
+        assert_eq!(file.write(user_init_elf()), Ok(user_init_elf().len()));
+        vfs::open(Some(&root), "data", O_WRONLY | O_CREAT, 0o644).unwrap();
+
+        let process = unsafe { &mut *Process::create() };
+        process.set_cwd(root);
+        let task = unsafe { &mut *build_user_thread(process, 0, 0) };
+        task.trap_frame_mut().regs[cpu::reg(Register::A0)] = 1;
+        assert_eq!(do_execve(task, b"none", &[], &[]), Err(E_NO_ENT));
+        assert_eq!(do_execve(task, b"data", &[], &[]), Err(E_ACCESS));
+        assert_eq!(task.trap_frame().regs[cpu::reg(Register::A0)], 1);
+
+        // The other thread is killed first, the exec waits until it has exited.
+        let other = build_user_thread(process, 0, 0);
+        assert_eq!(do_execve(task, b"init", &[b"init"], &[]), Ok(false));
+        assert_eq!(task.status(), TaskStatus::UninterruptibleSleep);
+        assert_eq!(process.comm(), "");
+        // A killed thread exits alone, and no other thread may exec meanwhile.
+        assert_eq!(start_group_exit(unsafe { &mut *other }, 9), 9);
+        assert_eq!(process.signal().group_exit(), None);
+        assert_eq!(do_execve(unsafe { &mut *other }, b"init", &[], &[]), Err(E_AGAIN));
+        list::delete(&mut task.list);
+        unsafe { free_user_thread(other); }
+
+        assert_eq!(do_execve(task, b"init", &[b"init"], &[]), Ok(true));
+        let frame = task.trap_frame();
+        assert_eq!(frame.satp, process.address_space().satp());
+        assert_eq!(frame.regs[cpu::reg(Register::A0)], 0);
+        assert_ne!(frame.regs[cpu::reg(Register::Sp)], 0);
+        assert!(process.address_space().find_vma(frame.pc).is_some());
+        assert_eq!(process.comm(), "init");
+
+        activate_identity_map();
+        unsafe {
+            free_user_thread(task);
+            Process::destroy(process);
+        }
+    }
+}
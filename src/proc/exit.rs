@@ -0,0 +1,219 @@
+//! Thread and process exit, and waiting for the child processes.
+//!
+//! The exit status uses the `wait` status format: `(code & 0xff) << 8` for a normal exit, or the
+//! signal number if the thread is killed by a signal. When the last thread of a process exits,
+//! the process becomes a zombie until its parent reaps it by [`do_wait`], see the
+//! [process](crate::proc::process) document.
+//!
+//! `exit_group` and the fatal signals exit the whole process: the other threads are killed by
+//! `SIGKILL` when they are about to return to the U-mode, and the process exit status is the
+//! one of the group exit.
+
+use crate::mm::activate_identity_map;
+use crate::mm::uaccess::write_user;
+use crate::proc::futex::{futex_key, futex_wake};
+use crate::proc::process::Process;
+use crate::proc::signal::start_group_exit;
+use crate::proc::task::TaskInfo;
+use crate::proc::user::free_user_thread;
+use crate::sched::schedule;
+
+
+/// Build the exit status of a normal exit with the exit `code`.
+#[inline(always)]
+pub const fn exit_status(code: usize) -> usize {
+    (code & 0xff) << 8
+}
+
+/// Exit the user thread `task` running on this hart with the `status`. If it is the last
+/// thread, the process becomes a zombie. The init process can not exit.
+pub fn do_exit(task: &mut TaskInfo, status: usize) -> ! {
+    exit_thread(task, status);
+
+    // The thread has gone, never return.
+    schedule();
+    unreachable!()
+}
+
+/// Exit the process of the user thread `task` running on this hart with the `status`, the
+/// other threads are killed.
+pub fn do_exit_group(task: &mut TaskInfo, status: usize) -> ! {
+    exit_group_thread(task, status);
+    schedule();
+    unreachable!()
+}
+
+/// Exit the process of the user thread `task` like [`do_exit_group`], but return without
+/// scheduling. The `task` is running on this hart, or has been taken from the ready list and
+/// not run.
+pub fn exit_group_thread(task: &mut TaskInfo, status: usize) {
+    start_group_exit(task, status);
+    exit_thread(task, status);
+}
+
+/// Exit the user thread `task` like [`do_exit`], but return without scheduling.
+fn exit_thread(task: &mut TaskInfo, status: usize) {
+    let process = task.process();
+    let status = unsafe { (*process).signal().group_exit().unwrap_or(status) };
+    let pid = unsafe { (*process).pid() };
+    assert_ne!(pid, 1, "Attempted to kill init, exit status: {:#x}.", status);
+    debug!("Thread {} of PID {} exit with status {:#x}.", task.tid(), pid, status);
+    task.set_exit_code(status);
+    if task.clear_child_tid() != 0 {
+        let aspace = unsafe { (*process).address_space_mut() };
+        let addr = task.clear_child_tid();
+        if write_user(aspace, addr, &0i32).is_ok() {
+            if let Ok(key) = futex_key(aspace, addr, false) {
+                futex_wake(key, 1);
+            }
+        }
+    }
+
+    // The address space may be destroyed, switch to the kernel one first.
+    activate_identity_map();
+    unsafe {
+        if (*process).thread_count() > 1 {
+            free_user_thread(task);
+        } else {
+            Process::exit_notify(process, task, status);
+        }
+    }
+}
+
+/// Children to wait for.
+#[derive(Copy, Clone)]
+pub enum WaitTarget {
+    /// Any child.
+    Any,
+    /// The child with the PID.
+    Pid(u32),
+    /// Any child in the process group.
+    Group(u32),
+}
+
+impl WaitTarget {
+    fn matches(self, child: &Process) -> bool {
+        match self {
+            WaitTarget::Any => true,
+            WaitTarget::Pid(pid) => child.pid() == pid,
+            WaitTarget::Group(pgid) => child.pgid() == pgid,
+        }
+    }
+}
+
+/// Reap an exited child of the process of `task` which matches the `target`. Returns the PID
+/// and the exit status of the child, or `Ok(None)` if the matching children are all running.
+/// Returns `Err` with `E_CHILD` if no child matches.
+///
+/// If `block` and no matching child has exited, the `task` is added to the wait queue of the
+/// process before `Ok(None)` is returned, the caller should call `schedule` and retry after
+/// the task is woken up.
+pub fn do_wait(task: &mut TaskInfo, target: WaitTarget, block: bool)
+    -> Result<Option<(u32, usize)>, i32> {
+    let process = unsafe { &mut *task.process() };
+    let sleeper = if block { task as *mut TaskInfo } else { core::ptr::null_mut() };
+    let Some(child) = process.take_zombie_child(|child| target.matches(child), sleeper)? else {
+        return Ok(None);
+    };
+
+    let ret = unsafe { ((*child).pid(), (*child).exit_status()) };
+    unsafe { Process::reap(child); }
+    Ok(Some(ret))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errno::{E_CHILD, E_PERM, E_SRCH};
+    use crate::proc::process::{find_process, set_pgid, set_sid};
+    use crate::proc::task::TaskStatus;
+    use crate::proc::user::build_user_thread;
+    use crate::util::list;
+
+    /// Create a process with its main thread, which is not added to the ready list.
+    fn create_process(parent: Option<&mut Process>) -> (&'static mut Process, *mut TaskInfo) {
+        let process = unsafe { &mut *Process::create() };
+        let task = build_user_thread(process, 0, 0);
+        assert!(!task.is_null());
+        if let Some(parent) = parent {
+            process.link_to_parent(parent);
+        }
+        (process, task)
+    }
+
+    /// Free a process which has no children and is not a zombie.
+    fn destroy_process(process: &mut Process, task: *mut TaskInfo) {
+        unsafe {
+            free_user_thread(task);
+            Process::destroy(process);
+        }
+    }
+
+    #[kernel_test]
+    fn wait_reaps_zombie() {
+        let (parent, parent_task) = create_process(None);
+        let parent_task = unsafe { &mut *parent_task };
+        assert_eq!(do_wait(parent_task, WaitTarget::Any, false), Err(E_CHILD));
+        let (child, child_task) = create_process(Some(parent));
+        let pid = child.pid();
+        assert_eq!(child.ppid(), parent.pid());
+        assert_eq!(do_wait(parent_task, WaitTarget::Pid(pid + 1), false), Err(E_CHILD));
+        assert_eq!(do_wait(parent_task, WaitTarget::Pid(pid), false), Ok(None));
+
+        // A blocking wait sleeps on the children exit.
+        assert_eq!(do_wait(parent_task, WaitTarget::Any, true), Ok(None));
+        assert_eq!(parent_task.status(), TaskStatus::InterruptibleSleep);
+        list::delete(&mut parent_task.list);
+        parent_task.set_status(TaskStatus::Ready);
+
+        // The zombie keeps its PID until it is reaped.
+        unsafe { Process::exit_notify(child, &mut *child_task, exit_status(3)); }
+        assert!(child.is_zombie());
+        assert_eq!(unsafe { (*child_task).status() }, TaskStatus::DeadZombie);
+        assert_eq!(find_process(pid), child as *mut Process);
+        assert_eq!(do_wait(parent_task, WaitTarget::Any, false), Ok(Some((pid, 0x300))));
+        assert!(find_process(pid).is_null());
+        assert_eq!(do_wait(parent_task, WaitTarget::Any, false), Err(E_CHILD));
+        destroy_process(parent, parent_task);
+    }
+
+    #[kernel_test]
+    fn process_groups() {
+        let (parent, parent_task) = create_process(None);
+        let (child, child_task) = create_process(Some(parent));
+        let (other, other_task) = create_process(Some(parent));
+        let (stranger, stranger_task) = create_process(None);
+        assert_eq!((child.pgid(), child.sid()), (parent.pid(), parent.pid()));
+
+        // A session leader can not change its group, a child gets a new group in the session.
+        assert_eq!(set_pgid(parent, 0, 0), Err(E_PERM));
+        assert_eq!(set_pgid(parent, stranger.pid(), 0), Err(E_SRCH));
+        assert_eq!(set_pgid(parent, child.pid(), 0), Ok(()));
+        assert_eq!(child.pgid(), child.pid());
+        assert_eq!(set_pgid(parent, other.pid(), stranger.pid()), Err(E_PERM));
+        assert_eq!(set_pgid(other, 0, child.pid()), Ok(()));
+        assert_eq!(other.pgid(), child.pid());
+
+        // Wait for the children in a group.
+        let parent_task = unsafe { &mut *parent_task };
+        let group = WaitTarget::Group(child.pid());
+        assert_eq!(do_wait(parent_task, WaitTarget::Group(parent.pid()), false), Err(E_CHILD));
+        assert_eq!(do_wait(parent_task, group, false), Ok(None));
+        let pid = other.pid();
+        unsafe { Process::exit_notify(other, &mut *other_task, exit_status(0)); }
+        assert_eq!(do_wait(parent_task, group, false), Ok(Some((pid, 0))));
+
+        // A group leader can not create a session, the new session has a new group.
+        assert_eq!(set_sid(child), Err(E_PERM));
+        assert_eq!(set_pgid(child, 0, parent.pid()), Ok(()));
+        assert_eq!(set_sid(child), Ok(child.pid()));
+        assert_eq!((child.pgid(), child.sid()), (child.pid(), child.pid()));
+        assert_eq!(set_pgid(parent, child.pid(), 0), Err(E_PERM));
+
+        let pid = child.pid();
+        unsafe { Process::exit_notify(child, &mut *child_task, exit_status(1)); }
+        assert_eq!(do_wait(parent_task, WaitTarget::Pid(pid), false), Ok(Some((pid, 0x100))));
+        destroy_process(parent, parent_task);
+        destroy_process(stranger, stranger_task);
+    }
+}
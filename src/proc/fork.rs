@@ -0,0 +1,266 @@
+//! Create processes and threads by `clone`.
+//!
+//! Without `CLONE_THREAD`, `clone` forks the calling process: the child gets a copy of the
+//! address space which shares the pages copy-on-write (see [`AddressSpace::fork`]), and a copy
+//! of the fd table and the current directory. Sharing the files, the file system info or the
+//! signal handlers with a new process is not supported and fails with `EINVAL`.
+//!
+//! With `CLONE_VFORK`, the calling thread is suspended until the child execs or exits.
+//! `CLONE_VM` without `CLONE_THREAD` is only accepted with `CLONE_VFORK` (like `vfork` and the
+//! `posix_spawn` of musl): the child still gets a copy-on-write address space, so its writes are
+//! not seen by the parent, which does not matter to a child which only execs or exits.
+//!
+//! A new thread of the calling process is created with
+//! `CLONE_VM | CLONE_FS | CLONE_FILES | CLONE_SIGHAND | CLONE_THREAD`, all of these are required
+//! because the threads of a process share all of them.
+//!
+//! A forked child is linked to the process tree as a child of the calling process, and
+//! inherits its process group, session, signal actions, open files and current directory. The
+//! new thread inherits the blocked signal mask of the calling thread.
+//!
+//! In both cases the new thread resumes from the same `pc` with the same registers as the
+//! calling thread, except that `a0` (the return value of `clone`) is 0.
+//!
+//! [`AddressSpace::fork`]: crate::mm::address_space::AddressSpace::fork
+
+use crate::arch::cpu::{self, Register};
+use crate::errno::{E_INVALID, E_NO_MEM};
+use crate::mm::uaccess::write_user;
+use crate::proc::process::Process;
+use crate::proc::task::TaskInfo;
+use crate::proc::user::build_user_thread;
+use crate::sched::ready_list_add_task;
+
+
+/// Mask of the signal sent to the parent when the child exits.
+pub const CSIGNAL: usize = 0x0000_00ff;
+/// Share the address space.
+pub const CLONE_VM: usize = 0x0000_0100;
+/// Share the file system info.
+pub const CLONE_FS: usize = 0x0000_0200;
+/// Share the file descriptor table.
+pub const CLONE_FILES: usize = 0x0000_0400;
+/// Share the signal handlers.
+pub const CLONE_SIGHAND: usize = 0x0000_0800;
+/// Suspend the parent until the child execs or exits.
+pub const CLONE_VFORK: usize = 0x0000_4000;
+/// Create a thread in the same thread group.
+pub const CLONE_THREAD: usize = 0x0001_0000;
+/// Share the System V semaphore undo values.
+pub const CLONE_SYSVSEM: usize = 0x0004_0000;
+/// Set the `tp` register of the child to the `tls` argument.
+pub const CLONE_SETTLS: usize = 0x0008_0000;
+/// Write the child `tid` to the `parent_tid` address of the parent.
+pub const CLONE_PARENT_SETTID: usize = 0x0010_0000;
+/// Clear the `child_tid` address of the child when the child exits.
+pub const CLONE_CHILD_CLEARTID: usize = 0x0020_0000;
+/// Ignored, kept for the compatibility.
+pub const CLONE_DETACHED: usize = 0x0040_0000;
+/// Write the child `tid` to the `child_tid` address of the child.
+pub const CLONE_CHILD_SETTID: usize = 0x0100_0000;
+
+/// All supported `clone` flags.
+const CLONE_SUPPORTED: usize = CSIGNAL | CLONE_VM | CLONE_FS | CLONE_FILES | CLONE_SIGHAND |
+    CLONE_VFORK | CLONE_THREAD | CLONE_SYSVSEM | CLONE_SETTLS | CLONE_PARENT_SETTID |
+    CLONE_CHILD_CLEARTID | CLONE_DETACHED | CLONE_CHILD_SETTID;
+
+/// Flags required by `CLONE_THREAD`.
+const CLONE_THREAD_SHARED: usize = CLONE_VM | CLONE_FS | CLONE_FILES | CLONE_SIGHAND;
+
+/// Arguments of `clone`.
+pub struct CloneArgs {
+    pub flags: usize,
+    /// Stack pointer of the new thread, 0 to use the stack pointer of the calling thread.
+    pub stack: usize,
+    pub parent_tid: usize,
+    pub tls: usize,
+    pub child_tid: usize,
+}
+
+/// Create a new process or thread from the user thread `task` by the `args`, and add the new
+/// thread to the ready list. The new thread resumes from the trap frame of `task`, so the
+/// `pc` of the trap frame must have been advanced past the `ecall`.
+///
+/// With `CLONE_VFORK`, the `task` is added to a wait queue of the child before the child can
+/// run, the caller should call `schedule` later.
+///
+/// Returns the `tid` of the new thread (the PID if a process is created), or `Err` with the
+/// errno.
+pub fn do_clone(task: &mut TaskInfo, args: &CloneArgs) -> Result<u32, i32> {
+    let flags = args.flags;
+    let valid = if flags & CLONE_THREAD != 0 {
+        flags & CLONE_THREAD_SHARED == CLONE_THREAD_SHARED && flags & CLONE_VFORK == 0
+    } else {
+        flags & (CLONE_FS | CLONE_FILES | CLONE_SIGHAND) == 0 &&
+            (flags & CLONE_VM == 0 || flags & CLONE_VFORK != 0)
+    };
+    if flags & !CLONE_SUPPORTED != 0 || !valid {
+        return Err(E_INVALID);
+    }
+
+    let parent = unsafe { &mut *task.process() };
+    let frame = task.trap_frame();
+    let process = if flags & CLONE_THREAD != 0 {
+        parent as *mut Process
+    } else {
+        let aspace = parent.address_space_mut().fork()?;
+        let child = Process::create_with(aspace);
+        if !child.is_null() {
+            unsafe {
+                (*child).link_to_parent(parent);
+                (*child).with_signal(|signal, _| signal.inherit(parent.signal()));
+                (*child).inherit_files(parent);
+                (*child).set_comm(parent.comm().as_bytes());
+            }
+        }
+        child
+    };
+    if process.is_null() {
+        return Err(E_NO_MEM);
+    }
+
+    let sp = if args.stack != 0 { args.stack } else { frame.regs[cpu::reg(Register::Sp)] };
+    let child = build_user_thread(unsafe { &mut *process }, frame.pc, sp);
+    if child.is_null() {
+        if process != parent as *mut Process {
+            unsafe { Process::destroy(process); }
+        }
+        return Err(E_NO_MEM);
+    }
+
+    let child = unsafe { &mut *child };
+    child.signal_mut().inherit(task.signal());
+    let child_frame = child.trap_frame_mut();
+    child_frame.regs = frame.regs;
+    child_frame.fregs = frame.fregs;
+    child_frame.fcsr = frame.fcsr;
+    child_frame.regs[cpu::reg(Register::Sp)] = sp;
+    child_frame.regs[cpu::reg(Register::A0)] = 0;
+    if flags & CLONE_SETTLS != 0 {
+        child_frame.regs[cpu::reg(Register::Tp)] = args.tls;
+    }
+
+    // Like Linux, a bad tid address does not fail the `clone`.
+    let tid = child.tid();
+    if flags & CLONE_PARENT_SETTID != 0 {
+        let _ = write_user(parent.address_space_mut(), args.parent_tid, &(tid as i32));
+    }
+    if flags & CLONE_CHILD_SETTID != 0 {
+        let aspace = unsafe { (*process).address_space_mut() };
+        let _ = write_user(aspace, args.child_tid, &(tid as i32));
+    }
+    if flags & CLONE_CHILD_CLEARTID != 0 {
+        child.set_clear_child_tid(args.child_tid);
+    }
+
+    if flags & CLONE_VFORK != 0 {
+        unsafe { (*process).vfork_sleep_on(task); }
+    }
+    ready_list_add_task(child);
+    Ok(tid)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use core::ptr::null_mut;
+    use super::*;
+    use crate::mm::address_space::MapPlacement;
+    use crate::mm::mmu::EntryBits;
+    use crate::mm::{page, PAGE_SIZE};
+    use crate::proc::process::find_process;
+    use crate::proc::signal::SIGCHLD;
+    use crate::proc::task::TaskStatus;
+    use crate::proc::user::free_user_thread;
+    use crate::sched::{preempt_disable, preempt_enable};
+    use crate::util::list;
+
+    /// Clone the `task` by the `flags`. The new thread is taken off the ready list, returns it
+    /// with its process.
+    fn clone_task(task: &mut TaskInfo, flags: usize)
+        -> Result<(&'static mut Process, *mut TaskInfo), i32> {
+        let args = CloneArgs { flags, stack: 0, parent_tid: 0, tls: 0, child_tid: 0 };
+        preempt_disable();
+        let ret = do_clone(task, &args).map(|tid| {
+            let process = if flags & CLONE_THREAD != 0 {
+                unsafe { &mut *task.process() }
+            } else {
+                unsafe { &mut *find_process(tid) }
+            };
+            let child = process.find_thread(tid);
+            list::delete(unsafe { &mut (*child).list });
+            (process, child)
+        });
+        preempt_enable();
+        ret
+    }
+
+    #[kernel_test]
+    fn fork_copy_on_write() {
+        let parent = unsafe { &mut *Process::create() };
+        let task = unsafe { &mut *build_user_thread(parent, 0, 0) };
+        let rw = EntryBits::ReadWrite.val();
+        let aspace = parent.address_space_mut();
+        let addr = aspace.map_anonymous(MapPlacement::Hint(0), PAGE_SIZE, rw, false).unwrap();
+        assert!(aspace.write_bytes(addr, &[1, 2, 3]));
+        let pa = aspace.translate(addr).unwrap();
+
+        let (child, child_task) = clone_task(task, SIGCHLD).unwrap();
+        assert_ne!(child as *mut Process, parent as *mut Process);
+        assert_eq!(child.ppid(), parent.pid());
+        assert_eq!(child.address_space().translate(addr), Some(pa));
+        assert_eq!(page::page_ref_count(pa), 2);
+
+        // The parent writes to its own copy, the child still sees the old data.
+        assert!(parent.address_space_mut().write_bytes(addr, &[7]));
+        let parent_pa = parent.address_space().translate(addr).unwrap();
+        assert_ne!(parent_pa, pa);
+        assert_eq!(child.address_space().translate(addr), Some(pa));
+        assert_eq!(unsafe { *(parent_pa as *const [u8; 3]) }, [7, 2, 3]);
+        assert_eq!(unsafe { *(pa as *const [u8; 3]) }, [1, 2, 3]);
+        assert_eq!(page::page_ref_count(pa), 1);
+        assert_eq!(page::page_ref_count(parent_pa), 1);
+
+        unsafe {
+            free_user_thread(child_task);
+            Process::destroy(child);
+            free_user_thread(task);
+            Process::destroy(parent);
+        }
+    }
+
+    #[kernel_test]
+    fn clone_flags_and_vfork() {
+        let parent = unsafe { &mut *Process::create() };
+        let task = unsafe { &mut *build_user_thread(parent, 0, 0) };
+
+        // The fd table, the file system info and the address space are not shared with a new
+        // process, and a thread must share all of them.
+        for flags in [CLONE_FILES, CLONE_FS, CLONE_VM, CLONE_VM | CLONE_SIGHAND,
+                      CLONE_VM | CLONE_SIGHAND | CLONE_THREAD,
+                      CLONE_THREAD_SHARED | CLONE_THREAD | CLONE_VFORK, 1 << 31] {
+            assert_eq!(clone_task(task, flags).err(), Some(E_INVALID), "flags {:#x}", flags);
+        }
+        let (process, thread) = clone_task(task, CLONE_THREAD_SHARED | CLONE_THREAD).unwrap();
+        assert_eq!(process as *mut Process, parent as *mut Process);
+        assert_eq!(parent.thread_count(), 2);
+        unsafe { free_user_thread(thread); }
+
+        // The parent thread is suspended until the `posix_spawn` child exits.
+        let (child, child_task) = clone_task(task, CLONE_VM | CLONE_VFORK | SIGCHLD).unwrap();
+        assert_eq!(task.status(), TaskStatus::UninterruptibleSleep);
+        preempt_disable();
+        unsafe { Process::exit_notify(child, &mut *child_task, 0); }
+        assert_eq!(task.status(), TaskStatus::Ready);
+        list::delete(&mut task.list);
+        preempt_enable();
+
+        let zombie = parent.take_zombie_child(|_| true, null_mut()).unwrap().unwrap();
+        assert_eq!(zombie, child as *mut Process);
+        unsafe {
+            Process::reap(zombie);
+            free_user_thread(task);
+            Process::destroy(parent);
+        }
+    }
+}
@@ -0,0 +1,250 @@
+//! Fast user-space mutex (futex) wait queues.
+//!
+//! A user thread waits on a 32-bit user word by `FUTEX_WAIT` if the word still has the expected
+//! value, and is woken up by `FUTEX_WAKE` on the same word. The waiters are identified by a
+//! [`FutexKey`]: a private futex (or a futex in a private mapping) is keyed by the address space
+//! and the virtual address, a futex in a shared mapping is keyed by the physical address, so the
+//! processes sharing the page can wait on it together.
+//!
+//! The waiters are linked by the `list` of [`TaskInfo`] into the hash buckets with the
+//! `InterruptibleSleep` status, like a [`WaitQueue`], so a signal can wake them up. All buckets are
+//! protected by one lock, which is also held when the user word is compared: a waker changes the
+//! word before taking the lock, so the wake up can not be lost. A wait with a timeout records the
+//! wake time in the task, and the timer interrupt wakes up the expired waiters with `ETIMEDOUT`
+//! by [`wake_up_timed_out`].
+//!
+//! [`WaitQueue`]: crate::sched::WaitQueue
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::arch::cpu::{self, Register};
+use crate::base::sync::lock::SpinLockPure;
+use crate::errno::{E_AGAIN, E_FAULT, E_INVALID, E_TIMED_OUT};
+use crate::mm::address_space::AddressSpace;
+use crate::mm::uaccess::read_user;
+use crate::proc::task::{TaskInfo, TaskStatus};
+use crate::sched::ready_list_add_task;
+use crate::util::list::{self, List};
+
+
+/// Count of the hash buckets.
+const FUTEX_BUCKETS: usize = 64;
+/// Wake time of a waiter without a timeout.
+const NO_TIMEOUT: usize = usize::MAX;
+
+/// Key of a futex.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct FutexKey {
+    /// Address of the [`AddressSpace`] of a private futex, 0 for a shared one.
+    aspace: usize,
+    /// Virtual address of a private futex, or the physical address of a shared one.
+    addr: usize,
+}
+
+impl FutexKey {
+    #[inline(always)]
+    fn bucket(&self) -> usize {
+        ((self.addr >> 2) ^ (self.aspace >> 6)) % FUTEX_BUCKETS
+    }
+}
+
+const EMPTY_QUEUE: List = List::new();
+static mut FUTEX_QUEUES: [List; FUTEX_BUCKETS] = [EMPTY_QUEUE; FUTEX_BUCKETS];
+static FUTEX_LOCK: SpinLockPure = SpinLockPure::new();
+/// Count of the waiters with a timeout, may be larger than the real count. The timer interrupt
+/// skips the scan if it is 0.
+static TIMED_WAITERS: AtomicUsize = AtomicUsize::new(0);
+
+pub(super) fn init() {
+    unsafe {
+        for queue in (*core::ptr::addr_of_mut!(FUTEX_QUEUES)).iter_mut() {
+            queue.init_empty();
+        }
+    }
+}
+
+/// Get the key of the futex word at the user address `addr`. A `private` futex is always keyed
+/// by the address space, otherwise a futex in a shared mapping is keyed by the physical page,
+/// which is populated first.
+///
+/// Returns `Err` with `E_INVALID` if the `addr` is not aligned, or `E_FAULT` if not mapped.
+pub fn futex_key(aspace: &mut AddressSpace, addr: usize, private: bool) -> Result<FutexKey, i32> {
+    if addr & 3 != 0 {
+        return Err(E_INVALID);
+    }
+    let vma = aspace.find_vma(addr).ok_or(E_FAULT)?;
+    if private || vma.is_private() {
+        return Ok(FutexKey { aspace: aspace as *mut AddressSpace as usize, addr });
+    }
+
+    read_user::<u32>(aspace, addr)?;
+    let p_addr = aspace.translate(addr).ok_or(E_FAULT)?;
+    Ok(FutexKey { aspace: 0, addr: p_addr })
+}
+
+/// Call `handle` on each waiter of the futex `key`, until it returns false. The `handle` may
+/// remove the waiter from the bucket. The [`FUTEX_LOCK`] must be held.
+unsafe fn for_each_waiter<F>(key: FutexKey, mut handle: F) where F: FnMut(&mut TaskInfo) -> bool {
+    let head = &mut (*core::ptr::addr_of_mut!(FUTEX_QUEUES))[key.bucket()] as *mut List;
+    let mut cur = (*head).next;
+    while cur != head {
+        let next = (*cur).next;
+        let task = &mut *container_of_mut!(cur, TaskInfo, list);
+        if task.futex_key() == key && !handle(task) {
+            break;
+        }
+        cur = next;
+    }
+}
+
+/// Remove the waiter `task` from its bucket, and move it to the ready list.
+fn wake_waiter(task: &mut TaskInfo) {
+    list::delete(&mut task.list);
+    ready_list_add_task(task);
+}
+
+/// Add the current thread `task` to the waiters of the futex `key` if the word at the user
+/// address `addr` is still `val`. The thread is woken up after `timeout` timer ticks at most
+/// (`None` for no limit). The caller should set the syscall return value to 0 and call
+/// `schedule` later; the timeout returns `ETIMEDOUT` and a signal returns `EINTR` instead.
+///
+/// Returns `Err` with `E_AGAIN` if the word is not `val`, or `E_FAULT` if it can not be read.
+pub fn futex_wait(task: &mut TaskInfo, key: FutexKey, addr: usize, val: u32,
+                  timeout: Option<u64>) -> Result<(), i32> {
+    let aspace = unsafe { (*task.process()).address_space_mut() };
+    let _guard = FUTEX_LOCK.lock_guard_irq_save();
+    if read_user::<u32>(aspace, addr)? != val {
+        return Err(E_AGAIN);
+    }
+
+    let wake_time = match timeout {
+        Some(ticks) => {
+            TIMED_WAITERS.fetch_add(1, Ordering::Relaxed);
+            (cpu::read_time() as u64).saturating_add(ticks).min(NO_TIMEOUT as u64 - 1) as usize
+        }
+        None => NO_TIMEOUT,
+    };
+    task.set_wake_time(wake_time);
+    task.set_futex_key(key);
+    task.set_status(TaskStatus::InterruptibleSleep);
+    let queue = unsafe { &mut (*core::ptr::addr_of_mut!(FUTEX_QUEUES))[key.bucket()] };
+    list::tail_append(queue, &mut task.list);
+
+    Ok(())
+}
+
+/// Wake up at most `count` waiters of the futex `key`. Returns the count of the woken waiters.
+pub fn futex_wake(key: FutexKey, count: usize) -> usize {
+    let _guard = FUTEX_LOCK.lock_guard_irq_save();
+    let mut woken = 0;
+    unsafe {
+        for_each_waiter(key, |task| {
+            if woken >= count {
+                return false;
+            }
+            wake_waiter(task);
+            woken += 1;
+            true
+        });
+    }
+
+    woken
+}
+
+/// Wake up at most `wake` waiters of the futex `from`, then move at most `requeue` other waiters
+/// to the futex `to`. If `cmp` is given, the word at the user address `addr` of the `aspace` is
+/// compared with it first.
+///
+/// Returns the count of the woken and moved waiters, or `Err` with `E_AGAIN` if the word is not
+/// `cmp`.
+pub fn futex_requeue(aspace: &mut AddressSpace, addr: usize, from: FutexKey, to: FutexKey,
+                     wake: usize, requeue: usize, cmp: Option<u32>) -> Result<usize, i32> {
+    let _guard = FUTEX_LOCK.lock_guard_irq_save();
+    if let Some(val) = cmp {
+        if read_user::<u32>(aspace, addr)? != val {
+            return Err(E_AGAIN);
+        }
+    }
+
+    let (mut woken, mut moved) = (0, 0);
+    let to_queue = unsafe { &mut (*core::ptr::addr_of_mut!(FUTEX_QUEUES))[to.bucket()] };
+    unsafe {
+        for_each_waiter(from, |task| {
+            if woken < wake {
+                wake_waiter(task);
+                woken += 1;
+            } else if moved < requeue && from != to {
+                // A waiter moved to the tail of the same bucket has the other key, and is skipped.
+                list::delete(&mut task.list);
+                task.set_futex_key(to);
+                list::tail_append(to_queue, &mut task.list);
+                moved += 1;
+            } else {
+                return false;
+            }
+            true
+        });
+    }
+
+    Ok(woken + moved)
+}
+
+/// Wake up the futex waiters whose timeout expired at the timer value `now`, the waiting
+/// syscalls return `ETIMEDOUT`. Called by the timer interrupt.
+pub fn wake_up_timed_out(now: usize) {
+    if TIMED_WAITERS.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+
+    let _guard = FUTEX_LOCK.lock_guard_irq_save();
+    let mut timed = 0;
+    for queue in unsafe { (*core::ptr::addr_of_mut!(FUTEX_QUEUES)).iter_mut() } {
+        let head = queue as *mut List;
+        let mut cur = queue.next;
+        while cur != head {
+            let task = unsafe { &mut *container_of_mut!(cur, TaskInfo, list) };
+            cur = unsafe { (*cur).next };
+            if task.wake_time() <= now {
+                task.trap_frame_mut().regs[cpu::reg(Register::A0)] =
+                    -E_TIMED_OUT as isize as usize;
+                wake_waiter(task);
+            } else if task.wake_time() != NO_TIMEOUT {
+                timed += 1;
+            }
+        }
+    }
+    TIMED_WAITERS.store(timed, Ordering::Relaxed);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mm::address_space::MapPlacement;
+    use crate::mm::mmu::EntryBits;
+    use crate::mm::uaccess::write_user;
+
+    #[kernel_test]
+    fn futex_keys() {
+        let mut aspace = AddressSpace::new().unwrap();
+        let rw = EntryBits::ReadWrite.val();
+        let private = aspace.map_anonymous(MapPlacement::Hint(0), 4096, rw, false).unwrap();
+        let shared = aspace.map_anonymous(MapPlacement::Hint(0), 4096, rw, true).unwrap();
+        assert_eq!(futex_key(&mut aspace, private + 2, false), Err(E_INVALID));
+        assert_eq!(futex_key(&mut aspace, private - 4096, false), Err(E_FAULT));
+
+        // A shared futex is keyed by the physical page, unless the private flag is set.
+        let key = futex_key(&mut aspace, private + 8, false).unwrap();
+        assert_eq!(key.addr, private + 8);
+        assert_ne!(key.aspace, 0);
+        let key = futex_key(&mut aspace, shared + 8, false).unwrap();
+        assert_eq!((key.aspace, key.addr), (0, aspace.translate(shared + 8).unwrap()));
+        assert_eq!(futex_key(&mut aspace, shared + 8, true).unwrap().addr, shared + 8);
+
+        // Nothing to wake or requeue, the compared word must match.
+        write_user(&mut aspace, private, &5u32).unwrap();
+        assert_eq!(futex_wake(key, 1), 0);
+        assert_eq!(futex_requeue(&mut aspace, private, key, key, 1, 1, Some(4)), Err(E_AGAIN));
+        assert_eq!(futex_requeue(&mut aspace, private, key, key, 1, 1, Some(5)), Ok(0));
+        unsafe { aspace.destroy(); }
+    }
+}
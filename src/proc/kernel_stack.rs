@@ -44,6 +44,8 @@ pub struct KernelTrapFrame {
     pub cpu_stack: *const HartFrameInfo,
     // 528
     pub user_frame: *mut TaskTrapFrame,
+    // 536
+    pub fcsr: usize,
 }
 
 const KERNEL_STACK_SIZE: usize = PAGE_SIZE - size_of::<usize>() - size_of::<KernelTrapFrame>();
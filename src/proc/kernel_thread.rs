@@ -27,6 +27,9 @@ pub unsafe fn build_kernel_thread_on_place(
 }
 
 
+/// Stack size of the kernel thread, in the page order. 2^2 pages, 16KiB.
+pub const KERNEL_THREAD_STACK_ORDER: usize = 2;
+
 /// Kernel tid allocation counter.
 static KERNEL_TID: AtomicU32 = AtomicU32::new(0);
 
@@ -38,7 +41,7 @@ impl ThreadBuilder {
     pub fn new_on_place(entry: ThreadEntry, user_data: *mut (), ptr: *mut TaskInfo) -> Option<Self> {
         // todo: use vmalloc to get a virtual address protection.
         // Kernel thread has a stack size of 2^2 pages, 16KiB.
-        let stack = page::alloc_pages(0, 2);    // todo: const val = 2
+        let stack = page::alloc_pages(0, KERNEL_THREAD_STACK_ORDER);
         if stack == 0 {
             return None;
         }
@@ -64,7 +67,7 @@ impl ThreadBuilder {
             *regs.get_unchecked_mut(cpu::reg(Register::A1)) = user_data as _;
             *regs.get_unchecked_mut(cpu::reg(Register::A2)) = ptr as _;
             // Set thread stack. Stack is growing from high to low address.
-            let top = stack + PAGE_SIZE * (1usize << 2) - size_of::<usize>();
+            let top = stack + PAGE_SIZE * (1usize << KERNEL_THREAD_STACK_ORDER) - size_of::<usize>();
             *regs.get_unchecked_mut(cpu::reg(Register::Sp)) = top;
         }
 
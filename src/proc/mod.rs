@@ -1,10 +1,20 @@
 //! Controls the lifetime of a process, provides the interfaces to operate with a process.
 
 pub(crate) mod task;
+pub(crate) mod elf;
+pub(crate) mod exec;
+pub(crate) mod exit;
+pub(crate) mod fork;
+pub(crate) mod futex;
+pub(crate) mod pid;
+pub(crate) mod process;
+pub(crate) mod signal;
+pub(crate) mod user;
 mod idle;
 mod kernel_context;
 mod kernel_stack;
 mod kernel_thread;
+#[cfg(not(test))]
 mod kernel_test;
 
 /// Kernel stack and kernel thread structs and functions definition. This mod should only be
@@ -21,9 +31,13 @@ pub(crate) mod kernel {
     }
 }
 
+#[cfg(not(test))]
 pub use kernel_test::add_test_kernel_threads;
+#[cfg(not(test))]
+pub use process::create_init_process;
 
 
 pub fn init() {
-    //
+    process::init();
+    futex::init();
 }
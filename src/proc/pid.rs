@@ -0,0 +1,66 @@
+//! PID allocator.
+//!
+//! The PIDs are allocated from a bitmap in the range \[1, [`PID_MAX`]). PID 0 is reserved for
+//! the kernel threads. Like the Linux, a new PID is searched from the last allocated one, so a
+//! freed PID is not reused immediately.
+
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+
+/// Max PID value (exclusive).
+pub const PID_MAX: u32 = 32768;
+
+const BITS_PER_WORD: u32 = u64::BITS;
+
+static PID_MAP: [AtomicU64; (PID_MAX / BITS_PER_WORD) as usize] =
+    [const { AtomicU64::new(0) }; (PID_MAX / BITS_PER_WORD) as usize];
+/// Last allocated PID.
+static LAST_PID: AtomicU32 = AtomicU32::new(0);
+
+/// Alloc a PID. Returns `None` if all PIDs are used.
+pub fn alloc_pid() -> Option<u32> {
+    let last = LAST_PID.load(Ordering::Relaxed);
+    for i in 1..PID_MAX {
+        let pid = (last + i) % PID_MAX;
+        if pid == 0 {
+            continue;
+        }
+
+        let bit = 1u64 << (pid % BITS_PER_WORD);
+        let word = &PID_MAP[(pid / BITS_PER_WORD) as usize];
+        if word.load(Ordering::Relaxed) & bit == 0 && word.fetch_or(bit, Ordering::AcqRel) & bit == 0 {
+            LAST_PID.store(pid, Ordering::Relaxed);
+            return Some(pid);
+        }
+    }
+
+    None
+}
+
+/// Free a PID allocated by [`alloc_pid`].
+pub fn free_pid(pid: u32) {
+    debug_assert!(pid != 0 && pid < PID_MAX);
+    let bit = 1u64 << (pid % BITS_PER_WORD);
+    let old = PID_MAP[(pid / BITS_PER_WORD) as usize].fetch_and(!bit, Ordering::AcqRel);
+    debug_assert!(old & bit != 0, "Free an unused PID {}.", pid);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[kernel_test]
+    fn alloc_unique_and_not_reused() {
+        let a = alloc_pid().unwrap();
+        let b = alloc_pid().unwrap();
+        assert_ne!(a, 0);
+        assert_ne!(a, b);
+
+        free_pid(a);
+        let c = alloc_pid().unwrap();
+        assert_ne!(c, a, "freed PID should not be reused immediately");
+        free_pid(b);
+        free_pid(c);
+    }
+}
@@ -0,0 +1,682 @@
+//! User process. A process owns a user [`AddressSpace`] and a list of threads (see
+//! [`build_user_thread`]), all threads of a process run on the same address space.
+//!
+//! All processes are linked in a global process list and can be found by the PID. The first
+//! thread of a process has a `tid` equal to the PID; other threads get their `tid` from the
+//! same PID allocator.
+//!
+//! The processes also form a tree: each process except the init one has a parent, which is
+//! notified when the process exits. An exited process becomes a *zombie*: the address space is
+//! destroyed, but the process object, the PID and the last thread (with the
+//! `TaskStatus::DeadZombie` status) are kept until the parent reaps it by `wait4`. The children
+//! of an exiting process are reparented to the init process. The tree and the process groups
+//! are protected by the process list lock.
+//!
+//! Each process belongs to a process group and a session, which are identified by the PID of
+//! the group leader and the session leader. A child inherits the group and the session of its
+//! parent.
+//!
+//! The process lock protects the thread list and the [`signal`] state of the process and its
+//! threads, and the current directory.
+//!
+//! The name of a process (`comm`) is the last component of the executable path, it is inherited
+//! by `fork` and replaced by `execve`.
+//!
+//! A process owns a [`FdTable`] shared by its threads. A forked child gets a copy of the table
+//! and the current directory, the files are closed when the process exits. A child created by
+//! `CLONE_VFORK` keeps the calling thread of the parent suspended until it execs or exits.
+//!
+//! [`AddressSpace`]: crate::mm::address_space::AddressSpace
+//! [`build_user_thread`]: crate::proc::user::build_user_thread
+//! [`signal`]: crate::proc::signal
+//! [`FdTable`]: crate::fs::FdTable
+
+use alloc::sync::Arc;
+use core::mem::size_of;
+use core::ptr::null_mut;
+use crate::base::sync::lock::SpinLockPure;
+use crate::errno::{E_CHILD, E_NO_MEM, E_PERM, E_SRCH};
+use crate::fs::console::console_file;
+use crate::fs::{Dentry, FdTable};
+use crate::mm::{kfree, kzalloc};
+use crate::mm::address_space::AddressSpace;
+use crate::proc::elf::{load_elf, user_init_elf, ElfSource};
+use crate::proc::exec::open_executable;
+use crate::proc::pid::{alloc_pid, free_pid};
+use crate::proc::signal::{send_signal, ProcessSignal, SIGCHLD};
+use crate::proc::task::{TaskInfo, TaskStatus};
+use crate::proc::user::{build_user_thread, free_user_thread};
+use crate::sched::{ready_list_add_task, WaitQueue};
+use crate::util::list::{self, List};
+
+
+/// Size of the process name, the NUL included.
+pub const TASK_COMM_LEN: usize = 16;
+
+/// Process struct.
+#[repr(C)]
+pub struct Process {
+    /// Entry of the global process list.
+    list: List,
+    /// Threads of the process, linked by the `thread_list` of [`TaskInfo`].
+    threads: List,
+    /// Entry of the `children` list of the parent.
+    sibling: List,
+    /// Child processes, linked by the `sibling`.
+    children: List,
+    /// Threads of this process waiting for a child to exit.
+    child_wait: WaitQueue,
+    /// Thread of the parent suspended by `CLONE_VFORK` until this process execs or exits.
+    vfork_wait: WaitQueue,
+    /// Parent process, null for the init process.
+    parent: *mut Process,
+    aspace: AddressSpace,
+    signal: ProcessSignal,
+    files: FdTable,
+    /// Current directory, `None` for the root.
+    cwd: Option<Arc<Dentry>>,
+    /// Name of the executable, NUL-padded.
+    comm: [u8; TASK_COMM_LEN],
+    lock: SpinLockPure,
+    pid: u32,
+    thread_count: u32,
+    /// Process group ID.
+    pgid: u32,
+    /// Session ID.
+    sid: u32,
+    /// Exit status in the `wait` format, valid if the process is a zombie.
+    exit_status: usize,
+    zombie: bool,
+}
+
+impl Process {
+    /// Create a process with an empty user address space and add it to the process list.
+    /// Returns null if out of memory or PIDs.
+    pub fn create() -> *mut Process {
+        match AddressSpace::new() {
+            Some(aspace) => Self::create_with(aspace),
+            None => null_mut(),
+        }
+    }
+
+    /// Create a process owning the address space `aspace` and add it to the process list.
+    /// Returns null if out of memory or PIDs, the `aspace` is destroyed in that case.
+    pub fn create_with(mut aspace: AddressSpace) -> *mut Process {
+        let Some(pid) = alloc_pid() else {
+            unsafe { aspace.destroy(); }
+            return null_mut();
+        };
+        let ptr = kzalloc(size_of::<Process>(), 0) as *mut Process;
+        if ptr.is_null() {
+            unsafe { aspace.destroy(); }
+            free_pid(pid);
+            return null_mut();
+        }
+
+        unsafe {
+            ptr.write(Process {
+                list: List::new(),
+                threads: List::new(),
+                sibling: List::new(),
+                children: List::new(),
+                child_wait: WaitQueue::new(),
+                vfork_wait: WaitQueue::new(),
+                parent: null_mut(),
+                aspace,
+                signal: ProcessSignal::new(),
+                files: FdTable::new(),
+                cwd: None,
+                comm: [0; TASK_COMM_LEN],
+                lock: SpinLockPure::new(),
+                pid,
+                thread_count: 0,
+                pgid: pid,
+                sid: pid,
+                exit_status: 0,
+                zombie: false,
+            });
+            let process = &mut *ptr;
+            process.threads.init_empty();
+            process.sibling.init_empty();
+            process.children.init_empty();
+            process.child_wait.init();
+            process.vfork_wait.init();
+            process.signal.init();
+
+            let _guard = PROCESS_LIST_LOCK.lock_guard_irq_save();
+            list::tail_append(&mut PROCESS_LIST, &mut process.list);
+        }
+
+        ptr
+    }
+
+    /// Get the PID.
+    #[inline(always)]
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// Get the PID of the parent, 0 for the init process.
+    pub fn ppid(&self) -> u32 {
+        let _guard = PROCESS_LIST_LOCK.lock_guard_irq_save();
+        if self.parent.is_null() { 0 } else { unsafe { (*self.parent).pid } }
+    }
+
+    /// Get the process group ID.
+    #[inline(always)]
+    pub fn pgid(&self) -> u32 {
+        self.pgid
+    }
+
+    /// Get the session ID.
+    #[inline(always)]
+    pub fn sid(&self) -> u32 {
+        self.sid
+    }
+
+    /// Get the name of the executable.
+    pub fn comm(&self) -> &str {
+        let len = self.comm.iter().position(|&c| c == 0).unwrap_or(TASK_COMM_LEN);
+        match core::str::from_utf8(&self.comm[..len]) {
+            Ok(name) => name,
+            // The name may be truncated in a multibyte character.
+            Err(err) => unsafe { core::str::from_utf8_unchecked(&self.comm[..err.valid_up_to()]) },
+        }
+    }
+
+    /// Set the name from the executable `path`, only the last component is kept and it is
+    /// truncated to `TASK_COMM_LEN - 1` bytes.
+    pub fn set_comm(&mut self, path: &[u8]) {
+        let name = path.rsplit(|&c| c == b'/').next().unwrap_or(path);
+        let len = name.len().min(TASK_COMM_LEN - 1);
+        self.comm = [0; TASK_COMM_LEN];
+        self.comm[..len].copy_from_slice(&name[..len]);
+    }
+
+    /// Check if the process has exited and not been reaped.
+    #[inline(always)]
+    pub fn is_zombie(&self) -> bool {
+        self.zombie
+    }
+
+    /// Get the exit status in the `wait` format, only valid for a zombie.
+    #[inline(always)]
+    pub fn exit_status(&self) -> usize {
+        self.exit_status
+    }
+
+    /// Add the process as a child of `parent`, the process group and the session are inherited.
+    pub fn link_to_parent(&mut self, parent: &mut Process) {
+        let _guard = PROCESS_LIST_LOCK.lock_guard_irq_save();
+        debug_assert!(self.parent.is_null());
+        self.parent = parent;
+        self.pgid = parent.pgid;
+        self.sid = parent.sid;
+        list::tail_append(&mut parent.children, &mut self.sibling);
+    }
+
+    /// Get the address space.
+    #[inline(always)]
+    pub fn address_space(&self) -> &AddressSpace {
+        &self.aspace
+    }
+
+    /// Get the mutable address space.
+    #[inline(always)]
+    pub fn address_space_mut(&mut self) -> &mut AddressSpace {
+        &mut self.aspace
+    }
+
+    /// Get the signal state, the fields may change without the process lock.
+    #[inline(always)]
+    pub fn signal(&self) -> &ProcessSignal {
+        &self.signal
+    }
+
+    /// Get the file descriptor table.
+    #[inline(always)]
+    pub fn files(&self) -> &FdTable {
+        &self.files
+    }
+
+    /// Get the mutable file descriptor table.
+    #[inline(always)]
+    pub fn files_mut(&mut self) -> &mut FdTable {
+        &mut self.files
+    }
+
+    /// Get the current directory, `None` for the root.
+    pub fn cwd(&self) -> Option<Arc<Dentry>> {
+        let _guard = self.lock.lock_guard_irq_save();
+        self.cwd.clone()
+    }
+
+    /// Set the current directory.
+    pub fn set_cwd(&mut self, cwd: Arc<Dentry>) {
+        let old = {
+            let _guard = self.lock.lock_guard_irq_save();
+            self.cwd.replace(cwd)
+        };
+        drop(old);
+    }
+
+    /// Copy the file descriptor table and the current directory of the `parent` for a forked
+    /// process.
+    pub fn inherit_files(&mut self, parent: &Process) {
+        self.files = parent.files.fork();
+        self.cwd = parent.cwd();
+    }
+
+    /// Close the files and release the current directory.
+    fn release_files(&mut self) {
+        self.files.close_all();
+        self.cwd = None;
+    }
+
+    /// Call `f` with the signal state and the thread list under the process lock.
+    pub fn with_signal<F, R>(&mut self, f: F) -> R
+        where F: FnOnce(&mut ProcessSignal, &mut List) -> R {
+        let _guard = self.lock.lock_guard_irq_save();
+        f(&mut self.signal, &mut self.threads)
+    }
+
+    /// Find the thread by `tid`. Returns null if not found.
+    pub fn find_thread(&mut self, tid: u32) -> *mut TaskInfo {
+        let _guard = self.lock.lock_guard_irq_save();
+        let mut ret = null_mut();
+        list::for_each(&mut self.threads, |cur| {
+            let task = unsafe { container_of_mut!(cur, TaskInfo, thread_list) };
+            if unsafe { (*task).tid() } == tid {
+                ret = task;
+                return false;
+            }
+            true
+        });
+
+        ret
+    }
+
+    /// Call `handle` on each thread until it returns false, under the process lock.
+    pub fn for_each_thread<F>(&mut self, mut handle: F) where F: FnMut(&TaskInfo) -> bool {
+        let _guard = self.lock.lock_guard_irq_save();
+        list::for_each(&mut self.threads, |cur| {
+            handle(unsafe { &*container_of_mut!(cur, TaskInfo, thread_list) })
+        });
+    }
+
+    /// Get the count of threads.
+    #[inline(always)]
+    pub fn thread_count(&self) -> u32 {
+        self.thread_count
+    }
+
+    /// Add a thread to the process. The `pid` and `satp` of the thread trap frame are set.
+    pub fn add_thread(&mut self, task: &mut TaskInfo) {
+        let _guard = self.lock.lock_guard_irq_save();
+        task.set_process(self);
+        let frame = task.trap_frame_mut();
+        frame.pid = self.pid as usize;
+        frame.satp = self.aspace.satp();
+        list::tail_append(&mut self.threads, &mut task.thread_list);
+        self.thread_count += 1;
+    }
+
+    /// Remove a thread from the process.
+    pub fn remove_thread(&mut self, task: &mut TaskInfo) {
+        debug_assert!(task.process() == self as *mut Process);
+        let _guard = self.lock.lock_guard_irq_save();
+        list::delete(&mut task.thread_list);
+        task.set_process(null_mut());
+        self.thread_count -= 1;
+        self.signal.thread_removed(self.thread_count);
+    }
+
+    /// Destroy a process which has never run: the process is removed from the process list and
+    /// its parent, and the address space, the PID and the process object are freed.
+    ///
+    /// # Safety
+    ///
+    /// All threads must have been removed, and the `process` must not be used after this call.
+    pub unsafe fn destroy(process: *mut Process) {
+        let p = &mut *process;
+        debug_assert!(p.thread_count == 0 && list::is_empty(&p.children));
+        {
+            let _guard = PROCESS_LIST_LOCK.lock_guard_irq_save();
+            list::delete(&mut p.list);
+            if !p.parent.is_null() {
+                list::delete(&mut p.sibling);
+            }
+        }
+
+        p.aspace.destroy();
+        p.release_files();
+        free_pid(p.pid);
+        kfree(process as _);
+    }
+
+    /// Turn the process into a zombie after its last thread `task` exits with the `status`: the
+    /// address space is destroyed and the files are closed, the children are reparented to the
+    /// init process, and the parent is woken up to reap the process (and the parent thread
+    /// suspended by `CLONE_VFORK` continues). The `task` is kept as the zombie thread.
+    ///
+    /// # Safety
+    ///
+    /// The `task` must be the last thread and must not run again, and the address space must not
+    /// be active on any hart.
+    pub unsafe fn exit_notify(process: *mut Process, task: &mut TaskInfo, status: usize) {
+        let p = &mut *process;
+        debug_assert!(p.thread_count == 1 && !p.parent.is_null());
+        p.aspace.destroy();
+        p.release_files();
+        task.set_status(TaskStatus::DeadZombie);
+
+        let _guard = PROCESS_LIST_LOCK.lock_guard_irq_save();
+        if !list::is_empty(&p.children) {
+            let init = &mut *INIT_PROCESS;
+            let mut orphan_zombie = false;
+            while !list::is_empty(&p.children) {
+                let next = p.children.next;
+                let child = &mut *container_of_mut!(next, Process, sibling);
+                list::delete(&mut child.sibling);
+                child.parent = init;
+                list::tail_append(&mut init.children, &mut child.sibling);
+                orphan_zombie |= child.zombie;
+            }
+            if orphan_zombie {
+                init.child_wait.wake_up_all();
+            }
+        }
+
+        p.vfork_wait.wake_up_all();
+        // The process may be reaped on other harts since here.
+        p.exit_status = status;
+        p.zombie = true;
+        let parent = &mut *p.parent;
+        parent.child_wait.wake_up_all();
+        send_signal(parent, SIGCHLD);
+    }
+
+    /// Suspend the thread `parent` until this process execs or exits, for `CLONE_VFORK`. The
+    /// `parent` is added to a wait queue, the caller should call `schedule` later.
+    pub fn vfork_sleep_on(&mut self, parent: *mut TaskInfo) {
+        let _guard = PROCESS_LIST_LOCK.lock_guard_irq_save();
+        self.vfork_wait.sleep_uninterruptible_on(parent);
+    }
+
+    /// Wake up the parent thread suspended by [`vfork_sleep_on`], called when the process execs.
+    ///
+    /// [`vfork_sleep_on`]: Process::vfork_sleep_on
+    pub fn vfork_release(&mut self) {
+        let _guard = PROCESS_LIST_LOCK.lock_guard_irq_save();
+        self.vfork_wait.wake_up_all();
+    }
+
+    /// Find a zombie child matching `filter` and remove it from the children. Returns `Ok(None)`
+    /// if some children match but none of them has exited, and the `sleeper` (if not null) is
+    /// added to the wait queue of the children exit in that case. Returns `Err` with `E_CHILD`
+    /// if no child matches.
+    pub fn take_zombie_child<F>(&mut self, mut filter: F, sleeper: *mut TaskInfo)
+        -> Result<Option<*mut Process>, i32> where F: FnMut(&Process) -> bool {
+        let _guard = PROCESS_LIST_LOCK.lock_guard_irq_save();
+        let mut found = false;
+        let mut zombie = null_mut();
+        list::for_each(&mut self.children, |cur| {
+            let child = unsafe { container_of_mut!(cur, Process, sibling) };
+            if filter(unsafe { &*child }) {
+                found = true;
+                if unsafe { (*child).zombie } {
+                    zombie = child;
+                    return false;
+                }
+            }
+            true
+        });
+
+        if !zombie.is_null() {
+            unsafe { list::delete(&mut (*zombie).sibling); }
+            Ok(Some(zombie))
+        } else if found {
+            if !sleeper.is_null() {
+                self.child_wait.sleep_on(sleeper);
+            }
+            Ok(None)
+        } else {
+            Err(E_CHILD)
+        }
+    }
+
+    /// Free a zombie removed by [`take_zombie_child`]: the zombie thread, the PID and the process
+    /// object are freed.
+    ///
+    /// # Safety
+    ///
+    /// The `process` must not be used after this call.
+    ///
+    /// [`take_zombie_child`]: Process::take_zombie_child
+    pub unsafe fn reap(process: *mut Process) {
+        let p = &mut *process;
+        debug_assert!(p.zombie && p.thread_count == 1);
+        {
+            let _guard = PROCESS_LIST_LOCK.lock_guard_irq_save();
+            list::delete(&mut p.list);
+        }
+
+        let task = container_of_mut!(p.threads.next, TaskInfo, thread_list);
+        free_user_thread(task);
+        free_pid(p.pid);
+        kfree(process as _);
+    }
+}
+
+/// Set the process group of the process `pid` (0 for `caller`) to `pgid` (0 for the same as
+/// the PID) like `setpgid`. The target must be the `caller` or its child in the same session and
+/// must not be a session leader, and the group must be in the same session.
+pub fn set_pgid(caller: &mut Process, pid: u32, pgid: u32) -> Result<(), i32> {
+    let _guard = PROCESS_LIST_LOCK.lock_guard_irq_save();
+    let target = if pid == 0 || pid == caller.pid {
+        caller as *mut Process
+    } else {
+        let target = find_process_locked(pid);
+        if target.is_null() || unsafe { (*target).parent } != caller as *mut Process {
+            return Err(E_SRCH);
+        }
+        target
+    };
+
+    let target = unsafe { &mut *target };
+    let pgid = if pgid == 0 { target.pid } else { pgid };
+    if target.sid != caller.sid || target.sid == target.pid {
+        return Err(E_PERM);
+    }
+    if pgid != target.pid {
+        let sid = target.sid;
+        let mut exists = false;
+        for_each_process_locked(|p| {
+            exists = p.pgid == pgid && p.sid == sid;
+            !exists
+        });
+        if !exists {
+            return Err(E_PERM);
+        }
+    }
+    target.pgid = pgid;
+
+    Ok(())
+}
+
+/// Create a new session and process group led by the `process` like `setsid`. Returns the new
+/// session ID, or `Err` with `E_PERM` if the process is a process group leader.
+pub fn set_sid(process: &mut Process) -> Result<u32, i32> {
+    let _guard = PROCESS_LIST_LOCK.lock_guard_irq_save();
+    if process.pgid == process.pid {
+        return Err(E_PERM);
+    }
+    process.sid = process.pid;
+    process.pgid = process.pid;
+
+    Ok(process.sid)
+}
+
+/// Find the process by `pid`, zombies included. Returns null if not found.
+pub fn find_process(pid: u32) -> *mut Process {
+    let _guard = PROCESS_LIST_LOCK.lock_guard_irq_save();
+    find_process_locked(pid)
+}
+
+/// Call `handle` on each process until it returns false, zombies included. The process list
+/// lock is held during the call.
+pub fn for_each_process<F>(handle: F) where F: FnMut(&mut Process) -> bool {
+    let _guard = PROCESS_LIST_LOCK.lock_guard_irq_save();
+    for_each_process_locked(handle);
+}
+
+/// Get the init process, null before it is created.
+#[inline(always)]
+pub fn init_process() -> *mut Process {
+    unsafe { INIT_PROCESS }
+}
+
+/// Call `handle` on each process until it returns false, the process list lock must be held.
+fn for_each_process_locked<F>(mut handle: F) where F: FnMut(&mut Process) -> bool {
+    unsafe {
+        list::for_each(&mut PROCESS_LIST, |cur| {
+            handle(&mut *container_of_mut!(cur, Process, list))
+        });
+    }
+}
+
+fn find_process_locked(pid: u32) -> *mut Process {
+    let mut ret = null_mut();
+    for_each_process_locked(|p| {
+        if p.pid == pid {
+            ret = p as *mut Process;
+            return false;
+        }
+        true
+    });
+
+    ret
+}
+
+/// Global process list.
+static mut PROCESS_LIST: List = List::new();
+/// Lock of the process list, the process tree and the process groups.
+static PROCESS_LIST_LOCK: SpinLockPure = SpinLockPure::new();
+/// The init process (PID 1), which adopts the orphans.
+static mut INIT_PROCESS: *mut Process = null_mut();
+/// Path of the executable of the init process in the root file system.
+const INIT_PATH: &[u8] = b"/init";
+
+pub(super) fn init() {
+    unsafe {
+        PROCESS_LIST.init_empty();
+    }
+}
+
+
+/// Create a process running the ELF executable `src` with the arguments `argv` and the
+/// environments `envp`, and add its main thread to the ready list. The fd `0`, `1` and `2` are
+/// bound to the console. The process is a child of the init process if it exists. Returns `Err`
+/// with the errno if the executable can not be loaded or out of memory.
+pub fn create_process_from_elf<S: ElfSource + ?Sized>(src: &S, argv: &[&[u8]], envp: &[&[u8]])
+    -> Result<*mut Process, i32> {
+    let ptr = Process::create();
+    if ptr.is_null() {
+        return Err(E_NO_MEM);
+    }
+    let process = unsafe { &mut *ptr };
+    process.set_comm(argv.first().copied().unwrap_or_default());
+    // A new table always has room for the standard fds.
+    let console = console_file();
+    for _ in 0..3 {
+        process.files_mut().alloc(console.clone(), false).unwrap();
+    }
+
+    let task = match load_elf(process.address_space_mut(), src, argv, envp) {
+        Ok(info) => {
+            process.address_space_mut().set_brk_start(info.program_break);
+            build_user_thread(process, info.entry, info.stack_pointer)
+        }
+        Err(err) => {
+            unsafe { Process::destroy(ptr); }
+            return Err(err);
+        }
+    };
+    if task.is_null() {
+        unsafe { Process::destroy(ptr); }
+        return Err(E_NO_MEM);
+    }
+    let init = init_process();
+    if !init.is_null() {
+        process.link_to_parent(unsafe { &mut *init });
+    }
+    ready_list_add_task(task);
+
+    Ok(ptr)
+}
+
+/// Create the first user process (PID 1) and add its main thread to the ready list.
+///
+/// The process runs `/init` of the root file system (e.g. unpacked from the initramfs), or the
+/// built-in init executable (`asm/user_init.S`) if there is none.
+pub fn create_init_process() {
+    let process = match open_executable(None, INIT_PATH) {
+        Ok(inode) => create_process_from_elf(&*inode, &[INIT_PATH], &[]),
+        Err(errno) => {
+            info!("No executable /init (errno = {}), run the built-in init.", errno);
+            create_process_from_elf(user_init_elf(), &[b"init"], &[])
+        }
+    };
+    let process = process
+        .unwrap_or_else(|err| panic!("Create init process failed, errno = {}.", err));
+    let pid = unsafe { (*process).pid() };
+    assert_eq!(pid, 1);
+    unsafe { INIT_PROCESS = process; }
+    info!("Init process created, pid = {}.", pid);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use super::*;
+
+    #[kernel_test]
+    fn comm_and_threads() {
+        let process = unsafe { &mut *Process::create() };
+        assert_eq!(process.comm(), "");
+        process.set_comm(b"/usr/bin/sh");
+        assert_eq!(process.comm(), "sh");
+        process.set_comm(b"init");
+        assert_eq!(process.comm(), "init");
+        process.set_comm(b"/bin/0123456789abcdefgh");
+        assert_eq!(process.comm(), "0123456789abcde");
+        // The name cut in a multibyte character is shown up to the last whole one.
+        process.set_comm("/bin/\u{e9}\u{e9}\u{e9}\u{e9}\u{e9}\u{e9}\u{e9}\u{e9}".as_bytes());
+        assert_eq!(process.comm(), "\u{e9}\u{e9}\u{e9}\u{e9}\u{e9}\u{e9}\u{e9}");
+
+        let tasks = [build_user_thread(process, 0, 0), build_user_thread(process, 0, 0)];
+        assert!(tasks.iter().all(|task| !task.is_null()));
+        assert_eq!(process.thread_count(), 2);
+        let mut tids = Vec::new();
+        process.for_each_thread(|task| {
+            tids.push(task.tid());
+            true
+        });
+        assert_eq!(tids.len(), 2);
+        assert_ne!(tids[0], tids[1]);
+        let mut visited = 0;
+        process.for_each_thread(|_| {
+            visited += 1;
+            false
+        });
+        assert_eq!(visited, 1);
+
+        unsafe {
+            for task in tasks {
+                free_user_thread(task);
+            }
+            Process::destroy(process);
+        }
+    }
+}
@@ -0,0 +1,960 @@
+//! POSIX signals, following the Linux riscv64 ABI.
+//!
+//! A signal is sent to a process ([`send_signal`]) or to a thread ([`send_thread_signal`]) by
+//! setting a pending bit of the process or the thread. The handlers ([`SigAction`]) are shared
+//! by all threads of a process, while each thread has its own blocked mask; a signal sent to the
+//! process is taken by any thread which does not block it. The signal state of a process and
+//! its threads is protected by the process lock.
+//!
+//! The pending signals are delivered by [`deliver_signals`] when a user thread is about to
+//! return to the U-mode, at the end of a trap or when the thread is scheduled:
+//!
+//! - With a handler (`SA_SIGINFO` is implied, `SA_ONSTACK` is ignored), a signal frame
+//! (`struct rt_sigframe`: the `siginfo` and the `ucontext` with the saved trap frame) is pushed
+//! on the user stack, and the handler is entered with the `ra` pointing to the sigreturn
+//! trampoline page (the riscv64 ABI has no `SA_RESTORER`, the trampoline takes the place of the
+//! vDSO). `rt_sigreturn` restores the trap frame and the blocked mask from the `ucontext`.
+//! - Without a handler, the default action is taken: terminate the process (no core file is
+//! written for the core dump actions), stop or continue the process, or ignore the signal.
+//!
+//! A signal which wakes a thread sleeping in a syscall (`TaskStatus::InterruptibleSleep`)
+//! interrupts the syscall with `EINTR`, the interrupted syscalls are never restarted
+//! (`SA_RESTART` is ignored). Stopping and continuing a process is not reported to the parent.
+//!
+//! Exceptions of a user thread raise the signals by [`force_signal`], which can not be blocked
+//! or ignored. The `si_code` and the `si_addr` of the fault are passed to the handler, the
+//! other signals are reported with `SI_USER`.
+//!
+//! `execve` kills the other threads of the process by [`kill_other_threads`] and waits for them
+//! to exit, like the `de_thread` of Linux. A thread killed this way exits alone, it does not
+//! start a group exit.
+
+use core::ptr::null_mut;
+use crate::arch::cpu::{self, Register};
+use crate::errno::{E_AGAIN, E_INTR, E_INVALID, E_SRCH};
+use crate::mm::PAGE_SIZE;
+use crate::mm::address_space::{AddressSpace, MMAP_TOP};
+use crate::mm::mmu::EntryBits;
+use crate::mm::uaccess::{read_user, write_user};
+use crate::mm::vma::VmaBacking;
+use crate::proc::exit::exit_group_thread;
+use crate::proc::process::{find_process, for_each_process, Process};
+use crate::proc::task::{TaskInfo, TaskStatus};
+use crate::sched::{ready_list_add_task, WaitQueue};
+use crate::util::list::{self, List};
+
+
+/// Hangup.
+pub const SIGHUP: usize = 1;
+/// Interrupt from the keyboard.
+pub const SIGINT: usize = 2;
+/// Quit from the keyboard.
+pub const SIGQUIT: usize = 3;
+/// Illegal instruction.
+pub const SIGILL: usize = 4;
+/// Breakpoint.
+pub const SIGTRAP: usize = 5;
+/// Abort.
+pub const SIGABRT: usize = 6;
+/// Bus error, e.g. a misaligned or a physical access fault.
+pub const SIGBUS: usize = 7;
+/// Floating-point exception.
+pub const SIGFPE: usize = 8;
+/// Kill, can not be caught, blocked or ignored.
+pub const SIGKILL: usize = 9;
+/// User-defined signal 1.
+pub const SIGUSR1: usize = 10;
+/// Invalid memory reference.
+pub const SIGSEGV: usize = 11;
+/// User-defined signal 2.
+pub const SIGUSR2: usize = 12;
+/// Broken pipe.
+pub const SIGPIPE: usize = 13;
+/// Timer signal from `alarm`.
+pub const SIGALRM: usize = 14;
+/// Termination.
+pub const SIGTERM: usize = 15;
+/// Stack fault on coprocessor (unused).
+pub const SIGSTKFLT: usize = 16;
+/// Child stopped or terminated.
+pub const SIGCHLD: usize = 17;
+/// Continue if stopped.
+pub const SIGCONT: usize = 18;
+/// Stop, can not be caught, blocked or ignored.
+pub const SIGSTOP: usize = 19;
+/// Stop typed at the terminal.
+pub const SIGTSTP: usize = 20;
+/// Terminal input for a background process.
+pub const SIGTTIN: usize = 21;
+/// Terminal output for a background process.
+pub const SIGTTOU: usize = 22;
+/// Urgent condition on a socket.
+pub const SIGURG: usize = 23;
+/// CPU time limit exceeded.
+pub const SIGXCPU: usize = 24;
+/// File size limit exceeded.
+pub const SIGXFSZ: usize = 25;
+/// Virtual alarm clock.
+pub const SIGVTALRM: usize = 26;
+/// Profiling timer expired.
+pub const SIGPROF: usize = 27;
+/// Window resize.
+pub const SIGWINCH: usize = 28;
+/// I/O now possible.
+pub const SIGIO: usize = 29;
+/// Power failure.
+pub const SIGPWR: usize = 30;
+/// Bad system call.
+pub const SIGSYS: usize = 31;
+/// Number of the signals, the realtime signals are `32..=NSIG`.
+pub const NSIG: usize = 64;
+
+/// Default action.
+pub const SIG_DFL: usize = 0;
+/// Ignore the signal.
+pub const SIG_IGN: usize = 1;
+
+/// Do not block the signal in its handler.
+pub const SA_NODEFER: usize = 0x4000_0000;
+/// Reset the action to the default when the handler is entered.
+pub const SA_RESETHAND: usize = 0x8000_0000;
+
+// `how` of `rt_sigprocmask`.
+pub const SIG_BLOCK: usize = 0;
+pub const SIG_UNBLOCK: usize = 1;
+pub const SIG_SETMASK: usize = 2;
+
+// `si_code` of the fault signals.
+/// Illegal opcode (`SIGILL`).
+pub const ILL_ILLOPC: i32 = 1;
+/// Address not mapped (`SIGSEGV`).
+pub const SEGV_MAPERR: i32 = 1;
+/// Invalid permissions for the mapped address (`SIGSEGV`).
+pub const SEGV_ACCERR: i32 = 2;
+/// Invalid address alignment (`SIGBUS`).
+pub const BUS_ADRALN: i32 = 1;
+/// Process breakpoint (`SIGTRAP`).
+pub const TRAP_BRKPT: i32 = 1;
+
+/// Signal set, bit `sig - 1` for the signal `sig`.
+pub type SigSet = u64;
+
+/// Get the bit of the signal `sig` in a [`SigSet`].
+#[inline(always)]
+pub const fn sig_bit(sig: usize) -> SigSet {
+    1 << (sig - 1)
+}
+
+const UNBLOCKABLE: SigSet = sig_bit(SIGKILL) | sig_bit(SIGSTOP);
+const STOP_SIGNALS: SigSet = sig_bit(SIGSTOP) | sig_bit(SIGTSTP) | sig_bit(SIGTTIN) |
+    sig_bit(SIGTTOU);
+
+/// `si_code` of a signal sent by `kill`, which is also used for the other sources except the
+/// faults.
+const SI_USER: i32 = 0;
+/// `ss_flags` of a disabled alternate signal stack.
+const SS_DISABLE: i32 = 2;
+
+/// Default action of a signal.
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum DefaultAction {
+    Terminate,
+    Core,
+    Stop,
+    Continue,
+    Ignore,
+}
+
+fn default_action(sig: usize) -> DefaultAction {
+    match sig {
+        SIGQUIT | SIGILL | SIGTRAP | SIGABRT | SIGBUS | SIGFPE | SIGSEGV | SIGXCPU | SIGXFSZ |
+        SIGSYS => DefaultAction::Core,
+        SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+        SIGCONT => DefaultAction::Continue,
+        SIGCHLD | SIGURG | SIGWINCH => DefaultAction::Ignore,
+        _ => DefaultAction::Terminate,
+    }
+}
+
+/// Signal action, the layout of the `struct sigaction` of the `rt_sigaction` syscall.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct SigAction {
+    /// Handler address, or [`SIG_DFL`] or [`SIG_IGN`].
+    pub handler: usize,
+    /// `SA_*` flags.
+    pub flags: usize,
+    /// Signals blocked during the handler, in addition to the signal itself.
+    pub mask: SigSet,
+}
+
+impl SigAction {
+    const DEFAULT: SigAction = SigAction { handler: SIG_DFL, flags: 0, mask: 0 };
+
+    /// Check if the signal `sig` is discarded when it is sent.
+    fn ignores(&self, sig: usize) -> bool {
+        self.handler == SIG_IGN || (self.handler == SIG_DFL &&
+            matches!(default_action(sig), DefaultAction::Ignore | DefaultAction::Continue))
+    }
+}
+
+/// Signal state of a user thread.
+#[repr(C)]
+pub struct ThreadSignal {
+    /// Signals sent to the thread.
+    pending: SigSet,
+    blocked: SigSet,
+    /// `pc` after the `ecall` of the last syscall, where an interrupted syscall returns to.
+    syscall_pc: usize,
+    /// Signal raised by [`force_signal`] and not delivered yet, 0 if none.
+    fault_sig: usize,
+    /// `si_code` of the `fault_sig`.
+    fault_code: i32,
+    /// `si_addr` of the `fault_sig`.
+    fault_addr: usize,
+}
+
+impl ThreadSignal {
+    /// Record the `pc` to return to if the syscall is interrupted.
+    #[inline(always)]
+    pub fn set_syscall_pc(&mut self, pc: usize) {
+        self.syscall_pc = pc;
+    }
+
+    /// Init the state of a new thread created by `creator`, the blocked mask is inherited.
+    pub fn inherit(&mut self, creator: &ThreadSignal) {
+        self.pending = 0;
+        self.blocked = creator.blocked;
+        self.fault_sig = 0;
+    }
+
+    /// Take the `si_code` and the `si_addr` of the signal `sig` if it is raised by a fault.
+    fn take_fault(&mut self, sig: usize) -> Option<(i32, usize)> {
+        if self.fault_sig != sig {
+            return None;
+        }
+        self.fault_sig = 0;
+        Some((self.fault_code, self.fault_addr))
+    }
+}
+
+/// Signal state of a process.
+#[repr(C)]
+pub struct ProcessSignal {
+    /// Signals sent to the process.
+    pending: SigSet,
+    actions: [SigAction; NSIG],
+    /// Exit status of the process if it is exiting, the threads are being killed.
+    group_exit: Option<usize>,
+    /// Whether the process is stopped.
+    stopped: bool,
+    /// Threads stopped by a stop signal.
+    stop_wait: WaitQueue,
+    /// Thread running `execve` which kills the other threads, null if none.
+    exec_task: *mut TaskInfo,
+    /// The `exec_task` waiting for the other threads to exit.
+    exec_wait: WaitQueue,
+}
+
+impl ProcessSignal {
+    /// Create the signal state with all default actions, [`init`] must be called after the
+    /// state is placed at its final address.
+    ///
+    /// [`init`]: ProcessSignal::init
+    pub const fn new() -> Self {
+        Self {
+            pending: 0,
+            actions: [SigAction::DEFAULT; NSIG],
+            group_exit: None,
+            stopped: false,
+            stop_wait: WaitQueue::new(),
+            exec_task: null_mut(),
+            exec_wait: WaitQueue::new(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn init(&mut self) {
+        self.stop_wait.init();
+        self.exec_wait.init();
+    }
+
+    /// Get the exit status if the process is exiting.
+    #[inline(always)]
+    pub fn group_exit(&self) -> Option<usize> {
+        self.group_exit
+    }
+
+    /// Copy the actions of the `parent` on fork.
+    pub fn inherit(&mut self, parent: &ProcessSignal) {
+        self.actions = parent.actions;
+    }
+
+    /// Reset the caught signals to the default action on `execve`, the ignored ones are kept.
+    pub fn reset_on_exec(&mut self) {
+        for action in self.actions.iter_mut() {
+            if action.handler != SIG_IGN {
+                *action = SigAction::DEFAULT;
+            }
+        }
+    }
+
+    /// Remove the pending signals `set` from the process and the `threads`.
+    fn discard(&mut self, threads: &mut List, set: SigSet) {
+        self.pending &= !set;
+        for_each_thread(threads, |task| {
+            task.signal_mut().pending &= !set;
+            true
+        });
+    }
+
+    /// Wake up the thread waiting in `execve` if only `thread_count` threads are left. Called
+    /// with the process lock held when a thread is removed.
+    pub fn thread_removed(&mut self, thread_count: u32) {
+        if thread_count == 1 {
+            self.exec_wait.wake_up_all();
+        }
+    }
+
+    /// Continue the stopped threads.
+    fn resume(&mut self) {
+        if self.stopped {
+            self.stopped = false;
+            self.stop_wait.wake_up_all();
+        }
+    }
+
+    /// Handle the stop and continue signals when `sig` is sent to the process of `pid`.
+    /// Returns false if the signal is discarded.
+    fn prepare(&mut self, threads: &mut List, pid: u32, sig: usize) -> bool {
+        if sig == SIGKILL || sig == SIGCONT {
+            self.discard(threads, STOP_SIGNALS);
+            self.resume();
+        } else if STOP_SIGNALS & sig_bit(sig) != 0 {
+            self.discard(threads, sig_bit(SIGCONT));
+        }
+
+        // The init process only receives the signals it handles.
+        let action = &self.actions[sig - 1];
+        !action.ignores(sig) && !(pid == 1 && action.handler == SIG_DFL)
+    }
+
+    /// Take a pending signal of the `task` to deliver.
+    fn dequeue(&mut self, task: &mut TaskInfo) -> Dequeue {
+        if let Some(status) = self.group_exit {
+            return Dequeue::Exit(status);
+        }
+        let signal = task.signal_mut();
+        if self.stopped && signal.pending & sig_bit(SIGKILL) == 0 {
+            self.stop_wait.stop_on(task);
+            return Dequeue::Stop;
+        }
+
+        loop {
+            let signal = task.signal_mut();
+            let deliverable = (signal.pending | self.pending) & !(signal.blocked & !UNBLOCKABLE);
+            if deliverable == 0 {
+                return Dequeue::None;
+            }
+            let bit = if deliverable & sig_bit(SIGKILL) != 0 {
+                sig_bit(SIGKILL)
+            } else {
+                1 << deliverable.trailing_zeros()
+            };
+            if signal.pending & bit != 0 {
+                signal.pending &= !bit;
+            } else {
+                self.pending &= !bit;
+            }
+            let sig = bit.trailing_zeros() as usize + 1;
+
+            let action = self.actions[sig - 1];
+            if action.handler == SIG_IGN {
+                continue;
+            }
+            if action.handler != SIG_DFL {
+                if action.flags & SA_RESETHAND != 0 {
+                    self.actions[sig - 1] = SigAction::DEFAULT;
+                }
+                let old_blocked = signal.blocked;
+                let nodefer = if action.flags & SA_NODEFER != 0 { 0 } else { bit };
+                signal.blocked |= (action.mask | nodefer) & !UNBLOCKABLE;
+                return Dequeue::Handle(sig, action, old_blocked);
+            }
+            match default_action(sig) {
+                DefaultAction::Ignore | DefaultAction::Continue => continue,
+                DefaultAction::Stop => {
+                    self.stopped = true;
+                    self.stop_wait.stop_on(task);
+                    return Dequeue::Stop;
+                }
+                DefaultAction::Terminate | DefaultAction::Core => return Dequeue::Exit(sig),
+            }
+        }
+    }
+}
+
+/// Result of taking a pending signal.
+enum Dequeue {
+    /// No signal to deliver.
+    None,
+    /// The thread is stopped.
+    Stop,
+    /// Exit the process with the status.
+    Exit(usize),
+    /// Enter the handler of the signal, with the blocked mask to restore.
+    Handle(usize, SigAction, SigSet),
+}
+
+/// Call `handle` on each thread in the thread list `threads` until it returns false.
+fn for_each_thread<F>(threads: &mut List, mut handle: F) where F: FnMut(&mut TaskInfo) -> bool {
+    list::for_each(threads, |cur| {
+        handle(unsafe { &mut *container_of_mut!(cur, TaskInfo, thread_list) })
+    });
+}
+
+/// Wake up the `task` if it sleeps in a syscall, the syscall returns `EINTR`.
+fn signal_wake_up(task: &mut TaskInfo) {
+    if task.status() != TaskStatus::InterruptibleSleep {
+        return;
+    }
+
+    list::delete(&mut task.list);
+    let pc = task.signal().syscall_pc;
+    let frame = task.trap_frame_mut();
+    frame.pc = pc;
+    frame.regs[cpu::reg(Register::A0)] = -E_INTR as isize as usize;
+    ready_list_add_task(task);
+}
+
+/// Send the signal `sig` to the `process`, one thread which does not block the signal is woken
+/// up if it sleeps.
+pub fn send_signal(process: &mut Process, sig: usize) {
+    debug_assert!((1..=NSIG).contains(&sig));
+    let pid = process.pid();
+    process.with_signal(|signal, threads| {
+        if !signal.prepare(threads, pid, sig) {
+            return;
+        }
+        signal.pending |= sig_bit(sig);
+        for_each_thread(threads, |task| {
+            if task.signal().blocked & sig_bit(sig) & !UNBLOCKABLE != 0 {
+                return true;
+            }
+            signal_wake_up(task);
+            false
+        });
+    });
+}
+
+/// Send the signal `sig` to the user thread `task`.
+pub fn send_thread_signal(task: &mut TaskInfo, sig: usize) {
+    debug_assert!((1..=NSIG).contains(&sig));
+    let process = unsafe { &mut *task.process() };
+    let pid = process.pid();
+    process.with_signal(|signal, threads| {
+        if !signal.prepare(threads, pid, sig) {
+            return;
+        }
+        let thread_signal = task.signal_mut();
+        thread_signal.pending |= sig_bit(sig);
+        if thread_signal.blocked & sig_bit(sig) & !UNBLOCKABLE == 0 {
+            signal_wake_up(task);
+        }
+    });
+}
+
+/// Raise the signal `sig` on the current user thread `task` for an exception, the `code` and
+/// the `addr` are reported as the `si_code` and the `si_addr`. If the signal is blocked or
+/// ignored, it is unblocked and the action is reset to the default.
+pub fn force_signal(task: &mut TaskInfo, sig: usize, code: i32, addr: usize) {
+    let process = unsafe { &mut *task.process() };
+    process.with_signal(|signal, _| {
+        let thread_signal = task.signal_mut();
+        let action = &mut signal.actions[sig - 1];
+        if action.handler == SIG_IGN || thread_signal.blocked & sig_bit(sig) != 0 {
+            *action = SigAction::DEFAULT;
+            thread_signal.blocked &= !sig_bit(sig);
+        }
+        thread_signal.pending |= sig_bit(sig);
+        thread_signal.fault_sig = sig;
+        thread_signal.fault_code = code;
+        thread_signal.fault_addr = addr;
+    });
+}
+
+/// Kill the threads in `threads` except the `task` by `SIGKILL`, the stopped threads are
+/// continued to exit.
+fn zap_other_threads(signal: &mut ProcessSignal, threads: &mut List, task: *mut TaskInfo) {
+    signal.resume();
+    for_each_thread(threads, |thread| {
+        if thread as *mut TaskInfo != task {
+            thread.signal_mut().pending |= sig_bit(SIGKILL);
+            signal_wake_up(thread);
+        }
+        true
+    });
+}
+
+/// Start the exit of the process of `task` with the `status`: the other threads are killed
+/// when they are about to return to the U-mode. Returns the exit status of the process, which
+/// is the first one if the process is already exiting. If another thread is running `execve`,
+/// the `task` exits alone.
+pub fn start_group_exit(task: &mut TaskInfo, status: usize) -> usize {
+    let process = unsafe { &mut *task.process() };
+    let task_ptr = task as *mut TaskInfo;
+    process.with_signal(|signal, threads| {
+        if let Some(status) = signal.group_exit {
+            return status;
+        }
+        if !signal.exec_task.is_null() && signal.exec_task != task_ptr {
+            return status;
+        }
+        signal.group_exit = Some(status);
+        zap_other_threads(signal, threads, task_ptr);
+        status
+    })
+}
+
+/// Kill the other threads of the process of the user thread `task` for `execve`. Returns true
+/// if `task` is the only thread left. Otherwise the other threads are killed (on the first
+/// call), the `task` is added to the wait queue in the `UninterruptibleSleep` status and false
+/// is returned, the caller should call `schedule` and retry after the task is woken up by the
+/// exit of the last other thread.
+///
+/// Returns `Err` with `E_AGAIN` if the process is exiting or another thread is running
+/// `execve`, the `task` is killed when it returns to the U-mode.
+pub fn kill_other_threads(task: &mut TaskInfo) -> Result<bool, i32> {
+    let process = unsafe { &mut *task.process() };
+    let task_ptr = task as *mut TaskInfo;
+    process.with_signal(|signal, threads| {
+        if signal.group_exit.is_some() ||
+            (!signal.exec_task.is_null() && signal.exec_task != task_ptr) {
+            return Err(E_AGAIN);
+        }
+        let mut alone = true;
+        for_each_thread(threads, |thread| {
+            alone = thread as *mut TaskInfo == task_ptr;
+            alone
+        });
+        if alone {
+            signal.exec_task = null_mut();
+            return Ok(true);
+        }
+
+        if signal.exec_task.is_null() {
+            signal.exec_task = task_ptr;
+            zap_other_threads(signal, threads, task_ptr);
+        }
+        signal.exec_wait.sleep_uninterruptible_on(task_ptr);
+        Ok(false)
+    })
+}
+
+/// Deliver the pending signals of the user thread `task`, which is about to return to the
+/// U-mode with its trap frame. Returns false if the thread is stopped or has exited, the caller
+/// should schedule another task.
+pub fn deliver_signals(task: &mut TaskInfo) -> bool {
+    let process = unsafe { &mut *task.process() };
+    // Fast path without the lock, a signal sent meanwhile is delivered on the next return.
+    let signal = process.signal();
+    if task.signal().pending | signal.pending == 0 && !signal.stopped &&
+        signal.group_exit.is_none() {
+        return true;
+    }
+
+    let status = match process.with_signal(|signal, _| signal.dequeue(task)) {
+        Dequeue::None => return true,
+        Dequeue::Stop => return false,
+        Dequeue::Exit(status) => status,
+        Dequeue::Handle(sig, action, blocked) => {
+            if setup_frame(process.address_space_mut(), task, sig, &action, blocked).is_ok() {
+                return true;
+            }
+            debug!("Bad signal frame of thread {}, sp: {:#x}.", task.tid(),
+                task.trap_frame().regs[cpu::reg(Register::Sp)]);
+            SIGSEGV
+        }
+    };
+    exit_group_thread(task, status);
+    false
+}
+
+// Layout of the signal frame (`struct rt_sigframe`) on the user stack.
+/// Size of the `siginfo`, which is at the start of the frame.
+const SIGINFO_SIZE: usize = 128;
+/// Offset of the `ucontext`.
+const FRAME_UCONTEXT: usize = SIGINFO_SIZE;
+// Offsets in the `ucontext`. The `uc_sigmask` is followed by the reserved space up to 1024
+// bits, and the `uc_mcontext` is 16 bytes aligned.
+const UC_STACK: usize = 16;
+const UC_SIGMASK: usize = 40;
+const UC_MCONTEXT: usize = 176;
+// Offsets in the `mcontext`. The general registers are saved with the `pc` in place of `x0`,
+// followed by the floating-point state union, which is filled in the layout of the D extension
+// (`f[32]`, `fcsr`) and sized by the one of the Q extension (`f[64]`, `fcsr`, reserved).
+const MC_FREGS: usize = 256;
+const MC_FCSR: usize = MC_FREGS + 32 * 8;
+const MC_SIZE: usize = MC_FREGS + 64 * 8 + 16;
+/// Size of the signal frame.
+const SIGFRAME_SIZE: usize = FRAME_UCONTEXT + UC_MCONTEXT + MC_SIZE;
+
+/// `siginfo`, only the common header and the `si_addr` of the fault signals are filled.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct SigInfo {
+    signo: i32,
+    errno: i32,
+    code: i32,
+    _pad0: i32,
+    addr: usize,
+    _pad: [usize; SIGINFO_SIZE / 8 - 3],
+}
+
+/// `stack_t`.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct SigStack {
+    sp: usize,
+    flags: i32,
+    size: usize,
+}
+
+/// Push the signal frame of `sig` on the user stack of `task` and enter the handler, the
+/// blocked mask `blocked` is saved to restore by `rt_sigreturn`.
+fn setup_frame(aspace: &mut AddressSpace, task: &mut TaskInfo, sig: usize, action: &SigAction,
+               blocked: SigSet) -> Result<(), i32> {
+    let (code, addr) = task.signal_mut().take_fault(sig).unwrap_or((SI_USER, 0));
+    let frame = task.trap_frame_mut();
+    let sp_reg = cpu::reg(Register::Sp);
+    let sp = frame.regs[sp_reg].wrapping_sub(SIGFRAME_SIZE) & !0xf;
+    let uc = sp + FRAME_UCONTEXT;
+    let mc = uc + UC_MCONTEXT;
+
+    let info = SigInfo {
+        signo: sig as i32,
+        errno: 0,
+        code,
+        _pad0: 0,
+        addr,
+        _pad: [0; SIGINFO_SIZE / 8 - 3],
+    };
+    write_user(aspace, sp, &info)?;
+    write_user(aspace, uc, &[0usize; 2])?;
+    write_user(aspace, uc + UC_STACK, &SigStack { sp: 0, flags: SS_DISABLE, size: 0 })?;
+    write_user(aspace, uc + UC_SIGMASK, &blocked)?;
+    write_user(aspace, mc, &frame.regs)?;
+    write_user(aspace, mc, &frame.pc)?;
+    write_user(aspace, mc + MC_FREGS, &frame.fregs)?;
+    write_user(aspace, mc + MC_FCSR, &(frame.fcsr as u32))?;
+
+    frame.regs[sp_reg] = sp;
+    frame.regs[cpu::reg(Register::Ra)] = SIGRETURN_TRAMPOLINE;
+    frame.regs[cpu::reg(Register::A0)] = sig;
+    frame.regs[cpu::reg(Register::A1)] = sp;
+    frame.regs[cpu::reg(Register::A2)] = uc;
+    frame.pc = action.handler;
+
+    Ok(())
+}
+
+/// Restore the trap frame and the blocked mask of the user thread `task` from the signal frame
+/// at the stack pointer, which is where the handler returns to the trampoline.
+pub fn sigreturn(task: &mut TaskInfo) -> Result<(), i32> {
+    let aspace = unsafe { (*task.process()).address_space_mut() };
+    let frame = task.trap_frame_mut();
+    let uc = frame.regs[cpu::reg(Register::Sp)] + FRAME_UCONTEXT;
+    let mc = uc + UC_MCONTEXT;
+    let blocked = read_user::<SigSet>(aspace, uc + UC_SIGMASK)?;
+    let regs = read_user::<[usize; 32]>(aspace, mc)?;
+    let fregs = read_user::<[usize; 32]>(aspace, mc + MC_FREGS)?;
+    let fcsr = read_user::<u32>(aspace, mc + MC_FCSR)?;
+
+    frame.pc = regs[0];
+    frame.regs[1..].copy_from_slice(&regs[1..]);
+    frame.fregs = fregs;
+    frame.fcsr = fcsr as usize;
+    let process = unsafe { &mut *task.process() };
+    process.with_signal(|_, _| task.signal_mut().blocked = blocked & !UNBLOCKABLE);
+
+    Ok(())
+}
+
+/// Set the action of the signal `sig` of the `process` to `new` if it is not `None`, like
+/// `rt_sigaction`. Returns the old action.
+pub fn set_action(process: &mut Process, sig: usize, new: Option<SigAction>)
+    -> Result<SigAction, i32> {
+    if !(1..=NSIG).contains(&sig) || (new.is_some() && UNBLOCKABLE & sig_bit(sig) != 0) {
+        return Err(E_INVALID);
+    }
+
+    process.with_signal(|signal, threads| {
+        let old = signal.actions[sig - 1];
+        if let Some(action) = new {
+            signal.actions[sig - 1] = action;
+            // The pending signal is discarded if it is ignored now.
+            if action.ignores(sig) {
+                signal.discard(threads, sig_bit(sig));
+            }
+        }
+        Ok(old)
+    })
+}
+
+/// Change the blocked mask of the user thread `task` by `set` like `rt_sigprocmask`, `SIGKILL`
+/// and `SIGSTOP` can not be blocked. Returns the old mask.
+pub fn set_blocked(task: &mut TaskInfo, how: usize, set: Option<SigSet>) -> Result<SigSet, i32> {
+    let process = unsafe { &mut *task.process() };
+    process.with_signal(|_, _| {
+        let signal = task.signal_mut();
+        let old = signal.blocked;
+        let Some(set) = set else {
+            return Ok(old);
+        };
+        signal.blocked = match how {
+            SIG_BLOCK => old | set,
+            SIG_UNBLOCK => old & !set,
+            SIG_SETMASK => set,
+            _ => return Err(E_INVALID),
+        } & !UNBLOCKABLE;
+        Ok(old)
+    })
+}
+
+/// Get the pending signals of the user thread `task` which are blocked, like `rt_sigpending`.
+pub fn pending_blocked(task: &mut TaskInfo) -> SigSet {
+    let process = unsafe { &mut *task.process() };
+    process.with_signal(|signal, _| {
+        (signal.pending | task.signal().pending) & task.signal().blocked
+    })
+}
+
+/// Send the signal `sig` like `kill`: `pid > 0` for the process, `0` for the process group of
+/// the `caller`, `-1` for all processes except the init process and the `caller`, `< -1` for
+/// the process group `-pid`. A zero `sig` only checks the existence of the processes.
+pub fn kill(caller: &mut Process, pid: i32, sig: usize) -> Result<(), i32> {
+    if sig > NSIG {
+        return Err(E_INVALID);
+    }
+    if pid > 0 {
+        let process = find_process(pid as u32);
+        if process.is_null() {
+            return Err(E_SRCH);
+        }
+        if sig != 0 && unsafe { !(*process).is_zombie() } {
+            send_signal(unsafe { &mut *process }, sig);
+        }
+        return Ok(());
+    }
+
+    let caller_pid = caller.pid();
+    let pgid = match pid {
+        0 => caller.pgid(),
+        -1 => 0,
+        pid => pid.unsigned_abs(),
+    };
+    let mut found = false;
+    for_each_process(|process| {
+        let matched = if pid == -1 {
+            process.pid() != 1 && process.pid() != caller_pid
+        } else {
+            process.pgid() == pgid
+        };
+        if matched && !process.is_zombie() {
+            found = true;
+            if sig != 0 {
+                send_signal(process, sig);
+            }
+        }
+        true
+    });
+
+    if found { Ok(()) } else { Err(E_SRCH) }
+}
+
+/// Send the signal `sig` to the thread `tid` like `tgkill`, the thread must be in the process
+/// `tgid` if it is not `None`. A zero `sig` only checks the existence of the thread.
+pub fn tgkill(tgid: Option<u32>, tid: u32, sig: usize) -> Result<(), i32> {
+    if sig > NSIG {
+        return Err(E_INVALID);
+    }
+
+    let mut task = core::ptr::null_mut();
+    for_each_process(|process| {
+        if tgid.unwrap_or(process.pid()) == process.pid() && !process.is_zombie() {
+            task = process.find_thread(tid);
+        }
+        task.is_null()
+    });
+    if task.is_null() {
+        return Err(E_SRCH);
+    }
+    if sig != 0 {
+        send_thread_signal(unsafe { &mut *task }, sig);
+    }
+
+    Ok(())
+}
+
+/// Address of the sigreturn trampoline page in the user address spaces, right above the mmap
+/// area.
+pub const SIGRETURN_TRAMPOLINE: usize = MMAP_TOP;
+
+/// The sigreturn trampoline page, shared by all user address spaces.
+#[repr(C, align(4096))]
+struct TrampolinePage([u32; PAGE_SIZE / 4]);
+
+static SIGRETURN_TRAMPOLINE_PAGE: TrampolinePage = {
+    let mut code = [0u32; PAGE_SIZE / 4];
+    // li a7, 139 (rt_sigreturn)
+    code[0] = 0x08b0_0893;
+    // ecall
+    code[1] = 0x0000_0073;
+    TrampolinePage(code)
+};
+
+/// Map the sigreturn trampoline page to the user address space `aspace`.
+pub fn map_sigreturn_trampoline(aspace: &mut AddressSpace) -> Result<(), i32> {
+    let p_addr = &SIGRETURN_TRAMPOLINE_PAGE as *const TrampolinePage as usize;
+    aspace.add_vma(SIGRETURN_TRAMPOLINE, PAGE_SIZE, EntryBits::ReadExecute.val(),
+                   VmaBacking::Device { p_addr })
+}
+
+#[cfg(test)]
+mod tests {
+    use core::mem::size_of;
+    use super::*;
+    use crate::mm::address_space::MapPlacement;
+    use crate::proc::user::{build_user_thread, free_user_thread};
+
+    const HANDLER: usize = 0x1000;
+    const PC: usize = 0x2000;
+    /// `fcsr` with the rounding mode `RMM` and the inexact flag.
+    const FCSR: u32 = 0x81;
+
+    /// Create a process with a thread running at `PC` on a stack of 2 pages.
+    fn create_thread() -> (&'static mut Process, &'static mut TaskInfo) {
+        let process = unsafe { &mut *Process::create() };
+        let rw = EntryBits::ReadWrite.val();
+        let stack = process.address_space_mut()
+            .map_anonymous(MapPlacement::Hint(0), 2 * PAGE_SIZE, rw, false).unwrap();
+        let task = build_user_thread(process, PC, stack + 2 * PAGE_SIZE - 8);
+        (process, unsafe { &mut *task })
+    }
+
+    fn destroy_thread(process: &mut Process, task: &mut TaskInfo) {
+        unsafe {
+            free_user_thread(task);
+            Process::destroy(process);
+        }
+    }
+
+    fn handler(flags: usize, mask: SigSet) -> Option<SigAction> {
+        Some(SigAction { handler: HANDLER, flags, mask })
+    }
+
+    #[kernel_test]
+    fn sigframe_layout() {
+        // `fcsr` follows `f[31]` in the D extension layout of the `mcontext`.
+        assert_eq!(size_of::<SigInfo>(), SIGINFO_SIZE);
+        assert_eq!(FRAME_UCONTEXT + UC_MCONTEXT + MC_FCSR, 128 + 176 + 8 * 32 + 8 * 32);
+        assert_eq!(SIGFRAME_SIZE, 1088);
+
+        let (process, task) = create_thread();
+        set_action(process, SIGSEGV, handler(SA_NODEFER, 0)).unwrap();
+        let old_sp = task.trap_frame().regs[cpu::reg(Register::Sp)];
+        task.trap_frame_mut().fcsr = FCSR as usize;
+        force_signal(task, SIGSEGV, SEGV_MAPERR, 0x1234);
+        assert!(deliver_signals(task));
+
+        let frame = task.trap_frame();
+        let sp = frame.regs[cpu::reg(Register::Sp)];
+        assert_eq!(sp, (old_sp - SIGFRAME_SIZE) & !0xf);
+        assert_eq!(frame.pc, HANDLER);
+        assert_eq!(frame.regs[cpu::reg(Register::Ra)], SIGRETURN_TRAMPOLINE);
+        assert_eq!(frame.regs[cpu::reg(Register::A0)], SIGSEGV);
+        assert_eq!(frame.regs[cpu::reg(Register::A1)], sp);
+        assert_eq!(frame.regs[cpu::reg(Register::A2)], sp + 128);
+
+        let aspace = process.address_space_mut();
+        assert_eq!(read_user::<[i32; 3]>(aspace, sp), Ok([SIGSEGV as i32, 0, SEGV_MAPERR]));
+        assert_eq!(read_user::<usize>(aspace, sp + 16), Ok(0x1234));
+        let uc = sp + 128;
+        assert_eq!(read_user::<i32>(aspace, uc + 16 + 8), Ok(SS_DISABLE));
+        assert_eq!(read_user::<SigSet>(aspace, uc + 40), Ok(0));
+        let mc = uc + 176;
+        assert_eq!(read_user::<usize>(aspace, mc), Ok(PC));
+        assert_eq!(read_user::<usize>(aspace, mc + 2 * 8), Ok(old_sp));
+        assert_eq!(read_user::<u32>(aspace, mc + 256 + 32 * 8), Ok(FCSR));
+
+        // The fault information is taken once, a signal sent by `kill` uses `SI_USER`.
+        send_thread_signal(task, SIGSEGV);
+        assert!(deliver_signals(task));
+        let sp = task.trap_frame().regs[cpu::reg(Register::Sp)];
+        let aspace = process.address_space_mut();
+        assert_eq!(read_user::<[i32; 3]>(aspace, sp), Ok([SIGSEGV as i32, 0, SI_USER]));
+        assert_eq!(read_user::<usize>(aspace, sp + 16), Ok(0));
+        destroy_thread(process, task);
+    }
+
+    #[kernel_test]
+    fn sigreturn_restores_context() {
+        let (process, task) = create_thread();
+        set_action(process, SIGUSR1, handler(0, sig_bit(SIGUSR2))).unwrap();
+        set_blocked(task, SIG_SETMASK, Some(sig_bit(SIGHUP))).unwrap();
+        let frame = task.trap_frame_mut();
+        for i in (1..32).filter(|&i| i != cpu::reg(Register::Sp)) {
+            frame.regs[i] = i;
+            frame.fregs[i] = i << 8;
+        }
+        frame.fcsr = FCSR as usize;
+        let (regs, fregs) = (frame.regs, frame.fregs);
+
+        send_thread_signal(task, SIGUSR1);
+        assert!(deliver_signals(task));
+        let blocked = sig_bit(SIGHUP) | sig_bit(SIGUSR1) | sig_bit(SIGUSR2);
+        assert_eq!(set_blocked(task, SIG_BLOCK, None), Ok(blocked));
+
+        // The handler changes the registers, the rounding mode and the saved `pc`, and tries to
+        // block `SIGKILL`.
+        let frame = task.trap_frame_mut();
+        frame.fregs = [0; 32];
+        frame.fcsr = 0x1f;
+        frame.regs[cpu::reg(Register::S0)] = 0;
+        let uc = frame.regs[cpu::reg(Register::Sp)] + FRAME_UCONTEXT;
+        let aspace = process.address_space_mut();
+        write_user(aspace, uc + UC_MCONTEXT, &(PC + 4)).unwrap();
+        write_user(aspace, uc + UC_SIGMASK, &(sig_bit(SIGHUP) | sig_bit(SIGKILL))).unwrap();
+        sigreturn(task).unwrap();
+
+        let frame = task.trap_frame();
+        assert_eq!(frame.pc, PC + 4);
+        assert_eq!((frame.regs, frame.fregs, frame.fcsr), (regs, fregs, FCSR as usize));
+        assert_eq!(set_blocked(task, SIG_BLOCK, None), Ok(sig_bit(SIGHUP)));
+        destroy_thread(process, task);
+    }
+
+    #[kernel_test]
+    fn handler_flags() {
+        let (process, task) = create_thread();
+        set_action(process, SIGUSR1, handler(SA_RESETHAND | SA_NODEFER, 0)).unwrap();
+        set_action(process, SIGUSR2, handler(0, 0)).unwrap();
+
+        // The action is reset when the handler is entered, and the signal is not blocked.
+        send_thread_signal(task, SIGUSR1);
+        assert!(deliver_signals(task));
+        assert_eq!(task.trap_frame().regs[cpu::reg(Register::A0)], SIGUSR1);
+        assert_eq!(set_action(process, SIGUSR1, None).unwrap().handler, SIG_DFL);
+        assert_eq!(set_blocked(task, SIG_BLOCK, None), Ok(0));
+
+        // By default, the action is kept and the signal is blocked in its handler.
+        send_thread_signal(task, SIGUSR2);
+        assert!(deliver_signals(task));
+        assert_eq!(set_action(process, SIGUSR2, None).unwrap().handler, HANDLER);
+        assert_eq!(set_blocked(task, SIG_BLOCK, None), Ok(sig_bit(SIGUSR2)));
+        send_thread_signal(task, SIGUSR2);
+        assert!(deliver_signals(task));
+        assert_eq!(pending_blocked(task), sig_bit(SIGUSR2));
+        destroy_thread(process, task);
+    }
+}
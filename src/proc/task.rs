@@ -3,13 +3,16 @@
 
 use core::ptr::addr_of_mut;
 use crate::proc::kernel::KernelTrapFrame;
+use crate::proc::futex::FutexKey;
+use crate::proc::process::Process;
+use crate::proc::signal::ThreadSignal;
 use crate::smp::HartFrameInfo;
 use crate::util::list::List;
 
 
 /// Status of a task.
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug)]
 #[derive(Eq, PartialEq)]
 pub enum TaskStatus {
     /// Task is ready to run.
@@ -24,6 +27,8 @@ pub enum TaskStatus {
     DeadZombie = 4,
     /// Task dead, destroy all resource including the `TaskInfo` struct.
     Dead = 5,
+    /// Stopped by a signal, until the process is continued.
+    Stopped = 6,
 }
 
 impl TaskStatus {
@@ -79,6 +84,10 @@ pub(crate) union PreemptUnion {
 pub struct TaskInfo {
     frame: TaskTrapFrame,
     pub(crate) list: List,
+    /// Entry of the thread list of the owner process.
+    pub(crate) thread_list: List,
+    /// Owner process, null if the task is a kernel thread.
+    process: *mut Process,
     /// Preemption counter. Preemption is disabled if the `preempt_count` is not zero
     /// (The most 2 bits are flags used for the scheduler: `PREEMPT_NEED_RESCHED` &
     /// `SCHED_CUR_CPU`).
@@ -95,9 +104,16 @@ pub struct TaskInfo {
     /// has a priority that between in \[51, 60] (10 levels). **Normal task** has a priority
     /// of \[-10, 10] (21 levels), `0` means the most normal priority.
     priority: i8,
-    /// Thread exit code.
-    exit_code: usize
-    // todo: Process info
+    /// Thread exit status, see [`exit`](crate::proc::exit).
+    exit_code: usize,
+    /// Timer value to wake up the task if it is in the sleep list or waits on a futex.
+    wake_time: usize,
+    /// Key of the futex the task waits on.
+    futex_key: FutexKey,
+    /// User address to clear when the thread exits (`CLONE_CHILD_CLEARTID`), 0 if not set.
+    clear_child_tid: usize,
+    /// Signal state of a user thread.
+    signal: ThreadSignal,
 }
 
 const TY_MASK_USER_TRAP_IN: u8 = 0b1000_0000u8;
@@ -143,6 +159,19 @@ impl TaskInfo {
         self.ty_flag = (self.ty_flag & !0b0000_0001u8) | ty.val();
     }
 
+    /// Get the owner process, null if the task is a kernel thread.
+    #[inline(always)]
+    pub fn process(&self) -> *mut Process {
+        self.process
+    }
+
+    /// Set the owner process. Only can be called by the process, or by a test to run a kernel
+    /// thread on behalf of a process.
+    #[inline(always)]
+    pub(crate) fn set_process(&mut self, process: *mut Process) {
+        self.process = process;
+    }
+
     /// Check if a user thread is running in the kernel mode.
     #[inline(always)]
     pub fn is_user_in_kernel_mode(&self) -> bool {
@@ -203,6 +232,55 @@ impl TaskInfo {
         self.exit_code = exit_code;
     }
 
+    /// Get the wake up time of a sleeping task.
+    #[inline(always)]
+    pub fn wake_time(&self) -> usize {
+        self.wake_time
+    }
+
+    /// Set the wake up time, only used by the scheduler sleep list and the futex.
+    #[inline(always)]
+    pub(crate) fn set_wake_time(&mut self, wake_time: usize) {
+        self.wake_time = wake_time;
+    }
+
+    /// Get the key of the futex the task waits on.
+    #[inline(always)]
+    pub fn futex_key(&self) -> FutexKey {
+        self.futex_key
+    }
+
+    /// Set the key of the futex the task waits on.
+    #[inline(always)]
+    pub(crate) fn set_futex_key(&mut self, key: FutexKey) {
+        self.futex_key = key;
+    }
+
+    /// Get the user address to clear when the thread exits.
+    #[inline(always)]
+    pub fn clear_child_tid(&self) -> usize {
+        self.clear_child_tid
+    }
+
+    /// Set the user address to clear when the thread exits, 0 to disable.
+    #[inline(always)]
+    pub fn set_clear_child_tid(&mut self, addr: usize) {
+        self.clear_child_tid = addr;
+    }
+
+    /// Get the signal state.
+    #[inline(always)]
+    pub fn signal(&self) -> &ThreadSignal {
+        &self.signal
+    }
+
+    /// Get the mutable signal state, the process lock must be held except for the current
+    /// thread's `syscall_pc`.
+    #[inline(always)]
+    pub fn signal_mut(&mut self) -> &mut ThreadSignal {
+        &mut self.signal
+    }
+
     /// Get ref of the trap frame object.
     #[inline(always)]
     pub fn trap_frame(&self) -> &TaskTrapFrame {
@@ -252,12 +330,14 @@ pub struct TaskTrapFrame {
     pub cpu_stack: *const HartFrameInfo,
     // 528
     pub kernel_stack: *mut KernelTrapFrame,
-    // 536
-    pub satp: usize,
+    // 536, saved and restored with the `fregs`.
+    pub fcsr: usize,
     // 544
-    pub qm: usize,
+    pub satp: usize,
     // 552
-    pub pid: usize,
+    pub qm: usize,
     // 560
+    pub pid: usize,
+    // 568
     pub mode: usize,
 }
@@ -0,0 +1,87 @@
+//! Utilities to build the user thread.
+
+use core::mem::size_of;
+use core::ptr::null_mut;
+use crate::arch::cpu::{self, Register};
+use crate::mm::{kfree, kzalloc, page, PAGE_SIZE, USER_SPACE_END};
+use crate::proc::kernel::KernelStack;
+use crate::proc::pid::{alloc_pid, free_pid};
+use crate::proc::process::Process;
+use crate::proc::task::{TaskInfo, TaskType};
+
+
+/// Top address (exclusive) of the main thread user stack.
+pub const USER_STACK_TOP: usize = USER_SPACE_END;
+/// Size of the main thread user stack, the pages are populated on demand.
+pub const USER_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Build a user thread of `process`, the thread starts to run at `pc` in the U-mode with the
+/// stack pointer `sp`. The first thread of a process uses the PID as its `tid`.
+///
+/// Each user thread has a [`KernelStack`] page, the `kernel_stack` of the trap frame points to
+/// the [`KernelTrapFrame`] part of the kernel stack.
+///
+/// Returns null if out of memory or PIDs.
+///
+/// [`KernelStack`]: crate::proc::kernel::KernelStack
+/// [`KernelTrapFrame`]: crate::proc::kernel::KernelTrapFrame
+pub fn build_user_thread(process: &mut Process, pc: usize, sp: usize) -> *mut TaskInfo {
+    let tid = if process.thread_count() == 0 {
+        process.pid()
+    } else {
+        match alloc_pid() {
+            Some(tid) => tid,
+            None => return null_mut(),
+        }
+    };
+
+    let ptr = kzalloc(size_of::<TaskInfo>(), 0) as *mut TaskInfo;
+    let kernel_stack = page::alloc_page(0) as *mut KernelStack;
+    if ptr.is_null() || kernel_stack.is_null() {
+        kfree(ptr as _);
+        if !kernel_stack.is_null() {
+            page::free_page(kernel_stack as usize);
+        }
+        if tid != process.pid() {
+            free_pid(tid);
+        }
+        return null_mut();
+    }
+
+    let task = unsafe { &mut *ptr };
+    task.set_tid(tid);
+    task.set_task_type(TaskType::User);
+    let frame_ptr = task.get_trap_frame_ptr();
+    let frame = task.trap_frame_mut();
+    frame.pc = pc;
+    unsafe {
+        // SAFETY: reg index is guarded within the ranges.
+        *frame.regs.get_unchecked_mut(cpu::reg(Register::Sp)) = sp;
+        frame.kernel_stack = &mut (*kernel_stack).frame;
+        (*kernel_stack).frame.user_frame = frame_ptr;
+    }
+    process.add_thread(task);
+
+    ptr
+}
+
+/// Remove the user thread from its process and free the thread.
+///
+/// # Safety
+///
+/// The thread must not be running or in any scheduler list, and must not be used after this
+/// call.
+pub unsafe fn free_user_thread(task: *mut TaskInfo) {
+    let task_ref = &mut *task;
+    debug_assert!(task_ref.task_type() == TaskType::User);
+
+    let process = &mut *task_ref.process();
+    if task_ref.tid() != process.pid() {
+        free_pid(task_ref.tid());
+    }
+    process.remove_thread(task_ref);
+
+    let kernel_stack = task_ref.trap_frame().kernel_stack as usize & !(PAGE_SIZE - 1);
+    page::free_page(kernel_stack);
+    kfree(task as _);
+}
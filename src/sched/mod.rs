@@ -71,7 +71,8 @@
 //! ## Floating registers status
 //!
 //! The `FS` field encodes the status of the floating-point unit state, including the
-//! floating-point registers `f0`–`f31` and the CSRs `fcsr`, `frm`, and `fflags`.
+//! floating-point registers `f0`–`f31` and the CSRs `fcsr`, `frm`, and `fflags`. The `fcsr`
+//! (which contains the `frm` and the `fflags`) is saved and restored with the registers.
 //!
 //! The `FS` field is set to `Initial` on boot to enable the floating-point instructions.
 //!
@@ -83,12 +84,16 @@
 mod trap;
 mod scheduler;
 mod preempt;
+mod wait;
 
 // Re-export all.
 pub use scheduler::*;
 pub use preempt::*;
+pub use wait::WaitQueue;
 
 use crate::arch::cpu;
+use crate::mm;
+use crate::proc::signal::deliver_signals;
 use crate::proc::task::{TaskStatus, TaskType};
 use crate::smp::{current_cpu_frame, current_cpu_info};
 
@@ -108,13 +113,20 @@ pub(crate) fn init() {
 
 /// Schedule a task on current CPU.
 ///
-/// 1. Select a task of user process thread or kernel thread.
+/// 1. Select a task of user process thread or kernel thread. The pending signals of a user
+/// thread are delivered, a thread which is stopped or killed by a signal is skipped.
 /// 2. Set the `sstatus->sPP` to correspond the select task type.
-/// 3. Set timer event to next context switching time.
-/// 4. Call `switch_to_task` to restore context and switch to the selected task.
+/// 3. Switch the `satp` to the address space of the selected task.
+/// 4. Set timer event to next context switching time.
+/// 5. Call `switch_to_task` to restore context and switch to the selected task.
 pub(crate) fn schedule() /* -> ! */ {
-    let task = find_ready_task_or_idle();
-    let task_ref = unsafe { &mut *task };
+    let task_ref = loop {
+        let task_ref = unsafe { &mut *find_ready_task_or_idle() };
+        if task_ref.task_type() == TaskType::Kernel || task_ref.is_user_in_kernel_mode() ||
+            deliver_signals(task_ref) {
+            break task_ref;
+        }
+    };
 
     if task_ref.task_type() == TaskType::Kernel || task_ref.is_user_in_kernel_mode() {
         cpu::sstatus_set_bits(cpu::SSTATUS_SPP_BIT);
@@ -122,6 +134,13 @@ pub(crate) fn schedule() /* -> ! */ {
         cpu::sstatus_clear_bits(cpu::SSTATUS_SPP_BIT);
     }
 
+    // All address spaces share the kernel mappings, so it is safe to switch here.
+    if task_ref.task_type() == TaskType::Kernel {
+        mm::activate_identity_map();
+    } else {
+        unsafe { (*task_ref.process()).address_space().activate(); }
+    }
+
     let cpu_info = current_cpu_info();
     cpu::stimecmp_write_delta(if task_ref.is_realtime_task() {
         cpu_info.get_time_slice_realtime()
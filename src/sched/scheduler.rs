@@ -11,6 +11,7 @@ use crate::util::list::List;
 pub(super) fn init_and_set_idle_task() {
     unsafe {
         TASK_LIST.ready_head.init_empty();
+        TASK_LIST.sleep_head.init_empty();
         // todo: add idle task to ready_head with the lowest priority.
         TASK_LIST.cpu_idle.init();
         let all_cpu_data = TASK_LIST.cpu_idle.as_array_mut();
@@ -52,9 +53,52 @@ pub fn ready_list_add_task(task: *mut TaskInfo) {
     list::tail_append(unsafe { &mut TASK_LIST.ready_head }, &mut task_ref.list);
 }
 
+/// Add a task to the sleep list, the task is moved to the ready list by
+/// [`wake_up_sleeping_tasks`] after the timer reaches `wake_time`. The sleep list is sorted by
+/// the wake up time.
+pub fn sleep_list_add_task(task: *mut TaskInfo, wake_time: usize) {
+    let task_ref = unsafe { &mut *task };
+    task_ref.set_status(TaskStatus::InterruptibleSleep);
+    task_ref.set_wake_time(wake_time);
+
+    unsafe {
+        let head = &mut TASK_LIST.sleep_head as *mut List;
+        // Insert before the first task that wakes up later.
+        let mut pos = head;
+        list::for_each(&mut *head, |cur| {
+            let cur_task = container_of_mut!(cur, TaskInfo, list);
+            if (*cur_task).wake_time() > wake_time {
+                pos = cur;
+                return false;
+            }
+            true
+        });
+        list::insert_before(&mut *pos, &mut task_ref.list);
+    }
+}
+
+/// Move all tasks whose wake up time is not later than `now` from the sleep list to the ready
+/// list.
+pub fn wake_up_sleeping_tasks(now: usize) {
+    unsafe {
+        let head = &mut TASK_LIST.sleep_head as *mut List;
+        while !list::is_empty(&*head) {
+            let next = (*head).next;
+            let task = container_of_mut!(next, TaskInfo, list);
+            if (*task).wake_time() > now {
+                break;
+            }
+            list::delete(&mut *next);
+            ready_list_add_task(task);
+        }
+    }
+}
+
 
 struct TaskList {
     pub ready_head: List,
+    /// Sleeping tasks, sorted by the wake up time.
+    pub sleep_head: List,
     /// Idle task struct on per-cpu.
     pub cpu_idle: PerCpuPtr<TaskInfo>,
 }
@@ -63,6 +107,7 @@ impl TaskList {
     pub const fn new() -> Self {
         Self {
             ready_head: List::new(),
+            sleep_head: List::new(),
             cpu_idle: PerCpuPtr::null()
         }
     }
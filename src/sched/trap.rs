@@ -1,8 +1,15 @@
 //! Handle traps in Supervisor mode.
 
+use crate::arch::cpu;
+use crate::{debug, dev};
+use crate::mm::vma::FaultAccess;
+use crate::proc::futex;
+use crate::proc::signal::{deliver_signals, force_signal, BUS_ADRALN, ILL_ILLOPC, SEGV_ACCERR,
+                          SEGV_MAPERR, SIGBUS, SIGILL, SIGSEGV, SIGTRAP, TRAP_BRKPT};
 use crate::proc::task::{TaskInfo, TaskTrapFrame};
-use crate::sched::{ready_list_add_task, schedule};
-use crate::smp::CpuInfo;
+use crate::sched::{ready_list_add_task, schedule, wake_up_sleeping_tasks};
+use crate::smp::{self, CpuInfo};
+use crate::syscall;
 
 
 /// Check the `SPP` field of `sstatus`, return true if Previous Privilege is S-mode.
@@ -16,6 +23,12 @@ fn trap_from_s_mode(status: usize) -> bool {
     status & 0b1_0000_0000 != 0
 }
 
+/// Raise the signal `sig` with the `si_code` `code` on the user thread of `frame` for an
+/// exception at the address `addr`, the signal is delivered before the trap returns.
+fn raise_fault(frame: &mut TaskTrapFrame, sig: usize, code: i32, addr: usize) {
+    force_signal(unsafe { &mut *TaskInfo::from_trap_frame_ptr(frame as _) }, sig, code, addr);
+}
+
 /// Rust trap handler. The `sscratch` register value need to keep unchanged before return.
 ///
 /// Parameters are passed in from the asm code (`asm/trap.S`) by `a0`~`a5`:
@@ -56,12 +69,18 @@ fn handle_trap(
             1 => {
                 // Supervisor software interrupt.
                 // We will use this interrupt to waken our CPUs so that they can process processes.
-                debug!("Supervisor software interrupt on hart #{}", hart.get_hart_id());
+                trace!("Supervisor software interrupt on hart #{}", hart.get_hart_id());
+                dev::irq::count_ipi();
+                smp::handle_ipi();
             }
             5 => {
                 // Supervisor timer interrupt.
                 // Do context switching.
                 trace!("Supervisor timer interrupt on hart #{}", hart.get_hart_id());
+                dev::irq::count_timer_irq();
+                let now = cpu::read_time();
+                wake_up_sleeping_tasks(now);
+                futex::wake_up_timed_out(now);
                 // Get the task struct from TrapFrame. Add current task to ready list.
                 let task = unsafe { TaskInfo::from_trap_frame_ptr(frame as _) };
                 ready_list_add_task(task);
@@ -73,6 +92,7 @@ fn handle_trap(
             9 => {
                 // Supervisor external interrupt.
                 trace!("Supervisor external interrupt on hart #{}", hart.get_hart_id());
+                dev::irq::handle_external_irq();
             }
             _ => {
                 // Unhandled/Unexpected interrupts.
@@ -89,18 +109,27 @@ fn handle_trap(
                 // 2: Illegal Instruction.
                 if trap_from_s_mode(status) {
                     // S-mode code exception.
+                    debug::dump_fatal_trap_stack(epc, frame);
                     panic!("S-mode instruction exception, code: {}, epc: {:#x}, trap val: {}.",
                            exp_code, epc, val);
                 }
 
-                error!("Instruction exception with PID {}, exp code: {}. epc: {:#x}, trap val: {}.",
+                debug!("Instruction exception with PID {}, exp code: {}. epc: {:#x}, trap val: {}.",
                     frame.pid, exp_code, epc, val);
-                // Close the exception process, re-schedule.
+                match exp_code {
+                    1 => raise_fault(frame, SIGSEGV, SEGV_ACCERR, val),
+                    2 => raise_fault(frame, SIGILL, ILL_ILLOPC, epc),
+                    _ => raise_fault(frame, SIGBUS, BUS_ADRALN, val),
+                }
             }
             3 => {
                 // Breakpoint.
                 debug!("Breakpoint on hart #{}, pc @{:#x}", hart.get_hart_id(), epc);
-                return_pc += 2;
+                if trap_from_s_mode(status) {
+                    return_pc += 2;
+                } else {
+                    raise_fault(frame, SIGTRAP, TRAP_BRKPT, epc);
+                }
             }
             4 | 5 | 6 | 7 => {
                 // 4: Load address misaligned.
@@ -108,40 +137,70 @@ fn handle_trap(
                 // 6: Store/AMO address misaligned.
                 // 7: Store/AMO access fault.
                 if trap_from_s_mode(status) {
+                    debug::dump_fatal_trap_stack(epc, frame);
                     panic!("S-mode memory access exception, code: {}, epc: {:#x}, trap val: {}.",
                            exp_code, epc, val);
                 }
 
-                error!("Memory access exception with PID {}, exp code: {}. epc: {:#x}, trap val: {}.",
+                debug!("Memory access exception with PID {}, exp code: {}. epc: {:#x}, trap val: {}.",
                     frame.pid, exp_code, epc, val);
-                // Close the exception process, re-schedule.
+                if exp_code == 5 || exp_code == 7 {
+                    raise_fault(frame, SIGSEGV, SEGV_ACCERR, val);
+                } else {
+                    raise_fault(frame, SIGBUS, BUS_ADRALN, val);
+                }
             }
             8 => {
                 // Environment call from U-mode.
-                debug!("Env call from PID {}.", frame.pid);
-                return_pc += 4;
+                return_pc = syscall::handle_syscall(frame, epc);
             }
             12 | 13 | 15 => {
                 // 12: Instruction page fault.
                 // 13: Load page fault.
                 // 15: Store/AMO page fault.
                 if trap_from_s_mode(status) {
+                    debug::dump_fatal_trap_stack(epc, frame);
                     panic!("S-mode page fault, code: {}, epc: {:#x}, trap val: {}.", exp_code, epc, val);
                 }
 
-                error!("Page fault. exp code: {}, epc: {:#x}, trap val: {}.",
-                    exp_code, epc, val);
-                return_pc += 4;
-                // todo: swap page. keep return pc unchanged.
+                // Populate the page and restart the faulting instruction.
+                let access = match exp_code {
+                    12 => FaultAccess::Execute,
+                    13 => FaultAccess::Read,
+                    _ => FaultAccess::Write,
+                };
+                let task = unsafe { &mut *TaskInfo::from_trap_frame_ptr(frame as _) };
+                let aspace = unsafe { (*task.process()).address_space_mut() };
+                if let Err(errno) = aspace.handle_page_fault(val, access) {
+                    debug!("Page fault with PID {}, exp code: {}, epc: {:#x}, trap val: {:#x}, \
+                           errno: {}.", frame.pid, exp_code, epc, val, errno);
+                    // A mapped address is faulted by the permissions.
+                    let mapped = aspace.find_vma(val).is_some();
+                    let code = if mapped { SEGV_ACCERR } else { SEGV_MAPERR };
+                    raise_fault(frame, SIGSEGV, code, val);
+                }
             }
             _ => {
                 // Unhandled exceptions.
                 let hart_id = hart.get_hart_id();
+                if trap_from_s_mode(status) {
+                    debug::dump_fatal_trap_stack(epc, frame);
+                }
                 panic!("Unhandled exception on hart #{}, exp code: {}, pc @{:#x}, trap val: {:#x}.",
                        hart_id, exp_code, epc, val);
             }
         }
     }
 
+    if !trap_from_s_mode(status) {
+        // Deliver the pending signals before returning to the U-mode.
+        frame.pc = return_pc;
+        let task = unsafe { &mut *TaskInfo::from_trap_frame_ptr(frame as _) };
+        if !deliver_signals(task) {
+            schedule();
+        }
+        return_pc = frame.pc;
+    }
+
     return_pc
 }
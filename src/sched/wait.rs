@@ -0,0 +1,77 @@
+//! Wait queue of the sleeping tasks.
+//!
+//! A task waiting for an event is added to a [`WaitQueue`] with the `InterruptibleSleep` status
+//! (or `UninterruptibleSleep` if the signals must not interrupt the wait), and is moved back to
+//! the ready list when the event happens. Like the other scheduler lists,
+//! the queue is not locked itself: the owner of the queue must protect it with the same lock
+//! which protects the event condition, so a wake up between checking the condition and sleeping
+//! can not be lost.
+
+use crate::proc::task::{TaskInfo, TaskStatus};
+use crate::sched::ready_list_add_task;
+use crate::util::list::{self, List};
+
+
+/// Wait queue, linked by the `list` of [`TaskInfo`].
+#[repr(C)]
+pub struct WaitQueue {
+    head: List,
+}
+
+impl WaitQueue {
+    /// Create a wait queue, [`init`] must be called after the queue is placed at its final
+    /// address.
+    ///
+    /// [`init`]: WaitQueue::init
+    pub const fn new() -> Self {
+        Self { head: List::new() }
+    }
+
+    /// Init the queue as empty.
+    #[inline(always)]
+    pub fn init(&mut self) {
+        self.head.init_empty();
+    }
+
+    /// Check if no task is waiting.
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        list::is_empty(&self.head)
+    }
+
+    /// Add the `task` to the queue, the caller should call `schedule` later.
+    #[inline(always)]
+    pub fn sleep_on(&mut self, task: *mut TaskInfo) {
+        self.wait_on(task, TaskStatus::InterruptibleSleep);
+    }
+
+    /// Add the `task` to the queue with the `UninterruptibleSleep` status, which is not woken up
+    /// by the signals. The caller should call `schedule` later.
+    #[inline(always)]
+    pub fn sleep_uninterruptible_on(&mut self, task: *mut TaskInfo) {
+        self.wait_on(task, TaskStatus::UninterruptibleSleep);
+    }
+
+    /// Add the `task` to the queue with the `Stopped` status, which is not woken up by the
+    /// signals.
+    #[inline(always)]
+    pub fn stop_on(&mut self, task: *mut TaskInfo) {
+        self.wait_on(task, TaskStatus::Stopped);
+    }
+
+    fn wait_on(&mut self, task: *mut TaskInfo, status: TaskStatus) {
+        let task_ref = unsafe { &mut *task };
+        task_ref.set_status(status);
+        list::tail_append(&mut self.head, &mut task_ref.list);
+    }
+
+    /// Move all waiting tasks to the ready list.
+    pub fn wake_up_all(&mut self) {
+        while !list::is_empty(&self.head) {
+            let next = self.head.next;
+            let task = unsafe { container_of_mut!(next, TaskInfo, list) };
+            list::delete(unsafe { &mut *next });
+            ready_list_add_task(task);
+        }
+    }
+}
@@ -1,5 +1,6 @@
 //! CPU information.
 
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 /// Represents the CPU info.
 #[repr(C)]
@@ -17,6 +18,12 @@ pub struct CpuInfo {
     hart_id: usize,
     /// A quick reference to get the cpu_id of current `CpuInfo` object.
     cpu_id: usize,
+    /// Set if the CPU is running the kernel and can respond to the IPI.
+    online: AtomicBool,
+    /// Pending IPI messages, each bit refers to a [`IpiMessage`].
+    ///
+    /// [`IpiMessage`]: crate::smp::IpiMessage
+    ipi_pending: AtomicUsize,
     // Extensions supported by the CPU.
     //extensions: usize,
 }
@@ -83,4 +90,34 @@ impl CpuInfo {
     pub fn get_cpu_id(&self) -> usize {
         self.cpu_id
     }
+
+    /// Mark the CPU online or offline.
+    #[inline(always)]
+    pub fn set_online(&self, online: bool) {
+        self.online.store(online, Ordering::Release);
+    }
+
+    /// Check if the CPU is online.
+    #[inline(always)]
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::Acquire)
+    }
+
+    /// Set the pending IPI bits, return the old pending bits.
+    #[inline(always)]
+    pub fn add_ipi_pending(&self, bits: usize) -> usize {
+        self.ipi_pending.fetch_or(bits, Ordering::AcqRel)
+    }
+
+    /// Take all pending IPI bits and clear them.
+    #[inline(always)]
+    pub fn take_ipi_pending(&self) -> usize {
+        self.ipi_pending.swap(0, Ordering::AcqRel)
+    }
+
+    /// Reset the runtime states. Called on the boot time when the `CpuInfo` object is allocated.
+    pub(super) fn reset_states(&mut self) {
+        self.online = AtomicBool::new(false);
+        self.ipi_pending = AtomicUsize::new(0);
+    }
 }
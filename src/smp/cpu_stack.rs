@@ -53,6 +53,8 @@ pub(super) fn init_per_cpu_stack(cpu_count: usize) {
             stack.frame.sp = &stack.reserved as *const _ as usize;
             stack.frame.gp = gp_val;
             stack.frame.tp = &stack.info as *const _ as usize;
+            stack.info.set_cpu_id(i);
+            stack.info.reset_states();
         }
 
         CPU_STACKS = cpus;
@@ -100,6 +102,17 @@ pub fn get_cpu_stack_by_cpuid_mut(cpuid: usize) -> &'static mut HartTrapStack {
     }
 }
 
+/// Get the memory range `[start, end)` of the trap stack of the CPU `cpuid`.
+pub fn get_cpu_trap_stack_range(cpuid: usize) -> (usize, usize) {
+    unsafe {
+        debug_assert!(cpuid < CPU_COUNT);
+
+        let cpu = CPU_STACKS.add(cpuid);
+        let start = cpu as usize;
+        (start, addr_of!((*cpu).reserved) as usize + size_of::<usize>())
+    }
+}
+
 /// Get cpu stack of boot cpu (hart id == 0).
 pub fn get_boot_cpu_stack() -> &'static mut HartTrapStack {
     unsafe {
@@ -0,0 +1,104 @@
+//! Inter-processor interrupt (IPI) support.
+//!
+//! An IPI is delivered as a **supervisor software interrupt**. The sender records the message in
+//! the pending bits of the target [`CpuInfo`], then asks the M-mode firmware to raise the software
+//! interrupt on the target harts. The receiver handles all pending messages in [`handle_ipi`].
+//!
+//! [`CpuInfo`]: crate::smp::CpuInfo
+//! [`handle_ipi`]: handle_ipi
+
+use crate::arch::{cpu, firmware};
+use crate::base::irq;
+use crate::mm::tlb;
+use super::{current_cpu_info, get_cpu_count, get_cpu_info_by_cpuid};
+
+
+/// Messages that can be sent by IPI.
+#[repr(usize)]
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum IpiMessage {
+    /// Stop the CPU. The CPU will be parked until reset.
+    Stop = 0,
+    /// Ask the CPU to do a reschedule.
+    Reschedule = 1,
+    /// Ask the CPU to handle the TLB flush requests, see [`tlb`](crate::mm::tlb).
+    TlbFlush = 2,
+}
+
+impl IpiMessage {
+    #[inline(always)]
+    pub const fn bit(self) -> usize {
+        1usize << (self as usize)
+    }
+}
+
+/// Send the IPI `msg` to the CPU of `cpu_id`.
+pub fn send_ipi(cpu_id: usize, msg: IpiMessage) {
+    let cpu = get_cpu_info_by_cpuid(cpu_id);
+    cpu.add_ipi_pending(msg.bit());
+    firmware::send_ipi(1usize << cpu.get_hart_id());
+}
+
+/// Send the IPI `msg` to the online CPUs in the `cpu_mask` (bit `n` for the cpu id `n`).
+pub fn send_ipi_many(cpu_mask: usize, msg: IpiMessage) {
+    let mut hart_mask = 0usize;
+    for id in 0..get_cpu_count() {
+        let cpu = get_cpu_info_by_cpuid(id);
+        if cpu_mask & (1usize << id) == 0 || !cpu.is_online() {
+            continue;
+        }
+
+        cpu.add_ipi_pending(msg.bit());
+        hart_mask |= 1usize << cpu.get_hart_id();
+    }
+
+    if hart_mask != 0 {
+        firmware::send_ipi(hart_mask);
+    }
+}
+
+/// Send the IPI `msg` to all online CPUs except for the current CPU.
+pub fn send_ipi_all_but_self(msg: IpiMessage) {
+    let self_id = current_cpu_info().get_cpu_id();
+    let mut hart_mask = 0usize;
+    for id in 0..get_cpu_count() {
+        let cpu = get_cpu_info_by_cpuid(id);
+        if id == self_id || !cpu.is_online() {
+            continue;
+        }
+
+        cpu.add_ipi_pending(msg.bit());
+        hart_mask |= 1usize << cpu.get_hart_id();
+    }
+
+    if hart_mask != 0 {
+        firmware::send_ipi(hart_mask);
+    }
+}
+
+/// Handle the supervisor software interrupt. Called from the trap handler.
+pub(crate) fn handle_ipi() {
+    cpu::sip_clear_bits(cpu::SIP_SSIP_BIT);
+
+    let cpu = current_cpu_info();
+    let pending = cpu.take_ipi_pending();
+    if pending & IpiMessage::Stop.bit() != 0 {
+        stop_self();
+    }
+    if pending & IpiMessage::Reschedule.bit() != 0 {
+        // The time slice will be checked on the next timer interrupt.
+        trace!("Reschedule IPI on cpu #{}", cpu.get_cpu_id());
+    }
+    if pending & IpiMessage::TlbFlush.bit() != 0 {
+        tlb::handle_flush_request();
+    }
+}
+
+/// Mark the current CPU offline and park it forever.
+pub(crate) fn stop_self() -> ! {
+    irq::local_irq_disable();
+    current_cpu_info().set_online(false);
+    loop {
+        cpu::wait_for_interrupt();
+    }
+}
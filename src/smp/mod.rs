@@ -3,10 +3,15 @@
 mod cpu_info;
 mod per_cpu;
 mod cpu_stack;
+mod ipi;
 
 pub use cpu_info::CpuInfo;
 pub use cpu_stack::*;
 pub use per_cpu::PerCpuPtr;
+pub use ipi::{IpiMessage, send_ipi, send_ipi_all_but_self, send_ipi_many};
+pub(crate) use ipi::handle_ipi;
+
+use crate::arch::cpu;
 
 
 /// SMP CPU count.
@@ -29,3 +34,36 @@ pub fn get_cpu_count() -> usize {
         CPU_COUNT
     }
 }
+
+/// Get the number of online CPUs.
+pub fn get_online_cpu_count() -> usize {
+    (0..get_cpu_count()).filter(|&id| get_cpu_info_by_cpuid(id).is_online()).count()
+}
+
+/// Mark the current CPU online. Called when the CPU enters the kernel.
+pub fn set_current_cpu_online() {
+    current_cpu_info().set_online(true);
+}
+
+/// Stop all other CPUs. Used on the system shutdown, reboot and panic path.
+///
+/// The function waits at most 1 second for other CPUs to be stopped; returns `true` if all other
+/// CPUs are stopped.
+pub fn stop_other_cpus() -> bool {
+    if get_online_cpu_count() <= 1 {
+        return true;
+    }
+
+    send_ipi_all_but_self(IpiMessage::Stop);
+
+    let timeout = current_cpu_info().get_timebase_freq();
+    let start = cpu::read_time();
+    while cpu::read_time() - start < timeout {
+        if get_online_cpu_count() <= 1 {
+            return true;
+        }
+    }
+
+    warn!("Failed to stop other CPUs, {} CPU(s) still online.", get_online_cpu_count() - 1);
+    false
+}
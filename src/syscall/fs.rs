@@ -0,0 +1,590 @@
+//! File syscalls. The fds are looked up in the [`FdTable`] of the calling process, and the paths
+//! are resolved by the [VFS](crate::fs) relative to the current directory or a `dirfd`.
+//!
+//! [`FdTable`]: crate::fs::FdTable
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use crate::errno::{E_AGAIN, E_FAULT, E_INVALID, E_NOT_DIR, E_RANGE};
+use crate::fs::file::{File, O_CLOEXEC, O_NONBLOCK};
+use crate::fs::vfs::RENAME_NOREPLACE;
+use crate::fs::{mount, vfs, lookup_path, Dentry, InodeType, Stat, PATH_MAX};
+use crate::mm::address_space::AddressSpace;
+use crate::mm::page_cache;
+use crate::mm::uaccess::{copy_from_user, copy_string_from_user, copy_to_user, write_user};
+use crate::mm::PAGE_SIZE;
+use crate::proc::process::Process;
+use crate::proc::task::TaskTrapFrame;
+use crate::sched::{ready_list_add_task, schedule};
+use crate::util::align::align_up_by;
+use super::{current_process, current_task, to_return};
+
+
+/// The `dirfd` of the current directory.
+const AT_FDCWD: isize = -100;
+const AT_SYMLINK_NOFOLLOW: usize = 0x100;
+const AT_REMOVEDIR: usize = 0x200;
+const AT_SYMLINK_FOLLOW: usize = 0x400;
+const AT_EMPTY_PATH: usize = 0x1000;
+
+// Mount flags.
+const MS_RDONLY: usize = 1;
+const MS_REMOUNT: usize = 32;
+const MS_BIND: usize = 4096;
+const MS_MOVE: usize = 8192;
+// Unmount flags.
+const MNT_FORCE: usize = 1;
+const MNT_DETACH: usize = 2;
+const MNT_EXPIRE: usize = 4;
+const UMOUNT_NOFOLLOW: usize = 8;
+
+/// Size of the kernel buffer used to copy the user data.
+const COPY_CHUNK_SIZE: usize = PAGE_SIZE;
+
+/// Max count of the buffers of `readv` and `writev`.
+const IOV_MAX: usize = 1024;
+
+/// `struct iovec` of the Linux riscv64 ABI.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct IoVec {
+    base: usize,
+    len: usize,
+}
+
+/// `struct stat` of the Linux riscv64 ABI.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct UserStat {
+    st_dev: u64,
+    st_ino: u64,
+    st_mode: u32,
+    st_nlink: u32,
+    st_uid: u32,
+    st_gid: u32,
+    st_rdev: u64,
+    __pad1: u64,
+    st_size: i64,
+    st_blksize: i32,
+    __pad2: i32,
+    st_blocks: i64,
+    st_atime: i64,
+    st_atime_nsec: u64,
+    st_mtime: i64,
+    st_mtime_nsec: u64,
+    st_ctime: i64,
+    st_ctime_nsec: u64,
+    __unused4: u32,
+    __unused5: u32,
+}
+
+impl From<Stat> for UserStat {
+    fn from(stat: Stat) -> UserStat {
+        const NSEC_PER_SEC: u64 = 1_000_000_000;
+        UserStat {
+            st_dev: stat.dev,
+            st_ino: stat.ino,
+            st_mode: stat.mode,
+            st_nlink: stat.nlink,
+            st_uid: stat.uid,
+            st_gid: stat.gid,
+            st_rdev: stat.rdev,
+            st_size: stat.size as i64,
+            st_blksize: stat.blksize as i32,
+            st_blocks: stat.blocks as i64,
+            st_atime: (stat.atime / NSEC_PER_SEC) as i64,
+            st_atime_nsec: stat.atime % NSEC_PER_SEC,
+            st_mtime: (stat.mtime / NSEC_PER_SEC) as i64,
+            st_mtime_nsec: stat.mtime % NSEC_PER_SEC,
+            st_ctime: (stat.ctime / NSEC_PER_SEC) as i64,
+            st_ctime_nsec: stat.ctime % NSEC_PER_SEC,
+            ..UserStat::default()
+        }
+    }
+}
+
+/// Size of the fixed part of a `struct linux_dirent64`.
+const DIRENT64_HEADER_SIZE: usize = 19;
+
+/// Read the path at the user address `addr`. Returns `Err` with `E_INVALID` if it is not UTF-8.
+fn read_path(aspace: &mut AddressSpace, addr: usize) -> Result<String, i32> {
+    let mut buf = vec![0u8; PATH_MAX];
+    let len = copy_string_from_user(aspace, &mut buf, addr)?;
+    buf.truncate(len);
+    String::from_utf8(buf).map_err(|_| E_INVALID)
+}
+
+/// Get the base directory to resolve the `path` from: `None` (the root) for an absolute path,
+/// the current directory for `AT_FDCWD`, or the directory opened as `dirfd`.
+fn base_dir(process: &Process, dirfd: usize, path: &str) -> Result<Option<Arc<Dentry>>, i32> {
+    if path.starts_with('/') {
+        return Ok(None);
+    }
+    if dirfd as isize == AT_FDCWD {
+        return Ok(process.cwd());
+    }
+
+    let file = process.files().get(dirfd)?;
+    let dentry = file.dentry().ok_or(E_NOT_DIR)?;
+    if dentry.inode_type()? != InodeType::Dir {
+        return Err(E_NOT_DIR);
+    }
+    Ok(Some(dentry.clone()))
+}
+
+/// Read the path at the user address `addr` and its base directory of `dirfd`.
+fn read_path_at(process: &mut Process, dirfd: usize, addr: usize)
+    -> Result<(Option<Arc<Dentry>>, String), i32> {
+    let path = read_path(process.address_space_mut(), addr)?;
+    Ok((base_dir(process, dirfd, &path)?, path))
+}
+
+/// `getcwd(buf, size)`. Returns the length of the path with the NUL.
+pub(super) fn sys_getcwd(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let (buf, size) = (args[0], args[1]);
+    let process = current_process(frame);
+    let mut path = process.cwd().map_or(String::from("/"), |cwd| cwd.path());
+    path.push('\0');
+    if size < path.len() {
+        return -E_RANGE as isize;
+    }
+    to_return(copy_to_user(process.address_space_mut(), buf, path.as_bytes()).map(|_| path.len()))
+}
+
+/// `chdir(path)`.
+pub(super) fn sys_chdir(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let process = current_process(frame);
+    let result = read_path_at(process, AT_FDCWD as usize, args[0]).and_then(|(base, path)| {
+        let dentry = lookup_path(base.as_ref(), &path, true)?;
+        if dentry.inode_type()? != InodeType::Dir {
+            return Err(E_NOT_DIR);
+        }
+        process.set_cwd(dentry);
+        Ok(0)
+    });
+    to_return(result)
+}
+
+/// `openat(dirfd, path, flags, mode)`.
+pub(super) fn sys_openat(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let (dirfd, flags, mode) = (args[0], args[2], args[3] as u32);
+    let process = current_process(frame);
+    let result = read_path_at(process, dirfd, args[1]).and_then(|(base, path)| {
+        let file = vfs::open(base.as_ref(), &path, flags, mode)?;
+        process.files_mut().alloc(file, flags & O_CLOEXEC != 0)
+    });
+    to_return(result)
+}
+
+/// `close(fd)`.
+pub(super) fn sys_close(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    to_return(current_process(frame).files_mut().close(args[0]).map(|_| 0))
+}
+
+/// `mkdirat(dirfd, path, mode)`.
+pub(super) fn sys_mkdirat(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let process = current_process(frame);
+    let result = read_path_at(process, args[0], args[1])
+        .and_then(|(base, path)| vfs::mkdir(base.as_ref(), &path, args[2] as u32));
+    to_return(result.map(|_| 0))
+}
+
+/// `unlinkat(dirfd, path, flags)`. A directory is removed with the `AT_REMOVEDIR` flag.
+pub(super) fn sys_unlinkat(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let flags = args[2];
+    if flags & !AT_REMOVEDIR != 0 {
+        return -E_INVALID as isize;
+    }
+    let process = current_process(frame);
+    let result = read_path_at(process, args[0], args[1])
+        .and_then(|(base, path)| vfs::unlink(base.as_ref(), &path, flags & AT_REMOVEDIR != 0));
+    to_return(result.map(|_| 0))
+}
+
+/// `mknodat(dirfd, path, mode, dev)`.
+pub(super) fn sys_mknodat(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let (mode, dev) = (args[2] as u32, args[3] as u32 as u64);
+    let process = current_process(frame);
+    let result = read_path_at(process, args[0], args[1])
+        .and_then(|(base, path)| vfs::mknod(base.as_ref(), &path, mode, dev));
+    to_return(result.map(|_| 0))
+}
+
+/// `symlinkat(target, dirfd, path)`.
+pub(super) fn sys_symlinkat(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let process = current_process(frame);
+    let result = read_path(process.address_space_mut(), args[0]).and_then(|target| {
+        let (base, path) = read_path_at(process, args[1], args[2])?;
+        vfs::symlink(base.as_ref(), &target, &path)
+    });
+    to_return(result.map(|_| 0))
+}
+
+/// `linkat(olddirfd, oldpath, newdirfd, newpath, flags)`. Only the `AT_SYMLINK_FOLLOW` flag is
+/// supported.
+pub(super) fn sys_linkat(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let flags = args[4];
+    if flags & !AT_SYMLINK_FOLLOW != 0 {
+        return -E_INVALID as isize;
+    }
+    let process = current_process(frame);
+    let result = read_path_at(process, args[0], args[1]).and_then(|(old_base, old_path)| {
+        let (new_base, new_path) = read_path_at(process, args[2], args[3])?;
+        let follow = flags & AT_SYMLINK_FOLLOW != 0;
+        vfs::link(old_base.as_ref(), &old_path, new_base.as_ref(), &new_path, follow)
+    });
+    to_return(result.map(|_| 0))
+}
+
+/// `renameat2(olddirfd, oldpath, newdirfd, newpath, flags)`. Only the `RENAME_NOREPLACE` flag
+/// is supported.
+pub(super) fn sys_renameat2(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let flags = args[4];
+    if flags & !RENAME_NOREPLACE != 0 {
+        return -E_INVALID as isize;
+    }
+    let process = current_process(frame);
+    let result = read_path_at(process, args[0], args[1]).and_then(|(old_base, old_path)| {
+        let (new_base, new_path) = read_path_at(process, args[2], args[3])?;
+        vfs::rename(old_base.as_ref(), &old_path, new_base.as_ref(), &new_path, flags)
+    });
+    to_return(result.map(|_| 0))
+}
+
+/// `readlinkat(dirfd, path, buf, size)`. The target is truncated to `size` bytes without the
+/// NUL, returns the length copied.
+pub(super) fn sys_readlinkat(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let (buf, size) = (args[2], args[3] as isize);
+    if size <= 0 {
+        return -E_INVALID as isize;
+    }
+    let process = current_process(frame);
+    let result = read_path_at(process, args[0], args[1]).and_then(|(base, path)| {
+        let target = vfs::read_link(base.as_ref(), &path)?;
+        let len = target.len().min(size as usize);
+        copy_to_user(process.address_space_mut(), buf, &target.as_bytes()[..len]).map(|_| len)
+    });
+    to_return(result)
+}
+
+/// `newfstatat(dirfd, path, statbuf, flags)`. With the `AT_EMPTY_PATH` flag, an empty path
+/// refers to the `dirfd` itself.
+pub(super) fn sys_newfstatat(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let (dirfd, statbuf, flags) = (args[0], args[2], args[3]);
+    if flags & !(AT_SYMLINK_NOFOLLOW | AT_EMPTY_PATH) != 0 {
+        return -E_INVALID as isize;
+    }
+    let process = current_process(frame);
+    let result = read_path(process.address_space_mut(), args[1]).and_then(|path| {
+        if path.is_empty() && flags & AT_EMPTY_PATH != 0 {
+            return match dirfd as isize {
+                AT_FDCWD => vfs::stat(process.cwd().as_ref(), ".", true),
+                _ => process.files().get(dirfd)?.stat(),
+            };
+        }
+        let base = base_dir(process, dirfd, &path)?;
+        vfs::stat(base.as_ref(), &path, flags & AT_SYMLINK_NOFOLLOW == 0)
+    });
+    let result = result.and_then(|stat| {
+        write_user(process.address_space_mut(), statbuf, &UserStat::from(stat))
+    });
+    to_return(result.map(|_| 0))
+}
+
+/// `fstat(fd, statbuf)`.
+pub(super) fn sys_fstat(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let process = current_process(frame);
+    let result = process.files().get(args[0]).and_then(|file| file.stat()).and_then(|stat| {
+        write_user(process.address_space_mut(), args[1], &UserStat::from(stat))
+    });
+    to_return(result.map(|_| 0))
+}
+
+/// `sync()`. Writes back the file systems and then all dirty pages, the errors are ignored.
+pub(super) fn sys_sync(_frame: &mut TaskTrapFrame, _args: &[usize; 6]) -> isize {
+    let _ = mount::sync_filesystems();
+    let _ = page_cache::sync_all();
+    0
+}
+
+/// `fsync(fd)`.
+pub(super) fn sys_fsync(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let result = current_process(frame).files().get(args[0]).and_then(|file| file.sync(false));
+    to_return(result.map(|_| 0))
+}
+
+/// `fdatasync(fd)`. Like `fsync`, but the attributes are not written unless needed to read
+/// the data.
+pub(super) fn sys_fdatasync(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let result = current_process(frame).files().get(args[0]).and_then(|file| file.sync(true));
+    to_return(result.map(|_| 0))
+}
+
+/// Read the optional string at the user address `addr`, empty if it is NULL.
+fn read_optional_string(aspace: &mut AddressSpace, addr: usize) -> Result<String, i32> {
+    if addr == 0 { Ok(String::new()) } else { read_path(aspace, addr) }
+}
+
+/// `mount(source, target, fstype, flags, data)`. The `data` is the option string of the file
+/// system, `MS_RDONLY` is passed as the `ro` option. The remount, bind and move operations are
+/// not supported, the other flags are ignored.
+pub(super) fn sys_mount(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let flags = args[3];
+    if flags & (MS_REMOUNT | MS_BIND | MS_MOVE) != 0 {
+        return -E_INVALID as isize;
+    }
+    let process = current_process(frame);
+    let result = read_path_at(process, AT_FDCWD as usize, args[1]).and_then(|(base, path)| {
+        let aspace = process.address_space_mut();
+        let source = read_optional_string(aspace, args[0])?;
+        let fs_name = read_path(aspace, args[2])?;
+        let mut data = read_optional_string(aspace, args[4])?;
+        if flags & MS_RDONLY != 0 {
+            data.insert_str(0, if data.is_empty() { "ro" } else { "ro," });
+        }
+        // The mount table resolves the target from the root.
+        let target = lookup_path(base.as_ref(), &path, true)?.path();
+        mount::mount(&source, &target, &fs_name, &data)
+    });
+    to_return(result.map(|_| 0))
+}
+
+/// `umount2(target, flags)`. The dirty data is written back first. The flags other than
+/// `UMOUNT_NOFOLLOW` are checked but make no difference.
+pub(super) fn sys_umount2(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let flags = args[1];
+    if flags & !(MNT_FORCE | MNT_DETACH | MNT_EXPIRE | UMOUNT_NOFOLLOW) != 0 ||
+        (flags & MNT_EXPIRE != 0 && flags & (MNT_FORCE | MNT_DETACH) != 0) {
+        return -E_INVALID as isize;
+    }
+    let process = current_process(frame);
+    let result = read_path_at(process, AT_FDCWD as usize, args[0]).and_then(|(base, path)| {
+        let follow = flags & UMOUNT_NOFOLLOW == 0;
+        mount::umount(&lookup_path(base.as_ref(), &path, follow)?.path())
+    });
+    to_return(result.map(|_| 0))
+}
+
+/// `getdents64(fd, dirp, count)`. Returns the bytes of the entries filled, 0 at the end of the
+/// directory, or `EINVAL` if the buffer is too small for the next entry.
+pub(super) fn sys_getdents64(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let (fd, dirp, count) = (args[0], args[1], args[2]);
+    let process = current_process(frame);
+    let file = match process.files().get(fd) {
+        Ok(file) => file,
+        Err(errno) => return -errno as isize,
+    };
+
+    let mut data = vec![0u8; count.min(COPY_CHUNK_SIZE)];
+    let mut len = 0;
+    let mut too_small = false;
+    let result = file.read_dir(&mut |entry, next| {
+        let reclen = align_up_by(DIRENT64_HEADER_SIZE + entry.name.len() + 1, 8);
+        if len + reclen > data.len() {
+            too_small = len == 0;
+            return false;
+        }
+        let dirent = &mut data[len..len + reclen];
+        dirent[0..8].copy_from_slice(&entry.ino.to_ne_bytes());
+        dirent[8..16].copy_from_slice(&(next as i64).to_ne_bytes());
+        dirent[16..18].copy_from_slice(&(reclen as u16).to_ne_bytes());
+        dirent[18] = entry.kind.dirent_type();
+        dirent[DIRENT64_HEADER_SIZE..DIRENT64_HEADER_SIZE + entry.name.len()]
+            .copy_from_slice(entry.name.as_bytes());
+        len += reclen;
+        true
+    });
+    let result = result.and_then(|_| {
+        if too_small {
+            return Err(E_INVALID);
+        }
+        copy_to_user(process.address_space_mut(), dirp, &data[..len]).map(|_| len)
+    });
+    to_return(result)
+}
+
+/// `ioctl(fd, cmd, arg)`. The request is done by the file, usually a device.
+pub(super) fn sys_ioctl(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let process = current_process(frame);
+    let result = process.files().get(args[0])
+        .and_then(|file| file.ioctl(args[1] as u32, args[2], process.address_space_mut()));
+    to_return(result)
+}
+
+/// `lseek(fd, offset, whence)`.
+pub(super) fn sys_lseek(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let (fd, offset, whence) = (args[0], args[1] as isize, args[2]);
+    let result = current_process(frame).files().get(fd)
+        .and_then(|file| file.seek(offset, whence));
+    to_return(result)
+}
+
+/// `read(fd, buf, count)`.
+///
+/// If no data is available and the file is not non-blocking, the syscall is restarted after the
+/// other tasks run.
+pub(super) fn sys_read(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let (fd, buf, count) = (args[0], args[1], args[2]);
+    let process = current_process(frame);
+    let file = match process.files().get(fd) {
+        Ok(file) => file,
+        Err(errno) => return -errno as isize,
+    };
+
+    let mut data = vec![0u8; count.min(COPY_CHUNK_SIZE)];
+    match file.read(&mut data) {
+        Ok(len) => {
+            to_return(copy_to_user(process.address_space_mut(), buf, &data[..len]).map(|_| len))
+        }
+        Err(E_AGAIN) if file.flags() & O_NONBLOCK == 0 => {
+            // The `schedule` never returns, release the file and the buffer first.
+            drop(file);
+            drop(data);
+            // Restart the `ecall` later.
+            frame.pc -= 4;
+            ready_list_add_task(current_task(frame));
+            schedule();
+            unreachable!()
+        }
+        Err(errno) => -errno as isize,
+    }
+}
+
+/// `write(fd, buf, count)`.
+pub(super) fn sys_write(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let (fd, buf, count) = (args[0], args[1], args[2]);
+    let process = current_process(frame);
+    let result = process.files().get(fd)
+        .and_then(|file| write_from_user(process.address_space_mut(), &*file, buf, count));
+    to_return(result)
+}
+
+/// Write `count` bytes at the user address `buf` to the `file`, by the chunks of
+/// [`COPY_CHUNK_SIZE`]. Returns the count written, which is short if the file takes less, or
+/// the count written before an error.
+fn write_from_user(aspace: &mut AddressSpace, file: &dyn File, buf: usize, count: usize)
+    -> Result<usize, i32> {
+    let mut data = vec![0u8; count.min(COPY_CHUNK_SIZE)];
+    let mut done = 0;
+    while done < count {
+        let len = (count - done).min(COPY_CHUNK_SIZE);
+        let written = copy_from_user(aspace, &mut data[..len], buf.wrapping_add(done))
+            .map_err(|_| E_FAULT)
+            .and_then(|_| file.write(&data[..len]));
+        match written {
+            Ok(written) => {
+                done += written;
+                if written < len {
+                    break;
+                }
+            }
+            // Returns the count written before the error.
+            Err(_) if done > 0 => break,
+            Err(errno) => return Err(errno),
+        }
+    }
+
+    Ok(done)
+}
+
+/// Read the `struct iovec` array of `count` buffers at the user address `addr`. Returns `Err`
+/// with `E_INVALID` if there are more than [`IOV_MAX`] buffers or the total length overflows.
+fn read_iovecs(aspace: &mut AddressSpace, addr: usize, count: usize) -> Result<Vec<IoVec>, i32> {
+    if count > IOV_MAX {
+        return Err(E_INVALID);
+    }
+    let mut iovecs = vec![IoVec::default(); count];
+    let buf = unsafe {
+        core::slice::from_raw_parts_mut(iovecs.as_mut_ptr() as *mut u8,
+                                        count * size_of::<IoVec>())
+    };
+    copy_from_user(aspace, buf, addr)?;
+    iovecs.iter()
+        .try_fold(0usize, |total, iov| total.checked_add(iov.len))
+        .filter(|&total| total <= isize::MAX as usize)
+        .ok_or(E_INVALID)?;
+    Ok(iovecs)
+}
+
+/// `readv(fd, iov, iovcnt)`. The buffers are filled in order until a short read, returns the
+/// total count read, or the count read before an error.
+///
+/// Like `read`, the syscall is restarted if no data is available for the first buffer.
+pub(super) fn sys_readv(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let (fd, iov, iovcnt) = (args[0], args[1], args[2]);
+    let process = current_process(frame);
+    let result = process.files().get(fd).and_then(|file| {
+        Ok((file, read_iovecs(process.address_space_mut(), iov, iovcnt)?))
+    });
+    let (file, iovecs) = match result {
+        Ok(val) => val,
+        Err(errno) => return -errno as isize,
+    };
+
+    let mut data = vec![0u8; COPY_CHUNK_SIZE];
+    let mut done = 0;
+    for iov in &iovecs {
+        let mut offset = 0;
+        while offset < iov.len {
+            let len = (iov.len - offset).min(COPY_CHUNK_SIZE);
+            let read = file.read(&mut data[..len]).and_then(|read| {
+                copy_to_user(process.address_space_mut(), iov.base.wrapping_add(offset),
+                             &data[..read]).map(|_| read)
+            });
+            match read {
+                Ok(read) => {
+                    done += read;
+                    offset += read;
+                    if read < len {
+                        return done as isize;
+                    }
+                }
+                Err(E_AGAIN) if done == 0 && file.flags() & O_NONBLOCK == 0 => {
+                    // The `schedule` never returns, release the file and the buffers first.
+                    drop(file);
+                    drop(data);
+                    drop(iovecs);
+                    // Restart the `ecall` later.
+                    frame.pc -= 4;
+                    ready_list_add_task(current_task(frame));
+                    schedule();
+                    unreachable!()
+                }
+                // Returns the count read before the error.
+                Err(_) if done > 0 => return done as isize,
+                Err(errno) => return -errno as isize,
+            }
+        }
+    }
+
+    done as isize
+}
+
+/// `writev(fd, iov, iovcnt)`. The buffers are written in order until a short write, returns the
+/// total count written, or the count written before an error.
+pub(super) fn sys_writev(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let (fd, iov, iovcnt) = (args[0], args[1], args[2]);
+    let process = current_process(frame);
+    let result = process.files().get(fd).and_then(|file| {
+        let aspace = process.address_space_mut();
+        let mut done = 0;
+        for iov in read_iovecs(aspace, iov, iovcnt)? {
+            match write_from_user(aspace, &*file, iov.base, iov.len) {
+                Ok(written) => {
+                    done += written;
+                    if written < iov.len {
+                        break;
+                    }
+                }
+                // Returns the count written before the error.
+                Err(_) if done > 0 => break,
+                Err(errno) => return Err(errno),
+            }
+        }
+        Ok(done)
+    });
+    to_return(result)
+}
@@ -0,0 +1,64 @@
+//! Futex syscall, see [`futex`](crate::proc::futex).
+
+use crate::errno::E_NO_SYS;
+use crate::proc::futex::{self, futex_key};
+use crate::proc::task::TaskTrapFrame;
+use crate::sched::schedule;
+use super::{current_process, current_task, set_return, to_return};
+use super::time::read_timeout;
+
+
+const FUTEX_WAIT: usize = 0;
+const FUTEX_WAKE: usize = 1;
+const FUTEX_REQUEUE: usize = 3;
+const FUTEX_CMP_REQUEUE: usize = 4;
+const FUTEX_PRIVATE_FLAG: usize = 128;
+const FUTEX_CLOCK_REALTIME: usize = 256;
+
+/// Convert an `int` count argument, the negative ones are 0.
+#[inline(always)]
+fn to_count(arg: usize) -> usize {
+    (arg as i32).max(0) as usize
+}
+
+/// `futex(uaddr, futex_op, val, timeout or val2, uaddr2, val3)`. Supports `FUTEX_WAIT` with a
+/// relative timeout, `FUTEX_WAKE`, `FUTEX_REQUEUE` and `FUTEX_CMP_REQUEUE`.
+pub(super) fn sys_futex(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let (addr, op, val) = (args[0], args[1], args[2]);
+    let private = op & FUTEX_PRIVATE_FLAG != 0;
+    let aspace = current_process(frame).address_space_mut();
+    let key = match futex_key(aspace, addr, private) {
+        Ok(key) => key,
+        Err(errno) => return -errno as isize,
+    };
+
+    match op & !(FUTEX_PRIVATE_FLAG | FUTEX_CLOCK_REALTIME) {
+        FUTEX_WAIT => {
+            let timeout = match args[3] {
+                0 => None,
+                timeout => match read_timeout(aspace, timeout) {
+                    Ok(ticks) => Some(ticks),
+                    Err(errno) => return -errno as isize,
+                },
+            };
+            set_return(frame, 0);
+            if let Err(errno) = futex::futex_wait(current_task(frame), key, addr, val as u32,
+                                                  timeout) {
+                return -errno as isize;
+            }
+            schedule();
+            0
+        }
+        FUTEX_WAKE => futex::futex_wake(key, to_count(val)) as isize,
+        cmd @ (FUTEX_REQUEUE | FUTEX_CMP_REQUEUE) => {
+            let to = match futex_key(aspace, args[4], private) {
+                Ok(key) => key,
+                Err(errno) => return -errno as isize,
+            };
+            let cmp = (cmd == FUTEX_CMP_REQUEUE).then_some(args[5] as u32);
+            to_return(futex::futex_requeue(aspace, addr, key, to, to_count(val),
+                                           to_count(args[3]), cmp))
+        }
+        _ => -E_NO_SYS as isize,
+    }
+}
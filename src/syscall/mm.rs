@@ -0,0 +1,90 @@
+//! Memory syscalls. The pages are populated lazily on the page faults, see [`AddressSpace`].
+//!
+//! [`AddressSpace`]: crate::mm::address_space::AddressSpace
+
+use crate::errno::{E_INVALID, E_NO_DEV};
+use crate::mm::PAGE_SIZE;
+use crate::mm::address_space::{AddressSpace, MapPlacement};
+use crate::mm::mmu::EntryBits;
+use crate::proc::task::TaskTrapFrame;
+use super::{current_process, to_return};
+
+
+const PROT_READ: usize = 0x1;
+const PROT_WRITE: usize = 0x2;
+const PROT_EXEC: usize = 0x4;
+
+const MAP_SHARED: usize = 0x01;
+const MAP_PRIVATE: usize = 0x02;
+const MAP_FIXED: usize = 0x10;
+const MAP_ANONYMOUS: usize = 0x20;
+const MAP_FIXED_NOREPLACE: usize = 0x10_0000;
+
+/// Convert the `PROT_*` flags to the [`EntryBits`]. Writable pages must also be readable.
+fn prot_to_bits(prot: usize) -> u32 {
+    let mut bits = 0;
+    if prot & PROT_READ != 0 {
+        bits |= EntryBits::Read.val();
+    }
+    if prot & PROT_WRITE != 0 {
+        bits |= EntryBits::ReadWrite.val();
+    }
+    if prot & PROT_EXEC != 0 {
+        bits |= EntryBits::Execute.val();
+    }
+
+    bits
+}
+
+/// `brk(addr)`. Returns the new program break, or the current one on failure (`addr` 0 is
+/// used to query the program break).
+pub(super) fn sys_brk(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    current_process(frame).address_space_mut().set_brk(args[0]) as isize
+}
+
+/// `mmap(addr, len, prot, flags, fd, offset)`. Only the anonymous mappings are supported, the
+/// `addr` is a hint unless `MAP_FIXED` or `MAP_FIXED_NOREPLACE`.
+pub(super) fn sys_mmap(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let (addr, len, prot, flags, offset) = (args[0], args[1], args[2], args[3], args[5]);
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 ||
+        flags & (MAP_SHARED | MAP_PRIVATE) == 0 || offset & (PAGE_SIZE - 1) != 0 {
+        return -E_INVALID as isize;
+    }
+    if flags & MAP_ANONYMOUS == 0 {
+        // todo: file mappings.
+        return -E_NO_DEV as isize;
+    }
+
+    let placement = if flags & MAP_FIXED != 0 {
+        MapPlacement::Fixed(addr)
+    } else if flags & MAP_FIXED_NOREPLACE != 0 {
+        MapPlacement::FixedNoReplace(addr)
+    } else {
+        MapPlacement::Hint(addr)
+    };
+    // `MAP_SHARED_VALIDATE` has both bits set.
+    let shared = flags & MAP_SHARED != 0;
+    let aspace = current_process(frame).address_space_mut();
+    to_return(aspace.map_anonymous(placement, len, prot_to_bits(prot), shared))
+}
+
+/// `munmap(addr, len)`.
+pub(super) fn sys_munmap(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let (addr, len) = (args[0], args[1]);
+    if addr & (PAGE_SIZE - 1) != 0 || len == 0 || !AddressSpace::is_user_range(addr, len) {
+        return -E_INVALID as isize;
+    }
+
+    to_return(current_process(frame).address_space_mut().unmap_range(addr, len).map(|_| 0))
+}
+
+/// `mprotect(addr, len, prot)`.
+pub(super) fn sys_mprotect(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let (addr, len, prot) = (args[0], args[1], args[2]);
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 || addr & (PAGE_SIZE - 1) != 0 {
+        return -E_INVALID as isize;
+    }
+
+    let aspace = current_process(frame).address_space_mut();
+    to_return(aspace.protect_range(addr, len, prot_to_bits(prot)).map(|_| 0))
+}
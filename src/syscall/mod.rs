@@ -0,0 +1,199 @@
+//! System call dispatch.
+//!
+//! A user thread issues a syscall by the `ecall` instruction with the syscall number in `a7` and
+//! the arguments in `a0`~`a5`, the return value is written back to `a0`. The syscall numbers and
+//! the argument layouts follow the Linux riscv64 ABI (the generic syscall table), so statically
+//! linked musl binaries can run. Errors are returned as the negative errno.
+//!
+//! The handlers run in the trap context on the per-hart stack with the interrupts disabled. A
+//! handler may block the thread by adding it to a scheduler list and calling `schedule`, which
+//! never returns: the thread resumes from its trap frame later, so the `pc` in the trap frame is
+//! advanced before the handler is called and a blocking handler must set the return value by
+//! [`set_return`] itself. A handler can also restart the syscall by rewinding the `pc`. A
+//! thread sleeping with the `InterruptibleSleep` status may be woken up by a signal, the
+//! syscall then returns `EINTR` to the `pc` after the `ecall`.
+//!
+//! User pointers are never dereferenced directly, see [`uaccess`].
+//!
+//! [`uaccess`]: crate::mm::uaccess
+
+mod fs;
+mod futex;
+mod mm;
+mod proc;
+mod signal;
+mod time;
+
+use crate::arch::cpu::{self, Register};
+use crate::errno::E_NO_SYS;
+use crate::proc::process::Process;
+use crate::proc::task::{TaskInfo, TaskTrapFrame};
+
+
+pub const SYS_GETCWD: usize = 17;
+pub const SYS_IOCTL: usize = 29;
+pub const SYS_MKNODAT: usize = 33;
+pub const SYS_MKDIRAT: usize = 34;
+pub const SYS_UNLINKAT: usize = 35;
+pub const SYS_SYMLINKAT: usize = 36;
+pub const SYS_LINKAT: usize = 37;
+pub const SYS_UMOUNT2: usize = 39;
+pub const SYS_MOUNT: usize = 40;
+pub const SYS_CHDIR: usize = 49;
+pub const SYS_OPENAT: usize = 56;
+pub const SYS_CLOSE: usize = 57;
+pub const SYS_GETDENTS64: usize = 61;
+pub const SYS_LSEEK: usize = 62;
+pub const SYS_READ: usize = 63;
+pub const SYS_WRITE: usize = 64;
+pub const SYS_READV: usize = 65;
+pub const SYS_WRITEV: usize = 66;
+pub const SYS_READLINKAT: usize = 78;
+pub const SYS_NEWFSTATAT: usize = 79;
+pub const SYS_FSTAT: usize = 80;
+pub const SYS_SYNC: usize = 81;
+pub const SYS_FSYNC: usize = 82;
+pub const SYS_FDATASYNC: usize = 83;
+pub const SYS_EXIT: usize = 93;
+pub const SYS_EXIT_GROUP: usize = 94;
+pub const SYS_SET_TID_ADDRESS: usize = 96;
+pub const SYS_FUTEX: usize = 98;
+pub const SYS_NANOSLEEP: usize = 101;
+pub const SYS_CLOCK_GETTIME: usize = 113;
+pub const SYS_SCHED_YIELD: usize = 124;
+pub const SYS_KILL: usize = 129;
+pub const SYS_TKILL: usize = 130;
+pub const SYS_TGKILL: usize = 131;
+pub const SYS_RT_SIGACTION: usize = 134;
+pub const SYS_RT_SIGPROCMASK: usize = 135;
+pub const SYS_RT_SIGPENDING: usize = 136;
+pub const SYS_RT_SIGRETURN: usize = 139;
+pub const SYS_SETPGID: usize = 154;
+pub const SYS_GETPGID: usize = 155;
+pub const SYS_GETSID: usize = 156;
+pub const SYS_SETSID: usize = 157;
+pub const SYS_GETPID: usize = 172;
+pub const SYS_GETPPID: usize = 173;
+pub const SYS_GETTID: usize = 178;
+pub const SYS_BRK: usize = 214;
+pub const SYS_MUNMAP: usize = 215;
+pub const SYS_CLONE: usize = 220;
+pub const SYS_EXECVE: usize = 221;
+pub const SYS_MMAP: usize = 222;
+pub const SYS_MPROTECT: usize = 226;
+pub const SYS_WAIT4: usize = 260;
+pub const SYS_RENAMEAT2: usize = 276;
+
+/// Size of the syscall table, all syscall numbers are less than this.
+pub const NR_SYSCALLS: usize = 512;
+
+/// Syscall handler. The params are the trap frame of the calling thread and the arguments in
+/// `a0`~`a5`; returns the value for `a0`.
+type SyscallFn = fn(&mut TaskTrapFrame, &[usize; 6]) -> isize;
+
+static SYSCALL_TABLE: [Option<SyscallFn>; NR_SYSCALLS] = build_syscall_table();
+
+const fn build_syscall_table() -> [Option<SyscallFn>; NR_SYSCALLS] {
+    let mut table: [Option<SyscallFn>; NR_SYSCALLS] = [None; NR_SYSCALLS];
+    table[SYS_GETCWD] = Some(fs::sys_getcwd);
+    table[SYS_IOCTL] = Some(fs::sys_ioctl);
+    table[SYS_MKNODAT] = Some(fs::sys_mknodat);
+    table[SYS_MKDIRAT] = Some(fs::sys_mkdirat);
+    table[SYS_UNLINKAT] = Some(fs::sys_unlinkat);
+    table[SYS_SYMLINKAT] = Some(fs::sys_symlinkat);
+    table[SYS_LINKAT] = Some(fs::sys_linkat);
+    table[SYS_UMOUNT2] = Some(fs::sys_umount2);
+    table[SYS_MOUNT] = Some(fs::sys_mount);
+    table[SYS_CHDIR] = Some(fs::sys_chdir);
+    table[SYS_OPENAT] = Some(fs::sys_openat);
+    table[SYS_CLOSE] = Some(fs::sys_close);
+    table[SYS_GETDENTS64] = Some(fs::sys_getdents64);
+    table[SYS_LSEEK] = Some(fs::sys_lseek);
+    table[SYS_READ] = Some(fs::sys_read);
+    table[SYS_WRITE] = Some(fs::sys_write);
+    table[SYS_READV] = Some(fs::sys_readv);
+    table[SYS_WRITEV] = Some(fs::sys_writev);
+    table[SYS_READLINKAT] = Some(fs::sys_readlinkat);
+    table[SYS_NEWFSTATAT] = Some(fs::sys_newfstatat);
+    table[SYS_FSTAT] = Some(fs::sys_fstat);
+    table[SYS_SYNC] = Some(fs::sys_sync);
+    table[SYS_FSYNC] = Some(fs::sys_fsync);
+    table[SYS_FDATASYNC] = Some(fs::sys_fdatasync);
+    table[SYS_EXIT] = Some(proc::sys_exit);
+    table[SYS_EXIT_GROUP] = Some(proc::sys_exit_group);
+    table[SYS_SET_TID_ADDRESS] = Some(proc::sys_set_tid_address);
+    table[SYS_FUTEX] = Some(futex::sys_futex);
+    table[SYS_NANOSLEEP] = Some(time::sys_nanosleep);
+    table[SYS_CLOCK_GETTIME] = Some(time::sys_clock_gettime);
+    table[SYS_SCHED_YIELD] = Some(proc::sys_sched_yield);
+    table[SYS_KILL] = Some(signal::sys_kill);
+    table[SYS_TKILL] = Some(signal::sys_tkill);
+    table[SYS_TGKILL] = Some(signal::sys_tgkill);
+    table[SYS_RT_SIGACTION] = Some(signal::sys_rt_sigaction);
+    table[SYS_RT_SIGPROCMASK] = Some(signal::sys_rt_sigprocmask);
+    table[SYS_RT_SIGPENDING] = Some(signal::sys_rt_sigpending);
+    table[SYS_RT_SIGRETURN] = Some(signal::sys_rt_sigreturn);
+    table[SYS_SETPGID] = Some(proc::sys_setpgid);
+    table[SYS_GETPGID] = Some(proc::sys_getpgid);
+    table[SYS_GETSID] = Some(proc::sys_getsid);
+    table[SYS_SETSID] = Some(proc::sys_setsid);
+    table[SYS_GETPID] = Some(proc::sys_getpid);
+    table[SYS_GETPPID] = Some(proc::sys_getppid);
+    table[SYS_GETTID] = Some(proc::sys_gettid);
+    table[SYS_BRK] = Some(mm::sys_brk);
+    table[SYS_MUNMAP] = Some(mm::sys_munmap);
+    table[SYS_CLONE] = Some(proc::sys_clone);
+    table[SYS_EXECVE] = Some(proc::sys_execve);
+    table[SYS_MMAP] = Some(mm::sys_mmap);
+    table[SYS_MPROTECT] = Some(mm::sys_mprotect);
+    table[SYS_WAIT4] = Some(proc::sys_wait4);
+    table[SYS_RENAMEAT2] = Some(fs::sys_renameat2);
+    table
+}
+
+/// Handle the `ecall` from U-mode at `epc`. Returns the `pc` to continue.
+pub(crate) fn handle_syscall(frame: &mut TaskTrapFrame, epc: usize) -> usize {
+    frame.pc = epc + 4;
+    current_task(frame).signal_mut().set_syscall_pc(epc + 4);
+
+    let nr = frame.regs[cpu::reg(Register::A7)];
+    let a0 = cpu::reg(Register::A0);
+    let args: [usize; 6] = frame.regs[a0..a0 + 6].try_into().unwrap();
+    let ret = match SYSCALL_TABLE.get(nr) {
+        Some(Some(handler)) => handler(frame, &args),
+        _ => {
+            debug!("Unknown syscall {} from PID {}.", nr, frame.pid);
+            -E_NO_SYS as isize
+        }
+    };
+    set_return(frame, ret);
+
+    frame.pc
+}
+
+/// Set the syscall return value.
+#[inline(always)]
+pub(crate) fn set_return(frame: &mut TaskTrapFrame, ret: isize) {
+    frame.regs[cpu::reg(Register::A0)] = ret as usize;
+}
+
+/// Get the calling thread.
+#[inline(always)]
+fn current_task(frame: &mut TaskTrapFrame) -> &mut TaskInfo {
+    unsafe { &mut *TaskInfo::from_trap_frame_ptr(frame) }
+}
+
+/// Get the process of the calling thread.
+#[inline(always)]
+fn current_process(frame: &mut TaskTrapFrame) -> &mut Process {
+    unsafe { &mut *current_task(frame).process() }
+}
+
+/// Convert a `Result` of the errno to the syscall return value.
+#[inline(always)]
+fn to_return(result: Result<usize, i32>) -> isize {
+    match result {
+        Ok(val) => val as isize,
+        Err(errno) => -errno as isize,
+    }
+}
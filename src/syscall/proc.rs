@@ -0,0 +1,235 @@
+//! Process and scheduler syscalls.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::arch::cpu;
+use crate::errno::{E_FAULT, E_INVALID, E_NAME_TOO_LONG, E_SRCH, E_TOO_BIG};
+use crate::fs::PATH_MAX;
+use crate::mm::address_space::AddressSpace;
+use crate::mm::uaccess::{copy_string_from_user, copy_to_user, read_user, write_user};
+use crate::proc::elf::ARG_MAX_SIZE;
+use crate::proc::exec::do_execve;
+use crate::proc::exit::{do_exit, do_exit_group, do_wait, exit_status, WaitTarget};
+use crate::proc::fork::{do_clone, CloneArgs, CLONE_VFORK};
+use crate::proc::process::{find_process, set_pgid, set_sid, Process};
+use crate::proc::task::TaskTrapFrame;
+use crate::sched::{ready_list_add_task, schedule};
+use super::{current_process, current_task, set_return, to_return};
+
+
+// `wait4` options.
+const WNOHANG: usize = 0x1;
+const WUNTRACED: usize = 0x2;
+const WCONTINUED: usize = 0x8;
+const WNOTHREAD: usize = 0x2000_0000;
+const WALL: usize = 0x4000_0000;
+const WCLONE: usize = 0x8000_0000;
+
+/// Size of the `struct rusage`.
+const RUSAGE_SIZE: usize = 144;
+
+
+/// `exit(code)`.
+pub(super) fn sys_exit(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    do_exit(current_task(frame), exit_status(args[0]))
+}
+
+/// `exit_group(code)`.
+pub(super) fn sys_exit_group(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    do_exit_group(current_task(frame), exit_status(args[0]))
+}
+
+/// Copy the NULL-terminated user string pointer array at `addr` (NULL is taken as empty) into
+/// `buf` from `*used`. Returns the ranges of the strings in `buf`.
+fn copy_string_array(aspace: &mut AddressSpace, addr: usize, buf: &mut [u8], used: &mut usize)
+    -> Result<Vec<(usize, usize)>, i32> {
+    let mut ranges = Vec::new();
+    if addr == 0 {
+        return Ok(ranges);
+    }
+
+    loop {
+        let ptr = read_user::<usize>(aspace, addr.wrapping_add(ranges.len() * 8))?;
+        if ptr == 0 {
+            return Ok(ranges);
+        }
+        let start = *used;
+        let len = copy_string_from_user(aspace, &mut buf[start..], ptr)
+            .map_err(|errno| if errno == E_NAME_TOO_LONG { E_TOO_BIG } else { errno })?;
+        ranges.push((start, len));
+        *used += len + 1;
+    }
+}
+
+/// `execve(path, argv, envp)`. Returns nothing on success, the new program starts with zeroed
+/// registers. The syscall is restarted after the other threads of the process have exited.
+pub(super) fn sys_execve(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let aspace = current_process(frame).address_space_mut();
+    let mut path = [0u8; PATH_MAX].to_vec();
+    let path_len = match copy_string_from_user(aspace, &mut path, args[0]) {
+        Ok(len) => len,
+        Err(errno) => return -errno as isize,
+    };
+
+    // The argument strings are limited by the stack size of the new program.
+    let mut buf = vec![0u8; ARG_MAX_SIZE];
+    let mut used = 0;
+    let argv = match copy_string_array(aspace, args[1], &mut buf, &mut used) {
+        Ok(argv) => argv,
+        Err(errno) => return -errno as isize,
+    };
+    let envp = match copy_string_array(aspace, args[2], &mut buf, &mut used) {
+        Ok(envp) => envp,
+        Err(errno) => return -errno as isize,
+    };
+    let argv: Vec<&[u8]> = argv.iter().map(|&(start, len)| &buf[start..start + len]).collect();
+    let envp: Vec<&[u8]> = envp.iter().map(|&(start, len)| &buf[start..start + len]).collect();
+
+    // Restart the syscall if the thread waits for the other threads, the `pc` is replaced by
+    // the entry of the new program on success.
+    frame.pc -= 4;
+    match do_execve(current_task(frame), &path[..path_len], &argv, &envp) {
+        Ok(true) => {
+            // Load the cleared floating-point state on the return.
+            cpu::sstatus_set_bits(cpu::SSTATUS_FS_DIRTY);
+            0
+        }
+        Ok(false) => {
+            schedule();
+            0
+        }
+        Err(errno) => {
+            frame.pc += 4;
+            -errno as isize
+        }
+    }
+}
+
+/// `wait4(pid, wstatus, options, rusage)`. The `pid` selects the children: `-1` for any child,
+/// `0` for the children in the same process group, `< -1` for the process group `-pid`. The
+/// resource usage is not tracked, `rusage` is zeroed.
+pub(super) fn sys_wait4(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let (pid, wstatus, options, rusage) = (args[0] as i32, args[1], args[2], args[3]);
+    if options & !(WNOHANG | WUNTRACED | WCONTINUED | WNOTHREAD | WALL | WCLONE) != 0 {
+        return -E_INVALID as isize;
+    }
+    let target = match pid {
+        -1 => WaitTarget::Any,
+        0 => WaitTarget::Group(current_process(frame).pgid()),
+        pid if pid > 0 => WaitTarget::Pid(pid as u32),
+        pid => WaitTarget::Group(pid.unsigned_abs()),
+    };
+
+    // Restart the syscall if the thread sleeps, the `pc` must be set before the thread is
+    // added to the wait queue.
+    let block = options & WNOHANG == 0;
+    if block {
+        frame.pc -= 4;
+    }
+    let ret = do_wait(current_task(frame), target, block);
+    if let (true, Ok(None)) = (block, &ret) {
+        schedule();
+    }
+    if block {
+        frame.pc += 4;
+    }
+
+    let (pid, status) = match ret {
+        Ok(Some(ret)) => ret,
+        Ok(None) => return 0,
+        Err(errno) => return -errno as isize,
+    };
+    let aspace = current_process(frame).address_space_mut();
+    if wstatus != 0 && write_user(aspace, wstatus, &(status as i32)).is_err() {
+        return -E_FAULT as isize;
+    }
+    if rusage != 0 && copy_to_user(aspace, rusage, &[0u8; RUSAGE_SIZE]).is_err() {
+        return -E_FAULT as isize;
+    }
+
+    pid as isize
+}
+
+/// `getpid()`.
+pub(super) fn sys_getpid(frame: &mut TaskTrapFrame, _args: &[usize; 6]) -> isize {
+    current_process(frame).pid() as isize
+}
+
+/// `getppid()`.
+pub(super) fn sys_getppid(frame: &mut TaskTrapFrame, _args: &[usize; 6]) -> isize {
+    current_process(frame).ppid() as isize
+}
+
+/// Find the process by the `pid` argument, 0 for the calling process.
+fn process_by_pid(frame: &mut TaskTrapFrame, pid: usize) -> Result<&mut Process, i32> {
+    if pid == 0 {
+        return Ok(current_process(frame));
+    }
+    let process = find_process(pid as u32);
+    if process.is_null() { Err(E_SRCH) } else { Ok(unsafe { &mut *process }) }
+}
+
+/// `setpgid(pid, pgid)`.
+pub(super) fn sys_setpgid(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    if (args[1] as i32) < 0 {
+        return -E_INVALID as isize;
+    }
+    to_return(set_pgid(current_process(frame), args[0] as u32, args[1] as u32).map(|_| 0))
+}
+
+/// `getpgid(pid)`.
+pub(super) fn sys_getpgid(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    to_return(process_by_pid(frame, args[0]).map(|p| p.pgid() as usize))
+}
+
+/// `getsid(pid)`.
+pub(super) fn sys_getsid(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    to_return(process_by_pid(frame, args[0]).map(|p| p.sid() as usize))
+}
+
+/// `setsid()`.
+pub(super) fn sys_setsid(frame: &mut TaskTrapFrame, _args: &[usize; 6]) -> isize {
+    to_return(set_sid(current_process(frame)).map(|sid| sid as usize))
+}
+
+/// `gettid()`.
+pub(super) fn sys_gettid(frame: &mut TaskTrapFrame, _args: &[usize; 6]) -> isize {
+    current_task(frame).tid() as isize
+}
+
+/// `set_tid_address(tidptr)`. Returns the `tid` of the calling thread.
+pub(super) fn sys_set_tid_address(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let task = current_task(frame);
+    task.set_clear_child_tid(args[0]);
+    task.tid() as isize
+}
+
+/// `clone(flags, stack, parent_tid, tls, child_tid)`, the argument order of riscv64. Returns
+/// the `tid` of the new thread to the parent, and 0 to the child. With `CLONE_VFORK`, the
+/// parent returns after the child execs or exits.
+pub(super) fn sys_clone(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let args = CloneArgs {
+        flags: args[0],
+        stack: args[1],
+        parent_tid: args[2],
+        tls: args[3],
+        child_tid: args[4],
+    };
+    match do_clone(current_task(frame), &args) {
+        Ok(tid) if args.flags & CLONE_VFORK != 0 => {
+            // The thread is suspended by `do_clone`.
+            set_return(frame, tid as isize);
+            schedule();
+            unreachable!()
+        }
+        ret => to_return(ret.map(|tid| tid as usize)),
+    }
+}
+
+/// `sched_yield()`.
+pub(super) fn sys_sched_yield(frame: &mut TaskTrapFrame, _args: &[usize; 6]) -> isize {
+    set_return(frame, 0);
+    ready_list_add_task(current_task(frame));
+    schedule();
+    0
+}
@@ -0,0 +1,99 @@
+//! Signal syscalls, see [`signal`](crate::proc::signal).
+
+use core::mem::size_of;
+use crate::arch::cpu::{self, Register};
+use crate::errno::E_INVALID;
+use crate::mm::uaccess::{read_user, write_user};
+use crate::proc::exit::do_exit_group;
+use crate::proc::signal::{self, SigAction, SigSet, SIGSEGV};
+use crate::proc::task::TaskTrapFrame;
+use super::{current_process, current_task, to_return};
+
+
+/// Check the `sigsetsize` argument, only the 64 bits signal set is supported.
+#[inline(always)]
+fn check_sigset_size(size: usize) -> Result<(), i32> {
+    if size == size_of::<SigSet>() { Ok(()) } else { Err(E_INVALID) }
+}
+
+/// `kill(pid, sig)`.
+pub(super) fn sys_kill(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    to_return(signal::kill(current_process(frame), args[0] as i32, args[1]).map(|_| 0))
+}
+
+/// `tkill(tid, sig)`.
+pub(super) fn sys_tkill(_frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    if (args[0] as i32) <= 0 {
+        return -E_INVALID as isize;
+    }
+    to_return(signal::tgkill(None, args[0] as u32, args[1]).map(|_| 0))
+}
+
+/// `tgkill(tgid, tid, sig)`.
+pub(super) fn sys_tgkill(_frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    if (args[0] as i32) <= 0 || (args[1] as i32) <= 0 {
+        return -E_INVALID as isize;
+    }
+    to_return(signal::tgkill(Some(args[0] as u32), args[1] as u32, args[2]).map(|_| 0))
+}
+
+/// `rt_sigaction(sig, act, oldact, sigsetsize)`.
+pub(super) fn sys_rt_sigaction(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let (sig, act, oldact) = (args[0], args[1], args[2]);
+    let result = check_sigset_size(args[3]).and_then(|_| {
+        let process = current_process(frame);
+        let new = match act {
+            0 => None,
+            act => Some(read_user::<SigAction>(process.address_space_mut(), act)?),
+        };
+        let old = signal::set_action(process, sig, new)?;
+        if oldact != 0 {
+            write_user(process.address_space_mut(), oldact, &old)?;
+        }
+        Ok(0)
+    });
+
+    to_return(result)
+}
+
+/// `rt_sigprocmask(how, set, oldset, sigsetsize)`.
+pub(super) fn sys_rt_sigprocmask(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let (how, set, oldset) = (args[0], args[1], args[2]);
+    let result = check_sigset_size(args[3]).and_then(|_| {
+        let aspace = current_process(frame).address_space_mut();
+        let set = match set {
+            0 => None,
+            set => Some(read_user::<SigSet>(aspace, set)?),
+        };
+        let old = signal::set_blocked(current_task(frame), how, set)?;
+        if oldset != 0 {
+            write_user(current_process(frame).address_space_mut(), oldset, &old)?;
+        }
+        Ok(0)
+    });
+
+    to_return(result)
+}
+
+/// `rt_sigpending(set, sigsetsize)`.
+pub(super) fn sys_rt_sigpending(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let result = check_sigset_size(args[1]).and_then(|_| {
+        let pending = signal::pending_blocked(current_task(frame));
+        write_user(current_process(frame).address_space_mut(), args[0], &pending).map(|_| 0)
+    });
+
+    to_return(result)
+}
+
+/// `rt_sigreturn()`, called by the sigreturn trampoline when a handler returns. Returns the
+/// restored `a0`, a bad signal frame kills the process with `SIGSEGV`.
+pub(super) fn sys_rt_sigreturn(frame: &mut TaskTrapFrame, _args: &[usize; 6]) -> isize {
+    if signal::sigreturn(current_task(frame)).is_err() {
+        debug!("Bad signal frame of PID {}, sp: {:#x}.", frame.pid,
+            frame.regs[cpu::reg(Register::Sp)]);
+        do_exit_group(current_task(frame), SIGSEGV);
+    }
+    // Load the restored floating-point state on the return.
+    cpu::sstatus_set_bits(cpu::SSTATUS_FS_DIRTY);
+    frame.regs[cpu::reg(Register::A0)] as isize
+}
@@ -0,0 +1,74 @@
+//! Time syscalls. There is no RTC yet, all clocks count from the boot time.
+
+use crate::arch::cpu;
+use crate::errno::E_INVALID;
+use crate::mm::address_space::AddressSpace;
+use crate::mm::uaccess::{read_user, write_user};
+use crate::proc::task::TaskTrapFrame;
+use crate::sched::{schedule, sleep_list_add_task};
+use crate::smp::current_cpu_info;
+use super::{current_process, current_task, set_return, to_return};
+
+
+const CLOCK_REALTIME: usize = 0;
+const CLOCK_MONOTONIC: usize = 1;
+const CLOCK_MONOTONIC_RAW: usize = 4;
+const CLOCK_REALTIME_COARSE: usize = 5;
+const CLOCK_MONOTONIC_COARSE: usize = 6;
+const CLOCK_BOOTTIME: usize = 7;
+
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+/// `struct timespec` of the Linux riscv64 ABI.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct TimeSpec {
+    tv_sec: i64,
+    tv_nsec: i64,
+}
+
+/// Read the relative `struct timespec` at the user address `addr`, and convert it to the timer
+/// ticks (rounded up).
+pub(super) fn read_timeout(aspace: &mut AddressSpace, addr: usize) -> Result<u64, i32> {
+    let ts = read_user::<TimeSpec>(aspace, addr)?;
+    if ts.tv_sec < 0 || !(0..NSEC_PER_SEC as i64).contains(&ts.tv_nsec) {
+        return Err(E_INVALID);
+    }
+
+    let freq = current_cpu_info().get_timebase_freq() as u64;
+    Ok((ts.tv_sec as u64).saturating_mul(freq)
+        .saturating_add((ts.tv_nsec as u64 * freq + NSEC_PER_SEC - 1) / NSEC_PER_SEC))
+}
+
+/// `clock_gettime(clock_id, tp)`.
+pub(super) fn sys_clock_gettime(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let (clock_id, tp) = (args[0], args[1]);
+    match clock_id {
+        CLOCK_REALTIME | CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_REALTIME_COARSE |
+        CLOCK_MONOTONIC_COARSE | CLOCK_BOOTTIME => {}
+        _ => return -E_INVALID as isize,
+    }
+
+    let freq = current_cpu_info().get_timebase_freq() as u64;
+    let ticks = cpu::read_time() as u64;
+    let ts = TimeSpec {
+        tv_sec: (ticks / freq) as i64,
+        tv_nsec: ((ticks % freq) * NSEC_PER_SEC / freq) as i64,
+    };
+    to_return(write_user(current_process(frame).address_space_mut(), tp, &ts).map(|_| 0))
+}
+
+/// `nanosleep(req, rem)`. A sleep interrupted by a signal returns `EINTR`, `rem` is not
+/// written.
+pub(super) fn sys_nanosleep(frame: &mut TaskTrapFrame, args: &[usize; 6]) -> isize {
+    let ticks = match read_timeout(current_process(frame).address_space_mut(), args[0]) {
+        Ok(ticks) => ticks,
+        Err(errno) => return -errno as isize,
+    };
+    let wake_time = (cpu::read_time() as u64).saturating_add(ticks);
+
+    set_return(frame, 0);
+    sleep_list_add_task(current_task(frame), wake_time as usize);
+    schedule();
+    0
+}
@@ -1,5 +1,3 @@
-pub mod align;
-pub mod bit;
-pub mod forward_list;
-pub mod list;
+// The platform-independent helpers live in the `vos-core` crate so they can be tested on the host.
+pub use vos_core::util::{align, forward_list, list, rbtree};
 pub mod type_trait;
@@ -0,0 +1,182 @@
+#!/usr/bin/env python3
+"""Embed the kernel symbol table into the `.ksyms` section of the kernel image.
+
+The kernel reserves a fixed size `.ksyms` section (see `src/debug/ksyms.rs`). This script reads
+the function symbols from the ELF `.symtab`, demangles the Rust names and writes the table into
+the reserved space in place, so the addresses of the image are not changed.
+
+Usage: ksyms.py <kernel-elf>
+"""
+
+import re
+import struct
+import sys
+
+KSYMS_MAGIC = 0x4d59534b
+KSYMS_VERSION = 1
+
+HEADER = struct.Struct('<IHHII')
+ENTRY = struct.Struct('<QIIII')
+
+STT_NOTYPE = 0
+STT_FUNC = 2
+
+SHT_SYMTAB = 2
+
+
+class ElfError(Exception):
+    pass
+
+
+def read_sections(data):
+    if data[:4] != b'\x7fELF' or data[4] != 2 or data[5] != 1:
+        raise ElfError('not a little-endian ELF64 file')
+
+    e_shoff, = struct.unpack_from('<Q', data, 0x28)
+    e_shentsize, e_shnum, e_shstrndx = struct.unpack_from('<HHH', data, 0x3a)
+    sections = []
+    for i in range(e_shnum):
+        (name, ty, flags, addr, offset, size, link, info, align,
+         entsize) = struct.unpack_from('<IIQQQQIIQQ', data, e_shoff + i * e_shentsize)
+        sections.append({'name_off': name, 'type': ty, 'addr': addr, 'offset': offset,
+                         'size': size, 'link': link, 'entsize': entsize})
+
+    shstr = sections[e_shstrndx]
+    for sec in sections:
+        sec['name'] = c_string(data, shstr['offset'] + sec['name_off'])
+    return sections
+
+
+def c_string(data, off):
+    end = data.index(b'\0', off)
+    return data[off:end].decode('utf-8', errors='replace')
+
+
+def find_section(sections, name):
+    for sec in sections:
+        if sec['name'] == name:
+            return sec
+    raise ElfError('section %s not found' % name)
+
+
+_ESCAPES = {
+    'SP': '@', 'BP': '*', 'RF': '&', 'LT': '<', 'GT': '>', 'LP': '(', 'RP': ')', 'C': ',',
+}
+
+
+def _unescape(ident):
+    def repl(m):
+        code = m.group(1)
+        if code in _ESCAPES:
+            return _ESCAPES[code]
+        if code.startswith('u'):
+            return chr(int(code[1:], 16))
+        return m.group(0)
+
+    if ident.startswith('_$'):
+        ident = ident[1:]
+    ident = re.sub(r'\$([A-Za-z0-9]+)\$', repl, ident)
+    return ident.replace('..', '::')
+
+
+def demangle(name):
+    """Demangle a legacy Rust symbol name, other names are returned unchanged."""
+    if not name.startswith('_ZN') or not name.endswith('E'):
+        return name
+
+    idents = []
+    pos = 3
+    body = name[:-1]
+    while pos < len(body):
+        m = re.match(r'\d+', body[pos:])
+        if not m:
+            return name
+        n = int(m.group(0))
+        pos += len(m.group(0))
+        idents.append(body[pos:pos + n])
+        pos += n
+
+    if idents and re.fullmatch(r'h[0-9a-f]{16}', idents[-1]):
+        idents.pop()
+    return '::'.join(_unescape(i) for i in idents)
+
+
+def collect_symbols(data, sections):
+    text = find_section(sections, '.text')
+    text_start, text_end = text['addr'], text['addr'] + text['size']
+    symtab = next((s for s in sections if s['type'] == SHT_SYMTAB), None)
+    if symtab is None:
+        raise ElfError('no symbol table, is the image stripped?')
+    strtab = sections[symtab['link']]
+
+    by_addr = {}
+    for off in range(symtab['offset'], symtab['offset'] + symtab['size'], symtab['entsize']):
+        name_off, info, _other, _shndx, value, size = struct.unpack_from('<IBBHQQ', data, off)
+        ty = info & 0xf
+        if ty not in (STT_FUNC, STT_NOTYPE) or not (text_start <= value < text_end):
+            continue
+        name = c_string(data, strtab['offset'] + name_off)
+        # Skip the local labels and the mapping symbols.
+        if not name or name.startswith('.L') or name.startswith('$'):
+            continue
+
+        # Prefer the function symbols over the assembly labels on the same address.
+        old = by_addr.get(value)
+        if old is None or (old[2] != STT_FUNC and ty == STT_FUNC):
+            by_addr[value] = (demangle(name), size, ty)
+
+    return sorted((addr, name, size) for addr, (name, size, _ty) in by_addr.items())
+
+
+def build_table(symbols):
+    strtab = bytearray()
+    entries = bytearray()
+    for addr, name, size in symbols:
+        encoded = name.encode('utf-8')
+        entries += ENTRY.pack(addr, min(size, 0xffffffff), len(strtab), len(encoded), 0)
+        strtab += encoded
+
+    strtab_off = HEADER.size + len(entries)
+    header = HEADER.pack(KSYMS_MAGIC, KSYMS_VERSION, 0, len(symbols), strtab_off)
+    return header + entries + strtab
+
+
+def main(argv):
+    if len(argv) != 2:
+        print(__doc__.strip(), file=sys.stderr)
+        return 2
+
+    path = argv[1]
+    with open(path, 'rb') as f:
+        data = bytearray(f.read())
+
+    try:
+        sections = read_sections(data)
+        ksyms = find_section(sections, '.ksyms')
+        symbols = collect_symbols(data, sections)
+    except ElfError as e:
+        print('ksyms: %s: %s' % (path, e), file=sys.stderr)
+        return 1
+
+    magic, = struct.unpack_from('<I', data, ksyms['offset'])
+    if magic != KSYMS_MAGIC:
+        print('ksyms: %s: bad magic of the .ksyms section' % path, file=sys.stderr)
+        return 1
+
+    table = build_table(symbols)
+    if len(table) > ksyms['size']:
+        print('ksyms: symbol table needs %d bytes but only %d bytes are reserved, increase '
+              '`KSYMS_CAPACITY`' % (len(table), ksyms['size']), file=sys.stderr)
+        return 1
+
+    start = ksyms['offset']
+    data[start:start + ksyms['size']] = table + bytes(ksyms['size'] - len(table))
+    with open(path, 'wb') as f:
+        f.write(data)
+
+    print('ksyms: %d symbols, %d bytes' % (len(symbols), len(table)))
+    return 0
+
+
+if __name__ == '__main__':
+    sys.exit(main(sys.argv))
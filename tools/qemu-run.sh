@@ -0,0 +1,40 @@
+#!/bin/sh
+# Cargo runner: embed the kernel symbol table into the image, then boot it in QEMU.
+#
+# Usage: qemu-run.sh <kernel-elf> [args...]
+#
+# For a normal kernel image the extra args are passed to QEMU. For a test image built by
+# `cargo test` (which lives in the `deps` dir), the first non-option arg is used as the test
+# filter, the machine is powered off on panic and the QEMU exit code is the test result. The
+# test run is killed after `KTEST_TIMEOUT` seconds (default 300).
+
+set -e
+
+KERNEL="$1"
+shift
+
+python3 "$(dirname "$0")/ksyms.py" "$KERNEL"
+
+case "$KERNEL" in
+*/deps/*)
+    APPEND="panic_action=poweroff"
+    for arg in "$@"; do
+        case "$arg" in
+        -*) ;;
+        *) APPEND="$APPEND ktest_filter=$arg"; break ;;
+        esac
+    done
+
+    TIMEOUT=""
+    if command -v timeout >/dev/null 2>&1; then
+        TIMEOUT="timeout ${KTEST_TIMEOUT:-300}"
+    fi
+
+    exec $TIMEOUT qemu-system-riscv64 -machine virt -cpu rv64 -smp 4 -m 128M -nographic \
+        -serial mon:stdio -bios none -append "$APPEND" -kernel "$KERNEL"
+    ;;
+esac
+
+exec qemu-system-riscv64 -machine virt -cpu rv64 -smp 4 -m 128M -nographic -s \
+    -drive if=none,format=raw,file=hdd.dsk,id=foo -device virtio-blk-device,drive=foo \
+    -serial mon:stdio -bios none "$@" -kernel "$KERNEL"